@@ -31,15 +31,33 @@ pub mod lnpd {
 pub mod peerd {
     include!("src/peerd/opts.rs");
 }
+pub mod chaind {
+    include!("src/chaind/opts.rs");
+}
 pub mod channeld {
     include!("src/channeld/opts.rs");
 }
+pub mod fundingd {
+    include!("src/fundingd/opts.rs");
+}
 pub mod gossipd {
     include!("src/gossipd/opts.rs");
 }
 pub mod routed {
     include!("src/routed/opts.rs");
 }
+pub mod towerd {
+    include!("src/towerd/opts.rs");
+}
+pub mod wtclientd {
+    include!("src/wtclientd/opts.rs");
+}
+pub mod lspd {
+    include!("src/lspd/opts.rs");
+}
+pub mod swapd {
+    include!("src/swapd/opts.rs");
+}
 
 fn main() -> Result<(), configure_me_codegen::Error> {
     let outdir = "./shell";
@@ -48,8 +66,14 @@ fn main() -> Result<(), configure_me_codegen::Error> {
         lnpd::Opts::into_app(),
         peerd::Opts::into_app(),
         channeld::Opts::into_app(),
+        fundingd::Opts::into_app(),
         gossipd::Opts::into_app(),
         routed::Opts::into_app(),
+        towerd::Opts::into_app(),
+        wtclientd::Opts::into_app(),
+        lspd::Opts::into_app(),
+        swapd::Opts::into_app(),
+        chaind::Opts::into_app(),
         cli::Opts::into_app(),
     ]
     .iter_mut()
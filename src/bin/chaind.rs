@@ -0,0 +1,65 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+#![recursion_limit = "256"]
+// Coding conventions
+#![deny(
+    non_upper_case_globals,
+    non_camel_case_types,
+    non_snake_case,
+    unused_mut,
+    unused_imports,
+    dead_code,
+    missing_docs
+)]
+
+//! Main executable for chaind: chain watching microservice.
+
+#[macro_use]
+extern crate log;
+
+use clap::Clap;
+
+use lnp_node::chaind::{self, Opts, SimulationConfig};
+use lnp_node::{Config, ServiceId};
+
+fn main() {
+    println!("chaind: chain watching microservice");
+
+    let mut opts = Opts::parse();
+    trace!("Command-line arguments: {:?}", &opts);
+    opts.process();
+    trace!("Processed arguments: {:?}", &opts);
+
+    let config: Config = opts.shared.clone().into();
+    trace!("Daemon configuration: {:?}", &config);
+    debug!("MSG RPC socket {}", &config.msg_endpoint);
+    debug!("CTL RPC socket {}", &config.ctl_endpoint);
+
+    let simulation = SimulationConfig {
+        confirmations: opts.confirmations,
+        feerate_per_kw: opts.feerate,
+        reorgs: opts
+            .reorgs
+            .iter()
+            .map(|reorg| (reorg.height, reorg.depth))
+            .collect(),
+    };
+
+    debug!("Starting runtime ...");
+    chaind::run(config, ServiceId::Chain, simulation)
+        .expect("Error running chaind runtime");
+
+    unreachable!()
+}
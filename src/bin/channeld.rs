@@ -72,6 +72,7 @@ fn main() {
         opts.key_opts.local_node(),
         opts.channel_id,
         opts.shared.chain,
+        opts.shared.data_dir.clone(),
         rgb20_socket_addr,
     )
     .expect("Error running channeld runtime");
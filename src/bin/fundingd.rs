@@ -0,0 +1,66 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+#![recursion_limit = "256"]
+// Coding conventions
+#![deny(
+    non_upper_case_globals,
+    non_camel_case_types,
+    non_snake_case,
+    unused_mut,
+    unused_imports,
+    dead_code,
+    missing_docs
+)]
+
+//! Main executable for fundingd: channel funding wallet microservice.
+
+#[macro_use]
+extern crate log;
+
+use clap::Clap;
+
+use lnp_node::fundingd::{self, Opts};
+use lnp_node::{Config, LogStyle};
+
+fn main() {
+    println!("fundingd: channel funding wallet microservice");
+
+    let mut opts = Opts::parse();
+    trace!("Command-line arguments: {:?}", &opts);
+    opts.process();
+    trace!("Processed arguments: {:?}", &opts);
+
+    let config: Config = opts.shared.clone().into();
+    trace!("Daemon configuration: {:?}", &config);
+    debug!("MSG RPC socket {}", &config.msg_endpoint);
+    debug!("CTL RPC socket {}", &config.ctl_endpoint);
+
+    let local_node = opts.key_opts.local_node();
+    info!(
+        "{}: {}",
+        "Local node id".ended(),
+        local_node.node_id().addr()
+    );
+
+    debug!("Starting runtime ...");
+    fundingd::run(
+        config,
+        local_node,
+        opts.shared.chain,
+        opts.shared.data_dir.clone(),
+    )
+    .expect("Error running fundingd runtime");
+
+    unreachable!()
+}
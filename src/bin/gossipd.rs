@@ -58,7 +58,8 @@ fn main() {
      */
 
     debug!("Starting runtime ...");
-    gossipd::run(config).expect("Error running gossipd runtime");
+    gossipd::run(config, opts.key_opts.local_node())
+        .expect("Error running gossipd runtime");
 
     unreachable!()
 }
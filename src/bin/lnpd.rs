@@ -30,8 +30,14 @@
 extern crate log;
 
 use clap::Clap;
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::thread;
 
-use lnp_node::lnpd::{self, Opts};
+use lnp_node::lnpd::{
+    self, AcceptancePolicy, LaunchMode, NodeConfig, Opts, ProcessOpts,
+    ResourceLimits, WebhookConfig,
+};
 use lnp_node::{Config, LogStyle};
 
 fn main() {
@@ -39,16 +45,115 @@ fn main() {
 
     let mut opts = Opts::parse();
     trace!("Command-line arguments: {:?}", &opts);
+    let raw_opts = opts.clone();
     opts.process();
     trace!("Processed arguments: {:?}", &opts);
 
+    // Node keys are read from the per-chain directories, so the legacy data
+    // must be moved there before any of the keys is loaded
+    let base_dir = opts
+        .shared
+        .data_dir
+        .parent()
+        .expect("per-chain data directory has a parent");
+    if let Err(err) =
+        lnpd::migrate_legacy_layout(base_dir, opts.legacy_chain.as_ref())
+    {
+        eprintln!("{} {}", "Unable to start lnpd:".err(), err);
+        std::process::exit(1);
+    }
+
+    let webhooks = WebhookConfig {
+        endpoints: opts.webhooks.clone(),
+        secret: opts.webhook_secret.clone(),
+    };
+
+    let acceptance = AcceptancePolicy {
+        min_funding: opts.accept_min_funding,
+        max_channels_per_peer: opts.max_channels_per_peer,
+        allowed_peers: opts.accept_peers.iter().copied().collect(),
+        min_feerate_per_kw: opts.accept_min_feerate,
+        max_feerate_per_kw: opts.accept_max_feerate,
+    };
+
+    let limits = ResourceLimits {
+        max_channels: opts.max_channels,
+        max_peers: opts.max_peers,
+        max_inbound_peers: opts.max_inbound_peers,
+        max_pending_channels: opts.max_pending_channels,
+    };
+
+    let mode = if opts.threaded {
+        LaunchMode::Threads {
+            rgb20_socket: opts
+                .rgb_opts
+                .rgb20_socket
+                .clone()
+                .try_into()
+                .expect("RPC socket must be a valid ZMQ local file socket"),
+        }
+    } else {
+        LaunchMode::Processes(ProcessOpts {
+            bin_dir: opts.bin_dir.clone(),
+            log_dir: opts.log_dir.clone(),
+        })
+    };
+
+    let mut chains = vec![opts.shared.chain.clone()];
+    for chain in &raw_opts.parallel_chains {
+        if !chains.contains(chain) {
+            chains.push(chain.clone());
+        }
+    }
+
+    for chain in &raw_opts.parallel_chains {
+        if *chain == opts.shared.chain {
+            continue;
+        }
+        let mut opts = raw_opts.clone();
+        opts.shared.chain = chain.clone();
+        opts.process();
+
+        let config: Config = opts.shared.clone().into();
+        let node = NodeConfig {
+            local_node: opts.key_opts.local_node(),
+            key_file: PathBuf::from(&opts.key_opts.key_file),
+            data_dir: opts.shared.data_dir.clone(),
+            webhooks: webhooks.clone(),
+            acceptance: acceptance.clone(),
+            limits,
+            chains: chains.clone(),
+            mode: mode.clone(),
+            onion: opts.onion_config(),
+            listens: vec![],
+            access: opts.peer_access(),
+            autoconnect: opts.autoconnect_peers,
+            bootstrap: opts.bootstrap_config(),
+            addresses: opts.address_preference(),
+        };
+        info!(
+            "{} for {}: {}",
+            "Local node id".ended(),
+            chain,
+            node.local_node.node_id().addr()
+        );
+        thread::spawn(move || {
+            debug!("Starting runtime for {} ...", config.chain);
+            lnpd::run(config, node).expect("Error running lnpd runtime")
+        });
+    }
+
     let config: Config = opts.shared.clone().into();
     trace!("Daemon configuration: {:?}", &config);
     debug!("MSG RPC socket {}", &config.msg_endpoint);
     debug!("CTL RPC socket {}", &config.ctl_endpoint);
 
-    let node_id = opts.key_opts.local_node().node_id();
-    info!("{}: {}", "Local node id".ended(), node_id.addr());
+    let local_node = opts.key_opts.local_node();
+    info!(
+        "{}: {}",
+        "Local node id".ended(),
+        local_node.node_id().addr()
+    );
 
     /*
     use self::internal::ResultExt;
@@ -60,7 +165,23 @@ fn main() {
      */
 
     debug!("Starting runtime ...");
-    lnpd::run(config, node_id).expect("Error running lnpd runtime");
+    let node = NodeConfig {
+        local_node,
+        key_file: PathBuf::from(&opts.key_opts.key_file),
+        data_dir: opts.shared.data_dir.clone(),
+        webhooks,
+        acceptance,
+        limits,
+        chains,
+        mode,
+        onion: opts.onion_config(),
+        listens: opts.listen_addrs(),
+        access: opts.peer_access(),
+        autoconnect: opts.autoconnect_peers,
+        bootstrap: opts.bootstrap_config(),
+        addresses: opts.address_preference(),
+    };
+    lnpd::run(config, node).expect("Error running lnpd runtime");
 
     unreachable!()
 }
@@ -0,0 +1,96 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+#![recursion_limit = "256"]
+// Coding conventions
+#![deny(
+    non_upper_case_globals,
+    non_camel_case_types,
+    non_snake_case,
+    unused_mut,
+    unused_imports,
+    dead_code,
+    missing_docs
+)]
+
+//! Main executable for lspd: lightning service provider microservice.
+
+#[macro_use]
+extern crate log;
+
+use clap::Clap;
+use internet2::ToNodeAddr;
+use lnp::LIGHTNING_P2P_DEFAULT_PORT;
+use std::str::FromStr;
+
+use lnp_node::lspd::{self, Opts};
+use lnp_node::rpc::request::LspOffer;
+use lnp_node::{ClientName, Config, ServiceId};
+
+fn main() {
+    println!("lspd: lightning service provider microservice");
+
+    let mut opts = Opts::parse();
+    trace!("Command-line arguments: {:?}", &opts);
+    opts.process();
+    trace!("Processed arguments: {:?}", &opts);
+
+    let config: Config = opts.shared.clone().into();
+    trace!("Daemon configuration: {:?}", &config);
+    debug!("MSG RPC socket {}", &config.msg_endpoint);
+    debug!("CTL RPC socket {}", &config.ctl_endpoint);
+
+    /*
+    use self::internal::ResultExt;
+    let (config_from_file, _) =
+        internal::Config::custom_args_and_optional_files(std::iter::empty::<
+            &str,
+        >())
+        .unwrap_or_exit();
+     */
+
+    let service_id = |name: &str| match name {
+        "lspd" => ServiceId::Lsp,
+        name => ServiceId::Other(
+            ClientName::from_str(name)
+                .expect("ClientName conversion never fails"),
+        ),
+    };
+    let identity = opts
+        .name
+        .as_deref()
+        .map(service_id)
+        .unwrap_or(ServiceId::Lsp);
+    let provider = opts.lsp.as_ref().map(|node| {
+        node.to_node_addr(LIGHTNING_P2P_DEFAULT_PORT)
+            .expect("Provided provider node address is invalid")
+    });
+    let offer = if opts.sell {
+        Some(LspOffer {
+            min_channel_sat: opts.min_channel,
+            max_channel_sat: opts.max_channel,
+            fee_base_sat: opts.fee_base,
+            fee_ppm: opts.fee_ppm,
+            zero_conf: opts.zero_conf,
+        })
+    } else {
+        None
+    };
+
+    debug!("Starting runtime ...");
+    lspd::run(config, identity, provider, offer)
+        .expect("Error running lspd runtime");
+
+    unreachable!()
+}
@@ -99,15 +99,11 @@ use nix::unistd::{fork, ForkResult};
 use std::convert::TryFrom;
 use std::net::TcpListener;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::time::Duration;
 
 use bitcoin::secp256k1::PublicKey;
-use internet2::{
-    session, FramingProtocol, NodeAddr, RemoteNodeAddr, RemoteSocketAddr,
-};
+use internet2::{FramingProtocol, NodeAddr, RemoteNodeAddr, RemoteSocketAddr};
 use lnp_node::peerd::{self, Opts};
 use lnp_node::{Config, LogStyle};
-use microservices::peer::PeerConnection;
 
 /*
 mod internal {
@@ -141,16 +137,18 @@ impl From<Opts> for PeerSocket {
         if let Some(peer_addr) = opts.connect {
             Self::Connect(peer_addr)
         } else if let Some(bind_addr) = opts.listen {
+            let inet_addr = InetSocketAddr {
+                address: bind_addr
+                    .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+                    .into(),
+                port: opts.port,
+            };
             Self::Listen(match opts.overlay {
-                FramingProtocol::FramedRaw => {
-                    RemoteSocketAddr::Ftcp(InetSocketAddr {
-                        address: bind_addr
-                            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
-                            .into(),
-                        port: opts.port,
-                    })
+                FramingProtocol::FramedRaw => RemoteSocketAddr::Ftcp(inet_addr),
+                FramingProtocol::Websocket => {
+                    RemoteSocketAddr::Websocket(inet_addr)
                 }
-                // TODO: (v2) implement overlay protocols
+                // TODO: (v2) implement other overlay protocols
                 _ => unimplemented!(),
             })
         } else {
@@ -186,24 +184,26 @@ fn main() {
     let local_node = opts.key_opts.local_node();
     let local_id = local_node.node_id();
     info!("{}: {}", "Local node id".ended(), local_id.addr());
+    let onion = opts.onion;
     let peer_socket = PeerSocket::from(opts);
     debug!("Peer socket parameter interpreted as {}", peer_socket);
 
-    let id: NodeAddr;
+    let mut id: NodeAddr;
     let mut local_socket: Option<InetSocketAddr> = None;
     let mut remote_id: Option<PublicKey> = None;
     let mut remote_socket: InetSocketAddr;
     let connect: bool;
-    let connection = match peer_socket {
-        PeerSocket::Listen(RemoteSocketAddr::Ftcp(inet_addr)) => {
+    let (connection, custom) = match peer_socket {
+        PeerSocket::Listen(remote_addr) => {
             debug!("Running in LISTEN mode");
+            let (inet_addr, websocket) = match remote_addr {
+                RemoteSocketAddr::Ftcp(inet_addr) => (inet_addr, false),
+                RemoteSocketAddr::Websocket(inet_addr) => (inet_addr, true),
+                _ => unimplemented!(),
+            };
 
             connect = false;
             local_socket = Some(inet_addr);
-            id = NodeAddr::Remote(RemoteNodeAddr {
-                node_id: local_node.node_id(),
-                remote_addr: RemoteSocketAddr::Ftcp(inet_addr),
-            });
 
             debug!("Binding TCP socket {}", inet_addr);
             let listener = TcpListener::bind(
@@ -212,6 +212,8 @@ fn main() {
             )
             .expect("Unable to bind to Lightning network peer socket");
 
+            let mut rate_limiter =
+                peerd::RateLimiter::with(peerd::MAX_HANDSHAKES_PER_MINUTE);
             debug!("Running TCP listener event loop");
             loop {
                 debug!("Awaiting for incoming connections...");
@@ -219,8 +221,31 @@ fn main() {
                     .accept()
                     .expect("Error accepting incpming peer connection");
                 debug!("New connection from {}", remote_socket_addr);
+                if !rate_limiter.admit(remote_socket_addr.ip()) {
+                    warn!(
+                        "{} connection from {}: too many attempts ({} \
+                         rejected in total)",
+                        "Rejecting".err(),
+                        remote_socket_addr,
+                        rate_limiter.rejected()
+                    );
+                    continue;
+                }
+                if !peerd::admits_connection(&config, remote_socket_addr) {
+                    continue;
+                }
 
                 remote_socket = remote_socket_addr.into();
+                let addr =
+                    peerd::listener_addr(inet_addr, onion, remote_socket_addr);
+                id = NodeAddr::Remote(RemoteNodeAddr {
+                    node_id: local_node.node_id(),
+                    remote_addr: if websocket {
+                        RemoteSocketAddr::Websocket(addr)
+                    } else {
+                        RemoteSocketAddr::Ftcp(addr)
+                    },
+                });
 
                 // TODO: Support multithread mode
                 debug!("Forking child process");
@@ -233,19 +258,17 @@ fn main() {
                     continue;
                 }
 
-                stream
-                    .set_read_timeout(Some(Duration::from_secs(30)))
-                    .expect("Unable to set up timeout for TCP connection");
-
-                debug!("Establishing session with the remote");
-                let session =
-                    session::Raw::with_ftcp_unencrypted(stream, inet_addr)
-                        .expect(
-                            "Unable to establish session with the remote peer",
-                        );
+                let (connection, custom, remote_node_id) = peerd::accept(
+                    stream,
+                    inet_addr,
+                    websocket,
+                    &local_node.private_key(),
+                )
+                .expect("Unable to establish session with the remote peer");
+                remote_id = Some(remote_node_id);
 
                 debug!("Session successfully established");
-                break PeerConnection::with(session);
+                break (connection, Some(custom));
             }
         }
         PeerSocket::Connect(remote_node_addr) => {
@@ -257,16 +280,16 @@ fn main() {
             remote_socket = remote_node_addr.remote_addr.into();
 
             info!("Connecting to {}", &remote_node_addr);
-            PeerConnection::connect(remote_node_addr, &local_node)
+            peerd::connect(&config, &local_node, remote_node_addr)
                 .expect("Unable to connect to the remote peer")
         }
-        _ => unimplemented!(),
     };
 
     debug!("Starting runtime ...");
     peerd::run(
         config,
         connection,
+        custom,
         id,
         local_id,
         remote_id,
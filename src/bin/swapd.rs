@@ -0,0 +1,97 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+#![recursion_limit = "256"]
+// Coding conventions
+#![deny(
+    non_upper_case_globals,
+    non_camel_case_types,
+    non_snake_case,
+    unused_mut,
+    unused_imports,
+    dead_code,
+    missing_docs
+)]
+
+//! Main executable for swapd: submarine swap microservice.
+
+#[macro_use]
+extern crate log;
+
+use clap::Clap;
+use std::str::FromStr;
+
+use lnp_node::swapd::{self, Opts, SwapTerms};
+use lnp_node::{ClientName, Config, ServiceId};
+
+fn main() {
+    println!("swapd: submarine swap microservice");
+
+    let mut opts = Opts::parse();
+    trace!("Command-line arguments: {:?}", &opts);
+    opts.process();
+    trace!("Processed arguments: {:?}", &opts);
+
+    let config: Config = opts.shared.clone().into();
+    trace!("Daemon configuration: {:?}", &config);
+    debug!("MSG RPC socket {}", &config.msg_endpoint);
+    debug!("CTL RPC socket {}", &config.ctl_endpoint);
+
+    /*
+    use self::internal::ResultExt;
+    let (config_from_file, _) =
+        internal::Config::custom_args_and_optional_files(std::iter::empty::<
+            &str,
+        >())
+        .unwrap_or_exit();
+     */
+
+    let service_id = |name: &str| match name {
+        "swapd" => ServiceId::Swap,
+        name => ServiceId::Other(
+            ClientName::from_str(name)
+                .expect("ClientName conversion never fails"),
+        ),
+    };
+    let identity = opts
+        .name
+        .as_deref()
+        .map(service_id)
+        .unwrap_or(ServiceId::Swap);
+    let provider = opts.provider.as_deref().map(service_id);
+    let terms = if opts.serve {
+        Some(SwapTerms {
+            min_amount: opts.min_amount,
+            max_amount: opts.max_amount,
+            fee_ppm: opts.fee_ppm,
+            timeout: opts.timeout,
+        })
+    } else {
+        None
+    };
+
+    debug!("Starting runtime ...");
+    swapd::run(
+        config,
+        identity,
+        opts.shared.data_dir.clone(),
+        provider,
+        terms,
+        opts.sweep_address.script_pubkey(),
+        opts.sweep_fee,
+    )
+    .expect("Error running swapd runtime");
+
+    unreachable!()
+}
@@ -0,0 +1,71 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+#![recursion_limit = "256"]
+// Coding conventions
+#![deny(
+    non_upper_case_globals,
+    non_camel_case_types,
+    non_snake_case,
+    unused_mut,
+    unused_imports,
+    dead_code,
+    missing_docs
+)]
+
+//! Main executable for wtclientd: watchtower client microservice.
+
+#[macro_use]
+extern crate log;
+
+use clap::Clap;
+use std::time::Duration;
+
+use lnp_node::wtclientd::{self, Opts};
+use lnp_node::Config;
+
+fn main() {
+    println!("wtclientd: watchtower client microservice");
+
+    let mut opts = Opts::parse();
+    trace!("Command-line arguments: {:?}", &opts);
+    opts.process();
+    trace!("Processed arguments: {:?}", &opts);
+
+    let config: Config = opts.shared.clone().into();
+    trace!("Daemon configuration: {:?}", &config);
+    debug!("MSG RPC socket {}", &config.msg_endpoint);
+    debug!("CTL RPC socket {}", &config.ctl_endpoint);
+
+    /*
+    use self::internal::ResultExt;
+    let (config_from_file, _) =
+        internal::Config::custom_args_and_optional_files(std::iter::empty::<
+            &str,
+        >())
+        .unwrap_or_exit();
+     */
+
+    debug!("Starting runtime ...");
+    wtclientd::run(
+        config,
+        opts.towers.clone(),
+        opts.session_updates,
+        opts.max_backlog,
+        Duration::from_secs(opts.ack_timeout),
+    )
+    .expect("Error running wtclientd runtime");
+
+    unreachable!()
+}
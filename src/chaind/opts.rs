@@ -0,0 +1,104 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use clap::{AppSettings, Clap};
+use std::str::FromStr;
+
+/// Chain watching daemon; part of LNP Node
+///
+/// The daemon broadcasts transactions on behalf of other node daemons and
+/// notifies subscribed daemons about newly mined transactions. It runs
+/// against a simulated chain, which mines blocks instantly or on demand, and
+/// is intended for development and integration testing of the on-chain
+/// logic; the daemon is built only with `simulation` feature.
+///
+/// The daemon is controlled though ZMQ ctl socket (see `ctl-socket` argument
+/// description)
+#[derive(Clap, Clone, PartialEq, Eq, Debug)]
+#[clap(
+    name = "chaind",
+    bin_name = "chaind",
+    author,
+    version,
+    setting = AppSettings::ColoredHelp
+)]
+pub struct Opts {
+    /// Number of blocks mined right after each broadcast transaction
+    ///
+    /// Zero disables automatic mining: blocks are mined only with `mine`
+    /// command
+    #[clap(long, default_value = "1", env = "LNP_NODE_SIM_CONFIRMATIONS")]
+    pub confirmations: u32,
+
+    /// Initial fee rate estimate reported by the simulated chain, in satoshis
+    /// per 1000 weight units
+    #[clap(long, default_value = "253", env = "LNP_NODE_SIM_FEERATE")]
+    pub feerate: u32,
+
+    /// Scripted chain reorganization in `<height>:<depth>` format
+    ///
+    /// Once the simulated chain reaches the given height, the given number of
+    /// top blocks is replaced with a longer chain. May be repeated
+    #[clap(long = "reorg")]
+    pub reorgs: Vec<ReorgScript>,
+
+    /// RGB configuration: ignored by this daemon
+    #[clap(short, long = "rgb20-rpc")]
+    pub r: Option<String>,
+
+    /// These params can be read also from the configuration file, not just
+    /// command-line args or environment variables
+    #[clap(flatten)]
+    pub shared: crate::opts::Opts,
+}
+
+impl Opts {
+    pub fn process(&mut self) {
+        self.shared.process()
+    }
+}
+
+/// Chain reorganization scheduled at a given block height
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{height}:{depth}")]
+pub struct ReorgScript {
+    /// Height of the chain tip triggering the reorganization
+    pub height: u32,
+
+    /// Number of blocks disconnected from the tip
+    pub depth: u32,
+}
+
+impl FromStr for ReorgScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.split(':');
+        match (split.next(), split.next(), split.next()) {
+            (Some(height), Some(depth), None) => Ok(ReorgScript {
+                height: height.parse().map_err(|_| {
+                    format!("Invalid reorganization height `{}`", height)
+                })?,
+                depth: depth.parse().map_err(|_| {
+                    format!("Invalid reorganization depth `{}`", depth)
+                })?,
+            }),
+            _ => Err(format!(
+                "Reorganization must be given in `<height>:<depth>` format, \
+                 not `{}`",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, HashSet};
+
+use bitcoin::Transaction;
+use internet2::TypedEnum;
+use microservices::esb;
+use microservices::rpc::Failure;
+
+use super::simulator::Simulator;
+use crate::rpc::request::{
+    ChainInfo, ChainReorg, ChannelOutput, OutputLocation, TxDepth,
+};
+use crate::rpc::{Request, ServiceBus};
+use crate::{Config, CtlServer, Error, LogStyle, Senders, Service, ServiceId};
+
+/// Parameters of the simulated chain
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SimulationConfig {
+    /// Number of blocks mined after each broadcast transaction
+    pub confirmations: u32,
+
+    /// Initial fee rate estimate, in satoshis per 1000 weight units
+    pub feerate_per_kw: u32,
+
+    /// Depth of the reorganizations triggered at given chain heights
+    pub reorgs: BTreeMap<u32, u32>,
+}
+
+pub fn run(
+    config: Config,
+    identity: ServiceId,
+    simulation: SimulationConfig,
+) -> Result<(), Error> {
+    let runtime = Runtime {
+        identity,
+        chain: Simulator::new(simulation.feerate_per_kw),
+        confirmations: simulation.confirmations,
+        reorgs: simulation.reorgs,
+        // Daemons acting on mined transactions are subscribed by default;
+        // others have to send `ChainSubscribe` request
+        subscribers: vec![
+            ServiceId::Tower,
+            ServiceId::Swap,
+            ServiceId::Funding,
+            ServiceId::Gossip,
+        ]
+        .into_iter()
+        .collect(),
+        watches: vec![],
+    };
+
+    Service::run(config, runtime, false)
+}
+
+pub struct Runtime {
+    identity: ServiceId,
+    chain: Simulator,
+    confirmations: u32,
+    reorgs: BTreeMap<u32, u32>,
+    subscribers: HashSet<ServiceId>,
+    watches: Vec<(ServiceId, TxDepth)>,
+}
+
+impl CtlServer for Runtime {}
+
+impl esb::Handler<ServiceBus> for Runtime {
+    type Request = Request;
+    type Address = ServiceId;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId {
+        self.identity.clone()
+    }
+
+    fn handle(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        bus: ServiceBus,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Self::Error> {
+        match bus {
+            ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
+            _ => Err(Error::NotSupported(bus, request.get_type())),
+        }
+    }
+
+    fn handle_err(&mut self, _: esb::Error) -> Result<(), esb::Error> {
+        // We do nothing and do not propagate error; it's already being reported
+        // with `error!` macro by the controller. If we propagate error here
+        // this will make whole daemon panic
+        Ok(())
+    }
+}
+
+impl Runtime {
+    fn handle_rpc_ctl(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::Hello(_) => {
+                // Ignoring; this is used to set remote identity at ZMQ level
+            }
+
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
+            Request::ChainSubscribe => {
+                debug!("{} subscribed to chain notifications", source);
+                self.subscribers.insert(source);
+            }
+
+            Request::WatchTransaction(watch) => {
+                debug!(
+                    "{} watches transaction {} for {} confirmation(s)",
+                    source, watch.txid, watch.depth
+                );
+                self.watches.push((source, watch));
+                self.check_watches(senders);
+            }
+
+            Request::GetChannelOutput(short_channel_id) => {
+                let (outpoint, output) = match self
+                    .chain
+                    .unspent_output(short_channel_id)
+                {
+                    Some((outpoint, output)) => (Some(outpoint), Some(output)),
+                    None => (None, None),
+                };
+                self.send_ctl(
+                    senders,
+                    source,
+                    Request::ChannelOutput(ChannelOutput {
+                        short_channel_id,
+                        output,
+                        outpoint,
+                    }),
+                )?;
+            }
+
+            Request::LocateOutput(outpoint) => {
+                let short_channel_id = self.chain.locate(outpoint);
+                self.send_ctl(
+                    senders,
+                    source,
+                    Request::OutputLocation(OutputLocation {
+                        outpoint,
+                        short_channel_id,
+                    }),
+                )?;
+            }
+
+            Request::BroadcastTransaction(tx) => {
+                match self.chain.broadcast(tx) {
+                    Ok(txid) => {
+                        info!(
+                            "{} {} to the simulated mempool",
+                            "Broadcasting transaction".promo(),
+                            txid.promoter()
+                        );
+                        self.mine(senders, self.confirmations)?;
+                    }
+                    Err(info) => {
+                        // The broadcasting daemon does not wait for the reply,
+                        // so we do not report the failure back
+                        warn!("Transaction is rejected: {}", info);
+                    }
+                }
+            }
+
+            Request::MineBlocks(blocks) => {
+                self.mine(senders, blocks)?;
+                self.report_success_to(
+                    senders,
+                    source,
+                    Some(format!(
+                        "Mined {} block(s), chain height is {}",
+                        blocks,
+                        self.chain.height()
+                    )),
+                )?;
+            }
+
+            Request::ReorgChain(ChainReorg { depth, evict }) => {
+                if depth > self.chain.height() {
+                    return Err(self.report_failure_to(
+                        senders,
+                        source,
+                        Failure {
+                            code: 0,
+                            info: format!(
+                                "Reorganization depth {} exceeds chain height \
+                                 {}",
+                                depth,
+                                self.chain.height()
+                            ),
+                        },
+                    ));
+                }
+                self.reorg(senders, depth, evict)?;
+                self.report_success_to(
+                    senders,
+                    source,
+                    Some(format!(
+                        "Replaced {} block(s), chain height is {}",
+                        depth,
+                        self.chain.height()
+                    )),
+                )?;
+            }
+
+            Request::SetFeeEstimate(feerate_per_kw) => {
+                self.chain.set_feerate_per_kw(feerate_per_kw);
+                self.notify_fee(senders, feerate_per_kw);
+                self.report_success_to(
+                    senders,
+                    source,
+                    Some(format!(
+                        "Fee estimate is set to {} sat/kw",
+                        feerate_per_kw
+                    )),
+                )?;
+            }
+
+            Request::GetFeeEstimate => {
+                let feerate_per_kw = self.chain.feerate_per_kw();
+                self.send_ctl(
+                    senders,
+                    source,
+                    Request::FeeEstimate(feerate_per_kw),
+                )?;
+            }
+
+            Request::GetChainInfo => {
+                let info = ChainInfo {
+                    simulated: true,
+                    height: self.chain.height(),
+                    tip_time: self.chain.tip_time(),
+                    mempool_size: self.chain.mempool_size() as u32,
+                    feerate_per_kw: self.chain.feerate_per_kw(),
+                    subscribers: self.subscribers.len() as u32,
+                };
+                self.send_ctl(senders, source, Request::ChainInfo(info))?;
+            }
+
+            _ => {
+                error!("Request is not supported by the CTL interface");
+                return Err(Error::NotSupported(
+                    ServiceBus::Ctl,
+                    request.get_type(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mine(
+        &mut self,
+        senders: &mut Senders,
+        blocks: u32,
+    ) -> Result<(), Error> {
+        for _ in 0..blocks {
+            let block = self.chain.mine();
+            let height = block.height;
+            debug!(
+                "Mined block {} with {} transaction(s)",
+                height,
+                block.txs.len()
+            );
+            let txs = block.txs.clone();
+            self.notify(senders, txs);
+            self.check_watches(senders);
+
+            if let Some(depth) = self.reorgs.remove(&height) {
+                info!("Running scripted reorganization at height {}", height);
+                self.reorg(senders, depth, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces `depth` top blocks with a chain one block longer
+    fn reorg(
+        &mut self,
+        senders: &mut Senders,
+        depth: u32,
+        evict: bool,
+    ) -> Result<(), Error> {
+        let txids = self.chain.disconnect(depth, evict);
+        info!(
+            "{} of {} block(s) unconfirming {} transaction(s)",
+            "Chain reorganization".promo(),
+            depth,
+            txids.len()
+        );
+        self.mine(senders, depth + 1)
+    }
+
+    /// Reports watched transactions which have reached required depth
+    fn check_watches(&mut self, senders: &mut Senders) {
+        let chain = &self.chain;
+        let (confirmed, pending): (Vec<_>, Vec<_>) =
+            self.watches.drain(..).partition(|(_, watch)| {
+                chain.confirmations(&watch.txid).unwrap_or_default()
+                    >= watch.depth
+            });
+        self.watches = pending;
+        for (watcher, watch) in confirmed {
+            if let Err(err) = self.send_ctl(
+                senders,
+                watcher.clone(),
+                Request::TransactionConfirmed(watch),
+            ) {
+                debug!("Unable to notify {}: {}", watcher, err);
+            }
+        }
+    }
+
+    fn notify_fee(&mut self, senders: &mut Senders, feerate_per_kw: u32) {
+        for subscriber in self.subscribers.clone() {
+            if let Err(err) = self.send_ctl(
+                senders,
+                subscriber.clone(),
+                Request::FeeEstimate(feerate_per_kw),
+            ) {
+                debug!("Unable to notify {}: {}", subscriber, err);
+            }
+        }
+    }
+
+    fn notify(&mut self, senders: &mut Senders, txs: Vec<Transaction>) {
+        if txs.is_empty() {
+            return;
+        }
+        for subscriber in self.subscribers.clone() {
+            // Subscribed daemon may be not running; this must not stop the
+            // chain from progressing
+            if let Err(err) = self.send_ctl(
+                senders,
+                subscriber.clone(),
+                Request::ChainTransactions(txs.clone()),
+            ) {
+                debug!("Unable to notify {}: {}", subscriber, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+
+use crate::rpc::request::ShortChannelId;
+
+/// Time between two consecutive simulated blocks, in seconds
+pub const BLOCK_INTERVAL: u32 = 600;
+
+/// Locktime values below this threshold are block heights, otherwise they
+/// are UNIX timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+
+/// Number of blocks which times define median time past (BIP-113)
+const MEDIAN_TIME_SPAN: usize = 11;
+
+pub struct Block {
+    pub height: u32,
+    pub time: u32,
+    pub txs: Vec<Transaction>,
+}
+
+/// Deterministic in-memory chain.
+///
+/// Blocks are mined only on request and contain all mempool transactions
+/// which are final at the block height and time, including transactions
+/// spending outputs of other transactions in the same block. Absolute and
+/// relative timelocks are enforced, with time locks measured against the
+/// median time past (BIP-113), so transactions which are not final yet stay
+/// in the mempool until enough blocks are mined.
+///
+/// Transactions spending outputs unknown to the simulator (i.e. coins from
+/// the wallet) are considered to spend deeply buried outputs.
+pub struct Simulator {
+    genesis_time: u32,
+    blocks: Vec<Block>,
+    mempool: Vec<Transaction>,
+    mined: HashMap<Txid, u32>,
+    spent: HashMap<OutPoint, Txid>,
+    feerate_per_kw: u32,
+}
+
+impl Simulator {
+    pub fn new(feerate_per_kw: u32) -> Simulator {
+        let genesis_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs() as u32;
+        Simulator {
+            genesis_time,
+            blocks: vec![],
+            mempool: vec![],
+            mined: none!(),
+            spent: none!(),
+            feerate_per_kw,
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        self.blocks.len() as u32
+    }
+
+    pub fn tip_time(&self) -> u32 {
+        self.blocks
+            .last()
+            .map(|block| block.time)
+            .unwrap_or(self.genesis_time)
+    }
+
+    /// Median time of the block at the given height and the ones preceding
+    /// it (BIP-113); genesis time for the empty chain
+    fn median_time_past(&self, height: u32) -> u32 {
+        let end = (height as usize).min(self.blocks.len());
+        let start = end.saturating_sub(MEDIAN_TIME_SPAN);
+        let mut times = self.blocks[start..end]
+            .iter()
+            .map(|block| block.time)
+            .collect::<Vec<_>>();
+        if times.is_empty() {
+            return self.genesis_time;
+        }
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    /// Number of confirmations of a mined transaction
+    pub fn confirmations(&self, txid: &Txid) -> Option<u32> {
+        self.mined
+            .get(txid)
+            .map(|height| self.height() - height + 1)
+    }
+
+    /// Mined output at the given position in the chain with its outpoint,
+    /// unless it is spent
+    pub fn unspent_output(
+        &self,
+        position: ShortChannelId,
+    ) -> Option<(OutPoint, TxOut)> {
+        let tx = self
+            .blocks
+            .get(position.block_height.checked_sub(1)? as usize)?
+            .txs
+            .get(position.tx_index as usize)?;
+        let outpoint = OutPoint::new(tx.txid(), position.output_index as u32);
+        if self.spent.contains_key(&outpoint) {
+            return None;
+        }
+        let output = tx.output.get(position.output_index as usize)?;
+        Some((outpoint, output.clone()))
+    }
+
+    /// Position of the mined output in the chain, unless the output does
+    /// not exist or is not mined yet
+    pub fn locate(&self, outpoint: OutPoint) -> Option<ShortChannelId> {
+        let height = *self.mined.get(&outpoint.txid)?;
+        let block = self.blocks.get(height.checked_sub(1)? as usize)?;
+        let tx_index =
+            block.txs.iter().position(|tx| tx.txid() == outpoint.txid)?;
+        if outpoint.vout as usize >= block.txs[tx_index].output.len() {
+            return None;
+        }
+        Some(ShortChannelId {
+            block_height: height,
+            tx_index: tx_index as u32,
+            output_index: outpoint.vout as u16,
+        })
+    }
+
+    pub fn mempool_size(&self) -> usize {
+        self.mempool.len()
+    }
+
+    pub fn feerate_per_kw(&self) -> u32 {
+        self.feerate_per_kw
+    }
+
+    pub fn set_feerate_per_kw(&mut self, feerate_per_kw: u32) {
+        self.feerate_per_kw = feerate_per_kw
+    }
+
+    /// Adds transaction to the mempool; fails if the transaction is already
+    /// known or conflicts with a transaction in the mempool or in the chain
+    pub fn broadcast(&mut self, tx: Transaction) -> Result<Txid, String> {
+        let txid = tx.txid();
+        if self.mined.contains_key(&txid)
+            || self.mempool.iter().any(|known| known.txid() == txid)
+        {
+            return Err(format!("Transaction {} is already known", txid));
+        }
+        let mempool_spent = self
+            .mempool
+            .iter()
+            .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output))
+            .collect::<HashSet<_>>();
+        if let Some(txin) = tx.input.iter().find(|txin| {
+            self.spent.contains_key(&txin.previous_output)
+                || mempool_spent.contains(&txin.previous_output)
+        }) {
+            return Err(format!(
+                "Transaction {} double-spends output {}",
+                txid, txin.previous_output
+            ));
+        }
+        self.mempool.push(tx);
+        Ok(txid)
+    }
+
+    /// Mines a new block, advancing chain time by [`BLOCK_INTERVAL`]
+    pub fn mine(&mut self) -> &Block {
+        let height = self.height() + 1;
+        let time = self.tip_time() + BLOCK_INTERVAL;
+        let mut txs = vec![];
+        // Each pass may make final the transactions spending outputs of the
+        // ones included during the previous pass
+        loop {
+            let mempool = std::mem::take(&mut self.mempool);
+            let unconfirmed = txids_set(&mempool);
+            let (ready, pending): (Vec<_>, Vec<_>) = mempool
+                .into_iter()
+                .partition(|tx| self.is_final(tx, height, &unconfirmed));
+            self.mempool = pending;
+            if ready.is_empty() {
+                break;
+            }
+            for tx in ready {
+                let txid = tx.txid();
+                for txin in &tx.input {
+                    self.spent.insert(txin.previous_output, txid);
+                }
+                self.mined.insert(txid, height);
+                txs.push(tx);
+            }
+        }
+        self.blocks.push(Block { height, time, txs });
+        self.blocks.last().expect("block is just added")
+    }
+
+    /// Disconnects `depth` top blocks. Their transactions are returned to the
+    /// mempool unless `evict` is set, in which case they are dropped together
+    /// with all mempool transactions depending on them.
+    pub fn disconnect(&mut self, depth: u32, evict: bool) -> Vec<Txid> {
+        let depth = (depth as usize).min(self.blocks.len());
+        let disconnected = self
+            .blocks
+            .split_off(self.blocks.len() - depth)
+            .into_iter()
+            .flat_map(|block| block.txs)
+            .collect::<Vec<_>>();
+        for tx in &disconnected {
+            self.mined.remove(&tx.txid());
+            for txin in &tx.input {
+                self.spent.remove(&txin.previous_output);
+            }
+        }
+        let txids = disconnected.iter().map(Transaction::txid).collect();
+        if evict {
+            let mut evicted: HashSet<Txid> = txids_set(&disconnected);
+            // Dropping descendants in a loop, since they may form chains
+            loop {
+                let (dropped, kept): (Vec<_>, Vec<_>) =
+                    self.mempool.drain(..).partition(|tx| {
+                        tx.input.iter().any(|txin| {
+                            evicted.contains(&txin.previous_output.txid)
+                        })
+                    });
+                self.mempool = kept;
+                if dropped.is_empty() {
+                    break;
+                }
+                evicted.extend(txids_set(&dropped));
+            }
+        } else {
+            self.mempool.splice(0..0, disconnected);
+        }
+        txids
+    }
+
+    /// Whether the transaction can be included into the block at the given
+    /// height, which follows the current chain tip
+    fn is_final(
+        &self,
+        tx: &Transaction,
+        height: u32,
+        unconfirmed: &HashSet<Txid>,
+    ) -> bool {
+        let lock_time = tx.lock_time;
+        let locktime_enabled =
+            tx.input.iter().any(|txin| txin.sequence != u32::MAX);
+        // Time locks are measured against the median time past of the
+        // previous block (BIP-113)
+        let median_time = self.median_time_past(height - 1);
+        if locktime_enabled
+            && lock_time != 0
+            && ((lock_time < LOCKTIME_THRESHOLD && lock_time >= height)
+                || (lock_time >= LOCKTIME_THRESHOLD
+                    && lock_time >= median_time))
+        {
+            return false;
+        }
+
+        tx.input.iter().all(|txin| {
+            let prev_height = match self.mined.get(&txin.previous_output.txid) {
+                Some(prev_height) => *prev_height,
+                // Parent transaction is still in mempool
+                None if unconfirmed.contains(&txin.previous_output.txid) => {
+                    return false
+                }
+                // Parent transaction is unknown to the simulator
+                None => return true,
+            };
+            let sequence = txin.sequence;
+            if tx.version < 2 || sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0
+            {
+                return true;
+            }
+            let lock = sequence & SEQUENCE_LOCKTIME_MASK;
+            if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+                // Relative time locks are measured in 512 second units from
+                // the median time past of the block preceding the parent one
+                // (BIP-68)
+                let prev_time =
+                    self.median_time_past(prev_height.saturating_sub(1));
+                median_time.saturating_sub(prev_time) >= lock * 512
+            } else {
+                height - prev_height >= lock
+            }
+        })
+    }
+}
+
+fn txids_set(txs: &[Transaction]) -> HashSet<Txid> {
+    txs.iter().map(Transaction::txid).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::{OutPoint, Script, TxIn};
+
+    use super::*;
+
+    fn locked_tx(lock_time: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0,
+                witness: vec![],
+            }],
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn time_lock_uses_median_time_past() {
+        let mut chain = Simulator::new(253);
+        for _ in 0..MEDIAN_TIME_SPAN {
+            chain.mine();
+        }
+        let median_time = chain.median_time_past(chain.height());
+        assert_eq!(median_time, chain.genesis_time + 6 * BLOCK_INTERVAL);
+        // Locktime preceding the tip time is not yet final
+        let tx = locked_tx(median_time);
+        assert!(tx.lock_time < chain.tip_time());
+        chain.broadcast(tx).unwrap();
+        assert!(chain.mine().txs.is_empty());
+        assert_eq!(chain.mine().txs.len(), 1);
+    }
+}
@@ -0,0 +1,48 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Scripts of the anchor outputs commitment format (`option_anchors`)
+
+use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::secp256k1;
+use bitcoin::Script;
+
+/// Value of each of the anchor outputs
+pub const ANCHOR_OUTPUT_VALUE: u64 = 330;
+
+/// Anchor output spendable by the owner of the funding key or by anyone after
+/// 16 blocks
+pub fn anchor_script(funding_pubkey: secp256k1::PublicKey) -> Script {
+    Builder::new()
+        .push_slice(&funding_pubkey.serialize())
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_IFDUP)
+        .push_opcode(OP_NOTIF)
+        .push_int(16)
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_ENDIF)
+        .into_script()
+}
+
+/// `to_remote` output, which is delayed by a single block so it can't be
+/// used to pin the commitment transaction with a CPFP child
+pub fn to_remote_script(remote_pubkey: secp256k1::PublicKey) -> Script {
+    Builder::new()
+        .push_slice(&remote_pubkey.serialize())
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_int(1)
+        .push_opcode(OP_CSV)
+        .into_script()
+}
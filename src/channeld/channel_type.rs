@@ -0,0 +1,145 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! BOLT-2 channel types negotiated with `channel_type` TLV records of
+//! `open_channel` and `accept_channel` messages
+
+use std::fmt::{self, Display, Formatter};
+
+const STATIC_REMOTEKEY_BIT: u16 = 12;
+const ANCHORS_BIT: u16 = 22;
+const ZERO_CONF_BIT: u16 = 50;
+
+/// Channel type is a set of even feature bits which are not supported by the
+/// node
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display("channel type requires unsupported feature bits {0:?}")]
+pub struct UnsupportedBits(pub Vec<u16>);
+
+/// Features of the channel which are fixed for its lifetime
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct ChannelType {
+    /// `to_remote` outputs pay to the static payment basepoint
+    pub static_remotekey: bool,
+    /// Commitment transactions have anchor outputs and zero-fee HTLC
+    /// transactions
+    pub anchors: bool,
+    /// Channel is used before its funding transaction is confirmed
+    pub zero_conf: bool,
+}
+
+impl Display for ChannelType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut features = vec![];
+        if self.static_remotekey {
+            features.push("static_remotekey");
+        }
+        if self.anchors {
+            features.push("anchors_zero_fee_htlc_tx");
+        }
+        if self.zero_conf {
+            features.push("zeroconf");
+        }
+        if features.is_empty() {
+            f.write_str("legacy")
+        } else {
+            f.write_str(&features.join("+"))
+        }
+    }
+}
+
+impl ChannelType {
+    fn bits(&self) -> Vec<u16> {
+        let mut bits = vec![];
+        if self.static_remotekey {
+            bits.push(STATIC_REMOTEKEY_BIT);
+        }
+        if self.anchors {
+            bits.push(ANCHORS_BIT);
+        }
+        if self.zero_conf {
+            bits.push(ZERO_CONF_BIT);
+        }
+        bits
+    }
+
+    /// Serializes the channel type as a big-endian bit field of minimal
+    /// length
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bits = self.bits();
+        let len = bits.last().map(|bit| *bit as usize / 8 + 1).unwrap_or(0);
+        let mut data = vec![0u8; len];
+        for bit in bits {
+            data[len - 1 - bit as usize / 8] |= 1 << (bit % 8);
+        }
+        data
+    }
+
+    /// Parses the channel type from the bit field, failing if it contains
+    /// any of the features not known to the node
+    pub fn from_bytes(data: &[u8]) -> Result<ChannelType, UnsupportedBits> {
+        let mut channel_type = ChannelType::default();
+        let mut unsupported = vec![];
+        for (index, byte) in data.iter().rev().enumerate() {
+            for shift in 0..8u16 {
+                if byte & (1 << shift) == 0 {
+                    continue;
+                }
+                match index as u16 * 8 + shift {
+                    STATIC_REMOTEKEY_BIT => {
+                        channel_type.static_remotekey = true
+                    }
+                    ANCHORS_BIT => channel_type.anchors = true,
+                    ZERO_CONF_BIT => channel_type.zero_conf = true,
+                    bit => unsupported.push(bit),
+                }
+            }
+        }
+        if !unsupported.is_empty() {
+            return Err(UnsupportedBits(unsupported));
+        }
+        Ok(channel_type)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bit_field() {
+        let channel_type = ChannelType {
+            static_remotekey: true,
+            anchors: true,
+            zero_conf: false,
+        };
+        assert_eq!(channel_type.to_bytes(), vec![0x40, 0x10, 0x00]);
+        assert_eq!(
+            ChannelType::from_bytes(&[0x40, 0x10, 0x00]),
+            Ok(channel_type)
+        );
+        assert_eq!(ChannelType::default().to_bytes(), Vec::<u8>::new());
+        assert_eq!(
+            ChannelType::from_bytes(&[0x04, 0, 0, 0, 0, 0, 0]),
+            Ok(ChannelType {
+                zero_conf: true,
+                ..ChannelType::default()
+            })
+        );
+        assert_eq!(
+            ChannelType::from_bytes(&[0x20, 0x00]),
+            Err(UnsupportedBits(vec![13]))
+        );
+    }
+}
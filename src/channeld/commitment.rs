@@ -0,0 +1,522 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! BOLT3 commitment transaction with the obscured commitment number and
+//! scripts of its outputs
+
+use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::{hash160, ripemd160, sha256, Hash, HashEngine};
+use bitcoin::secp256k1;
+use bitcoin::{OutPoint, Script, SigHashType, Transaction, TxIn, TxOut};
+
+/// Commitment numbers are obscured within the lower 48 bits of lock time and
+/// sequence
+const COMMITMENT_NUMBER_MASK: u64 = 0xFFFF_FFFF_FFFF;
+
+/// Factor obscuring commitment numbers: lower 48 bits of
+/// `SHA256(funder payment_basepoint || fundee payment_basepoint)`
+pub fn obscuring_factor(
+    funder_payment_basepoint: secp256k1::PublicKey,
+    fundee_payment_basepoint: secp256k1::PublicKey,
+) -> u64 {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&funder_payment_basepoint.serialize());
+    engine.input(&fundee_payment_basepoint.serialize());
+    let obscuring_hash = sha256::Hash::from_engine(engine);
+
+    let mut buf = [0u8; 8];
+    buf[2..].copy_from_slice(&obscuring_hash[26..]);
+    u64::from_be_bytes(buf)
+}
+
+/// Commitment transaction without outputs, spending the funding output and
+/// keeping the obscured commitment number in its lock time and sequence
+pub fn base_tx(
+    funding_outpoint: OutPoint,
+    commitment_number: u64,
+    obscuring_factor: u64,
+) -> Transaction {
+    let obscured =
+        (commitment_number ^ obscuring_factor) & COMMITMENT_NUMBER_MASK;
+    Transaction {
+        version: 2,
+        lock_time: 0x2000_0000 | (obscured & 0xFF_FFFF) as u32,
+        input: vec![TxIn {
+            previous_output: funding_outpoint,
+            script_sig: Script::new(),
+            sequence: 0x8000_0000 | (obscured >> 24) as u32,
+            witness: vec![],
+        }],
+        output: vec![],
+    }
+}
+
+/// Extracts commitment number obscured within the commitment transaction
+pub fn commitment_number(tx: &Transaction, obscuring_factor: u64) -> u64 {
+    let sequence = tx.input.first().map(|txin| txin.sequence).unwrap_or(0);
+    let obscured =
+        (sequence as u64 & 0xFF_FFFF) << 24 | (tx.lock_time as u64 & 0xFF_FFFF);
+    (obscured ^ obscuring_factor) & COMMITMENT_NUMBER_MASK
+}
+
+/// `to_local` output of the commitment and outputs of the second-stage HTLC
+/// transactions, spendable by the owner after `to_self_delay` blocks or by
+/// the counterparty with the revocation key
+pub fn to_local_script(
+    revocation_pubkey: secp256k1::PublicKey,
+    delayed_pubkey: secp256k1::PublicKey,
+    to_self_delay: u16,
+) -> Script {
+    Builder::new()
+        .push_opcode(OP_IF)
+        .push_slice(&revocation_pubkey.serialize())
+        .push_opcode(OP_ELSE)
+        .push_int(to_self_delay as i64)
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_DROP)
+        .push_slice(&delayed_pubkey.serialize())
+        .push_opcode(OP_ENDIF)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Branch of the HTLC scripts spending the output with the revocation key
+fn revocation_branch(revocation_pubkey: secp256k1::PublicKey) -> Builder {
+    Builder::new()
+        .push_opcode(OP_DUP)
+        .push_opcode(OP_HASH160)
+        .push_slice(&hash160::Hash::hash(&revocation_pubkey.serialize())[..])
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_IF)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ELSE)
+}
+
+/// Closes the HTLC script; with `option_anchors` HTLC outputs can't be spent
+/// in the same block as the commitment, preventing pinning with CPFP
+fn close_htlc_script(builder: Builder, anchors: bool) -> Script {
+    let builder = if anchors {
+        builder.push_int(1).push_opcode(OP_CSV).push_opcode(OP_DROP)
+    } else {
+        builder
+    };
+    builder.push_opcode(OP_ENDIF).into_script()
+}
+
+/// HTLC offered by the commitment owner, which is spent by the counterparty
+/// with the payment preimage or by HTLC-timeout transaction signed by both
+/// sides
+pub fn offered_htlc_script(
+    revocation_pubkey: secp256k1::PublicKey,
+    local_htlc_pubkey: secp256k1::PublicKey,
+    remote_htlc_pubkey: secp256k1::PublicKey,
+    payment_hash: &[u8],
+    anchors: bool,
+) -> Script {
+    let builder = revocation_branch(revocation_pubkey)
+        .push_slice(&remote_htlc_pubkey.serialize())
+        .push_opcode(OP_SWAP)
+        .push_opcode(OP_SIZE)
+        .push_int(32)
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_NOTIF)
+        .push_opcode(OP_DROP)
+        .push_int(2)
+        .push_opcode(OP_SWAP)
+        .push_slice(&local_htlc_pubkey.serialize())
+        .push_int(2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .push_opcode(OP_ELSE)
+        .push_opcode(OP_HASH160)
+        .push_slice(&ripemd160::Hash::hash(payment_hash)[..])
+        .push_opcode(OP_EQUALVERIFY)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ENDIF);
+    close_htlc_script(builder, anchors)
+}
+
+/// HTLC received by the commitment owner, which is spent by HTLC-success
+/// transaction signed by both sides or by the counterparty after the HTLC
+/// expiry
+pub fn received_htlc_script(
+    revocation_pubkey: secp256k1::PublicKey,
+    local_htlc_pubkey: secp256k1::PublicKey,
+    remote_htlc_pubkey: secp256k1::PublicKey,
+    payment_hash: &[u8],
+    cltv_expiry: u32,
+    anchors: bool,
+) -> Script {
+    let builder = revocation_branch(revocation_pubkey)
+        .push_slice(&remote_htlc_pubkey.serialize())
+        .push_opcode(OP_SWAP)
+        .push_opcode(OP_SIZE)
+        .push_int(32)
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_IF)
+        .push_opcode(OP_HASH160)
+        .push_slice(&ripemd160::Hash::hash(payment_hash)[..])
+        .push_opcode(OP_EQUALVERIFY)
+        .push_int(2)
+        .push_opcode(OP_SWAP)
+        .push_slice(&local_htlc_pubkey.serialize())
+        .push_int(2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .push_opcode(OP_ELSE)
+        .push_opcode(OP_DROP)
+        .push_int(cltv_expiry as i64)
+        .push_opcode(OP_CLTV)
+        .push_opcode(OP_DROP)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ENDIF);
+    close_htlc_script(builder, anchors)
+}
+
+/// Second-stage HTLC-timeout transaction, timelocked until the HTLC expiry,
+/// or HTLC-success transaction spending HTLC output of the commitment to the
+/// `to_local` script of the commitment owner
+pub fn htlc_tx(
+    htlc_outpoint: OutPoint,
+    value: u64,
+    cltv_expiry: Option<u32>,
+    anchors: bool,
+    to_local_script: &Script,
+) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: cltv_expiry.unwrap_or(0),
+        input: vec![TxIn {
+            previous_output: htlc_outpoint,
+            script_sig: Script::new(),
+            sequence: if anchors { 1 } else { 0 },
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value,
+            script_pubkey: to_local_script.to_v0_p2wsh(),
+        }],
+    }
+}
+
+/// Witness of the second-stage HTLC transaction signed by both sides.
+/// HTLC-success transaction reveals the payment preimage, which is replaced
+/// with an empty element in HTLC-timeout transaction.
+pub fn htlc_tx_witness(
+    remote_signature: secp256k1::Signature,
+    local_signature: secp256k1::Signature,
+    preimage: Option<&[u8]>,
+    anchors: bool,
+    witness_script: &Script,
+) -> Vec<Vec<u8>> {
+    // With anchors the remote signature allows adding inputs and outputs
+    // for bumping the transaction fee
+    let remote_sighash_type = if anchors {
+        SigHashType::SinglePlusAnyoneCanPay
+    } else {
+        SigHashType::All
+    };
+    let mut remote_signature = remote_signature.serialize_der().to_vec();
+    remote_signature.push(remote_sighash_type.as_u32() as u8);
+    let mut local_signature = local_signature.serialize_der().to_vec();
+    local_signature.push(SigHashType::All.as_u32() as u8);
+    // Leading empty element is consumed by OP_CHECKMULTISIG
+    vec![
+        vec![],
+        remote_signature,
+        local_signature,
+        preimage.map(<[u8]>::to_vec).unwrap_or_default(),
+        witness_script.to_bytes(),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+    use bitcoin::PublicKey;
+    use std::str::FromStr;
+
+    // BOLT3 Appendix C: Commitment and HTLC Transaction Test Vectors
+    fn pubkey(hex: &str) -> secp256k1::PublicKey {
+        secp256k1::PublicKey::from_str(hex).unwrap()
+    }
+
+    fn funding_outpoint() -> OutPoint {
+        OutPoint::new(
+            "8984484a580b825b9972d7adb15050b3ab624ccd731946b3eeddb92f4e7ef6be"
+                .parse()
+                .unwrap(),
+            0,
+        )
+    }
+
+    fn local_payment_basepoint() -> secp256k1::PublicKey {
+        pubkey(
+            "034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa",
+        )
+    }
+
+    fn remote_payment_basepoint() -> secp256k1::PublicKey {
+        pubkey(
+            "032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991",
+        )
+    }
+
+    fn revocation_pubkey() -> secp256k1::PublicKey {
+        pubkey(
+            "0212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402bf2b19",
+        )
+    }
+
+    fn delayed_pubkey() -> secp256k1::PublicKey {
+        pubkey(
+            "03fd5960528dc152014952efdb702a88f71e3c1653b2314431701ec77e57fde83c",
+        )
+    }
+
+    fn local_htlc_pubkey() -> secp256k1::PublicKey {
+        pubkey(
+            "030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e7",
+        )
+    }
+
+    fn remote_htlc_pubkey() -> secp256k1::PublicKey {
+        pubkey(
+            "0394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b",
+        )
+    }
+
+    fn to_remote_script() -> Script {
+        Script::new_v0_wpkh(
+            &PublicKey {
+                compressed: true,
+                key: remote_payment_basepoint(),
+            }
+            .wpubkey_hash()
+            .unwrap(),
+        )
+    }
+
+    /// Commitment #42 with its `to_local` and `to_remote` outputs
+    fn commitment_tx(
+        to_local: u64,
+        to_remote: u64,
+        htlc_outputs: Vec<TxOut>,
+    ) -> Transaction {
+        let factor = obscuring_factor(
+            local_payment_basepoint(),
+            remote_payment_basepoint(),
+        );
+        let mut tx = base_tx(funding_outpoint(), 42, factor);
+        tx.output.push(TxOut {
+            value: to_local,
+            script_pubkey: to_local_script(
+                revocation_pubkey(),
+                delayed_pubkey(),
+                144,
+            )
+            .to_v0_p2wsh(),
+        });
+        tx.output.push(TxOut {
+            value: to_remote,
+            script_pubkey: to_remote_script(),
+        });
+        tx.output.extend(htlc_outputs);
+        tx.output.sort_by(|a, b| {
+            (a.value, &a.script_pubkey).cmp(&(b.value, &b.script_pubkey))
+        });
+        tx
+    }
+
+    /// HTLCs 0 to 4 of the test vectors; HTLC #n has preimage with all bytes
+    /// set to n
+    fn htlc_scripts(anchors: bool) -> Vec<(u64, Script)> {
+        vec![
+            (false, 1000, 500),
+            (false, 2000, 501),
+            (true, 2000, 502),
+            (true, 3000, 503),
+            (false, 4000, 504),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(no, (offered, amount, cltv_expiry))| {
+            let payment_hash = sha256::Hash::hash(&[no as u8; 32]);
+            let script = if offered {
+                offered_htlc_script(
+                    revocation_pubkey(),
+                    local_htlc_pubkey(),
+                    remote_htlc_pubkey(),
+                    &payment_hash[..],
+                    anchors,
+                )
+            } else {
+                received_htlc_script(
+                    revocation_pubkey(),
+                    local_htlc_pubkey(),
+                    remote_htlc_pubkey(),
+                    &payment_hash[..],
+                    cltv_expiry,
+                    anchors,
+                )
+            };
+            (amount, script)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn obscured_commitment_number() {
+        let factor = obscuring_factor(
+            local_payment_basepoint(),
+            remote_payment_basepoint(),
+        );
+        assert_eq!(factor, 0x2bb038521914);
+
+        let tx = base_tx(funding_outpoint(), 42, factor);
+        assert_eq!(tx.lock_time, 0x2052193e);
+        assert_eq!(tx.input[0].sequence, 0x802bb038);
+        assert_eq!(commitment_number(&tx, factor), 42);
+    }
+
+    #[test]
+    fn to_local() {
+        assert_eq!(
+            to_local_script(revocation_pubkey(), delayed_pubkey(), 144)
+                .to_hex(),
+            "63210212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402\
+             bf2b1967029000b2752103fd5960528dc152014952efdb702a88f71e3c1653b2\
+             314431701ec77e57fde83c68ac"
+        );
+    }
+
+    #[test]
+    fn simple_commitment() {
+        // Simple commitment tx with no HTLCs
+        let tx = commitment_tx(6989140, 3000000, vec![]);
+        assert_eq!(
+            tx.txid().to_string(),
+            "35af2c90e84decff1c178c6d600bc0e9de29af15a11b3711db623f960f24ae11"
+        );
+    }
+
+    #[test]
+    fn htlc_outputs() {
+        let expected = [
+            "52bfef0479d7b293c27e0f1eb294bea154c63a3294ef092c19af51409bce0e2a",
+            "748eba944fedc8827f6b06bc44678f93c0f9e6078b35c6331ed31e75f8ce0c2d",
+            "403d394747cae42e98ff01734ad5c08f82ba123d3d9a620abda88989651e2ab5",
+            "c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419",
+            "8c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4",
+        ];
+        for ((_, script), expected) in htlc_scripts(false).iter().zip(&expected)
+        {
+            assert_eq!(
+                script.to_v0_p2wsh(),
+                Script::from(
+                    Vec::from_hex(&format!("0020{}", expected)).unwrap()
+                )
+            );
+        }
+
+        // Commitment tx with all five HTLCs untrimmed (minimum feerate)
+        let htlc_outputs = htlc_scripts(false)
+            .into_iter()
+            .map(|(value, script)| TxOut {
+                value,
+                script_pubkey: script.to_v0_p2wsh(),
+            })
+            .collect();
+        let tx = commitment_tx(6988000, 3000000, htlc_outputs);
+        assert_eq!(
+            tx.txid().to_string(),
+            "2b887d4c1c59cd605144a1e2f971d168437db453f841f2fefb2c164f28ff84ab"
+        );
+    }
+
+    #[test]
+    fn anchor_htlc_outputs() {
+        // With `option_anchors` HTLC scripts end with
+        // `1 OP_CHECKSEQUENCEVERIFY OP_DROP OP_ENDIF`
+        for ((_, plain), (_, anchor)) in
+            htlc_scripts(false).iter().zip(htlc_scripts(true).iter())
+        {
+            let plain = plain.to_bytes();
+            let mut expected = plain[..plain.len() - 1].to_vec();
+            expected.extend(&[0x51, 0xb2, 0x75, 0x68]);
+            assert_eq!(anchor.to_bytes(), expected);
+        }
+    }
+
+    #[test]
+    fn htlc_timeout_tx() {
+        let (amount, _) = htlc_scripts(false)[2];
+        let outpoint =
+            OutPoint::new(commitment_tx(6988000, 3000000, vec![]).txid(), 2);
+        let to_local =
+            to_local_script(revocation_pubkey(), delayed_pubkey(), 144);
+
+        let tx = htlc_tx(outpoint, amount, Some(502), false, &to_local);
+        assert_eq!(tx.lock_time, 502);
+        assert_eq!(tx.input[0].previous_output, outpoint);
+        assert_eq!(tx.input[0].sequence, 0);
+        assert_eq!(tx.output[0].value, amount);
+        assert_eq!(tx.output[0].script_pubkey, to_local.to_v0_p2wsh());
+
+        // HTLC outputs of anchor commitments are spent after one block
+        let tx = htlc_tx(outpoint, amount, Some(502), true, &to_local);
+        assert_eq!(tx.input[0].sequence, 1);
+
+        // HTLC-success transaction is not timelocked
+        let tx = htlc_tx(outpoint, amount, None, false, &to_local);
+        assert_eq!(tx.lock_time, 0);
+    }
+
+    #[test]
+    fn htlc_witness() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let msg = secp256k1::Message::from_slice(&[1u8; 32]).unwrap();
+        let sign = |byte: u8| {
+            let key = secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap();
+            secp.sign(&msg, &key)
+        };
+        let (remote, local) = (sign(2), sign(3));
+        let (_, script) = htlc_scripts(false).remove(0);
+        let preimage = [0u8; 32];
+
+        let witness =
+            htlc_tx_witness(remote, local, Some(&preimage), false, &script);
+        assert_eq!(witness.len(), 5);
+        assert!(witness[0].is_empty());
+        assert_eq!(
+            witness[1][..witness[1].len() - 1],
+            remote.serialize_der()[..]
+        );
+        assert_eq!(witness[1].last(), Some(&0x01));
+        assert_eq!(
+            witness[2][..witness[2].len() - 1],
+            local.serialize_der()[..]
+        );
+        assert_eq!(witness[2].last(), Some(&0x01));
+        assert_eq!(witness[3], preimage.to_vec());
+        assert_eq!(witness[4], script.to_bytes());
+
+        // HTLC-timeout has no preimage, and with anchors the remote
+        // signature is SIGHASH_SINGLE|SIGHASH_ANYONECANPAY
+        let witness = htlc_tx_witness(remote, local, None, true, &script);
+        assert!(witness[3].is_empty());
+        assert_eq!(witness[1].last(), Some(&0x83));
+        assert_eq!(witness[2].last(), Some(&0x01));
+    }
+}
@@ -0,0 +1,101 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! HTLC failure reasons as defined by BOLT-4
+
+/// Failure code flags
+const BADONION: u16 = 0x8000;
+const PERM: u16 = 0x4000;
+const NODE: u16 = 0x2000;
+const UPDATE: u16 = 0x1000;
+
+/// Size of HMAC preceding the failure message in the failure packet
+const HMAC_LEN: usize = 32;
+
+/// Reason of HTLC failure reported by a remote node
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HtlcFailure {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl HtlcFailure {
+    pub const INCORRECT_OR_UNKNOWN_PAYMENT_DETAILS: u16 = PERM | 15;
+
+    pub fn with(code: u16) -> HtlcFailure {
+        HtlcFailure { code, data: vec![] }
+    }
+
+    /// Parses failure packet from `update_fail_htlc` message.
+    ///
+    /// TODO: Decrypt the packet with the shared secrets of the onion route
+    ///       once onion packets will be constructed
+    pub fn parse(reason: &[u8]) -> Option<HtlcFailure> {
+        let mut len = [0u8; 2];
+        len.copy_from_slice(reason.get(HMAC_LEN..HMAC_LEN + 2)?);
+        let len = u16::from_be_bytes(len) as usize;
+        let msg = reason.get(HMAC_LEN + 2..HMAC_LEN + 2 + len)?;
+        if msg.len() < 2 {
+            return None;
+        }
+        Some(HtlcFailure {
+            code: u16::from_be_bytes([msg[0], msg[1]]),
+            data: msg[2..].to_vec(),
+        })
+    }
+
+    /// Serializes failure into the packet for `update_fail_htlc` message
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut msg = self.code.to_be_bytes().to_vec();
+        msg.extend(&self.data);
+        let mut packet = vec![0u8; HMAC_LEN];
+        packet.extend(&(msg.len() as u16).to_be_bytes());
+        packet.extend(msg);
+        packet.extend(&0u16.to_be_bytes());
+        packet
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.code {
+            c if c == BADONION | PERM | 4 => "invalid_onion_version",
+            c if c == BADONION | PERM | 5 => "invalid_onion_hmac",
+            c if c == BADONION | PERM | 6 => "invalid_onion_key",
+            c if c == UPDATE | 7 => "temporary_channel_failure",
+            c if c == PERM | 8 => "permanent_channel_failure",
+            c if c == PERM | 9 => "required_channel_feature_missing",
+            c if c == PERM | 10 => "unknown_next_peer",
+            c if c == UPDATE | 11 => "amount_below_minimum",
+            c if c == UPDATE | 12 => "fee_insufficient",
+            c if c == UPDATE | 13 => "incorrect_cltv_expiry",
+            c if c == UPDATE | 14 => "expiry_too_soon",
+            c if c == PERM | 15 => "incorrect_or_unknown_payment_details",
+            18 => "final_incorrect_cltv_expiry",
+            19 => "final_incorrect_htlc_amount",
+            c if c == UPDATE | 20 => "channel_disabled",
+            21 => "expiry_too_far",
+            c if c == NODE | 2 => "temporary_node_failure",
+            c if c == PERM | NODE | 2 => "permanent_node_failure",
+            c if c == PERM | NODE | 3 => "required_node_feature_missing",
+            c if c == PERM | 22 => "invalid_onion_payload",
+            23 => "mpp_timeout",
+            _ => "unknown_failure",
+        }
+    }
+}
+
+impl std::fmt::Display for HtlcFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({:#06x})", self.name(), self.code)
+    }
+}
@@ -0,0 +1,110 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! BOLT3 fee calculation for commitment and second-stage HTLC transactions
+
+use super::anchors::ANCHOR_OUTPUT_VALUE;
+
+/// BOLT3 weight of commitment transaction without HTLC outputs
+pub const COMMITMENT_TX_WEIGHT: u64 = 724;
+
+/// BOLT3 weight of anchor commitment transaction without HTLC outputs
+pub const ANCHOR_COMMITMENT_TX_WEIGHT: u64 = 1124;
+
+/// Weight added to the commitment transaction by each of HTLC outputs
+pub const HTLC_OUTPUT_WEIGHT: u64 = 172;
+
+/// BOLT3 weights of the second-stage HTLC transactions
+pub const HTLC_TIMEOUT_WEIGHT: u64 = 663;
+pub const HTLC_SUCCESS_WEIGHT: u64 = 703;
+
+/// Fee of a transaction with the given weight; BOLT3 requires rounding down
+pub fn fee(feerate_per_kw: u32, weight: u64) -> u64 {
+    feerate_per_kw as u64 * weight / 1000
+}
+
+/// Weight of a commitment transaction with the given number of HTLC outputs
+/// which are not trimmed to dust
+pub fn commitment_weight(anchors: bool, htlc_outputs: usize) -> u64 {
+    let base_weight = if anchors {
+        ANCHOR_COMMITMENT_TX_WEIGHT
+    } else {
+        COMMITMENT_TX_WEIGHT
+    };
+    base_weight + HTLC_OUTPUT_WEIGHT * htlc_outputs as u64
+}
+
+/// Fee of a commitment transaction, which is paid by the channel funder
+pub fn commitment_fee(
+    feerate_per_kw: u32,
+    anchors: bool,
+    htlc_outputs: usize,
+) -> u64 {
+    fee(feerate_per_kw, commitment_weight(anchors, htlc_outputs))
+}
+
+/// Fee of the second-stage HTLC-timeout transaction for HTLCs offered by the
+/// commitment owner, or HTLC-success transaction otherwise
+pub fn htlc_tx_fee(feerate_per_kw: u32, anchors: bool, offered: bool) -> u64 {
+    // With anchors HTLC transactions have zero fee and are bumped by adding
+    // inputs, which is allowed by their signature hash type
+    if anchors {
+        return 0;
+    }
+    let weight = if offered {
+        HTLC_TIMEOUT_WEIGHT
+    } else {
+        HTLC_SUCCESS_WEIGHT
+    };
+    fee(feerate_per_kw, weight)
+}
+
+/// Checks whether HTLC can't pay for its second-stage transaction and thus
+/// is trimmed from the commitment transaction. HTLC amount is in
+/// millisatoshis, which are rounded down to the output value in satoshis.
+pub fn is_dust_htlc(
+    amount_msat: u64,
+    offered: bool,
+    feerate_per_kw: u32,
+    anchors: bool,
+    dust_limit_satoshis: u64,
+) -> bool {
+    amount_msat / 1000
+        < dust_limit_satoshis + htlc_tx_fee(feerate_per_kw, anchors, offered)
+}
+
+/// Deducts commitment fee and value of the anchor outputs from the funder
+/// amount. If the funder can't pay the whole fee, it pays as much as it can.
+pub fn funder_amount(funder_amount: u64, fee: u64, anchors: bool) -> u64 {
+    let anchors_value = if anchors { 2 * ANCHOR_OUTPUT_VALUE } else { 0 };
+    funder_amount
+        .saturating_sub(fee)
+        .saturating_sub(anchors_value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dust_htlc_msat() {
+        // 546 sat dust limit plus 663 sat HTLC-timeout fee at 1000 sat/kw
+        assert!(is_dust_htlc(1_208_999, true, 1000, false, 546));
+        assert!(!is_dust_htlc(1_209_001, true, 1000, false, 546));
+        // HTLC-success transaction is heavier
+        assert!(is_dust_htlc(1_248_999, false, 1000, false, 546));
+        // With anchors HTLC transactions have no fee
+        assert!(!is_dust_htlc(546_001, false, 1000, true, 546));
+        assert!(is_dust_htlc(545_999, false, 1000, true, 546));
+    }
+}
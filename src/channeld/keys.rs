@@ -0,0 +1,363 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! BOLT3 key derivation
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Secp256k1};
+use lnp::payment::channel::Keyset;
+
+/// Keys of a commitment transaction, derived from the basepoints of both
+/// sides and the per-commitment point of the commitment owner
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CommitmentKeys {
+    /// Key of the counterparty spending outputs of the revoked commitment
+    pub revocation_pubkey: secp256k1::PublicKey,
+    /// Key of the owner spending `to_local` and second-stage HTLC outputs
+    /// after `to_self_delay`
+    pub delayed_pubkey: secp256k1::PublicKey,
+    /// HTLC key of the commitment owner
+    pub owner_htlc_pubkey: secp256k1::PublicKey,
+    /// HTLC key of the counterparty
+    pub counterparty_htlc_pubkey: secp256k1::PublicKey,
+    /// Key of the counterparty receiving `to_remote` output
+    pub to_remote_pubkey: secp256k1::PublicKey,
+}
+
+impl CommitmentKeys {
+    pub fn derive(
+        owner: &Keyset,
+        counterparty: &Keyset,
+        per_commitment_point: secp256k1::PublicKey,
+        static_remotekey: bool,
+    ) -> CommitmentKeys {
+        CommitmentKeys {
+            revocation_pubkey: derive_revocation_pubkey(
+                counterparty.revocation_basepoint,
+                per_commitment_point,
+            ),
+            delayed_pubkey: derive_pubkey(
+                owner.delayed_payment_basepoint,
+                per_commitment_point,
+            ),
+            owner_htlc_pubkey: derive_pubkey(
+                owner.htlc_basepoint,
+                per_commitment_point,
+            ),
+            counterparty_htlc_pubkey: derive_pubkey(
+                counterparty.htlc_basepoint,
+                per_commitment_point,
+            ),
+            to_remote_pubkey: if static_remotekey {
+                counterparty.payment_basepoint
+            } else {
+                derive_pubkey(
+                    counterparty.payment_basepoint,
+                    per_commitment_point,
+                )
+            },
+        }
+    }
+}
+
+/// Secret keys of a channel: funding key and basepoint secrets, each of
+/// which is derived from the channel seed with its own tag
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChannelKeys {
+    pub funding_secret: secp256k1::SecretKey,
+    pub revocation_basepoint_secret: secp256k1::SecretKey,
+    pub payment_basepoint_secret: secp256k1::SecretKey,
+    pub delayed_payment_basepoint_secret: secp256k1::SecretKey,
+    pub htlc_basepoint_secret: secp256k1::SecretKey,
+}
+
+impl ChannelKeys {
+    /// Derives channel keys from the channel seed
+    pub fn derive(seed: [u8; 32]) -> ChannelKeys {
+        ChannelKeys {
+            funding_secret: derive_channel_key(&seed, b"funding"),
+            revocation_basepoint_secret: derive_channel_key(
+                &seed,
+                b"revocation",
+            ),
+            payment_basepoint_secret: derive_channel_key(&seed, b"payment"),
+            delayed_payment_basepoint_secret: derive_channel_key(
+                &seed,
+                b"delayed_payment",
+            ),
+            htlc_basepoint_secret: derive_channel_key(&seed, b"htlc"),
+        }
+    }
+
+    pub fn funding_pubkey(&self) -> secp256k1::PublicKey {
+        pubkey(&self.funding_secret)
+    }
+
+    pub fn revocation_basepoint(&self) -> secp256k1::PublicKey {
+        pubkey(&self.revocation_basepoint_secret)
+    }
+
+    pub fn payment_basepoint(&self) -> secp256k1::PublicKey {
+        pubkey(&self.payment_basepoint_secret)
+    }
+
+    pub fn delayed_payment_basepoint(&self) -> secp256k1::PublicKey {
+        pubkey(&self.delayed_payment_basepoint_secret)
+    }
+
+    pub fn htlc_basepoint(&self) -> secp256k1::PublicKey {
+        pubkey(&self.htlc_basepoint_secret)
+    }
+}
+
+fn pubkey(secret: &secp256k1::SecretKey) -> secp256k1::PublicKey {
+    secp256k1::PublicKey::from_secret_key(&Secp256k1::signing_only(), secret)
+}
+
+/// Derives channel key for the given purpose tag:
+/// `SHA256(seed || tag)`, rehashed in the negligible case it is not a valid
+/// secret key
+fn derive_channel_key(seed: &[u8; 32], tag: &[u8]) -> secp256k1::SecretKey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(seed);
+    engine.input(tag);
+    let mut hash = sha256::Hash::from_engine(engine);
+    loop {
+        if let Ok(secret) = secp256k1::SecretKey::from_slice(&hash[..]) {
+            return secret;
+        }
+        hash = sha256::Hash::hash(&hash[..]);
+    }
+}
+
+fn tweak(
+    first: &secp256k1::PublicKey,
+    second: &secp256k1::PublicKey,
+) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&first.serialize());
+    engine.input(&second.serialize());
+    sha256::Hash::from_engine(engine)
+}
+
+/// Derives commitment-specific public key from a basepoint:
+/// `basepoint + SHA256(per_commitment_point || basepoint) * G`
+pub fn derive_pubkey(
+    basepoint: secp256k1::PublicKey,
+    per_commitment_point: secp256k1::PublicKey,
+) -> secp256k1::PublicKey {
+    let tweak = tweak(&per_commitment_point, &basepoint);
+
+    let mut pubkey = basepoint;
+    pubkey
+        .add_exp_assign(&Secp256k1::verification_only(), &tweak[..])
+        .expect("negligible probability of tweak overflow");
+    pubkey
+}
+
+/// Derives secret key for the public key produced by [`derive_pubkey`] from
+/// the basepoint secret:
+/// `basepoint_secret + SHA256(per_commitment_point || basepoint)`
+pub fn derive_secret(
+    basepoint_secret: secp256k1::SecretKey,
+    per_commitment_point: secp256k1::PublicKey,
+) -> secp256k1::SecretKey {
+    let basepoint = secp256k1::PublicKey::from_secret_key(
+        &Secp256k1::signing_only(),
+        &basepoint_secret,
+    );
+    let tweak = tweak(&per_commitment_point, &basepoint);
+
+    let mut secret = basepoint_secret;
+    secret
+        .add_assign(&tweak[..])
+        .expect("negligible probability of tweak overflow");
+    secret
+}
+
+/// Derives revocation public key from the revocation basepoint of the
+/// counterparty and the per-commitment point of the commitment owner:
+/// `revocation_basepoint * SHA256(revocation_basepoint || per_commitment_point)
+/// + per_commitment_point * SHA256(per_commitment_point || revocation_basepoint)`
+pub fn derive_revocation_pubkey(
+    revocation_basepoint: secp256k1::PublicKey,
+    per_commitment_point: secp256k1::PublicKey,
+) -> secp256k1::PublicKey {
+    let secp = Secp256k1::verification_only();
+    let mut basepoint_part = revocation_basepoint;
+    basepoint_part
+        .mul_assign(
+            &secp,
+            &tweak(&revocation_basepoint, &per_commitment_point)[..],
+        )
+        .expect("negligible probability of tweak overflow");
+    let mut point_part = per_commitment_point;
+    point_part
+        .mul_assign(
+            &secp,
+            &tweak(&per_commitment_point, &revocation_basepoint)[..],
+        )
+        .expect("negligible probability of tweak overflow");
+    basepoint_part
+        .combine(&point_part)
+        .expect("negligible probability of points summing to infinity")
+}
+
+/// Derives secret key for the public key produced by
+/// [`derive_revocation_pubkey`], which is known only once the commitment
+/// owner reveals the per-commitment secret
+pub fn derive_revocation_secret(
+    revocation_basepoint_secret: secp256k1::SecretKey,
+    per_commitment_secret: secp256k1::SecretKey,
+) -> secp256k1::SecretKey {
+    let secp = Secp256k1::signing_only();
+    let revocation_basepoint = secp256k1::PublicKey::from_secret_key(
+        &secp,
+        &revocation_basepoint_secret,
+    );
+    let per_commitment_point =
+        secp256k1::PublicKey::from_secret_key(&secp, &per_commitment_secret);
+
+    let mut basepoint_part = revocation_basepoint_secret;
+    basepoint_part
+        .mul_assign(&tweak(&revocation_basepoint, &per_commitment_point)[..])
+        .expect("negligible probability of tweak overflow");
+    let mut secret_part = per_commitment_secret;
+    secret_part
+        .mul_assign(&tweak(&per_commitment_point, &revocation_basepoint)[..])
+        .expect("negligible probability of tweak overflow");
+    basepoint_part
+        .add_assign(&secret_part[..])
+        .expect("negligible probability of secrets summing to zero");
+    basepoint_part
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use std::str::FromStr;
+
+    // BOLT3 Appendix E: Key Derivation Test Vectors
+    fn base_secret() -> secp256k1::SecretKey {
+        secp256k1::SecretKey::from_slice(
+            &Vec::from_hex(
+                "000102030405060708090a0b0c0d0e0f\
+                 101112131415161718191a1b1c1d1e1f",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn per_commitment_secret() -> secp256k1::SecretKey {
+        secp256k1::SecretKey::from_slice(
+            &Vec::from_hex(
+                "1f1e1d1c1b1a19181716151413121110\
+                 0f0e0d0c0b0a09080706050403020100",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn pubkey(hex: &str) -> secp256k1::PublicKey {
+        secp256k1::PublicKey::from_str(hex).unwrap()
+    }
+
+    fn secret(hex: &str) -> secp256k1::SecretKey {
+        secp256k1::SecretKey::from_str(hex).unwrap()
+    }
+
+    #[test]
+    fn points() {
+        let secp = Secp256k1::signing_only();
+        assert_eq!(
+            secp256k1::PublicKey::from_secret_key(&secp, &base_secret()),
+            pubkey(
+                "036d6caac248af96f6afa7f904f550253a0f3ef3f5aa2fe6838a95b216691468e2"
+            )
+        );
+        assert_eq!(
+            secp256k1::PublicKey::from_secret_key(
+                &secp,
+                &per_commitment_secret()
+            ),
+            pubkey(
+                "025f7117a78150fe2ef97db7cfc83bd57b2e2c0d0dd25eaf467a4a1c2a45ce1486"
+            )
+        );
+    }
+
+    #[test]
+    fn local_key() {
+        let basepoint = pubkey(
+            "036d6caac248af96f6afa7f904f550253a0f3ef3f5aa2fe6838a95b216691468e2",
+        );
+        let per_commitment_point = pubkey(
+            "025f7117a78150fe2ef97db7cfc83bd57b2e2c0d0dd25eaf467a4a1c2a45ce1486",
+        );
+        assert_eq!(
+            derive_pubkey(basepoint, per_commitment_point),
+            pubkey(
+                "0235f2dbfaa89b57ec7b055afe29849ef7ddfeb1cefdb9ebdc43f5494984db29e5"
+            )
+        );
+        assert_eq!(
+            derive_secret(base_secret(), per_commitment_point),
+            secret(
+                "cbced912d3b21bf196a766651e436aff192362621ce317704ea2f75d87e7be0f"
+            )
+        );
+    }
+
+    #[test]
+    fn revocation_key() {
+        let basepoint = pubkey(
+            "036d6caac248af96f6afa7f904f550253a0f3ef3f5aa2fe6838a95b216691468e2",
+        );
+        let per_commitment_point = pubkey(
+            "025f7117a78150fe2ef97db7cfc83bd57b2e2c0d0dd25eaf467a4a1c2a45ce1486",
+        );
+        assert_eq!(
+            derive_revocation_pubkey(basepoint, per_commitment_point),
+            pubkey(
+                "02916e326636d19c33f13e8c0c3a03dd157f332f3e99c317c141dd865eb01f8ff0"
+            )
+        );
+        assert_eq!(
+            derive_revocation_secret(base_secret(), per_commitment_secret()),
+            secret(
+                "d09ffff62ddb2297ab000cc85bcb4283fdeb6aa052affbc9dddcf33b61078110"
+            )
+        );
+    }
+
+    #[test]
+    fn channel_keys() {
+        let keys = ChannelKeys::derive([7u8; 32]);
+        assert_eq!(keys, ChannelKeys::derive([7u8; 32]));
+        assert_ne!(keys, ChannelKeys::derive([8u8; 32]));
+        let secrets = [
+            keys.funding_secret,
+            keys.revocation_basepoint_secret,
+            keys.payment_basepoint_secret,
+            keys.delayed_payment_basepoint_secret,
+            keys.htlc_basepoint_secret,
+        ];
+        for (no, secret) in secrets.iter().enumerate() {
+            assert!(!secrets[no + 1..].contains(secret));
+        }
+    }
+}
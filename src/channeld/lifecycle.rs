@@ -0,0 +1,236 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! Typed channel lifecycle: states together with the data which exists
+//! only within them, and the allowed transitions between the states
+
+use lnp::payment::Lifecycle;
+
+/// Channel state together with the data specific to it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    /// Channel is not negotiated yet
+    Initial,
+    /// We have proposed the channel to the remote peer
+    Proposed,
+    /// Channel parameters are agreed by both peers
+    Accepted,
+    /// Funding transaction is being constructed and signed
+    Funding,
+    /// Funding transaction is signed by both peers and awaits confirmation
+    Funded {
+        /// Chain height at which we have started to await confirmation of
+        /// the funding transaction published by the remote peer
+        funding_height: Option<u32>,
+    },
+    /// Funding transaction is deep enough and we have sent `funding_locked`
+    Locked,
+    /// Both peers have locked the channel, which may be used for payments
+    Active,
+    /// One of the commitments or the mutual close transaction is published
+    Closed,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Initial
+    }
+}
+
+impl State {
+    /// Restores the state from its persisted representation
+    pub fn with(lifecycle: Lifecycle, funding_height: Option<u32>) -> State {
+        match lifecycle {
+            Lifecycle::Proposed => State::Proposed,
+            Lifecycle::Accepted => State::Accepted,
+            Lifecycle::Funding => State::Funding,
+            Lifecycle::Funded => State::Funded { funding_height },
+            Lifecycle::Locked => State::Locked,
+            Lifecycle::Active => State::Active,
+            Lifecycle::Closed => State::Closed,
+            _ => State::Initial,
+        }
+    }
+
+    /// Lifecycle stage of the state as it is reported and persisted
+    pub fn lifecycle(self) -> Lifecycle {
+        match self {
+            State::Initial => Lifecycle::default(),
+            State::Proposed => Lifecycle::Proposed,
+            State::Accepted => Lifecycle::Accepted,
+            State::Funding => Lifecycle::Funding,
+            State::Funded { .. } => Lifecycle::Funded,
+            State::Locked => Lifecycle::Locked,
+            State::Active => Lifecycle::Active,
+            State::Closed => Lifecycle::Closed,
+        }
+    }
+
+    /// Chain height at which we have started to await funding confirmation
+    pub fn funding_height(self) -> Option<u32> {
+        match self {
+            State::Funded { funding_height } => funding_height,
+            _ => None,
+        }
+    }
+}
+
+/// Cause of the channel state change, determining which transitions are
+/// allowed
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display)]
+pub enum Trigger {
+    /// Progress of the channel protocol with the remote peer or on chain
+    #[display("protocol")]
+    Protocol,
+
+    /// Resuming the channel from the state stored before the daemon restart
+    #[display("restore")]
+    Restore,
+
+    /// Initializing the channel from the static backup after its state was
+    /// lost
+    #[display("recovery")]
+    Recovery,
+}
+
+/// Error of moving the channel into a state which can't follow the current
+/// one
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
+#[display("channel can't move from {from:?} to {to:?} state on {trigger}")]
+pub struct IllegalTransition {
+    pub from: Lifecycle,
+    pub to: Lifecycle,
+    pub trigger: Trigger,
+}
+
+/// Checks whether the channel may move from one state to the other
+pub fn check_transition(
+    from: State,
+    to: State,
+    trigger: Trigger,
+) -> Result<(), IllegalTransition> {
+    let allowed = match (trigger, from, to) {
+        // Stored state is loaded only into the daemon which has just started
+        (Trigger::Restore, State::Initial, _) => true,
+        // Channels get into the static backup once they are locked
+        (Trigger::Recovery, State::Initial, State::Locked) => true,
+        (Trigger::Restore, ..) | (Trigger::Recovery, ..) => false,
+
+        // Channel is either proposed by us or accepted right away when
+        // proposed by the remote peer
+        (_, State::Initial, State::Proposed) => true,
+        (_, State::Initial, State::Accepted)
+        | (_, State::Proposed, State::Accepted) => true,
+        (_, State::Accepted, State::Funding) => true,
+        (_, State::Funding, State::Funded { .. }) => true,
+        // Height at which funding confirmation is awaited gets known
+        (
+            _,
+            State::Funded {
+                funding_height: None,
+            },
+            State::Funded { .. },
+        ) => true,
+        (_, State::Funded { .. }, State::Locked) => true,
+        (_, State::Locked, State::Active) => true,
+        // Funded channel may be closed at any moment by publishing one of
+        // the commitments
+        (_, State::Funded { .. }, State::Closed)
+        | (_, State::Locked, State::Closed)
+        | (_, State::Active, State::Closed) => true,
+        _ => false,
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(IllegalTransition {
+            from: from.lifecycle(),
+            to: to.lifecycle(),
+            trigger,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FUNDED: State = State::Funded {
+        funding_height: None,
+    };
+
+    #[test]
+    fn protocol_transitions() {
+        let path = [
+            State::Initial,
+            State::Proposed,
+            State::Accepted,
+            State::Funding,
+            FUNDED,
+            State::Funded {
+                funding_height: Some(700_000),
+            },
+            State::Locked,
+            State::Active,
+            State::Closed,
+        ];
+        for pair in path.windows(2) {
+            check_transition(pair[0], pair[1], Trigger::Protocol).unwrap();
+        }
+    }
+
+    #[test]
+    fn no_downgrade() {
+        for (from, to) in &[
+            (FUNDED, State::Funding),
+            (State::Locked, FUNDED),
+            (State::Active, State::Locked),
+            (State::Closed, State::Active),
+            (State::Accepted, State::Proposed),
+            (
+                State::Funded {
+                    funding_height: Some(700_000),
+                },
+                FUNDED,
+            ),
+        ] {
+            assert!(check_transition(*from, *to, Trigger::Protocol).is_err());
+        }
+    }
+
+    #[test]
+    fn restore_and_recovery() {
+        check_transition(State::Initial, State::Active, Trigger::Restore)
+            .unwrap();
+        check_transition(State::Initial, State::Locked, Trigger::Recovery)
+            .unwrap();
+        assert!(check_transition(
+            State::Initial,
+            State::Active,
+            Trigger::Protocol
+        )
+        .is_err());
+        assert!(check_transition(
+            State::Locked,
+            State::Active,
+            Trigger::Restore
+        )
+        .is_err());
+        assert!(check_transition(
+            State::Active,
+            State::Locked,
+            Trigger::Recovery
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,175 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! BOLT-2 limits for HTLCs offered to a channel side
+
+use lnp::message;
+
+/// Errors of HTLC validation against the channel limits
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum LimitError {
+    /// HTLC amount {0} is below the minimum of {1}
+    BelowMinimum(u64, u64),
+
+    /// number of HTLCs in flight would exceed the maximum of {0}
+    TooManyHtlcs(u16),
+
+    /// value of HTLCs in flight would reach {0}, exceeding the maximum of {1}
+    InFlightExceeded(u64, u64),
+
+    /// balance of the offering side would drop to {0} msat, below the channel
+    /// reserve of {1} msat
+    ReserveViolated(u64, u64),
+
+    /// asset HTLC must have non-zero amount
+    ZeroAssetAmount,
+
+    /// value of asset HTLCs in flight would reach {0}, exceeding the maximum
+    /// of {1}
+    AssetInFlightExceeded(u64, u64),
+}
+
+/// Limits one channel side imposes on HTLCs offered to it, and reserve it
+/// requires from the other side
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Default, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct HtlcLimits {
+    pub channel_reserve_satoshis: u64,
+    pub max_htlc_value_in_flight_msat: u64,
+    pub htlc_minimum_msat: u64,
+    pub max_accepted_htlcs: u16,
+}
+
+impl From<&message::OpenChannel> for HtlcLimits {
+    fn from(open_channel: &message::OpenChannel) -> Self {
+        HtlcLimits {
+            channel_reserve_satoshis: open_channel.channel_reserve_satoshis,
+            max_htlc_value_in_flight_msat: open_channel
+                .max_htlc_value_in_flight_msat,
+            htlc_minimum_msat: open_channel.htlc_minimum_msat,
+            max_accepted_htlcs: open_channel.max_accepted_htlcs,
+        }
+    }
+}
+
+impl From<&message::AcceptChannel> for HtlcLimits {
+    fn from(accept_channel: &message::AcceptChannel) -> Self {
+        HtlcLimits {
+            channel_reserve_satoshis: accept_channel.channel_reserve_satoshis,
+            max_htlc_value_in_flight_msat: accept_channel
+                .max_htlc_value_in_flight_msat,
+            htlc_minimum_msat: accept_channel.htlc_minimum_msat,
+            max_accepted_htlcs: accept_channel.max_accepted_htlcs,
+        }
+    }
+}
+
+impl HtlcLimits {
+    /// Checks new HTLC against the limits.
+    ///
+    /// `htlcs` is the number of HTLCs already offered to the same side and
+    /// `in_flight` is their bitcoin value. Amount and reserve are checked
+    /// for bitcoin HTLCs only, where `balance` is the bitcoin balance of
+    /// the offering side before the HTLC is added. All amounts are in
+    /// millisatoshis.
+    pub fn check(
+        &self,
+        amount: u64,
+        is_bitcoin: bool,
+        htlcs: usize,
+        in_flight: u64,
+        balance: u64,
+    ) -> Result<(), LimitError> {
+        if htlcs >= self.max_accepted_htlcs as usize {
+            return Err(LimitError::TooManyHtlcs(self.max_accepted_htlcs));
+        }
+        if !is_bitcoin {
+            return Ok(());
+        }
+        if amount < self.htlc_minimum_msat {
+            return Err(LimitError::BelowMinimum(
+                amount,
+                self.htlc_minimum_msat,
+            ));
+        }
+        if in_flight + amount > self.max_htlc_value_in_flight_msat {
+            return Err(LimitError::InFlightExceeded(
+                in_flight + amount,
+                self.max_htlc_value_in_flight_msat,
+            ));
+        }
+        let remaining = balance.saturating_sub(amount);
+        let reserve_msat = self.channel_reserve_satoshis * 1000;
+        if remaining < reserve_msat {
+            return Err(LimitError::ReserveViolated(remaining, reserve_msat));
+        }
+        Ok(())
+    }
+
+    /// Checks new asset HTLC against the limits.
+    ///
+    /// Asset value in flight is limited to the same share of the asset
+    /// capacity of the channel as the bitcoin value in flight is limited to
+    /// of the bitcoin capacity `capacity_msat`. `in_flight` is the value of
+    /// the asset HTLCs already offered to the same side.
+    pub fn check_asset(
+        &self,
+        amount: u64,
+        in_flight: u64,
+        asset_capacity: u64,
+        capacity_msat: u64,
+    ) -> Result<(), LimitError> {
+        if amount == 0 {
+            return Err(LimitError::ZeroAssetAmount);
+        }
+        let max_in_flight = if capacity_msat == 0
+            || self.max_htlc_value_in_flight_msat >= capacity_msat
+        {
+            asset_capacity
+        } else {
+            (asset_capacity as u128
+                * self.max_htlc_value_in_flight_msat as u128
+                / capacity_msat as u128) as u64
+        };
+        if in_flight + amount > max_in_flight {
+            return Err(LimitError::AssetInFlightExceeded(
+                in_flight + amount,
+                max_in_flight,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reserve_in_msat() {
+        let limits = HtlcLimits {
+            channel_reserve_satoshis: 1000,
+            max_htlc_value_in_flight_msat: 10_000_000,
+            htlc_minimum_msat: 1,
+            max_accepted_htlcs: 10,
+        };
+        assert_eq!(limits.check(1_000_500, true, 0, 0, 2_000_500), Ok(()));
+        assert_eq!(
+            limits.check(1_000_501, true, 0, 0, 2_000_500),
+            Err(LimitError::ReserveViolated(999_999, 1_000_000))
+        );
+    }
+}
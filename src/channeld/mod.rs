@@ -12,12 +12,23 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+mod anchors;
+mod channel_type;
+mod commitment;
+mod failure;
+mod fees;
+mod keys;
+mod lifecycle;
+mod limits;
 #[cfg(feature = "shell")]
 mod opts;
+mod policy;
+mod quiescence;
 mod runtime;
+mod shachain;
 #[allow(dead_code)]
 pub(self) mod storage;
 
 #[cfg(feature = "shell")]
 pub use opts::{Opts, RgbOpts};
-pub use runtime::run;
+pub use runtime::{import_channel, persisted_channels, run};
@@ -0,0 +1,186 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! Validation of the channel parameters proposed by remote peers against
+//! the node policy
+
+use bitcoin::Script;
+use lnp::message;
+use lnp::payment::channel::NegotiationError;
+use wallet::PubkeyScript;
+
+use super::channel_type::{ChannelType, UnsupportedBits};
+use crate::rpc::tlv::{self, TlvStream};
+use crate::ChannelPolicy;
+
+/// Errors of channel negotiation
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum PolicyError {
+    /// {0}
+    #[from]
+    Negotiation(NegotiationError),
+
+    /// channel funding of {0} sat is below the minimum of {1} sat
+    ChannelTooSmall(u64, u64),
+
+    /// channel funding of {0} sat exceeds the maximum of {1} sat
+    ChannelTooLarge(u64, u64),
+
+    /// dust limit of {0} sat is outside of the accepted range of {1}..{2} sat
+    DustLimit(u64, u64, u64),
+
+    /// to_self_delay of {0} blocks exceeds the maximum of {1} blocks
+    ToSelfDelay(u16, u16),
+
+    /// channel reserve of {0} sat exceeds the maximum of {1} sat
+    ChannelReserve(u64, u64),
+
+    /// minimum depth of {0} confirmations exceeds the maximum of {1}
+    MinimumDepth(u32, u32),
+
+    /// channel funding of {0} sat requires option_support_large_channel,
+    /// which is not supported by the remote peer
+    LargeChannel(u64),
+
+    /// upfront shutdown script {0} is not of a standard type
+    UpfrontShutdownScript(PubkeyScript),
+
+    /// {0}
+    #[from]
+    UnsupportedChannelType(UnsupportedBits),
+
+    /// channel type {0} is disabled by the node configuration
+    ChannelTypeDisabled(ChannelType),
+
+    /// remote peer has accepted the channel as {0} instead of the proposed
+    /// {1}
+    ChannelTypeMismatch(ChannelType, ChannelType),
+}
+
+/// Checks parameters which are common for `open_channel` and
+/// `accept_channel` messages
+fn check_common(
+    policy: &ChannelPolicy,
+    funding_satoshis: u64,
+    dust_limit_satoshis: u64,
+    to_self_delay: u16,
+    channel_reserve_satoshis: u64,
+) -> Result<(), PolicyError> {
+    if dust_limit_satoshis < policy.min_dust_limit
+        || dust_limit_satoshis > policy.max_dust_limit
+    {
+        return Err(PolicyError::DustLimit(
+            dust_limit_satoshis,
+            policy.min_dust_limit,
+            policy.max_dust_limit,
+        ));
+    }
+    if to_self_delay > policy.max_to_self_delay {
+        return Err(PolicyError::ToSelfDelay(
+            to_self_delay,
+            policy.max_to_self_delay,
+        ));
+    }
+    // Reserve can't be below the dust limit, so we accept it whatever our
+    // reserve percentage is
+    let max_reserve = policy
+        .channel_reserve(funding_satoshis)
+        .max(dust_limit_satoshis);
+    if channel_reserve_satoshis > max_reserve {
+        return Err(PolicyError::ChannelReserve(
+            channel_reserve_satoshis,
+            max_reserve,
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the channel funding does not exceed the maximum, which
+/// depends on the large channels support
+pub fn check_channel_size(
+    policy: &ChannelPolicy,
+    funding_satoshis: u64,
+) -> Result<(), PolicyError> {
+    if funding_satoshis > policy.max_channel_size {
+        return Err(PolicyError::ChannelTooLarge(
+            funding_satoshis,
+            policy.max_channel_size,
+        ));
+    }
+    Ok(())
+}
+
+/// Validates `open_channel` message received from the remote peer
+pub fn check_open_channel(
+    policy: &ChannelPolicy,
+    open_channel: &message::OpenChannel,
+) -> Result<(), PolicyError> {
+    if open_channel.funding_satoshis < policy.min_channel_size {
+        return Err(PolicyError::ChannelTooSmall(
+            open_channel.funding_satoshis,
+            policy.min_channel_size,
+        ));
+    }
+    check_channel_size(policy, open_channel.funding_satoshis)?;
+    check_common(
+        policy,
+        open_channel.funding_satoshis,
+        open_channel.dust_limit_satoshis,
+        open_channel.to_self_delay,
+        open_channel.channel_reserve_satoshis,
+    )
+}
+
+/// Validates `accept_channel` message received from the remote peer in
+/// reply to our `open_channel`
+pub fn check_accept_channel(
+    policy: &ChannelPolicy,
+    funding_satoshis: u64,
+    accept_channel: &message::AcceptChannel,
+) -> Result<(), PolicyError> {
+    if accept_channel.minimum_depth > policy.max_depth {
+        return Err(PolicyError::MinimumDepth(
+            accept_channel.minimum_depth,
+            policy.max_depth,
+        ));
+    }
+    check_common(
+        policy,
+        funding_satoshis,
+        accept_channel.dust_limit_satoshis,
+        accept_channel.to_self_delay,
+        accept_channel.channel_reserve_satoshis,
+    )
+}
+
+/// Reads the upfront shutdown script from the TLV records of `open_channel`
+/// or `accept_channel` message; empty script means the remote peer does not
+/// commit to any
+pub fn upfront_shutdown_script(
+    tlvs: &TlvStream,
+) -> Result<Option<PubkeyScript>, PolicyError> {
+    let script = match tlvs.get(&tlv::UPFRONT_SHUTDOWN_SCRIPT) {
+        Some(data) if !data.is_empty() => Script::from(data.clone()),
+        _ => return Ok(None),
+    };
+    if !(script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_v0_p2wpkh()
+        || script.is_v0_p2wsh()
+        || script.is_witness_program())
+    {
+        return Err(PolicyError::UpfrontShutdownScript(script.into()));
+    }
+    Ok(Some(script.into()))
+}
@@ -0,0 +1,146 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! Quiescence negotiation, where both peers agree with `stfu` messages to
+//! stop updating the channel, such that its state does not change during
+//! splicing or protocol upgrades
+
+use lnp::Messages;
+
+/// Errors of the remote peer in the quiescence negotiation, each of which
+/// fails the channel
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum QuiescenceError {
+    /// remote peer has sent `stfu` while having updates which are not
+    /// irrevocably committed yet
+    PendingUpdates,
+
+    /// remote peer has sent `stfu` twice
+    RepeatedStfu,
+
+    /// remote peer has sent a channel update after `stfu`
+    UpdateAfterStfu,
+}
+
+/// State of the quiescence negotiation. Quiescence is not persisted: it ends
+/// once the peers get disconnected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Quiescence {
+    /// Quiescence is requested by one of the node subsystems, so we send
+    /// `stfu` once we have no pending updates
+    requested: bool,
+    local_sent: bool,
+    remote_sent: bool,
+    /// Whether we are the initiator of the quiescence, who is allowed to
+    /// start the operation requiring it
+    initiator: Option<bool>,
+}
+
+impl Quiescence {
+    /// Requests quiescence of the channel
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Whether we must not start new channel updates
+    pub fn is_pending(&self) -> bool {
+        self.requested || self.local_sent || self.remote_sent
+    }
+
+    /// Whether we must not send any channel update messages
+    pub fn is_local_sent(&self) -> bool {
+        self.local_sent
+    }
+
+    /// Whether we have to send our `stfu` now: it is requested locally or by
+    /// the remote peer, and there are no pending updates
+    pub fn should_send(&self, pending_updates: bool) -> bool {
+        (self.requested || self.remote_sent)
+            && !self.local_sent
+            && !pending_updates
+    }
+
+    /// Registers our `stfu` message, returning the value of its `initiator`
+    /// field
+    pub fn local_stfu(&mut self) -> bool {
+        self.local_sent = true;
+        if self.initiator.is_none() {
+            self.initiator = Some(true);
+        }
+        // We are the initiator unless we only respond to the remote peer
+        self.requested
+    }
+
+    /// Processes `stfu` message from the remote peer. If both peers have
+    /// initiated quiescence simultaneously, the channel funder becomes the
+    /// initiator.
+    pub fn remote_stfu(
+        &mut self,
+        initiator: bool,
+        pending_updates: bool,
+        is_originator: bool,
+    ) -> Result<(), QuiescenceError> {
+        if self.remote_sent {
+            return Err(QuiescenceError::RepeatedStfu);
+        }
+        if pending_updates {
+            return Err(QuiescenceError::PendingUpdates);
+        }
+        self.remote_sent = true;
+        self.initiator = match (self.local_sent, initiator) {
+            (false, _) => Some(false),
+            (true, true) if self.requested => Some(is_originator),
+            (true, _) => Some(true),
+        };
+        Ok(())
+    }
+
+    /// Checks channel update message received from the remote peer
+    pub fn check_remote(
+        &self,
+        message: &Messages,
+    ) -> Result<(), QuiescenceError> {
+        if self.remote_sent && is_update(message) {
+            return Err(QuiescenceError::UpdateAfterStfu);
+        }
+        Ok(())
+    }
+
+    /// Channel is quiescent once both peers have sent `stfu`
+    pub fn is_quiescent(&self) -> bool {
+        self.local_sent && self.remote_sent
+    }
+
+    pub fn is_initiator(&self) -> bool {
+        self.initiator == Some(true)
+    }
+
+    /// Ends quiescence once the operation requiring it is completed or the
+    /// peers are disconnected
+    pub fn reset(&mut self) {
+        *self = Quiescence::default();
+    }
+}
+
+/// Checks whether the message updates the commitment transactions
+pub fn is_update(message: &Messages) -> bool {
+    match message {
+        Messages::UpdateAddHtlc(_)
+        | Messages::UpdateFulfillHtlc(_)
+        | Messages::UpdateFailHtlc(_)
+        | Messages::UpdateFailMalformedHtlc(_)
+        | Messages::UpdateFee(_) => true,
+        _ => false,
+    }
+}
@@ -12,41 +12,165 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::thread::{sleep, spawn};
 use std::time::{Duration, SystemTime};
 
+use amplify::{Slice32, Wrapper};
 use bitcoin::hashes::{sha256, Hash, HashEngine};
 use bitcoin::secp256k1;
+use bitcoin::secp256k1::rand::{self, Rng};
 use bitcoin::util::bip143::SigHashCache;
-use bitcoin::{OutPoint, SigHashType, Transaction};
+use bitcoin::{OutPoint, Script, SigHashType, Transaction, TxIn, TxOut};
 use internet2::zmqsocket::{self, ZmqSocketAddr, ZmqType};
 use internet2::{
     session, CreateUnmarshaller, LocalNode, NodeAddr, Session, TypedEnum,
-    Unmarshall, Unmarshaller,
+    Unmarshall, Unmarshaller, ZMQ_CONTEXT,
 };
-use lnp::payment::bolt3::{ScriptGenerators, TxGenerators};
+use lnp::payment::bolt3::ScriptGenerators;
 use lnp::payment::htlc::{HtlcKnown, HtlcSecret};
-use lnp::payment::{self, AssetsBalance, Lifecycle};
+use lnp::payment::{self, AssetsBalance};
 use lnp::{message, ChannelId, Messages, TempChannelId};
 use lnpbp::seals::OutpointReveal;
+use lnpbp::strict_encoding::{
+    strict_deserialize, strict_serialize, StrictEncode,
+};
 use lnpbp::{chain::AssetId, Chain};
 use microservices::esb::{self, Handler};
-use wallet::{HashPreimage, PubkeyScript};
+use wallet::{HashLock, HashPreimage, PubkeyScript, WitnessScript};
 
 #[cfg(feature = "rgb")]
 use rgb::Consignment;
 
-use super::storage::{self, Driver};
-use crate::rpc::request::ChannelInfo;
+use super::anchors::{self, ANCHOR_OUTPUT_VALUE};
+use super::channel_type::ChannelType;
+use super::commitment;
+use super::failure::HtlcFailure;
+use super::fees;
+use super::keys::{
+    derive_pubkey, derive_revocation_secret, derive_secret, ChannelKeys,
+    CommitmentKeys,
+};
+use super::lifecycle::{self, State, Trigger};
+use super::limits::{HtlcLimits, LimitError};
+use super::policy::{self, PolicyError};
+use super::quiescence::Quiescence;
+use super::shachain::{self, ShachainStore};
+use super::storage::{
+    self, Closing, Driver, FundingCandidate, HtlcClaim, HtlcLockIn, HtlcRecord,
+};
+use crate::config::MAX_FUNDING_SATOSHIS;
+use crate::gossipd::{AnnouncementSignatures, UnsignedChannelAnnouncement};
+use crate::rpc::request::{
+    AccountingEvent, AccountingEventKind, ChannelBackup, ChannelInfo,
+    ChannelPolicyUpdate, CpfpChild, CpfpRequest, CustomMessage,
+    ExtendedMessage, ForwardingPolicy, HookCall, HookPoint, HookResult,
+    HtlcForward, HtlcSettlement, IncomingHtlc, InterceptedHtlc, JusticeBlob,
+    NodeEvent, NodeEventKind, OutputLocation, PeerFeatures, ShortChannelId,
+    TxDepth, STFU_TYPE,
+};
+use crate::rpc::tlv::{self, TlvStream};
 use crate::rpc::{request, Request, ServiceBus};
-use crate::{Config, CtlServer, Error, LogStyle, Senders, Service, ServiceId};
+use crate::service::{self, BridgeHandler};
+use crate::{
+    ChannelPolicy, Config, CtlServer, Error, LogStyle, Senders, Service,
+    ServiceId,
+};
+
+pub const CHANNELS_DIR: &'static str = "channels";
+
+/// Weight of a closing transaction with two P2WPKH outputs, used for the
+/// closing fee estimation
+const CLOSING_TX_WEIGHT: u64 = 672;
+
+/// Weight of a transaction sweeping `to_local` commitment output into a
+/// single P2WPKH output
+const SWEEP_TX_WEIGHT: u64 = 483;
+
+/// Weight of a penalty transaction spending `to_local` output of a revoked
+/// remote commitment into a single P2WPKH output
+const PENALTY_TX_WEIGHT: u64 = 484;
+
+/// Weight of each penalty transaction input spending HTLC output of a revoked
+/// remote commitment (BOLT5), including `1 OP_CSV OP_DROP` of anchor channels
+const PENALTY_HTLC_INPUT_WEIGHT: u64 = 416;
+
+/// Relative change of the fee estimate, in percents, making channel funder to
+/// update the commitment feerate
+const FEE_UPDATE_THRESHOLD: u64 = 25;
+
+/// Number of confirmations of the funding transaction after which public
+/// channels are announced (BOLT-7)
+const ANNOUNCEMENT_DEPTH: u32 = 6;
+
+/// Period of checking the channel negotiation timeout
+const TIMER_PERIOD: Duration = Duration::from_secs(30);
+
+/// Lists channels with the state persisted by the channel daemons, which
+/// must be resumed on node restart. Channels are identified by the id their
+/// daemons were launched with.
+pub fn persisted_channels(
+    config: &Config,
+    data_dir: &Path,
+) -> Result<Vec<ChannelId>, Error> {
+    match config.kv_storage {
+        Some(ref path) => storage::KvDriver::list(path),
+        None => storage::DiskDriver::list(&data_dir.join(CHANNELS_DIR)),
+    }
+}
+
+/// Stores channel data exported from another node instance, such that the
+/// channel daemon launched with the same channel id resumes the channel
+pub fn import_channel(
+    config: &Config,
+    data_dir: &Path,
+    channel_id: ChannelId,
+    data: &[u8],
+) -> Result<(), Error> {
+    let dump: storage::ChannelDump =
+        strict_deserialize(data).map_err(|err| {
+            Error::Other(format!(
+                "Exported state of channel {} is corrupted: {}",
+                channel_id, err
+            ))
+        })?;
+    let mut storage = open_storage(config, data_dir, channel_id)?;
+    if storage.load()?.is_some() {
+        return Err(Error::Other(format!(
+            "Channel {} already has a stored state",
+            channel_id
+        )));
+    }
+    dump.write(storage.as_mut())
+}
+
+fn open_storage(
+    config: &Config,
+    data_dir: &Path,
+    channel_id: ChannelId,
+) -> Result<Box<dyn storage::Driver>, Error> {
+    Ok(match config.kv_storage {
+        Some(ref path) => Box::new(storage::KvDriver::init(
+            channel_id,
+            Box::new(storage::KvConfig { path: path.clone() }),
+        )?),
+        None => Box::new(storage::DiskDriver::init(
+            channel_id,
+            Box::new(storage::DiskConfig {
+                path: data_dir.join(CHANNELS_DIR),
+            }),
+        )?),
+    })
+}
 
 pub fn run(
     config: Config,
     local_node: LocalNode,
     channel_id: ChannelId,
     chain: Chain,
+    data_dir: PathBuf,
     rgb20_socket_addr: ZmqSocketAddr,
 ) -> Result<(), Error> {
     let rgb20_rpc = session::Raw::with_zmq_unencrypted(
@@ -57,43 +181,132 @@ pub fn run(
     )?;
     let rgb_unmarshaller = rgb_node::rpc::Reply::create_unmarshaller();
 
-    let runtime = Runtime {
+    let storage = open_storage(&config, &data_dir, channel_id)?;
+
+    let mut runtime = Runtime {
         identity: ServiceId::Channel(channel_id),
         peer_service: ServiceId::Loopback,
         local_node,
         chain,
         channel_id: zero!(),
         temporary_channel_id: channel_id.into(),
+        key_salt: [0u8; 32],
         state: default!(),
         local_capacity: 0,
         remote_capacity: 0,
         local_balances: zero!(),
         remote_balances: zero!(),
         funding_outpoint: default!(),
+        funding_candidates: vec![],
         remote_peer: None,
         started: SystemTime::now(),
+        state_changed: SystemTime::now(),
+        negotiation_timeout: config.negotiation_timeout,
+        threaded: config.threaded,
+        funding_timeout: config.funding_timeout,
         commitment_number: 0,
+        remote_commitment_number: 0,
+        remote_commitment_dirty: false,
         total_payments: 0,
         pending_payments: 0,
         params: default!(),
         local_keys: dumb!(),
         remote_keys: dumb!(),
+        remote_signature: None,
+        local_commitment: None,
+        force_closing: None,
+        htlc_claims: empty!(),
+        bumping_commitment: false,
+        last_commitment_signed: None,
+        last_revoke_and_ack: None,
+        remote_commitments: empty!(),
+        remote_secrets: default!(),
+        remote_per_commitment_point: None,
+        remote_revoked_point: None,
+        minimum_depth: 0,
+        feerate_per_kw: 0,
+        min_feerate_per_kw: config.min_feerate_per_kw,
+        max_feerate_per_kw: config.max_feerate_per_kw,
+        dust_limit_satoshis: 0,
+        local_limits: default!(),
+        remote_limits: default!(),
+        anchors: config.anchors,
+        zero_conf: config.zero_conf,
+        scid_alias: rand::thread_rng().gen(),
+        remote_scid_alias: None,
+        channel_type: None,
+        static_remotekey: config.static_remotekey,
+        policy: config.policy,
+        upfront_shutdown_script: config.shutdown_script.clone(),
+        remote_upfront_shutdown_script: None,
+        wallet_script: None,
+        fund_from_wallet: false,
+        funding_batch: None,
+        closing: None,
+        forwarding_policy: config.forwarding_policy,
+        recovering: false,
+        public: false,
+        short_channel_id: None,
+        remote_announcement: None,
+        peer_connected: true,
+        peer_features: None,
+        quiescence: default!(),
+        quiescence_requester: None,
         offered_htlc: empty!(),
+        offered_hashes: empty!(),
         received_htlc: empty!(),
+        received_lockin: empty!(),
+        resolved_htlc: empty!(),
+        chain_height: None,
+        hooked_htlc: empty!(),
+        payment_enquirers: empty!(),
         is_originator: false,
         obscuring_factor: 0,
         enquirer: None,
         rgb20_rpc,
         rgb_unmarshaller,
-        storage: Box::new(storage::DiskDriver::init(
-            channel_id,
-            Box::new(storage::DiskConfig {
-                path: Default::default(),
-            }),
-        )?),
+        storage,
     };
 
-    Service::run(config, runtime, false)
+    if let Some(state) = runtime.storage.load()? {
+        runtime.restore(state)?;
+    }
+
+    debug!("Opening bridge between runtime and timer threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    // Channel daemons may run as threads of a single process
+    let timer_addr = format!("inproc://timer-{:x}", channel_id);
+    tx.connect(&timer_addr)?;
+    rx.bind(&timer_addr)?;
+
+    let identity = runtime.identity.clone();
+    let mut timer = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    spawn(move || loop {
+        sleep(TIMER_PERIOD);
+        if let Err(err) = timer.send_to(
+            ServiceBus::Bridge,
+            identity.clone(),
+            Request::CheckTimeouts,
+        ) {
+            error!("Unable to notify channel runtime on timer: {}", err);
+        }
+    });
+
+    let mut service = Service::service(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
 }
 
 pub struct Runtime {
@@ -104,23 +317,153 @@ pub struct Runtime {
 
     channel_id: ChannelId,
     temporary_channel_id: TempChannelId,
-    state: Lifecycle,
+    /// Local randomness mixed into the derivation of our channel keys, so
+    /// the temporary channel id chosen by the remote peer can't make us
+    /// reuse the keys
+    key_salt: [u8; 32],
+    state: State,
+    /// Bitcoin balances of the channel sides in millisatoshis, excluding
+    /// HTLCs in flight
     local_capacity: u64,
     remote_capacity: u64,
     local_balances: AssetsBalance,
     remote_balances: AssetsBalance,
     funding_outpoint: OutPoint,
+    /// Replaced funding transactions, which are watched together with the
+    /// current one until any of them confirms
+    funding_candidates: Vec<FundingCandidate>,
     remote_peer: Option<NodeAddr>,
     started: SystemTime,
+    /// Time of the last lifecycle state change
+    state_changed: SystemTime,
+    /// Time after which negotiation of unfunded channel is aborted
+    negotiation_timeout: Duration,
+    /// Whether the daemon runs as a thread of the lnpd process
+    threaded: bool,
+    /// Number of blocks after which channel funded by the remote peer is
+    /// closed if the funding transaction is not confirmed
+    funding_timeout: u32,
+    /// Last chain height reported by the chain daemon
+    chain_height: Option<u32>,
+    /// Commitments which must be revoked before the received HTLCs are
+    /// irrevocably committed
+    received_lockin: BTreeMap<u64, HtlcLockIn>,
+    /// Resolutions of the received HTLCs awaiting for the HTLCs to be
+    /// irrevocably committed; `None` fails the HTLC
+    resolved_htlc: BTreeMap<u64, Option<HashPreimage>>,
+    /// Number of our current commitment transaction
     commitment_number: u64,
+    /// Number of the current commitment transaction of the remote peer
+    remote_commitment_number: u64,
+    /// Whether HTLC set has changed since we have signed the remote
+    /// commitment transaction
+    remote_commitment_dirty: bool,
     total_payments: u64,
     pending_payments: u16,
     params: payment::channel::Params,
     local_keys: payment::channel::Keyset,
     remote_keys: payment::channel::Keyset,
+    /// Remote signature for our current commitment transaction
+    remote_signature: Option<secp256k1::Signature>,
+    /// Our commitment transaction signed by the remote peer
+    local_commitment: Option<Transaction>,
+    /// Our commitment transaction published on unilateral close, which
+    /// `to_local` output awaits maturity
+    force_closing: Option<Transaction>,
+    /// Second-stage transactions for the HTLC outputs of our current
+    /// commitment, signed by the remote peer
+    htlc_claims: Vec<HtlcClaim>,
+    /// Whether the fee estimate is awaited to bump the fee of the published
+    /// commitment transaction through our anchor output
+    bumping_commitment: bool,
+    /// Last `commitment_signed` sent to the remote peer
+    last_commitment_signed: Option<message::CommitmentSigned>,
+    /// Last `revoke_and_ack` sent to the remote peer
+    last_revoke_and_ack: Option<message::RevokeAndAck>,
+    /// Remote commitment transactions signed by us and not yet revoked, for
+    /// which justice blobs are uploaded to the watchtowers once they are
+    /// revoked
+    remote_commitments: BTreeMap<u64, Transaction>,
+    /// Per-commitment secrets revealed by the remote peer; secret for
+    /// commitment #n revokes remote commitment #n
+    remote_secrets: ShachainStore,
+    /// Per-commitment point for the next remote commitment transaction
+    remote_per_commitment_point: Option<secp256k1::PublicKey>,
+    /// Per-commitment point of the remote commitment transaction revoked by
+    /// the next `revoke_and_ack`
+    remote_revoked_point: Option<secp256k1::PublicKey>,
+    /// Number of confirmations of the funding transaction required before
+    /// the channel becomes active
+    minimum_depth: u32,
+    feerate_per_kw: u32,
+    /// Bounds for the commitment feerate proposed by the remote peer
+    min_feerate_per_kw: u32,
+    max_feerate_per_kw: u32,
+    dust_limit_satoshis: u64,
+    /// Limits we impose on HTLCs offered by the remote peer
+    local_limits: HtlcLimits,
+    /// Limits the remote peer imposes on HTLCs offered by us
+    remote_limits: HtlcLimits,
+    /// Whether the channel uses anchor outputs commitment format
+    anchors: bool,
+    /// Whether `to_remote` outputs pay to the static payment basepoint
+    static_remotekey: bool,
+    /// Whether we accept channels without funding confirmation
+    zero_conf: bool,
+    /// Random short channel id alias for the channel
+    scid_alias: u64,
+    /// Short channel id alias assigned to the channel by the remote peer
+    remote_scid_alias: Option<u64>,
+    /// Channel type negotiated explicitly during the channel opening
+    channel_type: Option<ChannelType>,
+    /// Limits for the channel parameters proposed by the remote peer
+    policy: ChannelPolicy,
+    /// Script receiving our funds on cooperative close, to which we have
+    /// committed the channel
+    upfront_shutdown_script: Option<PubkeyScript>,
+    /// Script the remote peer has committed to close the channel to
+    remote_upfront_shutdown_script: Option<PubkeyScript>,
+    /// Fresh script of the funding wallet receiving our funds on close,
+    /// unless the user has provided one
+    wallet_script: Option<PubkeyScript>,
+    /// Funding transaction is constructed and published by `fundingd`
+    fund_from_wallet: bool,
+    /// Channels sharing the funding transaction constructed by `fundingd`
+    funding_batch: Option<request::FundingBatch>,
+    /// Mutual close negotiation, present once shutdown is initiated by
+    /// either of the peers
+    closing: Option<Closing>,
+    /// Policy of forwarding payments over the channel
+    forwarding_policy: ForwardingPolicy,
+    /// Channel is restored from the static backup: its state is lost and
+    /// the remote peer is asked to close it
+    recovering: bool,
+    /// Whether the channel is announced to the network
+    public: bool,
+    /// Position of the funding output in the chain, known once the funding
+    /// transaction is deep enough for announcing the channel
+    short_channel_id: Option<ShortChannelId>,
+    /// Channel announcement signatures received from the remote peer
+    remote_announcement: Option<AnnouncementSignatures>,
+    /// Whether the connection with the remote peer is alive. Channel updates
+    /// are paused while it is lost, until the channel is re-established.
+    peer_connected: bool,
+    /// Features negotiated with the remote peer by the connection daemon
+    peer_features: Option<PeerFeatures>,
+    /// Negotiation of stopping channel updates, and the daemon which has
+    /// requested it
+    quiescence: Quiescence,
+    quiescence_requester: Option<ServiceId>,
 
     offered_htlc: Vec<HtlcKnown>,
+    /// Payment hashes of the offered HTLCs paying invoices of other nodes,
+    /// which preimages are not known until the HTLCs are fulfilled
+    offered_hashes: BTreeMap<u64, HashLock>,
     received_htlc: Vec<HtlcSecret>,
+    /// HTLCs offered by the remote peer which await decision of plugins
+    hooked_htlc: BTreeMap<u64, message::UpdateAddHtlc>,
+    /// Enquirers of the outgoing payments awaiting settlement, by HTLC id
+    payment_enquirers: HashMap<u64, ServiceId>,
 
     is_originator: bool,
     obscuring_factor: u64,
@@ -129,10 +472,22 @@ pub struct Runtime {
     rgb20_rpc: session::Raw<session::PlainTranscoder, zmqsocket::Connection>,
     rgb_unmarshaller: Unmarshaller<rgb_node::rpc::Reply>,
 
-    #[allow(dead_code)]
     storage: Box<dyn storage::Driver>,
 }
 
+/// HTLC output of a commitment transaction
+struct HtlcOutput {
+    witness_script: Script,
+    script_pubkey: Script,
+    /// Output value in satoshis
+    amount: u64,
+    payment_hash: HashLock,
+    /// Timelock of the HTLC-timeout transaction; `None` for the HTLCs
+    /// received by the commitment owner, which are spent with HTLC-success
+    /// transaction
+    timeout: Option<u32>,
+}
+
 impl CtlServer for Runtime {}
 
 impl Runtime {
@@ -141,9 +496,90 @@ impl Runtime {
         self.local_node.node_id()
     }
 
+    /// Bitcoin capacity of the channel in millisatoshis
     #[inline]
     pub fn channel_capacity(&self) -> u64 {
-        self.local_capacity + self.remote_capacity
+        // Bitcoin locked in HTLCs in flight is deducted from the balances
+        let in_flight: u64 = self
+            .offered_htlc
+            .iter()
+            .filter(|htlc| htlc.asset_id.is_none())
+            .map(|htlc| htlc.amount)
+            .chain(
+                self.received_htlc
+                    .iter()
+                    .filter(|htlc| htlc.asset_id.is_none())
+                    .map(|htlc| htlc.amount),
+            )
+            .sum();
+        self.local_capacity + self.remote_capacity + in_flight
+    }
+
+    /// Value of the funding output
+    #[inline]
+    pub fn funding_satoshis(&self) -> u64 {
+        self.channel_capacity() / 1000
+    }
+
+    /// Miner fee paid by the published commitment transaction
+    fn paid_commitment_fee(&self, cmt_tx: &Transaction) -> u64 {
+        self.funding_satoshis()
+            .saturating_sub(cmt_tx.output.iter().map(|txout| txout.value).sum())
+    }
+
+    /// Asset amounts locked in HTLCs offered by us (`offered` is `true`) or
+    /// by the remote peer
+    pub fn assets_in_flight(&self, offered: bool) -> AssetsBalance {
+        let htlcs: Vec<(Option<AssetId>, u64)> = if offered {
+            self.offered_htlc
+                .iter()
+                .map(|htlc| (htlc.asset_id, htlc.amount))
+                .collect()
+        } else {
+            self.received_htlc
+                .iter()
+                .map(|htlc| (htlc.asset_id, htlc.amount))
+                .collect()
+        };
+        let mut in_flight = AssetsBalance::new();
+        for (asset_id, amount) in htlcs {
+            if let Some(asset_id) = asset_id {
+                *in_flight.entry(asset_id).or_insert(0) += amount;
+            }
+        }
+        in_flight
+    }
+
+    /// Total amount of the asset in the channel, including HTLCs in flight
+    pub fn asset_capacity(&self, asset_id: AssetId) -> u64 {
+        let balance = |balances: &AssetsBalance| {
+            balances.get(&asset_id).copied().unwrap_or(0)
+        };
+        balance(&self.local_balances)
+            + balance(&self.remote_balances)
+            + balance(&self.assets_in_flight(true))
+            + balance(&self.assets_in_flight(false))
+    }
+
+    /// Checks asset HTLC offered to the side imposing given limits
+    fn check_asset_htlc(
+        &self,
+        limits: &HtlcLimits,
+        offered: bool,
+        asset_id: AssetId,
+        amount: u64,
+    ) -> Result<(), LimitError> {
+        let in_flight = self
+            .assets_in_flight(offered)
+            .get(&asset_id)
+            .copied()
+            .unwrap_or(0);
+        limits.check_asset(
+            amount,
+            in_flight,
+            self.asset_capacity(asset_id),
+            self.params.funding_satoshis * 1000,
+        )
     }
 }
 
@@ -166,9 +602,7 @@ impl esb::Handler<ServiceBus> for Runtime {
         match bus {
             ServiceBus::Msg => self.handle_rpc_msg(senders, source, request),
             ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
-            _ => {
-                Err(Error::NotSupported(ServiceBus::Bridge, request.get_type()))
-            }
+            ServiceBus::Bridge => self.handle_bridge(senders, request),
         }
     }
 
@@ -182,19 +616,90 @@ impl esb::Handler<ServiceBus> for Runtime {
 
 impl Runtime {
     fn send_peer(
-        &self,
+        &mut self,
+        senders: &mut Senders,
+        message: Messages,
+    ) -> Result<(), Error> {
+        self.send_extended(senders, message, empty!())
+    }
+
+    /// Sends the peer message extended with the TLV records
+    fn send_extended(
+        &mut self,
         senders: &mut Senders,
         message: Messages,
+        tlvs: TlvStream,
     ) -> Result<(), Error> {
+        // Keeping last commitment update messages for retransmission on
+        // channel re-establishment
+        match message {
+            Messages::CommitmentSigned(ref commitment_signed) => {
+                self.last_commitment_signed = Some(commitment_signed.clone());
+                self.save()?;
+            }
+            Messages::RevokeAndAck(ref revoke_and_ack) => {
+                self.last_revoke_and_ack = Some(revoke_and_ack.clone());
+                self.save()?;
+            }
+            _ => {}
+        }
+        if !self.peer_connected {
+            // Commitment updates are retransmitted on re-establishment
+            debug!(
+                "Remote peer is disconnected; message {} is not sent",
+                message
+            );
+            return Ok(());
+        }
+        let request = if tlvs.is_empty() {
+            Request::PeerMessage(message)
+        } else {
+            Request::ExtendedPeerMessage(ExtendedMessage { message, tlvs })
+        };
         senders.send_to(
             ServiceBus::Msg,
             self.identity(),
             self.peer_service.clone(),
-            Request::PeerMessage(message),
+            request,
         )?;
         Ok(())
     }
 
+    /// TLV records of our `open_channel` or `accept_channel` message
+    fn opening_tlvs(&self) -> TlvStream {
+        let mut tlvs = TlvStream::new();
+        if let Some(ref script) = self.upfront_shutdown_script {
+            tlvs.insert(
+                tlv::UPFRONT_SHUTDOWN_SCRIPT,
+                script.as_inner().to_bytes(),
+            );
+        }
+        if let Some(channel_type) = self.channel_type {
+            tlvs.insert(tlv::CHANNEL_TYPE, channel_type.to_bytes());
+        }
+        tlvs
+    }
+
+    /// Sends our `funding_locked` carrying the alias of the short channel
+    /// id, which the remote peer uses in route hints to the channel
+    fn send_funding_locked(
+        &mut self,
+        senders: &mut Senders,
+    ) -> Result<(), Error> {
+        let funding_locked = message::FundingLocked {
+            channel_id: self.channel_id,
+            next_per_commitment_point: self.local_per_commitment_point(1),
+        };
+        let tlvs = bmap! {
+            tlv::SHORT_CHANNEL_ID_ALIAS => self.scid_alias.to_be_bytes().to_vec()
+        };
+        self.send_extended(
+            senders,
+            Messages::FundingLocked(funding_locked),
+            tlvs,
+        )
+    }
+
     fn request_rbg20(
         &mut self,
         request: rgb_node::rpc::fungible::Request,
@@ -215,13 +720,20 @@ impl Runtime {
         source: ServiceId,
         request: Request,
     ) -> Result<(), Error> {
+        let (request, tlvs) = request.extract_tlvs();
+        if let Request::PeerMessage(ref message) = request {
+            if let Err(err) = self.quiescence.check_remote(message) {
+                return Err(self.fail_channel(senders, err.to_string()));
+            }
+        }
+
         match request {
             Request::PeerMessage(Messages::AcceptChannel(accept_channel)) => {
-                self.state = Lifecycle::Accepted;
+                self.transition(State::Accepted, Trigger::Protocol)?;
 
                 let enquirer = self.enquirer.clone();
 
-                self.channel_accepted(senders, &accept_channel, &source)
+                self.channel_accepted(senders, &accept_channel, &tlvs, &source)
                     .map_err(|err| {
                         self.report_failure_to(
                             senders,
@@ -232,6 +744,7 @@ impl Runtime {
                             },
                         )
                     })?;
+                self.save()?;
 
                 // Construct funding output scriptPubkey
                 let remote_pk = accept_channel.funding_pubkey;
@@ -242,7 +755,7 @@ impl Runtime {
                     remote_pk
                 );
                 let script_pubkey = PubkeyScript::ln_funding(
-                    self.channel_capacity(),
+                    self.funding_satoshis(),
                     local_pk,
                     remote_pk,
                 );
@@ -261,19 +774,53 @@ impl Runtime {
                     )
                 }
 
-                // Ignoring possible error here: do not want to
-                // halt the channel just because the client disconnected
-                let _ = self.send_ctl(
-                    senders,
-                    &enquirer,
-                    Request::ChannelFunding(script_pubkey),
-                );
+                if self.fund_from_wallet {
+                    let _ = self.report_progress_to(
+                        senders,
+                        &enquirer,
+                        s!("Constructing funding transaction with fundingd"),
+                    );
+                    self.send_ctl(
+                        senders,
+                        ServiceId::Funding,
+                        Request::ConstructFunding(request::FundingRequest {
+                            script_pubkey,
+                            amount: self.funding_satoshis(),
+                            feerate_per_kw: self.feerate_per_kw,
+                            batch: self.funding_batch,
+                        }),
+                    )?;
+                } else {
+                    // Ignoring possible error here: do not want to
+                    // halt the channel just because the client disconnected
+                    let _ = self.send_ctl(
+                        senders,
+                        &enquirer,
+                        Request::ChannelFunding(script_pubkey),
+                    );
+                }
             }
 
             Request::PeerMessage(Messages::FundingCreated(funding_created)) => {
                 let enquirer = self.enquirer.clone();
 
-                self.state = Lifecycle::Funding;
+                let replacing = matches!(self.state, State::Funded { .. });
+                if replacing {
+                    if self.is_originator {
+                        let info = s!("Remote peer tried to replace funding \
+                                       of the channel we have funded");
+                        return Err(self.fail_channel(senders, info));
+                    }
+                    info!(
+                        "Remote peer {} funding transaction {} with {}",
+                        "replaces".promo(),
+                        self.funding_outpoint.txid,
+                        funding_created.funding_txid
+                    );
+                    self.keep_funding_candidate();
+                } else {
+                    self.transition(State::Funding, Trigger::Protocol)?;
+                }
 
                 let funding_signed =
                     self.funding_created(senders, funding_created)?;
@@ -283,7 +830,14 @@ impl Runtime {
                     Messages::FundingSigned(funding_signed),
                 )?;
 
-                self.state = Lifecycle::Funded;
+                if !replacing {
+                    self.transition(
+                        State::Funded {
+                            funding_height: None,
+                        },
+                        Trigger::Protocol,
+                    )?;
+                }
 
                 // Ignoring possible error here: do not want to
                 // halt the channel just because the client disconnected
@@ -293,18 +847,25 @@ impl Runtime {
                 );
                 info!("{}", msg);
                 let _ = self.report_progress_to(senders, &enquirer, msg);
-            }
 
-            Request::PeerMessage(Messages::FundingSigned(_funding_signed)) => {
-                // TODO:
-                //      1. Get commitment tx
-                //      2. Verify signature
-                //      3. Save signature/commitment tx
-                //      4. Send funding locked request
+                self.await_funding(senders)?;
+            }
 
+            Request::PeerMessage(Messages::FundingSigned(funding_signed)) => {
                 let enquirer = self.enquirer.clone();
 
-                self.state = Lifecycle::Funded;
+                self.verify_funding(senders, funding_signed.signature)?;
+                if matches!(self.state, State::Funded { .. }) {
+                    // Replacement of the funding transaction is signed
+                    self.save()?;
+                } else {
+                    self.transition(
+                        State::Funded {
+                            funding_height: None,
+                        },
+                        Trigger::Protocol,
+                    )?;
+                }
 
                 // Ignoring possible error here: do not want to
                 // halt the channel just because the client disconnected
@@ -313,65 +874,276 @@ impl Runtime {
                     "Channel funded:".ended()
                 );
                 info!("{}", msg);
-                let _ = self.report_progress_to(senders, &enquirer, msg);
+                if self.fund_from_wallet {
+                    self.send_ctl(
+                        senders,
+                        ServiceId::Funding,
+                        Request::PublishFunding,
+                    )?;
+                    let published = match self.funding_batch {
+                        // Batch transaction is published once all its
+                        // channels are funded
+                        Some(batch) => format!(
+                            "funding transaction {} of {} will be published \
+                             once all its channels are funded",
+                            self.funding_outpoint.txid, batch
+                        ),
+                        None => format!(
+                            "funding transaction {} is published",
+                            self.funding_outpoint.txid
+                        ),
+                    };
+                    let _ = self.report_success_to(
+                        senders,
+                        &enquirer,
+                        Some(format!("{}; {}", msg, published)),
+                    );
+                } else {
+                    let _ = self.report_progress_to(senders, &enquirer, msg);
+                }
 
-                let funding_locked = message::FundingLocked {
-                    channel_id: self.channel_id,
-                    next_per_commitment_point: self
-                        .local_keys
-                        .first_per_commitment_point,
-                };
+                self.await_funding(senders)?;
+            }
 
-                self.send_peer(
-                    senders,
-                    Messages::FundingLocked(funding_locked),
+            Request::PeerMessage(Messages::FundingLocked(funding_locked)) => {
+                if funding_locked.channel_id != self.channel_id {
+                    warn!(
+                        "Got funding_locked for channel {} instead of {}",
+                        funding_locked.channel_id, self.channel_id
+                    );
+                    return Err(Error::Misbehaving);
+                }
+                self.remote_revoked_point =
+                    Some(self.remote_keys.first_per_commitment_point);
+                self.remote_per_commitment_point =
+                    Some(funding_locked.next_per_commitment_point);
+                self.storage.append_commitment_point(
+                    1,
+                    funding_locked.next_per_commitment_point,
                 )?;
+                match tlvs.get(&tlv::SHORT_CHANNEL_ID_ALIAS) {
+                    Some(data) if data.len() == 8 => {
+                        let mut alias = [0u8; 8];
+                        alias.copy_from_slice(data);
+                        self.remote_scid_alias =
+                            Some(u64::from_be_bytes(alias));
+                    }
+                    Some(_) => {
+                        warn!("Remote peer sent invalid short channel id alias")
+                    }
+                    None => {}
+                }
+
+                if self.state == State::Locked {
+                    // We have already sent our `funding_locked`
+                    self.activate(senders)?;
+                } else {
+                    self.save()?;
+                    info!(
+                        "Remote peer has locked channel {}; {}",
+                        self.channel_id,
+                        "awaiting funding transaction confirmation".promo()
+                    );
+                }
+            }
 
-                self.state = Lifecycle::Active;
-                self.local_capacity = self.params.funding_satoshis;
+            Request::PeerMessage(Messages::ChannelReestablish(
+                channel_reestablish,
+            )) => {
+                self.channel_reestablished(senders, channel_reestablish)?;
+            }
 
-                // Ignoring possible error here: do not want to
-                // halt the channel just because the client disconnected
-                let msg = format!(
-                    "{} transaction confirmed",
-                    "Channel active:".ended()
-                );
-                info!("{}", msg);
-                let _ = self.report_success_to(senders, &enquirer, Some(msg));
+            Request::PeerMessage(
+                message @ Messages::AnnouncementSignatures(_),
+            ) => {
+                let remote = AnnouncementSignatures::with(&message)?;
+                if remote.channel_id != self.channel_id {
+                    warn!(
+                        "Got announcement_signatures for channel {} instead \
+                         of {}",
+                        remote.channel_id, self.channel_id
+                    );
+                    return Err(Error::Misbehaving);
+                }
+                if !self.public {
+                    debug!(
+                        "Ignoring announcement_signatures for private channel"
+                    );
+                    return Ok(());
+                }
+                self.remote_announcement = Some(remote);
+                self.complete_announcement(senders)?;
             }
 
-            Request::PeerMessage(Messages::FundingLocked(_funding_locked)) => {
-                let enquirer = self.enquirer.clone();
+            Request::PeerMessage(Messages::Error(error)) => {
+                self.peer_error(senders, error)?;
+            }
 
-                self.state = Lifecycle::Locked;
+            Request::PeerMessage(Messages::Warning(warning)) => {
+                self.peer_warning(senders, warning);
+            }
 
-                // TODO:
-                //      1. Change the channel state
-                //      2. Do something with per-commitment point
+            Request::CustomPeerMessage(CustomMessage {
+                msg_type: STFU_TYPE,
+                payload,
+                ..
+            }) => {
+                self.stfu_received(senders, &payload)?;
+            }
 
-                self.state = Lifecycle::Active;
-                self.remote_capacity = self.params.funding_satoshis;
+            Request::PeerMessage(Messages::Shutdown(shutdown)) => {
+                // Script is checked before we commit to closing with our own
+                // `shutdown`
+                if let Some(ref upfront) = self.remote_upfront_shutdown_script {
+                    if *upfront != shutdown.scriptpubkey {
+                        let info = format!(
+                            "Remote peer tries to close channel {} to a \
+                             script different from its upfront shutdown \
+                             script",
+                            self.channel_id
+                        );
+                        return Err(self.fail_channel(senders, info));
+                    }
+                }
+                if self.closing.is_none() {
+                    info!(
+                        "Remote peer {} channel {}",
+                        "initiated closing of".promo(),
+                        self.channel_id.promoter()
+                    );
+                    let local_script = match self.upfront_shutdown_script {
+                        Some(ref script) => script.clone(),
+                        None => self.default_shutdown_script()?,
+                    };
+                    self.shutdown(senders, local_script)?;
+                }
+                if let Some(ref mut closing) = self.closing {
+                    closing.remote_script = Some(shutdown.scriptpubkey);
+                }
+                self.save()?;
+                self.try_negotiate_closing(senders)?;
+            }
 
-                // Ignoring possible error here: do not want to
-                // halt the channel just because the client disconnected
-                let msg = format!(
-                    "{} transaction confirmed",
-                    "Channel active:".ended()
-                );
-                info!("{}", msg);
-                let _ = self.report_success_to(senders, &enquirer, Some(msg));
+            Request::PeerMessage(Messages::ClosingSigned(closing_signed)) => {
+                let fee_proposed = match self.closing {
+                    Some(Closing {
+                        remote_script: Some(_),
+                        fee_proposed,
+                        ..
+                    }) => fee_proposed,
+                    _ => {
+                        warn!("Got closing_signed before shutdown");
+                        return Err(Error::Misbehaving);
+                    }
+                };
+                if self.has_pending_htlcs() {
+                    let info = format!(
+                        "Remote peer sent closing_signed for channel {} with \
+                         HTLCs in flight",
+                        self.channel_id
+                    );
+                    return Err(self.fail_channel(senders, info));
+                }
+                let fee = closing_signed.fee_satoshis;
+                let max_fee = self.max_closing_fee();
+                if fee > max_fee {
+                    let info = format!(
+                        "Closing fee of {} sat proposed by the remote peer \
+                         exceeds the base fee of the final commitment {} sat",
+                        fee, max_fee
+                    );
+                    return Err(self.fail_channel(senders, info));
+                }
+                self.verify_closing(senders, fee, closing_signed.signature)?;
+
+                match fee_proposed {
+                    // Remote peer has accepted our fee
+                    Some(ours) if ours == fee => {
+                        self.complete_closing(
+                            senders,
+                            fee,
+                            closing_signed.signature,
+                        )?;
+                    }
+                    ours => {
+                        let ours =
+                            ours.unwrap_or_else(|| self.closing_fee_estimate());
+                        // Each round moves the fee halfway to the remote
+                        // proposal, so the negotiation always converges
+                        let next = (ours + fee) / 2;
+                        if next == ours || next == fee {
+                            self.propose_closing_fee(senders, fee)?;
+                            self.complete_closing(
+                                senders,
+                                fee,
+                                closing_signed.signature,
+                            )?;
+                        } else {
+                            self.propose_closing_fee(senders, next)?;
+                        }
+                    }
+                }
             }
 
             Request::PeerMessage(Messages::UpdateAddHtlc(update_add_htlc)) => {
-                let _commitment_signed =
-                    self.htlc_receive(senders, update_add_htlc)?;
+                // Next commitment transactions of both sides must include the
+                // HTLC, so it is registered before plugins decide on it
+                if let Err(err) = self.htlc_add(&update_add_htlc) {
+                    // Invalid HTLCs can't be failed, only the whole channel
+                    return Err(self.fail_channel(senders, err.to_string()));
+                }
+                // The HTLC is processed once plugins subscribed to it accept
+                // it; `lnpd` replies immediately if there are no such plugins
+                self.run_htlc_hook(senders, &update_add_htlc)?;
+                self.hooked_htlc
+                    .insert(update_add_htlc.htlc_id, update_add_htlc);
+                self.save()?;
+            }
+
+            Request::PeerMessage(Messages::UpdateFulfillHtlc(
+                update_fulfill_htlc,
+            )) => {
+                self.htlc_fulfilled(senders, update_fulfill_htlc)?;
+            }
+
+            Request::PeerMessage(Messages::UpdateFailHtlc(
+                message::UpdateFailHtlc {
+                    htlc_id, reason, ..
+                },
+            )) => {
+                let reason = HtlcFailure::parse(&reason)
+                    .map(|failure| failure.to_string())
+                    .unwrap_or(s!("unparseable failure packet"));
+                self.htlc_failed(senders, htlc_id, reason)?;
+            }
+
+            Request::PeerMessage(Messages::UpdateFailMalformedHtlc(
+                message::UpdateFailMalformedHtlc {
+                    htlc_id,
+                    failure_code,
+                    ..
+                },
+            )) => {
+                let reason = format!(
+                    "malformed onion: {}",
+                    HtlcFailure::with(failure_code)
+                );
+                self.htlc_failed(senders, htlc_id, reason)?;
+            }
+
+            Request::PeerMessage(Messages::UpdateFee(update_fee)) => {
+                self.fee_updated(senders, update_fee.feerate_per_kw)?;
             }
 
             Request::PeerMessage(Messages::CommitmentSigned(
-                _commitment_signed,
-            )) => {}
+                commitment_signed,
+            )) => {
+                self.commitment_signed(senders, commitment_signed)?;
+            }
 
-            Request::PeerMessage(Messages::RevokeAndAck(_revoke_ack)) => {}
+            Request::PeerMessage(Messages::RevokeAndAck(revoke_and_ack)) => {
+                self.revoke_and_ack(senders, revoke_and_ack)?;
+            }
 
             #[cfg(feature = "rgb")]
             Request::PeerMessage(Messages::AssignFunds(assign_req)) => {
@@ -382,6 +1154,12 @@ impl Runtime {
                     assign_req.blinding,
                     false,
                 )?;
+                let enquirer = self.enquirer.clone();
+                let _ = self.report_success_to(
+                    senders,
+                    &enquirer,
+                    Some("transfer completed"),
+                );
 
                 // TODO: Re-sign the commitment and return to the remote peer
             }
@@ -410,48 +1188,87 @@ impl Runtime {
         request: Request,
     ) -> Result<(), Error> {
         match request {
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
             Request::OpenChannelWith(request::CreateChannel {
                 channel_req,
                 peerd,
                 report_to,
+                shutdown_scriptpubkey,
+                fund_from_wallet,
+                funding_batch,
+                minimum_depth,
+                private,
+                ..
             }) => {
                 self.peer_service = peerd.clone();
                 self.enquirer = report_to.clone();
+                self.public = !private;
+                self.fund_from_wallet = fund_from_wallet;
+                self.funding_batch = funding_batch;
+                // Channels which must be used right away, like just-in-time
+                // channels of the service providers, are of zero-conf type
+                if minimum_depth == Some(0) {
+                    self.zero_conf = true;
+                }
+                if shutdown_scriptpubkey.is_some() {
+                    self.upfront_shutdown_script = shutdown_scriptpubkey;
+                }
 
                 if let ServiceId::Peer(ref addr) = peerd {
                     self.remote_peer = Some(addr.clone());
                 }
 
-                self.open_channel(senders, &channel_req).map_err(|err| {
-                    self.report_failure_to(
-                        senders,
-                        &report_to,
-                        microservices::rpc::Failure {
-                            code: 0, // TODO: Create error type system
-                            info: err.to_string(),
-                        },
-                    )
-                })?;
+                self.open_channel(senders, &mut channel_req).map_err(
+                    |err| {
+                        self.report_failure_to(
+                            senders,
+                            &report_to,
+                            microservices::rpc::Failure {
+                                code: 0, // TODO: Create error type system
+                                info: err.to_string(),
+                            },
+                        )
+                    },
+                )?;
 
-                self.send_peer(senders, Messages::OpenChannel(channel_req))?;
+                self.transition(State::Proposed, Trigger::Protocol)?;
 
-                self.state = Lifecycle::Proposed;
+                let tlvs = self.opening_tlvs();
+                self.send_extended(
+                    senders,
+                    Messages::OpenChannel(channel_req),
+                    tlvs,
+                )?;
             }
 
             Request::AcceptChannelFrom(request::CreateChannel {
                 channel_req,
                 peerd,
                 report_to,
+                minimum_depth,
+                private,
+                remote_tlvs,
+                ..
             }) => {
                 self.peer_service = peerd.clone();
-                self.state = Lifecycle::Proposed;
+                self.public = !private;
+                self.transition(State::Proposed, Trigger::Protocol)?;
 
                 if let ServiceId::Peer(ref addr) = peerd {
                     self.remote_peer = Some(addr.clone());
                 }
 
                 let accept_channel = self
-                    .accept_channel(senders, &channel_req, &peerd)
+                    .accept_channel(
+                        senders,
+                        &channel_req,
+                        &remote_tlvs,
+                        &peerd,
+                        minimum_depth,
+                    )
                     .map_err(|err| {
                         self.report_failure_to(
                             senders,
@@ -463,62 +1280,510 @@ impl Runtime {
                         )
                     })?;
 
-                self.send_peer(
+                self.transition(State::Accepted, Trigger::Protocol)?;
+
+                let tlvs = self.opening_tlvs();
+                self.send_extended(
                     senders,
                     Messages::AcceptChannel(accept_channel),
+                    tlvs,
                 )?;
+            }
+
+            Request::Failure(failure)
+                if source == ServiceId::Funding
+                    && self.force_closing.is_some() =>
+            {
+                warn!(
+                    "Funding wallet is unable to bump the commitment fee: {}",
+                    failure.info
+                );
+            }
+
+            Request::SignCpfp(child) => {
+                self.sign_cpfp(senders, child)?;
+            }
+
+            Request::WalletScript(script) if source == ServiceId::Funding => {
+                debug!("Funding wallet provides closing script {}", script);
+                self.wallet_script = Some(script);
+                // Channel which is not proposed yet has nothing to store
+                if self.state != State::Initial {
+                    self.save()?;
+                }
+            }
 
-                self.state = Lifecycle::Accepted;
+            Request::Failure(failure) if source == ServiceId::Funding => {
+                // Channel still may be funded by the user
+                self.fund_from_wallet = false;
+                let script_pubkey = PubkeyScript::ln_funding(
+                    self.funding_satoshis(),
+                    self.local_keys.funding_pubkey,
+                    self.remote_keys.funding_pubkey,
+                );
+                let info = format!(
+                    "Funding wallet is unable to fund the channel: {}; use \
+                     `fund` command with an output to {} instead",
+                    failure.info, script_pubkey
+                );
+                error!("{}", info);
+                let enquirer = self.enquirer.clone();
+                let _ = self.report_failure_to(
+                    senders,
+                    &enquirer,
+                    microservices::rpc::Failure { code: 0, info },
+                );
             }
 
             Request::FundChannel(funding_outpoint) => {
-                self.enquirer = source.into();
+                // Funding wallet acts on behalf of the client which has
+                // requested the channel
+                if source != ServiceId::Funding {
+                    self.enquirer = source.into();
+                }
 
                 let funding_created =
                     self.fund_channel(senders, funding_outpoint)?;
 
-                self.state = Lifecycle::Funding;
+                self.transition(State::Funding, Trigger::Protocol)?;
                 self.send_peer(
                     senders,
                     Messages::FundingCreated(funding_created),
                 )?;
             }
 
-            #[cfg(feature = "rgb")]
-            Request::RefillChannel(refill_req) => {
+            Request::SetChannelPolicy(policy) => {
                 self.enquirer = source.into();
+                self.set_forwarding_policy(senders, policy)?;
+            }
 
-                self.refill(
-                    senders,
-                    refill_req.consignment.clone(),
-                    refill_req.outpoint,
-                    refill_req.blinding,
-                    true,
-                )?;
-
-                let assign_funds = message::AssignFunds {
-                    channel_id: self.channel_id,
-                    consignment: refill_req.consignment,
-                    outpoint: refill_req.outpoint,
-                    blinding: refill_req.blinding,
-                };
-
-                self.send_peer(senders, Messages::AssignFunds(assign_funds))?;
+            Request::AbortChannel(_) => {
+                if !self.is_negotiating() {
+                    let failure = microservices::rpc::Failure {
+                        code: 0, // TODO: Create error type system
+                        info: format!(
+                            "Channel in {:?} state is funded and can't be \
+                             aborted; close it instead",
+                            self.state
+                        ),
+                    };
+                    self.send_ctl(senders, source, Request::Failure(failure))?;
+                    return Ok(());
+                }
+                let info = s!("Channel opening is cancelled by the user");
+                return Err(self.abort(senders, info));
             }
 
-            Request::Transfer(transfer_req) => {
+            Request::ReplaceFunding(funding_outpoint) => {
                 self.enquirer = source.into();
 
-                let update_add_htlc = self.transfer(senders, transfer_req)?;
+                let funding_created =
+                    self.replace_funding(senders, funding_outpoint)?;
 
                 self.send_peer(
                     senders,
-                    Messages::UpdateAddHtlc(update_add_htlc),
+                    Messages::FundingCreated(funding_created),
                 )?;
             }
 
-            Request::GetInfo => {
-                fn bmap<T>(
+            #[cfg(feature = "rgb")]
+            Request::FundChannelWithAssets(funding_req) => {
+                self.enquirer = source.into();
+
+                // Assets must be valid and known to RGB Node before we commit
+                // to the funding transaction
+                self.refill(
+                    senders,
+                    funding_req.consignment.clone(),
+                    funding_req.funding_outpoint,
+                    funding_req.blinding,
+                    true,
+                )?;
+
+                let funding_created =
+                    self.fund_channel(senders, funding_req.funding_outpoint)?;
+
+                self.transition(State::Funding, Trigger::Protocol)?;
+                self.send_peer(
+                    senders,
+                    Messages::FundingCreated(funding_created),
+                )?;
+
+                let assign_funds = message::AssignFunds {
+                    channel_id: self.channel_id,
+                    consignment: funding_req.consignment,
+                    outpoint: funding_req.funding_outpoint,
+                    blinding: funding_req.blinding,
+                };
+                self.send_peer(senders, Messages::AssignFunds(assign_funds))?;
+            }
+
+            #[cfg(feature = "rgb")]
+            Request::RefillChannel(refill_req) => {
+                self.enquirer = source.into();
+
+                self.refill(
+                    senders,
+                    refill_req.consignment.clone(),
+                    refill_req.outpoint,
+                    refill_req.blinding,
+                    true,
+                )?;
+
+                let assign_funds = message::AssignFunds {
+                    channel_id: self.channel_id,
+                    consignment: refill_req.consignment,
+                    outpoint: refill_req.outpoint,
+                    blinding: refill_req.blinding,
+                };
+
+                self.send_peer(senders, Messages::AssignFunds(assign_funds))?;
+                let enquirer = self.enquirer.clone();
+                let _ = self.report_success_to(
+                    senders,
+                    &enquirer,
+                    Some("transfer completed"),
+                );
+            }
+
+            Request::CloseChannel(request::CloseChannel { scriptpubkey }) => {
+                self.enquirer = source.into();
+
+                let upfront = self.upfront_shutdown_script.clone();
+                let failure = if self.state != State::Active {
+                    Some("Only active channels can be closed cooperatively")
+                } else if self.closing.is_some() {
+                    Some("Channel is already closing")
+                } else if upfront.is_some()
+                    && scriptpubkey.is_some()
+                    && scriptpubkey != upfront
+                {
+                    Some(
+                        "Channel is committed to the upfront shutdown script \
+                         and can't be closed to a different one",
+                    )
+                } else {
+                    None
+                };
+                if let Some(info) = failure {
+                    let enquirer = self.enquirer.clone();
+                    return Err(self.report_failure_to(
+                        senders,
+                        &enquirer,
+                        microservices::rpc::Failure {
+                            code: 0, // TODO: Create error type system
+                            info: info.to_owned(),
+                        },
+                    ));
+                }
+
+                let local_script = match scriptpubkey.or(upfront) {
+                    Some(script) => script,
+                    None => self.default_shutdown_script()?,
+                };
+                self.shutdown(senders, local_script)?;
+            }
+
+            Request::RecoverChannel(backup) => {
+                self.recover(senders, backup)?;
+            }
+
+            Request::RequestQuiescence => {
+                self.quiesce(senders, source)?;
+            }
+
+            Request::ReleaseQuiescence => {
+                self.release_quiescence(senders, source)?;
+            }
+
+            Request::PeerFeatures(features) => {
+                debug!("Remote peer has negotiated {}", features);
+                self.peer_features = Some(features);
+            }
+
+            Request::PeerDisconnected(node_addr) => {
+                if self.remote_peer.as_ref().map(|peer| peer.id)
+                    != Some(node_addr.id)
+                {
+                    warn!(
+                        "Ignoring disconnection of {} which is not the remote \
+                         peer of the channel",
+                        node_addr
+                    );
+                    return Ok(());
+                }
+                info!(
+                    "Remote peer {} is {}; channel updates are paused",
+                    node_addr,
+                    "disconnected".ended()
+                );
+                self.peer_connected = false;
+                self.peer_service = ServiceId::Loopback;
+            }
+
+            Request::PeerReconnected(node_addr) => {
+                if self.quiescence.is_pending() {
+                    // Quiescence does not survive disconnection
+                    self.quiescence.reset();
+                    let requester = self.quiescence_requester.take();
+                    let _ = self.report_failure_to(
+                        senders,
+                        &requester,
+                        microservices::rpc::Failure {
+                            code: 0, // TODO: Create error type system
+                            info: format!(
+                                "Quiescence of channel {} is ended by the \
+                                 peer disconnection",
+                                self.channel_id
+                            ),
+                        },
+                    );
+                }
+                self.peer_service = ServiceId::Peer(node_addr.clone());
+                self.remote_peer = Some(node_addr);
+                self.peer_connected = true;
+                self.reestablish(senders)?;
+            }
+
+            Request::UpdateChannelFee(feerate_per_kw) => {
+                self.enquirer = source.into();
+                let enquirer = self.enquirer.clone();
+                if let Err(err) = self.update_fee(senders, feerate_per_kw) {
+                    return Err(self.report_failure_to(
+                        senders,
+                        &enquirer,
+                        microservices::rpc::Failure {
+                            code: 0, // TODO: Create error type system
+                            info: err.to_string(),
+                        },
+                    ));
+                }
+                let msg = format!(
+                    "{} {} sat/kw",
+                    "Commitment feerate updated to".ended(),
+                    feerate_per_kw.ender()
+                );
+                let _ = self.report_success_to(senders, &enquirer, Some(msg));
+            }
+
+            Request::FeeEstimate(feerate_per_kw) if self.bumping_commitment => {
+                self.bumping_commitment = false;
+                self.bump_commitment(senders, feerate_per_kw)?;
+            }
+
+            Request::FeeEstimate(feerate_per_kw) => {
+                self.fee_estimated(senders, feerate_per_kw)?;
+            }
+
+            Request::ChainTransactions(txs) => {
+                for tx in txs {
+                    if self.recovering {
+                        self.sweep_recovered(senders, &tx)?;
+                    } else {
+                        self.check_breach(senders, &tx)?;
+                    }
+                }
+                // Chain height is used for funding timeout and validation of
+                // received HTLC expiry
+                self.send_ctl(
+                    senders,
+                    ServiceId::Chain,
+                    Request::GetChainInfo,
+                )?;
+            }
+
+            Request::ChainInfo(info) => {
+                self.chain_height = Some(info.height);
+                self.check_funding_timeout(senders, info.height)?;
+                self.claim_htlcs(senders)?;
+            }
+
+            Request::ForwardHtlc(forward) => {
+                self.htlc_forward(senders, forward)?;
+            }
+
+            Request::HtlcSettlement(HtlcSettlement { htlc_id, preimage }) => {
+                if !self.received_htlc.iter().any(|htlc| htlc.id == htlc_id) {
+                    warn!("Settlement for unknown HTLC #{}", htlc_id);
+                    return Ok(());
+                }
+                self.resolved_htlc.insert(htlc_id, preimage);
+                self.save()?;
+                self.resolve_htlcs(senders)?;
+                // Preimage allows claiming the HTLC output of our published
+                // commitment
+                self.claim_htlcs(senders)?;
+            }
+
+            Request::ForceCloseChannel => {
+                self.enquirer = source.into();
+                self.force_close(senders)?;
+            }
+
+            Request::Transfer(transfer_req) => {
+                self.enquirer = source.into();
+
+                let amount = transfer_req.amount;
+                let update_add_htlc = match self.transfer(senders, transfer_req)
+                {
+                    Ok(update_add_htlc) => update_add_htlc,
+                    Err(err) => {
+                        let enquirer = self.enquirer.clone();
+                        let _ = self.report_failure_to(
+                            senders,
+                            &enquirer,
+                            microservices::rpc::Failure {
+                                code: 0,
+                                info: err.to_string(),
+                            },
+                        );
+                        self.send_ctl(
+                            senders,
+                            ServiceId::Lnpd,
+                            Request::NodeEvent(NodeEvent {
+                                kind: NodeEventKind::PaymentFailed,
+                                channel_id: Some(self.channel_id),
+                                txid: None,
+                                amount_msat: Some(amount),
+                                details: err.to_string(),
+                            }),
+                        )?;
+                        return Err(err);
+                    }
+                };
+
+                self.send_peer(
+                    senders,
+                    Messages::UpdateAddHtlc(update_add_htlc),
+                )?;
+                self.send_commitment(senders)?;
+            }
+
+            Request::HookResult(HookResult {
+                id,
+                reject,
+                preimage,
+                ..
+            }) => {
+                let update_add_htlc = match self.hooked_htlc.remove(&id) {
+                    Some(update_add_htlc) => update_add_htlc,
+                    None => {
+                        warn!("Hook result for unknown HTLC #{}", id);
+                        return Ok(());
+                    }
+                };
+                match (reject, preimage) {
+                    (Some(reason), _) => {
+                        warn!("HTLC #{} is rejected by plugin: {}", id, reason);
+                        self.resolved_htlc.insert(id, None);
+                        self.save()?;
+                        self.resolve_htlcs(senders)?;
+                    }
+                    (None, Some(preimage))
+                        if HashLock::from(preimage)
+                            != update_add_htlc.payment_hash =>
+                    {
+                        warn!(
+                            "Plugin has resolved HTLC #{} with invalid \
+                             preimage",
+                            id
+                        );
+                        self.resolved_htlc.insert(id, None);
+                        self.save()?;
+                        self.resolve_htlcs(senders)?;
+                    }
+                    (None, Some(preimage)) => {
+                        info!("HTLC #{} is resolved by plugin", id);
+                        self.resolved_htlc.insert(id, Some(preimage));
+                        self.save()?;
+                        self.resolve_htlcs(senders)?;
+                    }
+                    (None, None) => {
+                        self.htlc_receive(senders, update_add_htlc)?;
+                    }
+                }
+            }
+
+            Request::TransactionConfirmed(TxDepth { txid, .. })
+                if self.funding_candidates.iter().any(|candidate| {
+                    candidate.funding_outpoint.txid == txid
+                }) =>
+            {
+                self.candidate_confirmed(senders, txid)?;
+                self.funding_confirmed(senders)?;
+            }
+
+            Request::TransactionConfirmed(TxDepth { txid, depth })
+                if txid == self.funding_outpoint.txid =>
+            {
+                info!(
+                    "Funding transaction {} has {} confirmation(s)",
+                    txid, depth
+                );
+                if self.state == State::Active && depth >= ANNOUNCEMENT_DEPTH {
+                    self.send_ctl(
+                        senders,
+                        ServiceId::Chain,
+                        Request::LocateOutput(self.funding_outpoint),
+                    )?;
+                } else {
+                    self.funding_confirmed(senders)?;
+                }
+            }
+
+            Request::OutputLocation(OutputLocation {
+                outpoint,
+                short_channel_id: Some(short_channel_id),
+            }) if outpoint == self.funding_outpoint => {
+                debug!(
+                    "Funding output of channel {} is at {}",
+                    self.channel_id, short_channel_id
+                );
+                self.short_channel_id = Some(short_channel_id);
+                self.save()?;
+                self.send_announcement_signatures(senders)?;
+                self.complete_announcement(senders)?;
+            }
+
+            Request::OutputLocation(OutputLocation { outpoint, .. })
+                if outpoint == self.funding_outpoint =>
+            {
+                warn!(
+                    "Funding output {} is not found in the chain; channel {} \
+                     is not announced",
+                    outpoint, self.channel_id
+                );
+            }
+
+            Request::TransactionConfirmed(TxDepth { txid, .. })
+                if Some(txid)
+                    == self.force_closing.as_ref().map(Transaction::txid) =>
+            {
+                self.sweep_to_local(senders)?;
+            }
+
+            Request::TransactionConfirmed(TxDepth { txid, .. })
+                if self.htlc_claims.iter().any(|claim| {
+                    claim.published && claim.tx.txid() == txid
+                }) =>
+            {
+                self.sweep_htlc(senders, txid)?;
+            }
+
+            Request::GetState => {
+                let data =
+                    match storage::ChannelDump::read(self.storage.as_ref())? {
+                        Some(dump) => strict_serialize(&dump)
+                            .map_err(|err| Error::Other(err.to_string()))?,
+                        // Channel which was never stored has nothing to
+                        // migrate
+                        None => vec![],
+                    };
+                self.send_ctl(senders, source, Request::DaemonState(data))?;
+            }
+
+            Request::GetInfo => {
+                fn bmap<T>(
                     remote_peer: &Option<NodeAddr>,
                     v: &T,
                 ) -> BTreeMap<NodeAddr, T>
@@ -539,19 +1804,30 @@ impl Runtime {
                 let info = ChannelInfo {
                     channel_id,
                     temporary_channel_id: self.temporary_channel_id,
-                    state: self.state,
-                    local_capacity: self.local_capacity,
+                    state: self.state.lifecycle(),
+                    local_capacity: self.local_capacity / 1000,
                     remote_capacities: bmap(
                         &self.remote_peer,
-                        &self.remote_capacity,
+                        &(self.remote_capacity / 1000),
                     ),
-                    assets: self.local_balances.keys().cloned().collect(),
+                    assets: self
+                        .local_balances
+                        .keys()
+                        .chain(self.remote_balances.keys())
+                        .cloned()
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect(),
                     local_balances: self.local_balances.clone(),
                     remote_balances: bmap(
                         &self.remote_peer,
                         &self.remote_balances,
                     ),
+                    offered_in_flight: self.assets_in_flight(true),
+                    received_in_flight: self.assets_in_flight(false),
                     funding_outpoint: self.funding_outpoint,
+                    scid_alias: self.scid_alias,
+                    remote_scid_alias: self.remote_scid_alias,
                     remote_peers: self
                         .remote_peer
                         .clone()
@@ -572,6 +1848,7 @@ impl Runtime {
                     params: self.params,
                     local_keys: self.local_keys.clone(),
                     remote_keys: bmap(&self.remote_peer, &self.remote_keys),
+                    forwarding_policy: self.forwarding_policy,
                 };
                 self.send_ctl(senders, source, Request::ChannelInfo(info))?;
             }
@@ -623,8 +1900,8 @@ impl Runtime {
     pub fn open_channel(
         &mut self,
         senders: &mut Senders,
-        channel_req: &message::OpenChannel,
-    ) -> Result<(), payment::channel::NegotiationError> {
+        channel_req: &mut message::OpenChannel,
+    ) -> Result<(), PolicyError> {
         info!(
             "{} remote peer to {} with temp id {:#}",
             "Proposing".promo(),
@@ -640,20 +1917,73 @@ impl Runtime {
             format!("Proposing remote peer to open a channel"),
         );
 
+        policy::check_channel_size(&self.policy, channel_req.funding_satoshis)?;
+        self.adapt_to_peer();
+        if channel_req.funding_satoshis >= MAX_FUNDING_SATOSHIS
+            && self
+                .peer_features
+                .map(|features| !features.large_channel)
+                .unwrap_or_default()
+        {
+            return Err(PolicyError::LargeChannel(
+                channel_req.funding_satoshis,
+            ));
+        }
+
+        // Remote peer may use the channel without funding confirmation only
+        // if we are ready to use it as well
+        self.channel_type = Some(ChannelType {
+            static_remotekey: self.static_remotekey,
+            anchors: self.anchors,
+            zero_conf: self.zero_conf,
+        });
+
         self.is_originator = true;
         self.params = payment::channel::Params::with(&channel_req)?;
-        self.local_keys = payment::channel::Keyset::from(channel_req);
+        self.feerate_per_kw = channel_req.feerate_per_kw;
+        self.dust_limit_satoshis = channel_req.dust_limit_satoshis;
+        self.key_salt = rand::random();
+        self.request_wallet_script(senders);
+        let keys = self.channel_keys();
+        channel_req.funding_pubkey = keys.funding_pubkey();
+        channel_req.revocation_basepoint = keys.revocation_basepoint();
+        channel_req.payment_point = keys.payment_basepoint();
+        channel_req.delayed_payment_basepoint =
+            keys.delayed_payment_basepoint();
+        channel_req.htlc_basepoint = keys.htlc_basepoint();
+        channel_req.first_per_commitment_point =
+            self.local_per_commitment_point(0);
+        self.local_keys = payment::channel::Keyset::from(&*channel_req);
+        self.local_limits = HtlcLimits::from(&*channel_req);
 
         Ok(())
     }
 
+    /// Disables channel options which are not supported by the remote peer.
+    /// Features may be unknown if the peer connection predates the channel
+    /// daemon, in which case the node configuration is used.
+    fn adapt_to_peer(&mut self) {
+        let features = match self.peer_features {
+            Some(features) => features,
+            None => return,
+        };
+        if self.static_remotekey && !features.static_remotekey {
+            info!(
+                "Remote peer does not support option_static_remotekey; \
+                 remote key will be rotated with each commitment"
+            );
+            self.static_remotekey = false;
+        }
+    }
+
     pub fn accept_channel(
         &mut self,
         senders: &mut Senders,
         channel_req: &message::OpenChannel,
+        tlvs: &TlvStream,
         peerd: &ServiceId,
-    ) -> Result<message::AcceptChannel, payment::channel::NegotiationError>
-    {
+        minimum_depth: Option<u32>,
+    ) -> Result<message::AcceptChannel, PolicyError> {
         let msg = format!(
             "{} with temp id {:#} from remote peer {}",
             "Accepting channel".promo(),
@@ -667,33 +1997,74 @@ impl Runtime {
         let enquirer = self.enquirer.clone();
         let _ = self.report_progress_to(senders, &enquirer, msg);
 
+        policy::check_open_channel(&self.policy, channel_req)?;
+        self.remote_upfront_shutdown_script =
+            policy::upfront_shutdown_script(tlvs)?;
+        self.adapt_to_peer();
+        let channel_type = match tlvs.get(&tlv::CHANNEL_TYPE) {
+            Some(data) => Some(ChannelType::from_bytes(data)?),
+            None => None,
+        };
+        if let Some(channel_type) = channel_type {
+            if (channel_type.static_remotekey && !self.static_remotekey)
+                || (channel_type.anchors && !self.anchors)
+                || (channel_type.zero_conf && !self.zero_conf)
+            {
+                return Err(PolicyError::ChannelTypeDisabled(channel_type));
+            }
+            // Channel type proposed by the remote peer is used as it is, or
+            // the channel is rejected
+            self.static_remotekey = channel_type.static_remotekey;
+            self.anchors = channel_type.anchors;
+        }
+        self.channel_type = channel_type;
+        let zero_conf = channel_type
+            .map(|channel_type| channel_type.zero_conf)
+            .unwrap_or_default();
+
         self.is_originator = false;
         self.params = payment::channel::Params::with(channel_req)?;
+        self.feerate_per_kw = channel_req.feerate_per_kw;
+        self.dust_limit_satoshis = channel_req.dust_limit_satoshis;
         self.remote_keys = payment::channel::Keyset::from(channel_req);
+        self.remote_limits = HtlcLimits::from(channel_req);
 
-        let dumb_key = self.node_id();
+        self.key_salt = rand::random();
+        self.request_wallet_script(senders);
+        let keys = self.channel_keys();
+        let funding_satoshis = channel_req.funding_satoshis;
         let accept_channel = message::AcceptChannel {
             temporary_channel_id: channel_req.temporary_channel_id,
             dust_limit_satoshis: channel_req.dust_limit_satoshis,
             max_htlc_value_in_flight_msat: channel_req
                 .max_htlc_value_in_flight_msat,
-            channel_reserve_satoshis: channel_req.channel_reserve_satoshis,
+            // Reserve must not be below any of the dust limits
+            channel_reserve_satoshis: self
+                .policy
+                .channel_reserve(funding_satoshis)
+                .max(channel_req.dust_limit_satoshis),
             htlc_minimum_msat: channel_req.htlc_minimum_msat,
-            minimum_depth: 3, // TODO: take from config options
+            // Channels of zero-conf type are locked right after funding,
+            // unless the depth is set by lnpd acceptance plugins
+            minimum_depth: match minimum_depth {
+                Some(depth) => depth,
+                None if zero_conf => 0,
+                None => self.policy.minimum_depth(funding_satoshis),
+            },
             to_self_delay: channel_req.to_self_delay,
             max_accepted_htlcs: channel_req.max_accepted_htlcs,
-            funding_pubkey: dumb_key,
-            revocation_basepoint: dumb_key,
-            payment_point: dumb_key,
-            delayed_payment_basepoint: dumb_key,
-            htlc_basepoint: dumb_key,
-            first_per_commitment_point: dumb_key,
-            /* shutdown_scriptpubkey: None,
-             * unknown_tlvs: none!(), */
+            funding_pubkey: keys.funding_pubkey(),
+            revocation_basepoint: keys.revocation_basepoint(),
+            payment_point: keys.payment_basepoint(),
+            delayed_payment_basepoint: keys.delayed_payment_basepoint(),
+            htlc_basepoint: keys.htlc_basepoint(),
+            first_per_commitment_point: self.local_per_commitment_point(0),
         };
 
         self.params.updated(&accept_channel, None)?;
         self.local_keys = payment::channel::Keyset::from(&accept_channel);
+        self.local_limits = HtlcLimits::from(&accept_channel);
+        self.minimum_depth = accept_channel.minimum_depth;
 
         let msg = format!(
             "{} channel {:#} from remote peer {}",
@@ -711,8 +2082,9 @@ impl Runtime {
         &mut self,
         senders: &mut Senders,
         accept_channel: &message::AcceptChannel,
+        tlvs: &TlvStream,
         peerd: &ServiceId,
-    ) -> Result<(), payment::channel::NegotiationError> {
+    ) -> Result<(), PolicyError> {
         info!(
             "Channel {:#} {} by the remote peer {}",
             accept_channel.temporary_channel_id.ender(),
@@ -735,9 +2107,26 @@ impl Runtime {
         );
         info!("{}", msg);
 
-        // TODO: Add a reasonable min depth bound
+        policy::check_accept_channel(
+            &self.policy,
+            self.params.funding_satoshis,
+            accept_channel,
+        )?;
+        self.remote_upfront_shutdown_script =
+            policy::upfront_shutdown_script(tlvs)?;
+        if let Some(data) = tlvs.get(&tlv::CHANNEL_TYPE) {
+            let accepted = ChannelType::from_bytes(data)?;
+            let proposed = self.channel_type.unwrap_or_default();
+            if accepted != proposed {
+                return Err(PolicyError::ChannelTypeMismatch(
+                    accepted, proposed,
+                ));
+            }
+        }
         self.params.updated(accept_channel, None)?;
         self.remote_keys = payment::channel::Keyset::from(accept_channel);
+        self.remote_limits = HtlcLimits::from(accept_channel);
+        self.minimum_depth = accept_channel.minimum_depth;
 
         let msg = format!(
             "Channel {:#} is {}",
@@ -770,6 +2159,13 @@ impl Runtime {
 
         self.funding_outpoint = funding_outpoint;
         self.funding_update(senders)?;
+        self.book_event(
+            senders,
+            AccountingEventKind::ChannelFunded,
+            None,
+            self.channel_capacity(),
+            format!("Funding of channel {}", self.channel_id),
+        )?;
 
         let signature = self.sign_funding();
         let funding_created = message::FundingCreated {
@@ -791,6 +2187,117 @@ impl Runtime {
         Ok(funding_created)
     }
 
+    /// Replaces funding transaction of the channel which is not locked yet,
+    /// i.e. for bumping its fee. The remote peer has to sign commitment
+    /// transaction spending the new funding output once again. Replaced
+    /// transaction is kept as a candidate, since it still may get mined
+    /// instead of the new one.
+    pub fn replace_funding(
+        &mut self,
+        senders: &mut Senders,
+        funding_outpoint: OutPoint,
+    ) -> Result<message::FundingCreated, Error> {
+        let enquirer = self.enquirer.clone();
+
+        if !self.is_originator {
+            return Err(Error::Other(s!(
+                "Funding transaction can be replaced only by the channel \
+                 originator"
+            )));
+        }
+        if self.state != State::Funding
+            && !matches!(self.state, State::Funded { .. })
+        {
+            return Err(Error::Other(format!(
+                "Funding transaction can't be replaced in {:?} state",
+                self.state
+            )));
+        }
+        if !self.local_balances.is_empty() {
+            return Err(Error::Other(s!(
+                "Funding transaction of the channel with assets can't be \
+                 replaced"
+            )));
+        }
+        if funding_outpoint == self.funding_outpoint {
+            return Err(Error::Other(s!(
+                "New funding outpoint matches the current one"
+            )));
+        }
+
+        info!(
+            "{} funding transaction {} with {}",
+            "Replacing".promo(),
+            self.funding_outpoint.txid,
+            funding_outpoint.txid.promoter()
+        );
+        if matches!(self.state, State::Funded { .. }) {
+            self.keep_funding_candidate();
+        }
+        self.funding_outpoint = funding_outpoint;
+        self.funding_update(senders)?;
+        self.save()?;
+
+        let signature = self.sign_funding();
+        let funding_created = message::FundingCreated {
+            temporary_channel_id: self.temporary_channel_id,
+            funding_txid: self.funding_outpoint.txid,
+            funding_output_index: self.funding_outpoint.vout as u16,
+            signature,
+        };
+        trace!("Prepared funding_created: {:?}", funding_created);
+
+        let msg = format!(
+            "{} for channel {:#}. Awaiting for remote node signature.",
+            "Funding replaced".ended(),
+            self.channel_id.ender()
+        );
+        info!("{}", msg);
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+
+        Ok(funding_created)
+    }
+
+    /// Keeps the current signed funding transaction as a candidate before it
+    /// is replaced
+    fn keep_funding_candidate(&mut self) {
+        self.funding_candidates.push(FundingCandidate {
+            funding_outpoint: self.funding_outpoint,
+            remote_signature: self.remote_signature,
+            local_commitment: self.local_commitment.clone(),
+        });
+    }
+
+    /// Switches the channel to the replaced funding transaction which has
+    /// got mined instead of the current one
+    fn candidate_confirmed(
+        &mut self,
+        senders: &mut Senders,
+        txid: bitcoin::Txid,
+    ) -> Result<(), Error> {
+        if !matches!(self.state, State::Funded { .. }) {
+            debug!("Ignoring funding confirmation in {:?} state", self.state);
+            return Ok(());
+        }
+        let pos = self
+            .funding_candidates
+            .iter()
+            .position(|candidate| candidate.funding_outpoint.txid == txid)
+            .expect("candidate presence is checked by the caller");
+        let candidate = self.funding_candidates.remove(pos);
+        warn!(
+            "Replaced funding transaction {} is {} instead of {}",
+            txid,
+            "mined".promo(),
+            self.funding_outpoint.txid
+        );
+        self.funding_outpoint = candidate.funding_outpoint;
+        self.remote_signature = candidate.remote_signature;
+        self.local_commitment = candidate.local_commitment;
+        self.update_channel_id(senders)?;
+        self.save()
+    }
+
     pub fn funding_created(
         &mut self,
         senders: &mut Senders,
@@ -816,8 +2323,9 @@ impl Runtime {
             txid: funding_created.funding_txid,
             vout: funding_created.funding_output_index as u32,
         };
-        // TODO: Save signature!
         self.funding_update(senders)?;
+        self.verify_funding(senders, funding_created.signature)?;
+        self.save()?;
 
         let signature = self.sign_funding();
         let funding_signed = message::FundingSigned {
@@ -841,63 +2349,2941 @@ impl Runtime {
         &mut self,
         senders: &mut Senders,
     ) -> Result<(), Error> {
-        let mut engine = sha256::Hash::engine();
-        if self.is_originator {
-            engine.input(&self.local_keys.payment_basepoint.serialize());
-            engine.input(&self.remote_keys.payment_basepoint.serialize());
+        self.obscuring_factor = if self.is_originator {
+            commitment::obscuring_factor(
+                self.local_keys.payment_basepoint,
+                self.remote_keys.payment_basepoint,
+            )
         } else {
-            engine.input(&self.remote_keys.payment_basepoint.serialize());
-            engine.input(&self.local_keys.payment_basepoint.serialize());
-        }
-        let obscuring_hash = sha256::Hash::from_engine(engine);
-        trace!("Obscuring hash: {}", obscuring_hash);
-
-        let mut buf = [0u8; 8];
-        buf.copy_from_slice(&obscuring_hash[24..]);
-        self.obscuring_factor = u64::from_be_bytes(buf);
+            commitment::obscuring_factor(
+                self.remote_keys.payment_basepoint,
+                self.local_keys.payment_basepoint,
+            )
+        };
         trace!("Obscuring factor: {:#016x}", self.obscuring_factor);
         self.commitment_number = 0;
+        self.init_capacity();
 
         self.update_channel_id(senders)?;
 
         Ok(())
     }
 
-    pub fn sign_funding(&mut self) -> secp256k1::Signature {
-        // We are doing counterparty's transaction!
-        let mut cmt_tx = Transaction::ln_cmt_base(
-            self.remote_capacity,
-            self.local_capacity,
-            self.commitment_number,
-            self.obscuring_factor,
-            self.funding_outpoint,
-            self.local_keys.payment_basepoint,
-            self.local_keys.revocation_basepoint,
-            self.remote_keys.delayed_payment_basepoint,
-            self.params.to_self_delay,
-        );
-        trace!("Counterparty's commitment tx: {:?}", cmt_tx);
+    /// Assigns the whole channel capacity to the funder, such that the first
+    /// commitment transactions pay to the funder and have the fee deducted
+    fn init_capacity(&mut self) {
+        if self.local_capacity != 0 || self.remote_capacity != 0 {
+            return;
+        }
+        if self.is_originator {
+            self.local_capacity = self.params.funding_satoshis * 1000;
+        } else {
+            self.remote_capacity = self.params.funding_satoshis * 1000;
+        }
+    }
 
-        let mut sig_hasher = SigHashCache::new(&mut cmt_tx);
+    /// Computes signature hash of a commitment or closing transaction
+    /// spending the funding output
+    fn funding_sighash(&self, cmt_tx: &mut Transaction) -> secp256k1::Message {
+        // BIP-143 script code of P2WSH output is its witness script
+        let witness_script = WitnessScript::ln_funding(
+            self.funding_satoshis(),
+            self.local_keys.funding_pubkey,
+            self.remote_keys.funding_pubkey,
+        );
+        let mut sig_hasher = SigHashCache::new(cmt_tx);
         let sighash = sig_hasher.signature_hash(
             0,
-            &PubkeyScript::ln_funding(
-                self.channel_capacity(),
-                self.local_keys.funding_pubkey,
-                self.remote_keys.funding_pubkey,
-            )
-            .into(),
-            self.channel_capacity(),
+            &witness_script,
+            self.funding_satoshis(),
             SigHashType::All,
         );
-        let sign_msg = secp256k1::Message::from_slice(&sighash[..])
+        secp256k1::Message::from_slice(&sighash[..])
+            .expect("Sighash size always match requirements")
+    }
+
+    /// Verifies remote signature for our first commitment transaction and
+    /// keeps it, allowing unilateral close of the channel. If the signature
+    /// is invalid the channel is failed: the remote peer is sent an error
+    /// and the enquirer is reported about the failure.
+    pub fn verify_funding(
+        &mut self,
+        senders: &mut Senders,
+        signature: secp256k1::Signature,
+    ) -> Result<(), Error> {
+        // This time it is our own transaction
+        let mut cmt_tx = self.local_commitment_tx();
+        trace!("Local commitment tx: {:?}", cmt_tx);
+
+        let sign_msg = self.funding_sighash(&mut cmt_tx);
+        if let Err(err) = secp256k1::Secp256k1::verification_only().verify(
+            &sign_msg,
+            &signature,
+            &self.remote_keys.funding_pubkey,
+        ) {
+            let info = format!(
+                "Remote signature for commitment transaction of channel {} \
+                 is invalid: {}",
+                self.channel_id, err
+            );
+            return Err(self.fail_channel(senders, info));
+        }
+
+        trace!("Remote commitment transaction signature verified");
+        self.remote_signature = Some(signature);
+        self.local_commitment = Some(cmt_tx);
+        Ok(())
+    }
+
+    /// Fails the channel: the remote peer is sent an error and the enquirer
+    /// is reported about the failure
+    fn fail_channel(&mut self, senders: &mut Senders, info: String) -> Error {
+        error!("{}", info.err());
+        // Channels which are not funded yet are known to the peer by their
+        // temporary id; all-zero id would fail all channels with the peer
+        let channel_id = if self.channel_id == zero!() {
+            self.temporary_channel_id.into()
+        } else {
+            self.channel_id
+        };
+        // Ignoring possible error here: the channel is failed anyway
+        let _ = self.send_peer(
+            senders,
+            Messages::Error(message::Error {
+                channel_id,
+                data: info.as_bytes().to_vec(),
+            }),
+        );
+        let enquirer = self.enquirer.clone();
+        self.report_failure_to(
+            senders,
+            &enquirer,
+            microservices::rpc::Failure {
+                code: 0, // TODO: Create error type system
+                info,
+            },
+        )
+    }
+
+    /// Processes error sent by the remote peer, which fails the channel. Funded
+    /// channels are closed with our latest commitment transaction.
+    fn peer_error(
+        &mut self,
+        senders: &mut Senders,
+        error: message::Error,
+    ) -> Result<(), Error> {
+        let info = format!(
+            "Remote peer failed channel {}: {}",
+            self.channel_id,
+            String::from_utf8_lossy(&error.data)
+        );
+        error!("{}", info.err());
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::NodeEvent(NodeEvent {
+                kind: NodeEventKind::ChannelFailed,
+                channel_id: Some(self.channel_id),
+                txid: None,
+                amount_msat: None,
+                details: info.clone(),
+            }),
+        )?;
+        let enquirer = self.enquirer.clone();
+        let _ = self.report_failure_to(
+            senders,
+            &enquirer,
+            microservices::rpc::Failure { code: 0, info },
+        );
+
+        let funded = matches!(self.state, State::Funded { .. })
+            || self.state == State::Locked
+            || self.state == State::Active;
+        // Recovered channels have no commitment to publish: the remote peer
+        // is expected to close the channel
+        if funded && self.force_closing.is_none() && !self.recovering {
+            self.force_close(senders)?;
+        }
+        Ok(())
+    }
+
+    /// Logs `warning` from the remote peer and passes it to the enquirer.
+    /// Unlike errors, warnings do not fail the channel.
+    fn peer_warning(
+        &mut self,
+        senders: &mut Senders,
+        warning: message::Warning,
+    ) {
+        let msg = format!(
+            "Remote peer warns on channel {}: {}",
+            self.channel_id,
+            String::from_utf8_lossy(&warning.data)
+        );
+        warn!("{}", msg.err());
+        let enquirer = self.enquirer.clone();
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+    }
+
+    /// Constructs our current commitment transaction
+    fn local_commitment_tx(&self) -> Transaction {
+        self.commitment_tx(true, self.commitment_number).0
+    }
+
+    /// Constructs commitment transaction of the local or remote side with
+    /// outputs for all HTLCs in flight. Returns the transaction together with
+    /// its HTLC outputs and their output numbers.
+    fn commitment_tx(
+        &self,
+        local: bool,
+        commitment_number: u64,
+    ) -> (Transaction, Vec<(u32, HtlcOutput)>) {
+        let (owner_keys, counterparty_keys) = if local {
+            (&self.local_keys, &self.remote_keys)
+        } else {
+            (&self.remote_keys, &self.local_keys)
+        };
+        let keys = self.commitment_keys(local, commitment_number);
+
+        // Only bitcoin HTLCs have outputs; assets are transferred with RGB
+        // state transitions
+        let offered = self
+            .offered_htlc
+            .iter()
+            .filter(|htlc| htlc.asset_id.is_none())
+            .map(|htlc| {
+                (htlc.amount, htlc.cltv_expiry, self.offered_hash(htlc))
+            });
+        let received = self
+            .received_htlc
+            .iter()
+            .filter(|htlc| htlc.asset_id.is_none())
+            .map(|htlc| (htlc.amount, htlc.cltv_expiry, htlc.hashlock));
+        let (owner_offered, owner_received): (Vec<_>, Vec<_>) = if local {
+            (offered.collect(), received.collect())
+        } else {
+            (received.collect(), offered.collect())
+        };
+
+        // Funder pays commitment fee, which depends on the number of HTLC
+        // outputs not trimmed to dust, and for both anchor outputs
+        let htlc_outputs = owner_offered
+            .iter()
+            .map(|(amount, ..)| (true, *amount))
+            .chain(owner_received.iter().map(|(amount, ..)| (false, *amount)))
+            .filter(|(is_offered, amount)| {
+                !self.is_dust_htlc(*amount, *is_offered, self.feerate_per_kw)
+            })
+            .count();
+        let fee = fees::commitment_fee(
+            self.feerate_per_kw,
+            self.anchors,
+            htlc_outputs,
+        );
+        // Balances are rounded down to satoshis
+        let (mut owner_amount, mut counterparty_amount) = if local {
+            (self.local_capacity / 1000, self.remote_capacity / 1000)
+        } else {
+            (self.remote_capacity / 1000, self.local_capacity / 1000)
+        };
+        let funder_amount = if local == self.is_originator {
+            &mut owner_amount
+        } else {
+            &mut counterparty_amount
+        };
+        *funder_amount = fees::funder_amount(*funder_amount, fee, self.anchors);
+        trace!(
+            "Commitment fee is {} sat for {} HTLC outputs at {} sat/kw",
+            fee,
+            htlc_outputs,
+            self.feerate_per_kw
+        );
+
+        let mut cmt_tx = commitment::base_tx(
+            self.funding_outpoint,
+            commitment_number,
+            self.obscuring_factor,
+        );
+        let to_remote_script = if self.anchors {
+            anchors::to_remote_script(keys.to_remote_pubkey).to_v0_p2wsh()
+        } else {
+            Script::new_v0_wpkh(
+                &bitcoin::PublicKey {
+                    compressed: true,
+                    key: keys.to_remote_pubkey,
+                }
+                .wpubkey_hash()
+                .expect("compressed public key always has witness hash"),
+            )
+        };
+        cmt_tx.output = vec![
+            TxOut {
+                value: owner_amount,
+                script_pubkey: commitment::to_local_script(
+                    keys.revocation_pubkey,
+                    keys.delayed_pubkey,
+                    self.params.to_self_delay,
+                )
+                .to_v0_p2wsh(),
+            },
+            TxOut {
+                value: counterparty_amount,
+                script_pubkey: to_remote_script,
+            },
+        ];
+        // Outputs below the dust limit are trimmed and go to fees
+        cmt_tx
+            .output
+            .retain(|txout| txout.value >= self.dust_limit_satoshis);
+
+        let mut htlcs = vec![];
+        // HTLC amounts are in millisatoshis, and their outputs are rounded
+        // down to satoshis
+        for (is_offered, (amount_msat, cltv_expiry, payment_hash)) in
+            owner_offered
+                .into_iter()
+                .map(|htlc| (true, htlc))
+                .chain(owner_received.into_iter().map(|htlc| (false, htlc)))
+        {
+            // HTLCs which can't pay for their second-stage transaction are
+            // trimmed to dust, and their amounts go to fees
+            if self.is_dust_htlc(amount_msat, is_offered, self.feerate_per_kw) {
+                trace!(
+                    "Trimming {} HTLC of {} msat to dust",
+                    if is_offered { "offered" } else { "received" },
+                    amount_msat
+                );
+                continue;
+            }
+            let amount = amount_msat / 1000;
+            let (witness_script, timeout) = if is_offered {
+                (
+                    commitment::offered_htlc_script(
+                        keys.revocation_pubkey,
+                        keys.owner_htlc_pubkey,
+                        keys.counterparty_htlc_pubkey,
+                        payment_hash.as_ref(),
+                        self.anchors,
+                    ),
+                    Some(cltv_expiry),
+                )
+            } else {
+                (
+                    commitment::received_htlc_script(
+                        keys.revocation_pubkey,
+                        keys.owner_htlc_pubkey,
+                        keys.counterparty_htlc_pubkey,
+                        payment_hash.as_ref(),
+                        cltv_expiry,
+                        self.anchors,
+                    ),
+                    None,
+                )
+            };
+            let script_pubkey = witness_script.to_v0_p2wsh();
+            cmt_tx.output.push(TxOut {
+                value: amount,
+                script_pubkey: script_pubkey.clone(),
+            });
+            htlcs.push(HtlcOutput {
+                witness_script,
+                script_pubkey,
+                amount,
+                payment_hash,
+                timeout,
+            });
+        }
+
+        if self.anchors {
+            // Each side has an anchor if it has an output to spend it with
+            for (amount, funding_pubkey) in vec![
+                (owner_amount, owner_keys.funding_pubkey),
+                (counterparty_amount, counterparty_keys.funding_pubkey),
+            ] {
+                if amount >= self.dust_limit_satoshis || !htlcs.is_empty() {
+                    cmt_tx.output.push(TxOut {
+                        value: ANCHOR_OUTPUT_VALUE,
+                        script_pubkey: anchors::anchor_script(funding_pubkey)
+                            .to_v0_p2wsh(),
+                    });
+                }
+            }
+        }
+
+        // BIP69 output ordering required by BOLT3
+        cmt_tx.output.sort_by(|a, b| {
+            (a.value, &a.script_pubkey).cmp(&(b.value, &b.script_pubkey))
+        });
+        // Identical HTLC outputs are ordered by their CLTV expiry
+        htlcs.sort_by_key(|htlc| htlc.timeout);
+        let mut htlc_outputs = vec![];
+        for htlc in htlcs {
+            let vout = cmt_tx
+                .output
+                .iter()
+                .enumerate()
+                .position(|(vout, txout)| {
+                    txout.script_pubkey == htlc.script_pubkey
+                        && !htlc_outputs
+                            .iter()
+                            .any(|(used, _)| *used == vout as u32)
+                })
+                .expect("HTLC output is always present in the commitment")
+                as u32;
+            htlc_outputs.push((vout, htlc));
+        }
+        htlc_outputs.sort_by_key(|(vout, _)| *vout);
+
+        (cmt_tx, htlc_outputs)
+    }
+
+    /// Seed for the local per-commitment secrets. Derived from the node key,
+    /// the temporary channel id and the persisted key salt.
+    fn commitment_seed(&self) -> [u8; 32] {
+        self.derive_seed(b"lnp-node:per-commitment-seed")
+    }
+
+    /// Local per-commitment secret for the commitment number
+    fn local_per_commitment_secret(
+        &self,
+        commitment_number: u64,
+    ) -> secp256k1::SecretKey {
+        shachain::commitment_secret(self.commitment_seed(), commitment_number)
+    }
+
+    /// Local per-commitment point for the commitment number
+    fn local_per_commitment_point(
+        &self,
+        commitment_number: u64,
+    ) -> secp256k1::PublicKey {
+        shachain::commitment_point(self.commitment_seed(), commitment_number)
+    }
+
+    /// Per-commitment point of the local commitment transaction with the
+    /// given number or of the next remote commitment transaction
+    fn per_commitment_point(
+        &self,
+        local: bool,
+        commitment_number: u64,
+    ) -> secp256k1::PublicKey {
+        if local {
+            return self.local_per_commitment_point(commitment_number);
+        }
+        // Remote peer reveals the point of its next commitment together
+        // with the secret of the revoked one, so we know the points of the
+        // current and the next commitments and derive the revoked ones
+        let revoked = self.remote_secrets.count();
+        let first_point = self.remote_keys.first_per_commitment_point;
+        match commitment_number {
+            0 => first_point,
+            n if n == revoked => {
+                self.remote_revoked_point.unwrap_or(first_point)
+            }
+            n if n == revoked + 1 => {
+                self.remote_per_commitment_point.unwrap_or(first_point)
+            }
+            n => self
+                .remote_secrets
+                .get(n)
+                .map(|secret| {
+                    secp256k1::PublicKey::from_secret_key(
+                        &secp256k1::Secp256k1::signing_only(),
+                        &secret,
+                    )
+                })
+                .expect(
+                    "remote commitment is signed only after the previous one \
+                     is revoked",
+                ),
+        }
+    }
+
+    /// Whether the remote peer has not yet revoked its previous commitment,
+    /// so the point for its next commitment is not known
+    fn awaiting_revocation(&self) -> bool {
+        self.remote_secrets.count() < self.remote_commitment_number
+    }
+
+    /// Keys of the local or remote commitment transaction with the given
+    /// number
+    fn commitment_keys(
+        &self,
+        local: bool,
+        commitment_number: u64,
+    ) -> CommitmentKeys {
+        let (owner_keys, counterparty_keys) = if local {
+            (&self.local_keys, &self.remote_keys)
+        } else {
+            (&self.remote_keys, &self.local_keys)
+        };
+        CommitmentKeys::derive(
+            owner_keys,
+            counterparty_keys,
+            self.per_commitment_point(local, commitment_number),
+            self.static_remotekey,
+        )
+    }
+
+    /// Seed of the channel keys. Derived from the node key, the temporary
+    /// channel id and the persisted key salt.
+    fn channel_seed(&self) -> [u8; 32] {
+        self.derive_seed(b"lnp-node:channel-seed")
+    }
+
+    fn derive_seed(&self, tag: &[u8]) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        engine.input(tag);
+        engine.input(&self.local_node.private_key()[..]);
+        self.temporary_channel_id
+            .strict_encode(&mut engine)
+            .expect("hash engines do not fail");
+        engine.input(&self.key_salt);
+        sha256::Hash::from_engine(engine).into_inner()
+    }
+
+    /// Our funding key and basepoint secrets
+    fn channel_keys(&self) -> ChannelKeys {
+        ChannelKeys::derive(self.channel_seed())
+    }
+
+    /// Signs transaction spending the funding output with our funding key
+    fn sign_with_funding_key(
+        &self,
+        sign_msg: &secp256k1::Message,
+    ) -> secp256k1::Signature {
+        secp256k1::Secp256k1::signing_only()
+            .sign(sign_msg, &self.channel_keys().funding_secret)
+    }
+
+    /// Fee of the second-stage HTLC-timeout transaction for HTLCs offered by
+    /// the commitment owner, or HTLC-success transaction otherwise
+    fn htlc_tx_fee(&self, offered: bool, feerate_per_kw: u32) -> u64 {
+        fees::htlc_tx_fee(feerate_per_kw, self.anchors, offered)
+    }
+
+    /// Checks whether HTLC is trimmed from the commitment transaction at the
+    /// given feerate
+    fn is_dust_htlc(
+        &self,
+        amount_msat: u64,
+        offered: bool,
+        feerate_per_kw: u32,
+    ) -> bool {
+        fees::is_dust_htlc(
+            amount_msat,
+            offered,
+            feerate_per_kw,
+            self.anchors,
+            self.dust_limit_satoshis,
+        )
+    }
+
+    /// Constructs second-stage HTLC-timeout (for HTLCs offered by the
+    /// commitment owner) or HTLC-success transaction spending HTLC output
+    /// of a commitment transaction
+    fn htlc_tx(
+        &self,
+        keys: &CommitmentKeys,
+        cmt_tx: &Transaction,
+        vout: u32,
+        htlc: &HtlcOutput,
+    ) -> Transaction {
+        let fee = self.htlc_tx_fee(htlc.timeout.is_some(), self.feerate_per_kw);
+        commitment::htlc_tx(
+            OutPoint::new(cmt_tx.txid(), vout),
+            htlc.amount.saturating_sub(fee),
+            htlc.timeout,
+            self.anchors,
+            &commitment::to_local_script(
+                keys.revocation_pubkey,
+                keys.delayed_pubkey,
+                self.params.to_self_delay,
+            ),
+        )
+    }
+
+    /// Computes signature hash of a second-stage HTLC transaction
+    fn htlc_sighash(
+        &self,
+        htlc_tx: &mut Transaction,
+        htlc: &HtlcOutput,
+    ) -> secp256k1::Message {
+        let mut sig_hasher = SigHashCache::new(htlc_tx);
+        let sighash = sig_hasher.signature_hash(
+            0,
+            &htlc.witness_script,
+            htlc.amount,
+            if self.anchors {
+                SigHashType::SinglePlusAnyoneCanPay
+            } else {
+                SigHashType::All
+            },
+        );
+        secp256k1::Message::from_slice(&sighash[..])
+            .expect("Sighash size always match requirements")
+    }
+
+    /// Signs remote commitment transaction with the given number and the
+    /// second-stage transactions spending its HTLC outputs
+    fn sign_commitment(
+        &self,
+        commitment_number: u64,
+    ) -> (secp256k1::Signature, Vec<secp256k1::Signature>) {
+        // We are doing counterparty's transaction!
+        let (mut cmt_tx, htlcs) = self.commitment_tx(false, commitment_number);
+        trace!("Counterparty's commitment tx: {:?}", cmt_tx);
+
+        let sign_msg = self.funding_sighash(&mut cmt_tx);
+        let signature = self.sign_with_funding_key(&sign_msg);
+        // Our HTLC key in the remote commitment is derived with the remote
+        // per-commitment point
+        let keys = self.commitment_keys(false, commitment_number);
+        let htlc_secret = derive_secret(
+            self.channel_keys().htlc_basepoint_secret,
+            self.per_commitment_point(false, commitment_number),
+        );
+        let secp = secp256k1::Secp256k1::signing_only();
+        let htlc_signatures = htlcs
+            .iter()
+            .map(|(vout, htlc)| {
+                let mut htlc_tx = self.htlc_tx(&keys, &cmt_tx, *vout, htlc);
+                let sign_msg = self.htlc_sighash(&mut htlc_tx, htlc);
+                secp.sign(&sign_msg, &htlc_secret)
+            })
+            .collect();
+        trace!("Commitment transaction signatures created");
+
+        (signature, htlc_signatures)
+    }
+
+    /// Signs the next remote commitment transaction reflecting current set of
+    /// HTLCs and sends it to the remote peer. If the remote peer has not yet
+    /// revoked its previous commitment, the new one is signed once it does.
+    fn send_commitment(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        if self.awaiting_revocation() {
+            debug!(
+                "Remote commitment is signed after revocation of #{}",
+                self.remote_commitment_number.saturating_sub(1)
+            );
+            self.remote_commitment_dirty = true;
+            return self.save();
+        }
+        let commitment_number = self.remote_commitment_number + 1;
+        let (signature, htlc_signatures) =
+            self.sign_commitment(commitment_number);
+        self.keep_remote_commitment(commitment_number);
+        self.remote_commitment_number = commitment_number;
+        self.remote_commitment_dirty = false;
+        self.save()?;
+
+        debug!("Signing remote commitment #{}", commitment_number);
+        self.send_peer(
+            senders,
+            Messages::CommitmentSigned(message::CommitmentSigned {
+                channel_id: self.channel_id,
+                signature,
+                htlc_signatures,
+            }),
+        )
+    }
+
+    /// Verifies remote signatures for our next commitment transaction and
+    /// revokes the previous one. If the HTLC set has changed since we have
+    /// signed the remote commitment, we sign the new one.
+    fn commitment_signed(
+        &mut self,
+        senders: &mut Senders,
+        commitment_signed: message::CommitmentSigned,
+    ) -> Result<(), Error> {
+        let commitment_number = self.commitment_number + 1;
+        let (mut cmt_tx, htlcs) = self.commitment_tx(true, commitment_number);
+        trace!("Local commitment tx #{}: {:?}", commitment_number, cmt_tx);
+
+        let keys = self.commitment_keys(true, commitment_number);
+        let secp = secp256k1::Secp256k1::verification_only();
+        let sign_msg = self.funding_sighash(&mut cmt_tx);
+        let mut valid = secp
+            .verify(
+                &sign_msg,
+                &commitment_signed.signature,
+                &self.remote_keys.funding_pubkey,
+            )
+            .is_ok()
+            && htlcs.len() == commitment_signed.htlc_signatures.len();
+        for ((vout, htlc), signature) in
+            htlcs.iter().zip(&commitment_signed.htlc_signatures)
+        {
+            let mut htlc_tx = self.htlc_tx(&keys, &cmt_tx, *vout, htlc);
+            let sign_msg = self.htlc_sighash(&mut htlc_tx, htlc);
+            valid &= secp
+                .verify(&sign_msg, signature, &keys.counterparty_htlc_pubkey)
+                .is_ok();
+        }
+        if !valid {
+            let info = format!(
+                "Remote signatures for commitment #{} of channel {} are \
+                 invalid",
+                commitment_number, self.channel_id
+            );
+            return Err(self.fail_channel(senders, info));
+        }
+
+        // Remote HTLC signatures allow us to claim HTLC outputs if we close
+        // the channel unilaterally
+        let htlc_claims = htlcs
+            .iter()
+            .zip(&commitment_signed.htlc_signatures)
+            .map(|((vout, htlc), signature)| HtlcClaim {
+                tx: self.htlc_tx(&keys, &cmt_tx, *vout, htlc),
+                witness_script: htlc.witness_script.clone(),
+                amount: htlc.amount,
+                payment_hash: htlc.payment_hash,
+                offered: htlc.timeout.is_some(),
+                remote_signature: *signature,
+                // Preimage may be revealed before the HTLC is removed
+                preimage: self
+                    .htlc_claims
+                    .iter()
+                    .find(|claim| claim.payment_hash == htlc.payment_hash)
+                    .and_then(|claim| claim.preimage),
+                published: false,
+            })
+            .collect();
+
+        self.commitment_number = commitment_number;
+        self.remote_signature = Some(commitment_signed.signature);
+        self.local_commitment = Some(cmt_tx);
+        self.htlc_claims = htlc_claims;
+        self.save()?;
+
+        debug!("Revoking local commitment #{}", commitment_number - 1);
+        let revoke_and_ack = message::RevokeAndAck {
+            channel_id: self.channel_id,
+            per_commitment_secret: self
+                .local_per_commitment_secret(commitment_number - 1),
+            next_per_commitment_point: self
+                .local_per_commitment_point(commitment_number + 1),
+        };
+        self.send_peer(senders, Messages::RevokeAndAck(revoke_and_ack))?;
+
+        if self.remote_commitment_dirty {
+            self.send_commitment(senders)?;
+        }
+        self.resolve_htlcs(senders)?;
+        self.try_negotiate_closing(senders)?;
+        self.try_quiesce(senders)
+    }
+
+    /// Processes revocation of the previous remote commitment transaction
+    fn revoke_and_ack(
+        &mut self,
+        senders: &mut Senders,
+        revoke_and_ack: message::RevokeAndAck,
+    ) -> Result<(), Error> {
+        let revoked = self.remote_commitment_number.saturating_sub(1);
+        if !self.awaiting_revocation() || self.remote_secrets.count() != revoked
+        {
+            let info = format!(
+                "Unexpected revocation of remote commitment #{} of channel {}; \
+                 {} secrets are known",
+                revoked,
+                self.channel_id,
+                self.remote_secrets.count()
+            );
+            return Err(self.fail_channel(senders, info));
+        }
+
+        let secret = revoke_and_ack.per_commitment_secret;
+        let point = secp256k1::PublicKey::from_secret_key(
+            &secp256k1::Secp256k1::signing_only(),
+            &secret,
+        );
+        if Some(point) != self.remote_revoked_point {
+            let info = format!(
+                "Per-commitment secret revoking commitment #{} of channel {} \
+                 does not match its per-commitment point",
+                revoked, self.channel_id
+            );
+            return Err(self.fail_channel(senders, info));
+        }
+        if let Err(err) = self.remote_secrets.insert(revoked, secret) {
+            let info = format!(
+                "Invalid revocation of channel {}: {}",
+                self.channel_id, err
+            );
+            return Err(self.fail_channel(senders, info));
+        }
+        self.storage.append_commitment_secret(revoked, secret)?;
+
+        self.remote_revoked_point = self.remote_per_commitment_point;
+        self.remote_per_commitment_point =
+            Some(revoke_and_ack.next_per_commitment_point);
+        self.storage.append_commitment_point(
+            self.remote_commitment_number + 1,
+            revoke_and_ack.next_per_commitment_point,
+        )?;
+
+        if let Some(revoked_tx) = self.remote_commitments.remove(&revoked) {
+            self.upload_justice(senders, &revoked_tx, secret);
+        }
+        self.remote_commitments =
+            self.remote_commitments.split_off(&(revoked + 1));
+        self.save()?;
+
+        let enquirer = self.enquirer.clone();
+        let msg = format!(
+            "{} #{}",
+            "Remote peer revoked commitment".ended(),
+            self.remote_commitment_number.saturating_sub(1)
+        );
+        info!("{}", msg);
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+        // Updates made while awaiting the revocation are signed now
+        if self.remote_commitment_dirty {
+            self.send_commitment(senders)?;
+        }
+        self.resolve_htlcs(senders)?;
+        self.try_negotiate_closing(senders)?;
+        self.try_quiesce(senders)
+    }
+
+    /// Keeps the remote commitment transaction we have signed until it is
+    /// revoked
+    fn keep_remote_commitment(&mut self, commitment_number: u64) {
+        let (cmt_tx, _) = self.commitment_tx(false, commitment_number);
+        self.remote_commitments.insert(commitment_number, cmt_tx);
+    }
+
+    /// Uploads the penalty transaction for the revoked remote commitment to
+    /// the watchtowers, encrypted such that it can be decrypted only once
+    /// the commitment is published
+    fn upload_justice(
+        &mut self,
+        senders: &mut Senders,
+        revoked_tx: &Transaction,
+        per_commitment_secret: secp256k1::SecretKey,
+    ) {
+        let penalty_tx =
+            match self.penalty_tx(revoked_tx, per_commitment_secret) {
+                Ok(Some(penalty_tx)) => penalty_tx,
+                Ok(None) => {
+                    trace!("Revoked commitment has nothing to claim");
+                    return;
+                }
+                Err(err) => {
+                    warn!("Unable to construct penalty transaction: {}", err);
+                    return;
+                }
+            };
+        let blob = JusticeBlob::seal(&revoked_tx.txid(), &penalty_tx);
+        trace!("Uploading justice blob {} to watchtowers", blob.hint);
+        // Watchtower client may not be running
+        if let Err(err) = self.send_ctl(
+            senders,
+            ServiceId::TowerClient,
+            Request::AppendJusticeBlob(blob),
+        ) {
+            debug!("Justice blob is not uploaded: {}", err);
+        }
+    }
+
+    /// Witness spending the 2-of-2 funding output
+    fn funding_witness(
+        &self,
+        local_signature: secp256k1::Signature,
+        remote_signature: secp256k1::Signature,
+    ) -> Vec<Vec<u8>> {
+        let witness_script = WitnessScript::ln_funding(
+            self.funding_satoshis(),
+            self.local_keys.funding_pubkey,
+            self.remote_keys.funding_pubkey,
+        );
+        let mut local_sig = local_signature.serialize_der().to_vec();
+        local_sig.push(SigHashType::All.as_u32() as u8);
+        let mut remote_sig = remote_signature.serialize_der().to_vec();
+        remote_sig.push(SigHashType::All.as_u32() as u8);
+        // Signatures must follow the order of the keys in the funding script
+        let (first, second) = if self.local_keys.funding_pubkey.serialize()
+            < self.remote_keys.funding_pubkey.serialize()
+        {
+            (local_sig, remote_sig)
+        } else {
+            (remote_sig, local_sig)
+        };
+        vec![vec![], first, second, witness_script.to_bytes()]
+    }
+
+    /// Locks zero-conf channel right away, or waits for the funding
+    /// transaction to reach the minimum depth
+    fn await_funding(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        self.watch_funding(senders);
+        if self.minimum_depth == 0 {
+            info!(
+                "Channel {} is {} and used without funding confirmation",
+                self.channel_id,
+                "zero-conf".promo()
+            );
+            return self.funding_confirmed(senders);
+        }
+        Ok(())
+    }
+
+    /// Asks lnpd to publish the funding transaction, if it is known, and to
+    /// report once the transaction or any of the replaced ones reaches the
+    /// minimum depth
+    fn watch_funding(&mut self, senders: &mut Senders) {
+        let txids = self
+            .funding_candidates
+            .iter()
+            .map(|candidate| candidate.funding_outpoint.txid)
+            .chain(Some(self.funding_outpoint.txid))
+            .collect::<Vec<_>>();
+        for txid in txids {
+            let watch = TxDepth {
+                txid,
+                depth: self.minimum_depth,
+            };
+            // Failure to watch the funding must not halt the channel, so we
+            // do not fail here
+            if let Err(err) = self.send_ctl(
+                senders,
+                ServiceId::Lnpd,
+                Request::AwaitFunding(watch),
+            ) {
+                warn!("Unable to watch funding transaction {}: {}", txid, err);
+            }
+        }
+        // We forget channels funded by remote peers if the funding is not
+        // confirmed in time, so we follow the chain height
+        if !self.is_originator {
+            for request in vec![Request::ChainSubscribe, Request::GetChainInfo]
+            {
+                if let Err(err) =
+                    self.send_ctl(senders, ServiceId::Chain, request)
+                {
+                    warn!("Unable to follow chain height: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Closes channel funded by the remote peer if the funding transaction
+    /// is not confirmed within the funding timeout
+    fn check_funding_timeout(
+        &mut self,
+        senders: &mut Senders,
+        height: u32,
+    ) -> Result<(), Error> {
+        let start = match self.state {
+            _ if self.is_originator => return Ok(()),
+            State::Funded {
+                funding_height: Some(start),
+            } => start,
+            State::Funded {
+                funding_height: None,
+            } => {
+                return self.transition(
+                    State::Funded {
+                        funding_height: Some(height),
+                    },
+                    Trigger::Protocol,
+                )
+            }
+            _ => return Ok(()),
+        };
+        if height.saturating_sub(start) < self.funding_timeout {
+            return Ok(());
+        }
+        let info = format!(
+            "Funding transaction {} is not confirmed within {} blocks",
+            self.funding_outpoint.txid, self.funding_timeout
+        );
+        Err(self.abort(senders, info))
+    }
+
+    /// Detects whether the channel is negotiated and none of the funding
+    /// signatures is exchanged yet, so it may be aborted
+    fn is_negotiating(&self) -> bool {
+        match self.state {
+            State::Initial | State::Proposed | State::Accepted => true,
+            _ => false,
+        }
+    }
+
+    /// Detects whether any of the peers has signed commitment spending the
+    /// funding transaction, so the funding transaction may get mined and
+    /// the channel state must be kept
+    fn is_funding_signed(&self) -> bool {
+        !self.is_negotiating()
+            || self.remote_signature.is_some()
+            || self.local_commitment.is_some()
+    }
+
+    /// Aborts negotiation of the channel which has not reached the required
+    /// state in time
+    fn check_timeouts(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        let negotiating = self.is_negotiating();
+        let elapsed = SystemTime::now()
+            .duration_since(self.state_changed)
+            .unwrap_or(Duration::from_secs(0));
+        if !negotiating || elapsed < self.negotiation_timeout {
+            return Ok(());
+        }
+        let info = format!(
+            "Channel negotiation has not progressed from {:?} state in {} \
+             seconds",
+            self.state,
+            elapsed.as_secs()
+        );
+        Err(self.abort(senders, info))
+    }
+
+    /// Fails the channel, notifies `lnpd` and terminates the daemon
+    fn abort(&mut self, senders: &mut Senders, info: String) -> Error {
+        let err = self.fail_channel(senders, info);
+        if self.fund_from_wallet {
+            if let Err(err) = self.send_ctl(
+                senders,
+                ServiceId::Funding,
+                Request::ReleaseFunding,
+            ) {
+                warn!("Unable to release funding wallet outputs: {}", err);
+            }
+        }
+        let channel_id = if self.channel_id == zero!() {
+            self.temporary_channel_id.into()
+        } else {
+            self.channel_id
+        };
+        if let Err(err) = self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::ChannelAborted(channel_id),
+        ) {
+            warn!("Unable to notify lnpd about aborted channel: {}", err);
+        }
+        if !self.is_funding_signed() {
+            if let Err(err) = self.storage.remove() {
+                warn!("Unable to remove state of aborted channel: {}", err);
+            }
+        } else {
+            // Our commitment is not published, so its HTLC outputs can't be
+            // claimed
+            self.htlc_claims.clear();
+            if let Err(err) = self.transition(State::Closed, Trigger::Protocol)
+            {
+                warn!("Unable to store state of aborted channel: {}", err);
+            }
+        }
+        info!("Channel {} is aborted; terminating", channel_id);
+        // Give the message bus time to deliver the notifications
+        sleep(Duration::from_secs(1));
+        service::terminate(self.threaded);
+        #[allow(unreachable_code)]
+        err
+    }
+
+    /// Subscribes to the chain daemon notifications to detect publication of
+    /// revoked commitments and, for the channel funder, to follow the fee
+    /// estimate
+    fn watch_chain(&mut self, senders: &mut Senders) {
+        let mut requests = vec![Request::ChainSubscribe, Request::GetChainInfo];
+        if self.is_originator {
+            requests.push(Request::GetFeeEstimate);
+        }
+        for request in requests {
+            // Commitment feerate still may be updated manually, so we do not
+            // fail here
+            if let Err(err) = self.send_ctl(senders, ServiceId::Chain, request)
+            {
+                warn!("Unable to request fee estimate: {}", err);
+            }
+        }
+    }
+
+    /// Updates commitment feerate if the fee estimate has drifted too far
+    /// from it
+    fn fee_estimated(
+        &mut self,
+        senders: &mut Senders,
+        feerate_per_kw: u32,
+    ) -> Result<(), Error> {
+        if !self.is_originator
+            || self.state != State::Active
+            || self.closing.is_some()
+            || self.force_closing.is_some()
+            || self.quiescence.is_pending()
+            || !self.peer_connected
+        {
+            return Ok(());
+        }
+        let feerate_per_kw = feerate_per_kw
+            .max(self.min_feerate_per_kw)
+            .min(self.max_feerate_per_kw);
+        if self.anchors && feerate_per_kw > self.feerate_per_kw {
+            // Commitment with anchors is bumped with CPFP once broadcasted,
+            // so we do not follow fee spikes
+            trace!(
+                "Fee estimate {} sat/kw is ignored for anchor channel",
+                feerate_per_kw
+            );
+            return Ok(());
+        }
+        let current = self.feerate_per_kw as u64;
+        let drift = (feerate_per_kw as u64).max(current)
+            - (feerate_per_kw as u64).min(current);
+        if drift * 100 <= current * FEE_UPDATE_THRESHOLD {
+            trace!(
+                "Fee estimate {} sat/kw is close to the commitment feerate",
+                feerate_per_kw
+            );
+            return Ok(());
+        }
+        self.update_fee(senders, feerate_per_kw)
+    }
+
+    /// Sends `update_fee` and signs remote commitment with the new feerate.
+    /// Only channel funder may update the feerate.
+    pub fn update_fee(
+        &mut self,
+        senders: &mut Senders,
+        feerate_per_kw: u32,
+    ) -> Result<(), Error> {
+        if !self.is_originator {
+            Err(Error::Other(s!(
+                "Commitment feerate may be updated by the channel funder only"
+            )))?
+        }
+        if self.state != State::Active {
+            Err(Error::Other(format!(
+                "Channel is not active yet; its current state is {:?}",
+                self.state
+            )))?
+        }
+        if self.closing.is_some() || self.force_closing.is_some() {
+            Err(Error::Other(s!("Channel is being closed")))?
+        }
+        if self.quiescence.is_pending() {
+            Err(Error::Other(s!(
+                "Channel updates are stopped for quiescence"
+            )))?
+        }
+        if !self.peer_connected {
+            Err(Error::Other(s!(
+                "Remote peer is disconnected; channel updates are paused \
+                 until the channel is re-established"
+            )))?
+        }
+        let fee = self.commitment_fee(feerate_per_kw);
+        if fee * 1000 > self.local_capacity {
+            Err(Error::Other(format!(
+                "Commitment fee of {} sat at feerate {} sat/kw exceeds our \
+                 balance",
+                fee, feerate_per_kw
+            )))?
+        }
+
+        info!(
+            "{} commitment feerate from {} to {} sat/kw",
+            "Updating".promo(),
+            self.feerate_per_kw,
+            feerate_per_kw.promoter()
+        );
+        self.feerate_per_kw = feerate_per_kw;
+        self.send_peer(
+            senders,
+            Messages::UpdateFee(message::UpdateFee {
+                channel_id: self.channel_id,
+                feerate_per_kw,
+            }),
+        )?;
+        self.send_commitment(senders)
+    }
+
+    /// Sends our `funding_locked` once the funding transaction is deep enough
+    pub fn funding_confirmed(
+        &mut self,
+        senders: &mut Senders,
+    ) -> Result<(), Error> {
+        if !matches!(self.state, State::Funded { .. }) {
+            debug!("Ignoring funding confirmation in {:?} state", self.state);
+            return Ok(());
+        }
+
+        self.send_funding_locked(senders)?;
+        // Other funding candidates are double-spent by the confirmed one
+        self.funding_candidates.clear();
+        self.transition(State::Locked, Trigger::Protocol)?;
+
+        if self.remote_per_commitment_point.is_some() {
+            self.activate(senders)
+        } else {
+            let msg = format!(
+                "{} awaiting funding_locked from the remote peer",
+                "Funding transaction confirmed:".ended()
+            );
+            info!("{}", msg);
+            let enquirer = self.enquirer.clone();
+            let _ = self.report_progress_to(senders, &enquirer, msg);
+            Ok(())
+        }
+    }
+
+    /// Makes the channel usable for payments after both peers have sent
+    /// `funding_locked`
+    fn activate(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        // Channels funded before the capacity was assigned on funding
+        self.init_capacity();
+        self.transition(State::Active, Trigger::Protocol)?;
+
+        if let Some(backup) = self.backup() {
+            self.send_ctl(
+                senders,
+                ServiceId::Lnpd,
+                Request::UpdateBackup(backup),
+            )?;
+        }
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::ChannelActivated(self.channel_id),
+        )?;
+
+        // Ignoring possible error here: do not want to
+        // halt the channel just because the client disconnected
+        self.watch_chain(senders);
+        self.announce_policy(senders);
+        if self.public {
+            self.watch_announcement_depth(senders);
+        }
+
+        let msg =
+            format!("{} transaction confirmed", "Channel active:".ended());
+        info!("{}", msg);
+        let enquirer = self.enquirer.clone();
+        let _ = self.report_success_to(senders, &enquirer, Some(msg));
+        Ok(())
+    }
+
+    /// Channel data for the static channel backup kept by lnpd
+    fn backup(&self) -> Option<ChannelBackup> {
+        let remote_peer = self.remote_peer.clone()?;
+        Some(ChannelBackup {
+            channel_id: self.channel_id,
+            temporary_channel_id: self.temporary_channel_id,
+            key_salt: self.key_salt,
+            remote_peer,
+            funding_outpoint: self.funding_outpoint,
+            funding_satoshis: self.params.funding_satoshis,
+            is_originator: self.is_originator,
+            anchors: self.anchors,
+            static_remotekey: self.static_remotekey,
+            local_keys: self.local_keys.clone(),
+            remote_keys: self.remote_keys.clone(),
+        })
+    }
+
+    /// Initializes the channel from the static backup after its state was
+    /// lost. Such channel can't be operated: we only ask the remote peer to
+    /// close it and sweep our funds from the remote commitment.
+    fn recover(
+        &mut self,
+        senders: &mut Senders,
+        backup: ChannelBackup,
+    ) -> Result<(), Error> {
+        if self.state != State::Initial {
+            warn!(
+                "Channel {} has its state stored and is not recovered from \
+                 the backup",
+                self.channel_id
+            );
+            return Ok(());
+        }
+        info!(
+            "{} channel {} from the static backup",
+            "Recovering".promo(),
+            backup.channel_id.promoter()
+        );
+        self.channel_id = backup.channel_id;
+        self.temporary_channel_id = backup.temporary_channel_id;
+        self.key_salt = backup.key_salt;
+        self.funding_outpoint = backup.funding_outpoint;
+        self.params.funding_satoshis = backup.funding_satoshis;
+        self.is_originator = backup.is_originator;
+        self.anchors = backup.anchors;
+        self.static_remotekey = backup.static_remotekey;
+        self.local_keys = backup.local_keys;
+        self.remote_keys = backup.remote_keys;
+        self.peer_service = ServiceId::Peer(backup.remote_peer.clone());
+        self.remote_peer = Some(backup.remote_peer);
+        self.recovering = true;
+        self.transition(State::Locked, Trigger::Recovery)?;
+        self.request_wallet_script(senders);
+
+        self.watch_chain(senders);
+        Ok(())
+    }
+
+    /// Updates policy of forwarding payments over the channel
+    fn set_forwarding_policy(
+        &mut self,
+        senders: &mut Senders,
+        policy: ForwardingPolicy,
+    ) -> Result<(), Error> {
+        let enquirer = self.enquirer.clone();
+        let capacity_msat = self.params.funding_satoshis * 1000;
+        let info = match policy.htlc_maximum_msat {
+            Some(max) if max < policy.htlc_minimum_msat => Some(format!(
+                "HTLC maximum {} msat is below HTLC minimum {} msat",
+                max, policy.htlc_minimum_msat
+            )),
+            Some(max) if capacity_msat > 0 && max > capacity_msat => {
+                Some(format!(
+                    "HTLC maximum {} msat exceeds channel capacity {} msat",
+                    max, capacity_msat
+                ))
+            }
+            _ => None,
+        };
+        if let Some(info) = info {
+            return Err(self.report_failure_to(
+                senders,
+                &enquirer,
+                microservices::rpc::Failure {
+                    code: 0, // TODO: Create error type system
+                    info,
+                },
+            ));
+        }
+
+        self.forwarding_policy = policy;
+        self.save()?;
+        if self.state == State::Active {
+            self.announce_policy(senders);
+        }
+
+        let msg = format!("{} {}", "Forwarding policy set to".ended(), policy);
+        info!("{}", msg);
+        let _ = self.report_success_to(senders, &enquirer, Some(msg));
+        Ok(())
+    }
+
+    /// Provides gossip daemon with the forwarding policy of the channel to
+    /// announce it with `channel_update`
+    fn announce_policy(&mut self, senders: &mut Senders) {
+        let update = ChannelPolicyUpdate {
+            channel_id: self.channel_id,
+            policy: self.forwarding_policy,
+        };
+        // Gossip daemon may not be running; channel operates anyway
+        if let Err(err) = self.send_ctl(
+            senders,
+            ServiceId::Gossip,
+            Request::ChannelPolicyUpdated(update),
+        ) {
+            warn!("Unable to announce channel forwarding policy: {}", err);
+        }
+    }
+
+    /// Asks the chain service to report once the funding transaction is deep
+    /// enough for announcing the channel
+    fn watch_announcement_depth(&mut self, senders: &mut Senders) {
+        let watch = TxDepth {
+            txid: self.funding_outpoint.txid,
+            depth: ANNOUNCEMENT_DEPTH,
+        };
+        // Channel operates even if it is never announced
+        if let Err(err) = self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::WatchTransaction(watch),
+        ) {
+            warn!("Unable to watch funding transaction depth: {}", err);
+        }
+    }
+
+    /// Announcement of the channel signed by both peers, once the funding
+    /// output is located in the chain
+    fn unsigned_announcement(&self) -> Option<UnsignedChannelAnnouncement> {
+        let remote_node = self.remote_peer.as_ref()?.id;
+        Some(UnsignedChannelAnnouncement::with(
+            self.chain.clone().chain_params().genesis_hash,
+            self.short_channel_id?,
+            (self.node_id(), self.local_keys.funding_pubkey),
+            (remote_node, self.remote_keys.funding_pubkey),
+        ))
+    }
+
+    fn local_announcement(
+        &self,
+        announcement: &UnsignedChannelAnnouncement,
+    ) -> AnnouncementSignatures {
+        let digest = announcement.digest();
+        AnnouncementSignatures {
+            channel_id: self.channel_id,
+            short_channel_id: announcement.short_channel_id,
+            node_signature: self.local_node.sign(&digest),
+            bitcoin_signature: secp256k1::Secp256k1::signing_only()
+                .sign(&digest, &self.channel_keys().funding_secret),
+        }
+    }
+
+    /// Sends our signatures of the channel announcement to the remote peer
+    fn send_announcement_signatures(
+        &mut self,
+        senders: &mut Senders,
+    ) -> Result<(), Error> {
+        let announcement = match self.unsigned_announcement() {
+            Some(announcement) => announcement,
+            None => return Ok(()),
+        };
+        let local = self.local_announcement(&announcement);
+        self.send_peer(senders, local.to_message()?)
+    }
+
+    /// Assembles `channel_announcement` once both peers have signed it and
+    /// passes it to the gossip daemon, which validates and broadcasts it
+    fn complete_announcement(
+        &mut self,
+        senders: &mut Senders,
+    ) -> Result<(), Error> {
+        let (announcement, remote) =
+            match (self.unsigned_announcement(), self.remote_announcement) {
+                (Some(announcement), Some(remote)) => (announcement, remote),
+                _ => return Ok(()),
+            };
+        if remote.short_channel_id != announcement.short_channel_id {
+            warn!(
+                "Remote peer has located channel {} at {} instead of {}",
+                self.channel_id,
+                remote.short_channel_id,
+                announcement.short_channel_id
+            );
+            self.remote_announcement = None;
+            return Err(Error::Misbehaving);
+        }
+        let local = self.local_announcement(&announcement);
+        let message = announcement.compose(&local, &remote, self.node_id())?;
+        info!(
+            "{} {} as {}",
+            "Announcing channel".promo(),
+            self.channel_id.promoter(),
+            announcement.short_channel_id.promoter()
+        );
+        senders.send_to(
+            ServiceBus::Msg,
+            self.identity(),
+            ServiceId::Gossip,
+            Request::PeerMessage(message),
+        )?;
+        Ok(())
+    }
+
+    /// Asks the funding wallet for a fresh script receiving our funds on
+    /// close; reply is stored once it arrives
+    fn request_wallet_script(&mut self, senders: &mut Senders) {
+        if self.wallet_script.is_some() {
+            return;
+        }
+        if let Err(err) =
+            self.send_ctl(senders, ServiceId::Funding, Request::GetWalletScript)
+        {
+            warn!("Unable to request wallet script: {}", err);
+        }
+    }
+
+    /// Script receiving our funds on close, unless the user has provided one
+    fn default_shutdown_script(&self) -> Result<PubkeyScript, Error> {
+        self.wallet_script.clone().ok_or_else(|| {
+            Error::Other(s!(
+                "funding wallet has not provided a script receiving the \
+                 channel funds; make sure fundingd is running"
+            ))
+        })
+    }
+
+    fn shutdown(
+        &mut self,
+        senders: &mut Senders,
+        local_script: PubkeyScript,
+    ) -> Result<(), Error> {
+        let shutdown = message::Shutdown {
+            channel_id: self.channel_id,
+            scriptpubkey: local_script.clone(),
+        };
+        self.closing = Some(Closing {
+            local_script,
+            remote_script: None,
+            fee_proposed: None,
+        });
+        self.save()?;
+        self.send_peer(senders, Messages::Shutdown(shutdown))?;
+
+        let msg = format!(
+            "{} for channel {:#}, negotiating closing fee",
+            "Shutdown sent".ended(),
+            self.channel_id.ender()
+        );
+        info!("{}", msg);
+        let enquirer = self.enquirer.clone();
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+        Ok(())
+    }
+
+    fn closing_fee_estimate(&self) -> u64 {
+        (self.feerate_per_kw as u64 * CLOSING_TX_WEIGHT / 1000)
+            .min(self.max_closing_fee())
+    }
+
+    /// Upper bound of the closing fee, which can't exceed the base fee of the
+    /// final commitment transaction (BOLT-2)
+    fn max_closing_fee(&self) -> u64 {
+        fees::commitment_fee(self.feerate_per_kw, self.anchors, 0)
+    }
+
+    /// Checks whether some HTLCs are not yet irrevocably removed from both
+    /// commitment transactions, so the closing transaction can't be signed
+    fn has_pending_htlcs(&self) -> bool {
+        !self.offered_htlc.is_empty()
+            || !self.received_htlc.is_empty()
+            || self.has_uncommitted_updates()
+    }
+
+    /// Funder starts closing fee negotiation once both peers have sent
+    /// `shutdown` and all HTLCs are settled
+    fn try_negotiate_closing(
+        &mut self,
+        senders: &mut Senders,
+    ) -> Result<(), Error> {
+        let negotiating = match self.closing {
+            Some(Closing {
+                remote_script: Some(_),
+                fee_proposed,
+                ..
+            }) => fee_proposed.is_some(),
+            _ => return Ok(()),
+        };
+        if !self.is_originator || negotiating || self.has_pending_htlcs() {
+            return Ok(());
+        }
+        let fee = self.closing_fee_estimate();
+        self.propose_closing_fee(senders, fee)
+    }
+
+    /// Constructs closing transaction paying the fee from the funder output
+    /// and omitting dust outputs
+    fn closing_tx(&self, fee: u64) -> Transaction {
+        let closing = self
+            .closing
+            .as_ref()
+            .expect("closing transaction is constructed only after shutdown");
+        let (mut local_amount, mut remote_amount) =
+            (self.local_capacity / 1000, self.remote_capacity / 1000);
+        if self.is_originator {
+            local_amount = local_amount.saturating_sub(fee);
+        } else {
+            remote_amount = remote_amount.saturating_sub(fee);
+        }
+
+        let mut output = vec![];
+        if local_amount >= self.dust_limit_satoshis {
+            output.push(TxOut {
+                value: local_amount,
+                script_pubkey: closing.local_script.clone().into(),
+            });
+        }
+        if let Some(ref remote_script) = closing.remote_script {
+            if remote_amount >= self.dust_limit_satoshis {
+                output.push(TxOut {
+                    value: remote_amount,
+                    script_pubkey: remote_script.clone().into(),
+                });
+            }
+        }
+        // BIP69 output ordering required by BOLT3
+        output.sort_by(|a, b| {
+            (a.value, &a.script_pubkey).cmp(&(b.value, &b.script_pubkey))
+        });
+
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: self.funding_outpoint,
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![],
+            }],
+            output,
+        }
+    }
+
+    fn propose_closing_fee(
+        &mut self,
+        senders: &mut Senders,
+        fee: u64,
+    ) -> Result<(), Error> {
+        let mut closing_tx = self.closing_tx(fee);
+        let sign_msg = self.funding_sighash(&mut closing_tx);
+        let closing_signed = message::ClosingSigned {
+            channel_id: self.channel_id,
+            fee_satoshis: fee,
+            signature: self.sign_with_funding_key(&sign_msg),
+        };
+        if let Some(ref mut closing) = self.closing {
+            closing.fee_proposed = Some(fee);
+        }
+        self.save()?;
+        self.send_peer(senders, Messages::ClosingSigned(closing_signed))?;
+
+        let msg = format!("Proposed closing fee of {} sat", fee);
+        debug!("{}", msg);
+        let enquirer = self.enquirer.clone();
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+        Ok(())
+    }
+
+    fn verify_closing(
+        &mut self,
+        senders: &mut Senders,
+        fee: u64,
+        signature: secp256k1::Signature,
+    ) -> Result<(), Error> {
+        let mut closing_tx = self.closing_tx(fee);
+        let sign_msg = self.funding_sighash(&mut closing_tx);
+        if let Err(err) = secp256k1::Secp256k1::verification_only().verify(
+            &sign_msg,
+            &signature,
+            &self.remote_keys.funding_pubkey,
+        ) {
+            let info = format!(
+                "Remote signature for closing transaction with fee {} sat is \
+                 invalid: {}",
+                fee, err
+            );
+            error!("{}", info.err());
+            let enquirer = self.enquirer.clone();
+            return Err(self.report_failure_to(
+                senders,
+                &enquirer,
+                microservices::rpc::Failure {
+                    code: 0, // TODO: Create error type system
+                    info,
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Signs and broadcasts the closing transaction with the agreed fee
+    fn complete_closing(
+        &mut self,
+        senders: &mut Senders,
+        fee: u64,
+        remote_signature: secp256k1::Signature,
+    ) -> Result<(), Error> {
+        let mut closing_tx = self.closing_tx(fee);
+        let sign_msg = self.funding_sighash(&mut closing_tx);
+        let local_signature = self.sign_with_funding_key(&sign_msg);
+        closing_tx.input[0].witness =
+            self.funding_witness(local_signature, remote_signature);
+
+        let txid = closing_tx.txid();
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(closing_tx),
+        )?;
+        if self.is_originator {
+            self.book_event(
+                senders,
+                AccountingEventKind::OnchainFee,
+                None,
+                fee * 1000,
+                format!("Closing of channel {}", self.channel_id),
+            )?;
+        }
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::NodeEvent(NodeEvent {
+                kind: NodeEventKind::ChannelClosed,
+                channel_id: Some(self.channel_id),
+                txid: Some(txid),
+                amount_msat: Some(self.local_capacity),
+                details: format!(
+                    "Channel closed cooperatively, fee {} sat",
+                    fee
+                ),
+            }),
+        )?;
+        self.transition(State::Closed, Trigger::Protocol)?;
+
+        let msg = format!(
+            "{} with transaction {}",
+            "Channel closed".ended(),
+            txid.ender()
+        );
+        info!("{}", msg);
+        let enquirer = self.enquirer.clone();
+        let _ = self.report_success_to(senders, &enquirer, Some(msg));
+        Ok(())
+    }
+
+    /// Publishes our latest commitment transaction signed by the remote peer,
+    /// claims its HTLC outputs and waits for maturity of its `to_local`
+    /// output
+    pub fn force_close(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        let enquirer = self.enquirer.clone();
+        let (mut cmt_tx, remote_signature) =
+            match (self.local_commitment.clone(), self.remote_signature) {
+                (Some(cmt_tx), Some(signature)) => (cmt_tx, signature),
+                _ => {
+                    return Err(self.report_failure_to(
+                        senders,
+                        &enquirer,
+                        microservices::rpc::Failure {
+                            code: 0, // TODO: Create error type system
+                            info: s!("Channel has no commitment transaction \
+                                      signed by the remote peer"),
+                        },
+                    ));
+                }
+            };
+
+        let sign_msg = self.funding_sighash(&mut cmt_tx);
+        let local_signature = self.sign_with_funding_key(&sign_msg);
+        cmt_tx.input[0].witness =
+            self.funding_witness(local_signature, remote_signature);
+        let txid = cmt_tx.txid();
+
+        info!(
+            "{} channel {} with commitment transaction {}",
+            "Force-closing".promo(),
+            self.channel_id.promoter(),
+            txid.promoter()
+        );
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(cmt_tx.clone()),
+        )?;
+        // Commitment fee is paid by the channel funder
+        if self.is_originator {
+            self.book_event(
+                senders,
+                AccountingEventKind::OnchainFee,
+                None,
+                self.paid_commitment_fee(&cmt_tx) * 1000,
+                format!("Force-closing of channel {}", self.channel_id),
+            )?;
+        }
+        self.force_closing = Some(cmt_tx);
+        if self.state != State::Closed {
+            self.transition(State::Closed, Trigger::Protocol)?;
+        } else {
+            self.save()?;
+        }
+        if self.anchors {
+            // Commitment feerate does not follow fee spikes, so we compare it
+            // with the current estimate and bump it with CPFP if needed
+            self.bumping_commitment = true;
+            self.send_ctl(senders, ServiceId::Chain, Request::GetFeeEstimate)?;
+        }
+
+        // `to_local` output becomes spendable once the commitment is buried
+        // under `to_self_delay` blocks
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::WatchTransaction(TxDepth {
+                txid,
+                depth: self.params.to_self_delay as u32,
+            }),
+        )?;
+
+        // HTLC-timeout transactions are published once the chain reaches
+        // the HTLC expiry
+        if !self.htlc_claims.is_empty() {
+            for request in vec![Request::ChainSubscribe, Request::GetChainInfo]
+            {
+                self.send_ctl(senders, ServiceId::Chain, request)?;
+            }
+        }
+        self.claim_htlcs(senders)?;
+
+        let msg = format!(
+            "{} {}; funds will be swept after {} blocks",
+            "Commitment transaction published:".ended(),
+            txid.ender(),
+            self.params.to_self_delay
+        );
+        info!("{}", msg);
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+        Ok(())
+    }
+
+    /// Publishes second-stage transactions claiming HTLC outputs of our
+    /// published commitment: HTLC-timeout ones after the HTLC expiry and
+    /// HTLC-success ones once the payment preimage is known
+    fn claim_htlcs(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        if self.state != State::Closed {
+            return Ok(());
+        }
+        let preimages = self
+            .resolved_htlc
+            .values()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        let htlc_secret = derive_secret(
+            self.channel_keys().htlc_basepoint_secret,
+            self.local_per_commitment_point(self.commitment_number),
+        );
+        let secp = secp256k1::Secp256k1::signing_only();
+
+        let mut published = vec![];
+        for (no, claim) in self.htlc_claims.iter_mut().enumerate() {
+            if claim.published {
+                continue;
+            }
+            let preimage = if claim.offered {
+                match self.chain_height {
+                    Some(height) if height >= claim.tx.lock_time => None,
+                    _ => continue,
+                }
+            } else {
+                if claim.preimage.is_none() {
+                    claim.preimage = preimages
+                        .iter()
+                        .copied()
+                        .find(|p| HashLock::from(*p) == claim.payment_hash);
+                }
+                match claim.preimage {
+                    None => continue,
+                    preimage => preimage,
+                }
+            };
+
+            let mut tx = claim.tx.clone();
+            let sighash = SigHashCache::new(&mut tx).signature_hash(
+                0,
+                &claim.witness_script,
+                claim.amount,
+                SigHashType::All,
+            );
+            let sign_msg = secp256k1::Message::from_slice(&sighash[..])
+                .expect("Sighash size always match requirements");
+            tx.input[0].witness = commitment::htlc_tx_witness(
+                claim.remote_signature,
+                secp.sign(&sign_msg, &htlc_secret),
+                preimage.as_ref().map(|preimage| preimage.as_ref()),
+                self.anchors,
+                &claim.witness_script,
+            );
+            claim.published = true;
+            published.push((no, tx));
+        }
+        if published.is_empty() {
+            return Ok(());
+        }
+        self.save()?;
+
+        for (no, tx) in published {
+            let txid = tx.txid();
+            info!(
+                "{} HTLC output #{} of channel {} with transaction {}",
+                "Claiming".promo(),
+                no,
+                self.channel_id.promoter(),
+                txid.promoter()
+            );
+            if self.anchors {
+                warn!(
+                    "HTLC transaction {} pays no fee and needs to be bumped \
+                     with wallet inputs, which is not supported yet",
+                    txid
+                );
+            }
+            self.send_ctl(
+                senders,
+                ServiceId::Chain,
+                Request::BroadcastTransaction(tx),
+            )?;
+            // Output of the HTLC transaction is locked for `to_self_delay`
+            // blocks, like `to_local` output of the commitment
+            self.send_ctl(
+                senders,
+                ServiceId::Chain,
+                Request::WatchTransaction(TxDepth {
+                    txid,
+                    depth: self.params.to_self_delay as u32,
+                }),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sweeps matured output of the published HTLC transaction
+    fn sweep_htlc(
+        &mut self,
+        senders: &mut Senders,
+        txid: bitcoin::Txid,
+    ) -> Result<(), Error> {
+        let pos = match self
+            .htlc_claims
+            .iter()
+            .position(|claim| claim.published && claim.tx.txid() == txid)
+        {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+        let claim = self.htlc_claims.remove(pos);
+        let value = claim.tx.output[0].value;
+        if let Some((sweep_txid, swept)) =
+            self.sweep_delayed(senders, OutPoint { txid, vout: 0 }, value)?
+        {
+            let msg = format!(
+                "{} {} sat of HTLC output with transaction {}",
+                "Swept".ended(),
+                swept,
+                sweep_txid.ender()
+            );
+            info!("{}", msg);
+            let enquirer = self.enquirer.clone();
+            let _ = self.report_progress_to(senders, &enquirer, msg);
+        }
+        self.save()
+    }
+
+    /// Requests the funding wallet for the child transaction spending our
+    /// anchor output of the published commitment, if the commitment pays
+    /// less than the fee estimate
+    fn bump_commitment(
+        &mut self,
+        senders: &mut Senders,
+        feerate_per_kw: u32,
+    ) -> Result<(), Error> {
+        let cmt_tx = match self.force_closing {
+            Some(ref cmt_tx) => cmt_tx,
+            None => return Ok(()),
+        };
+        let txid = cmt_tx.txid();
+        if feerate_per_kw <= self.feerate_per_kw {
+            debug!(
+                "Commitment transaction {} pays {} sat/kw, which is above fee \
+                 estimate",
+                txid, self.feerate_per_kw
+            );
+            return Ok(());
+        }
+        let script_pubkey =
+            anchors::anchor_script(self.local_keys.funding_pubkey)
+                .to_v0_p2wsh();
+        let vout = match cmt_tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey == script_pubkey)
+        {
+            Some(vout) => vout as u32,
+            None => {
+                warn!(
+                    "Commitment transaction {} has no anchor output to bump \
+                     its fee",
+                    txid
+                );
+                return Ok(());
+            }
+        };
+        let parent_fee = self.paid_commitment_fee(&cmt_tx);
+        let cpfp_req = CpfpRequest {
+            anchor: OutPoint { txid, vout },
+            anchor_value: ANCHOR_OUTPUT_VALUE,
+            parent_weight: cmt_tx.get_weight() as u64,
+            parent_fee,
+            feerate_per_kw,
+        };
+        info!(
+            "{} of commitment transaction {} to {} sat/kw",
+            "Bumping fee".promo(),
+            txid.promoter(),
+            feerate_per_kw
+        );
+        self.send_ctl(
+            senders,
+            ServiceId::Funding,
+            Request::ConstructCpfp(cpfp_req),
+        )
+    }
+
+    /// Signs our anchor input of the child transaction constructed by the
+    /// funding wallet and publishes the child
+    fn sign_cpfp(
+        &mut self,
+        senders: &mut Senders,
+        child: CpfpChild,
+    ) -> Result<(), Error> {
+        let CpfpChild { mut tx, fee } = child;
+        let parent_txid = self.force_closing.as_ref().map(Transaction::txid);
+        if tx.input.first().map(|txin| txin.previous_output.txid) != parent_txid
+        {
+            warn!("CPFP transaction does not spend the published commitment");
+            return Ok(());
+        }
+        let witness_script =
+            anchors::anchor_script(self.local_keys.funding_pubkey);
+        let sighash = SigHashCache::new(&mut tx).signature_hash(
+            0,
+            &witness_script,
+            ANCHOR_OUTPUT_VALUE,
+            SigHashType::All,
+        );
+        let sign_msg = secp256k1::Message::from_slice(&sighash[..])
+            .expect("Sighash size always match requirements");
+        let mut signature = self
+            .sign_with_funding_key(&sign_msg)
+            .serialize_der()
+            .to_vec();
+        signature.push(SigHashType::All.as_u32() as u8);
+        tx.input[0].witness = vec![signature, witness_script.to_bytes()];
+
+        let txid = tx.txid();
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(tx),
+        )?;
+        self.book_event(
+            senders,
+            AccountingEventKind::OnchainFee,
+            None,
+            fee * 1000,
+            format!("Bumping commitment fee of channel {}", self.channel_id),
+        )?;
+
+        let msg = format!(
+            "{} {} paying {} sat fee",
+            "CPFP transaction published:".ended(),
+            txid.ender(),
+            fee
+        );
+        info!("{}", msg);
+        let enquirer = self.enquirer.clone();
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+        Ok(())
+    }
+
+    /// Sweeps matured `to_local` output of the published commitment
+    /// transaction to the wallet
+    fn sweep_to_local(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        let enquirer = self.enquirer.clone();
+        let cmt_tx = match self.force_closing.take() {
+            Some(cmt_tx) => cmt_tx,
+            None => return Ok(()),
+        };
+        let script_pubkey = self.delayed_script().to_v0_p2wsh();
+        let to_local = cmt_tx
+            .output
+            .iter()
+            .enumerate()
+            .find(|(_, txout)| txout.script_pubkey == script_pubkey);
+        let (vout, value) = match to_local {
+            Some((vout, txout)) => (vout as u32, txout.value),
+            None => {
+                self.save()?;
+                let msg =
+                    format!("{} no funds to sweep", "Channel closed:".ended());
+                info!("{}", msg);
+                let _ = self.report_success_to(senders, &enquirer, Some(msg));
+                return Ok(());
+            }
+        };
+
+        let outpoint = OutPoint {
+            txid: cmt_tx.txid(),
+            vout,
+        };
+        let (txid, swept) =
+            match self.sweep_delayed(senders, outpoint, value)? {
+                Some(sweep) => sweep,
+                None => return self.save(),
+            };
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::NodeEvent(NodeEvent {
+                kind: NodeEventKind::ChannelClosed,
+                channel_id: Some(self.channel_id),
+                txid: Some(txid),
+                amount_msat: Some(swept * 1000),
+                details: s!("Channel force-closed, own funds are swept"),
+            }),
+        )?;
+        self.save()?;
+
+        let msg = format!(
+            "{} {} sat with transaction {}",
+            "Swept".ended(),
+            swept,
+            txid.ender()
+        );
+        info!("{}", msg);
+        let _ = self.report_success_to(senders, &enquirer, Some(msg));
+        Ok(())
+    }
+
+    /// Script of `to_local` output of our current commitment and of the
+    /// outputs of its second-stage HTLC transactions
+    fn delayed_script(&self) -> Script {
+        let keys = self.commitment_keys(true, self.commitment_number);
+        commitment::to_local_script(
+            keys.revocation_pubkey,
+            keys.delayed_pubkey,
+            self.params.to_self_delay,
+        )
+    }
+
+    /// Sweeps matured output locked with the delayed key of our current
+    /// commitment to the wallet. Returns id of the sweep transaction and
+    /// the swept amount, unless the output is not worth sweeping.
+    fn sweep_delayed(
+        &mut self,
+        senders: &mut Senders,
+        outpoint: OutPoint,
+        value: u64,
+    ) -> Result<Option<(bitcoin::Txid, u64)>, Error> {
+        let fee = self.feerate_per_kw as u64 * SWEEP_TX_WEIGHT / 1000;
+        if value <= fee + self.dust_limit_satoshis {
+            warn!(
+                "Channel {} output {} of {} sat is not worth sweeping",
+                self.channel_id, outpoint, value
+            );
+            return Ok(None);
+        }
+
+        let witness_script = self.delayed_script();
+        let mut sweep_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: Script::new(),
+                // Relative timelock of the delayed output
+                sequence: self.params.to_self_delay as u32,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: value - fee,
+                script_pubkey: self.default_shutdown_script()?.into(),
+            }],
+        };
+        let sighash = SigHashCache::new(&mut sweep_tx).signature_hash(
+            0,
+            &witness_script,
+            value,
+            SigHashType::All,
+        );
+        let sign_msg = secp256k1::Message::from_slice(&sighash[..])
+            .expect("Sighash size always match requirements");
+        let delayed_secret = derive_secret(
+            self.channel_keys().delayed_payment_basepoint_secret,
+            self.local_per_commitment_point(self.commitment_number),
+        );
+        let mut signature = secp256k1::Secp256k1::signing_only()
+            .sign(&sign_msg, &delayed_secret)
+            .serialize_der()
+            .to_vec();
+        signature.push(SigHashType::All.as_u32() as u8);
+        // Empty element selects the delayed (non-revocation) branch
+        sweep_tx.input[0].witness =
+            vec![signature, vec![], witness_script.to_bytes()];
+
+        let txid = sweep_tx.txid();
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(sweep_tx),
+        )?;
+        self.book_event(
+            senders,
+            AccountingEventKind::OnchainFee,
+            None,
+            fee * 1000,
+            format!("Sweeping funds of channel {}", self.channel_id),
+        )?;
+        Ok(Some((txid, value - fee)))
+    }
+
+    /// Sweeps our output from the remote commitment transaction closing the
+    /// channel recovered from the static backup
+    fn sweep_recovered(
+        &mut self,
+        senders: &mut Senders,
+        tx: &Transaction,
+    ) -> Result<(), Error> {
+        if !tx
+            .input
+            .iter()
+            .any(|txin| txin.previous_output == self.funding_outpoint)
+        {
+            return Ok(());
+        }
+        info!(
+            "Channel {} recovered from the backup is {} with transaction {}",
+            self.channel_id,
+            "closed by the remote peer".promo(),
+            tx.txid()
+        );
+        self.recovering = false;
+        self.htlc_claims.clear();
+        self.transition(State::Closed, Trigger::Protocol)?;
+
+        let basepoint = self.local_keys.payment_basepoint;
+        let payment_secret = self.channel_keys().payment_basepoint_secret;
+        let (to_remote_key, secret) = if self.static_remotekey {
+            (basepoint, payment_secret)
+        } else if let Some(point) = self.remote_per_commitment_point {
+            (
+                derive_pubkey(basepoint, point),
+                derive_secret(payment_secret, point),
+            )
+        } else {
+            error!(
+                "{} channel {} can't be swept: remote peer has not provided \
+                 its per-commitment point",
+                "Funds of".err(),
+                self.channel_id
+            );
+            return Ok(());
+        };
+        let pubkey = bitcoin::PublicKey {
+            compressed: true,
+            key: to_remote_key,
+        };
+        let (script_pubkey, script_code, sequence) = if self.anchors {
+            let witness_script = anchors::to_remote_script(to_remote_key);
+            (witness_script.to_v0_p2wsh(), witness_script, 1)
+        } else {
+            let script_pubkey = Script::new_v0_wpkh(
+                &pubkey
+                    .wpubkey_hash()
+                    .expect("compressed public key always has witness hash"),
+            );
+            (
+                script_pubkey,
+                Script::new_p2pkh(&pubkey.pubkey_hash()),
+                0xFFFFFFFF,
+            )
+        };
+        let (vout, value) = match tx
+            .output
+            .iter()
+            .enumerate()
+            .find(|(_, txout)| txout.script_pubkey == script_pubkey)
+        {
+            Some((vout, txout)) => (vout as u32, txout.value),
+            None => {
+                let msg = format!(
+                    "{} no funds to sweep in channel {}",
+                    "Channel closed:".ended(),
+                    self.channel_id
+                );
+                info!("{}", msg);
+                return Ok(());
+            }
+        };
+
+        let fee = self.feerate_per_kw.max(self.min_feerate_per_kw) as u64
+            * SWEEP_TX_WEIGHT
+            / 1000;
+        if value <= fee + self.dust_limit_satoshis {
+            warn!(
+                "Channel {} output of {} sat is not worth sweeping",
+                self.channel_id, value
+            );
+            return Ok(());
+        }
+
+        let mut sweep_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: tx.txid(),
+                    vout,
+                },
+                script_sig: Script::new(),
+                sequence,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: value - fee,
+                script_pubkey: self.default_shutdown_script()?.into(),
+            }],
+        };
+        let sighash = SigHashCache::new(&mut sweep_tx).signature_hash(
+            0,
+            &script_code,
+            value,
+            SigHashType::All,
+        );
+        let sign_msg = secp256k1::Message::from_slice(&sighash[..])
             .expect("Sighash size always match requirements");
-        let signature = self.local_node.sign(&sign_msg);
-        trace!("Commitment transaction signature created");
-        // .serialize_der();
-        // let mut with_hashtype = signature.to_vec();
-        // with_hashtype.push(SigHashType::All.as_u32() as u8);
+        let mut signature = secp256k1::Secp256k1::signing_only()
+            .sign(&sign_msg, &secret)
+            .serialize_der()
+            .to_vec();
+        signature.push(SigHashType::All.as_u32() as u8);
+        sweep_tx.input[0].witness = if self.anchors {
+            vec![signature, script_code.to_bytes()]
+        } else {
+            vec![signature, pubkey.to_bytes()]
+        };
+
+        let txid = sweep_tx.txid();
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(sweep_tx),
+        )?;
+        self.book_event(
+            senders,
+            AccountingEventKind::OnchainFee,
+            None,
+            fee * 1000,
+            format!("Sweeping funds of recovered channel {}", self.channel_id),
+        )?;
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::NodeEvent(NodeEvent {
+                kind: NodeEventKind::ChannelClosed,
+                channel_id: Some(self.channel_id),
+                txid: Some(txid),
+                amount_msat: Some((value - fee) * 1000),
+                details: s!("Channel recovered from the backup is closed by \
+                             the remote peer, own funds are swept"),
+            }),
+        )?;
+
+        info!(
+            "{} {} sat from recovered channel {} with transaction {}",
+            "Swept".ended(),
+            value - fee,
+            self.channel_id,
+            txid.ender()
+        );
+        Ok(())
+    }
+
+    /// Checks whether a transaction spends the funding output with a revoked
+    /// remote commitment, and punishes the remote peer if it does
+    fn check_breach(
+        &mut self,
+        senders: &mut Senders,
+        tx: &Transaction,
+    ) -> Result<(), Error> {
+        if !tx
+            .input
+            .iter()
+            .any(|txin| txin.previous_output == self.funding_outpoint)
+        {
+            return Ok(());
+        }
+        let commitment_number =
+            commitment::commitment_number(tx, self.obscuring_factor);
+        let secret = match self.remote_secrets.get(commitment_number) {
+            Some(secret)
+                if commitment_number < self.remote_commitment_number =>
+            {
+                secret
+            }
+            // Our own or current remote commitment, or mutual close
+            _ => return Ok(()),
+        };
+
+        let txid = tx.txid();
+        warn!(
+            "{} {} of channel {}",
+            "Revoked commitment published in transaction".err(),
+            txid.err_details(),
+            self.channel_id
+        );
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::NodeEvent(NodeEvent {
+                kind: NodeEventKind::BreachDetected,
+                channel_id: Some(self.channel_id),
+                txid: Some(txid),
+                amount_msat: None,
+                details: format!(
+                    "Remote peer published revoked commitment #{}",
+                    commitment_number
+                ),
+            }),
+        )?;
+
+        let penalty_tx = match self.penalty_tx(tx, secret)? {
+            Some(penalty_tx) => penalty_tx,
+            None => {
+                warn!("Revoked commitment {} has nothing to claim", txid);
+                return Ok(());
+            }
+        };
+        let penalty_txid = penalty_tx.txid();
+        let value = penalty_tx.output[0].value;
+        info!(
+            "{} {}",
+            "Broadcasting penalty transaction".promo(),
+            penalty_txid.promoter()
+        );
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(penalty_tx),
+        )?;
+        self.force_closing = None;
+        self.htlc_claims.clear();
+        self.transition(State::Closed, Trigger::Protocol)?;
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::NodeEvent(NodeEvent {
+                kind: NodeEventKind::ChannelClosed,
+                channel_id: Some(self.channel_id),
+                txid: Some(penalty_txid),
+                amount_msat: Some(value * 1000),
+                details: s!("Channel breached, remote funds are claimed"),
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// Constructs justice transaction claiming `to_local` and HTLC outputs of
+    /// a revoked remote commitment with the revocation key
+    fn penalty_tx(
+        &self,
+        revoked_tx: &Transaction,
+        per_commitment_secret: secp256k1::SecretKey,
+    ) -> Result<Option<Transaction>, Error> {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let per_commitment_point = secp256k1::PublicKey::from_secret_key(
+            &secp,
+            &per_commitment_secret,
+        );
+        // Revoked commitment is owned by the remote peer
+        let keys = CommitmentKeys::derive(
+            &self.remote_keys,
+            &self.local_keys,
+            per_commitment_point,
+            self.static_remotekey,
+        );
+        let revocation_secret = derive_revocation_secret(
+            self.channel_keys().revocation_basepoint_secret,
+            per_commitment_secret,
+        );
+
+        // Witness scripts of the outputs spendable with the revocation key,
+        // indexed by their script pubkeys
+        let mut scripts = HashMap::new();
+        let to_local_script = commitment::to_local_script(
+            keys.revocation_pubkey,
+            keys.delayed_pubkey,
+            self.params.to_self_delay,
+        );
+        scripts.insert(to_local_script.to_v0_p2wsh(), (to_local_script, true));
+        // HTLC set of the revoked commitment is unknown, so we try all HTLCs
+        // which have ever been in the channel. HTLCs offered by us are
+        // received by the remote peer in its commitment and vice versa.
+        for htlc in self.storage.htlcs()? {
+            if htlc.asset_id.is_some() {
+                continue;
+            }
+            let witness_script = if htlc.offered {
+                commitment::received_htlc_script(
+                    keys.revocation_pubkey,
+                    keys.owner_htlc_pubkey,
+                    keys.counterparty_htlc_pubkey,
+                    htlc.payment_hash.as_ref(),
+                    htlc.cltv_expiry,
+                    self.anchors,
+                )
+            } else {
+                commitment::offered_htlc_script(
+                    keys.revocation_pubkey,
+                    keys.owner_htlc_pubkey,
+                    keys.counterparty_htlc_pubkey,
+                    htlc.payment_hash.as_ref(),
+                    self.anchors,
+                )
+            };
+            scripts
+                .insert(witness_script.to_v0_p2wsh(), (witness_script, false));
+        }
+        let claims = revoked_tx
+            .output
+            .iter()
+            .enumerate()
+            .filter_map(|(vout, txout)| {
+                scripts.get(&txout.script_pubkey).map(
+                    |(witness_script, to_local)| {
+                        (vout as u32, txout.value, witness_script, *to_local)
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        if claims.is_empty() {
+            return Ok(None);
+        }
+
+        let value = claims.iter().map(|(_, value, ..)| value).sum::<u64>();
+        let htlc_count =
+            claims.iter().filter(|(.., to_local)| !to_local).count() as u64;
+        let weight = PENALTY_TX_WEIGHT + PENALTY_HTLC_INPUT_WEIGHT * htlc_count;
+        let fee = self.feerate_per_kw as u64 * weight / 1000;
+        if value <= fee + self.dust_limit_satoshis {
+            return Ok(None);
+        }
+
+        let txid = revoked_tx.txid();
+        let mut penalty_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: claims
+                .iter()
+                .map(|(vout, ..)| TxIn {
+                    previous_output: OutPoint::new(txid, *vout),
+                    script_sig: Script::new(),
+                    // Revocation branches have no relative timelock
+                    sequence: 0xFFFFFFFF,
+                    witness: vec![],
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: value - fee,
+                script_pubkey: self.default_shutdown_script()?.into(),
+            }],
+        };
+        let mut sig_hasher = SigHashCache::new(&penalty_tx);
+        let signatures = claims
+            .iter()
+            .enumerate()
+            .map(|(index, (_, value, witness_script, _))| {
+                let sighash = sig_hasher.signature_hash(
+                    index,
+                    witness_script,
+                    *value,
+                    SigHashType::All,
+                );
+                let sign_msg = secp256k1::Message::from_slice(&sighash[..])
+                    .expect("Sighash size always match requirements");
+                let mut signature = secp
+                    .sign(&sign_msg, &revocation_secret)
+                    .serialize_der()
+                    .to_vec();
+                signature.push(SigHashType::All.as_u32() as u8);
+                signature
+            })
+            .collect::<Vec<_>>();
+        for ((txin, (_, _, witness_script, to_local)), signature) in
+            penalty_tx.input.iter_mut().zip(&claims).zip(signatures)
+        {
+            // Non-empty element selects the revocation branch of `to_local`
+            // output; HTLC outputs require the revocation key itself
+            let selector = if *to_local {
+                vec![1]
+            } else {
+                keys.revocation_pubkey.serialize().to_vec()
+            };
+            txin.witness = vec![signature, selector, witness_script.to_bytes()];
+        }
+        Ok(Some(penalty_tx))
+    }
+
+    /// Links the channel to a new connection with the remote peer and sends
+    /// `channel_reestablish` to it
+    fn reestablish(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        if !matches!(self.state, State::Funded { .. })
+            && self.state != State::Locked
+            && self.state != State::Active
+        {
+            // Negotiation of unfunded channels does not survive disconnection
+            debug!("Channel in {:?} state is not re-established", self.state);
+            return Ok(());
+        }
+
+        info!(
+            "{} channel {} with {}",
+            "Re-establishing".promo(),
+            self.channel_id.promoter(),
+            self.peer_service.promoter()
+        );
+        // New peer connection does not know about our channel id
+        self.send_ctl(
+            senders,
+            self.peer_service.clone(),
+            Request::UpdateChannelId(self.channel_id),
+        )?;
+        if self.recovering {
+            return self.request_remote_close(senders);
+        }
+        let channel_reestablish = message::ChannelReestablish {
+            channel_id: self.channel_id,
+            next_commitment_number: self.commitment_number + 1,
+            next_revocation_number: self.remote_commitment_number,
+            your_last_per_commitment_secret: self
+                .remote_secrets
+                .last()
+                .map(|secret| {
+                    let mut bytes = [0u8; 32];
+                    bytes.copy_from_slice(&secret[..]);
+                    Slice32::from_inner(bytes)
+                })
+                .unwrap_or_default(),
+            my_current_per_commitment_point: self
+                .local_per_commitment_point(self.commitment_number),
+        };
+        self.send_peer(
+            senders,
+            Messages::ChannelReestablish(channel_reestablish),
+        )
+    }
+
+    /// Asks the remote peer to close the channel which state we have lost.
+    /// Claiming that we are at the first commitment with an invalid secret
+    /// makes a peer supporting data loss protection fail the channel with
+    /// its latest commitment; other peers are asked with an error message.
+    fn request_remote_close(
+        &mut self,
+        senders: &mut Senders,
+    ) -> Result<(), Error> {
+        info!(
+            "{} peer {} to close channel {} recovered from the backup",
+            "Requesting".promo(),
+            self.peer_service.promoter(),
+            self.channel_id.promoter()
+        );
+        let channel_reestablish = message::ChannelReestablish {
+            channel_id: self.channel_id,
+            next_commitment_number: 1,
+            next_revocation_number: 0,
+            your_last_per_commitment_secret: default!(),
+            my_current_per_commitment_point: self
+                .local_keys
+                .first_per_commitment_point,
+        };
+        self.send_peer(
+            senders,
+            Messages::ChannelReestablish(channel_reestablish),
+        )?;
+        self.send_peer(
+            senders,
+            Messages::Error(message::Error {
+                channel_id: self.channel_id,
+                data: b"Channel state is lost; please close the channel \
+                        with your latest commitment"
+                    .to_vec(),
+            }),
+        )
+    }
+
+    /// Compares remote view of the commitment state with ours and
+    /// retransmits messages the remote peer has not received
+    fn channel_reestablished(
+        &mut self,
+        senders: &mut Senders,
+        channel_reestablish: message::ChannelReestablish,
+    ) -> Result<(), Error> {
+        if self.recovering {
+            // Remote per-commitment point allows to spend our output from the
+            // remote commitment if the channel has no static remote key
+            self.remote_per_commitment_point =
+                Some(channel_reestablish.my_current_per_commitment_point);
+            self.save()?;
+            info!(
+                "Remote peer has channel {} at commitment #{}; {}",
+                self.channel_id,
+                channel_reestablish.next_commitment_number,
+                "awaiting it to close the channel".promo()
+            );
+            return Ok(());
+        }
+
+        let next_commitment = channel_reestablish.next_commitment_number;
+        let next_revocation = channel_reestablish.next_revocation_number;
+        debug!(
+            "Remote peer expects commitment #{} and revocation #{}; our \
+             commitment numbers are {} (local) and {} (remote)",
+            next_commitment,
+            next_revocation,
+            self.commitment_number,
+            self.remote_commitment_number
+        );
+
+        if next_commitment > self.remote_commitment_number + 1
+            || next_revocation > self.commitment_number
+        {
+            // We must not publish our commitment: it may be revoked already
+            let info = format!(
+                "Remote peer has newer state of the channel {}; our data are \
+                 probably lost",
+                self.channel_id
+            );
+            error!("{}", info.err());
+            let enquirer = self.enquirer.clone();
+            return Err(self.report_failure_to(
+                senders,
+                &enquirer,
+                microservices::rpc::Failure { code: 0, info },
+            ));
+        }
+        if next_commitment + 1 < self.remote_commitment_number {
+            error!(
+                "{} peer has lost channel {} state",
+                "Remote".err(),
+                self.channel_id
+            );
+            return Err(Error::Misbehaving);
+        }
+
+        if next_commitment == 1
+            && self.remote_commitment_number == 0
+            && (self.state == State::Locked || self.state == State::Active)
+        {
+            // Remote peer may have missed our `funding_locked`
+            self.send_funding_locked(senders)?;
+        }
+        if next_revocation + 1 == self.commitment_number {
+            if let Some(revoke_and_ack) = self.last_revoke_and_ack.clone() {
+                debug!("Retransmitting revoke_and_ack");
+                self.send_peer(
+                    senders,
+                    Messages::RevokeAndAck(revoke_and_ack),
+                )?;
+            }
+        }
+        if next_commitment == self.remote_commitment_number {
+            if let Some(commitment_signed) = self.last_commitment_signed.clone()
+            {
+                debug!("Retransmitting commitment_signed");
+                self.send_peer(
+                    senders,
+                    Messages::CommitmentSigned(commitment_signed),
+                )?;
+            }
+        }
+        if let Some(local_script) = self
+            .closing
+            .as_ref()
+            .map(|closing| closing.local_script.clone())
+        {
+            // Closing negotiation restarts from the shutdown
+            self.shutdown(senders, local_script)?;
+        }
+        // Funding watches are lost when the node restarts
+        if matches!(self.state, State::Funded { .. }) {
+            self.watch_funding(senders);
+        }
+        // HTLCs settled while the peer was disconnected
+        self.resolve_htlcs(senders)?;
+        // Pending plugin decisions are lost if lnpd or this daemon has
+        // restarted, so they are asked for again
+        for update_add_htlc in
+            self.hooked_htlc.values().cloned().collect::<Vec<_>>()
+        {
+            self.run_htlc_hook(senders, &update_add_htlc)?;
+        }
+        // Announcement signatures are exchanged again until the channel is
+        // announced
+        if self.public
+            && self.state == State::Active
+            && self.remote_announcement.is_none()
+        {
+            match self.short_channel_id {
+                Some(_) => self.send_announcement_signatures(senders)?,
+                None => self.watch_announcement_depth(senders),
+            }
+        }
+
+        let msg = format!(
+            "{} {}",
+            "Channel re-established with".ended(),
+            self.peer_service.ender()
+        );
+        info!("{}", msg);
+        let enquirer = self.enquirer.clone();
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+        Ok(())
+    }
+
+    /// Persists channel state with the storage driver
+    pub fn save(&mut self) -> Result<(), Error> {
+        let state = storage::ChannelState {
+            channel_id: self.channel_id,
+            temporary_channel_id: self.temporary_channel_id,
+            key_salt: self.key_salt,
+            state: self.state.lifecycle(),
+            funding_outpoint: self.funding_outpoint,
+            local_capacity: self.local_capacity,
+            remote_capacity: self.remote_capacity,
+            commitment_number: self.commitment_number,
+            remote_commitment_number: self.remote_commitment_number,
+            feerate_per_kw: self.feerate_per_kw,
+            anchors: self.anchors,
+            static_remotekey: self.static_remotekey,
+            scid_alias: self.scid_alias,
+            remote_scid_alias: self.remote_scid_alias,
+            obscuring_factor: self.obscuring_factor,
+            remote_signature: self.remote_signature,
+            local_commitment: self.local_commitment.clone(),
+            force_closing: self.force_closing.clone(),
+            htlc_claims: self.htlc_claims.clone(),
+            remote_per_commitment_point: self.remote_per_commitment_point,
+            remote_revoked_point: self.remote_revoked_point,
+            minimum_depth: self.minimum_depth,
+            funding_height: self.state.funding_height(),
+            funding_candidates: self.funding_candidates.clone(),
+            is_originator: self.is_originator,
+            remote_peer: self.remote_peer.clone(),
+            params: self.params,
+            local_keys: self.local_keys.clone(),
+            remote_keys: self.remote_keys.clone(),
+            dust_limit_satoshis: self.dust_limit_satoshis,
+            local_limits: self.local_limits,
+            remote_limits: self.remote_limits,
+            local_balances: self.local_balances.clone(),
+            remote_balances: self.remote_balances.clone(),
+            total_payments: self.total_payments,
+            pending_payments: self.pending_payments,
+            offered_htlc: self.offered_htlc.clone(),
+            offered_hashes: self.offered_hashes.clone(),
+            received_htlc: self.received_htlc.clone(),
+            received_lockin: self.received_lockin.clone(),
+            resolved_htlc: self.resolved_htlc.clone(),
+            hooked_htlc: self.hooked_htlc.clone(),
+            last_commitment_signed: self.last_commitment_signed.clone(),
+            last_revoke_and_ack: self.last_revoke_and_ack.clone(),
+            remote_commitments: self.remote_commitments.clone(),
+            remote_secrets: self.remote_secrets.clone(),
+            upfront_shutdown_script: self.upfront_shutdown_script.clone(),
+            remote_upfront_shutdown_script: self
+                .remote_upfront_shutdown_script
+                .clone(),
+            wallet_script: self.wallet_script.clone(),
+            closing: self.closing.clone(),
+            forwarding_policy: self.forwarding_policy,
+            recovering: self.recovering,
+            public: self.public,
+            short_channel_id: self.short_channel_id,
+        };
+        self.storage.store(&state)
+    }
+
+    fn handle_bridge(
+        &mut self,
+        senders: &mut Senders,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::CheckTimeouts => self.check_timeouts(senders),
+            _ => {
+                Err(Error::NotSupported(ServiceBus::Bridge, request.get_type()))
+            }
+        }
+    }
+
+    /// Moves the channel into the next lifecycle state, persisting it
+    fn transition(
+        &mut self,
+        state: State,
+        trigger: Trigger,
+    ) -> Result<(), Error> {
+        lifecycle::check_transition(self.state, state, trigger)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        debug!(
+            "Channel {} moves from {:?} to {:?} state on {}",
+            self.channel_id, self.state, state, trigger
+        );
+        self.state = state;
+        self.state_changed = SystemTime::now();
+        self.save()
+    }
 
+    /// Resumes the channel from the state stored before the daemon restart
+    fn restore(&mut self, state: storage::ChannelState) -> Result<(), Error> {
+        info!(
+            "{} channel {} from the stored state {:?}",
+            "Restoring".promo(),
+            state.channel_id.promoter(),
+            state.state
+        );
+        self.channel_id = state.channel_id;
+        self.temporary_channel_id = state.temporary_channel_id;
+        self.key_salt = state.key_salt;
+        self.funding_outpoint = state.funding_outpoint;
+        self.funding_candidates = state.funding_candidates;
+        self.local_capacity = state.local_capacity;
+        self.remote_capacity = state.remote_capacity;
+        self.commitment_number = state.commitment_number;
+        self.remote_commitment_number = state.remote_commitment_number;
+        self.feerate_per_kw = state.feerate_per_kw;
+        self.anchors = state.anchors;
+        self.static_remotekey = state.static_remotekey;
+        self.scid_alias = state.scid_alias;
+        self.remote_scid_alias = state.remote_scid_alias;
+        self.obscuring_factor = state.obscuring_factor;
+        self.remote_signature = state.remote_signature;
+        self.local_commitment = state.local_commitment;
+        self.force_closing = state.force_closing;
+        self.htlc_claims = state.htlc_claims;
+        self.remote_per_commitment_point = state.remote_per_commitment_point;
+        self.remote_revoked_point = state.remote_revoked_point;
+        self.minimum_depth = state.minimum_depth;
+        self.is_originator = state.is_originator;
+        self.params = state.params;
+        self.local_keys = state.local_keys;
+        self.remote_keys = state.remote_keys;
+        self.dust_limit_satoshis = state.dust_limit_satoshis;
+        self.local_limits = state.local_limits;
+        self.remote_limits = state.remote_limits;
+        self.local_balances = state.local_balances;
+        self.remote_balances = state.remote_balances;
+        self.total_payments = state.total_payments;
+        self.pending_payments = state.pending_payments;
+        self.offered_htlc = state.offered_htlc;
+        self.offered_hashes = state.offered_hashes;
+        self.received_htlc = state.received_htlc;
+        self.received_lockin = state.received_lockin;
+        self.resolved_htlc = state.resolved_htlc;
+        self.hooked_htlc = state.hooked_htlc;
+        self.last_commitment_signed = state.last_commitment_signed;
+        self.last_revoke_and_ack = state.last_revoke_and_ack;
+        self.remote_commitments = state.remote_commitments;
+        self.remote_secrets = state.remote_secrets;
+        self.upfront_shutdown_script = state.upfront_shutdown_script;
+        self.remote_upfront_shutdown_script =
+            state.remote_upfront_shutdown_script;
+        self.wallet_script = state.wallet_script;
+        self.closing = state.closing;
+        self.forwarding_policy = state.forwarding_policy;
+        self.recovering = state.recovering;
+        self.public = state.public;
+        self.short_channel_id = state.short_channel_id;
+        if let Some(ref remote_peer) = state.remote_peer {
+            self.peer_service = ServiceId::Peer(remote_peer.clone());
+        }
+        self.remote_peer = state.remote_peer;
+        self.transition(
+            State::with(state.state, state.funding_height),
+            Trigger::Restore,
+        )
+    }
+
+    pub fn sign_funding(&mut self) -> secp256k1::Signature {
+        // First commitment has no HTLCs
+        let (signature, _) =
+            self.sign_commitment(self.remote_commitment_number);
+        self.keep_remote_commitment(self.remote_commitment_number);
         signature
     }
 
@@ -908,6 +5294,27 @@ impl Runtime {
     ) -> Result<message::UpdateAddHtlc, Error> {
         let enquirer = self.enquirer.clone();
 
+        if self.state != State::Active {
+            Err(Error::Other(format!(
+                "Channel is not active yet; its current state is {:?}",
+                self.state
+            )))?
+        }
+        if self.closing.is_some() || self.force_closing.is_some() {
+            Err(Error::Other(s!("Channel is being closed")))?
+        }
+        if self.quiescence.is_pending() {
+            Err(Error::Other(s!(
+                "Channel updates are stopped for quiescence"
+            )))?
+        }
+        if !self.peer_connected {
+            Err(Error::Other(s!(
+                "Remote peer is disconnected; channel updates are paused \
+                 until the channel is re-established"
+            )))?
+        }
+
         let available = if let Some(asset_id) = transfer_req.asset {
             self.local_balances.get(&asset_id).copied().unwrap_or(0)
         } else {
@@ -920,6 +5327,33 @@ impl Runtime {
             )))?
         }
 
+        self.remote_limits
+            .check(
+                transfer_req.amount,
+                transfer_req.asset.is_none(),
+                self.offered_htlc.len(),
+                self.offered_htlc
+                    .iter()
+                    .filter(|htlc| htlc.asset_id.is_none())
+                    .map(|htlc| htlc.amount)
+                    .sum(),
+                self.local_capacity,
+            )
+            .map_err(|err| {
+                Error::Other(format!("HTLC can't be offered: {}", err))
+            })?;
+        if let Some(asset_id) = transfer_req.asset {
+            self.check_asset_htlc(
+                &self.remote_limits,
+                true,
+                asset_id,
+                transfer_req.amount,
+            )
+            .map_err(|err| {
+                Error::Other(format!("HTLC can't be offered: {}", err))
+            })?;
+        }
+
         info!(
             "{} {} {} to the remote peer",
             "Transferring".promo(),
@@ -931,8 +5365,11 @@ impl Runtime {
                 .promoter(),
         );
 
+        // Preimage of a paid invoice is unknown, and the random one is not
+        // used for HTLCs with the payment hash given
         let preimage = HashPreimage::random();
-        let payment_hash = preimage.into();
+        let payment_hash =
+            transfer_req.payment_hash.unwrap_or_else(|| preimage.into());
         let htlc = HtlcKnown {
             preimage,
             id: self.total_payments,
@@ -942,125 +5379,445 @@ impl Runtime {
         };
         trace!("Generated HTLC: {:?}", htlc);
         self.offered_htlc.push(htlc);
+        if transfer_req.payment_hash.is_some() {
+            self.offered_hashes.insert(htlc.id, payment_hash);
+        }
+        self.storage
+            .store_htlc(&HtlcRecord::offered(&htlc, payment_hash))?;
+
+        let update_add_htlc = message::UpdateAddHtlc {
+            channel_id: self.channel_id,
+            htlc_id: htlc.id,
+            amount_msat: transfer_req.amount,
+            payment_hash,
+            cltv_expiry: htlc.cltv_expiry,
+            onion_routing_packet: dumb!(), // TODO: Generate proper onion packet
+            asset_id: transfer_req.asset,
+        };
+        self.total_payments += 1;
+        self.pending_payments += 1;
+        if let Some(ref enquirer) = enquirer {
+            self.payment_enquirers.insert(htlc.id, enquirer.clone());
+        }
+        // Funds are locked in the HTLC until it is fulfilled or failed
+        match transfer_req.asset {
+            Some(asset_id) => {
+                self.local_balances.get_mut(&asset_id).map(|balance| {
+                    *balance -= transfer_req.amount;
+                });
+                self.report_balances(senders)?;
+            }
+            None => {
+                self.local_capacity -= transfer_req.amount;
+            }
+        }
+        self.save()?;
+
+        let msg = format!("{}", "HTLC offered".ended());
+        info!("{}", msg);
+        let _ = self.report_progress_to(senders, &enquirer, msg);
+
+        Ok(update_add_htlc)
+    }
+
+    #[cfg(feature = "rgb")]
+    pub fn refill(
+        &mut self,
+        senders: &mut Senders,
+        consignment: Consignment,
+        outpoint: OutPoint,
+        blinding: u64,
+        refill_originator: bool,
+    ) -> Result<(), Error> {
+        let enquirer = self.enquirer.clone();
+
+        debug!("Validating consignment with RGB Node ...");
+        self.request_rbg20(rgb_node::rpc::fungible::Request::Validate(
+            consignment.clone(),
+        ))?;
+
+        debug!("Adding consignment to stash via RGB Node ...");
+        self.request_rbg20(rgb_node::rpc::fungible::Request::Accept(
+            rgb_node::rpc::fungible::AcceptApi {
+                consignment: consignment.clone(),
+                reveal_outpoints: vec![OutpointReveal {
+                    blinding: blinding,
+                    txid: outpoint.txid,
+                    vout: outpoint.vout,
+                }],
+            },
+        ))?;
+
+        debug!("Requesting new balances for {} ...", outpoint);
+        match self
+            .request_rbg20(rgb_node::rpc::fungible::Request::Assets(outpoint))?
+        {
+            rgb_node::rpc::Reply::Assets(balances) => {
+                for (id, balances) in balances {
+                    let asset_id = AssetId::from(id);
+                    let balance: u64 = balances.into_iter().sum();
+                    info!(
+                        "{} {} of {} to balance",
+                        "Adding".promo(),
+                        balance.promoter(),
+                        asset_id.promoter()
+                    );
+                    let msg = format!(
+                        "adding {} of {} to balance",
+                        balance.ender(),
+                        asset_id.ender()
+                    );
+                    let _ = self.report_progress_to(senders, &enquirer, msg);
+
+                    // Each refill assigns assets to a new outpoint, so its
+                    // balance adds up to the assets already in the channel
+                    let (funded, counterparty) = if refill_originator {
+                        (&mut self.local_balances, &mut self.remote_balances)
+                    } else {
+                        (&mut self.remote_balances, &mut self.local_balances)
+                    };
+                    *funded.entry(asset_id).or_insert(0) += balance;
+                    counterparty.entry(asset_id).or_insert(0);
+                }
+            }
+            _ => Err(Error::Other(s!("Unrecognized RGB Node response")))?,
+        }
+
+        self.save()?;
+        self.report_balances(senders)
+    }
+
+    /// Records event affecting channel funds in the `lnpd` accounting journal
+    fn book_event(
+        &self,
+        senders: &mut Senders,
+        kind: AccountingEventKind,
+        asset: Option<AssetId>,
+        amount_msat: u64,
+        description: String,
+    ) -> Result<(), Error> {
+        senders.send_to(
+            ServiceBus::Ctl,
+            self.identity(),
+            ServiceId::Lnpd,
+            Request::BookEvent(AccountingEvent {
+                kind,
+                channel_id: Some(self.channel_id),
+                txid: None,
+                asset,
+                amount_msat,
+                fee_msat: 0,
+                description,
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// Notifies `lnpd` about the current local asset balances of the channel
+    pub fn report_balances(&self, senders: &mut Senders) -> Result<(), Error> {
+        let channel_id = if self.channel_id == zero!() {
+            self.temporary_channel_id.into()
+        } else {
+            self.channel_id
+        };
+        senders.send_to(
+            ServiceBus::Ctl,
+            self.identity(),
+            ServiceId::Lnpd,
+            Request::ChannelBalances(request::ChannelBalances {
+                channel_id,
+                balances: self.local_balances.clone(),
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// Settles outgoing payment once the remote peer reveals preimage of its
+    /// HTLC, crediting the remote side with the HTLC amount
+    pub fn htlc_fulfilled(
+        &mut self,
+        senders: &mut Senders,
+        update_fulfill_htlc: message::UpdateFulfillHtlc,
+    ) -> Result<(), Error> {
+        let htlc_id = update_fulfill_htlc.htlc_id;
+        let pos = match self
+            .offered_htlc
+            .iter()
+            .position(|htlc| htlc.id == htlc_id)
+        {
+            Some(pos) => pos,
+            None => {
+                let info =
+                    format!("Remote peer fulfilled unknown HTLC #{}", htlc_id);
+                return Err(self.fail_channel(senders, info));
+            }
+        };
+        let htlc = self.offered_htlc[pos];
+        let payment_hash = self.offered_hash(&htlc);
+        if HashLock::from(update_fulfill_htlc.payment_preimage) != payment_hash
+        {
+            let info = format!(
+                "Preimage provided by the remote peer for HTLC #{} does not \
+                 match its payment hash",
+                htlc_id
+            );
+            return Err(self.fail_channel(senders, info));
+        }
+
+        let htlc = self.offered_htlc.remove(pos);
+        let invoice_paid = self.offered_hashes.remove(&htlc.id).is_some();
+        self.storage
+            .store_htlc(&HtlcRecord::offered(&htlc, payment_hash).resolved())?;
+        self.pending_payments = self.pending_payments.saturating_sub(1);
+        match htlc.asset_id {
+            Some(asset_id) => {
+                *self.remote_balances.entry(asset_id).or_insert(0) +=
+                    htlc.amount;
+            }
+            None => {
+                self.remote_capacity += htlc.amount;
+            }
+        }
+        self.remote_commitment_dirty = true;
+        self.save()?;
+
+        self.book_event(
+            senders,
+            AccountingEventKind::PaymentSent,
+            htlc.asset_id,
+            htlc.amount,
+            format!("Transfer with HTLC #{}", htlc_id),
+        )?;
+
+        let msg = format!(
+            "{} with HTLC #{}",
+            "Payment settled".ended(),
+            htlc_id.ender()
+        );
+        info!("{}", msg);
+        let enquirer = self.payment_enquirers.remove(&htlc_id);
+        match enquirer {
+            // Preimage is the proof of the invoice payment
+            Some(enquirer) if invoice_paid => {
+                let _ = self.send_ctl(
+                    senders,
+                    enquirer,
+                    Request::PaymentPreimage(
+                        update_fulfill_htlc.payment_preimage,
+                    ),
+                );
+            }
+            enquirer => {
+                let _ = self.report_success_to(senders, &enquirer, Some(msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes outgoing HTLC failed by the remote peer, returning its amount
+    /// to the local balance
+    pub fn htlc_failed(
+        &mut self,
+        senders: &mut Senders,
+        htlc_id: u64,
+        reason: String,
+    ) -> Result<(), Error> {
+        let pos = match self
+            .offered_htlc
+            .iter()
+            .position(|htlc| htlc.id == htlc_id)
+        {
+            Some(pos) => pos,
+            None => {
+                let info =
+                    format!("Remote peer failed unknown HTLC #{}", htlc_id);
+                return Err(self.fail_channel(senders, info));
+            }
+        };
+        let htlc = self.offered_htlc.remove(pos);
+        let payment_hash = self.offered_hash(&htlc);
+        self.offered_hashes.remove(&htlc.id);
+        self.storage
+            .store_htlc(&HtlcRecord::offered(&htlc, payment_hash).resolved())?;
+        self.pending_payments = self.pending_payments.saturating_sub(1);
+        match htlc.asset_id {
+            Some(asset_id) => {
+                *self.local_balances.entry(asset_id).or_insert(0) +=
+                    htlc.amount;
+                self.report_balances(senders)?;
+            }
+            None => {
+                self.local_capacity += htlc.amount;
+            }
+        }
+        self.remote_commitment_dirty = true;
+        self.save()?;
+
+        let info = format!("Payment with HTLC #{} failed: {}", htlc_id, reason);
+        warn!("{}", info);
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::NodeEvent(NodeEvent {
+                kind: NodeEventKind::PaymentFailed,
+                channel_id: Some(self.channel_id),
+                txid: None,
+                amount_msat: Some(htlc.amount),
+                details: reason,
+            }),
+        )?;
+        let enquirer = self.payment_enquirers.remove(&htlc_id);
+        let _ = self.report_failure_to(
+            senders,
+            &enquirer,
+            microservices::rpc::Failure {
+                code: 0, // TODO: Create error type system
+                info,
+            },
+        );
+        Ok(())
+    }
 
-        let update_add_htlc = message::UpdateAddHtlc {
-            channel_id: self.channel_id,
-            htlc_id: htlc.id,
-            amount_msat: transfer_req.amount,
-            payment_hash,
-            cltv_expiry: htlc.cltv_expiry,
-            onion_routing_packet: dumb!(), // TODO: Generate proper onion packet
-            asset_id: transfer_req.asset,
+    /// Fails HTLC offered by the remote peer, returning its amount to the
+    /// remote balance, and signs the updated remote commitment
+    pub fn htlc_fail(
+        &mut self,
+        senders: &mut Senders,
+        htlc_id: u64,
+        failure: HtlcFailure,
+    ) -> Result<(), Error> {
+        let pos = match self
+            .received_htlc
+            .iter()
+            .position(|htlc| htlc.id == htlc_id)
+        {
+            Some(pos) => pos,
+            None => {
+                warn!("Can't fail unknown HTLC #{}", htlc_id);
+                return Ok(());
+            }
         };
-        self.total_payments += 1;
-        match transfer_req.asset {
+        let htlc = self.received_htlc.remove(pos);
+        self.received_lockin.remove(&htlc_id);
+        self.storage
+            .store_htlc(&HtlcRecord::received(&htlc).resolved())?;
+        match htlc.asset_id {
             Some(asset_id) => {
-                self.local_balances.get_mut(&asset_id).map(|balance| {
-                    *balance -= transfer_req.amount;
-                });
-
-                let entry = self.remote_balances.entry(asset_id).or_insert(0);
-                *entry += transfer_req.amount;
+                *self.remote_balances.entry(asset_id).or_insert(0) +=
+                    htlc.amount;
             }
             None => {
-                self.local_capacity -= transfer_req.amount;
-                self.remote_capacity += transfer_req.amount;
+                self.remote_capacity += htlc.amount;
             }
         }
 
-        let msg = format!("{}", "Funding transferred".ended());
-        info!("{}", msg);
-        let _ = self.report_progress_to(senders, &enquirer, msg);
+        debug!("Failing HTLC #{} with {}", htlc_id, failure);
+        self.send_peer(
+            senders,
+            Messages::UpdateFailHtlc(message::UpdateFailHtlc {
+                channel_id: self.channel_id,
+                htlc_id,
+                reason: failure.serialize(),
+            }),
+        )?;
+        self.send_commitment(senders)
+    }
 
-        Ok(update_add_htlc)
+    /// Payment hash locking the HTLC offered by us
+    fn offered_hash(&self, htlc: &HtlcKnown) -> HashLock {
+        self.offered_hashes
+            .get(&htlc.id)
+            .copied()
+            .unwrap_or_else(|| HashLock::from(htlc.preimage))
     }
 
-    #[cfg(feature = "rgb")]
-    pub fn refill(
+    /// Commitment transaction fee at the given feerate, paid by the channel
+    /// funder
+    fn commitment_fee(&self, feerate_per_kw: u32) -> u64 {
+        // Only HTLCs which are not trimmed to dust have outputs
+        let offered = self
+            .offered_htlc
+            .iter()
+            .filter(|htlc| htlc.asset_id.is_none())
+            .filter(|htlc| {
+                !self.is_dust_htlc(htlc.amount, true, feerate_per_kw)
+            });
+        let received = self
+            .received_htlc
+            .iter()
+            .filter(|htlc| htlc.asset_id.is_none())
+            .filter(|htlc| {
+                !self.is_dust_htlc(htlc.amount, false, feerate_per_kw)
+            });
+        let htlc_count = offered.count() + received.count();
+        fees::commitment_fee(feerate_per_kw, self.anchors, htlc_count)
+    }
+
+    /// Applies commitment feerate proposed by the remote peer with
+    /// `update_fee`, failing the channel if the feerate is out of the
+    /// configured bounds or the funder is unable to pay it
+    pub fn fee_updated(
         &mut self,
         senders: &mut Senders,
-        consignment: Consignment,
-        outpoint: OutPoint,
-        blinding: u64,
-        refill_originator: bool,
+        feerate_per_kw: u32,
     ) -> Result<(), Error> {
-        let enquirer = self.enquirer.clone();
-
-        debug!("Validating consignment with RGB Node ...");
-        self.request_rbg20(rgb_node::rpc::fungible::Request::Validate(
-            consignment.clone(),
-        ))?;
-
-        debug!("Adding consignment to stash via RGB Node ...");
-        self.request_rbg20(rgb_node::rpc::fungible::Request::Accept(
-            rgb_node::rpc::fungible::AcceptApi {
-                consignment: consignment.clone(),
-                reveal_outpoints: vec![OutpointReveal {
-                    blinding: blinding,
-                    txid: outpoint.txid,
-                    vout: outpoint.vout,
-                }],
-            },
-        ))?;
-
-        debug!("Requesting new balances for {} ...", outpoint);
-        match self
-            .request_rbg20(rgb_node::rpc::fungible::Request::Assets(outpoint))?
+        if self.is_originator {
+            let info = format!(
+                "Remote peer sent update_fee for channel {} funded by us",
+                self.channel_id
+            );
+            return Err(self.fail_channel(senders, info));
+        }
+        if feerate_per_kw < self.min_feerate_per_kw
+            || feerate_per_kw > self.max_feerate_per_kw
         {
-            rgb_node::rpc::Reply::Assets(balances) => {
-                for (id, balances) in balances {
-                    let asset_id = AssetId::from(id);
-                    let balance: u64 = balances.into_iter().sum();
-                    info!(
-                        "{} {} of {} to balance",
-                        "Adding".promo(),
-                        balance.promoter(),
-                        asset_id.promoter()
-                    );
-                    let msg = format!(
-                        "adding {} of {} to balance",
-                        balance.ender(),
-                        asset_id.ender()
-                    );
-                    let _ = self.report_progress_to(senders, &enquirer, msg);
-
-                    if refill_originator {
-                        self.local_balances.insert(asset_id, balance);
-                        self.remote_balances.insert(asset_id, 0);
-                    } else {
-                        self.remote_balances.insert(asset_id, balance);
-                        self.local_balances.insert(asset_id, 0);
-                    };
-                }
-            }
-            _ => Err(Error::Other(s!("Unrecognized RGB Node response")))?,
+            let info = format!(
+                "Feerate {} sat/kw proposed by the remote peer is out of \
+                 acceptable bounds {}..{} sat/kw",
+                feerate_per_kw,
+                self.min_feerate_per_kw,
+                self.max_feerate_per_kw
+            );
+            return Err(self.fail_channel(senders, info));
+        }
+        let fee = self.commitment_fee(feerate_per_kw);
+        if fee * 1000 > self.remote_capacity {
+            let info = format!(
+                "Remote peer can't pay commitment fee of {} sat at feerate {} \
+                 sat/kw",
+                fee, feerate_per_kw
+            );
+            return Err(self.fail_channel(senders, info));
         }
 
-        let _ = self.report_success_to(
-            senders,
-            &enquirer,
-            Some("transfer completed"),
+        debug!(
+            "Commitment feerate updated from {} to {} sat/kw",
+            self.feerate_per_kw, feerate_per_kw
         );
-        Ok(())
+        self.feerate_per_kw = feerate_per_kw;
+        self.remote_commitment_dirty = true;
+        self.save()
     }
 
-    pub fn htlc_receive(
+    /// Adds HTLC offered by the remote peer to the channel, locking the
+    /// remote funds until the HTLC is fulfilled or failed
+    pub fn htlc_add(
         &mut self,
-        _senders: &mut Senders,
-        update_add_htlc: message::UpdateAddHtlc,
-    ) -> Result</* message::CommitmentSigned */ (), Error> {
+        update_add_htlc: &message::UpdateAddHtlc,
+    ) -> Result<(), Error> {
         trace!("Updating HTLCs with {:?}", update_add_htlc);
-        // TODO: Use From/To for message <-> Htlc conversion in LNP/BP
-        //       Core lib
-        let htlc = HtlcSecret {
-            amount: update_add_htlc.amount_msat,
-            hashlock: update_add_htlc.payment_hash,
-            id: update_add_htlc.htlc_id,
-            cltv_expiry: update_add_htlc.cltv_expiry,
-            asset_id: update_add_htlc.asset_id,
-        };
-        self.received_htlc.push(htlc);
-
+        if matches!(
+            self.closing,
+            Some(Closing {
+                remote_script: Some(_),
+                ..
+            })
+        ) {
+            Err(Error::Other(format!(
+                "Remote peer offered HTLC #{} after sending shutdown",
+                update_add_htlc.htlc_id
+            )))?
+        }
         let available = if let Some(asset_id) = update_add_htlc.asset_id {
             self.remote_balances.get(&asset_id).copied().unwrap_or(0)
         } else {
@@ -1073,29 +5830,465 @@ impl Runtime {
             )))?
         }
 
-        self.total_payments += 1;
+        self.local_limits
+            .check(
+                update_add_htlc.amount_msat,
+                update_add_htlc.asset_id.is_none(),
+                self.received_htlc.len(),
+                self.received_htlc
+                    .iter()
+                    .filter(|htlc| htlc.asset_id.is_none())
+                    .map(|htlc| htlc.amount)
+                    .sum(),
+                self.remote_capacity,
+            )
+            .map_err(|err| {
+                Error::Other(format!(
+                    "Remote peer offered HTLC #{} violating channel limits: {}",
+                    update_add_htlc.htlc_id, err
+                ))
+            })?;
+        if let Some(asset_id) = update_add_htlc.asset_id {
+            self.check_asset_htlc(
+                &self.local_limits,
+                false,
+                asset_id,
+                update_add_htlc.amount_msat,
+            )
+            .map_err(|err| {
+                Error::Other(format!(
+                    "Remote peer offered HTLC #{} violating channel limits: {}",
+                    update_add_htlc.htlc_id, err
+                ))
+            })?;
+        }
+
+        // TODO: Use From/To for message <-> Htlc conversion in LNP/BP
+        //       Core lib
+        let htlc = HtlcSecret {
+            amount: update_add_htlc.amount_msat,
+            hashlock: update_add_htlc.payment_hash,
+            id: update_add_htlc.htlc_id,
+            cltv_expiry: update_add_htlc.cltv_expiry,
+            asset_id: update_add_htlc.asset_id,
+        };
+        self.storage.store_htlc(&HtlcRecord::received(&htlc))?;
+        self.received_htlc.push(htlc);
+        // HTLC gets into our next commitment signed by the remote peer and
+        // into the next remote commitment signed by us
+        self.received_lockin.insert(
+            htlc.id,
+            HtlcLockIn {
+                local_commitment: self.commitment_number + 1,
+                remote_revocation: self.remote_commitment_number,
+            },
+        );
         match update_add_htlc.asset_id {
             Some(asset_id) => {
                 self.remote_balances.get_mut(&asset_id).map(|balance| {
                     *balance -= update_add_htlc.amount_msat;
                 });
-
-                let entry = self.local_balances.entry(asset_id).or_insert(0);
-                *entry += update_add_htlc.amount_msat;
             }
             None => {
                 self.remote_capacity -= update_add_htlc.amount_msat;
-                self.local_capacity += update_add_htlc.amount_msat;
             }
         }
+        self.remote_commitment_dirty = true;
+        self.save()
+    }
+
+    /// Books HTLC offered by the remote peer once it is accepted by plugins
+    /// Asks plugins subscribed to `htlc_accepted` hook point to decide on
+    /// the HTLC offered by the remote peer
+    fn run_htlc_hook(
+        &mut self,
+        senders: &mut Senders,
+        update_add_htlc: &message::UpdateAddHtlc,
+    ) -> Result<(), Error> {
+        let call = HookCall {
+            id: update_add_htlc.htlc_id,
+            point: HookPoint::HtlcAccepted,
+            peer: self.remote_peer.clone(),
+            channel_id: Some(self.channel_id),
+            amount_msat: Some(update_add_htlc.amount_msat),
+            payment_hash: Some(update_add_htlc.payment_hash),
+            details: format!(
+                "HTLC #{} offered by the remote peer",
+                update_add_htlc.htlc_id
+            ),
+        };
+        self.send_ctl(senders, ServiceId::Lnpd, Request::RunHook(call))
+    }
+
+    pub fn htlc_receive(
+        &mut self,
+        senders: &mut Senders,
+        update_add_htlc: message::UpdateAddHtlc,
+    ) -> Result<(), Error> {
+        self.total_payments += 1;
+        self.save()?;
+        if update_add_htlc.asset_id.is_some() {
+            self.report_balances(senders)?;
+        }
+
+        // HTLCs which are not paying to us are returned by lnpd with
+        // `ForwardHtlc` for the daemon intercepting their payment hash
+        let htlc = IncomingHtlc {
+            htlc_id: update_add_htlc.htlc_id,
+            payment_hash: update_add_htlc.payment_hash,
+            amount_msat: update_add_htlc.amount_msat,
+            asset_id: update_add_htlc.asset_id,
+            cltv_expiry: update_add_htlc.cltv_expiry,
+            chain_height: self.chain_height,
+        };
+        self.send_ctl(senders, ServiceId::Lnpd, Request::SettleHtlc(htlc))?;
+
+        // TODO: Generate new RGB state transitions and commit them into the
+        //       commitment transaction
+        Ok(())
+    }
+
+    /// Passes the received HTLC to the daemon forwarding it to the next hop,
+    /// which settles the HTLC once the forwarded payment is resolved
+    fn htlc_forward(
+        &mut self,
+        senders: &mut Senders,
+        forward: HtlcForward,
+    ) -> Result<(), Error> {
+        let htlc = match self
+            .received_htlc
+            .iter()
+            .find(|htlc| htlc.id == forward.htlc_id)
+        {
+            Some(htlc) => htlc,
+            None => {
+                warn!("Forwarding unknown HTLC #{}", forward.htlc_id);
+                return Ok(());
+            }
+        };
+        let intercepted = InterceptedHtlc {
+            htlc_id: htlc.id,
+            scid: forward.scid,
+            payment_hash: htlc.hashlock,
+            amount_msat: htlc.amount,
+        };
+        info!(
+            "{} HTLC #{} to {} via {}",
+            "Forwarding".promo(),
+            htlc.id,
+            forward.scid,
+            forward.interceptor.promoter()
+        );
+        if let Err(err) = self.send_ctl(
+            senders,
+            forward.interceptor,
+            Request::InterceptHtlc(intercepted),
+        ) {
+            warn!("Unable to forward HTLC #{}: {}", forward.htlc_id, err);
+            self.resolved_htlc.insert(forward.htlc_id, None);
+            self.save()?;
+            self.resolve_htlcs(senders)?;
+        }
+        Ok(())
+    }
+
+    /// Detects whether the received HTLC is irrevocably committed to both
+    /// commitment transactions
+    fn is_locked_in(&self, htlc_id: u64) -> bool {
+        match self.received_lockin.get(&htlc_id) {
+            Some(lockin) => {
+                self.commitment_number >= lockin.local_commitment
+                    && self.remote_secrets.count() > lockin.remote_revocation
+            }
+            // HTLCs received before lock-in tracking are committed long ago
+            None => true,
+        }
+    }
+
+    /// Starts negotiation of stopping channel updates by a request from one
+    /// of the node daemons, which is notified once the channel is quiescent
+    fn quiesce(
+        &mut self,
+        senders: &mut Senders,
+        requester: ServiceId,
+    ) -> Result<(), Error> {
+        let requester = Some(requester);
+        let info = if self.state != State::Active {
+            Some(format!(
+                "Channel is not active yet; its current state is {:?}",
+                self.state
+            ))
+        } else if self.closing.is_some() || self.force_closing.is_some() {
+            Some(s!("Channel is being closed"))
+        } else if self.quiescence.is_pending() {
+            Some(s!("Channel quiescence is already negotiated"))
+        } else {
+            None
+        };
+        if let Some(info) = info {
+            return Err(self.report_failure_to(
+                senders,
+                &requester,
+                microservices::rpc::Failure {
+                    code: 0, // TODO: Create error type system
+                    info,
+                },
+            ));
+        }
+
+        info!(
+            "{} of channel {}",
+            "Requesting quiescence".promo(),
+            self.channel_id.promoter()
+        );
+        self.quiescence.request();
+        self.quiescence_requester = requester;
+        self.try_quiesce(senders)
+    }
+
+    /// Checks whether some of the channel updates are not irrevocably
+    /// committed to both commitment transactions yet
+    fn has_uncommitted_updates(&self) -> bool {
+        self.remote_commitment_dirty
+            || self.awaiting_revocation()
+            || self
+                .received_lockin
+                .keys()
+                .any(|id| !self.is_locked_in(*id))
+    }
+
+    /// Checks whether some of the channel updates are not committed yet or
+    /// are still to be sent for the received HTLCs
+    fn has_pending_updates(&self) -> bool {
+        self.has_uncommitted_updates()
+            || !self.hooked_htlc.is_empty()
+            || !self.resolved_htlc.is_empty()
+    }
+
+    /// Sends our `stfu` once there are no pending updates, after which we do
+    /// not update the channel
+    fn try_quiesce(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        if !self.quiescence.should_send(self.has_pending_updates()) {
+            return Ok(());
+        }
+        let remote_id = match self.remote_peer {
+            Some(ref peer) => peer.id,
+            None => {
+                return Err(Error::Other(s!(
+                    "Remote peer of the channel is not known"
+                )))
+            }
+        };
+        let initiator = self.quiescence.local_stfu();
+        debug!(
+            "Stopping updates of channel {} as {}",
+            self.channel_id,
+            if initiator { "initiator" } else { "responder" }
+        );
+        let mut payload = self.channel_id.as_inner().as_inner().to_vec();
+        payload.push(initiator as u8);
+        senders.send_to(
+            ServiceBus::Msg,
+            self.identity(),
+            self.peer_service.clone(),
+            Request::CustomPeerMessage(CustomMessage {
+                remote_id,
+                msg_type: STFU_TYPE,
+                payload,
+            }),
+        )?;
+
+        if !self.quiescence.is_quiescent() {
+            let requester = self.quiescence_requester.clone();
+            let msg = format!(
+                "{} of channel {}; awaiting the remote peer",
+                "Updates are stopped".ended(),
+                self.channel_id
+            );
+            info!("{}", msg);
+            let _ = self.report_progress_to(senders, &requester, msg);
+        }
+        self.report_quiescent(senders)
+    }
+
+    /// Processes `stfu` message of the remote peer, which stops channel
+    /// updates of the both peers
+    fn stfu_received(
+        &mut self,
+        senders: &mut Senders,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let initiator = match payload.get(32) {
+            Some(flag) if payload.len() == 33 => *flag == 1,
+            _ => {
+                let info = s!("Remote peer has sent malformed stfu message");
+                return Err(self.fail_channel(senders, info));
+            }
+        };
+        let uncommitted = self.has_uncommitted_updates();
+        if let Err(err) = self.quiescence.remote_stfu(
+            initiator,
+            uncommitted,
+            self.is_originator,
+        ) {
+            return Err(self.fail_channel(senders, err.to_string()));
+        }
+        info!(
+            "Remote peer has {} of channel {}",
+            "stopped updates".promo(),
+            self.channel_id.promoter()
+        );
+        if self.quiescence.is_local_sent() {
+            self.report_quiescent(senders)
+        } else {
+            // We reply with our `stfu` once our updates are committed
+            self.try_quiesce(senders)
+        }
+    }
+
+    /// Notifies the requester once both peers have sent `stfu`
+    fn report_quiescent(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        if !self.quiescence.is_quiescent() {
+            return Ok(());
+        }
+        let requester = self.quiescence_requester.clone();
+        let msg = format!(
+            "Channel {} is {} with the local node as {}",
+            self.channel_id,
+            "quiescent".ended(),
+            if self.quiescence.is_initiator() {
+                "initiator"
+            } else {
+                "responder"
+            }
+        );
+        info!("{}", msg);
+        let _ = self.report_success_to(senders, &requester, Some(msg));
+        Ok(())
+    }
+
+    /// Ends quiescence once the operation which has required it is completed
+    fn release_quiescence(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+    ) -> Result<(), Error> {
+        if !self.quiescence.is_pending() {
+            warn!("Channel {} is not quiescent", self.channel_id);
+            return Ok(());
+        }
+        self.quiescence.reset();
+        self.quiescence_requester = None;
+
+        let msg = format!(
+            "{} of channel {} is ended",
+            "Quiescence".ended(),
+            self.channel_id
+        );
+        info!("{}", msg);
+        let _ = self.report_success_to(senders, source, Some(msg));
+        // Resolution of HTLCs is postponed during quiescence
+        self.resolve_htlcs(senders)
+    }
 
+    /// Fulfills or fails received HTLCs which have been resolved and are
+    /// irrevocably committed
+    fn resolve_htlcs(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        if self.quiescence.is_local_sent() {
+            // HTLCs are resolved once quiescence is released
+            return Ok(());
+        }
+        if !self.peer_connected {
+            // HTLCs are resolved once the channel is re-established
+            return Ok(());
+        }
+        let ready = self
+            .resolved_htlc
+            .iter()
+            .filter(|(id, _)| self.is_locked_in(**id))
+            .map(|(id, preimage)| (*id, *preimage))
+            .collect::<Vec<_>>();
+        for (htlc_id, preimage) in ready {
+            self.resolved_htlc.remove(&htlc_id);
+            self.received_lockin.remove(&htlc_id);
+            match preimage {
+                Some(preimage) => {
+                    self.htlc_fulfill(senders, htlc_id, preimage)?
+                }
+                None => self.htlc_fail(
+                    senders,
+                    htlc_id,
+                    HtlcFailure::with(
+                        HtlcFailure::INCORRECT_OR_UNKNOWN_PAYMENT_DETAILS,
+                    ),
+                )?,
+            }
+        }
         Ok(())
+    }
+
+    /// Fulfills HTLC offered by the remote peer with the payment preimage,
+    /// moving its amount to our balance, and signs the updated remote
+    /// commitment
+    pub fn htlc_fulfill(
+        &mut self,
+        senders: &mut Senders,
+        htlc_id: u64,
+        preimage: HashPreimage,
+    ) -> Result<(), Error> {
+        let pos = match self
+            .received_htlc
+            .iter()
+            .position(|htlc| htlc.id == htlc_id)
+        {
+            Some(pos) => pos,
+            None => {
+                warn!("Can't fulfill unknown HTLC #{}", htlc_id);
+                return Ok(());
+            }
+        };
+        let htlc = self.received_htlc.remove(pos);
+        self.storage
+            .store_htlc(&HtlcRecord::received(&htlc).resolved())?;
+        // HTLC stays in our commitment until the remote peer signs the next
+        // one, so we keep the preimage for claiming its output
+        for claim in self
+            .htlc_claims
+            .iter_mut()
+            .filter(|claim| claim.payment_hash == htlc.hashlock)
+        {
+            claim.preimage = Some(preimage);
+        }
+        match htlc.asset_id {
+            Some(asset_id) => {
+                *self.local_balances.entry(asset_id).or_insert(0) +=
+                    htlc.amount;
+                self.report_balances(senders)?;
+            }
+            None => {
+                self.local_capacity += htlc.amount;
+            }
+        }
+        self.save()?;
+        // Payment is received only once the HTLC is fulfilled, which also
+        // triggers `invoice_paid` notifications
+        self.book_event(
+            senders,
+            AccountingEventKind::PaymentReceived,
+            htlc.asset_id,
+            htlc.amount,
+            format!("Transfer with HTLC #{}", htlc_id),
+        )?;
 
-        // TODO:
-        //      1. Generate new commitment tx
-        //      2. Generate new transitions and anchor, commit into tx
-        //      3. Sign commitment tx
-        //      4. Generate HTLCs, tweak etc each of them
-        //      3. Send response
+        info!("{} HTLC #{}", "Fulfilling".ended(), htlc_id);
+        self.send_peer(
+            senders,
+            Messages::UpdateFulfillHtlc(message::UpdateFulfillHtlc {
+                channel_id: self.channel_id,
+                htlc_id,
+                payment_preimage: preimage,
+            }),
+        )?;
+        self.send_commitment(senders)
     }
 }
@@ -0,0 +1,297 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! BOLT3 per-commitment secrets: generation from a seed with shachain and
+//! compact storage of the secrets revealed by the remote peer
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{self, Secp256k1};
+
+/// Index of the per-commitment secret for the first commitment transaction;
+/// indexes are counted down with each next commitment
+pub const START_INDEX: u64 = (1 << 48) - 1;
+
+/// Number of bits in the secret index
+const INDEX_BITS: u32 = 48;
+
+/// Errors in per-commitment secrets revealed by the remote peer
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ShachainError {
+    /// per-commitment secret for commitment #{0} is revealed out of order
+    OutOfOrder(u64),
+
+    /// per-commitment secret for commitment #{0} does not match the secrets
+    /// revealed for the previous commitments
+    Mismatch(u64),
+}
+
+/// Shachain index of the per-commitment secret for the commitment number
+pub fn index(commitment_number: u64) -> u64 {
+    START_INDEX - commitment_number
+}
+
+/// Derives the secret for the `index` from a base secret, flipping and
+/// hashing the lowest `bits` of the index
+fn derive(base: [u8; 32], bits: u32, index: u64) -> [u8; 32] {
+    let mut secret = base;
+    for bit in (0..bits).rev() {
+        if (index >> bit) & 1 == 1 {
+            secret[bit as usize / 8] ^= 1 << (bit % 8);
+            secret = sha256::Hash::hash(&secret).into_inner();
+        }
+    }
+    secret
+}
+
+fn bytes(secret: &secp256k1::SecretKey) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&secret[..]);
+    bytes
+}
+
+/// Generates the per-commitment secret with the given index from the seed
+pub fn generate_from_seed(seed: [u8; 32], index: u64) -> [u8; 32] {
+    derive(seed, INDEX_BITS, index)
+}
+
+/// Per-commitment secret for the commitment number
+pub fn commitment_secret(
+    seed: [u8; 32],
+    commitment_number: u64,
+) -> secp256k1::SecretKey {
+    let secret = generate_from_seed(seed, index(commitment_number));
+    secp256k1::SecretKey::from_slice(&secret)
+        .expect("negligible probability of secret exceeding curve order")
+}
+
+/// Per-commitment point for the commitment number
+pub fn commitment_point(
+    seed: [u8; 32],
+    commitment_number: u64,
+) -> secp256k1::PublicKey {
+    secp256k1::PublicKey::from_secret_key(
+        &Secp256k1::signing_only(),
+        &commitment_secret(seed, commitment_number),
+    )
+}
+
+/// Secret from which all secrets with the same higher bits of the index can
+/// be derived
+#[derive(Clone, Copy, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct Entry {
+    index: u64,
+    secret: secp256k1::SecretKey,
+}
+
+/// Compact storage of per-commitment secrets revealed by the remote peer.
+/// Keeps at most 49 secrets, from which all the previous secrets are derived.
+#[derive(Clone, PartialEq, Eq, Debug, Default, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct ShachainStore {
+    /// Entry at position `b` has lowest `b` bits of its index set to zero
+    known: Vec<Entry>,
+    /// Number of the revealed secrets, i.e. the number of the next commitment
+    /// which secret is expected
+    count: u64,
+}
+
+impl ShachainStore {
+    /// Number of secrets revealed by the remote peer
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Stores secret revoking commitment with the given number, checking that
+    /// all previously revealed secrets are derivable from it
+    pub fn insert(
+        &mut self,
+        commitment_number: u64,
+        secret: secp256k1::SecretKey,
+    ) -> Result<(), ShachainError> {
+        if commitment_number != self.count {
+            return Err(ShachainError::OutOfOrder(commitment_number));
+        }
+        let index = index(commitment_number);
+        let position = index.trailing_zeros().min(INDEX_BITS) as usize;
+        if position > self.known.len() {
+            return Err(ShachainError::OutOfOrder(commitment_number));
+        }
+        for entry in &self.known[..position] {
+            if derive(bytes(&secret), position as u32, entry.index)
+                != bytes(&entry.secret)
+            {
+                return Err(ShachainError::Mismatch(commitment_number));
+            }
+        }
+        let entry = Entry { index, secret };
+        if position == self.known.len() {
+            self.known.push(entry);
+        } else {
+            self.known[position] = entry;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Returns secret revoking commitment with the given number, if it was
+    /// revealed
+    pub fn get(&self, commitment_number: u64) -> Option<secp256k1::SecretKey> {
+        if commitment_number >= self.count {
+            return None;
+        }
+        let index = index(commitment_number);
+        self.known.iter().enumerate().find_map(|(position, entry)| {
+            let mask = !((1u64 << position) - 1);
+            if index & mask != entry.index {
+                return None;
+            }
+            let secret = derive(bytes(&entry.secret), position as u32, index);
+            secp256k1::SecretKey::from_slice(&secret).ok()
+        })
+    }
+
+    /// Returns the latest secret revealed by the remote peer
+    pub fn last(&self) -> Option<secp256k1::SecretKey> {
+        self.count.checked_sub(1).and_then(|last| self.get(last))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+
+    // BOLT3 Appendix D: Per-commitment Secret Generation Test Vectors
+    fn bytes32(hex: &str) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&Vec::from_hex(hex).unwrap());
+        bytes
+    }
+
+    fn secret(seed: [u8; 32], commitment_number: u64) -> secp256k1::SecretKey {
+        commitment_secret(seed, commitment_number)
+    }
+
+    #[test]
+    fn generation() {
+        assert_eq!(
+            generate_from_seed([0x00; 32], START_INDEX),
+            bytes32(
+                "02a40c85b6f28da08dfdbe0926c53fab2de6d28c10301f8f7c4073d5e42e3148"
+            )
+        );
+        assert_eq!(
+            generate_from_seed([0xFF; 32], START_INDEX),
+            bytes32(
+                "7cc854b54e3e0dcdb010d7a3fee464a9687be6e8db3be6854c475621e007a5dc"
+            )
+        );
+        assert_eq!(
+            generate_from_seed([0xFF; 32], 0xaaaaaaaaaaa),
+            bytes32(
+                "56f4008fb007ca9acf0e15b054d5c9fd12ee06cea347914ddbaed70d1c13a528"
+            )
+        );
+        assert_eq!(
+            generate_from_seed([0xFF; 32], 0x555555555555),
+            bytes32(
+                "9015daaeb06dba4ccc05b91b2f73bd54405f2be9f217fbacd3c5ac2e62327d31"
+            )
+        );
+        assert_eq!(
+            generate_from_seed([0x01; 32], 1),
+            bytes32(
+                "915c75942a26bb3a433a8ce2cb0427c29ec6c1775cfc78328b57f6ba7bfeaa9c"
+            )
+        );
+    }
+
+    #[test]
+    fn correct_sequence() {
+        let secrets = [
+            "7cc854b54e3e0dcdb010d7a3fee464a9687be6e8db3be6854c475621e007a5dc",
+            "c7518c8ae4660ed02894df8976fa1a3659c1a8b4b5bec0c4b872abeba4cb8964",
+            "2273e227a5b7449b6e70f1fb4652864038b1cbf9cd7c043a7d6456b7fc275ad8",
+            "27cddaa5624534cb6cb9d7da077cf2b22ab21e9b506fd4998a51d54502e99116",
+            "c65716add7aa98ba7acb236352d665cab17345fe45b55fb879ff80e6bd0c41dd",
+            "969660042a28f32d9be17344e09374b379962d03db1574df5a8a5a47e19ce3f2",
+            "a5a64476122ca0925fb344bdc1854c1c0a59fc614298e50a33e331980a220f32",
+            "05cde6323d949933f7f7b78776bcc1ea6d9b31447732e3802e1f7ac44b650e17",
+        ]
+        .iter()
+        .map(|hex| secp256k1::SecretKey::from_slice(&bytes32(hex)).unwrap())
+        .collect::<Vec<_>>();
+
+        let mut store = ShachainStore::default();
+        for (commitment_number, expected) in secrets.iter().enumerate() {
+            let commitment_number = commitment_number as u64;
+            assert_eq!(secret([0xFF; 32], commitment_number), *expected);
+            store.insert(commitment_number, *expected).unwrap();
+            assert_eq!(store.last(), Some(*expected));
+        }
+        assert_eq!(store.count(), 8);
+        for (commitment_number, secret) in secrets.iter().enumerate() {
+            assert_eq!(store.get(commitment_number as u64), Some(*secret));
+        }
+        assert_eq!(store.get(8), None);
+        // Store keeps only the secrets from which the rest are derived
+        assert_eq!(store.known.len(), 4);
+    }
+
+    /// Inserts correct secrets except the one for `incorrect` commitment,
+    /// which is generated from a different seed, returning the error
+    fn insert_incorrect(incorrect: u64) -> (u64, ShachainError) {
+        let mut store = ShachainStore::default();
+        for commitment_number in 0..16 {
+            let seed = if commitment_number == incorrect {
+                [0x00; 32]
+            } else {
+                [0xFF; 32]
+            };
+            if let Err(err) =
+                store.insert(commitment_number, secret(seed, commitment_number))
+            {
+                return (commitment_number, err);
+            }
+        }
+        panic!("incorrect secret was not detected");
+    }
+
+    #[test]
+    fn incorrect_secrets() {
+        // Incorrect secret is detected once a secret which it must be derived
+        // from is inserted
+        assert_eq!(insert_incorrect(0), (1, ShachainError::Mismatch(1)));
+        assert_eq!(insert_incorrect(1), (1, ShachainError::Mismatch(1)));
+        assert_eq!(insert_incorrect(2), (3, ShachainError::Mismatch(3)));
+        assert_eq!(insert_incorrect(4), (5, ShachainError::Mismatch(5)));
+        assert_eq!(insert_incorrect(6), (7, ShachainError::Mismatch(7)));
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut store = ShachainStore::default();
+        assert_eq!(
+            store.insert(1, secret([0xFF; 32], 1)),
+            Err(ShachainError::OutOfOrder(1))
+        );
+        store.insert(0, secret([0xFF; 32], 0)).unwrap();
+        assert_eq!(
+            store.insert(0, secret([0xFF; 32], 0)),
+            Err(ShachainError::OutOfOrder(0))
+        );
+        assert_eq!(store.count(), 1);
+    }
+}
@@ -13,14 +13,21 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::any::Any;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use bitcoin::secp256k1;
 use lnp::ChannelId;
+use lnpbp::strict_encoding::{strict_serialize, StrictDecode, StrictEncode};
 
-use super::Driver;
+use super::{ChannelState, CommitmentRecord, Driver, HtlcRecord};
 use crate::Error;
 
 pub struct DiskConfig {
+    /// Directory keeping channel state files
     pub path: PathBuf,
 }
 
@@ -29,16 +36,215 @@ pub struct DiskDriver {
     config: DiskConfig,
 }
 
+impl DiskDriver {
+    fn state_path(&self) -> PathBuf {
+        self.config
+            .path
+            .join(format!("{}.channel", self.channel_id))
+    }
+
+    fn commitments_path(&self) -> PathBuf {
+        self.state_path().with_extension("commitments")
+    }
+
+    fn htlcs_path(&self) -> PathBuf {
+        self.state_path().with_extension("htlcs")
+    }
+
+    /// Appends record to the end of the log file, creating the file if needed
+    fn append<T>(&self, path: PathBuf, record: &T) -> Result<(), Error>
+    where
+        T: StrictEncode,
+    {
+        let data = strict_serialize(record)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Reads all records from the log file in the order they were appended
+    fn replay<T>(&self, path: PathBuf) -> Result<Vec<T>, Error>
+    where
+        T: StrictDecode,
+    {
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let data = fs::read(&path)?;
+        let len = data.len() as u64;
+        let mut cursor = Cursor::new(data);
+        let mut records = vec![];
+        while cursor.position() < len {
+            let pos = cursor.position();
+            match T::strict_decode(&mut cursor) {
+                Ok(record) => records.push(record),
+                Err(err) => {
+                    // Record was not completely written before the daemon
+                    // has stopped, so we drop it to append the next ones
+                    warn!(
+                        "Truncating channel log {:?} at {} bytes: {}",
+                        path, pos, err
+                    );
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .open(&path)?
+                        .set_len(pos)?;
+                    break;
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
 impl Driver for DiskDriver {
     fn init(
         channel_id: ChannelId,
         config: Box<dyn Any>,
     ) -> Result<Self, Error> {
-        let config = *config.downcast().map_err(|_| Error::Other(s!("")))?;
+        let config: DiskConfig = *config.downcast().map_err(|_| {
+            Error::Other(s!("Disk storage driver requires disk configuration"))
+        })?;
+        fs::create_dir_all(&config.path)?;
         Ok(Self { channel_id, config })
     }
 
-    fn store(&mut self) -> Result<(), Error> {
-        unimplemented!()
+    fn store(&mut self, state: &ChannelState) -> Result<(), Error> {
+        let path = self.state_path();
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        state
+            .strict_encode(&mut file)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        // State must reach the disk before it replaces the previous one
+        file.sync_all()?;
+        fs::rename(tmp_path, path)?;
+        // Rename is durable only once the directory entry is synced
+        #[cfg(unix)]
+        fs::File::open(&self.config.path)?.sync_all()?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Option<ChannelState>, Error> {
+        let path = self.state_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = fs::File::open(path)?;
+        let state = ChannelState::strict_decode(file)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        Ok(Some(state))
+    }
+
+    fn remove(&mut self) -> Result<(), Error> {
+        for path in vec![
+            self.state_path(),
+            self.commitments_path(),
+            self.htlcs_path(),
+        ] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn append_commitment_point(
+        &mut self,
+        commitment_number: u64,
+        point: secp256k1::PublicKey,
+    ) -> Result<(), Error> {
+        let record = CommitmentRecord {
+            commitment_number,
+            per_commitment_point: Some(point),
+            per_commitment_secret: None,
+        };
+        self.append(self.commitments_path(), &record)
+    }
+
+    fn append_commitment_secret(
+        &mut self,
+        commitment_number: u64,
+        secret: secp256k1::SecretKey,
+    ) -> Result<(), Error> {
+        let record = CommitmentRecord {
+            commitment_number,
+            per_commitment_point: None,
+            per_commitment_secret: Some(secret),
+        };
+        self.append(self.commitments_path(), &record)
+    }
+
+    fn store_htlc(&mut self, htlc: &HtlcRecord) -> Result<(), Error> {
+        self.append(self.htlcs_path(), htlc)
+    }
+
+    fn commitments(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = CommitmentRecord>>, Error> {
+        // Points and secrets are appended separately, so we merge them here
+        let mut commitments = BTreeMap::<u64, CommitmentRecord>::new();
+        for record in
+            self.replay::<CommitmentRecord>(self.commitments_path())?
+        {
+            let entry = commitments
+                .entry(record.commitment_number)
+                .or_insert_with(|| CommitmentRecord {
+                    commitment_number: record.commitment_number,
+                    per_commitment_point: None,
+                    per_commitment_secret: None,
+                });
+            if record.per_commitment_point.is_some() {
+                entry.per_commitment_point = record.per_commitment_point;
+            }
+            if record.per_commitment_secret.is_some() {
+                entry.per_commitment_secret = record.per_commitment_secret;
+            }
+        }
+        Ok(Box::new(commitments.into_iter().map(|(_, record)| record)))
+    }
+
+    fn htlcs(&self) -> Result<Box<dyn Iterator<Item = HtlcRecord>>, Error> {
+        // Later records replace earlier ones for the same HTLC
+        let htlcs = self
+            .replay::<HtlcRecord>(self.htlcs_path())?
+            .into_iter()
+            .map(|htlc| ((htlc.offered, htlc.id), htlc))
+            .collect::<BTreeMap<_, _>>();
+        Ok(Box::new(htlcs.into_iter().map(|(_, htlc)| htlc)))
+    }
+
+    fn channels(&self) -> Result<Vec<ChannelId>, Error> {
+        Self::list(&self.config.path)
+    }
+}
+
+impl DiskDriver {
+    /// Lists ids of the channels which state files are kept in the directory
+    pub fn list(path: &Path) -> Result<Vec<ChannelId>, Error> {
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let mut channels = vec![];
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("channel")
+            {
+                continue;
+            }
+            if let Some(channel_id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| ChannelId::from_str(stem).ok())
+            {
+                channels.push(channel_id);
+            }
+        }
+        Ok(channels)
     }
 }
@@ -14,14 +14,124 @@
 
 use std::any::Any;
 
+use bitcoin::secp256k1;
+use lnp::payment::htlc::{HtlcKnown, HtlcSecret};
 use lnp::ChannelId;
+use lnpbp::chain::AssetId;
+use wallet::HashLock;
 
+use super::ChannelState;
 use crate::Error;
 
+/// Data on a single historical remote commitment transaction
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct CommitmentRecord {
+    pub commitment_number: u64,
+    /// Per-commitment point provided by the remote peer for the commitment
+    pub per_commitment_point: Option<secp256k1::PublicKey>,
+    /// Per-commitment secret revealed by the remote peer once the commitment
+    /// was revoked
+    pub per_commitment_secret: Option<secp256k1::SecretKey>,
+}
+
+/// Data on a single HTLC offered or received by the channel
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct HtlcRecord {
+    pub id: u64,
+    /// Whether the HTLC was offered by us (`true`) or by the remote peer
+    pub offered: bool,
+    pub amount: u64,
+    pub asset_id: Option<AssetId>,
+    pub payment_hash: HashLock,
+    pub cltv_expiry: u32,
+    /// Whether the HTLC was fulfilled or failed and is not in flight anymore
+    pub resolved: bool,
+}
+
+impl HtlcRecord {
+    /// Constructs record for the HTLC in flight offered by us, locked with
+    /// the given payment hash
+    pub fn offered(htlc: &HtlcKnown, payment_hash: HashLock) -> Self {
+        Self {
+            id: htlc.id,
+            offered: true,
+            amount: htlc.amount,
+            asset_id: htlc.asset_id,
+            payment_hash,
+            cltv_expiry: htlc.cltv_expiry,
+            resolved: false,
+        }
+    }
+
+    /// Constructs record for the HTLC in flight received from the remote peer
+    pub fn received(htlc: &HtlcSecret) -> Self {
+        Self {
+            id: htlc.id,
+            offered: false,
+            amount: htlc.amount,
+            asset_id: htlc.asset_id,
+            payment_hash: htlc.hashlock,
+            cltv_expiry: htlc.cltv_expiry,
+            resolved: false,
+        }
+    }
+
+    /// Marks the record as resolved
+    pub fn resolved(mut self) -> Self {
+        self.resolved = true;
+        self
+    }
+}
+
+/// Storage backend for the channel state.
+///
+/// Drivers must implement whole-state writes with [`Driver::store`]; the rest
+/// of the methods allow backends to persist frequently changing data
+/// incrementally instead of re-writing the whole state on each update.
 pub trait Driver {
     fn init(channel_id: ChannelId, config: Box<dyn Any>) -> Result<Self, Error>
     where
         Self: Sized;
 
-    fn store(&mut self) -> Result<(), Error>;
+    fn store(&mut self, state: &ChannelState) -> Result<(), Error>;
+
+    /// Loads previously stored channel state, if any
+    fn load(&self) -> Result<Option<ChannelState>, Error>;
+
+    /// Removes stored state of the channel which is forgotten
+    fn remove(&mut self) -> Result<(), Error>;
+
+    /// Appends per-commitment point provided by the remote peer for the
+    /// given remote commitment
+    fn append_commitment_point(
+        &mut self,
+        commitment_number: u64,
+        point: secp256k1::PublicKey,
+    ) -> Result<(), Error>;
+
+    /// Appends per-commitment secret revealed by the remote peer when it has
+    /// revoked the given remote commitment
+    fn append_commitment_secret(
+        &mut self,
+        commitment_number: u64,
+        secret: secp256k1::SecretKey,
+    ) -> Result<(), Error>;
+
+    /// Stores new or updated HTLC record, replacing the previous record with
+    /// the same id and direction
+    fn store_htlc(&mut self, htlc: &HtlcRecord) -> Result<(), Error>;
+
+    /// Iterates over historical remote commitments in the order of their
+    /// numbers
+    fn commitments(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = CommitmentRecord>>, Error>;
+
+    /// Iterates over all known HTLCs, including the resolved ones
+    fn htlcs(&self) -> Result<Box<dyn Iterator<Item = HtlcRecord>>, Error>;
+
+    /// Lists ids of all channels which state is kept by the same storage
+    fn channels(&self) -> Result<Vec<ChannelId>, Error>;
 }
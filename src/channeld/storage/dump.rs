@@ -0,0 +1,64 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use super::{ChannelState, CommitmentRecord, Driver, HtlcRecord};
+use crate::Error;
+
+/// All data kept by the storage driver for a single channel, used to move
+/// the channel between node instances
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct ChannelDump {
+    pub state: ChannelState,
+    pub commitments: Vec<CommitmentRecord>,
+    pub htlcs: Vec<HtlcRecord>,
+}
+
+impl ChannelDump {
+    /// Reads channel data from the storage; returns `None` if the channel
+    /// state was never stored
+    pub fn read(driver: &dyn Driver) -> Result<Option<ChannelDump>, Error> {
+        let state = match driver.load()? {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        Ok(Some(ChannelDump {
+            state,
+            commitments: driver.commitments()?.collect(),
+            htlcs: driver.htlcs()?.collect(),
+        }))
+    }
+
+    /// Writes channel data into the storage, which must not contain any
+    /// data for the channel yet
+    pub fn write(&self, driver: &mut dyn Driver) -> Result<(), Error> {
+        driver.store(&self.state)?;
+        for record in &self.commitments {
+            if let Some(point) = record.per_commitment_point {
+                driver
+                    .append_commitment_point(record.commitment_number, point)?;
+            }
+            if let Some(secret) = record.per_commitment_secret {
+                driver.append_commitment_secret(
+                    record.commitment_number,
+                    secret,
+                )?;
+            }
+        }
+        for htlc in &self.htlcs {
+            driver.store_htlc(htlc)?;
+        }
+        Ok(())
+    }
+}
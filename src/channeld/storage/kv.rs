@@ -0,0 +1,233 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::any::Any;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bitcoin::secp256k1;
+use lnp::ChannelId;
+use lnpbp::strict_encoding::{
+    strict_deserialize, strict_serialize, StrictDecode, StrictEncode,
+};
+
+use super::{ChannelState, CommitmentRecord, Driver, HtlcRecord};
+use crate::Error;
+
+const STATE_KEY: &[u8] = b"state";
+const COMMITMENTS_TREE: &[u8] = b"commitments";
+const HTLCS_TREE: &[u8] = b"htlcs";
+
+pub struct KvConfig {
+    /// Directory keeping channel databases
+    pub path: PathBuf,
+}
+
+/// Storage driver keeping channel state in embedded key-value database.
+///
+/// Each channel daemon runs in a separate process, while the database can be
+/// opened by a single process only; thus each channel gets its own database
+/// inside [`KvConfig::path`] directory, named after the channel id. Channel
+/// state is kept in the default tree of the database, and historical
+/// commitments and HTLCs are kept in separate trees.
+pub struct KvDriver {
+    channel_id: ChannelId,
+    config: KvConfig,
+    db: sled::Db,
+}
+
+impl KvDriver {
+    fn db_path(config: &KvConfig, channel_id: ChannelId) -> PathBuf {
+        config.path.join(channel_id.to_string())
+    }
+
+    fn tree(&self, name: &[u8]) -> Result<sled::Tree, Error> {
+        self.db.open_tree(name).map_err(kv_error)
+    }
+
+    fn get<T>(tree: &sled::Tree, key: &[u8]) -> Result<Option<T>, Error>
+    where
+        T: StrictDecode,
+    {
+        match tree.get(key).map_err(kv_error)? {
+            None => Ok(None),
+            Some(data) => strict_deserialize(&data)
+                .map(Some)
+                .map_err(|err| Error::Other(err.to_string())),
+        }
+    }
+
+    fn put<T>(tree: &sled::Tree, key: &[u8], value: &T) -> Result<(), Error>
+    where
+        T: StrictEncode,
+    {
+        let data = strict_serialize(value)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        tree.insert(key, data).map_err(kv_error)?;
+        Ok(())
+    }
+
+    fn values<T>(tree: &sled::Tree) -> Result<Vec<T>, Error>
+    where
+        T: StrictDecode,
+    {
+        tree.iter()
+            .values()
+            .map(|data| {
+                strict_deserialize(&data.map_err(kv_error)?)
+                    .map_err(|err| Error::Other(err.to_string()))
+            })
+            .collect()
+    }
+
+    /// Merges per-commitment point or secret into the commitment record
+    fn update_commitment(
+        &mut self,
+        commitment_number: u64,
+        point: Option<secp256k1::PublicKey>,
+        secret: Option<secp256k1::SecretKey>,
+    ) -> Result<(), Error> {
+        let tree = self.tree(COMMITMENTS_TREE)?;
+        // Big-endian keys keep the tree ordered by commitment number
+        let key = commitment_number.to_be_bytes();
+        let mut record = Self::get(&tree, &key)?.unwrap_or(CommitmentRecord {
+            commitment_number,
+            per_commitment_point: None,
+            per_commitment_secret: None,
+        });
+        if point.is_some() {
+            record.per_commitment_point = point;
+        }
+        if secret.is_some() {
+            record.per_commitment_secret = secret;
+        }
+        Self::put(&tree, &key, &record)?;
+        self.flush()
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        self.db.flush().map_err(kv_error)?;
+        Ok(())
+    }
+}
+
+impl Driver for KvDriver {
+    fn init(
+        channel_id: ChannelId,
+        config: Box<dyn Any>,
+    ) -> Result<Self, Error> {
+        let config: KvConfig = *config.downcast().map_err(|_| {
+            Error::Other(s!(
+                "Key-value storage driver requires key-value configuration"
+            ))
+        })?;
+        fs::create_dir_all(&config.path)?;
+        let db =
+            sled::open(Self::db_path(&config, channel_id)).map_err(kv_error)?;
+        Ok(Self {
+            channel_id,
+            config,
+            db,
+        })
+    }
+
+    fn store(&mut self, state: &ChannelState) -> Result<(), Error> {
+        Self::put(&self.db, STATE_KEY, state)?;
+        // State must reach the disk before we proceed with the protocol
+        self.flush()
+    }
+
+    fn load(&self) -> Result<Option<ChannelState>, Error> {
+        Self::get(&self.db, STATE_KEY)
+    }
+
+    fn remove(&mut self) -> Result<(), Error> {
+        self.db.clear().map_err(kv_error)?;
+        for name in &[COMMITMENTS_TREE, HTLCS_TREE] {
+            self.db.drop_tree(name).map_err(kv_error)?;
+        }
+        self.flush()?;
+        fs::remove_dir_all(Self::db_path(&self.config, self.channel_id))?;
+        Ok(())
+    }
+
+    fn append_commitment_point(
+        &mut self,
+        commitment_number: u64,
+        point: secp256k1::PublicKey,
+    ) -> Result<(), Error> {
+        self.update_commitment(commitment_number, Some(point), None)
+    }
+
+    fn append_commitment_secret(
+        &mut self,
+        commitment_number: u64,
+        secret: secp256k1::SecretKey,
+    ) -> Result<(), Error> {
+        self.update_commitment(commitment_number, None, Some(secret))
+    }
+
+    fn store_htlc(&mut self, htlc: &HtlcRecord) -> Result<(), Error> {
+        let tree = self.tree(HTLCS_TREE)?;
+        let mut key = vec![htlc.offered as u8];
+        key.extend_from_slice(&htlc.id.to_be_bytes());
+        Self::put(&tree, &key, htlc)?;
+        self.flush()
+    }
+
+    fn commitments(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = CommitmentRecord>>, Error> {
+        let commitments = Self::values(&self.tree(COMMITMENTS_TREE)?)?;
+        Ok(Box::new(commitments.into_iter()))
+    }
+
+    fn htlcs(&self) -> Result<Box<dyn Iterator<Item = HtlcRecord>>, Error> {
+        let htlcs = Self::values(&self.tree(HTLCS_TREE)?)?;
+        Ok(Box::new(htlcs.into_iter()))
+    }
+
+    fn channels(&self) -> Result<Vec<ChannelId>, Error> {
+        Self::list(&self.config.path)
+    }
+}
+
+impl KvDriver {
+    /// Lists ids of the channels which databases are kept in the directory
+    pub fn list(path: &Path) -> Result<Vec<ChannelId>, Error> {
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let mut channels = vec![];
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            if let Some(channel_id) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| ChannelId::from_str(name).ok())
+            {
+                channels.push(channel_id);
+            }
+        }
+        Ok(channels)
+    }
+}
+
+fn kv_error(err: sled::Error) -> Error {
+    Error::Other(format!("Key-value storage error: {}", err))
+}
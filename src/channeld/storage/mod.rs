@@ -14,6 +14,14 @@
 
 mod disk;
 mod driver;
+mod dump;
+mod kv;
+mod state;
 
 pub use disk::{DiskConfig, DiskDriver};
-pub use driver::Driver;
+pub use driver::{CommitmentRecord, Driver, HtlcRecord};
+pub use dump::ChannelDump;
+pub use kv::{KvConfig, KvDriver};
+pub use state::{
+    ChannelState, Closing, FundingCandidate, HtlcClaim, HtlcLockIn,
+};
@@ -0,0 +1,207 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+
+use bitcoin::{secp256k1, OutPoint, Script, Transaction};
+use internet2::NodeAddr;
+use lnp::payment::htlc::{HtlcKnown, HtlcSecret};
+use lnp::payment::{self, AssetsBalance, Lifecycle};
+use lnp::{message, ChannelId, TempChannelId};
+use wallet::{HashLock, HashPreimage, PubkeyScript};
+
+use super::super::limits::HtlcLimits;
+use super::super::shachain::ShachainStore;
+use crate::rpc::request::{ForwardingPolicy, ShortChannelId};
+
+/// Channel data which must survive restarts of the channel daemon
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct ChannelState {
+    pub channel_id: ChannelId,
+    pub temporary_channel_id: TempChannelId,
+    /// Local randomness mixed into the derivation of our channel keys, so
+    /// the temporary channel id chosen by the remote peer can't make us
+    /// reuse the keys
+    pub key_salt: [u8; 32],
+    pub state: Lifecycle,
+    pub funding_outpoint: OutPoint,
+    /// Bitcoin balances of the channel sides in millisatoshis
+    pub local_capacity: u64,
+    pub remote_capacity: u64,
+    pub commitment_number: u64,
+    pub remote_commitment_number: u64,
+    pub feerate_per_kw: u32,
+    pub anchors: bool,
+    pub static_remotekey: bool,
+    pub scid_alias: u64,
+    /// Short channel id alias assigned to the channel by the remote peer
+    pub remote_scid_alias: Option<u64>,
+    pub obscuring_factor: u64,
+
+    /// Remote signature for our current commitment transaction, which allows
+    /// us to close the channel unilaterally
+    pub remote_signature: Option<secp256k1::Signature>,
+
+    /// Our commitment transaction matching the remote signature
+    pub local_commitment: Option<Transaction>,
+
+    /// Commitment transaction published on unilateral close, which outputs
+    /// are not swept yet
+    pub force_closing: Option<Transaction>,
+
+    /// Second-stage transactions for the HTLC outputs of our current
+    /// commitment, signed by the remote peer
+    pub htlc_claims: Vec<HtlcClaim>,
+
+    /// Per-commitment point for the next remote commitment transaction
+    pub remote_per_commitment_point: Option<secp256k1::PublicKey>,
+
+    /// Per-commitment point of the remote commitment transaction revoked by
+    /// the next `revoke_and_ack`
+    pub remote_revoked_point: Option<secp256k1::PublicKey>,
+
+    /// Confirmations of the funding transaction required to lock the channel
+    pub minimum_depth: u32,
+
+    /// Chain height at which we have started to await confirmation of the
+    /// funding transaction published by the remote peer
+    pub funding_height: Option<u32>,
+
+    /// Replaced funding transactions, any of which still may get mined
+    /// instead of the current one
+    pub funding_candidates: Vec<FundingCandidate>,
+
+    pub is_originator: bool,
+    pub remote_peer: Option<NodeAddr>,
+    pub params: payment::channel::Params,
+    pub local_keys: payment::channel::Keyset,
+    pub remote_keys: payment::channel::Keyset,
+    pub dust_limit_satoshis: u64,
+    pub local_limits: HtlcLimits,
+    pub remote_limits: HtlcLimits,
+    pub local_balances: AssetsBalance,
+    pub remote_balances: AssetsBalance,
+    pub total_payments: u64,
+    pub pending_payments: u16,
+
+    /// HTLCs in flight
+    pub offered_htlc: Vec<HtlcKnown>,
+    /// Payment hashes of the offered HTLCs which preimages are not known
+    pub offered_hashes: BTreeMap<u64, HashLock>,
+    pub received_htlc: Vec<HtlcSecret>,
+
+    /// Commitments which must be revoked before the received HTLCs are
+    /// irrevocably committed, indexed by HTLC id
+    pub received_lockin: BTreeMap<u64, HtlcLockIn>,
+
+    /// Resolutions of the received HTLCs awaiting for the HTLCs to be
+    /// irrevocably committed; `None` fails the HTLC
+    pub resolved_htlc: BTreeMap<u64, Option<HashPreimage>>,
+
+    /// Received HTLCs awaiting decisions of the plugins, which are asked
+    /// again once the channel is re-established
+    pub hooked_htlc: BTreeMap<u64, message::UpdateAddHtlc>,
+
+    /// Last commitment update messages sent to the remote peer, which are
+    /// retransmitted on channel re-establishment
+    pub last_commitment_signed: Option<message::CommitmentSigned>,
+    pub last_revoke_and_ack: Option<message::RevokeAndAck>,
+
+    /// Remote commitment transactions signed by us and not yet revoked,
+    /// which justice transactions are uploaded to the watchtowers once they
+    /// are revoked
+    pub remote_commitments: BTreeMap<u64, Transaction>,
+
+    /// Per-commitment secrets revealed by the remote peer, stored compactly
+    /// with shachain
+    pub remote_secrets: ShachainStore,
+
+    /// Scripts the peers have committed to close the channel to
+    pub upfront_shutdown_script: Option<PubkeyScript>,
+    pub remote_upfront_shutdown_script: Option<PubkeyScript>,
+
+    /// Script given out by the funding wallet for our funds on close
+    pub wallet_script: Option<PubkeyScript>,
+
+    /// Mutual close negotiation
+    pub closing: Option<Closing>,
+
+    /// Policy of forwarding payments over the channel
+    pub forwarding_policy: ForwardingPolicy,
+
+    /// Channel is restored from the static backup and awaits the remote
+    /// peer to close it
+    pub recovering: bool,
+
+    /// Channel is announced to the network once its funding transaction is
+    /// deep enough
+    pub public: bool,
+    pub short_channel_id: Option<ShortChannelId>,
+}
+
+/// Funding transaction replaced before the channel is locked, together with
+/// our signed commitment spending it
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct FundingCandidate {
+    pub funding_outpoint: OutPoint,
+    pub remote_signature: Option<secp256k1::Signature>,
+    pub local_commitment: Option<Transaction>,
+}
+
+/// Condition of the HTLC becoming irrevocably committed to both commitment
+/// transactions, after which it may be fulfilled or failed
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Default, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct HtlcLockIn {
+    /// Number of our first commitment containing the HTLC
+    pub local_commitment: u64,
+    /// Number of the last remote commitment not containing the HTLC, which
+    /// must be revoked
+    pub remote_revocation: u64,
+}
+
+/// HTLC-timeout or HTLC-success transaction claiming HTLC output of our
+/// commitment on unilateral close
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct HtlcClaim {
+    /// Transaction without witness; HTLC-timeout transaction is timelocked
+    /// until the HTLC expiry
+    pub tx: Transaction,
+    pub witness_script: Script,
+    /// Value of the spent HTLC output in satoshis
+    pub amount: u64,
+    pub payment_hash: HashLock,
+    /// Whether the HTLC is offered by us and is claimed after its expiry
+    pub offered: bool,
+    pub remote_signature: secp256k1::Signature,
+    /// Preimage of the received HTLC, once it is known
+    pub preimage: Option<HashPreimage>,
+    /// Whether the transaction is published and its output awaits maturity
+    pub published: bool,
+}
+
+/// Progress of the mutual close negotiation
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct Closing {
+    pub local_script: PubkeyScript,
+    pub remote_script: Option<PubkeyScript>,
+    /// Last closing fee we have signed
+    pub fee_proposed: Option<u64>,
+}
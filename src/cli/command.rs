@@ -13,11 +13,19 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
 
+use bitcoin::consensus::deserialize;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{OutPoint, Transaction};
 use internet2::{NodeAddr, RemoteSocketAddr, ToNodeAddr};
 use lnp::{message, ChannelId, LIGHTNING_P2P_DEFAULT_PORT};
+use lnpbp::chain::AssetId;
+use lnpbp::strict_encoding::{self, StrictDecode, StrictEncode};
 use microservices::shell::Exec;
+use wallet::HashLock;
 
 #[cfg(feature = "rgb")]
 use rgb::Consignment;
@@ -80,6 +88,290 @@ impl Exec for Command {
                 runtime.report_response()?;
             }
 
+            Command::Assets => {
+                runtime.request(ServiceId::Lnpd, Request::AssetBalances)?;
+                runtime.report_response()?;
+            }
+
+            Command::Daemons => {
+                runtime.request(ServiceId::Lnpd, Request::ListDaemons)?;
+                runtime.report_response()?;
+            }
+
+            Command::Towers => {
+                runtime.request(ServiceId::TowerClient, Request::ListTowers)?;
+                runtime.report_response()?;
+            }
+
+            Command::LspInfo => {
+                runtime.request(ServiceId::Lsp, Request::LspGetInfo)?;
+                runtime.report_response()?;
+            }
+
+            Command::BuyChannel { amount, zero_conf } => {
+                runtime.request(
+                    ServiceId::Lsp,
+                    Request::LspBuyChannel(request::LspOrderRequest {
+                        lsp_balance_sat: *amount,
+                        zero_conf: *zero_conf,
+                    }),
+                )?;
+                runtime.report_response()?;
+            }
+
+            Command::JitBuy { payment_hash } => {
+                runtime.request(
+                    ServiceId::Lsp,
+                    Request::LspJitBuy(HashLock::from(*payment_hash)),
+                )?;
+                runtime.report_response()?;
+            }
+
+            Command::LspOrders => {
+                runtime.request(ServiceId::Lsp, Request::LspListOrders)?;
+                runtime.report_response()?;
+            }
+
+            Command::Nodes => {
+                runtime.request(ServiceId::Gossip, Request::ListNodes)?;
+                runtime.report_response()?;
+            }
+
+            Command::Graph { node, active } => {
+                runtime.request(
+                    ServiceId::Gossip,
+                    Request::DescribeGraph(request::GraphFilter {
+                        node_id: *node,
+                        active_only: *active,
+                    }),
+                )?;
+                runtime.report_response()?;
+            }
+
+            Command::Leases => {
+                runtime.request(ServiceId::Gossip, Request::ListLeases)?;
+                runtime.report_response()?;
+            }
+
+            Command::QuoteLease {
+                node_id,
+                amount,
+                feerate,
+            } => {
+                runtime.request(
+                    ServiceId::Gossip,
+                    Request::QuoteLease(request::LeaseRequest {
+                        node_id: *node_id,
+                        amount_sat: *amount,
+                        feerate_per_kw: *feerate,
+                    }),
+                )?;
+                runtime.report_response()?;
+            }
+
+            Command::SwapIn { channel, amount }
+            | Command::SwapOut { channel, amount } => {
+                let direction = match self {
+                    Command::SwapIn { .. } => request::SwapDirection::In,
+                    _ => request::SwapDirection::Out,
+                };
+                runtime.request(
+                    ServiceId::Swap,
+                    Request::SwapStart(request::SwapRequest {
+                        direction,
+                        amount_sat: *amount,
+                        channel_id: *channel,
+                    }),
+                )?;
+                runtime.report_response()?;
+            }
+
+            Command::Swaps => {
+                runtime.request(ServiceId::Swap, Request::ListSwaps)?;
+                runtime.report_response()?;
+            }
+
+            Command::SwapRefund { payment_hash } => {
+                runtime.request(
+                    ServiceId::Swap,
+                    Request::SwapRefund(*payment_hash),
+                )?;
+                runtime.report_progress()?;
+            }
+
+            Command::Webhooks => {
+                runtime
+                    .request(ServiceId::Lnpd, Request::ListWebhookDeliveries)?;
+                runtime.report_response()?;
+            }
+
+            Command::Accounting {
+                beancount,
+                since,
+                till,
+                output,
+            } => {
+                runtime.request(
+                    ServiceId::Lnpd,
+                    Request::ExportAccounting(request::ExportAccounting {
+                        format: if *beancount {
+                            request::AccountingFormat::Beancount
+                        } else {
+                            request::AccountingFormat::Csv
+                        },
+                        since: *since,
+                        till: *till,
+                    }),
+                )?;
+                match runtime.report_failure()? {
+                    Request::AccountingReport(report) => match output {
+                        Some(file) => {
+                            fs::write(file, report)?;
+                            println!(
+                                "{} {}",
+                                "Accounting records exported to".ended(),
+                                file.display()
+                            );
+                        }
+                        None => print!("{}", report),
+                    },
+                    other => Err(Error::Other(format!(
+                        "Unexpected server response {}",
+                        other
+                    )))?,
+                }
+            }
+
+            Command::ChainInfo => {
+                runtime.request(ServiceId::Chain, Request::GetChainInfo)?;
+                runtime.report_response()?;
+            }
+
+            Command::Wallet => {
+                runtime.request(ServiceId::Funding, Request::GetWalletInfo)?;
+                runtime.report_response()?;
+            }
+
+            Command::Mine { blocks } => {
+                runtime
+                    .request(ServiceId::Chain, Request::MineBlocks(*blocks))?;
+                runtime.report_progress()?;
+            }
+
+            Command::Reorg { depth, evict } => {
+                runtime.request(
+                    ServiceId::Chain,
+                    Request::ReorgChain(request::ChainReorg {
+                        depth: *depth,
+                        evict: *evict,
+                    }),
+                )?;
+                runtime.report_progress()?;
+            }
+
+            Command::SetFee { feerate } => {
+                runtime.request(
+                    ServiceId::Chain,
+                    Request::SetFeeEstimate(*feerate),
+                )?;
+                runtime.report_progress()?;
+            }
+
+            Command::Export {
+                file,
+                include_secrets,
+                full,
+            } => {
+                let export = request::ExportNode {
+                    include_secrets: *include_secrets,
+                };
+                runtime.request(
+                    ServiceId::Lnpd,
+                    if *full {
+                        Request::ExportState(export)
+                    } else {
+                        Request::ExportNode(export)
+                    },
+                )?;
+                let mut options = fs::OpenOptions::new();
+                options.write(true).create(true).truncate(true);
+                // Archive may contain node key, channel secrets and invoice
+                // preimages and must not be readable by other users
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let res = match runtime.report_failure()? {
+                    Request::NodeArchive(archive) => {
+                        archive.strict_encode(options.open(file)?)
+                    }
+                    Request::NodeState(state) => {
+                        state.strict_encode(options.open(file)?)
+                    }
+                    other => Err(Error::Other(format!(
+                        "Unexpected server response {}",
+                        other
+                    )))?,
+                };
+                res.map_err(|err| {
+                    Error::Other(format!(
+                        "Unable to save node archive: {}",
+                        err
+                    ))
+                })?;
+                println!(
+                    "{} {}",
+                    "Node state exported to".ended(),
+                    file.display()
+                );
+            }
+
+            Command::Import { file, full } => {
+                let fd = fs::File::open(file)?;
+                let corrupted = |err: strict_encoding::Error| {
+                    Error::Other(format!(
+                        "Node archive file is corrupted: {}",
+                        err
+                    ))
+                };
+                let request = if *full {
+                    Request::ImportState(
+                        request::NodeState::strict_decode(fd)
+                            .map_err(corrupted)?,
+                    )
+                } else {
+                    Request::ImportNode(
+                        request::NodeArchive::strict_decode(fd)
+                            .map_err(corrupted)?,
+                    )
+                };
+                runtime.request(ServiceId::Lnpd, request)?;
+                runtime.report_progress()?;
+            }
+
+            Command::Backup { file } => {
+                runtime.request(ServiceId::Lnpd, Request::ExportBackup)?;
+                match runtime.report_failure()? {
+                    Request::StaticBackup(blob) => {
+                        fs::write(file, blob)?;
+                        println!(
+                            "{} {}",
+                            "Static channel backup saved to".ended(),
+                            file.display()
+                        );
+                    }
+                    other => Err(Error::Other(format!(
+                        "Unexpected server response {}",
+                        other
+                    )))?,
+                }
+            }
+
+            Command::Recover { file } => {
+                let blob = fs::read(file)?;
+                runtime
+                    .request(ServiceId::Lnpd, Request::RestoreBackup(blob))?;
+                runtime.report_progress()?;
+            }
+
             Command::Listen {
                 ip_addr,
                 port,
@@ -100,6 +392,46 @@ impl Exec for Command {
                 runtime.report_progress()?;
             }
 
+            Command::Disconnect { node_id } => {
+                runtime.request(
+                    ServiceId::Lnpd,
+                    Request::DisconnectPeer(*node_id),
+                )?;
+                runtime.report_progress()?;
+            }
+
+            Command::PeerAccess {
+                allow,
+                deny,
+                remove,
+                nodes,
+                ip_ranges,
+                allowlist_only,
+            } => {
+                let has_entries = !nodes.is_empty() || !ip_ranges.is_empty();
+                if has_entries && !allow && !deny {
+                    return Err(Error::Other(s!(
+                        "Either `--allow` or `--deny` list must be specified"
+                    )));
+                }
+                if has_entries || allowlist_only.is_some() {
+                    runtime.request(
+                        ServiceId::Lnpd,
+                        Request::UpdatePeerAccess(request::PeerAccessUpdate {
+                            allow: *allow,
+                            remove: *remove,
+                            nodes: nodes.clone(),
+                            ip_ranges: ip_ranges.clone(),
+                            allowlist_only: *allowlist_only,
+                        }),
+                    )?;
+                    runtime.report_progress()?;
+                } else {
+                    runtime.request(ServiceId::Lnpd, Request::GetPeerAccess)?;
+                    runtime.report_response()?;
+                }
+            }
+
             Command::Ping { peer } => {
                 let node_addr = peer
                     .to_node_addr(LIGHTNING_P2P_DEFAULT_PORT)
@@ -112,6 +444,9 @@ impl Exec for Command {
             Command::Propose {
                 peer,
                 funding_satoshis,
+                shutdown_address,
+                wallet,
+                private,
             } => {
                 let node_addr = peer
                     .to_node_addr(LIGHTNING_P2P_DEFAULT_PORT)
@@ -128,9 +463,22 @@ impl Exec for Command {
                         },
                         peerd: ServiceId::Peer(node_addr),
                         report_to: Some(runtime.identity()),
+                        shutdown_scriptpubkey: shutdown_address
+                            .as_ref()
+                            .map(|address| address.script_pubkey().into()),
+                        fund_from_wallet: *wallet,
+                        minimum_depth: None,
+                        funding_batch: None,
+                        private: *private,
+                        remote_tlvs: empty!(),
                     }),
                 )?;
                 runtime.report_progress()?;
+                if *wallet {
+                    // Channel is funded from the wallet once the progress
+                    // reporting succeeds
+                    return Ok(());
+                }
                 match runtime.response()? {
                     Request::ChannelFunding(pubkey_script) => {
                         let address =
@@ -176,10 +524,79 @@ impl Exec for Command {
                 }
             }
 
+            Command::ProposeBatch {
+                peer,
+                funding_satoshis,
+                shutdown_address,
+                private,
+            } => {
+                let node_addr = peer
+                    .to_node_addr(LIGHTNING_P2P_DEFAULT_PORT)
+                    .expect("Provided node address is invalid");
+
+                runtime.request(
+                    ServiceId::Lnpd,
+                    Request::OpenChannelBatch(request::ChannelBatch {
+                        channel_reqs: funding_satoshis
+                            .iter()
+                            .map(|funding_satoshis| message::OpenChannel {
+                                funding_satoshis: *funding_satoshis,
+                                // The rest of parameters will be filled in by
+                                // the daemon
+                                ..dumb!()
+                            })
+                            .collect(),
+                        peerd: ServiceId::Peer(node_addr),
+                        report_to: Some(runtime.identity()),
+                        shutdown_scriptpubkey: shutdown_address
+                            .as_ref()
+                            .map(|address| address.script_pubkey().into()),
+                        private: *private,
+                    }),
+                )?;
+                runtime.report_progress()?;
+            }
+
+            #[cfg(feature = "rgb")]
             Command::Fund {
                 channel,
                 funding_outpoint,
+                funding_tx,
+                consignment: Some(consignment),
+                blinding_factor: Some(blinding_factor),
             } => {
+                if let Some(path) = funding_tx {
+                    provide_funding_tx(runtime, path, funding_outpoint)?;
+                }
+                trace!("Reading consignment from file {:?}", &consignment);
+                let consignment = Consignment::read_file(consignment.clone())
+                    .map_err(|err| {
+                    Error::Other(format!(
+                        "Error in consignment encoding: {}",
+                        err
+                    ))
+                })?;
+
+                runtime.request(
+                    channel.clone().into(),
+                    Request::FundChannelWithAssets(request::AssetFunding {
+                        funding_outpoint: *funding_outpoint,
+                        consignment,
+                        blinding: *blinding_factor,
+                    }),
+                )?;
+                runtime.report_progress()?;
+            }
+
+            Command::Fund {
+                channel,
+                funding_outpoint,
+                funding_tx,
+                ..
+            } => {
+                if let Some(path) = funding_tx {
+                    provide_funding_tx(runtime, path, funding_outpoint)?;
+                }
                 runtime.request(
                     channel.clone().into(),
                     Request::FundChannel(*funding_outpoint),
@@ -187,10 +604,30 @@ impl Exec for Command {
                 runtime.report_progress()?;
             }
 
+            Command::Abort { channel } => {
+                runtime.request(
+                    ServiceId::Lnpd,
+                    Request::AbortChannel(channel.clone().into()),
+                )?;
+                runtime.report_progress()?;
+            }
+
+            Command::ReplaceFunding {
+                channel,
+                funding_outpoint,
+            } => {
+                runtime.request(
+                    channel.clone().into(),
+                    Request::ReplaceFunding(*funding_outpoint),
+                )?;
+                runtime.report_progress()?;
+            }
+
             Command::Transfer {
                 channel,
                 amount,
                 asset,
+                payment_hash,
             } => {
                 runtime.request(
                     channel.clone().into(),
@@ -198,11 +635,59 @@ impl Exec for Command {
                         channeld: channel.clone().into(),
                         amount: *amount,
                         asset: asset.map(|id| id.into()),
+                        payment_hash: payment_hash.map(HashLock::from),
                     }),
                 )?;
                 runtime.report_progress()?;
             }
 
+            Command::SetPolicy {
+                channel,
+                base_fee,
+                fee_rate,
+                cltv_delta,
+                htlc_min,
+                htlc_max,
+            } => {
+                runtime.request(
+                    channel.clone().into(),
+                    Request::SetChannelPolicy(request::ForwardingPolicy {
+                        fee_base_msat: *base_fee,
+                        fee_proportional_millionths: *fee_rate,
+                        cltv_expiry_delta: *cltv_delta,
+                        htlc_minimum_msat: *htlc_min,
+                        htlc_maximum_msat: *htlc_max,
+                    }),
+                )?;
+                runtime.report_progress()?;
+            }
+
+            Command::Close {
+                channel,
+                address,
+                force,
+            } => {
+                let request = if *force {
+                    Request::ForceCloseChannel
+                } else {
+                    Request::CloseChannel(request::CloseChannel {
+                        scriptpubkey: address
+                            .as_ref()
+                            .map(|address| address.script_pubkey().into()),
+                    })
+                };
+                runtime.request(channel.clone().into(), request)?;
+                runtime.report_progress()?;
+            }
+
+            Command::UpdateFee { channel, feerate } => {
+                runtime.request(
+                    channel.clone().into(),
+                    Request::UpdateChannelFee(*feerate),
+                )?;
+                runtime.report_progress()?;
+            }
+
             #[cfg(feature = "rgb")]
             Command::Refill {
                 channel,
@@ -231,8 +716,59 @@ impl Exec for Command {
                 runtime.report_progress()?;
             }
 
+            Command::Invoice {
+                amount,
+                asset,
+                expiry,
+            } => {
+                let asset_id = if asset.to_lowercase() == "btc" {
+                    None
+                } else {
+                    Some(AssetId::from_str(asset).map_err(|_| {
+                        Error::Other(format!("Invalid asset id {}", asset))
+                    })?)
+                };
+                runtime.request(
+                    ServiceId::Lnpd,
+                    Request::CreateInvoice(request::InvoiceRequest {
+                        amount_msat: *amount,
+                        asset_id,
+                        expiry: *expiry,
+                    }),
+                )?;
+                runtime.report_response()?;
+            }
+
             _ => unimplemented!(),
         }
         Ok(())
     }
 }
+
+/// Passes signed funding transaction to lnpd, which publishes it once the
+/// channel is funded
+fn provide_funding_tx(
+    runtime: &mut Client,
+    path: &Path,
+    funding_outpoint: &OutPoint,
+) -> Result<(), Error> {
+    let hex = fs::read_to_string(path)?;
+    let tx: Transaction = Vec::from_hex(hex.trim())
+        .map_err(|err| err.to_string())
+        .and_then(|data| deserialize(&data).map_err(|err| err.to_string()))
+        .map_err(|err| {
+            Error::Other(format!("Invalid funding transaction: {}", err))
+        })?;
+    if tx.txid() != funding_outpoint.txid
+        || tx.output.len() <= funding_outpoint.vout as usize
+    {
+        return Err(Error::Other(format!(
+            "Funding transaction {} does not contain output {}",
+            tx.txid(),
+            funding_outpoint
+        )));
+    }
+    runtime.request(ServiceId::Lnpd, Request::FundingTransaction(tx))?;
+    runtime.report_progress()?;
+    Ok(())
+}
@@ -17,12 +17,15 @@ use std::net::IpAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 
-use bitcoin::OutPoint;
+use bitcoin::hashes::sha256;
+use bitcoin::{secp256k1, Address, OutPoint};
 use internet2::{FramingProtocol, PartialNodeAddr};
 use lnp::{ChannelId, TempChannelId};
 #[cfg(feature = "rgb")]
 use rgb::ContractId;
 
+use crate::rpc::request::IpRange;
+
 /// Command-line tool for working with LNP node
 #[derive(Clap, Clone, PartialEq, Eq, Debug)]
 #[clap(
@@ -75,6 +78,43 @@ pub enum Command {
         peer: PartialNodeAddr,
     },
 
+    /// Disconnect from the remote lightning network peer
+    Disconnect {
+        /// Node id of the remote peer
+        node_id: secp256k1::PublicKey,
+    },
+
+    /// Shows or modifies access lists of remote peers
+    ///
+    /// Without node ids, IP ranges or `--allowlist-only` shows the current
+    /// lists.
+    PeerAccess {
+        /// Modify the allowlist
+        #[clap(long, conflicts_with = "deny")]
+        allow: bool,
+
+        /// Modify the denylist
+        #[clap(long)]
+        deny: bool,
+
+        /// Remove the entries from the list instead of adding them
+        #[clap(long)]
+        remove: bool,
+
+        /// Node id of a remote peer; can be used multiple times
+        #[clap(long = "node")]
+        nodes: Vec<secp256k1::PublicKey>,
+
+        /// IP address range in `<ip>[/<prefix>]` format; can be used
+        /// multiple times
+        #[clap(long = "ip")]
+        ip_ranges: Vec<IpRange>,
+
+        /// Switch allowlist-only mode on or off
+        #[clap(long)]
+        allowlist_only: Option<bool>,
+    },
+
     /// Ping remote peer (must be already connected)
     Ping {
         /// Address of the remote node, in
@@ -83,6 +123,7 @@ pub enum Command {
     },
 
     /// General information about the running node
+    #[clap(alias = "getinfo")]
     Info {
         /// Remote peer address or temporary/permanent/short channel id. If
         /// absent, returns information about the node itself
@@ -103,15 +144,213 @@ pub enum Command {
     /// Lists existing peer connections
     Peers,
 
+    /// Lists daemons registered with the node and whether they respond to
+    /// heartbeats
+    Daemons,
+
+    /// Lists watchtowers used by the node and their synchronization status
+    Towers,
+
+    /// Shows terms under which the lightning service provider sells channels
+    LspInfo,
+
+    /// Orders an inbound channel from the lightning service provider
+    ///
+    /// The provider opens the channel once its invoice for the order fee is
+    /// paid with `transfer --payment-hash`
+    BuyChannel {
+        /// Channel capacity funded by the provider, in satoshis
+        amount: u64,
+
+        /// Order a channel which is usable before the funding transaction
+        /// is confirmed
+        #[clap(long)]
+        zero_conf: bool,
+    },
+
+    /// Requests a just-in-time channel from the lightning service provider
+    ///
+    /// The provider returns a short channel id alias; payment of the invoice
+    /// routed to it triggers opening of a new zero-conf channel to this node,
+    /// through which the payment is forwarded
+    JitBuy {
+        /// Payment hash of the invoice paid through the channel
+        payment_hash: sha256::Hash,
+    },
+
+    /// Lists channel orders sold or bought by the lightning service provider
+    /// daemon
+    LspOrders,
+
+    /// Lists remote nodes known from gossip
+    Nodes,
+
+    /// Describes nodes and channels of the network graph known from gossip
+    Graph {
+        /// Describe only the channels of the given node
+        #[clap(long)]
+        node: Option<secp256k1::PublicKey>,
+
+        /// Skip channels disabled in both directions
+        #[clap(long)]
+        active: bool,
+    },
+
+    /// Lists remote nodes advertising liquidity for lease
+    Leases,
+
+    /// Computes the fee for leasing liquidity from a remote node
+    QuoteLease {
+        /// Public key of the node advertising liquidity
+        node_id: secp256k1::PublicKey,
+
+        /// Amount of satoshis to lease
+        amount: u64,
+
+        /// Fee rate of the funding transaction, in satoshis per 1000 weight
+        /// units
+        #[clap(long, default_value = "253")]
+        feerate: u32,
+    },
+
+    /// Swaps on-chain funds for an off-chain payment, refilling outbound
+    /// channel liquidity
+    SwapIn {
+        /// Channel with the swap provider carrying the off-chain payment
+        channel: ChannelId,
+
+        /// Amount to swap, in satoshis
+        amount: u64,
+    },
+
+    /// Swaps an off-chain payment for on-chain funds, refilling inbound
+    /// channel liquidity
+    SwapOut {
+        /// Channel with the swap provider carrying the off-chain payment
+        channel: ChannelId,
+
+        /// Amount to swap, in satoshis
+        amount: u64,
+    },
+
+    /// Lists submarine swaps
+    Swaps,
+
+    /// Refunds a funded submarine swap after its timeout
+    SwapRefund {
+        /// Payment hash identifying the swap
+        payment_hash: sha256::Hash,
+    },
+
+    /// Exports node state into an archive file for host migration or cold
+    /// backup
+    Export {
+        /// File to save the node archive to
+        file: PathBuf,
+
+        /// Include node private key into the archive
+        #[clap(long)]
+        include_secrets: bool,
+
+        /// Include state of the channels, gossip data and invoices gathered
+        /// from all node daemons, allowing to migrate the node to another
+        /// machine
+        #[clap(long)]
+        full: bool,
+    },
+
+    /// Imports node state from an archive file created with `export` command
+    Import {
+        /// File containing node archive
+        file: PathBuf,
+
+        /// Archive contains full node state, exported with `export --full`
+        #[clap(long)]
+        full: bool,
+    },
+
+    /// Exports encrypted static backup of the open channels, which allows to
+    /// recover channel funds after the loss of the node data
+    Backup {
+        /// File to save the static channel backup to
+        file: PathBuf,
+    },
+
+    /// Recovers funds from the channels listed in a static channel backup by
+    /// asking remote peers to close the channels
+    Recover {
+        /// File containing static channel backup
+        file: PathBuf,
+    },
+
+    /// Lists deliveries of node event notifications to webhook endpoints
+    Webhooks,
+
+    /// Exports bookkeeping records of the node payments, channel fundings
+    /// and on-chain fees
+    Accounting {
+        /// Export records as beancount ledger instead of CSV
+        #[clap(long)]
+        beancount: bool,
+
+        /// Export only records made at or after this UNIX timestamp
+        #[clap(long)]
+        since: Option<u64>,
+
+        /// Export only records made before this UNIX timestamp
+        #[clap(long)]
+        till: Option<u64>,
+
+        /// File to save the records to; if omitted, the records are printed
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Shows chain height, mempool size and fee estimate known to the chain
+    /// daemon
+    ChainInfo,
+
+    /// Shows deposit address and balance of the funding wallet
+    Wallet,
+
+    /// Mines blocks on the simulated chain, warping chain time forward by ten
+    /// minutes per block
+    Mine {
+        /// Number of blocks to mine
+        #[clap(default_value = "1")]
+        blocks: u32,
+    },
+
+    /// Replaces top blocks of the simulated chain with a longer chain
+    Reorg {
+        /// Number of blocks disconnected from the chain tip
+        depth: u32,
+
+        /// Drop transactions from the disconnected blocks instead of mining
+        /// them again
+        #[clap(long)]
+        evict: bool,
+    },
+
+    /// Sets fee rate estimate reported by the simulated chain
+    SetFee {
+        /// Fee rate, in satoshis per 1000 weight units
+        feerate: u32,
+    },
+
     /// Lists existing channels
     Channels,
 
+    /// Lists balances of the assets in the channels, in total and per
+    /// channel
+    Assets,
+
     /// Proposes a new channel to the remote peer, which must be already
     /// connected.
     ///
     /// Bitcoins will be added after the channel acceptance with `fund`
-    /// command. RGB assets are added to the channel later with `refill``
-    /// command
+    /// command, unless `--wallet` flag is given. RGB assets are added to the
+    /// channel later with `refill`` command
     Propose {
         /// Address of the remote node, in
         /// '<public_key>@<ipv4>|<ipv6>|<onionv2>|<onionv3>[:<port>]' format
@@ -121,6 +360,43 @@ pub enum Command {
         /// allocation will happen later using `fund` command after the
         /// channel acceptance)
         funding_satoshis: u64,
+
+        /// Address receiving our funds on cooperative close. It is committed
+        /// to the channel, so the channel can't be closed to any other
+        /// address
+        #[clap(long)]
+        shutdown_address: Option<Address>,
+
+        /// Fund the channel from the node funding wallet, which must be
+        /// running
+        #[clap(long)]
+        wallet: bool,
+
+        /// Do not announce the channel to the network
+        #[clap(long)]
+        private: bool,
+    },
+
+    /// Proposes multiple channels to the remote peer, which must be already
+    /// connected, funding all of them from the node funding wallet with a
+    /// single transaction
+    ProposeBatch {
+        /// Address of the remote node, in
+        /// '<public_key>@<ipv4>|<ipv6>|<onionv2>|<onionv3>[:<port>]' format
+        peer: PartialNodeAddr,
+
+        /// Amounts of satoshis to allocate to each of the channels
+        #[clap(required = true)]
+        funding_satoshis: Vec<u64>,
+
+        /// Address receiving our funds on cooperative close of each of the
+        /// channels
+        #[clap(long)]
+        shutdown_address: Option<Address>,
+
+        /// Do not announce the channels to the network
+        #[clap(long)]
+        private: bool,
     },
 
     /// Fund new channel (which must be already accepted by the remote peer)
@@ -133,6 +409,42 @@ pub enum Command {
         /// channel funding. Output `scriptPubkey` must be equal to the one
         /// provided by the `propose` command.
         funding_outpoint: OutPoint,
+
+        /// File with hex-encoded signed funding transaction, which will be
+        /// published by the node once the remote peer signs the first
+        /// commitment. If omitted, the transaction must be published by the
+        /// user.
+        #[clap(long)]
+        funding_tx: Option<PathBuf>,
+
+        /// Consignment file assigning RGB20 assets to the funding outpoint,
+        /// if the channel must be funded with assets
+        #[cfg(feature = "rgb")]
+        #[clap(long, requires = "blinding-factor")]
+        consignment: Option<PathBuf>,
+
+        /// Funding outpoint blinding factor used in the asset transfer
+        #[cfg(feature = "rgb")]
+        #[clap(long, requires = "consignment")]
+        blinding_factor: Option<u64>,
+    },
+
+    /// Cancels opening of the channel which is not funded yet
+    Abort {
+        /// Channel which opening must be cancelled
+        channel: TempChannelId,
+    },
+
+    /// Replaces funding transaction of the channel which is not locked yet,
+    /// i.e. for bumping its fee
+    ReplaceFunding {
+        /// Funded channel, which must be originated by the local node
+        channel: TempChannelId,
+
+        /// Outpoint (in form of <txid>:<output_no>) of the replacement
+        /// funding transaction. Output `scriptPubkey` and amount must be
+        /// equal to the ones of the replaced funding output.
+        funding_outpoint: OutPoint,
     },
 
     /// Adds RGB assets to an existing channel
@@ -166,6 +478,64 @@ pub enum Command {
         #[cfg(feature = "rgb")]
         #[clap(short, long)]
         asset: Option<ContractId>,
+
+        /// Payment hash of the invoice paid with the transfer
+        #[clap(long)]
+        payment_hash: Option<sha256::Hash>,
+    },
+
+    /// Sets policy of forwarding payments over the channel
+    SetPolicy {
+        /// Channel which policy is updated
+        channel: ChannelId,
+
+        /// Fixed fee charged for each forwarded payment, in millisatoshis
+        #[clap(long, default_value = "1000")]
+        base_fee: u32,
+
+        /// Proportional fee charged for each forwarded payment, in millionths
+        /// of the forwarded amount
+        #[clap(long, default_value = "1")]
+        fee_rate: u32,
+
+        /// Number of blocks subtracted from the expiry of the forwarded HTLCs
+        #[clap(long, default_value = "40")]
+        cltv_delta: u16,
+
+        /// Minimal amount of the forwarded HTLCs, in millisatoshis
+        #[clap(long, default_value = "1")]
+        htlc_min: u64,
+
+        /// Maximal amount of the forwarded HTLCs, in millisatoshis
+        #[clap(long)]
+        htlc_max: Option<u64>,
+    },
+
+    /// Closes the channel, sending our funds on-chain
+    Close {
+        /// Channel to close
+        channel: ChannelId,
+
+        /// Address receiving our funds; if omitted the funds are sent to the
+        /// node key
+        #[clap(long, conflicts_with = "force")]
+        address: Option<Address>,
+
+        /// Close the channel unilaterally by publishing our latest commitment
+        /// transaction. Our funds are swept to the node key after the
+        /// channel `to_self_delay` expires
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Updates feerate of the commitment transactions of a channel funded by
+    /// the node
+    UpdateFee {
+        /// Channel to update
+        channel: ChannelId,
+
+        /// Feerate in satoshis per kiloweight
+        feerate: u32,
     },
 
     /// Create an invoice
@@ -177,6 +547,10 @@ pub enum Command {
         /// Asset ticker in which the invoice should be issued
         #[clap(default_value = "btc")]
         asset: String,
+
+        /// Time, in seconds, during which the invoice may be paid
+        #[clap(long, default_value = "3600")]
+        expiry: u32,
     },
 
     /// Pay the invoice
@@ -12,11 +12,34 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use internet2::addr::InetSocketAddr;
 use internet2::NodeAddr;
 use lnpbp::Chain;
+use wallet::PubkeyScript;
+
+#[cfg(feature = "shell")]
+use crate::opts::{Opts, LNP_NODE_TOR_PROXY};
+use crate::rpc::request::ForwardingPolicy;
+
+/// Default subdirectory of the data directory with the key-value database
+#[cfg(feature = "shell")]
+const KV_STORAGE_DIR: &str = "kv";
+
+/// Name of the file inside the data directory with the remote peer access
+/// lists
+#[cfg(feature = "shell")]
+const PEER_ACCESS_FILE: &str = "peer_access.dat";
 
+/// Name of the file inside the data directory with the network graph
 #[cfg(feature = "shell")]
-use crate::opts::Opts;
+const GOSSIP_STORE_FILE: &str = "gossip_store.dat";
+
+/// Channel funding limit for peers which do not support large channels
+pub const MAX_FUNDING_SATOSHIS: u64 = 1 << 24;
 
 /// Final configuration resulting from data contained in config file environment
 /// variables and command-line options. For security reasons node key is kept
@@ -27,11 +50,168 @@ pub struct Config {
     /// Bitcoin blockchain to use (mainnet, testnet, signet, liquid etc)
     pub chain: Chain,
 
+    /// Human-readable name of the node
+    pub alias: Option<String>,
+
+    /// Color of the node announced to the network; if absent, it is derived
+    /// from the node id
+    pub rgb_color: Option<[u8; 3]>,
+
+    /// Addresses announced to the network for connecting to the node
+    pub announce_addrs: Vec<InetSocketAddr>,
+
     /// ZMQ socket for lightning peer network message bus
     pub msg_endpoint: NodeAddr,
 
     /// ZMQ socket for internal service control bus
     pub ctl_endpoint: NodeAddr,
+
+    /// Minimal feerate of commitment transactions, in satoshis per
+    /// kiloweight, which may be proposed by the remote peer
+    pub min_feerate_per_kw: u32,
+
+    /// Maximal feerate of commitment transactions, in satoshis per
+    /// kiloweight, which may be proposed by the remote peer
+    pub max_feerate_per_kw: u32,
+
+    /// Whether new channels use anchor outputs commitment format
+    pub anchors: bool,
+
+    /// Whether `to_remote` outputs of new channels pay to the static payment
+    /// basepoint of the remote peer
+    pub static_remotekey: bool,
+
+    /// Whether channels of `option_zeroconf` type funded by remote peers are
+    /// used before the funding transaction is confirmed
+    pub zero_conf: bool,
+
+    /// Whether the node supports large channels
+    pub wumbo: bool,
+
+    /// Time after which negotiation of an unfunded channel is aborted
+    pub negotiation_timeout: Duration,
+
+    /// Interval at which gossip messages queued for the remote peers are
+    /// sent
+    pub gossip_flush_interval: Duration,
+
+    /// Interval at which gossipd relays the validated gossip to the remote
+    /// peers
+    pub gossip_broadcast_interval: Duration,
+
+    /// Rate limits of the messages exchanged with each of the remote peers
+    pub throttle: ThrottleLimits,
+
+    /// Number of blocks after which channels funded by remote peers are
+    /// closed if the funding transaction is not confirmed
+    pub funding_timeout: u32,
+
+    /// Limits for parameters of the channels negotiated with remote peers
+    pub policy: ChannelPolicy,
+
+    /// Parameters proposed to remote peers for the channels opened by the
+    /// node
+    pub channel_params: LocalChannelParams,
+
+    /// Upfront shutdown script of new channels, unless a specific one is
+    /// requested for the channel
+    pub shutdown_script: Option<PubkeyScript>,
+
+    /// Directory of the embedded key-value database keeping channel state;
+    /// if absent, channel state is kept in plain files
+    pub kv_storage: Option<PathBuf>,
+
+    /// Forwarding policy of new channels
+    pub forwarding_policy: ForwardingPolicy,
+
+    /// SOCKS5 proxy of the Tor daemon used for all outbound connections with
+    /// remote peers, including the ones with onion addresses
+    pub tor_proxy: Option<SocketAddr>,
+
+    /// File with the access lists of remote peers; it is re-read for each
+    /// incoming connection, so the lists can be modified at runtime
+    pub peer_access: PathBuf,
+
+    /// File keeping the network graph known from gossip across gossipd
+    /// restarts
+    pub gossip_store: PathBuf,
+
+    /// Whether the daemon runs as a thread of the lnpd process rather than
+    /// as a separate process
+    pub threaded: bool,
+}
+
+/// Limits for parameters of the channels negotiated with remote peers
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChannelPolicy {
+    /// Range of the dust limits accepted from the remote peer
+    pub min_dust_limit: u64,
+    pub max_dust_limit: u64,
+
+    /// Maximal delay for spending our funds from force-closed channel
+    pub max_to_self_delay: u16,
+
+    /// Minimal funding amount of channels opened by remote peers
+    pub min_channel_size: u64,
+
+    /// Maximal funding amount of channels
+    pub max_channel_size: u64,
+
+    /// Channel reserve which we require, in percents of channel capacity
+    pub reserve_percent: u8,
+
+    /// Required number of funding confirmations grows by one from
+    /// `min_depth` for each `depth_step` satoshis, up to `max_depth`
+    pub min_depth: u32,
+    pub max_depth: u32,
+    pub depth_step: u64,
+}
+
+impl ChannelPolicy {
+    /// Number of funding transaction confirmations we require for a
+    /// channel with the given capacity
+    pub fn minimum_depth(&self, funding_satoshis: u64) -> u32 {
+        let extra = funding_satoshis / self.depth_step.max(1);
+        (self.min_depth as u64 + extra).min(self.max_depth as u64) as u32
+    }
+
+    /// Channel reserve we require for a channel with the given capacity
+    pub fn channel_reserve(&self, funding_satoshis: u64) -> u64 {
+        funding_satoshis * self.reserve_percent as u64 / 100
+    }
+}
+
+/// Parameters proposed to remote peers for the channels opened by the node.
+/// Channel reserve follows [`ChannelPolicy::reserve_percent`] and minimal
+/// HTLC amount follows [`ForwardingPolicy::htlc_minimum_msat`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LocalChannelParams {
+    /// Initial feerate of commitment transactions, in satoshis per
+    /// kiloweight
+    pub feerate_per_kw: u32,
+
+    /// Dust limit of our commitment transactions
+    pub dust_limit_satoshis: u64,
+
+    /// Delay for spending remote funds from a channel force-closed by the
+    /// remote peer
+    pub to_self_delay: u16,
+
+    /// Maximal number of HTLCs the remote peer may offer to us
+    pub max_accepted_htlcs: u16,
+}
+
+/// Rate limits of the messages exchanged with each of the remote peers, in
+/// messages per minute; zero disables the limit. Channel messages are never
+/// limited.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ThrottleLimits {
+    /// Gossip messages received from the peer over the limit are dropped,
+    /// while the ones sent to the peer are delayed
+    pub gossip_per_minute: u32,
+
+    /// Pings received from the peer over the limit are left without reply
+    pub pings_per_minute: u32,
 }
 
 #[cfg(feature = "shell")]
@@ -39,8 +219,79 @@ impl From<Opts> for Config {
     fn from(opts: Opts) -> Self {
         Config {
             chain: opts.chain,
+            alias: opts.alias,
+            rgb_color: opts.rgb_color,
+            announce_addrs: opts.announce_addrs,
             msg_endpoint: opts.msg_socket.into(),
             ctl_endpoint: opts.ctl_socket.into(),
+            min_feerate_per_kw: opts.min_feerate,
+            max_feerate_per_kw: opts.max_feerate,
+            anchors: opts.anchors,
+            // Anchor outputs format requires static remote key
+            static_remotekey: opts.anchors || !opts.no_static_remotekey,
+            zero_conf: opts.zero_conf,
+            wumbo: opts.wumbo,
+            negotiation_timeout: Duration::from_secs(opts.negotiation_timeout),
+            gossip_flush_interval: Duration::from_millis(
+                opts.gossip_flush_interval,
+            ),
+            gossip_broadcast_interval: Duration::from_secs(
+                opts.gossip_broadcast_interval,
+            ),
+            throttle: ThrottleLimits {
+                gossip_per_minute: opts.gossip_rate_limit,
+                pings_per_minute: opts.ping_rate_limit,
+            },
+            funding_timeout: opts.funding_timeout,
+            policy: ChannelPolicy {
+                min_dust_limit: opts.min_dust_limit,
+                max_dust_limit: opts.max_dust_limit,
+                max_to_self_delay: opts.max_to_self_delay,
+                min_channel_size: opts.min_channel_size,
+                max_channel_size: if opts.wumbo {
+                    opts.max_wumbo_size
+                } else {
+                    MAX_FUNDING_SATOSHIS - 1
+                },
+                reserve_percent: opts.channel_reserve,
+                min_depth: opts.min_depth,
+                max_depth: opts.max_depth,
+                depth_step: opts.depth_step,
+            },
+            channel_params: LocalChannelParams {
+                feerate_per_kw: opts
+                    .feerate
+                    .max(opts.min_feerate)
+                    .min(opts.max_feerate),
+                dust_limit_satoshis: opts.dust_limit,
+                to_self_delay: opts.to_self_delay,
+                max_accepted_htlcs: opts.max_accepted_htlcs,
+            },
+            shutdown_script: opts
+                .shutdown_address
+                .map(|address| address.script_pubkey().into()),
+            kv_storage: match opts.kv_storage {
+                Some(Some(path)) => Some(path),
+                Some(None) => Some(opts.data_dir.join(KV_STORAGE_DIR)),
+                None => None,
+            },
+            forwarding_policy: ForwardingPolicy {
+                fee_base_msat: opts.fee_base_msat,
+                fee_proportional_millionths: opts.fee_proportional_millionths,
+                cltv_expiry_delta: opts.cltv_expiry_delta,
+                htlc_minimum_msat: opts.htlc_minimum_msat,
+                htlc_maximum_msat: opts.htlc_maximum_msat,
+            },
+            tor_proxy: opts.tor_proxy.map(|proxy| {
+                proxy.unwrap_or_else(|| {
+                    LNP_NODE_TOR_PROXY
+                        .parse()
+                        .expect("default Tor proxy address is valid")
+                })
+            }),
+            peer_access: opts.data_dir.join(PEER_ACCESS_FILE),
+            gossip_store: opts.data_dir.join(GOSSIP_STORE_FILE),
+            threaded: false,
         }
     }
 }
@@ -21,6 +21,10 @@ use internet2::{presentation, transport};
 #[cfg(feature = "_rpc")]
 use microservices::{esb, rpc};
 
+#[cfg(feature = "node")]
+use crate::gossipd::GossipError;
+#[cfg(feature = "node")]
+use crate::peerd::NoiseError;
 #[cfg(feature = "_rpc")]
 use crate::rpc::ServiceBus;
 
@@ -46,6 +50,16 @@ pub enum Error {
     #[from]
     Peer(presentation::Error),
 
+    /// Handshake with the remote peer has failed: {0}
+    #[cfg(feature = "node")]
+    #[from]
+    Handshake(NoiseError),
+
+    /// Invalid gossip message: {0}
+    #[cfg(feature = "node")]
+    #[from]
+    Gossip(GossipError),
+
     /// Bridge interface error: {0}
     #[cfg(any(feature = "node", feature = "client"))]
     #[from(zmq::Error)]
@@ -65,6 +79,9 @@ pub enum Error {
     /// unrecoverable error "{0}"
     Terminate(String),
 
+    /// node limit of {1} {0} is reached
+    ResourceLimit(Resource, usize),
+
     /// Other error type with string explanation
     #[display(inner)]
     #[from(internet2::addr::NoOnionSupportError)]
@@ -73,6 +90,27 @@ pub enum Error {
 
 impl microservices::error::Error for Error {}
 
+/// Node resources which can be capped to protect low-memory deployments
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+pub enum Resource {
+    /// Channels run by channel daemons or being created
+    #[display("channels")]
+    Channels,
+
+    /// Connections with remote peers, including the ones being established
+    #[display("peer connections")]
+    Peers,
+
+    /// Connections accepted from remote peers
+    #[display("incoming peer connections")]
+    InboundPeers,
+
+    /// Channels which are negotiated with remote peers but do not yet have
+    /// their daemons running
+    #[display("pending channel negotiations")]
+    PendingChannels,
+}
+
 #[cfg(feature = "_rpc")]
 impl From<Error> for esb::Error {
     fn from(err: Error) -> Self {
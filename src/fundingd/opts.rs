@@ -0,0 +1,59 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use clap::{AppSettings, Clap};
+
+use crate::peerd::KeyOpts;
+
+/// Funding wallet daemon; part of LNP Node
+///
+/// The daemon tracks on-chain outputs of the wallet, which keys are derived
+/// from the node key, constructs and signs funding transactions of the
+/// channels opened by the node and publishes them once the channel
+/// commitment is signed by the remote peer. Funds are deposited to the wallet
+/// by sending them to the address reported by `wallet` command, which is
+/// replaced once it receives funds; channel closing and sweeping transactions
+/// pay to fresh wallet addresses.
+///
+/// The daemon is controlled though ZMQ ctl socket (see `ctl-socket` argument
+/// description)
+#[derive(Clap, Clone, PartialEq, Eq, Debug)]
+#[clap(
+    name = "fundingd",
+    bin_name = "fundingd",
+    author,
+    version,
+    setting = AppSettings::ColoredHelp
+)]
+pub struct Opts {
+    /// Node key configuration
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
+    /// RGB configuration: ignored by this daemon
+    #[clap(short, long = "rgb20-rpc")]
+    pub r: Option<String>,
+
+    /// These params can be read also from the configuration file, not just
+    /// command-line args or environment variables
+    #[clap(flatten)]
+    pub shared: crate::opts::Opts,
+}
+
+impl Opts {
+    pub fn process(&mut self) {
+        self.shared.process();
+        self.key_opts.process(&self.shared);
+    }
+}
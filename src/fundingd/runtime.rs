@@ -0,0 +1,580 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::PathBuf;
+
+use bitcoin::{Address, OutPoint, Transaction};
+use internet2::{LocalNode, TypedEnum};
+use lnpbp::strict_encoding::{StrictDecode, StrictEncode};
+use lnpbp::Chain;
+use microservices::esb;
+use microservices::rpc::Failure;
+
+use super::wallet::{wallet_master_key, Coin, Wallet};
+use crate::rpc::request::{
+    CpfpChild, FundingBatch, FundingRequest, WalletInfo,
+};
+use crate::rpc::{Request, ServiceBus};
+use crate::{Config, CtlServer, Error, LogStyle, Senders, Service, ServiceId};
+
+pub const FUNDING_DB_FILE: &'static str = "funding.dat";
+
+pub fn run(
+    config: Config,
+    local_node: LocalNode,
+    chain: Chain,
+    data_dir: PathBuf,
+) -> Result<(), Error> {
+    let db_path = data_dir.join(FUNDING_DB_FILE);
+    let db = if db_path.exists() {
+        debug!("Loading funding wallet database from {:?}", db_path);
+        WalletDb::strict_decode(fs::File::open(&db_path)?).map_err(|err| {
+            Error::Other(format!(
+                "Funding wallet database is corrupted: {}",
+                err
+            ))
+        })?
+    } else {
+        WalletDb::default()
+    };
+
+    let wallet = Wallet::with(
+        wallet_master_key(&local_node.private_key(), &chain),
+        [db.next_external, db.next_internal],
+        db.utxos
+            .into_iter()
+            .map(|utxo| {
+                let coin = Coin {
+                    value: utxo.value,
+                    keychain: utxo.keychain,
+                    index: utxo.index,
+                };
+                (utxo.outpoint, coin)
+            })
+            .collect(),
+    );
+    info!(
+        "Funding wallet has {} sat in {} output(s)",
+        wallet.balance(),
+        wallet.utxos().len()
+    );
+
+    let runtime = Runtime {
+        identity: ServiceId::Funding,
+        chain,
+        db_path,
+        wallet,
+        pending: none!(),
+        batches: none!(),
+        batch_members: none!(),
+    };
+
+    Service::run(config, runtime, false)
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct Utxo {
+    outpoint: OutPoint,
+    value: u64,
+    keychain: u32,
+    index: u32,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct WalletDb {
+    /// Indexes of the first unused addresses of the external and internal
+    /// keychains
+    next_external: u32,
+    next_internal: u32,
+    utxos: Vec<Utxo>,
+}
+
+/// Channels funded by a single batch transaction
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+struct PendingBatch {
+    /// Funding requests of the channel daemons, in the order of the funding
+    /// outputs
+    requests: Vec<(ServiceId, FundingRequest)>,
+    /// Signed transaction, constructed once all channels of the batch have
+    /// requested their funding
+    tx: Option<Transaction>,
+    /// Channel daemons which have received the remote signature for their
+    /// funding output
+    published: HashSet<ServiceId>,
+}
+
+pub struct Runtime {
+    identity: ServiceId,
+    chain: Chain,
+    db_path: PathBuf,
+    wallet: Wallet,
+    /// Signed funding transactions awaiting the channel commitment, indexed
+    /// by the channel daemon
+    pending: HashMap<ServiceId, Transaction>,
+    /// Batch funding transactions which are collecting funding requests or
+    /// awaiting commitments of all their channels
+    batches: HashMap<u64, PendingBatch>,
+    /// Batch ids indexed by the channel daemons participating in them
+    batch_members: HashMap<ServiceId, u64>,
+}
+
+impl CtlServer for Runtime {}
+
+impl esb::Handler<ServiceBus> for Runtime {
+    type Request = Request;
+    type Address = ServiceId;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId {
+        self.identity.clone()
+    }
+
+    fn handle(
+        &mut self,
+        senders: &mut Senders,
+        bus: ServiceBus,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Self::Error> {
+        match bus {
+            ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
+            _ => Err(Error::NotSupported(bus, request.get_type())),
+        }
+    }
+
+    fn handle_err(&mut self, _: esb::Error) -> Result<(), esb::Error> {
+        // We do nothing and do not propagate error; it's already being reported
+        // with `error!` macro by the controller. If we propagate error here
+        // this will make whole daemon panic
+        Ok(())
+    }
+}
+
+impl Runtime {
+    fn handle_rpc_ctl(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::Hello(_) => {
+                // Ignoring; this is used to set remote identity at ZMQ level
+            }
+
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
+            Request::ConstructFunding(funding_req) => {
+                debug!("{} requests funding of {}", source, funding_req);
+                if let Some(batch) = funding_req.batch {
+                    return self.batch_funding(
+                        senders,
+                        source,
+                        funding_req,
+                        batch,
+                    );
+                }
+                // Channel may ask for a new transaction if its funding is
+                // replaced
+                if let Some(tx) = self.pending.remove(&source) {
+                    self.wallet.release(&tx);
+                }
+                let mut tx = match self
+                    .wallet
+                    .construct(std::slice::from_ref(&funding_req))
+                {
+                    Ok(tx) => tx,
+                    Err(err) => {
+                        error!("{} {}", "Unable to fund channel:".err(), err);
+                        return Err(self.report_failure_to(
+                            senders,
+                            source,
+                            Failure {
+                                code: 0,
+                                info: err.to_string(),
+                            },
+                        ));
+                    }
+                };
+                self.wallet.sign(&mut tx);
+                let funding_outpoint = OutPoint::new(tx.txid(), 0);
+                info!(
+                    "{} {} for {} spending {} output(s)",
+                    "Constructed funding transaction".promo(),
+                    funding_outpoint.txid.promoter(),
+                    source,
+                    tx.input.len()
+                );
+                self.pending.insert(source.clone(), tx);
+                self.send_ctl(
+                    senders,
+                    source,
+                    Request::FundChannel(funding_outpoint),
+                )?;
+            }
+
+            Request::PublishFunding
+                if self.batch_members.contains_key(&source) =>
+            {
+                self.publish_batch(senders, source)?;
+            }
+
+            Request::PublishFunding => {
+                let tx = match self.pending.remove(&source) {
+                    Some(tx) => tx,
+                    None => {
+                        warn!(
+                            "{} requests publishing of unknown funding \
+                             transaction",
+                            source
+                        );
+                        return Ok(());
+                    }
+                };
+                // Change output becomes available before the transaction is
+                // mined, since it can't be double-spent by anybody else
+                self.wallet.process(&tx);
+                self.save()?;
+                info!(
+                    "{} {} for {}",
+                    "Publishing funding transaction".promo(),
+                    tx.txid().promoter(),
+                    source
+                );
+                self.send_ctl(
+                    senders,
+                    ServiceId::Chain,
+                    Request::BroadcastTransaction(tx),
+                )?;
+            }
+
+            Request::ReleaseFunding
+                if self.batch_members.contains_key(&source) =>
+            {
+                self.release_batch(&source);
+            }
+
+            Request::ReleaseFunding => {
+                if let Some(tx) = self.pending.remove(&source) {
+                    info!(
+                        "Funding transaction {} for {} is abandoned",
+                        tx.txid(),
+                        source
+                    );
+                    self.wallet.release(&tx);
+                }
+            }
+
+            Request::ConstructCpfp(cpfp_req) => {
+                debug!("{} requests CPFP of {}", source, cpfp_req);
+                let (mut tx, fee) = match self.wallet.construct_cpfp(&cpfp_req)
+                {
+                    Ok(child) => child,
+                    Err(err) => {
+                        error!("{} {}", "Unable to bump fee:".err(), err);
+                        return Err(self.report_failure_to(
+                            senders,
+                            source,
+                            Failure {
+                                code: 0,
+                                info: err.to_string(),
+                            },
+                        ));
+                    }
+                };
+                // Anchor input is signed by the channel; wallet outputs
+                // remain reserved until the child is mined
+                self.wallet.sign(&mut tx);
+                info!(
+                    "{} {} for {} paying {} sat fee",
+                    "Constructed CPFP transaction".promo(),
+                    tx.txid().promoter(),
+                    source,
+                    fee
+                );
+                self.send_ctl(
+                    senders,
+                    source,
+                    Request::SignCpfp(CpfpChild { tx, fee }),
+                )?;
+            }
+
+            Request::ChainTransactions(txs) => {
+                let mut changed = false;
+                for tx in &txs {
+                    changed |= self.wallet.process(tx);
+                }
+                if changed {
+                    self.save()?;
+                    info!(
+                        "Funding wallet balance is {} sat in {} output(s)",
+                        self.wallet.balance(),
+                        self.wallet.utxos().len()
+                    );
+                }
+            }
+
+            Request::GetWalletScript => {
+                let script = self.wallet.fresh_script();
+                // Address must not be given out again after restart
+                self.save()?;
+                debug!("Wallet script {} is given out to {}", script, source);
+                self.send_ctl(
+                    senders,
+                    source,
+                    Request::WalletScript(script.into()),
+                )?;
+            }
+
+            Request::FeeEstimate(_) => {
+                // Fee rate is provided by the channel in the funding request
+            }
+
+            Request::GetWalletInfo => {
+                let info = WalletInfo {
+                    address: bitcoin::Network::try_from(&self.chain)
+                        .ok()
+                        .and_then(|network| {
+                            Address::from_script(
+                                &self.wallet.deposit_script(),
+                                network,
+                            )
+                        })
+                        .map(|address| address.to_string()),
+                    balance: self.wallet.balance(),
+                    reserved: self.wallet.reserved(),
+                    utxos: self.wallet.utxos().len() as u32,
+                };
+                self.send_ctl(senders, source, Request::WalletInfo(info))?;
+            }
+
+            _ => {
+                error!("Request is not supported by the CTL interface");
+                return Err(Error::NotSupported(
+                    ServiceBus::Ctl,
+                    request.get_type(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn batch_funding(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        funding_req: FundingRequest,
+        batch: FundingBatch,
+    ) -> Result<(), Error> {
+        if self
+            .batches
+            .get(&batch.batch_id)
+            .map(|pending| pending.tx.is_some())
+            .unwrap_or_default()
+        {
+            // Replacing the transaction would invalidate funding outputs of
+            // the other channels in the batch
+            return Err(self.report_failure_to(
+                senders,
+                source,
+                Failure {
+                    code: 0,
+                    info: format!(
+                        "funding transaction of {} can't be replaced",
+                        batch
+                    ),
+                },
+            ));
+        }
+
+        self.batch_members.insert(source.clone(), batch.batch_id);
+        let pending = self.batches.entry(batch.batch_id).or_default();
+        pending.requests.retain(|(channeld, _)| *channeld != source);
+        pending.requests.push((source, funding_req));
+        if pending.requests.len() < batch.size as usize {
+            debug!(
+                "Funding of {} awaits requests from {} more channel(s)",
+                batch,
+                batch.size as usize - pending.requests.len()
+            );
+            return Ok(());
+        }
+
+        let requests = pending
+            .requests
+            .iter()
+            .map(|(_, funding_req)| funding_req.clone())
+            .collect::<Vec<_>>();
+        let mut tx = match self.wallet.construct(&requests) {
+            Ok(tx) => tx,
+            Err(err) => {
+                error!("{} {}", "Unable to fund channel batch:".err(), err);
+                let failed = self
+                    .batches
+                    .remove(&batch.batch_id)
+                    .map(|pending| pending.requests)
+                    .unwrap_or_default();
+                for (channeld, _) in failed {
+                    self.batch_members.remove(&channeld);
+                    // Failure report always returns `Error::Terminate`
+                    let _ = self.report_failure_to(
+                        senders,
+                        channeld,
+                        Failure {
+                            code: 0,
+                            info: err.to_string(),
+                        },
+                    );
+                }
+                return Ok(());
+            }
+        };
+        self.wallet.sign(&mut tx);
+        let txid = tx.txid();
+        info!(
+            "{} {} for {} spending {} output(s)",
+            "Constructed batch funding transaction".promo(),
+            txid.promoter(),
+            batch,
+            tx.input.len()
+        );
+        let channelds = pending
+            .requests
+            .iter()
+            .map(|(channeld, _)| channeld.clone())
+            .collect::<Vec<_>>();
+        pending.tx = Some(tx);
+        for (vout, channeld) in channelds.into_iter().enumerate() {
+            self.send_ctl(
+                senders,
+                channeld,
+                Request::FundChannel(OutPoint::new(txid, vout as u32)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Publishes batch funding transaction once all of its channels have
+    /// received the remote signatures for their first commitments
+    fn publish_batch(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+    ) -> Result<(), Error> {
+        let batch_id = match self.batch_members.get(&source) {
+            Some(batch_id) => *batch_id,
+            None => return Ok(()),
+        };
+        let pending = match self.batches.get_mut(&batch_id) {
+            Some(pending) if pending.tx.is_some() => pending,
+            _ => {
+                warn!(
+                    "{} requests publishing of batch funding transaction \
+                     which is not constructed yet",
+                    source
+                );
+                return Ok(());
+            }
+        };
+        pending.published.insert(source);
+        if pending.published.len() < pending.requests.len() {
+            debug!(
+                "Batch funding transaction awaits commitments of {} more \
+                 channel(s)",
+                pending.requests.len() - pending.published.len()
+            );
+            return Ok(());
+        }
+
+        let pending = self
+            .batches
+            .remove(&batch_id)
+            .expect("batch presence is checked above");
+        for (channeld, _) in &pending.requests {
+            self.batch_members.remove(channeld);
+        }
+        let tx = pending.tx.expect("batch transaction is checked above");
+        self.wallet.process(&tx);
+        self.save()?;
+        info!(
+            "{} {} for {} channel(s)",
+            "Publishing batch funding transaction".promo(),
+            tx.txid().promoter(),
+            pending.requests.len()
+        );
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(tx),
+        )?;
+        Ok(())
+    }
+
+    /// Abandons the whole batch: once any of its channels is gone, the
+    /// transaction can't be published without locking funds in an output
+    /// nobody can spend.
+    fn release_batch(&mut self, source: &ServiceId) {
+        let batch_id = match self.batch_members.remove(source) {
+            Some(batch_id) => batch_id,
+            None => return,
+        };
+        let pending = match self.batches.remove(&batch_id) {
+            Some(pending) => pending,
+            None => return,
+        };
+        for (channeld, _) in &pending.requests {
+            self.batch_members.remove(channeld);
+        }
+        if let Some(tx) = pending.tx {
+            info!(
+                "Batch funding transaction {} is abandoned by {}",
+                tx.txid(),
+                source
+            );
+            self.wallet.release(&tx);
+        }
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let [next_external, next_internal] = self.wallet.next_index();
+        let db = WalletDb {
+            next_external,
+            next_internal,
+            utxos: self
+                .wallet
+                .utxos()
+                .iter()
+                .map(|(outpoint, coin)| Utxo {
+                    outpoint: *outpoint,
+                    value: coin.value,
+                    keychain: coin.keychain,
+                    index: coin.index,
+                })
+                .collect(),
+        };
+        let tmp_path = self.db_path.with_extension("tmp");
+        db.strict_encode(fs::File::create(&tmp_path)?)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        fs::rename(tmp_path, &self.db_path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,468 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::util::bip32::{ChildNumber, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::{OutPoint, Script, SigHashType, Transaction, TxIn, TxOut};
+use lnpbp::Chain;
+
+use crate::rpc::request::{CpfpRequest, FundingRequest};
+
+/// Keychain of the addresses receiving deposits and funds of the closed
+/// channels
+const EXTERNAL_KEYCHAIN: u32 = 0;
+
+/// Keychain of the change addresses
+const INTERNAL_KEYCHAIN: u32 = 1;
+
+/// Number of addresses of each keychain following the last used one, which
+/// are watched for incoming funds
+const LOOKAHEAD: u32 = 20;
+
+/// Weight of the transaction version, lock time, input and output counts and
+/// segwit marker
+const TX_BASE_WEIGHT: u64 = 42;
+
+/// Weight of P2WPKH input, including its witness
+const INPUT_WEIGHT: u64 = 272;
+
+/// Weight of the input spending anchor output with the funding key signature
+const ANCHOR_INPUT_WEIGHT: u64 = 280;
+
+/// Weight of P2WSH funding output
+const FUNDING_OUTPUT_WEIGHT: u64 = 172;
+
+/// Weight of P2WPKH change output
+const CHANGE_OUTPUT_WEIGHT: u64 = 124;
+
+/// Change below this value is not worth an output and is left to miners
+const DUST_LIMIT: u64 = 354;
+
+/// Minimal fee rate accepted by the network relay policy
+const MIN_FEERATE_PER_KW: u32 = 253;
+
+/// Errors constructing funding transaction
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum FundingError {
+    /// funding wallet has {available} sat available, while {required} sat
+    /// is required for the funding transaction
+    InsufficientFunds { required: u64, available: u64 },
+}
+
+/// Derives the master key of the funding wallet from the node key. Wallet
+/// keys are unrelated to the node identity key, while the wallet is still
+/// restored from the node key backup.
+pub fn wallet_master_key(
+    node_key: &secp256k1::SecretKey,
+    chain: &Chain,
+) -> ExtendedPrivKey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"lnp-node:funding-wallet");
+    engine.input(&node_key[..]);
+    let seed = sha256::Hash::from_engine(engine);
+    let network =
+        bitcoin::Network::try_from(chain).unwrap_or(bitcoin::Network::Testnet);
+    ExtendedPrivKey::new_master(network, &seed[..])
+        .expect("negligible probability of invalid master key")
+}
+
+/// Output descriptors of the funding wallet keychains
+pub fn wallet_descriptors(master: &ExtendedPrivKey) -> Vec<String> {
+    let xpub = ExtendedPubKey::from_private(&Secp256k1::new(), master);
+    vec![
+        format!("wpkh({}/{}/*)", xpub, EXTERNAL_KEYCHAIN),
+        format!("wpkh({}/{}/*)", xpub, INTERNAL_KEYCHAIN),
+    ]
+}
+
+/// Wallet output with the derivation of its key
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct Coin {
+    pub value: u64,
+    pub keychain: u32,
+    pub index: u32,
+}
+
+/// Outputs owned by the funding wallet. All of them use P2WPKH scripts of the
+/// keys derived from the wallet master key; each address is given out once.
+pub(super) struct Wallet {
+    secp: Secp256k1<secp256k1::All>,
+    master: ExtendedPrivKey,
+    /// Index of the first unused address of the external and internal
+    /// keychains
+    next_index: [u32; 2],
+    /// Scripts of the used and lookahead addresses, with the keychain and
+    /// index of their keys
+    scripts: HashMap<Script, (u32, u32)>,
+    /// Number of the watched addresses of each keychain
+    watched: [u32; 2],
+    utxos: BTreeMap<OutPoint, Coin>,
+    /// Outputs spent by funding transactions which are not published yet
+    reserved: BTreeSet<OutPoint>,
+}
+
+impl Wallet {
+    pub fn with(
+        master: ExtendedPrivKey,
+        next_index: [u32; 2],
+        utxos: BTreeMap<OutPoint, Coin>,
+    ) -> Self {
+        let mut wallet = Wallet {
+            secp: Secp256k1::new(),
+            master,
+            next_index,
+            scripts: empty!(),
+            watched: [0, 0],
+            utxos,
+            reserved: empty!(),
+        };
+        wallet.watch(EXTERNAL_KEYCHAIN);
+        wallet.watch(INTERNAL_KEYCHAIN);
+        wallet
+    }
+
+    pub fn next_index(&self) -> [u32; 2] {
+        self.next_index
+    }
+
+    pub fn utxos(&self) -> &BTreeMap<OutPoint, Coin> {
+        &self.utxos
+    }
+
+    pub fn balance(&self) -> u64 {
+        self.utxos.values().map(|coin| coin.value).sum()
+    }
+
+    pub fn reserved(&self) -> u64 {
+        self.reserved
+            .iter()
+            .filter_map(|outpoint| self.utxos.get(outpoint))
+            .map(|coin| coin.value)
+            .sum()
+    }
+
+    /// Script of the address receiving deposits; it is replaced with the
+    /// next one once it receives funds
+    pub fn deposit_script(&self) -> Script {
+        let index = self.next_index[EXTERNAL_KEYCHAIN as usize];
+        self.script(EXTERNAL_KEYCHAIN, index)
+    }
+
+    /// Gives out a fresh script of the external keychain
+    pub fn fresh_script(&mut self) -> Script {
+        self.allocate(EXTERNAL_KEYCHAIN)
+    }
+
+    fn allocate(&mut self, keychain: u32) -> Script {
+        let index = self.next_index[keychain as usize];
+        self.next_index[keychain as usize] += 1;
+        self.watch(keychain);
+        self.script(keychain, index)
+    }
+
+    /// Watches all used scripts of the keychain and the lookahead ones
+    /// following them
+    fn watch(&mut self, keychain: u32) {
+        let till = self.next_index[keychain as usize] + LOOKAHEAD;
+        for index in self.watched[keychain as usize]..till {
+            let script = self.script(keychain, index);
+            self.scripts.insert(script, (keychain, index));
+        }
+        self.watched[keychain as usize] = till;
+    }
+
+    fn key(&self, keychain: u32, index: u32) -> bitcoin::PrivateKey {
+        self.master
+            .ckd_priv(&self.secp, ChildNumber::Normal { index: keychain })
+            .and_then(|xpriv| {
+                xpriv.ckd_priv(&self.secp, ChildNumber::Normal { index })
+            })
+            .expect("negligible probability of invalid child key")
+            .private_key
+    }
+
+    fn script(&self, keychain: u32, index: u32) -> Script {
+        let wpubkey_hash = self
+            .key(keychain, index)
+            .public_key(&self.secp)
+            .wpubkey_hash()
+            .expect("compressed public key always has witness hash");
+        Script::new_v0_wpkh(&wpubkey_hash)
+    }
+
+    /// Selects outputs covering the funding amounts and the fee, largest
+    /// first, and constructs unsigned transaction with the funding outputs
+    /// following the order of the requests, starting from index 0. Selected
+    /// outputs are reserved until the transaction is released or processed.
+    pub fn construct(
+        &mut self,
+        requests: &[FundingRequest],
+    ) -> Result<Transaction, FundingError> {
+        let feerate_per_kw = requests
+            .iter()
+            .map(|request| request.feerate_per_kw)
+            .max()
+            .unwrap_or_default()
+            .max(MIN_FEERATE_PER_KW);
+        let amount: u64 = requests.iter().map(|request| request.amount).sum();
+        let fee = |inputs: usize, change: bool| {
+            let weight = TX_BASE_WEIGHT
+                + INPUT_WEIGHT * inputs as u64
+                + FUNDING_OUTPUT_WEIGHT * requests.len() as u64
+                + if change { CHANGE_OUTPUT_WEIGHT } else { 0 };
+            feerate_per_kw as u64 * weight / 1000
+        };
+
+        let (selected, total) =
+            self.select(|inputs| amount + fee(inputs, false))?;
+
+        let mut output = requests
+            .iter()
+            .map(|request| TxOut {
+                value: request.amount,
+                script_pubkey: request.script_pubkey.clone().into(),
+            })
+            .collect::<Vec<_>>();
+        let change = total.saturating_sub(amount + fee(selected.len(), true));
+        if change >= DUST_LIMIT {
+            output.push(TxOut {
+                value: change,
+                script_pubkey: self.allocate(INTERNAL_KEYCHAIN),
+            });
+        }
+
+        self.reserved.extend(selected.iter().copied());
+        Ok(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: selected.into_iter().map(replaceable_input).collect(),
+            output,
+        })
+    }
+
+    /// Selects outputs covering the fees of the child transaction, which
+    /// spends the anchor output of the commitment with its first input, such
+    /// that the package of both transactions pays the requested fee rate.
+    /// The child sends the rest of the funds back to the wallet. Returns the
+    /// unsigned transaction and its fee; selected outputs are reserved until
+    /// the transaction is processed.
+    pub fn construct_cpfp(
+        &mut self,
+        request: &CpfpRequest,
+    ) -> Result<(Transaction, u64), FundingError> {
+        let feerate_per_kw = request.feerate_per_kw.max(MIN_FEERATE_PER_KW);
+        let fee = |inputs: usize| {
+            let weight = request.parent_weight
+                + TX_BASE_WEIGHT
+                + ANCHOR_INPUT_WEIGHT
+                + INPUT_WEIGHT * inputs as u64
+                + CHANGE_OUTPUT_WEIGHT;
+            (feerate_per_kw as u64 * weight / 1000)
+                .saturating_sub(request.parent_fee)
+        };
+        // Child must have at least one output, so its change can't be
+        // left to miners
+        let (selected, total) = self.select(|inputs| {
+            (fee(inputs) + DUST_LIMIT).saturating_sub(request.anchor_value)
+        })?;
+        let fee = fee(selected.len());
+
+        self.reserved.extend(selected.iter().copied());
+        let input = std::iter::once(request.anchor)
+            .chain(selected)
+            .map(replaceable_input)
+            .collect();
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input,
+            output: vec![TxOut {
+                value: total + request.anchor_value - fee,
+                script_pubkey: self.allocate(INTERNAL_KEYCHAIN),
+            }],
+        };
+        Ok((tx, fee))
+    }
+
+    /// Selects available outputs, largest first, until their value covers
+    /// the amount required for the given number of inputs. Returns selected
+    /// outputs and their total value.
+    fn select(
+        &self,
+        required: impl Fn(usize) -> u64,
+    ) -> Result<(Vec<OutPoint>, u64), FundingError> {
+        let mut candidates = self
+            .utxos
+            .iter()
+            .filter(|(outpoint, _)| !self.reserved.contains(outpoint))
+            .map(|(outpoint, coin)| (*outpoint, coin.value))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|(_, a), (_, b)| b.cmp(a));
+        let available = candidates.iter().map(|(_, value)| value).sum();
+
+        let mut selected = vec![];
+        let mut total = 0u64;
+        for (outpoint, value) in candidates {
+            if total >= required(selected.len()) {
+                break;
+            }
+            selected.push(outpoint);
+            total += value;
+        }
+        let required = required(selected.len());
+        if total < required {
+            return Err(FundingError::InsufficientFunds {
+                required,
+                available,
+            });
+        }
+        Ok((selected, total))
+    }
+
+    /// Signs inputs of the transaction spending the wallet outputs; the rest
+    /// of the inputs are left to their owners
+    pub fn sign(&self, tx: &mut Transaction) {
+        let coins = tx
+            .input
+            .iter()
+            .enumerate()
+            .filter_map(|(index, txin)| {
+                self.utxos
+                    .get(&txin.previous_output)
+                    .map(|coin| (index, *coin))
+            })
+            .collect::<Vec<_>>();
+        let mut witnesses = vec![];
+        {
+            let mut sig_hasher = SigHashCache::new(&*tx);
+            for (index, coin) in coins {
+                let key = self.key(coin.keychain, coin.index);
+                let pubkey = key.public_key(&self.secp);
+                let script_code = Script::new_p2pkh(&pubkey.pubkey_hash());
+                let sighash = sig_hasher.signature_hash(
+                    index,
+                    &script_code,
+                    coin.value,
+                    SigHashType::All,
+                );
+                let sign_msg = secp256k1::Message::from_slice(&sighash[..])
+                    .expect("Sighash size always match requirements");
+                let mut signature = self
+                    .secp
+                    .sign(&sign_msg, &key.key)
+                    .serialize_der()
+                    .to_vec();
+                signature.push(SigHashType::All.as_u32() as u8);
+                witnesses.push((index, vec![signature, pubkey.to_bytes()]));
+            }
+        }
+        for (index, witness) in witnesses {
+            tx.input[index].witness = witness;
+        }
+    }
+
+    /// Returns outputs spent by the transaction to the set of the available
+    /// outputs
+    pub fn release(&mut self, tx: &Transaction) {
+        for txin in &tx.input {
+            self.reserved.remove(&txin.previous_output);
+        }
+    }
+
+    /// Accounts outputs spent and created by the transaction. Returns
+    /// whether the wallet has changed.
+    pub fn process(&mut self, tx: &Transaction) -> bool {
+        let mut changed = false;
+        for txin in &tx.input {
+            self.reserved.remove(&txin.previous_output);
+            changed |= self.utxos.remove(&txin.previous_output).is_some();
+        }
+        let txid = tx.txid();
+        for (vout, txout) in tx.output.iter().enumerate() {
+            let (keychain, index) = match self.scripts.get(&txout.script_pubkey)
+            {
+                Some(derivation) => *derivation,
+                None => continue,
+            };
+            let coin = Coin {
+                value: txout.value,
+                keychain,
+                index,
+            };
+            changed |= self
+                .utxos
+                .insert(OutPoint::new(txid, vout as u32), coin)
+                .is_none();
+            // Address is used, so the next ones are watched
+            if index >= self.next_index[keychain as usize] {
+                self.next_index[keychain as usize] = index + 1;
+                self.watch(keychain);
+            }
+        }
+        changed
+    }
+}
+
+fn replaceable_input(previous_output: OutPoint) -> TxIn {
+    TxIn {
+        previous_output,
+        script_sig: Script::new(),
+        // Signals replaceability, such that the transaction may be
+        // fee-bumped
+        sequence: 0xFFFF_FFFD,
+        witness: vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fresh_addresses() {
+        let node_key = secp256k1::SecretKey::from_slice(&[0x01; 32]).unwrap();
+        let master = wallet_master_key(&node_key, &Chain::Mainnet);
+        let mut wallet = Wallet::with(master, [0, 0], empty!());
+
+        let deposit = wallet.deposit_script();
+        let closing = wallet.fresh_script();
+        assert_eq!(deposit, closing);
+        assert_ne!(wallet.deposit_script(), closing);
+
+        // Funds received to an address watched ahead make it used
+        let lookahead = wallet.script(EXTERNAL_KEYCHAIN, 10);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: lookahead,
+            }],
+        };
+        assert!(wallet.process(&tx));
+        assert_eq!(wallet.balance(), 10_000);
+        assert_eq!(wallet.next_index(), [11, 0]);
+        assert_eq!(
+            wallet.deposit_script(),
+            wallet.script(EXTERNAL_KEYCHAIN, 11)
+        );
+    }
+}
@@ -0,0 +1,815 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Parsing and validation of BOLT-7 gossip messages. LNP/BP Core lib does
+//! not expose the message fields, so the messages are read from their wire
+//! encoding.
+
+use amplify::{Slice32, Wrapper};
+use bitcoin::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_2};
+use bitcoin::blockdata::script::Builder;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::{self, Secp256k1, Signature};
+use bitcoin::{BlockHash, Script};
+use internet2::addr::InetSocketAddr;
+use internet2::{
+    presentation, CreateUnmarshaller, LocalNode, RemoteSocketAddr, TypedEnum,
+    Unmarshall,
+};
+use lnp::{ChannelId, Messages};
+
+use crate::rpc::request::{ForwardingPolicy, LeaseRates, ShortChannelId};
+use crate::rpc::tlv::{self, TlvStream};
+
+/// Errors in the gossip messages received from the remote peers
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum GossipError {
+    /// gossip message is truncated
+    Truncated,
+
+    /// gossip message contains invalid public key
+    InvalidKey,
+
+    /// gossip message contains malformed signature
+    MalformedSignature,
+
+    /// {0} signature of the gossip message is invalid
+    InvalidSignature(&'static str),
+
+    /// channel {0} is announced for a different chain
+    ForeignChain(ShortChannelId),
+
+    /// node ids of channel {0} are not ordered
+    UnorderedNodes(ShortChannelId),
+
+    /// funding output of channel {0} does not exist or is spent
+    NoFundingOutput(ShortChannelId),
+
+    /// funding output of channel {0} does not match the announced keys
+    FundingMismatch(ShortChannelId),
+
+    /// short channel ids use unsupported encoding {0}
+    UnsupportedEncoding(u8),
+}
+
+/// Channel announced with `channel_announcement` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChannelAnnouncement {
+    pub chain_hash: BlockHash,
+    pub short_channel_id: ShortChannelId,
+    pub features: Vec<u8>,
+    pub node_id_1: secp256k1::PublicKey,
+    pub node_id_2: secp256k1::PublicKey,
+    pub bitcoin_key_1: secp256k1::PublicKey,
+    pub bitcoin_key_2: secp256k1::PublicKey,
+    /// Signatures of the nodes and of the funding keys, in the order of the
+    /// wire encoding
+    signatures: [Signature; 4],
+    /// Hash of the message part committed to by the signatures
+    digest: secp256k1::Message,
+    /// Wire encoding of the message, relayed in replies to gossip queries
+    /// and persisted in the graph store
+    pub(super) data: Vec<u8>,
+}
+
+impl ChannelAnnouncement {
+    /// Reads the announcement from `channel_announcement` message
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        let data = message.serialize();
+        // Message type is followed by the four signatures, which commit to
+        // the rest of the message
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        let signatures = [
+            reader.signature()?,
+            reader.signature()?,
+            reader.signature()?,
+            reader.signature()?,
+        ];
+        let digest = sha256d::Hash::hash(reader.rest());
+        let digest = secp256k1::Message::from_slice(&digest[..])
+            .expect("hash has message size");
+        let len = reader.u16()? as usize;
+        let features = reader.bytes(len)?.to_vec();
+        let chain_hash = BlockHash::from_slice(reader.bytes(32)?)
+            .expect("slice has hash size");
+        let short_channel_id = ShortChannelId::from(reader.u64()?);
+        Ok(ChannelAnnouncement {
+            chain_hash,
+            short_channel_id,
+            features,
+            node_id_1: reader.pubkey()?,
+            node_id_2: reader.pubkey()?,
+            bitcoin_key_1: reader.pubkey()?,
+            bitcoin_key_2: reader.pubkey()?,
+            signatures,
+            digest,
+            data: data.clone(),
+        })
+    }
+
+    /// Checks the signatures of both nodes and both funding keys
+    pub fn verify(&self, chain_hash: BlockHash) -> Result<(), GossipError> {
+        if self.chain_hash != chain_hash {
+            return Err(GossipError::ForeignChain(self.short_channel_id));
+        }
+        if self.node_id_1.serialize() >= self.node_id_2.serialize() {
+            return Err(GossipError::UnorderedNodes(self.short_channel_id));
+        }
+        let secp = Secp256k1::verification_only();
+        let signers = [
+            ("first node", &self.node_id_1),
+            ("second node", &self.node_id_2),
+            ("first funding key", &self.bitcoin_key_1),
+            ("second funding key", &self.bitcoin_key_2),
+        ];
+        for ((signer, key), signature) in
+            signers.iter().zip(self.signatures.iter())
+        {
+            secp.verify(&self.digest, signature, key)
+                .map_err(|_| GossipError::InvalidSignature(signer))?;
+        }
+        Ok(())
+    }
+
+    /// Restores `channel_announcement` message
+    pub fn message(&self) -> Result<Messages, presentation::Error> {
+        unmarshall(&self.data)
+    }
+
+    /// P2WSH script of the 2-of-2 multisig funding output spendable by the
+    /// announced funding keys
+    pub fn funding_script(&self) -> Script {
+        let (key_1, key_2) = (
+            self.bitcoin_key_1.serialize(),
+            self.bitcoin_key_2.serialize(),
+        );
+        let (first, second) = if key_1 < key_2 {
+            (key_1, key_2)
+        } else {
+            (key_2, key_1)
+        };
+        Builder::new()
+            .push_opcode(OP_PUSHNUM_2)
+            .push_slice(&first)
+            .push_slice(&second)
+            .push_opcode(OP_PUSHNUM_2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+            .to_v0_p2wsh()
+    }
+}
+
+/// BOLT-7 type of `channel_announcement` message
+const CHANNEL_ANNOUNCEMENT: u16 = 256;
+
+/// Part of `channel_announcement` message committed to by its signatures,
+/// constructed by the channel peers for announcing their channel
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnsignedChannelAnnouncement {
+    pub chain_hash: BlockHash,
+    pub short_channel_id: ShortChannelId,
+    pub node_id_1: secp256k1::PublicKey,
+    pub node_id_2: secp256k1::PublicKey,
+    pub bitcoin_key_1: secp256k1::PublicKey,
+    pub bitcoin_key_2: secp256k1::PublicKey,
+}
+
+impl UnsignedChannelAnnouncement {
+    /// Announcement of the channel between the local and the remote nodes,
+    /// given with their funding keys. Nodes are ordered as required by
+    /// BOLT-7, and the funding keys follow the order of their nodes.
+    pub fn with(
+        chain_hash: BlockHash,
+        short_channel_id: ShortChannelId,
+        local: (secp256k1::PublicKey, secp256k1::PublicKey),
+        remote: (secp256k1::PublicKey, secp256k1::PublicKey),
+    ) -> Self {
+        let (first, second) = if local.0.serialize() < remote.0.serialize() {
+            (local, remote)
+        } else {
+            (remote, local)
+        };
+        UnsignedChannelAnnouncement {
+            chain_hash,
+            short_channel_id,
+            node_id_1: first.0,
+            node_id_2: second.0,
+            bitcoin_key_1: first.1,
+            bitcoin_key_2: second.1,
+        }
+    }
+
+    /// Wire encoding of the signed part of the message
+    fn encode(&self) -> Vec<u8> {
+        // No channel features are announced
+        let mut data = 0u16.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.chain_hash[..]);
+        data.extend_from_slice(&u64::from(self.short_channel_id).to_be_bytes());
+        for key in &[
+            self.node_id_1,
+            self.node_id_2,
+            self.bitcoin_key_1,
+            self.bitcoin_key_2,
+        ] {
+            data.extend_from_slice(&key.serialize());
+        }
+        data
+    }
+
+    /// Hash signed by the nodes and by the funding keys
+    pub fn digest(&self) -> secp256k1::Message {
+        let digest = sha256d::Hash::hash(&self.encode());
+        secp256k1::Message::from_slice(&digest[..])
+            .expect("hash has message size")
+    }
+
+    /// Composes `channel_announcement` message from the signatures of the
+    /// local and the remote peers, checking all of them
+    pub fn compose(
+        &self,
+        local: &AnnouncementSignatures,
+        remote: &AnnouncementSignatures,
+        local_node: secp256k1::PublicKey,
+    ) -> Result<Messages, GossipError> {
+        let (first, second) = if local_node == self.node_id_1 {
+            (local, remote)
+        } else {
+            (remote, local)
+        };
+        let mut data = CHANNEL_ANNOUNCEMENT.to_be_bytes().to_vec();
+        for signature in &[
+            first.node_signature,
+            second.node_signature,
+            first.bitcoin_signature,
+            second.bitcoin_signature,
+        ] {
+            data.extend_from_slice(&signature.serialize_compact());
+        }
+        data.extend(self.encode());
+        let message = unmarshall(&data).map_err(|_| GossipError::Truncated)?;
+        ChannelAnnouncement::with(&message)?.verify(self.chain_hash)?;
+        Ok(message)
+    }
+}
+
+/// BOLT-7 type of `announcement_signatures` message
+const ANNOUNCEMENT_SIGNATURES: u16 = 259;
+
+/// Signatures of the channel announcement made by one of the channel peers,
+/// exchanged with `announcement_signatures` message
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AnnouncementSignatures {
+    pub channel_id: ChannelId,
+    pub short_channel_id: ShortChannelId,
+    pub node_signature: Signature,
+    pub bitcoin_signature: Signature,
+}
+
+impl AnnouncementSignatures {
+    /// Reads the signatures from `announcement_signatures` message
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        let data = message.serialize();
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        let mut channel_id = [0u8; 32];
+        channel_id.copy_from_slice(reader.bytes(32)?);
+        Ok(AnnouncementSignatures {
+            channel_id: ChannelId::from_inner(Slice32::from_inner(channel_id)),
+            short_channel_id: ShortChannelId::from(reader.u64()?),
+            node_signature: reader.signature()?,
+            bitcoin_signature: reader.signature()?,
+        })
+    }
+
+    /// Composes `announcement_signatures` message
+    pub fn to_message(&self) -> Result<Messages, presentation::Error> {
+        let mut data = ANNOUNCEMENT_SIGNATURES.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.channel_id.into_inner().into_inner());
+        data.extend_from_slice(&u64::from(self.short_channel_id).to_be_bytes());
+        data.extend_from_slice(&self.node_signature.serialize_compact());
+        data.extend_from_slice(&self.bitcoin_signature.serialize_compact());
+        unmarshall(&data)
+    }
+}
+
+/// Node announced with `node_announcement` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NodeAnnouncement {
+    pub node_id: secp256k1::PublicKey,
+    pub timestamp: u32,
+    pub features: Vec<u8>,
+    pub rgb_color: [u8; 3],
+    pub alias: String,
+    /// Addresses of the node which are supported by the node; DNS hostnames
+    /// and deprecated Tor v2 addresses are skipped
+    pub addresses: Vec<RemoteSocketAddr>,
+    /// Liquidity lease rates advertised by the node (`option_will_fund`)
+    pub lease_rates: Option<LeaseRates>,
+    /// TLV records of the message, which are relayed with it
+    pub tlvs: TlvStream,
+    signature: Signature,
+    digest: secp256k1::Message,
+    /// Wire encoding of the message, including its TLV stream
+    pub(super) data: Vec<u8>,
+}
+
+impl NodeAnnouncement {
+    /// Reads the announcement from `node_announcement` message without TLV
+    /// records
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        NodeAnnouncement::with_tlvs(message, empty!())
+    }
+
+    /// Reads the announcement from `node_announcement` message extended
+    /// with the TLV stream; signature commits to both of them
+    pub fn with_tlvs(
+        message: &Messages,
+        tlvs: TlvStream,
+    ) -> Result<Self, GossipError> {
+        let mut data = message.serialize();
+        data.extend(tlv::encode(&tlvs));
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        let signature = reader.signature()?;
+        let digest = sha256d::Hash::hash(reader.rest());
+        let digest = secp256k1::Message::from_slice(&digest[..])
+            .expect("hash has message size");
+        let len = reader.u16()? as usize;
+        let features = reader.bytes(len)?.to_vec();
+        let timestamp = reader.u32()?;
+        let node_id = reader.pubkey()?;
+        let mut rgb_color = [0u8; 3];
+        rgb_color.copy_from_slice(reader.bytes(3)?);
+        // Alias is zero-padded UTF-8 string
+        let alias = reader.bytes(32)?;
+        let alias = String::from_utf8_lossy(
+            &alias[..alias.iter().position(|b| *b == 0).unwrap_or(32)],
+        )
+        .into_owned();
+        let len = reader.u16()? as usize;
+        let addresses = parse_addresses(reader.bytes(len)?)?;
+        // Malformed lease rates do not invalidate the rest of the message
+        let lease_rates = tlvs
+            .get(&tlv::OPTION_WILL_FUND)
+            .and_then(|data| LeaseRates::from_bytes(data));
+        Ok(NodeAnnouncement {
+            node_id,
+            timestamp,
+            features,
+            rgb_color,
+            alias,
+            addresses,
+            lease_rates,
+            tlvs,
+            signature,
+            digest,
+            data: data.clone(),
+        })
+    }
+
+    /// Restores `node_announcement` message; its TLV records are kept
+    /// apart
+    pub fn message(&self) -> Result<Messages, presentation::Error> {
+        let len = tlv::base_len(NODE_ANNOUNCEMENT, &self.data[2..])
+            .unwrap_or_default();
+        unmarshall(&self.data[..2 + len])
+    }
+
+    /// Checks the node signature
+    pub fn verify(&self) -> Result<(), GossipError> {
+        Secp256k1::verification_only()
+            .verify(&self.digest, &self.signature, &self.node_id)
+            .map_err(|_| GossipError::InvalidSignature("node"))
+    }
+}
+
+/// BOLT-7 type of `node_announcement` message
+const NODE_ANNOUNCEMENT: u16 = 257;
+
+/// Data which the node advertises about itself with `node_announcement`
+/// message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NodeAdvert {
+    pub features: Vec<u8>,
+    pub rgb_color: [u8; 3],
+    pub alias: String,
+    /// Addresses for connecting to the node; DNS hostnames can't be
+    /// announced and are skipped
+    pub addresses: Vec<InetSocketAddr>,
+}
+
+impl NodeAdvert {
+    /// Composes `node_announcement` message signed with the node key,
+    /// returning it with the TLV stream extending it
+    pub fn sign(
+        &self,
+        local_node: &LocalNode,
+        timestamp: u32,
+    ) -> Result<(Messages, TlvStream), presentation::Error> {
+        let mut body = (self.features.len() as u16).to_be_bytes().to_vec();
+        body.extend_from_slice(&self.features);
+        body.extend_from_slice(&timestamp.to_be_bytes());
+        body.extend_from_slice(&local_node.node_id().serialize());
+        body.extend_from_slice(&self.rgb_color);
+        // Alias is zero-padded UTF-8 string, truncated at the character
+        // boundary
+        let mut len = self.alias.len().min(32);
+        while !self.alias.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut alias = [0u8; 32];
+        alias[..len].copy_from_slice(&self.alias.as_bytes()[..len]);
+        body.extend_from_slice(&alias);
+        let addresses = self
+            .addresses
+            .iter()
+            .filter_map(encode_address)
+            .flatten()
+            .collect::<Vec<_>>();
+        body.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        body.extend(addresses);
+        let tlvs = TlvStream::new();
+
+        let mut signed = body.clone();
+        signed.extend(tlv::encode(&tlvs));
+        let digest = sha256d::Hash::hash(&signed);
+        let digest = secp256k1::Message::from_slice(&digest[..])
+            .expect("hash has message size");
+        let mut data = NODE_ANNOUNCEMENT.to_be_bytes().to_vec();
+        data.extend_from_slice(&local_node.sign(&digest).serialize_compact());
+        data.extend(body);
+        Ok((unmarshall(&data)?, tlvs))
+    }
+}
+
+/// Forwarding policy of one direction of the channel, announced with
+/// `channel_update` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChannelUpdate {
+    pub chain_hash: BlockHash,
+    pub short_channel_id: ShortChannelId,
+    pub timestamp: u32,
+    /// Direction of the channel: `0` for the updates from the first node of
+    /// the channel, `1` for the ones from the second node
+    pub direction: u8,
+    pub disabled: bool,
+    pub policy: ForwardingPolicy,
+    signature: Signature,
+    digest: secp256k1::Message,
+    pub(super) data: Vec<u8>,
+}
+
+impl ChannelUpdate {
+    /// Reads the update from `channel_update` message
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        let data = message.serialize();
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        let signature = reader.signature()?;
+        let digest = sha256d::Hash::hash(reader.rest());
+        let digest = secp256k1::Message::from_slice(&digest[..])
+            .expect("hash has message size");
+        let chain_hash = BlockHash::from_slice(reader.bytes(32)?)
+            .expect("slice has hash size");
+        let short_channel_id = ShortChannelId::from(reader.u64()?);
+        let timestamp = reader.u32()?;
+        let message_flags = reader.bytes(1)?[0];
+        let channel_flags = reader.bytes(1)?[0];
+        let cltv_expiry_delta = reader.u16()?;
+        let htlc_minimum_msat = reader.u64()?;
+        let fee_base_msat = reader.u32()?;
+        let fee_proportional_millionths = reader.u32()?;
+        let htlc_maximum_msat = if message_flags & 0x01 != 0 {
+            Some(reader.u64()?)
+        } else {
+            None
+        };
+        Ok(ChannelUpdate {
+            chain_hash,
+            short_channel_id,
+            timestamp,
+            direction: channel_flags & 0x01,
+            disabled: channel_flags & 0x02 != 0,
+            policy: ForwardingPolicy {
+                fee_base_msat,
+                fee_proportional_millionths,
+                cltv_expiry_delta,
+                htlc_minimum_msat,
+                htlc_maximum_msat,
+            },
+            signature,
+            digest,
+            data: data.clone(),
+        })
+    }
+
+    /// Restores `channel_update` message
+    pub fn message(&self) -> Result<Messages, presentation::Error> {
+        unmarshall(&self.data)
+    }
+
+    /// Checks the signature of the node at the origin of the channel
+    /// direction
+    pub fn verify(
+        &self,
+        chain_hash: BlockHash,
+        node_id: &secp256k1::PublicKey,
+    ) -> Result<(), GossipError> {
+        if self.chain_hash != chain_hash {
+            return Err(GossipError::ForeignChain(self.short_channel_id));
+        }
+        Secp256k1::verification_only()
+            .verify(&self.digest, &self.signature, node_id)
+            .map_err(|_| GossipError::InvalidSignature("node"))
+    }
+}
+
+/// BOLT-7 type of `gossip_timestamp_filter` message
+const GOSSIP_TIMESTAMP_FILTER: u16 = 265;
+
+/// Filter of the gossip relayed to the remote peer, set by the peer with
+/// `gossip_timestamp_filter` message
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display)]
+#[display("{first_timestamp}+{timestamp_range}")]
+pub struct GossipTimestampFilter {
+    pub chain_hash: BlockHash,
+    pub first_timestamp: u32,
+    pub timestamp_range: u32,
+}
+
+impl GossipTimestampFilter {
+    /// Reads the filter from `gossip_timestamp_filter` message
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        let data = message.serialize();
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        Ok(GossipTimestampFilter {
+            chain_hash: BlockHash::from_slice(reader.bytes(32)?)
+                .expect("slice has hash size"),
+            first_timestamp: reader.u32()?,
+            timestamp_range: reader.u32()?,
+        })
+    }
+
+    /// Composes `gossip_timestamp_filter` message
+    pub fn to_message(&self) -> Result<Messages, presentation::Error> {
+        let mut data = GOSSIP_TIMESTAMP_FILTER.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.chain_hash[..]);
+        data.extend_from_slice(&self.first_timestamp.to_be_bytes());
+        data.extend_from_slice(&self.timestamp_range.to_be_bytes());
+        unmarshall(&data)
+    }
+
+    /// Checks whether the gossip message falls into the filtered time range.
+    /// Channel announcements have no timestamp of their own and pass the
+    /// filter as long as it covers the current time, since they are relayed
+    /// as soon as they are validated.
+    pub fn allows(&self, message: &Messages, now: u32) -> bool {
+        let timestamp = match message {
+            Messages::ChannelUpdate(_) => {
+                ChannelUpdate::with(message).map(|update| update.timestamp)
+            }
+            Messages::NodeAnnouncement(_) => NodeAnnouncement::with(message)
+                .map(|announcement| announcement.timestamp),
+            _ => Ok(now),
+        };
+        let end = self.first_timestamp as u64 + self.timestamp_range as u64;
+        match timestamp {
+            Ok(timestamp) => {
+                timestamp >= self.first_timestamp && (timestamp as u64) < end
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Numbers of the feature bits set in the BOLT-9 feature vector
+pub fn feature_bits(features: &[u8]) -> Vec<u16> {
+    let len = features.len();
+    (0..len * 8)
+        .filter(|bit| features[len - 1 - bit / 8] & (1 << (bit % 8)) != 0)
+        .map(|bit| bit as u16)
+        .collect()
+}
+
+/// BOLT-9 feature vector with the given feature bits set
+pub fn feature_vector(bits: impl IntoIterator<Item = u16>) -> Vec<u8> {
+    let bits = bits.into_iter().collect::<Vec<_>>();
+    let len = bits.iter().max().map(|max| *max as usize / 8 + 1);
+    let mut features = vec![0u8; len.unwrap_or_default()];
+    let len = features.len();
+    for bit in bits {
+        features[len - 1 - bit as usize / 8] |= 1 << (bit % 8);
+    }
+    features
+}
+
+/// Address descriptor of `node_announcement`; only IP and Tor v3 addresses
+/// have descriptors
+fn encode_address(addr: &InetSocketAddr) -> Option<Vec<u8>> {
+    let host = addr.address.to_string();
+    let mut data = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let mut data = vec![1u8];
+            data.extend_from_slice(&ip.octets());
+            data
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let mut data = vec![2u8];
+            data.extend_from_slice(&ip.octets());
+            data
+        }
+        Err(_) => {
+            let key = base32_decode(host.strip_suffix(".onion")?)?;
+            if key.len() != 35 {
+                return None;
+            }
+            let mut data = vec![4u8];
+            data.extend(key);
+            data
+        }
+    };
+    data.extend_from_slice(&addr.port.to_be_bytes());
+    Some(data)
+}
+
+/// Reads address descriptors of `node_announcement`. Parsing stops at the
+/// first unknown descriptor type, since its length is unknown.
+fn parse_addresses(data: &[u8]) -> Result<Vec<RemoteSocketAddr>, GossipError> {
+    let mut reader = Reader::with(data);
+    let mut addresses = vec![];
+    while !reader.rest().is_empty() {
+        let inet_addr = match reader.bytes(1)?[0] {
+            1 => {
+                let ip = reader.bytes(4)?;
+                let ip = Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]);
+                let port = reader.u16()?;
+                Some(SocketAddr::new(IpAddr::V4(ip), port).into())
+            }
+            2 => {
+                let mut ip = [0u8; 16];
+                ip.copy_from_slice(reader.bytes(16)?);
+                let port = reader.u16()?;
+                Some(
+                    SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port)
+                        .into(),
+                )
+            }
+            3 => {
+                reader.bytes(12)?;
+                None
+            }
+            4 => {
+                let onion = base32(reader.bytes(35)?);
+                let port = reader.u16()?;
+                // Onion addresses are supported only with `tor` feature
+                format!("{}.onion:{}", onion, port)
+                    .parse::<InetSocketAddr>()
+                    .ok()
+            }
+            5 => {
+                let len = reader.bytes(1)?[0] as usize;
+                reader.bytes(len + 2)?;
+                None
+            }
+            _ => break,
+        };
+        addresses.extend(inet_addr.map(RemoteSocketAddr::Ftcp));
+    }
+    Ok(addresses)
+}
+
+/// RFC 4648 base32 alphabet in lower case, as used by onion addresses
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// RFC 4648 base32 encoding without padding, as used by onion addresses
+fn base32(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(
+                BASE32_ALPHABET[(buffer >> bits) as usize & 0x1F] as char,
+            );
+        }
+    }
+    if bits > 0 {
+        encoded.push(
+            BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 0x1F] as char,
+        );
+    }
+    encoded
+}
+
+/// Decodes RFC 4648 base32 string without padding; trailing bits not
+/// forming a whole byte are dropped
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_lowercase())?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// Decodes message from its wire encoding
+pub(super) fn unmarshall(data: &[u8]) -> Result<Messages, presentation::Error> {
+    let message = Messages::create_unmarshaller().unmarshall(data)?;
+    Ok((*message).clone())
+}
+
+/// Decodes message from its wire encoding, cutting off the TLV stream of
+/// the extensible messages
+pub(super) fn unmarshall_extended(
+    data: &[u8],
+) -> Result<(Messages, TlvStream), GossipError> {
+    let msg_type = Reader::with(data).u16()?;
+    let len = tlv::base_len(msg_type, &data[2..])
+        .map(|len| 2 + len)
+        .unwrap_or(data.len());
+    let message =
+        unmarshall(&data[..len]).map_err(|_| GossipError::Truncated)?;
+    let tlvs = tlv::decode(msg_type, &data[len..])
+        .map_err(|_| GossipError::Truncated)?;
+    Ok((message, tlvs))
+}
+
+/// Cursor over the wire encoding of the message
+pub(super) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn with(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    pub(super) fn bytes(
+        &mut self,
+        len: usize,
+    ) -> Result<&'a [u8], GossipError> {
+        let data = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(GossipError::Truncated)?;
+        self.pos += len;
+        Ok(data)
+    }
+
+    pub(super) fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    pub(super) fn u16(&mut self) -> Result<u16, GossipError> {
+        let data = self.bytes(2)?;
+        Ok(u16::from_be_bytes([data[0], data[1]]))
+    }
+
+    pub(super) fn u32(&mut self) -> Result<u32, GossipError> {
+        let mut data = [0u8; 4];
+        data.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(data))
+    }
+
+    pub(super) fn u64(&mut self) -> Result<u64, GossipError> {
+        let mut data = [0u8; 8];
+        data.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(data))
+    }
+
+    pub(super) fn signature(&mut self) -> Result<Signature, GossipError> {
+        Signature::from_compact(self.bytes(64)?)
+            .map_err(|_| GossipError::MalformedSignature)
+    }
+
+    pub(super) fn pubkey(
+        &mut self,
+    ) -> Result<secp256k1::PublicKey, GossipError> {
+        secp256k1::PublicKey::from_slice(self.bytes(33)?)
+            .map_err(|_| GossipError::InvalidKey)
+    }
+}
@@ -0,0 +1,434 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::SystemTime;
+
+use bitcoin::{secp256k1, OutPoint};
+use internet2::presentation;
+use lnp::Messages;
+
+use super::announcements::{
+    feature_bits, ChannelAnnouncement, ChannelUpdate, NodeAnnouncement,
+};
+use crate::rpc::request::{
+    AnnouncedNode, EdgePolicy, GraphEdge, GraphFilter, GraphInfo,
+    ShortChannelId,
+};
+use crate::rpc::tlv::TlvStream;
+
+/// Age of the channel updates, in seconds, after which they are considered
+/// stale and pruned (BOLT-7)
+pub const STALE_UPDATE_AGE: u32 = 14 * 24 * 60 * 60;
+
+/// Public channel with validated announcement and funding output
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PublicChannel {
+    pub node_id_1: secp256k1::PublicKey,
+    pub node_id_2: secp256k1::PublicKey,
+    pub capacity_sat: u64,
+    pub funding_outpoint: OutPoint,
+    pub features: Vec<u8>,
+    /// Time the channel was added to the graph
+    pub added: u32,
+    /// Latest updates of the channel directions, from the first and the
+    /// second node
+    pub updates: [Option<ChannelUpdate>; 2],
+    pub announcement: ChannelAnnouncement,
+}
+
+impl PublicChannel {
+    /// Node at the origin of the channel direction
+    pub fn node_id(&self, direction: u8) -> secp256k1::PublicKey {
+        if direction == 0 {
+            self.node_id_1
+        } else {
+            self.node_id_2
+        }
+    }
+}
+
+pub fn unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+/// In-memory graph of the public channels of the lightning network
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct NetworkGraph {
+    channels: BTreeMap<ShortChannelId, PublicChannel>,
+    /// Channels with unspent funding outputs which have not been updated for
+    /// two weeks by any of their nodes; they are not used for routing and
+    /// not relayed until a fresh update arrives
+    zombies: BTreeMap<ShortChannelId, PublicChannel>,
+    /// Channels indexed by their funding outpoints
+    funding: HashMap<OutPoint, ShortChannelId>,
+    /// Channels of each of the nodes
+    nodes: HashMap<secp256k1::PublicKey, BTreeSet<ShortChannelId>>,
+    /// Latest announcements of the nodes
+    announcements: HashMap<secp256k1::PublicKey, NodeAnnouncement>,
+}
+
+impl NetworkGraph {
+    pub fn contains(&self, short_channel_id: ShortChannelId) -> bool {
+        self.channels.contains_key(&short_channel_id)
+            || self.zombies.contains_key(&short_channel_id)
+    }
+
+    /// Number of the zombie channels
+    pub fn zombies(&self) -> usize {
+        self.zombies.len()
+    }
+
+    /// Known channel, which may be a zombie one
+    pub fn channel(
+        &self,
+        short_channel_id: ShortChannelId,
+    ) -> Option<&PublicChannel> {
+        self.channels
+            .get(&short_channel_id)
+            .or_else(|| self.zombies.get(&short_channel_id))
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = &PublicChannel> {
+        self.channels.values()
+    }
+
+    pub fn node_announcements(
+        &self,
+    ) -> impl Iterator<Item = &NodeAnnouncement> {
+        self.announcements.values()
+    }
+
+    /// Latest announcement of the node
+    pub fn node_announcement(
+        &self,
+        node_id: &secp256k1::PublicKey,
+    ) -> Option<&NodeAnnouncement> {
+        self.announcements.get(node_id)
+    }
+
+    /// Whether the node has public channels, so its announcements are
+    /// tracked
+    pub fn has_node(&self, node_id: &secp256k1::PublicKey) -> bool {
+        self.nodes.contains_key(node_id)
+    }
+
+    /// Channels funded within the range of blocks
+    pub fn channels_in_range(
+        &self,
+        first_blocknum: u32,
+        end_blocknum: u32,
+    ) -> Vec<ShortChannelId> {
+        if first_blocknum >= end_blocknum {
+            return vec![];
+        }
+        let from = ShortChannelId {
+            block_height: first_blocknum,
+            tx_index: 0,
+            output_index: 0,
+        };
+        let to = ShortChannelId {
+            block_height: end_blocknum,
+            tx_index: 0,
+            output_index: 0,
+        };
+        self.channels.range(from..to).map(|(id, _)| *id).collect()
+    }
+
+    /// Gossip messages known for the channels, with their TLV records:
+    /// their announcements, followed by the channel updates and the
+    /// announcements of the channel nodes
+    pub fn gossip(
+        &self,
+        short_channel_ids: &[ShortChannelId],
+    ) -> Result<Vec<(Messages, TlvStream)>, presentation::Error> {
+        let mut announcements = vec![];
+        let mut updates = vec![];
+        let mut nodes = HashSet::new();
+        for channel in short_channel_ids
+            .iter()
+            .filter_map(|id| self.channels.get(id))
+        {
+            announcements.push((channel.announcement.message()?, empty!()));
+            for update in channel.updates.iter().flatten() {
+                updates.push((update.message()?, empty!()));
+            }
+            nodes.insert(channel.node_id_1);
+            nodes.insert(channel.node_id_2);
+        }
+        announcements.extend(updates);
+        for announcement in
+            nodes.iter().filter_map(|id| self.announcements.get(id))
+        {
+            announcements
+                .push((announcement.message()?, announcement.tlvs.clone()));
+        }
+        Ok(announcements)
+    }
+
+    /// Number of the known channels and nodes
+    pub fn size(&self) -> (usize, usize) {
+        (self.channels.len(), self.nodes.len())
+    }
+
+    /// Timestamp of the newest channel update or node announcement known
+    pub fn last_update(&self) -> Option<u32> {
+        self.channels
+            .values()
+            .flat_map(|channel| channel.updates.iter().flatten())
+            .map(|update| update.timestamp)
+            .chain(
+                self.announcements
+                    .values()
+                    .map(|announcement| announcement.timestamp),
+            )
+            .max()
+    }
+
+    /// Keeps node announcement if it is newer than the known one. Nodes
+    /// without public channels are not tracked (BOLT-7), so their
+    /// announcements are ignored. Returns whether the announcement is kept.
+    pub fn update_node(&mut self, announcement: &NodeAnnouncement) -> bool {
+        if !self.nodes.contains_key(&announcement.node_id) {
+            return false;
+        }
+        match self.announcements.get(&announcement.node_id) {
+            Some(known) if known.timestamp >= announcement.timestamp => false,
+            _ => {
+                self.announcements
+                    .insert(announcement.node_id, announcement.clone());
+                true
+            }
+        }
+    }
+
+    /// Nodes having public channels, with the information from their latest
+    /// announcements
+    pub fn nodes(&self) -> Vec<AnnouncedNode> {
+        self.nodes
+            .keys()
+            .filter_map(|node_id| self.node_info(node_id))
+            .collect()
+    }
+
+    fn node_info(
+        &self,
+        node_id: &secp256k1::PublicKey,
+    ) -> Option<AnnouncedNode> {
+        let channels = self.nodes.get(node_id)?;
+        let announcement = self.announcements.get(node_id);
+        Some(AnnouncedNode {
+            node_id: *node_id,
+            timestamp: announcement
+                .map(|announcement| announcement.timestamp)
+                .unwrap_or_default(),
+            alias: announcement
+                .map(|announcement| announcement.alias.clone())
+                .unwrap_or_default(),
+            color: announcement
+                .map(|announcement| {
+                    let [r, g, b] = announcement.rgb_color;
+                    format!("#{:02x}{:02x}{:02x}", r, g, b)
+                })
+                .unwrap_or_default(),
+            features: announcement
+                .map(|announcement| feature_bits(&announcement.features))
+                .unwrap_or_default(),
+            addresses: announcement
+                .map(|announcement| announcement.addresses.clone())
+                .unwrap_or_default(),
+            channels: channels.len(),
+        })
+    }
+
+    /// Describes the channels passing the filter, which are not zombies,
+    /// together with their nodes
+    pub fn describe(&self, filter: GraphFilter) -> GraphInfo {
+        let edges = self
+            .channels
+            .iter()
+            .filter(|(_, channel)| match filter.node_id {
+                Some(node_id) => {
+                    channel.node_id_1 == node_id || channel.node_id_2 == node_id
+                }
+                None => true,
+            })
+            .filter(|(_, channel)| {
+                !filter.active_only
+                    || channel
+                        .updates
+                        .iter()
+                        .flatten()
+                        .any(|update| !update.disabled)
+            })
+            .map(|(short_channel_id, channel)| {
+                let policy = |direction: usize| {
+                    channel.updates[direction].as_ref().map(|update| {
+                        EdgePolicy {
+                            timestamp: update.timestamp,
+                            disabled: update.disabled,
+                            policy: update.policy,
+                        }
+                    })
+                };
+                GraphEdge {
+                    short_channel_id: *short_channel_id,
+                    node_id_1: channel.node_id_1,
+                    node_id_2: channel.node_id_2,
+                    capacity_sat: channel.capacity_sat,
+                    policy_1: policy(0),
+                    policy_2: policy(1),
+                }
+            })
+            .collect::<Vec<_>>();
+        let nodes = match filter.node_id {
+            None => self.nodes(),
+            Some(node_id) => {
+                let mut node_ids = edges
+                    .iter()
+                    .flat_map(|edge| vec![edge.node_id_1, edge.node_id_2])
+                    .collect::<HashSet<_>>();
+                node_ids.insert(node_id);
+                node_ids
+                    .iter()
+                    .filter_map(|node_id| self.node_info(node_id))
+                    .collect()
+            }
+        };
+        GraphInfo { nodes, edges }
+    }
+
+    /// Adds channel which announcement and funding output are validated
+    pub fn insert(
+        &mut self,
+        announcement: ChannelAnnouncement,
+        capacity_sat: u64,
+        funding_outpoint: OutPoint,
+    ) {
+        let short_channel_id = announcement.short_channel_id;
+        self.funding.insert(funding_outpoint, short_channel_id);
+        self.link(PublicChannel {
+            node_id_1: announcement.node_id_1,
+            node_id_2: announcement.node_id_2,
+            capacity_sat,
+            funding_outpoint,
+            features: announcement.features.clone(),
+            added: unix_time(),
+            updates: [None, None],
+            announcement,
+        });
+    }
+
+    /// Adds the channel to the graph and to the channel lists of its nodes
+    fn link(&mut self, channel: PublicChannel) {
+        let short_channel_id = channel.announcement.short_channel_id;
+        for node_id in &[channel.node_id_1, channel.node_id_2] {
+            self.nodes
+                .entry(*node_id)
+                .or_default()
+                .insert(short_channel_id);
+        }
+        self.channels.insert(short_channel_id, channel);
+    }
+
+    /// Removes the channel from the graph and from the channel lists of its
+    /// nodes, forgetting the nodes left without channels
+    fn unlink(
+        &mut self,
+        short_channel_id: ShortChannelId,
+    ) -> Option<PublicChannel> {
+        let channel = self.channels.remove(&short_channel_id)?;
+        for node_id in &[channel.node_id_1, channel.node_id_2] {
+            let orphan = match self.nodes.get_mut(node_id) {
+                Some(channels) => {
+                    channels.remove(&short_channel_id);
+                    channels.is_empty()
+                }
+                None => false,
+            };
+            if orphan {
+                self.nodes.remove(node_id);
+                self.announcements.remove(node_id);
+            }
+        }
+        Some(channel)
+    }
+
+    /// Keeps channel update if it is newer than the known update of the
+    /// same channel direction and is not stale, resurrecting zombie channel.
+    /// Returns whether the update is kept.
+    pub fn update_channel(&mut self, update: &ChannelUpdate) -> bool {
+        if update.timestamp.saturating_add(STALE_UPDATE_AGE) < unix_time() {
+            return false;
+        }
+        if let Some(zombie) = self.zombies.remove(&update.short_channel_id) {
+            debug!("Zombie channel {} is resurrected", update.short_channel_id);
+            self.link(zombie);
+        }
+        let channel = match self.channels.get_mut(&update.short_channel_id) {
+            Some(channel) => channel,
+            None => return false,
+        };
+        let known = &mut channel.updates[update.direction as usize];
+        match known {
+            Some(known) if known.timestamp >= update.timestamp => false,
+            _ => {
+                *known = Some(update.clone());
+                true
+            }
+        }
+    }
+
+    /// Forgets channel updates older than two weeks and turns the channels
+    /// which have not been updated for that time into zombies. Returns the
+    /// number of new zombie channels.
+    pub fn prune(&mut self) -> usize {
+        let threshold = unix_time().saturating_sub(STALE_UPDATE_AGE);
+        let mut stale = vec![];
+        for (short_channel_id, channel) in self.channels.iter_mut() {
+            for update in channel.updates.iter_mut() {
+                if matches!(update, Some(known) if known.timestamp < threshold)
+                {
+                    *update = None;
+                }
+            }
+            if channel.added < threshold
+                && channel.updates.iter().all(Option::is_none)
+            {
+                stale.push(*short_channel_id);
+            }
+        }
+        for short_channel_id in &stale {
+            if let Some(channel) = self.unlink(*short_channel_id) {
+                self.zombies.insert(*short_channel_id, channel);
+            }
+        }
+        stale.len()
+    }
+
+    /// Removes the channel, live or zombie, which funding output is spent
+    pub fn funding_spent(
+        &mut self,
+        outpoint: &OutPoint,
+    ) -> Option<ShortChannelId> {
+        let short_channel_id = self.funding.remove(outpoint)?;
+        if self.zombies.remove(&short_channel_id).is_none() {
+            self.unlink(short_channel_id);
+        }
+        Some(short_channel_id)
+    }
+}
@@ -12,10 +12,18 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+mod announcements;
+mod graph;
 #[cfg(feature = "shell")]
 mod opts;
+mod queries;
 mod runtime;
+mod store;
 
+pub use announcements::{
+    AnnouncementSignatures, GossipError, GossipTimestampFilter,
+    UnsignedChannelAnnouncement,
+};
 #[cfg(feature = "shell")]
 pub use opts::Opts;
 pub use runtime::run;
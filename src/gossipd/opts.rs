@@ -14,6 +14,8 @@
 
 use clap::{AppSettings, Clap};
 
+use crate::peerd::KeyOpts;
+
 /// Lightning peer network gossip daemon; part of LNP Node
 ///
 /// The daemon is controlled though ZMQ ctl socket (see `ctl-socket` argument
@@ -27,6 +29,10 @@ use clap::{AppSettings, Clap};
     setting = AppSettings::ColoredHelp
 )]
 pub struct Opts {
+    /// Node key configuration
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
     /// RGB configuration: ignored by this daemon
     #[clap(short, long = "rgb20-rpc")]
     pub r: Option<String>,
@@ -39,6 +45,7 @@ pub struct Opts {
 
 impl Opts {
     pub fn process(&mut self) {
-        self.shared.process()
+        self.shared.process();
+        self.key_opts.process(&self.shared);
     }
 }
@@ -0,0 +1,236 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! BOLT-7 gossip queries, read from and written to their wire encoding in
+//! the same way as the announcements.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use internet2::{presentation, TypedEnum};
+use lnp::Messages;
+
+use super::announcements::{unmarshall, GossipError, Reader};
+use crate::rpc::request::ShortChannelId;
+
+const QUERY_SHORT_CHANNEL_IDS: u16 = 261;
+const REPLY_SHORT_CHANNEL_IDS_END: u16 = 262;
+const QUERY_CHANNEL_RANGE: u16 = 263;
+const REPLY_CHANNEL_RANGE: u16 = 264;
+
+/// Maximal number of short channel ids fitting into a single message
+pub const MAX_SHORT_IDS: usize = 8000;
+
+/// Short channel ids encoded as an array of 8-byte integers
+const ENCODING_UNCOMPRESSED: u8 = 0;
+
+/// Request for the channels funded in the range of blocks
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display)]
+#[display("blocks {first_blocknum}+{number_of_blocks}")]
+pub struct QueryChannelRange {
+    pub chain_hash: BlockHash,
+    pub first_blocknum: u32,
+    pub number_of_blocks: u32,
+}
+
+impl QueryChannelRange {
+    /// Reads the query from `query_channel_range` message
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        let data = message.serialize();
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        Ok(QueryChannelRange {
+            chain_hash: BlockHash::from_slice(reader.bytes(32)?)
+                .expect("slice has hash size"),
+            first_blocknum: reader.u32()?,
+            number_of_blocks: reader.u32()?,
+        })
+    }
+
+    /// Composes `query_channel_range` message
+    pub fn to_message(&self) -> Result<Messages, presentation::Error> {
+        let mut data = QUERY_CHANNEL_RANGE.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.chain_hash[..]);
+        data.extend_from_slice(&self.first_blocknum.to_be_bytes());
+        data.extend_from_slice(&self.number_of_blocks.to_be_bytes());
+        unmarshall(&data)
+    }
+
+    /// Block following the queried range
+    pub fn end_blocknum(&self) -> u32 {
+        self.first_blocknum.saturating_add(self.number_of_blocks)
+    }
+}
+
+/// Channels funded in the range of blocks, sent in reply to
+/// `query_channel_range`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ReplyChannelRange {
+    pub chain_hash: BlockHash,
+    pub first_blocknum: u32,
+    pub number_of_blocks: u32,
+    pub sync_complete: bool,
+    pub short_channel_ids: Vec<ShortChannelId>,
+}
+
+impl ReplyChannelRange {
+    /// Reads the reply from `reply_channel_range` message
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        let data = message.serialize();
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        let chain_hash = BlockHash::from_slice(reader.bytes(32)?)
+            .expect("slice has hash size");
+        let first_blocknum = reader.u32()?;
+        let number_of_blocks = reader.u32()?;
+        let sync_complete = reader.bytes(1)?[0] != 0;
+        let len = reader.u16()? as usize;
+        Ok(ReplyChannelRange {
+            chain_hash,
+            first_blocknum,
+            number_of_blocks,
+            sync_complete,
+            short_channel_ids: decode_short_ids(reader.bytes(len)?)?,
+        })
+    }
+
+    /// Composes `reply_channel_range` message
+    pub fn to_message(&self) -> Result<Messages, presentation::Error> {
+        let mut data = REPLY_CHANNEL_RANGE.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.chain_hash[..]);
+        data.extend_from_slice(&self.first_blocknum.to_be_bytes());
+        data.extend_from_slice(&self.number_of_blocks.to_be_bytes());
+        data.push(self.sync_complete as u8);
+        encode_short_ids(&mut data, &self.short_channel_ids);
+        unmarshall(&data)
+    }
+}
+
+/// Request for the announcements and updates of the channels
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QueryShortChannelIds {
+    pub chain_hash: BlockHash,
+    pub short_channel_ids: Vec<ShortChannelId>,
+}
+
+impl QueryShortChannelIds {
+    /// Reads the query from `query_short_channel_ids` message
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        let data = message.serialize();
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        let chain_hash = BlockHash::from_slice(reader.bytes(32)?)
+            .expect("slice has hash size");
+        let len = reader.u16()? as usize;
+        Ok(QueryShortChannelIds {
+            chain_hash,
+            short_channel_ids: decode_short_ids(reader.bytes(len)?)?,
+        })
+    }
+
+    /// Composes `query_short_channel_ids` message
+    pub fn to_message(&self) -> Result<Messages, presentation::Error> {
+        let mut data = QUERY_SHORT_CHANNEL_IDS.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.chain_hash[..]);
+        encode_short_ids(&mut data, &self.short_channel_ids);
+        unmarshall(&data)
+    }
+}
+
+/// End of the gossip sent in reply to `query_short_channel_ids`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReplyShortChannelIdsEnd {
+    pub chain_hash: BlockHash,
+    pub full_information: bool,
+}
+
+impl ReplyShortChannelIdsEnd {
+    /// Reads the reply from `reply_short_channel_ids_end` message
+    pub fn with(message: &Messages) -> Result<Self, GossipError> {
+        let data = message.serialize();
+        let mut reader = Reader::with(data.get(2..).unwrap_or_default());
+        Ok(ReplyShortChannelIdsEnd {
+            chain_hash: BlockHash::from_slice(reader.bytes(32)?)
+                .expect("slice has hash size"),
+            full_information: reader.bytes(1)?[0] != 0,
+        })
+    }
+
+    /// Composes `reply_short_channel_ids_end` message
+    pub fn to_message(&self) -> Result<Messages, presentation::Error> {
+        let mut data = REPLY_SHORT_CHANNEL_IDS_END.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.chain_hash[..]);
+        data.push(self.full_information as u8);
+        unmarshall(&data)
+    }
+}
+
+/// Initial sync of the network graph with the remote peer: the channels
+/// learned from `reply_channel_range` are queried in batches, each awaiting
+/// the reply to the previous one
+#[derive(Clone, Debug)]
+pub struct GossipSync {
+    /// Channels which are not queried yet
+    pub queue: VecDeque<ShortChannelId>,
+    /// Query of the short channel ids awaits the end of the reply
+    pub awaiting_reply: bool,
+    /// All replies to the channel range query are received
+    pub range_complete: bool,
+    /// Time of the last reply from the peer
+    pub updated: Instant,
+}
+
+impl Default for GossipSync {
+    fn default() -> Self {
+        GossipSync {
+            queue: none!(),
+            awaiting_reply: false,
+            range_complete: false,
+            updated: Instant::now(),
+        }
+    }
+}
+
+impl GossipSync {
+    pub fn is_complete(&self) -> bool {
+        self.range_complete && !self.awaiting_reply && self.queue.is_empty()
+    }
+}
+
+/// Reads encoded short channel ids. Only the uncompressed encoding is
+/// supported, since zlib encoding is deprecated by BOLT-7.
+fn decode_short_ids(data: &[u8]) -> Result<Vec<ShortChannelId>, GossipError> {
+    let mut reader = Reader::with(data);
+    if data.is_empty() {
+        return Ok(vec![]);
+    }
+    match reader.bytes(1)?[0] {
+        ENCODING_UNCOMPRESSED => {}
+        encoding => return Err(GossipError::UnsupportedEncoding(encoding)),
+    }
+    let mut short_channel_ids = Vec::with_capacity((data.len() - 1) / 8);
+    while !reader.rest().is_empty() {
+        short_channel_ids.push(ShortChannelId::from(reader.u64()?));
+    }
+    Ok(short_channel_ids)
+}
+
+/// Writes length-prefixed uncompressed encoding of the short channel ids
+fn encode_short_ids(data: &mut Vec<u8>, short_channel_ids: &[ShortChannelId]) {
+    let len = 1 + short_channel_ids.len() * 8;
+    data.extend_from_slice(&(len as u16).to_be_bytes());
+    data.push(ENCODING_UNCOMPRESSED);
+    for short_channel_id in short_channel_ids {
+        data.extend_from_slice(&u64::from(*short_channel_id).to_be_bytes());
+    }
+}
@@ -12,24 +12,190 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use internet2::TypedEnum;
+use std::collections::{HashMap, HashSet};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::{secp256k1, BlockHash};
+use internet2::{zmqsocket, LocalNode, TypedEnum, ZmqType, ZMQ_CONTEXT};
+use lnp::{ChannelId, Messages};
+use lnpbp::strict_encoding::{strict_deserialize, strict_serialize};
 use microservices::esb;
+use microservices::rpc::Failure;
 
+use super::announcements::{
+    feature_vector, ChannelAnnouncement, ChannelUpdate, GossipError,
+    GossipTimestampFilter, NodeAdvert, NodeAnnouncement,
+};
+use super::graph::{unix_time, NetworkGraph, STALE_UPDATE_AGE};
+use super::queries::{
+    GossipSync, QueryChannelRange, QueryShortChannelIds, ReplyChannelRange,
+    ReplyShortChannelIdsEnd, MAX_SHORT_IDS,
+};
+use super::store::GraphStore;
+use crate::rpc::request::{
+    ChannelOutput, ChannelPolicyUpdate, ForwardingPolicy, GossipBroadcast,
+    LeaseQuote, LeaseRates, LeaseRequest, NodeAddresses, NodeLease,
+    ShortChannelId,
+};
+use crate::rpc::tlv::{self, TlvStream};
 use crate::rpc::{Request, ServiceBus};
-use crate::{Config, Error, Service, ServiceId};
+use crate::service::BridgeHandler;
+use crate::{Config, CtlServer, Error, LogStyle, Service, ServiceId};
+
+/// Number of gossip messages remembered for not broadcasting them twice;
+/// once reached, the memory is reset
+const MAX_SEEN_GOSSIP: usize = 100_000;
+
+/// Number of channel announcements awaiting validation of their funding
+/// outputs; announcements above the limit are dropped
+const MAX_PENDING_CHANNELS: usize = 1_000;
+
+/// Period of pruning stale channel updates from the network graph
+const PRUNE_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Number of peers the network graph is synced from with gossip queries
+const GOSSIP_SYNC_PEERS: usize = 3;
+
+/// Number of channels queried from the syncing peer at once, keeping the
+/// replies within the gossip rate limits of peerd
+const GOSSIP_SYNC_BATCH: usize = 100;
+
+/// Time after which a peer not replying to the gossip queries is replaced
+/// with another one
+const GOSSIP_SYNC_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Minimal interval in seconds between our own node announcements, which
+/// other nodes would otherwise throttle
+const NODE_ANNOUNCEMENT_INTERVAL: u32 = 600;
+
+pub fn run(config: Config, local_node: LocalNode) -> Result<(), Error> {
+    let node_id = local_node.node_id();
+    // Nodes without configured color are told apart by the color derived
+    // from their node id
+    let mut default_color = [0u8; 3];
+    default_color.copy_from_slice(&node_id.serialize()[1..4]);
+    let advert = NodeAdvert {
+        features: feature_vector(
+            crate::lnpd::node_features(&config).keys().copied(),
+        ),
+        rgb_color: config.rgb_color.unwrap_or(default_color),
+        alias: config.alias.clone().unwrap_or_default(),
+        addresses: config.announce_addrs.clone(),
+    };
+
+    let (store, graph) = GraphStore::open(&config.gossip_store)?;
 
-pub fn run(config: Config) -> Result<(), Error> {
     let runtime = Runtime {
         identity: ServiceId::Gossip,
+        chain_hash: config.chain.clone().chain_params().genesis_hash,
+        local_node,
+        node_id,
+        advert,
+        advert_changed: true,
+        leases: none!(),
+        local_policies: none!(),
+        seen_gossip: none!(),
+        graph,
+        store,
+        pending_channels: none!(),
+        next_prune: Instant::now() + PRUNE_PERIOD,
+        syncs: none!(),
+        outbox: none!(),
+        outbox_index: none!(),
     };
 
-    Service::run(config, runtime, false)
+    debug!("Opening bridge between runtime and timer threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    tx.connect("inproc://gossipd-timer")?;
+    rx.bind("inproc://gossipd-timer")?;
+
+    let mut timer = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    let broadcast_interval = config.gossip_broadcast_interval;
+    spawn(move || loop {
+        sleep(broadcast_interval);
+        if let Err(err) = timer.send_to(
+            ServiceBus::Bridge,
+            ServiceId::Gossip,
+            Request::FlushGossip,
+        ) {
+            error!("Unable to notify gossip runtime on timer: {}", err);
+        }
+    });
+
+    let mut service = Service::service(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
+}
+
+/// Subject of the gossip message; newer messages on the same subject replace
+/// the older ones awaiting broadcast
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum GossipSubject {
+    Channel(ShortChannelId),
+    Direction(ShortChannelId, u8),
+    Node(secp256k1::PublicKey),
 }
 
 pub struct Runtime {
     identity: ServiceId,
+    /// Genesis hash of the chain which channels are accepted
+    chain_hash: BlockHash,
+    local_node: LocalNode,
+    node_id: secp256k1::PublicKey,
+    /// Data announced about our node
+    advert: NodeAdvert,
+    /// Whether our node announcement is to be (re)signed and broadcasted
+    advert_changed: bool,
+    /// Liquidity lease rates advertised by remote nodes
+    leases: HashMap<secp256k1::PublicKey, LeaseRates>,
+    /// Forwarding policies of our channels
+    local_policies: HashMap<ChannelId, ForwardingPolicy>,
+    /// Hashes of the gossip messages which were already broadcasted
+    seen_gossip: HashSet<sha256::Hash>,
+    /// Public channels with validated announcements
+    graph: NetworkGraph,
+    store: GraphStore,
+    /// Channel announcements with valid signatures awaiting the chain
+    /// service to report their funding outputs, with the original messages
+    /// and the peers they came from
+    pending_channels: HashMap<
+        ShortChannelId,
+        (ChannelAnnouncement, Messages, Option<ServiceId>),
+    >,
+    next_prune: Instant,
+    /// Graph syncs with the remote peers
+    syncs: HashMap<ServiceId, GossipSync>,
+    /// Validated gossip awaiting the next broadcast, with its TLV records
+    /// and the peers it came from, in the order of arrival
+    outbox: Vec<Option<(Messages, TlvStream, Option<ServiceId>)>>,
+    /// Positions of the outbox messages by their subjects
+    outbox_index: HashMap<GossipSubject, usize>,
+}
+
+/// Gossip data learned by the daemon, exported for node migration
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct GossipState {
+    leases: Vec<NodeLease>,
+    local_policies: Vec<ChannelPolicyUpdate>,
 }
 
+impl CtlServer for Runtime {}
+
 impl esb::Handler<ServiceBus> for Runtime {
     type Request = Request;
     type Address = ServiceId;
@@ -49,9 +215,7 @@ impl esb::Handler<ServiceBus> for Runtime {
         match bus {
             ServiceBus::Msg => self.handle_rpc_msg(senders, source, request),
             ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
-            _ => {
-                Err(Error::NotSupported(ServiceBus::Bridge, request.get_type()))
-            }
+            ServiceBus::Bridge => self.handle_bridge(senders, request),
         }
     }
 
@@ -64,16 +228,540 @@ impl esb::Handler<ServiceBus> for Runtime {
 }
 
 impl Runtime {
+    /// Queues the gossip message for the next broadcast to the connected
+    /// peers, unless it was already relayed before. Queued message on the
+    /// same subject is replaced.
+    fn broadcast(
+        &mut self,
+        subject: GossipSubject,
+        message: Messages,
+        tlvs: TlvStream,
+        origin: Option<ServiceId>,
+    ) -> Result<(), Error> {
+        let mut data = strict_serialize(&message)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        data.extend(tlv::encode(&tlvs));
+        if self.seen_gossip.len() >= MAX_SEEN_GOSSIP {
+            self.seen_gossip.clear();
+        }
+        if !self.seen_gossip.insert(sha256::Hash::hash(&data)) {
+            trace!("Gossip {} is already broadcasted", message);
+            return Ok(());
+        }
+        match self.outbox_index.get(&subject) {
+            Some(pos) => self.outbox[*pos] = Some((message, tlvs, origin)),
+            None => {
+                self.outbox_index.insert(subject, self.outbox.len());
+                self.outbox.push(Some((message, tlvs, origin)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Relays the queued gossip to the connected peers through lnpd; peer
+    /// daemons skip the messages not passing the gossip filters of the peers
+    fn flush_broadcast(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+    ) -> Result<(), Error> {
+        self.outbox_index.clear();
+        let outbox = self.outbox.drain(..).flatten().collect::<Vec<_>>();
+        if outbox.is_empty() {
+            return Ok(());
+        }
+        debug!("Broadcasting {} gossip message(s)", outbox.len());
+        for (message, tlvs, origin) in outbox {
+            self.send_ctl(
+                senders,
+                ServiceId::Lnpd,
+                Request::BroadcastGossip(GossipBroadcast {
+                    message,
+                    tlvs,
+                    origin,
+                }),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn handle_bridge(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::FlushGossip => {
+                self.announce_node()?;
+                self.flush_broadcast(senders)?;
+                if self.next_prune <= Instant::now() {
+                    self.next_prune = Instant::now() + PRUNE_PERIOD;
+                    self.prune()?;
+                }
+            }
+            _ => {
+                error!("Request is not supported by the BRIDGE interface");
+                return Err(Error::NotSupported(
+                    ServiceBus::Bridge,
+                    request.get_type(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Signs and queues for broadcast announcement of our node, once it has
+    /// public channels and the advertised data have changed since the last
+    /// announcement
+    fn announce_node(&mut self) -> Result<(), Error> {
+        if !self.advert_changed || !self.graph.has_node(&self.node_id) {
+            return Ok(());
+        }
+        let timestamp = unix_time();
+        if let Some(known) = self.graph.node_announcement(&self.node_id) {
+            if timestamp
+                < known.timestamp.saturating_add(NODE_ANNOUNCEMENT_INTERVAL)
+            {
+                return Ok(());
+            }
+        }
+        let (message, tlvs) = self.advert.sign(&self.local_node, timestamp)?;
+        let announcement = NodeAnnouncement::with_tlvs(&message, tlvs.clone())?;
+        if !self.graph.update_node(&announcement) {
+            return Ok(());
+        }
+        self.store.node_updated(&announcement)?;
+        self.advert_changed = false;
+        info!(
+            "{} {} as {:?}",
+            "Announcing node".promo(),
+            self.node_id.promoter(),
+            self.advert.alias
+        );
+        self.broadcast(GossipSubject::Node(self.node_id), message, tlvs, None)
+    }
+
+    /// Turns stale channels into zombies and compacts the graph store
+    fn prune(&mut self) -> Result<(), Error> {
+        let pruned = self.graph.prune();
+        let (channels, nodes) = self.graph.size();
+        debug!(
+            "{} stale channels became zombies; network graph has {} \
+             channels between {} nodes and {} zombie channels",
+            pruned,
+            channels,
+            nodes,
+            self.graph.zombies()
+        );
+        self.store.compact(&self.graph)
+    }
+
+    /// Checks signatures of the channel announcement and requests the chain
+    /// service for the funding output of the channel; the announcement is
+    /// relayed once the output is validated
+    fn channel_announced(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        message: Messages,
+        origin: ServiceId,
+    ) -> Result<(), Error> {
+        let announcement = ChannelAnnouncement::with(&message)?;
+        let short_channel_id = announcement.short_channel_id;
+        if self.graph.contains(short_channel_id)
+            || self.pending_channels.contains_key(&short_channel_id)
+        {
+            trace!("Channel {} is already known", short_channel_id);
+            return Ok(());
+        }
+        announcement.verify(self.chain_hash)?;
+        if self.pending_channels.len() >= MAX_PENDING_CHANNELS {
+            return Err(Error::Other(s!(
+                "too many channels await validation of their funding outputs"
+            )));
+        }
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::GetChannelOutput(short_channel_id),
+        )?;
+        self.pending_channels
+            .insert(short_channel_id, (announcement, message, Some(origin)));
+        Ok(())
+    }
+
+    /// Checks signature of the node announcement and, if it is the newest
+    /// one for the node, reports the node addresses to lnpd, records the
+    /// liquidity advertised by the node and relays the announcement
+    fn node_announced(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        message: Messages,
+        tlvs: TlvStream,
+        origin: ServiceId,
+    ) -> Result<(), Error> {
+        let announcement = NodeAnnouncement::with_tlvs(&message, tlvs)?;
+        announcement.verify()?;
+        let node_id = announcement.node_id;
+        if !self.graph.update_node(&announcement) {
+            trace!("Announcement of node {} is outdated or unused", node_id);
+            return Ok(());
+        }
+        self.store.node_updated(&announcement)?;
+        match announcement.lease_rates {
+            Some(rates) => {
+                if self.leases.insert(node_id, rates) != Some(rates) {
+                    debug!("Node {} advertises liquidity: {}", node_id, rates);
+                }
+            }
+            None => {
+                if self.leases.remove(&node_id).is_some() {
+                    debug!("Node {} stops advertising liquidity", node_id);
+                }
+            }
+        }
+        let tlvs = announcement.tlvs;
+        let addrs = announcement.addresses;
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::NodeAddresses(NodeAddresses { node_id, addrs }),
+        )?;
+        let subject = GossipSubject::Node(node_id);
+        self.broadcast(subject, message, tlvs, Some(origin))
+    }
+
+    /// Checks signature of the channel update and, if it is the newest one
+    /// for the channel direction, relays it
+    fn channel_updated(
+        &mut self,
+        message: Messages,
+        origin: ServiceId,
+    ) -> Result<(), Error> {
+        let update = ChannelUpdate::with(&message)?;
+        let short_channel_id = update.short_channel_id;
+        let node_id = match self.graph.channel(short_channel_id) {
+            Some(channel) => channel.node_id(update.direction),
+            None => {
+                trace!("Update of unknown channel {}", short_channel_id);
+                return Ok(());
+            }
+        };
+        update.verify(self.chain_hash, &node_id)?;
+        if !self.graph.update_channel(&update) {
+            trace!("Update of channel {} is outdated", short_channel_id);
+            return Ok(());
+        }
+        self.store.channel_updated(&update)?;
+        let subject =
+            GossipSubject::Direction(short_channel_id, update.direction);
+        self.broadcast(subject, message, empty!(), Some(origin))
+    }
+
+    /// Asks the remote peer to relay only the gossip newer than the one
+    /// already known, or not older than the staleness threshold if the
+    /// network graph is empty
+    fn filter_gossip(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        peerd: ServiceId,
+    ) -> Result<(), Error> {
+        let oldest = unix_time().saturating_sub(STALE_UPDATE_AGE);
+        let filter = GossipTimestampFilter {
+            chain_hash: self.chain_hash,
+            first_timestamp: self
+                .graph
+                .last_update()
+                .map(|timestamp| timestamp.max(oldest))
+                .unwrap_or(oldest),
+            timestamp_range: u32::MAX,
+        };
+        debug!("Requesting {} to filter gossip by {}", peerd, filter);
+        senders.send_to(
+            ServiceBus::Msg,
+            self.identity(),
+            peerd,
+            Request::PeerMessage(filter.to_message()?),
+        )?;
+        Ok(())
+    }
+
+    /// Sends the gossip message to the remote peer directly, bypassing
+    /// lnpd broadcast and gossip filter of the peer
+    fn send_peer(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        peerd: ServiceId,
+        message: Messages,
+    ) -> Result<(), Error> {
+        senders.send_to(
+            ServiceBus::Msg,
+            self.identity(),
+            peerd,
+            Request::PeerMessage(message),
+        )?;
+        Ok(())
+    }
+
+    /// Replies with the channels funded in the queried range of blocks,
+    /// splitting them into several messages without breaking the blocks
+    fn channel_range_queried(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        source: ServiceId,
+        query: QueryChannelRange,
+    ) -> Result<(), Error> {
+        let end = query.end_blocknum();
+        let short_channel_ids = if query.chain_hash == self.chain_hash {
+            self.graph.channels_in_range(query.first_blocknum, end)
+        } else {
+            vec![]
+        };
+        let mut chunks: Vec<Vec<ShortChannelId>> = vec![vec![]];
+        for short_channel_id in short_channel_ids {
+            let chunk = chunks.last_mut().expect("chunks are never empty");
+            let block_ends = chunk
+                .last()
+                .map(|last| last.block_height != short_channel_id.block_height)
+                .unwrap_or_default();
+            if block_ends && chunk.len() >= MAX_SHORT_IDS {
+                chunks.push(vec![short_channel_id]);
+            } else {
+                chunk.push(short_channel_id);
+            }
+        }
+        let mut first_blocknum = query.first_blocknum;
+        let count = chunks.len();
+        for (index, short_channel_ids) in chunks.into_iter().enumerate() {
+            let last_blocknum = match short_channel_ids.last() {
+                Some(last) if index + 1 < count => last.block_height + 1,
+                _ => end,
+            };
+            let reply = ReplyChannelRange {
+                chain_hash: query.chain_hash,
+                first_blocknum,
+                number_of_blocks: last_blocknum - first_blocknum,
+                sync_complete: query.chain_hash == self.chain_hash,
+                short_channel_ids,
+            };
+            first_blocknum = last_blocknum;
+            self.send_peer(senders, source.clone(), reply.to_message()?)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the announcements and updates of the queried channels, with the
+    /// announcements of their nodes
+    fn short_ids_queried(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        source: ServiceId,
+        query: QueryShortChannelIds,
+    ) -> Result<(), Error> {
+        let full_information = query.chain_hash == self.chain_hash;
+        if full_information {
+            for (message, tlvs) in
+                self.graph.gossip(&query.short_channel_ids)?
+            {
+                senders.send_to(
+                    ServiceBus::Msg,
+                    self.identity(),
+                    source.clone(),
+                    Request::with_tlvs(message, tlvs),
+                )?;
+            }
+        }
+        let reply = ReplyShortChannelIdsEnd {
+            chain_hash: query.chain_hash,
+            full_information,
+        };
+        self.send_peer(senders, source, reply.to_message()?)
+    }
+
+    /// Starts graph sync with the remote peer, unless the graph is already
+    /// synced with enough peers
+    fn start_sync(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        peerd: ServiceId,
+    ) -> Result<(), Error> {
+        self.syncs.retain(|peerd, sync| {
+            let alive = sync.is_complete()
+                || sync.updated.elapsed() < GOSSIP_SYNC_TIMEOUT;
+            if !alive {
+                warn!("Graph sync with {} has timed out", peerd);
+            }
+            alive
+        });
+        if self.syncs.len() >= GOSSIP_SYNC_PEERS
+            || self.syncs.contains_key(&peerd)
+        {
+            return Ok(());
+        }
+        info!("{} network graph with {}", "Syncing".promo(), peerd);
+        let query = QueryChannelRange {
+            chain_hash: self.chain_hash,
+            first_blocknum: 0,
+            number_of_blocks: u32::MAX,
+        };
+        self.send_peer(senders, peerd.clone(), query.to_message()?)?;
+        self.syncs.insert(peerd, GossipSync::default());
+        Ok(())
+    }
+
+    /// Queues the channels unknown to us for the queries to the syncing
+    /// peer
+    fn channel_range_replied(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        source: ServiceId,
+        reply: ReplyChannelRange,
+    ) -> Result<(), Error> {
+        let sync = match self.syncs.get_mut(&source) {
+            Some(sync) if reply.chain_hash == self.chain_hash => sync,
+            _ => {
+                debug!("Ignoring unrequested channel range from {}", source);
+                return Ok(());
+            }
+        };
+        let graph = &self.graph;
+        let pending = &self.pending_channels;
+        sync.queue.extend(
+            reply
+                .short_channel_ids
+                .into_iter()
+                .filter(|id| !graph.contains(*id) && !pending.contains_key(id)),
+        );
+        sync.updated = Instant::now();
+        sync.range_complete |=
+            reply.first_blocknum.saturating_add(reply.number_of_blocks)
+                == u32::MAX;
+        debug!(
+            "Peer {} has {} channels unknown to us",
+            source,
+            sync.queue.len()
+        );
+        self.query_short_ids(senders, source)
+    }
+
+    /// Queries the next batch of channels from the syncing peer, unless it
+    /// is replying to the previous query
+    fn query_short_ids(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        peerd: ServiceId,
+    ) -> Result<(), Error> {
+        let sync = match self.syncs.get_mut(&peerd) {
+            Some(sync) if !sync.awaiting_reply => sync,
+            _ => return Ok(()),
+        };
+        let graph = &self.graph;
+        let mut short_channel_ids = Vec::with_capacity(GOSSIP_SYNC_BATCH);
+        while short_channel_ids.len() < GOSSIP_SYNC_BATCH {
+            match sync.queue.pop_front() {
+                Some(id) if graph.contains(id) => {}
+                Some(id) => short_channel_ids.push(id),
+                None => break,
+            }
+        }
+        if short_channel_ids.is_empty() {
+            if sync.is_complete() {
+                let (channels, nodes) = self.graph.size();
+                info!(
+                    "Network graph is {} with {}: {} channels between {} nodes",
+                    "synced".ended(),
+                    peerd,
+                    channels,
+                    nodes
+                );
+            }
+            return Ok(());
+        }
+        sync.awaiting_reply = true;
+        let query = QueryShortChannelIds {
+            chain_hash: self.chain_hash,
+            short_channel_ids,
+        };
+        self.send_peer(senders, peerd, query.to_message()?)
+    }
+
     fn handle_rpc_msg(
         &mut self,
-        _senders: &mut esb::SenderList<ServiceBus, ServiceId>,
-        _source: ServiceId,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        source: ServiceId,
         request: Request,
     ) -> Result<(), Error> {
+        let (request, tlvs) = request.extract_tlvs();
         match request {
-            Request::PeerMessage(_message) => {
-                // TODO: Process message
+            Request::PeerMessage(
+                message @ Messages::ChannelAnnouncement(_),
+            ) => {
+                debug!("Got gossip {} from {}", message, source);
+                if let Err(err) =
+                    self.channel_announced(senders, message, source.clone())
+                {
+                    warn!(
+                        "Ignoring channel announcement from {}: {}",
+                        source, err
+                    );
+                }
+            }
+
+            Request::PeerMessage(message @ Messages::NodeAnnouncement(_)) => {
+                debug!("Got gossip {} from {}", message, source);
+                if let Err(err) =
+                    self.node_announced(senders, message, tlvs, source.clone())
+                {
+                    warn!(
+                        "Ignoring node announcement from {}: {}",
+                        source, err
+                    );
+                }
+            }
+
+            Request::PeerMessage(message @ Messages::ChannelUpdate(_)) => {
+                debug!("Got gossip {} from {}", message, source);
+                if let Err(err) = self.channel_updated(message, source.clone())
+                {
+                    warn!("Ignoring channel update from {}: {}", source, err);
+                }
+            }
+
+            Request::PeerMessage(message @ Messages::QueryChannelRange(_)) => {
+                debug!("Got gossip query {} from {}", message, source);
+                let query = QueryChannelRange::with(&message)?;
+                self.channel_range_queried(senders, source, query)?;
+            }
+
+            Request::PeerMessage(
+                message @ Messages::QueryShortChannelIds(_),
+            ) => {
+                debug!("Got gossip query {} from {}", message, source);
+                let query = QueryShortChannelIds::with(&message)?;
+                self.short_ids_queried(senders, source, query)?;
+            }
+
+            Request::PeerMessage(message @ Messages::ReplyChannelRange(_)) => {
+                let reply = ReplyChannelRange::with(&message)?;
+                self.channel_range_replied(senders, source, reply)?;
+            }
+
+            Request::PeerMessage(
+                message @ Messages::ReplyShortChannelIdsEnd(_),
+            ) => {
+                let reply = ReplyShortChannelIdsEnd::with(&message)?;
+                if !reply.full_information {
+                    warn!("Peer {} lacks the queried channels", source);
+                }
+                if let Some(sync) = self.syncs.get_mut(&source) {
+                    sync.awaiting_reply = false;
+                    sync.updated = Instant::now();
+                }
+                self.query_short_ids(senders, source)?;
+            }
+
+            Request::PeerMessage(message) => {
+                debug!("Ignoring {} from {}", message, source);
             }
+
             _ => {
                 error!(
                     "MSG RPC can be only used for forwarding LNPWP messages"
@@ -89,11 +777,230 @@ impl Runtime {
 
     fn handle_rpc_ctl(
         &mut self,
-        _senders: &mut esb::SenderList<ServiceBus, ServiceId>,
-        _source: ServiceId,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        source: ServiceId,
         request: Request,
     ) -> Result<(), Error> {
         match request {
+            Request::Hello(_) => {
+                // Ignoring; this is used to set remote identity at ZMQ level
+            }
+
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
+            Request::PeerFeatures(features) => {
+                debug!("Peer {} has negotiated {}", source, features);
+                if features.initial_routing_sync {
+                    // TODO: Send known gossip to the peer once gossip messages
+                    //       are supported by LNP/BP Core lib
+                    debug!("Peer {} requests initial routing sync", source);
+                }
+                if features.gossip_queries {
+                    self.filter_gossip(senders, source.clone())?;
+                    self.start_sync(senders, source)?;
+                }
+            }
+
+            Request::ChannelOutput(ChannelOutput {
+                short_channel_id,
+                output,
+                outpoint,
+            }) => {
+                let (announcement, message, origin) =
+                    match self.pending_channels.remove(&short_channel_id) {
+                        Some(pending) => pending,
+                        None => return Ok(()),
+                    };
+                let (output, outpoint) = match (output, outpoint) {
+                    (Some(output), Some(outpoint)) => (output, outpoint),
+                    _ => {
+                        warn!(
+                            "Ignoring channel announcement: {}",
+                            GossipError::NoFundingOutput(short_channel_id)
+                        );
+                        return Ok(());
+                    }
+                };
+                if output.script_pubkey != announcement.funding_script() {
+                    warn!(
+                        "Ignoring channel announcement: {}",
+                        GossipError::FundingMismatch(short_channel_id)
+                    );
+                    return Ok(());
+                }
+                self.store.channel_added(
+                    &announcement,
+                    output.value,
+                    outpoint,
+                )?;
+                self.graph.insert(announcement, output.value, outpoint);
+                let (channels, nodes) = self.graph.size();
+                debug!(
+                    "Channel {} is added to the network graph, which has {} \
+                     channels between {} nodes",
+                    short_channel_id, channels, nodes
+                );
+                let subject = GossipSubject::Channel(short_channel_id);
+                self.broadcast(subject, message, empty!(), origin)?;
+            }
+
+            Request::ChainTransactions(txs) => {
+                let spent = txs
+                    .iter()
+                    .flat_map(|tx| tx.input.iter())
+                    .filter_map(|input| {
+                        self.graph.funding_spent(&input.previous_output)
+                    })
+                    .collect::<Vec<_>>();
+                if !spent.is_empty() {
+                    let ids = spent
+                        .iter()
+                        .map(ShortChannelId::to_string)
+                        .collect::<Vec<_>>();
+                    debug!("Funding of channels {} is spent", ids.join(", "));
+                    info!(
+                        "{} {} closed channel(s) from the network graph",
+                        "Removed".ended(),
+                        spent.len()
+                    );
+                    self.store.compact(&self.graph)?;
+                }
+            }
+
+            Request::FeeEstimate(_) => {
+                // Gossip daemon is subscribed to the chain for the spending
+                // of the channel funding outputs only
+            }
+
+            Request::ChannelPolicyUpdated(ChannelPolicyUpdate {
+                channel_id,
+                policy,
+            }) => {
+                debug!(
+                    "Forwarding policy of channel {} is updated: {}",
+                    channel_id, policy
+                );
+                // TODO: Sign and broadcast `channel_update` once gossip
+                //       messages are supported by LNP/BP Core lib
+                self.local_policies.insert(channel_id, policy);
+            }
+
+            Request::GetState => {
+                let state = GossipState {
+                    leases: self
+                        .leases
+                        .iter()
+                        .map(|(node_id, rates)| NodeLease {
+                            node_id: *node_id,
+                            rates: *rates,
+                        })
+                        .collect(),
+                    local_policies: self
+                        .local_policies
+                        .iter()
+                        .map(|(channel_id, policy)| ChannelPolicyUpdate {
+                            channel_id: *channel_id,
+                            policy: *policy,
+                        })
+                        .collect(),
+                };
+                let data = strict_serialize(&state)
+                    .map_err(|err| Error::Other(err.to_string()))?;
+                self.send_ctl(senders, source, Request::DaemonState(data))?;
+            }
+
+            Request::RestoreState(data) => {
+                let state: GossipState =
+                    strict_deserialize(&data).map_err(|err| {
+                        Error::Other(format!(
+                            "Exported gossip state is corrupted: {}",
+                            err
+                        ))
+                    })?;
+                info!(
+                    "{} {} lease(s) and {} channel policies",
+                    "Restoring gossip state with".promo(),
+                    state.leases.len(),
+                    state.local_policies.len()
+                );
+                // Data learned by the running daemon is more recent
+                for NodeLease { node_id, rates } in state.leases {
+                    self.leases.entry(node_id).or_insert(rates);
+                }
+                for ChannelPolicyUpdate { channel_id, policy } in
+                    state.local_policies
+                {
+                    self.local_policies.entry(channel_id).or_insert(policy);
+                }
+            }
+
+            Request::ListNodes => {
+                let nodes = self.graph.nodes();
+                self.send_ctl(
+                    senders,
+                    source,
+                    Request::NodeList(nodes.into()),
+                )?;
+            }
+
+            Request::DescribeGraph(filter) => {
+                let graph = self.graph.describe(filter);
+                self.send_ctl(senders, source, Request::GraphInfo(graph))?;
+            }
+
+            Request::AdvertiseAddress(addr) => {
+                if !self.advert.addresses.contains(&addr) {
+                    debug!("Node is reachable on {}", addr);
+                    self.advert.addresses.push(addr);
+                    self.advert_changed = true;
+                }
+            }
+
+            Request::ListLeases => {
+                let list = self
+                    .leases
+                    .iter()
+                    .map(|(node_id, rates)| NodeLease {
+                        node_id: *node_id,
+                        rates: *rates,
+                    })
+                    .collect();
+                self.send_ctl(senders, source, Request::LeaseList(list))?;
+            }
+
+            Request::QuoteLease(LeaseRequest {
+                node_id,
+                amount_sat,
+                feerate_per_kw,
+            }) => {
+                let rates = match self.leases.get(&node_id) {
+                    Some(rates) => *rates,
+                    None => {
+                        return Err(self.report_failure_to(
+                            senders,
+                            source,
+                            Failure {
+                                code: 0,
+                                info: format!(
+                                    "Node {} does not advertise liquidity \
+                                     for lease",
+                                    node_id
+                                ),
+                            },
+                        ))
+                    }
+                };
+                let quote = LeaseQuote {
+                    node_id,
+                    amount_sat,
+                    fee_sat: rates.lease_fee(amount_sat, feerate_per_kw),
+                    rates,
+                };
+                self.send_ctl(senders, source, Request::LeaseQuote(quote))?;
+            }
+
             _ => {
                 error!("Request is not supported by the CTL interface");
                 return Err(Error::NotSupported(
@@ -102,5 +1009,6 @@ impl Runtime {
                 ));
             }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,207 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Persistent store of the network graph. Gossip accepted into the graph is
+//! appended to the store file; the file is rewritten from the graph when it
+//! is loaded and after each pruning, dropping superseded and stale records
+//! together with the zombie channels.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bitcoin::OutPoint;
+use lnp::Messages;
+use lnpbp::strict_encoding::{strict_serialize, StrictDecode, StrictEncode};
+
+use super::announcements::{
+    unmarshall_extended, ChannelAnnouncement, ChannelUpdate, NodeAnnouncement,
+};
+use super::graph::NetworkGraph;
+use crate::Error;
+
+/// Gossip message kept in the store
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct StoreRecord {
+    /// Wire encoding of the message
+    data: Vec<u8>,
+    /// Capacity of the announced channel; zero for the other messages
+    capacity_sat: u64,
+    /// Funding outpoint of the announced channel
+    funding_outpoint: Option<OutPoint>,
+}
+
+/// Append-only file with the gossip messages forming the network graph
+pub struct GraphStore {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl GraphStore {
+    /// Opens the store, restoring the network graph from it, and compacts
+    /// the store file
+    pub fn open(path: &Path) -> Result<(GraphStore, NetworkGraph), Error> {
+        let mut graph = NetworkGraph::default();
+        if path.exists() {
+            debug!("Loading network graph from {:?}", path);
+            let mut reader = io::BufReader::new(fs::File::open(path)?);
+            // The last record is incomplete if the daemon was terminated
+            // while writing it, so reading stops on the first failure
+            while let Ok(record) = StoreRecord::strict_decode(&mut reader) {
+                if let Err(err) = restore(&mut graph, record) {
+                    warn!(
+                        "Skipping invalid record of the graph store: {}",
+                        err
+                    );
+                }
+            }
+        }
+        let (channels, nodes) = graph.size();
+        info!(
+            "Network graph has {} channels between {} nodes",
+            channels, nodes
+        );
+        let file = compact(path, &graph)?;
+        let store = GraphStore {
+            path: path.to_path_buf(),
+            file,
+        };
+        Ok((store, graph))
+    }
+
+    /// Appends announcement of the channel with validated funding output
+    pub fn channel_added(
+        &mut self,
+        announcement: &ChannelAnnouncement,
+        capacity_sat: u64,
+        funding_outpoint: OutPoint,
+    ) -> Result<(), Error> {
+        self.append(StoreRecord {
+            data: announcement.data.clone(),
+            capacity_sat,
+            funding_outpoint: Some(funding_outpoint),
+        })
+    }
+
+    /// Appends channel update accepted into the graph
+    pub fn channel_updated(
+        &mut self,
+        update: &ChannelUpdate,
+    ) -> Result<(), Error> {
+        self.append(StoreRecord {
+            data: update.data.clone(),
+            capacity_sat: 0,
+            funding_outpoint: None,
+        })
+    }
+
+    /// Appends node announcement accepted into the graph
+    pub fn node_updated(
+        &mut self,
+        announcement: &NodeAnnouncement,
+    ) -> Result<(), Error> {
+        self.append(StoreRecord {
+            data: announcement.data.clone(),
+            capacity_sat: 0,
+            funding_outpoint: None,
+        })
+    }
+
+    /// Rewrites the store with the current state of the network graph
+    pub fn compact(&mut self, graph: &NetworkGraph) -> Result<(), Error> {
+        self.file = compact(&self.path, graph)?;
+        Ok(())
+    }
+
+    fn append(&mut self, record: StoreRecord) -> Result<(), Error> {
+        let data = strict_serialize(&record)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        self.file.write_all(&data)?;
+        Ok(())
+    }
+}
+
+/// Adds the stored message to the graph; the message signatures were checked
+/// before it was stored
+fn restore(graph: &mut NetworkGraph, record: StoreRecord) -> Result<(), Error> {
+    // Node announcements are stored together with their TLV records
+    let (message, tlvs) = unmarshall_extended(&record.data)?;
+    match message {
+        Messages::ChannelAnnouncement(_) => {
+            let announcement = ChannelAnnouncement::with(&message)?;
+            let funding_outpoint =
+                record.funding_outpoint.ok_or_else(|| {
+                    Error::Other(s!("channel funding outpoint is not stored"))
+                })?;
+            graph.insert(announcement, record.capacity_sat, funding_outpoint);
+        }
+        Messages::ChannelUpdate(_) => {
+            // Updates which became stale while the daemon was not running
+            // are not accepted
+            graph.update_channel(&ChannelUpdate::with(&message)?);
+        }
+        Messages::NodeAnnouncement(_) => {
+            graph.update_node(&NodeAnnouncement::with_tlvs(&message, tlvs)?);
+        }
+        _ => {
+            return Err(Error::Other(format!(
+                "{} is not a gossip message",
+                message
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Writes the graph into a new store file, replacing the existing one, and
+/// opens it for appending
+fn compact(path: &Path, graph: &NetworkGraph) -> Result<fs::File, Error> {
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = io::BufWriter::new(fs::File::create(&tmp_path)?);
+    let mut records = 0usize;
+    let mut write = |record: StoreRecord| -> Result<(), Error> {
+        record
+            .strict_encode(&mut writer)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        records += 1;
+        Ok(())
+    };
+    for channel in graph.channels() {
+        write(StoreRecord {
+            data: channel.announcement.data.clone(),
+            capacity_sat: channel.capacity_sat,
+            funding_outpoint: Some(channel.funding_outpoint),
+        })?;
+        for update in channel.updates.iter().flatten() {
+            write(StoreRecord {
+                data: update.data.clone(),
+                capacity_sat: 0,
+                funding_outpoint: None,
+            })?;
+        }
+    }
+    for announcement in graph.node_announcements() {
+        write(StoreRecord {
+            data: announcement.data.clone(),
+            capacity_sat: 0,
+            funding_outpoint: None,
+        })?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, path)?;
+    trace!("Graph store is compacted to {} records", records);
+    Ok(fs::OpenOptions::new().append(true).open(path)?)
+}
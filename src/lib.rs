@@ -54,23 +54,36 @@ pub mod opts;
 #[cfg(feature = "_rpc")]
 pub mod rpc;
 
+#[cfg(feature = "simulation")]
+pub mod chaind;
 #[cfg(feature = "node")]
 pub mod channeld;
 #[cfg(feature = "node")]
+pub mod fundingd;
+#[cfg(feature = "node")]
 pub mod gossipd;
 #[cfg(feature = "node")]
 pub mod lnpd;
 #[cfg(feature = "node")]
+pub mod lspd;
+#[cfg(feature = "node")]
 pub mod peerd;
 #[cfg(feature = "node")]
 pub mod routed;
 #[cfg(feature = "_rpc")]
 mod service;
+#[cfg(feature = "node")]
+pub mod swapd;
+#[cfg(feature = "node")]
+pub mod towerd;
+#[cfg(feature = "node")]
+pub mod wtclientd;
 
 #[cfg(feature = "_rpc")]
-pub use config::Config;
-pub use error::Error;
+pub use config::{ChannelPolicy, Config, LocalChannelParams, ThrottleLimits};
+pub use error::{Error, Resource};
 #[cfg(feature = "_rpc")]
 pub use service::{
-    CtlServer, LogStyle, Senders, Service, ServiceId, TryToServiceId,
+    ClientName, CtlServer, LogStyle, Senders, Service, ServiceId,
+    TryToServiceId,
 };
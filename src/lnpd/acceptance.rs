@@ -0,0 +1,134 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::HashSet;
+
+use bitcoin::secp256k1::PublicKey;
+use lnp::{message, ChannelId};
+
+use crate::Error;
+
+/// Rules applied by lnpd to channels proposed by remote peers before the
+/// channel daemon is launched. Channel parameters are further validated by
+/// channeld against the node-wide [`crate::ChannelPolicy`].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct AcceptancePolicy {
+    /// Minimal channel funding, in satoshis
+    pub min_funding: Option<u64>,
+
+    /// Maximal number of channels with a single remote peer, including the
+    /// ones which are being opened
+    pub max_channels_per_peer: Option<usize>,
+
+    /// Remote nodes allowed to open channels; if empty, any node can open
+    /// a channel
+    pub allowed_peers: HashSet<PublicKey>,
+
+    /// Minimal feerate of the commitment transactions, in satoshis per
+    /// kiloweight
+    pub min_feerate_per_kw: Option<u32>,
+
+    /// Maximal feerate of the commitment transactions, in satoshis per
+    /// kiloweight
+    pub max_feerate_per_kw: Option<u32>,
+}
+
+/// Reasons for rejecting a channel proposed by a remote peer
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AcceptanceError {
+    /// remote node {0} is not allowed to open channels
+    NotAllowed(PublicKey),
+
+    /// channel funding of {0} sat is below the minimum of {1} sat
+    FundingTooSmall(u64, u64),
+
+    /// remote node already has {0} channels, which is the maximum
+    TooManyChannels(usize),
+
+    /// commitment feerate of {0} sat/kw is below the minimum of {1} sat/kw
+    FeerateTooLow(u32, u32),
+
+    /// commitment feerate of {0} sat/kw exceeds the maximum of {1} sat/kw
+    FeerateTooHigh(u32, u32),
+}
+
+/// Conflicts of a new channel with the channels known to the node, which
+/// share its temporary channel id
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DuplicateChannel {
+    /// channel with temporary id {0} is already being created
+    Pending(ChannelId),
+
+    /// channel daemon for temporary id {0} is already running
+    Running(ChannelId),
+}
+
+impl From<DuplicateChannel> for Error {
+    fn from(err: DuplicateChannel) -> Self {
+        Error::Other(err.to_string())
+    }
+}
+
+impl AcceptancePolicy {
+    /// Checks channel proposal of the `remote` node, which already has
+    /// `channel_count` channels with us
+    pub fn check(
+        &self,
+        remote: PublicKey,
+        channel_count: usize,
+        open_channel: &message::OpenChannel,
+    ) -> Result<(), AcceptanceError> {
+        if !self.allowed_peers.is_empty()
+            && !self.allowed_peers.contains(&remote)
+        {
+            return Err(AcceptanceError::NotAllowed(remote));
+        }
+        match self.min_funding {
+            Some(min) if open_channel.funding_satoshis < min => {
+                return Err(AcceptanceError::FundingTooSmall(
+                    open_channel.funding_satoshis,
+                    min,
+                ))
+            }
+            _ => {}
+        }
+        match self.max_channels_per_peer {
+            Some(max) if channel_count >= max => {
+                return Err(AcceptanceError::TooManyChannels(channel_count))
+            }
+            _ => {}
+        }
+        match self.min_feerate_per_kw {
+            Some(min) if open_channel.feerate_per_kw < min => {
+                return Err(AcceptanceError::FeerateTooLow(
+                    open_channel.feerate_per_kw,
+                    min,
+                ))
+            }
+            _ => {}
+        }
+        match self.max_feerate_per_kw {
+            Some(max) if open_channel.feerate_per_kw > max => {
+                return Err(AcceptanceError::FeerateTooHigh(
+                    open_channel.feerate_per_kw,
+                    max,
+                ))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
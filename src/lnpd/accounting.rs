@@ -0,0 +1,398 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use chrono::NaiveDateTime;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::PathBuf;
+
+use lnp::ChannelId;
+use lnpbp::chain::AssetId;
+use lnpbp::strict_encoding::{
+    strict_deserialize, strict_serialize, StrictDecode, StrictEncode,
+};
+
+use crate::rpc::request::{
+    AccountingEvent, AccountingEventKind, AccountingFormat, ExportAccounting,
+};
+use crate::Error;
+
+pub const ACCOUNTING_DB_FILE: &'static str = "accounting.dat";
+
+const WALLET_ACCOUNT: &'static str = "Assets:Bitcoin:Wallet";
+const CHANNEL_ACCOUNT: &'static str = "Assets:Lightning:Channels";
+const PAYMENTS_ACCOUNT: &'static str = "Expenses:Lightning:Payments";
+const ROUTING_FEES_ACCOUNT: &'static str = "Expenses:Fees:Routing";
+const ONCHAIN_FEES_ACCOUNT: &'static str = "Expenses:Fees:Onchain";
+const SERVICE_FEES_ACCOUNT: &'static str = "Expenses:Fees:Services";
+const INCOME_ACCOUNT: &'static str = "Income:Lightning:Payments";
+const SERVICE_INCOME_ACCOUNT: &'static str = "Income:Lightning:Services";
+const ROUTING_INCOME_ACCOUNT: &'static str = "Income:Lightning:Routing";
+
+const MSAT_IN_BTC: u64 = 100_000_000_000;
+
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct LedgerEntry {
+    timestamp: u64,
+    event: AccountingEvent,
+}
+
+/// Balanced transfer of an amount from the credited to the debited account
+struct Record<'entry> {
+    timestamp: u64,
+    debit: String,
+    credit: String,
+    amount: u64,
+    asset: Option<AssetId>,
+    description: &'entry str,
+}
+
+impl LedgerEntry {
+    /// Derives double-entry records from the event
+    fn records(&self) -> Vec<Record> {
+        let event = &self.event;
+        let channel = match event.channel_id {
+            Some(channel_id) => channel_account(&channel_id),
+            None => s!(CHANNEL_ACCOUNT),
+        };
+        let record = |debit: &str, credit: &str, amount: u64| Record {
+            timestamp: self.timestamp,
+            debit: debit.to_owned(),
+            credit: credit.to_owned(),
+            amount,
+            asset: event.asset,
+            description: &event.description,
+        };
+
+        // Fees are paid in bitcoins from the same account as the amount
+        let (debit, credit, (fee_debit, fee_credit)) = match event.kind {
+            AccountingEventKind::ChannelFunded => (
+                channel.as_str(),
+                WALLET_ACCOUNT,
+                (ONCHAIN_FEES_ACCOUNT, WALLET_ACCOUNT),
+            ),
+            AccountingEventKind::PaymentSent => (
+                PAYMENTS_ACCOUNT,
+                channel.as_str(),
+                (ROUTING_FEES_ACCOUNT, channel.as_str()),
+            ),
+            AccountingEventKind::PaymentReceived => (
+                channel.as_str(),
+                INCOME_ACCOUNT,
+                (ROUTING_FEES_ACCOUNT, INCOME_ACCOUNT),
+            ),
+            AccountingEventKind::OnchainFee => (
+                ONCHAIN_FEES_ACCOUNT,
+                WALLET_ACCOUNT,
+                (ONCHAIN_FEES_ACCOUNT, WALLET_ACCOUNT),
+            ),
+            AccountingEventKind::ServiceFeePaid => (
+                SERVICE_FEES_ACCOUNT,
+                WALLET_ACCOUNT,
+                (ONCHAIN_FEES_ACCOUNT, WALLET_ACCOUNT),
+            ),
+            AccountingEventKind::ServiceFeeEarned => (
+                WALLET_ACCOUNT,
+                SERVICE_INCOME_ACCOUNT,
+                (ONCHAIN_FEES_ACCOUNT, SERVICE_INCOME_ACCOUNT),
+            ),
+            // Channels have booked the forwarded payment as received and
+            // sent, so we move it off the payment accounts, leaving only the
+            // fee as the routing income
+            AccountingEventKind::PaymentForwarded => (
+                INCOME_ACCOUNT,
+                PAYMENTS_ACCOUNT,
+                (INCOME_ACCOUNT, ROUTING_INCOME_ACCOUNT),
+            ),
+        };
+
+        let mut records = vec![];
+        if event.amount_msat > 0 {
+            records.push(record(debit, credit, event.amount_msat));
+        }
+        if event.fee_msat > 0 {
+            records.push(Record {
+                asset: None,
+                ..record(fee_debit, fee_credit, event.fee_msat)
+            });
+        }
+        records
+    }
+}
+
+impl Record<'_> {
+    fn date(&self) -> String {
+        NaiveDateTime::from_timestamp(self.timestamp as i64, 0)
+            .format("%Y-%m-%d")
+            .to_string()
+    }
+
+    fn csv_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}\n",
+            self.timestamp,
+            self.debit,
+            self.credit,
+            self.amount,
+            self.asset
+                .map(|asset| asset.to_string())
+                .unwrap_or(s!("msat")),
+            csv_escape(self.description)
+        )
+    }
+
+    fn beancount_transaction(&self) -> String {
+        let (amount, commodity) = match self.asset {
+            Some(asset) => (self.amount.to_string(), commodity(&asset)),
+            None => (
+                format!(
+                    "{}.{:011}",
+                    self.amount / MSAT_IN_BTC,
+                    self.amount % MSAT_IN_BTC
+                ),
+                s!("BTC"),
+            ),
+        };
+        format!(
+            "{} * \"{}\"\n  {}  {} {}\n  {}  -{} {}\n\n",
+            self.date(),
+            self.description.replace('"', "'"),
+            self.debit,
+            amount,
+            commodity,
+            self.credit,
+            amount,
+            commodity
+        )
+    }
+}
+
+/// Beancount commodity names are limited to 24 uppercase characters, so we
+/// derive them from the asset id and keep the full id in the commodity
+/// metadata
+fn commodity(asset: &AssetId) -> String {
+    let id = asset.to_string().to_uppercase();
+    format!("RGB{}", &id[id.len().saturating_sub(8)..])
+}
+
+/// Beancount account name components must start with a capital letter or a
+/// digit, while channel ids may start with a lowercase hex letter, so we
+/// prefix them
+fn channel_account(channel_id: &ChannelId) -> String {
+    format!("{}:Ch{}", CHANNEL_ACCOUNT, channel_id)
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Persistent journal of the events affecting node funds. Journal file is a
+/// sequence of entries, to which new entries are appended.
+pub struct Ledger {
+    path: PathBuf,
+    entries: Vec<LedgerEntry>,
+}
+
+impl Ledger {
+    pub fn load(data_dir: &PathBuf) -> Result<Ledger, Error> {
+        let path = data_dir.join(ACCOUNTING_DB_FILE);
+        let mut entries = vec![];
+        if path.exists() {
+            debug!("Loading accounting journal from {:?}", path);
+            let data = fs::read(&path)?;
+            let mut cursor = Cursor::new(&data);
+            while (cursor.position() as usize) < data.len() {
+                let pos = cursor.position();
+                match LedgerEntry::strict_decode(&mut cursor) {
+                    Ok(entry) => entries.push(entry),
+                    Err(err) => {
+                        // Entry was not completely written before the node
+                        // has stopped, so we drop it to append the next ones
+                        warn!(
+                            "Truncating accounting journal at {} bytes: {}",
+                            pos, err
+                        );
+                        fs::OpenOptions::new()
+                            .write(true)
+                            .open(&path)?
+                            .set_len(pos)?;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(Ledger { path, entries })
+    }
+
+    pub fn book(
+        &mut self,
+        timestamp: u64,
+        event: AccountingEvent,
+    ) -> Result<(), Error> {
+        let entry = LedgerEntry { timestamp, event };
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        entry
+            .strict_encode(&mut file)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        file.sync_data()?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Rewrites the whole journal, which is needed only when the entries are
+    /// reordered
+    fn store(&self) -> Result<(), Error> {
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = io::BufWriter::new(fs::File::create(&tmp_path)?);
+        for entry in &self.entries {
+            entry
+                .strict_encode(&mut file)
+                .map_err(|err| Error::Other(err.to_string()))?;
+        }
+        file.into_inner()
+            .map_err(|err| Error::Other(err.to_string()))?
+            .sync_data()?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Serializes the whole journal for the node migration
+    pub fn export_journal(&self) -> Result<Vec<u8>, Error> {
+        strict_serialize(&self.entries)
+            .map_err(|err| Error::Other(err.to_string()))
+    }
+
+    /// Adds journal entries exported from another node instance, skipping
+    /// the known ones; returns number of the added entries
+    pub fn import_journal(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let entries: Vec<LedgerEntry> =
+            strict_deserialize(data).map_err(|err| {
+                Error::Other(format!(
+                    "Exported accounting journal is corrupted: {}",
+                    err
+                ))
+            })?;
+        let mut added = 0usize;
+        for entry in entries {
+            if !self.entries.contains(&entry) {
+                self.entries.push(entry);
+                added += 1;
+            }
+        }
+        if added > 0 {
+            self.entries.sort_by_key(|entry| entry.timestamp);
+            self.store()?;
+        }
+        Ok(added)
+    }
+
+    pub fn export(&self, request: ExportAccounting) -> String {
+        let records = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                request.since.map_or(true, |since| entry.timestamp >= since)
+                    && request.till.map_or(true, |till| entry.timestamp < till)
+            })
+            .flat_map(LedgerEntry::records)
+            .collect::<Vec<_>>();
+
+        let mut report = String::new();
+        match request.format {
+            AccountingFormat::Csv => {
+                report.push_str(
+                    "timestamp,debit,credit,amount,unit,description\n",
+                );
+                for record in records {
+                    report.push_str(&record.csv_line());
+                }
+            }
+            AccountingFormat::Beancount => {
+                let accounts = records
+                    .iter()
+                    .flat_map(|record| {
+                        vec![record.debit.clone(), record.credit.clone()]
+                    })
+                    .collect::<BTreeSet<_>>();
+                let assets = records
+                    .iter()
+                    .filter_map(|record| record.asset)
+                    .collect::<BTreeSet<_>>();
+                report.push_str("option \"operating_currency\" \"BTC\"\n\n");
+                for asset in assets {
+                    let _ = writeln!(
+                        report,
+                        "1970-01-01 commodity {}\n  name: \"{}\"",
+                        commodity(&asset),
+                        asset
+                    );
+                }
+                for account in accounts {
+                    let _ = writeln!(report, "1970-01-01 open {}", account);
+                }
+                report.push('\n');
+                for record in records {
+                    report.push_str(&record.beancount_transaction());
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use amplify::{Slice32, Wrapper};
+
+    #[test]
+    fn beancount_channel_account() {
+        let channel_id = ChannelId::from_inner(Slice32::from_inner([0xab; 32]));
+        let ledger = Ledger {
+            path: PathBuf::new(),
+            entries: vec![LedgerEntry {
+                timestamp: 1_600_000_000,
+                event: AccountingEvent {
+                    kind: AccountingEventKind::ChannelFunded,
+                    channel_id: Some(channel_id),
+                    txid: None,
+                    asset: None,
+                    amount_msat: 100_000_000,
+                    fee_msat: 0,
+                    description: s!("Channel funding"),
+                },
+            }],
+        };
+        let report = ledger.export(ExportAccounting {
+            format: AccountingFormat::Beancount,
+            since: None,
+            till: None,
+        });
+        let account =
+            format!("Assets:Lightning:Channels:Ch{}", "ab".repeat(32));
+        assert!(report.contains(&format!("1970-01-01 open {}\n", account)));
+        let posting = format!("  {}  0.00100000000 BTC\n", account);
+        assert!(report.contains(&posting));
+    }
+}
@@ -0,0 +1,176 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! Static channel backups: encrypted data required to recover channel funds
+//! after the loss of the node data directory
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1;
+use bitcoin::secp256k1::rand::{self, RngCore};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use lnp::ChannelId;
+use lnpbp::strict_encoding::{strict_deserialize, strict_serialize};
+use lnpbp::Chain;
+
+use crate::rpc::request::ChannelBackup;
+use crate::Error;
+
+pub const BACKUP_FILE: &'static str = "channels.backup";
+
+/// Version of the [`StaticBackup`] format produced by the current code
+pub const STATIC_BACKUP_VERSION: u16 = 1;
+
+const NONCE_LEN: usize = 12;
+
+/// Tag making the backup encryption key different from any other key derived
+/// from the node key
+const KEY_TAG: &'static [u8] = b"lnp-node:static-channel-backup";
+
+/// Content of the encrypted backup blob
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct StaticBackup {
+    pub version: u16,
+    pub chain: Chain,
+    pub node_id: secp256k1::PublicKey,
+    pub channels: Vec<ChannelBackup>,
+}
+
+/// Static backup of all open channels, kept encrypted on disk and rewritten
+/// each time a channel opens or closes
+pub struct BackupStore {
+    path: PathBuf,
+    cipher: ChaCha20Poly1305,
+    chain: Chain,
+    node_id: secp256k1::PublicKey,
+    channels: BTreeMap<ChannelId, ChannelBackup>,
+}
+
+impl BackupStore {
+    /// Loads backup made by the node, if any; the backup is encrypted with a
+    /// key derived from the node private key, so it can be opened only by
+    /// the same node
+    pub fn load(
+        data_dir: &PathBuf,
+        chain: Chain,
+        node_key: &secp256k1::SecretKey,
+    ) -> Result<BackupStore, Error> {
+        let mut engine = sha256::Hash::engine();
+        engine.input(KEY_TAG);
+        engine.input(&node_key[..]);
+        let key = sha256::Hash::from_engine(engine);
+        let secp = secp256k1::Secp256k1::signing_only();
+
+        let mut store = BackupStore {
+            path: data_dir.join(BACKUP_FILE),
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key[..])),
+            chain,
+            node_id: secp256k1::PublicKey::from_secret_key(&secp, node_key),
+            channels: empty!(),
+        };
+        if store.path.exists() {
+            debug!("Loading static channel backup from {:?}", store.path);
+            let blob = fs::read(&store.path)?;
+            store.channels = store
+                .open(&blob)?
+                .channels
+                .into_iter()
+                .map(|backup| (backup.channel_id, backup))
+                .collect();
+        }
+        Ok(store)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, self.export())?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Adds or replaces backup of the channel
+    pub fn update(&mut self, backup: ChannelBackup) -> Result<(), Error> {
+        self.channels.insert(backup.channel_id, backup);
+        self.save()
+    }
+
+    /// Removes closed channel from the backup
+    pub fn remove(&mut self, channel_id: &ChannelId) -> Result<(), Error> {
+        if self.channels.remove(channel_id).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Produces encrypted backup blob of all open channels
+    pub fn export(&self) -> Vec<u8> {
+        let backup = StaticBackup {
+            version: STATIC_BACKUP_VERSION,
+            chain: self.chain.clone(),
+            node_id: self.node_id,
+            channels: self.channels.values().cloned().collect(),
+        };
+        let plaintext = strict_serialize(&backup)
+            .expect("Memory-based encoding does not fail");
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut blob = nonce.to_vec();
+        blob.extend(
+            self.cipher
+                .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+                .expect("ChaCha20Poly1305 encryption does not fail"),
+        );
+        blob
+    }
+
+    /// Decrypts backup blob and checks that it was made by this node for the
+    /// same chain
+    pub fn open(&self, blob: &[u8]) -> Result<StaticBackup, Error> {
+        if blob.len() <= NONCE_LEN {
+            return Err(Error::Other(s!("Static backup is malformed")));
+        }
+        let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                Error::Other(s!(
+                    "Static backup can't be decrypted: it was made by a \
+                     different node or is corrupted"
+                ))
+            })?;
+        let backup: StaticBackup =
+            strict_deserialize(&plaintext).map_err(|err| {
+                Error::Other(format!("Static backup is corrupted: {}", err))
+            })?;
+        if backup.version > STATIC_BACKUP_VERSION {
+            return Err(Error::Other(format!(
+                "Static backup version {} is not supported",
+                backup.version
+            )));
+        }
+        if backup.chain != self.chain {
+            return Err(Error::Other(format!(
+                "Static backup was made for {} while the node runs on {}",
+                backup.chain, self.chain
+            )));
+        }
+        Ok(backup)
+    }
+}
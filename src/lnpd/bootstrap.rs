@@ -0,0 +1,381 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Initial peer discovery through BOLT-10 DNS seeds, using minimal DNS
+//! client (RFC 1035) querying seeds for SRV records of the lightning nodes
+
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use amplify::IoError;
+use bech32::FromBase32;
+use bitcoin::secp256k1;
+use bitcoin::secp256k1::rand::{self, Rng};
+use internet2::{NodeAddr, RemoteNodeAddr, RemoteSocketAddr};
+
+/// DNS resolver used if none is configured in the system
+pub const LNP_NODE_DNS_RESOLVER: &'static str = "1.1.1.1:53";
+
+/// BOLT-10 DNS seeds of bitcoin mainnet
+pub const BITCOIN_DNS_SEEDS: [&'static str; 2] =
+    ["nodes.lightning.directory", "lseed.bitcoinstats.com"];
+
+/// Query prefix selecting the nodes of realm 0 reachable over IPv4 or IPv6
+const QUERY_PREFIX: &'static str = "r0.a6";
+
+const RESOLV_CONF: &'static str = "/etc/resolv.conf";
+
+/// Time the resolver is awaited to reply to a single query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximal number of compression pointers followed while reading a single
+/// domain name, preventing pointer loops
+const MAX_NAME_POINTERS: usize = 16;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// DNS bootstrap configuration
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BootstrapConfig {
+    /// Domains of the DNS seeds
+    pub seeds: Vec<String>,
+
+    /// Address of the DNS resolver
+    pub resolver: SocketAddr,
+
+    /// Number of the discovered peers the node connects to
+    pub peers: usize,
+}
+
+/// Errors querying DNS seeds
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum BootstrapError {
+    /// I/O error querying DNS resolver: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// domain name {0} is invalid
+    InvalidName(String),
+
+    /// DNS resolver has replied with error code {0}
+    ResponseCode(u8),
+
+    /// DNS response does not fit into a single datagram
+    Truncated,
+
+    /// DNS response is malformed
+    Malformed,
+}
+
+/// DNS resource record used for the peer discovery
+enum Record {
+    Srv { port: u16, target: String },
+    Ip { name: String, ip: IpAddr },
+}
+
+/// Resolver configured in the system, or the default one if there is none
+pub fn system_resolver() -> SocketAddr {
+    fs::read_to_string(RESOLV_CONF)
+        .ok()
+        .and_then(|conf| {
+            conf.lines().find_map(|line| {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next()) {
+                    (Some("nameserver"), Some(addr)) => {
+                        addr.parse::<IpAddr>().ok()
+                    }
+                    _ => None,
+                }
+            })
+        })
+        .map(|ip| SocketAddr::new(ip, 53))
+        .unwrap_or_else(|| {
+            LNP_NODE_DNS_RESOLVER
+                .parse()
+                .expect("default DNS resolver address is valid")
+        })
+}
+
+/// Queries the DNS seeds for the lightning nodes, returning addresses of up
+/// to the configured number of them. Seeds which fail to reply are skipped.
+pub fn discover(config: &BootstrapConfig) -> Vec<NodeAddr> {
+    let mut found = Vec::<RemoteNodeAddr>::new();
+    for seed in &config.seeds {
+        if found.len() >= config.peers {
+            break;
+        }
+        let nodes = match query_seed(config.resolver, seed) {
+            Ok(nodes) => nodes,
+            Err(err) => {
+                warn!("DNS seed {} is not available: {}", seed, err);
+                continue;
+            }
+        };
+        debug!("DNS seed {} has returned {} node(s)", seed, nodes.len());
+        for node in nodes {
+            if found.len() >= config.peers {
+                break;
+            }
+            if !found.iter().any(|known| known.node_id == node.node_id) {
+                found.push(node);
+            }
+        }
+    }
+    found.into_iter().map(NodeAddr::Remote).collect()
+}
+
+/// Queries single DNS seed, resolving addresses of the returned nodes which
+/// are not provided by the seed itself
+fn query_seed(
+    resolver: SocketAddr,
+    seed: &str,
+) -> Result<Vec<RemoteNodeAddr>, BootstrapError> {
+    let records =
+        query(resolver, &format!("{}.{}", QUERY_PREFIX, seed), TYPE_SRV)?;
+    let mut nodes = vec![];
+    for record in &records {
+        let (port, target) = match record {
+            Record::Srv { port, target } => (*port, target),
+            Record::Ip { .. } => continue,
+        };
+        let node_id = match parse_node_id(target) {
+            Some(node_id) => node_id,
+            None => {
+                debug!("SRV target {} does not contain node id", target);
+                continue;
+            }
+        };
+        let ip = match find_ip(&records, target) {
+            Some(ip) => ip,
+            None => match query(resolver, target, TYPE_A)
+                .map(|answer| find_ip(&answer, target))
+            {
+                Ok(Some(ip)) => ip,
+                Ok(None) => continue,
+                Err(err) => {
+                    debug!("Unable to resolve {}: {}", target, err);
+                    continue;
+                }
+            },
+        };
+        nodes.push(RemoteNodeAddr {
+            node_id,
+            remote_addr: RemoteSocketAddr::Ftcp(
+                SocketAddr::new(ip, port).into(),
+            ),
+        });
+    }
+    Ok(nodes)
+}
+
+fn find_ip(records: &[Record], name: &str) -> Option<IpAddr> {
+    records.iter().find_map(|record| match record {
+        Record::Ip { name: owner, ip } if owner.eq_ignore_ascii_case(name) => {
+            Some(*ip)
+        }
+        _ => None,
+    })
+}
+
+/// Extracts node id from the first label of SRV target, which is the bech32
+/// encoding of the node id with `ln` prefix
+fn parse_node_id(target: &str) -> Option<secp256k1::PublicKey> {
+    let label = target.split('.').next()?;
+    let (hrp, data) = bech32::decode(label).ok()?;
+    if hrp != "ln" {
+        return None;
+    }
+    let key = Vec::<u8>::from_base32(&data).ok()?;
+    secp256k1::PublicKey::from_slice(&key).ok()
+}
+
+/// Sends single DNS query to the resolver and returns records from all
+/// sections of the response
+fn query(
+    resolver: SocketAddr,
+    name: &str,
+    qtype: u16,
+) -> Result<Vec<Record>, BootstrapError> {
+    let id: u16 = rand::thread_rng().gen();
+    let mut request = Vec::with_capacity(512);
+    request.extend(&id.to_be_bytes());
+    // Recursion is desired; the query has a single question
+    request.extend(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(BootstrapError::InvalidName(name.to_owned()));
+        }
+        request.push(label.len() as u8);
+        request.extend(label.as_bytes());
+    }
+    request.push(0);
+    request.extend(&qtype.to_be_bytes());
+    request.extend(&CLASS_IN.to_be_bytes());
+
+    let socket = UdpSocket::bind(if resolver.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    socket.connect(resolver)?;
+    socket.send(&request)?;
+    let mut response = [0u8; 512];
+    loop {
+        let len = socket.recv(&mut response)?;
+        // Stray datagrams replying to other queries are ignored
+        if len >= 2 && response[..2] == id.to_be_bytes() {
+            return parse_response(&response[..len]);
+        }
+    }
+}
+
+fn parse_response(msg: &[u8]) -> Result<Vec<Record>, BootstrapError> {
+    let mut reader = Reader { msg, pos: 2 };
+    let flags = reader.u16()?;
+    if flags & 0x0200 != 0 {
+        return Err(BootstrapError::Truncated);
+    }
+    let rcode = (flags & 0x000F) as u8;
+    if rcode != 0 {
+        return Err(BootstrapError::ResponseCode(rcode));
+    }
+    let questions = reader.u16()?;
+    // Answer, authority and additional sections are read all together
+    let mut count = 0usize;
+    for _ in 0..3 {
+        count += reader.u16()? as usize;
+    }
+    for _ in 0..questions {
+        reader.name()?;
+        reader.skip(4)?;
+    }
+
+    let mut records = vec![];
+    for _ in 0..count {
+        let name = reader.name()?;
+        let rtype = reader.u16()?;
+        // Class and TTL
+        reader.skip(6)?;
+        let len = reader.u16()? as usize;
+        let end = reader.pos + len;
+        if end > msg.len() {
+            return Err(BootstrapError::Malformed);
+        }
+        match (rtype, len) {
+            (TYPE_SRV, _) => {
+                // Priority and weight
+                reader.skip(4)?;
+                let port = reader.u16()?;
+                let target = reader.name()?;
+                records.push(Record::Srv { port, target });
+            }
+            (TYPE_A, 4) => {
+                let data = reader.bytes(4)?;
+                let ip = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+                records.push(Record::Ip {
+                    name,
+                    ip: IpAddr::V4(ip),
+                });
+            }
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(reader.bytes(16)?);
+                records.push(Record::Ip {
+                    name,
+                    ip: IpAddr::V6(Ipv6Addr::from(octets)),
+                });
+            }
+            _ => {}
+        }
+        reader.pos = end;
+    }
+    Ok(records)
+}
+
+/// Cursor over the DNS message
+struct Reader<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], BootstrapError> {
+        let data = self
+            .msg
+            .get(self.pos..self.pos + len)
+            .ok_or(BootstrapError::Malformed)?;
+        self.pos += len;
+        Ok(data)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), BootstrapError> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u16(&mut self) -> Result<u16, BootstrapError> {
+        let data = self.bytes(2)?;
+        Ok(u16::from_be_bytes([data[0], data[1]]))
+    }
+
+    /// Reads domain name, following compression pointers
+    fn name(&mut self) -> Result<String, BootstrapError> {
+        let mut labels = Vec::<String>::new();
+        let mut pos = self.pos;
+        let mut pointers = 0;
+        loop {
+            let len =
+                *self.msg.get(pos).ok_or(BootstrapError::Malformed)? as usize;
+            match len {
+                0 => {
+                    if pointers == 0 {
+                        self.pos = pos + 1;
+                    }
+                    break;
+                }
+                len if len & 0xC0 == 0xC0 => {
+                    let low = *self
+                        .msg
+                        .get(pos + 1)
+                        .ok_or(BootstrapError::Malformed)?
+                        as usize;
+                    if pointers == 0 {
+                        self.pos = pos + 2;
+                    }
+                    pointers += 1;
+                    if pointers > MAX_NAME_POINTERS {
+                        return Err(BootstrapError::Malformed);
+                    }
+                    pos = ((len & 0x3F) << 8) | low;
+                }
+                len if len <= 63 => {
+                    let label = self
+                        .msg
+                        .get(pos + 1..pos + 1 + len)
+                        .ok_or(BootstrapError::Malformed)?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + len;
+                }
+                _ => return Err(BootstrapError::Malformed),
+            }
+        }
+        Ok(labels.join("."))
+    }
+}
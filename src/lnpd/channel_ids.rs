@@ -0,0 +1,96 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use lnp::ChannelId;
+use lnpbp::strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::Error;
+
+pub const CHANNEL_IDS_DB_FILE: &'static str = "channel_ids.dat";
+
+/// Persistent mapping of the temporary channel ids, which identify channel
+/// daemons on the service buses, to the final ids of the funded channels
+pub struct ChannelIdStore {
+    path: PathBuf,
+    ids: BTreeMap<ChannelId, ChannelId>,
+}
+
+impl ChannelIdStore {
+    pub fn load(data_dir: &PathBuf) -> Result<ChannelIdStore, Error> {
+        let path = data_dir.join(CHANNEL_IDS_DB_FILE);
+        let ids = if path.exists() {
+            debug!("Loading channel ids from {:?}", path);
+            StrictDecode::strict_decode(fs::File::open(&path)?).map_err(
+                |err| {
+                    Error::Other(format!(
+                        "Channel id store is corrupted: {}",
+                        err
+                    ))
+                },
+            )?
+        } else {
+            empty!()
+        };
+        Ok(ChannelIdStore { path, ids })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let tmp_path = self.path.with_extension("tmp");
+        self.ids
+            .strict_encode(fs::File::create(&tmp_path)?)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Returns final id of the channel with the given temporary id, if the
+    /// channel is funded
+    pub fn get(&self, temp_id: ChannelId) -> Option<ChannelId> {
+        self.ids.get(&temp_id).copied()
+    }
+
+    /// Finds temporary id of the channel with the given final id
+    pub fn find(&self, channel_id: ChannelId) -> Option<ChannelId> {
+        self.ids
+            .iter()
+            .find(|(_, id)| **id == channel_id)
+            .map(|(temp_id, _)| *temp_id)
+    }
+
+    /// Registers new final id of the channel, returning the previous one.
+    /// Channel id changes each time its funding transaction is replaced.
+    pub fn insert(
+        &mut self,
+        temp_id: ChannelId,
+        channel_id: ChannelId,
+    ) -> Result<Option<ChannelId>, Error> {
+        let prev_id = self.ids.insert(temp_id, channel_id);
+        if prev_id != Some(channel_id) {
+            self.save()?;
+        }
+        Ok(prev_id)
+    }
+
+    /// Forgets the channel
+    pub fn remove(&mut self, temp_id: ChannelId) -> Result<(), Error> {
+        if self.ids.remove(&temp_id).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
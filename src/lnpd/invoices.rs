@@ -0,0 +1,202 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use lnpbp::chain::AssetId;
+use lnpbp::strict_encoding::{
+    strict_deserialize, strict_serialize, StrictDecode, StrictEncode,
+};
+use wallet::{HashLock, HashPreimage};
+
+use crate::rpc::request::{IncomingHtlc, InvoiceInfo, InvoiceRequest};
+use crate::Error;
+
+pub const INVOICES_DB_FILE: &'static str = "invoices.dat";
+
+/// Number of blocks left before expiry of HTLCs paying our invoices which
+/// we require
+pub const MIN_FINAL_CLTV_EXPIRY: u32 = 18;
+
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct Invoice {
+    preimage: HashPreimage,
+    amount_msat: u64,
+    asset_id: Option<AssetId>,
+    expires_at: u64,
+    /// UNIX timestamp of the invoice payment
+    paid_at: Option<u64>,
+}
+
+/// Reason for not settling an incoming HTLC
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SettlementError {
+    /// there is no invoice with the HTLC payment hash
+    UnknownInvoice,
+
+    /// invoice is already paid
+    AlreadyPaid,
+
+    /// invoice has expired
+    Expired,
+
+    /// HTLC pays {paid} while the invoice requires {required}
+    WrongAmount { paid: u64, required: u64 },
+
+    /// HTLC pays in a different asset than the invoice requires
+    WrongAsset,
+
+    /// HTLC expires at block {expiry}, which is too close to the current
+    /// height {height}
+    ExpiryTooSoon { expiry: u32, height: u32 },
+}
+
+/// Persistent store of the invoices created by the node and their preimages
+pub struct InvoiceStore {
+    path: PathBuf,
+    invoices: BTreeMap<HashLock, Invoice>,
+}
+
+impl InvoiceStore {
+    pub fn load(data_dir: &PathBuf) -> Result<InvoiceStore, Error> {
+        let path = data_dir.join(INVOICES_DB_FILE);
+        let invoices = if path.exists() {
+            debug!("Loading invoices from {:?}", path);
+            StrictDecode::strict_decode(fs::File::open(&path)?).map_err(
+                |err| {
+                    Error::Other(format!("Invoice store is corrupted: {}", err))
+                },
+            )?
+        } else {
+            empty!()
+        };
+        Ok(InvoiceStore { path, invoices })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let tmp_path = self.path.with_extension("tmp");
+        self.invoices
+            .strict_encode(fs::File::create(&tmp_path)?)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Serializes all invoices, including their preimages, for the node
+    /// migration
+    pub fn export(&self) -> Result<Vec<u8>, Error> {
+        strict_serialize(&self.invoices)
+            .map_err(|err| Error::Other(err.to_string()))
+    }
+
+    /// Adds invoices exported from another node instance, keeping the known
+    /// ones intact; returns number of the added invoices
+    pub fn import(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let invoices: BTreeMap<HashLock, Invoice> = strict_deserialize(data)
+            .map_err(|err| {
+                Error::Other(format!(
+                    "Exported invoices are corrupted: {}",
+                    err
+                ))
+            })?;
+        let mut added = 0usize;
+        for (payment_hash, invoice) in invoices {
+            if !self.invoices.contains_key(&payment_hash) {
+                self.invoices.insert(payment_hash, invoice);
+                added += 1;
+            }
+        }
+        if added > 0 {
+            self.save()?;
+        }
+        Ok(added)
+    }
+
+    /// Creates new invoice with a random preimage
+    pub fn create(
+        &mut self,
+        now: u64,
+        request: InvoiceRequest,
+    ) -> Result<InvoiceInfo, Error> {
+        let preimage = HashPreimage::random();
+        let payment_hash = HashLock::from(preimage);
+        let invoice = Invoice {
+            preimage,
+            amount_msat: request.amount_msat,
+            asset_id: request.asset_id,
+            expires_at: now + request.expiry as u64,
+            paid_at: None,
+        };
+        let info = InvoiceInfo {
+            payment_hash,
+            amount_msat: invoice.amount_msat,
+            asset_id: invoice.asset_id,
+            min_final_cltv_expiry: MIN_FINAL_CLTV_EXPIRY,
+            expires_at: invoice.expires_at,
+        };
+        self.invoices.insert(payment_hash, invoice);
+        self.save()?;
+        Ok(info)
+    }
+
+    /// Checks that the HTLC pays one of our invoices, marking the invoice as
+    /// paid and returning its preimage
+    pub fn settle(
+        &mut self,
+        now: u64,
+        htlc: &IncomingHtlc,
+    ) -> Result<HashPreimage, SettlementError> {
+        let invoice = self
+            .invoices
+            .get_mut(&htlc.payment_hash)
+            .ok_or(SettlementError::UnknownInvoice)?;
+        if invoice.paid_at.is_some() {
+            return Err(SettlementError::AlreadyPaid);
+        }
+        if now > invoice.expires_at {
+            return Err(SettlementError::Expired);
+        }
+        if htlc.asset_id != invoice.asset_id {
+            return Err(SettlementError::WrongAsset);
+        }
+        // Overpayment up to twice the amount is allowed by BOLT-4
+        if htlc.amount_msat < invoice.amount_msat
+            || htlc.amount_msat > invoice.amount_msat.saturating_mul(2)
+        {
+            return Err(SettlementError::WrongAmount {
+                paid: htlc.amount_msat,
+                required: invoice.amount_msat,
+            });
+        }
+        if let Some(height) = htlc.chain_height {
+            if htlc.cltv_expiry < height + MIN_FINAL_CLTV_EXPIRY {
+                return Err(SettlementError::ExpiryTooSoon {
+                    expiry: htlc.cltv_expiry,
+                    height,
+                });
+            }
+        }
+        invoice.paid_at = Some(now);
+        let preimage = invoice.preimage;
+        if let Err(err) = self.save() {
+            // Preimage is still released: the payment is received anyway
+            error!("Unable to persist invoice payment: {}", err);
+        }
+        Ok(preimage)
+    }
+}
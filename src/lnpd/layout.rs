@@ -0,0 +1,91 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Migration of the data directory layout of the previous versions, which
+//! kept the data of a single chain right in the data directory, into the
+//! per-chain subdirectories
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use amplify::IoError;
+use lnpbp::Chain;
+
+/// Node key file which presence in the data directory marks the layout of
+/// the previous versions
+const LEGACY_KEY_FILE: &str = "key.dat";
+
+/// Errors migrating node data into the per-chain directory
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum LayoutError {
+    /// I/O error moving node data into per-chain directory: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// data directory {0:?} keeps node data of a previous version without
+    /// the chain it belongs to; please restart lnpd with `--legacy-chain`
+    /// naming that chain to move the data into its directory
+    UnknownChain(PathBuf),
+
+    /// node data of a previous version can't be moved into {0:?}, which
+    /// already keeps data of the chain
+    ChainDataExists(PathBuf),
+}
+
+/// Moves the node key, databases and sockets kept by the previous versions
+/// right in `base_dir` into the subdirectory of `legacy_chain`. Since the
+/// previous versions did not record the chain of the data, the chain must
+/// be confirmed by the user; without it the legacy data are kept untouched
+/// and an error is returned, so the node does not start with a new key.
+pub fn migrate_legacy_layout(
+    base_dir: &Path,
+    legacy_chain: Option<&Chain>,
+) -> Result<(), LayoutError> {
+    if !base_dir.join(LEGACY_KEY_FILE).is_file() {
+        return Ok(());
+    }
+    let chain_dir = match legacy_chain {
+        Some(chain) => base_dir.join(chain.to_string()),
+        None => return Err(LayoutError::UnknownChain(base_dir.to_owned())),
+    };
+    if chain_dir.join(LEGACY_KEY_FILE).exists() {
+        return Err(LayoutError::ChainDataExists(chain_dir));
+    }
+    info!(
+        "Moving node data from {} into per-chain directory {}",
+        base_dir.display(),
+        chain_dir.display()
+    );
+    fs::create_dir_all(&chain_dir)?;
+    for entry in fs::read_dir(base_dir)? {
+        let path = entry?.path();
+        // Data of other chains may be already kept in their directories,
+        // and empty ones are created for any chain the tools are run for
+        if path == chain_dir
+            || path.join(LEGACY_KEY_FILE).is_file()
+            || is_empty_dir(&path)?
+        {
+            continue;
+        }
+        let dest = chain_dir.join(path.file_name().expect("directory entry"));
+        fs::rename(&path, dest)?;
+    }
+    Ok(())
+}
+
+fn is_empty_dir(path: &Path) -> io::Result<bool> {
+    Ok(path.is_dir() && fs::read_dir(path)?.next().is_none())
+}
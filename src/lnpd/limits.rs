@@ -0,0 +1,55 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use crate::{Error, Resource};
+
+/// Caps on the resources used by lnpd; limits which are not set are not
+/// enforced
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ResourceLimits {
+    /// Maximal number of channels, including the ones being created
+    pub max_channels: Option<usize>,
+
+    /// Maximal number of peer connections, including the ones being
+    /// established
+    pub max_peers: Option<usize>,
+
+    /// Maximal number of connections accepted from remote peers; once it is
+    /// reached, peers without channels are evicted in favour of the peers
+    /// having channels with the node
+    pub max_inbound_peers: Option<usize>,
+
+    /// Maximal number of channels negotiated simultaneously, before their
+    /// daemons are running
+    pub max_pending_channels: Option<usize>,
+}
+
+impl ResourceLimits {
+    /// Checks whether one more unit of the resource can be used, given the
+    /// number of units which are already in use
+    pub fn check(&self, resource: Resource, used: usize) -> Result<(), Error> {
+        let limit = match resource {
+            Resource::Channels => self.max_channels,
+            Resource::Peers => self.max_peers,
+            Resource::InboundPeers => self.max_inbound_peers,
+            Resource::PendingChannels => self.max_pending_channels,
+        };
+        match limit {
+            Some(limit) if used >= limit => {
+                Err(Error::ResourceLimit(resource, limit))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -12,10 +12,33 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+mod acceptance;
+mod accounting;
+mod backup;
+mod bootstrap;
+mod channel_ids;
+mod invoices;
+mod layout;
+mod limits;
+mod onion;
 #[cfg(feature = "shell")]
 mod opts;
+mod peers;
+mod plugins;
 mod runtime;
+mod supervisor;
+mod webhooks;
 
+pub use acceptance::{AcceptanceError, AcceptancePolicy, DuplicateChannel};
+pub use bootstrap::{
+    BootstrapConfig, BootstrapError, BITCOIN_DNS_SEEDS, LNP_NODE_DNS_RESOLVER,
+};
+pub use layout::{migrate_legacy_layout, LayoutError};
+pub use limits::ResourceLimits;
+pub use onion::{OnionConfig, OnionError, LNP_NODE_TOR_CONTROL};
 #[cfg(feature = "shell")]
 pub use opts::Opts;
-pub use runtime::run;
+pub use peers::AddressPreference;
+pub use runtime::{node_features, run, NodeConfig};
+pub use supervisor::{LaunchError, LaunchMode, ProcessOpts};
+pub use webhooks::WebhookConfig;
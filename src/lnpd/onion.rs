@@ -0,0 +1,256 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Minimal Tor control port client (see `control-spec.txt` from Tor
+//! specifications) used for publishing node listeners as onion services
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+
+use amplify::IoError;
+use bitcoin::hashes::hex::ToHex;
+
+pub const LNP_NODE_TOR_CONTROL: &'static str = "127.0.0.1:9051";
+pub const ONION_KEY_FILE: &'static str = "onion_v3.key";
+
+/// Tor control port configuration
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct OnionConfig {
+    /// Address of the Tor control port
+    pub control: SocketAddr,
+
+    /// Password for `HASHEDPASSWORD` authentication; if not given, cookie
+    /// or null authentication is used, depending on what Tor offers
+    pub password: Option<String>,
+}
+
+/// Errors communicating with Tor control port
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum OnionError {
+    /// I/O error communicating with Tor control port: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// Tor has replied with an error to `{0}` command: {1}
+    Reply(&'static str, String),
+
+    /// Tor control port does not offer authentication methods supported by
+    /// the node; please provide control port password
+    AuthMethod,
+
+    /// Tor has not returned the address of the created onion service
+    NoServiceId,
+
+    /// onion service key file {0:?} is corrupted
+    KeyFile(PathBuf),
+}
+
+/// Onion service published through Tor control port
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct OnionService {
+    /// Onion address without `.onion` suffix
+    pub service_id: String,
+    /// Private key in Tor `ED25519-V3:<base64>` format
+    pub private_key: String,
+}
+
+impl OnionService {
+    fn load(path: &PathBuf) -> Result<Option<OnionService>, OnionError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)?;
+        let mut lines = data.lines();
+        match (lines.next(), lines.next()) {
+            (Some(service_id), Some(private_key))
+                if private_key.starts_with("ED25519-V3:") =>
+            {
+                Ok(Some(OnionService {
+                    service_id: service_id.to_owned(),
+                    private_key: private_key.to_owned(),
+                }))
+            }
+            _ => Err(OnionError::KeyFile(path.clone())),
+        }
+    }
+
+    fn save(&self, path: &PathBuf) -> Result<(), OnionError> {
+        fs::write(
+            path,
+            format!("{}\n{}\n", self.service_id, self.private_key),
+        )?;
+        Ok(())
+    }
+
+    /// Onion address of the service, including `.onion` suffix
+    pub fn address(&self) -> String {
+        format!("{}.onion", self.service_id)
+    }
+}
+
+/// Authenticated connection to Tor control port
+pub struct TorControl {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl TorControl {
+    /// Connects to the control port and authenticates with the password,
+    /// cookie or without credentials, whichever is possible
+    pub fn connect(config: &OnionConfig) -> Result<TorControl, OnionError> {
+        let stream = TcpStream::connect(config.control)?;
+        let mut control = TorControl {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        let info = control.command("PROTOCOLINFO", "PROTOCOLINFO 1")?;
+        let mut methods = Vec::<String>::new();
+        let mut cookie_file = None;
+        for line in &info {
+            let line = match line.strip_prefix("AUTH ") {
+                Some(line) => line,
+                None => continue,
+            };
+            for item in line.split(' ') {
+                if let Some(list) = item.strip_prefix("METHODS=") {
+                    methods = list.split(',').map(str::to_owned).collect();
+                } else if let Some(path) = item.strip_prefix("COOKIEFILE=") {
+                    cookie_file = Some(PathBuf::from(path.trim_matches('"')));
+                }
+            }
+        }
+        let has = |method: &str| methods.iter().any(|m| m == method);
+
+        let auth = if let (Some(password), true) =
+            (&config.password, has("HASHEDPASSWORD"))
+        {
+            format!(
+                "AUTHENTICATE \"{}\"",
+                password.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        } else if let (Some(path), true) = (&cookie_file, has("COOKIE")) {
+            format!("AUTHENTICATE {}", fs::read(path)?.to_hex())
+        } else if has("NULL") {
+            s!("AUTHENTICATE")
+        } else {
+            return Err(OnionError::AuthMethod);
+        };
+        control.command("AUTHENTICATE", &auth)?;
+        Ok(control)
+    }
+
+    /// Sends the command, returning the reply lines without status codes.
+    /// Fails if the reply status is not `250`.
+    fn command(
+        &mut self,
+        name: &'static str,
+        command: &str,
+    ) -> Result<Vec<String>, OnionError> {
+        self.writer.write_all(command.as_bytes())?;
+        self.writer.write_all(b"\r\n")?;
+        self.writer.flush()?;
+
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(
+                    io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                );
+            }
+            let line = line.trim_end();
+            if line.len() < 4 {
+                return Err(OnionError::Reply(name, line.to_owned()));
+            }
+            let (status, rest) = line.split_at(3);
+            if status != "250" {
+                return Err(OnionError::Reply(name, line.to_owned()));
+            }
+            lines.push(rest[1..].to_owned());
+            // Space after the status code marks the last line of the reply
+            if rest.starts_with(' ') {
+                break;
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Publishes onion service forwarding `port` to the local `target`
+    /// socket. The service is detached from the control connection, so it
+    /// keeps running after the node shuts down and is re-used on the next
+    /// start.
+    pub fn add_onion(
+        &mut self,
+        key: Option<&str>,
+        port: u16,
+        target: SocketAddr,
+    ) -> Result<OnionService, OnionError> {
+        let command = format!(
+            "ADD_ONION {} Flags=Detach Port={},{}",
+            key.unwrap_or("NEW:ED25519-V3"),
+            port,
+            target
+        );
+        let reply = self.command("ADD_ONION", &command)?;
+        let field = |name: &str| {
+            reply
+                .iter()
+                .find_map(|line| line.strip_prefix(name))
+                .map(str::to_owned)
+        };
+        Ok(OnionService {
+            service_id: field("ServiceID=").ok_or(OnionError::NoServiceId)?,
+            private_key: field("PrivateKey=")
+                .or_else(|| key.map(str::to_owned))
+                .ok_or(OnionError::NoServiceId)?,
+        })
+    }
+}
+
+/// Publishes the node onion service forwarding `port` to the `target`
+/// listener. The service key is kept in the data directory, so the node
+/// onion address does not change between restarts.
+pub fn publish(
+    config: &OnionConfig,
+    data_dir: &PathBuf,
+    port: u16,
+    target: SocketAddr,
+) -> Result<OnionService, OnionError> {
+    let path = data_dir.join(ONION_KEY_FILE);
+    let known = OnionService::load(&path)?;
+    let mut control = TorControl::connect(config)?;
+    match control.add_onion(
+        known.as_ref().map(|service| service.private_key.as_str()),
+        port,
+        target,
+    ) {
+        Ok(service) => {
+            if known.is_none() {
+                service.save(&path)?;
+            }
+            Ok(service)
+        }
+        // The service is still running since the previous start
+        Err(OnionError::Reply(_, ref reply))
+            if known.is_some() && reply.contains("collision") =>
+        {
+            Ok(known.expect("checked above"))
+        }
+        Err(err) => Err(err),
+    }
+}
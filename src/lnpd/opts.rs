@@ -12,10 +12,22 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use clap::{AppSettings, Clap};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
+use bitcoin::secp256k1::PublicKey;
+use clap::{AppSettings, Clap, ValueHint};
+use internet2::RemoteSocketAddr;
+use lnpbp::Chain;
+
+use super::bootstrap::system_resolver;
+use super::{
+    AddressPreference, BootstrapConfig, OnionConfig, BITCOIN_DNS_SEEDS,
+    LNP_NODE_TOR_CONTROL,
+};
 use crate::channeld::RgbOpts;
 use crate::peerd::KeyOpts;
+use crate::rpc::request::{IpRange, PeerAccess};
 
 /// Lightning node management daemon; part of LNP Node
 ///
@@ -30,6 +42,221 @@ use crate::peerd::KeyOpts;
     setting = AppSettings::ColoredHelp
 )]
 pub struct Opts {
+    /// Additional chains to run node services for, in parallel with the main
+    /// one
+    ///
+    /// Each chain uses its own data directory, node key and service buses.
+    /// Can be used multiple times.
+    #[clap(long = "parallel-chain", env = "LNP_NODE_PARALLEL_CHAINS")]
+    pub parallel_chains: Vec<Chain>,
+
+    /// Chain of the node data kept by the previous versions right in the
+    /// data directory
+    ///
+    /// The previous versions did not keep data of each chain in its own
+    /// subdirectory, nor recorded the chain of the data. If such data are
+    /// present, lnpd does not start until their chain is provided with this
+    /// argument, and then moves them into the directory of that chain.
+    #[clap(long, env = "LNP_NODE_LEGACY_CHAIN")]
+    pub legacy_chain: Option<Chain>,
+
+    /// URL of an endpoint receiving JSON notifications about node events
+    ///
+    /// Can be used multiple times.
+    #[clap(long = "webhook", env = "LNP_NODE_WEBHOOKS")]
+    pub webhooks: Vec<String>,
+
+    /// Secret used to sign webhook payloads with HMAC-SHA256
+    ///
+    /// The signature is provided in `X-Lnp-Signature` header and commits
+    /// to the `X-Lnp-Timestamp` header value and the payload, joined with a
+    /// dot.
+    #[clap(long, env = "LNP_NODE_WEBHOOK_SECRET", requires = "webhooks")]
+    pub webhook_secret: Option<String>,
+
+    /// Run peerd, channeld, gossipd and routed as threads of lnpd process
+    ///
+    /// Allows running the node where separate daemon binaries can't be
+    /// launched, like on mobile platforms.
+    #[clap(long, env = "LNP_NODE_THREADED")]
+    pub threaded: bool,
+
+    /// Directory containing peerd, channeld, gossipd and routed binaries
+    ///
+    /// Defaults to the directory of the lnpd binary.
+    #[clap(long, env = "LNP_NODE_BIN_DIR", conflicts_with = "threaded")]
+    pub bin_dir: Option<PathBuf>,
+
+    /// Directory for the log files receiving output of the launched daemons
+    ///
+    /// Each daemon writes to `<name>.log` file inside the directory. If not
+    /// given, daemons share standard output and error with lnpd.
+    #[clap(long, env = "LNP_NODE_DAEMON_LOG_DIR", conflicts_with = "threaded")]
+    pub log_dir: Option<PathBuf>,
+
+    /// Node id of a remote peer allowed to open channels with the node
+    ///
+    /// Can be used multiple times. If not given, channels proposed by any
+    /// peer are considered.
+    #[clap(long = "accept-peer", env = "LNP_NODE_ACCEPT_PEERS")]
+    pub accept_peers: Vec<PublicKey>,
+
+    /// Minimal funding of channels proposed by remote peers, in satoshis
+    #[clap(long, env = "LNP_NODE_ACCEPT_MIN_FUNDING")]
+    pub accept_min_funding: Option<u64>,
+
+    /// Maximal number of channels a single remote peer may open with the
+    /// node
+    #[clap(long, env = "LNP_NODE_MAX_CHANNELS_PER_PEER")]
+    pub max_channels_per_peer: Option<usize>,
+
+    /// Minimal commitment feerate of channels proposed by remote peers, in
+    /// satoshis per kiloweight
+    #[clap(long, env = "LNP_NODE_ACCEPT_MIN_FEERATE")]
+    pub accept_min_feerate: Option<u32>,
+
+    /// Maximal commitment feerate of channels proposed by remote peers, in
+    /// satoshis per kiloweight
+    #[clap(long, env = "LNP_NODE_ACCEPT_MAX_FEERATE")]
+    pub accept_max_feerate: Option<u32>,
+
+    /// Maximal number of channels run by the node, including the ones being
+    /// created
+    #[clap(long, env = "LNP_NODE_MAX_CHANNELS")]
+    pub max_channels: Option<usize>,
+
+    /// Maximal number of connections with remote peers
+    ///
+    /// Incoming connections exceeding the limit are closed.
+    #[clap(long, env = "LNP_NODE_MAX_PEERS")]
+    pub max_peers: Option<usize>,
+
+    /// Maximal number of connections accepted from remote peers
+    ///
+    /// Once the limit is reached, the remote peers without channels with
+    /// the node are disconnected to free slots for the peers having
+    /// channels; other incoming connections are closed.
+    #[clap(long, env = "LNP_NODE_MAX_INBOUND_PEERS")]
+    pub max_inbound_peers: Option<usize>,
+
+    /// Maximal number of channels which are negotiated simultaneously,
+    /// before their channel daemons are running
+    #[clap(long, env = "LNP_NODE_MAX_PENDING_CHANNELS")]
+    pub max_pending_channels: Option<usize>,
+
+    /// Number of known peers without channels which are reconnected on the
+    /// node startup
+    ///
+    /// Peers having channels with the node are always reconnected; others
+    /// are picked by the time of the last successful connection.
+    #[clap(long, default_value = "3", env = "LNP_NODE_AUTOCONNECT_PEERS")]
+    pub autoconnect_peers: usize,
+
+    /// BOLT-10 DNS seed queried for the initial peers if the node does not
+    /// know any peers yet
+    ///
+    /// Can be used multiple times. Defaults to the well-known seeds for
+    /// bitcoin mainnet; for other chains seeds must be given explicitly.
+    #[clap(long = "dns-seed", env = "LNP_NODE_DNS_SEEDS")]
+    pub dns_seeds: Vec<String>,
+
+    /// Number of peers discovered through DNS seeds the node connects to
+    ///
+    /// Zero value disables DNS bootstrap.
+    #[clap(long, default_value = "3", env = "LNP_NODE_BOOTSTRAP_PEERS")]
+    pub bootstrap_peers: usize,
+
+    /// DNS resolver used to query DNS seeds
+    ///
+    /// Defaults to the first nameserver from `/etc/resolv.conf`.
+    #[clap(long, env = "LNP_NODE_DNS_RESOLVER")]
+    pub dns_resolver: Option<SocketAddr>,
+
+    /// Connect to the remote peers at their onion addresses only
+    ///
+    /// Peers without onion addresses are not reconnected, such that
+    /// connections never leave Tor network. Requires Tor proxy.
+    #[clap(long, env = "LNP_NODE_TOR_ONLY", requires = "tor-proxy")]
+    pub tor_only: bool,
+
+    /// Try IPv6 addresses of the remote peers before IPv4 ones
+    #[clap(long, env = "LNP_NODE_PREFER_IPV6")]
+    pub prefer_ipv6: bool,
+
+    /// Local socket to listen for incoming connections from the remote peers
+    ///
+    /// Can be used multiple times, binding several IPv4 or IPv6 sockets at
+    /// once; each of them runs its own listening peerd. Listeners are run
+    /// for the main chain only.
+    #[clap(long = "listen", env = "LNP_NODE_LISTEN", value_hint = ValueHint::Hostname)]
+    pub listens: Vec<SocketAddr>,
+
+    /// Local socket to listen for incoming WebSocket connections from the
+    /// remote peers, like browser-based clients
+    ///
+    /// Can be used multiple times. TLS has to be terminated by a reverse
+    /// proxy in front of the socket.
+    #[clap(long = "listen-websocket", env = "LNP_NODE_LISTEN_WEBSOCKET", value_hint = ValueHint::Hostname)]
+    pub websocket_listens: Vec<SocketAddr>,
+
+    /// Node id of a remote peer which is not allowed to connect to the node
+    ///
+    /// Can be used multiple times. The lists can be modified at runtime with
+    /// `peer-access` command.
+    #[clap(long = "deny-node", env = "LNP_NODE_DENY_NODES")]
+    pub deny_nodes: Vec<PublicKey>,
+
+    /// IP address range, in `<ip>[/<prefix>]` format, from which incoming
+    /// connections are rejected
+    ///
+    /// Can be used multiple times.
+    #[clap(long = "deny-ip", env = "LNP_NODE_DENY_IPS")]
+    pub deny_ips: Vec<IpRange>,
+
+    /// Node id of a remote peer allowed to connect to the node in
+    /// allowlist-only mode
+    ///
+    /// Can be used multiple times.
+    #[clap(long = "allow-node", env = "LNP_NODE_ALLOW_NODES")]
+    pub allow_nodes: Vec<PublicKey>,
+
+    /// IP address range, in `<ip>[/<prefix>]` format, from which incoming
+    /// connections are accepted in allowlist-only mode
+    ///
+    /// Can be used multiple times. Tor forwards connections to the onion
+    /// service from the loopback interface, so it must be allowed for onion
+    /// peers.
+    #[clap(long = "allow-ip", env = "LNP_NODE_ALLOW_IPS")]
+    pub allow_ips: Vec<IpRange>,
+
+    /// Accept connections only with the peers from the allowlist
+    ///
+    /// Node ids of incoming connections are not known before the session is
+    /// established, so incoming connections are accepted only from the
+    /// allowed IP ranges.
+    #[clap(long, env = "LNP_NODE_ALLOWLIST_ONLY")]
+    pub allowlist_only: bool,
+
+    /// Publish node listeners as Tor onion service using the provided Tor
+    /// control port
+    ///
+    /// The onion service key is kept in the data directory, so the node
+    /// onion address persists between restarts. If the argument is provided
+    /// in form of flag, without value, uses `127.0.0.1:9051` as default Tor
+    /// control port address.
+    #[clap(long, env = "LNP_NODE_TOR_CONTROL", value_hint = ValueHint::Hostname)]
+    pub tor_control: Option<Option<SocketAddr>>,
+
+    /// Password for the Tor control port
+    ///
+    /// If not given, cookie authentication is used.
+    #[clap(
+        long,
+        env = "LNP_NODE_TOR_CONTROL_PASSWORD",
+        requires = "tor-control"
+    )]
+    pub tor_control_password: Option<String>,
+
     /// RGB configuration
     #[clap(flatten)]
     pub rgb_opts: RgbOpts,
@@ -50,4 +277,73 @@ impl Opts {
         self.key_opts.process(&self.shared);
         self.rgb_opts.process(&self.shared);
     }
+
+    /// Local sockets to listen on with their framing protocols
+    pub fn listen_addrs(&self) -> Vec<RemoteSocketAddr> {
+        self.listens
+            .iter()
+            .map(|addr| RemoteSocketAddr::Ftcp((*addr).into()))
+            .chain(
+                self.websocket_listens
+                    .iter()
+                    .map(|addr| RemoteSocketAddr::Websocket((*addr).into())),
+            )
+            .collect()
+    }
+
+    /// Peer access lists given in the command line and environment, which
+    /// are added to the persisted ones
+    pub fn peer_access(&self) -> PeerAccess {
+        PeerAccess {
+            allowlist_only: self.allowlist_only,
+            allowed_nodes: self.allow_nodes.clone(),
+            allowed_ips: self.allow_ips.clone(),
+            denied_nodes: self.deny_nodes.clone(),
+            denied_ips: self.deny_ips.clone(),
+        }
+    }
+
+    /// DNS bootstrap configuration, unless it is disabled or there are no
+    /// seeds for the chain
+    pub fn bootstrap_config(&self) -> Option<BootstrapConfig> {
+        let seeds = if self.dns_seeds.is_empty()
+            && self.shared.chain == Chain::Mainnet
+        {
+            BITCOIN_DNS_SEEDS
+                .iter()
+                .map(|seed| seed.to_string())
+                .collect()
+        } else {
+            self.dns_seeds.clone()
+        };
+        if seeds.is_empty() || self.bootstrap_peers == 0 {
+            return None;
+        }
+        Some(BootstrapConfig {
+            seeds,
+            resolver: self.dns_resolver.unwrap_or_else(system_resolver),
+            peers: self.bootstrap_peers,
+        })
+    }
+
+    /// Address types used to reconnect the remote peers
+    pub fn address_preference(&self) -> AddressPreference {
+        AddressPreference {
+            tor_proxy: self.shared.tor_proxy.is_some(),
+            tor_only: self.tor_only,
+            prefer_ipv6: self.prefer_ipv6,
+        }
+    }
+
+    /// Tor control port configuration, if onion service is requested
+    pub fn onion_config(&self) -> Option<OnionConfig> {
+        self.tor_control.map(|control| OnionConfig {
+            control: control.unwrap_or_else(|| {
+                LNP_NODE_TOR_CONTROL
+                    .parse()
+                    .expect("default Tor control address is valid")
+            }),
+            password: self.tor_control_password.clone(),
+        })
+    }
 }
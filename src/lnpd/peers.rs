@@ -0,0 +1,258 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use bitcoin::secp256k1;
+use internet2::{NodeAddr, RemoteNodeAddr, RemoteSocketAddr};
+use lnpbp::strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::Error;
+
+pub const PEERS_DB_FILE: &'static str = "peers.dat";
+
+/// Maximal number of addresses remembered per peer; further advertised
+/// addresses are ignored
+const MAX_PEER_ADDRS: usize = 8;
+
+/// Address types the node is able to and prefers to connect to
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct AddressPreference {
+    /// Onion addresses are reachable through Tor proxy
+    pub tor_proxy: bool,
+
+    /// Only onion addresses are used, such that connections never leave
+    /// Tor network
+    pub tor_only: bool,
+
+    /// IPv6 addresses are tried before IPv4 ones
+    pub prefer_ipv6: bool,
+}
+
+impl AddressPreference {
+    /// Rank of the address, the lower the better, or `None` if the address
+    /// is not reachable by the node
+    fn rank(&self, addr: &RemoteSocketAddr) -> Option<u8> {
+        let inet_addr = match addr {
+            RemoteSocketAddr::Ftcp(inet_addr)
+            | RemoteSocketAddr::Websocket(inet_addr) => *inet_addr,
+            _ => return None,
+        };
+        match SocketAddr::try_from(inet_addr) {
+            // Onion addresses do not convert into IP socket addresses
+            Err(_) if self.tor_proxy => Some(0),
+            Err(_) => None,
+            Ok(_) if self.tor_only => None,
+            Ok(socket_addr) if socket_addr.is_ipv6() == self.prefer_ipv6 => {
+                Some(1)
+            }
+            Ok(_) => Some(2),
+        }
+    }
+}
+
+/// Remote peer the node has connected to or has learned about
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct KnownPeer {
+    node_id: secp256k1::PublicKey,
+    /// Addresses of the peer, the most recently connected first, followed
+    /// by the advertised ones and the ones which have failed
+    addrs: Vec<RemoteSocketAddr>,
+    /// UNIX timestamp of the last successful connection
+    last_connected: u64,
+    has_channels: bool,
+}
+
+impl KnownPeer {
+    /// Best of the peer addresses reachable by the node
+    fn best_addr(&self, prefs: &AddressPreference) -> Option<RemoteSocketAddr> {
+        self.addrs
+            .iter()
+            .filter_map(|addr| prefs.rank(addr).map(|rank| (rank, addr)))
+            // Minimum is the first one among the addresses of the same rank
+            .min_by_key(|(rank, _)| *rank)
+            .map(|(_, addr)| *addr)
+    }
+}
+
+/// Persistent address book of the remote peers, used to restore connections
+/// on the node startup and to pick alternative addresses once connection
+/// fails
+pub struct PeerStore {
+    path: PathBuf,
+    peers: Vec<KnownPeer>,
+    prefs: AddressPreference,
+}
+
+impl PeerStore {
+    pub fn load(
+        data_dir: &PathBuf,
+        prefs: AddressPreference,
+    ) -> Result<PeerStore, Error> {
+        let path = data_dir.join(PEERS_DB_FILE);
+        let peers = if path.exists() {
+            debug!("Loading known peers from {:?}", path);
+            StrictDecode::strict_decode(fs::File::open(&path)?).map_err(
+                |err| Error::Other(format!("Peer store is corrupted: {}", err)),
+            )?
+        } else {
+            empty!()
+        };
+        Ok(PeerStore { path, peers, prefs })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let tmp_path = self.path.with_extension("tmp");
+        self.peers
+            .strict_encode(fs::File::create(&tmp_path)?)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn entry(&mut self, node_id: secp256k1::PublicKey) -> &mut KnownPeer {
+        match self.peers.iter().position(|peer| peer.node_id == node_id) {
+            Some(pos) => &mut self.peers[pos],
+            None => {
+                self.peers.push(KnownPeer {
+                    node_id,
+                    addrs: vec![],
+                    last_connected: 0,
+                    has_channels: false,
+                });
+                self.peers.last_mut().expect("peer is just added")
+            }
+        }
+    }
+
+    /// Whether the node has never connected to any peer
+    pub fn is_empty(&self) -> bool {
+        !self.peers.iter().any(|peer| peer.last_connected > 0)
+    }
+
+    /// Whether the peer is in the address book
+    pub fn contains(&self, node_id: secp256k1::PublicKey) -> bool {
+        self.peers.iter().any(|peer| peer.node_id == node_id)
+    }
+
+    /// Adds addresses advertised by the peer or given by the user, keeping
+    /// the known ones at their place
+    pub fn add_addrs(
+        &mut self,
+        node_id: secp256k1::PublicKey,
+        addrs: impl IntoIterator<Item = RemoteSocketAddr>,
+    ) -> Result<(), Error> {
+        let peer = self.entry(node_id);
+        let count = peer.addrs.len();
+        for addr in addrs {
+            if !peer.addrs.contains(&addr) {
+                peer.addrs.push(addr);
+            }
+        }
+        if peer.addrs.len() == count {
+            return Ok(());
+        }
+        peer.addrs.truncate(MAX_PEER_ADDRS);
+        self.save()
+    }
+
+    /// Records failed connection to the peer at the given address, moving it
+    /// to the end of the peer addresses, and returns the address to try
+    /// next. Returns `None` if the peer has no other reachable addresses.
+    pub fn failed(
+        &mut self,
+        node_id: secp256k1::PublicKey,
+        addr: RemoteSocketAddr,
+    ) -> Result<Option<RemoteSocketAddr>, Error> {
+        let prefs = self.prefs;
+        let peer =
+            match self.peers.iter_mut().find(|peer| peer.node_id == node_id) {
+                Some(peer) if peer.addrs.contains(&addr) => peer,
+                _ => return Ok(None),
+            };
+        peer.addrs.retain(|known| *known != addr);
+        peer.addrs.push(addr);
+        let next = peer.best_addr(&prefs).filter(|next| *next != addr);
+        self.save()?;
+        Ok(next)
+    }
+
+    /// Records successful connection to the peer at the given address
+    pub fn connected(
+        &mut self,
+        node_id: secp256k1::PublicKey,
+        addr: RemoteSocketAddr,
+        has_channels: bool,
+    ) -> Result<(), Error> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let peer = self.entry(node_id);
+        peer.addrs.retain(|known| *known != addr);
+        peer.addrs.insert(0, addr);
+        peer.last_connected = now;
+        peer.has_channels = has_channels;
+        self.save()
+    }
+
+    /// Updates whether the node has channels with the peer
+    pub fn set_channels(
+        &mut self,
+        node_id: secp256k1::PublicKey,
+        has_channels: bool,
+    ) -> Result<(), Error> {
+        let peer = self.entry(node_id);
+        if peer.has_channels == has_channels {
+            return Ok(());
+        }
+        peer.has_channels = has_channels;
+        self.save()
+    }
+
+    /// Peers to connect on the node startup at their best addresses: all
+    /// peers having channels with the node and up to `gossip_peers` most
+    /// recently connected other peers
+    pub fn autoconnect(&self, gossip_peers: usize) -> Vec<NodeAddr> {
+        let prefs = &self.prefs;
+        let mut others = self
+            .peers
+            .iter()
+            .filter(|peer| {
+                !peer.has_channels
+                    && peer.last_connected > 0
+                    && peer.best_addr(prefs).is_some()
+            })
+            .collect::<Vec<_>>();
+        others.sort_by(|a, b| b.last_connected.cmp(&a.last_connected));
+        self.peers
+            .iter()
+            .filter(|peer| peer.has_channels)
+            .chain(others.into_iter().take(gossip_peers))
+            .filter_map(|peer| {
+                peer.best_addr(prefs).map(|remote_addr| {
+                    NodeAddr::Remote(RemoteNodeAddr {
+                        node_id: peer.node_id,
+                        remote_addr,
+                    })
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,306 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use lnp::message;
+
+use crate::rpc::request::{HookCall, HookPoint, HookResult};
+use crate::rpc::tlv::TlvStream;
+use crate::ServiceId;
+
+/// Time given to a plugin to decide on a hook call; after it the action is
+/// rejected, since the plugin may have been about to veto it
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Action waiting for the plugin decisions
+pub enum HookOrigin {
+    /// Channel proposed by a remote peer, which is accepted only if no
+    /// plugin rejects it
+    ChannelProposal {
+        peerd: ServiceId,
+        open_channel: message::OpenChannel,
+        /// TLV records of the `open_channel` message
+        tlvs: TlvStream,
+    },
+
+    /// Hook run by other daemon, which receives the resulting decision
+    Daemon { service: ServiceId, call_id: u64 },
+}
+
+/// Next step in processing of a hook
+pub enum HookProgress {
+    /// The call must be sent to the plugin
+    Call(ServiceId, HookCall),
+
+    /// All plugins are done; contains the decision combined from their
+    /// results
+    Done(HookOrigin, HookResult),
+}
+
+struct PendingHook {
+    call: HookCall,
+    plugins: VecDeque<ServiceId>,
+    origin: HookOrigin,
+    /// Decision combined from the results of the plugins called so far
+    result: HookResult,
+    /// Time by which the plugin at the front of the queue must reply
+    deadline: Instant,
+}
+
+/// Registry of the external plugins and hook calls awaiting their decisions.
+///
+/// Plugins subscribed to a vetoable hook point are called one after another
+/// in the order of their registration. The first plugin rejecting the
+/// action or resolving it on its own stops the chain, while the amendments
+/// of the action made by the plugins are combined, such that the last
+/// plugin setting a value wins.
+#[derive(Default)]
+pub struct Plugins {
+    hooks: BTreeMap<HookPoint, Vec<ServiceId>>,
+    last_id: u64,
+    pending: HashMap<u64, PendingHook>,
+}
+
+impl Plugins {
+    pub fn register(&mut self, plugin: ServiceId, points: Vec<HookPoint>) {
+        for point in points {
+            let plugins = self.hooks.entry(point).or_insert_with(Vec::new);
+            if !plugins.contains(&plugin) {
+                plugins.push(plugin.clone());
+            }
+        }
+    }
+
+    /// Removes the plugin from all hook points. Pending calls awaiting its
+    /// decision are rejected; calls which have not reached it yet proceed
+    /// without it.
+    pub fn deregister(&mut self, plugin: &ServiceId) -> Vec<HookProgress> {
+        for plugins in self.hooks.values_mut() {
+            plugins.retain(|subscriber| subscriber != plugin);
+        }
+        self.hooks.retain(|_, plugins| !plugins.is_empty());
+        let ids = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.plugins.contains(plugin))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        ids.into_iter()
+            .filter_map(|id| {
+                let mut pending = self.pending.remove(&id)?;
+                let awaited = pending.plugins.front() == Some(plugin);
+                pending.plugins.retain(|subscriber| subscriber != plugin);
+                if awaited {
+                    warn!(
+                        "Plugin {} deregistered before deciding on {}; \
+                         rejecting the action",
+                        plugin, pending.call.point
+                    );
+                    Some(Self::fail(
+                        pending,
+                        format!("plugin {} has deregistered", plugin),
+                    ))
+                } else {
+                    self.pending.insert(id, pending);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the service is registered as a plugin
+    pub fn is_registered(&self, service: &ServiceId) -> bool {
+        self.hooks.values().any(|plugins| plugins.contains(service))
+    }
+
+    /// Rejects actions awaiting plugins which have not replied within
+    /// [`HOOK_TIMEOUT`]
+    pub fn expire(&mut self, now: Instant) -> Vec<HookProgress> {
+        let ids = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        ids.into_iter()
+            .filter_map(|id| self.pending.remove(&id))
+            .map(|pending| {
+                let plugin = pending
+                    .plugins
+                    .front()
+                    .cloned()
+                    .expect("only calls awaiting a plugin are pending");
+                warn!(
+                    "Plugin {} has not decided on {} within {:?}; \
+                     rejecting the action",
+                    plugin, pending.call.point, HOOK_TIMEOUT
+                );
+                Self::fail(
+                    pending,
+                    format!("plugin {} has not decided in time", plugin),
+                )
+            })
+            .collect()
+    }
+
+    /// Produces calls for all plugins subscribed to a notification-only hook
+    pub fn notify(&mut self, mut call: HookCall) -> Vec<(ServiceId, HookCall)> {
+        debug_assert!(!call.point.is_vetoable());
+        let plugins = self.hooks.get(&call.point).cloned().unwrap_or_default();
+        if plugins.is_empty() {
+            return vec![];
+        }
+        self.last_id += 1;
+        call.id = self.last_id;
+        plugins
+            .into_iter()
+            .map(|plugin| (plugin, call.clone()))
+            .collect()
+    }
+
+    pub fn run(
+        &mut self,
+        mut call: HookCall,
+        origin: HookOrigin,
+    ) -> HookProgress {
+        debug_assert!(call.point.is_vetoable());
+        let plugins: VecDeque<_> = self
+            .hooks
+            .get(&call.point)
+            .cloned()
+            .unwrap_or_default()
+            .into();
+        self.last_id += 1;
+        call.id = self.last_id;
+        let result = HookResult {
+            id: call.id,
+            reject: None,
+            minimum_depth: None,
+            preimage: None,
+        };
+        self.next(PendingHook {
+            call,
+            plugins,
+            origin,
+            result,
+            deadline: Instant::now(),
+        })
+    }
+
+    /// Processes plugin decision; returns `None` if the result does not
+    /// match any pending call of the plugin
+    pub fn result(
+        &mut self,
+        plugin: &ServiceId,
+        result: HookResult,
+    ) -> Option<HookProgress> {
+        match self.pending.get(&result.id) {
+            Some(pending) if pending.plugins.front() == Some(plugin) => {}
+            _ => return None,
+        }
+        let mut pending = self
+            .pending
+            .remove(&result.id)
+            .expect("pending call presence is checked above");
+        if let Some(ref reason) = result.reject {
+            info!(
+                "Plugin {} rejected {}: {}",
+                plugin, pending.call.point, reason
+            );
+            pending.result.reject = result.reject;
+            return Some(HookProgress::Done(pending.origin, pending.result));
+        }
+        if result.preimage.is_some() {
+            info!("Plugin {} resolved {}", plugin, pending.call.point);
+            pending.result.preimage = result.preimage;
+            return Some(HookProgress::Done(pending.origin, pending.result));
+        }
+        if result.minimum_depth.is_some() {
+            pending.result.minimum_depth = result.minimum_depth;
+        }
+        pending.plugins.pop_front();
+        Some(self.next(pending))
+    }
+
+    fn fail(mut pending: PendingHook, reason: String) -> HookProgress {
+        pending.result.reject = Some(reason);
+        HookProgress::Done(pending.origin, pending.result)
+    }
+
+    fn next(&mut self, mut pending: PendingHook) -> HookProgress {
+        match pending.plugins.front().cloned() {
+            None => HookProgress::Done(pending.origin, pending.result),
+            Some(plugin) => {
+                let call = pending.call.clone();
+                pending.deadline = Instant::now() + HOOK_TIMEOUT;
+                self.pending.insert(call.id, pending);
+                HookProgress::Call(plugin, call)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn htlc_call() -> HookCall {
+        HookCall {
+            id: 0,
+            point: HookPoint::HtlcAccepted,
+            peer: None,
+            channel_id: None,
+            amount_msat: Some(1000),
+            payment_hash: None,
+            details: s!(""),
+        }
+    }
+
+    fn origin() -> HookOrigin {
+        HookOrigin::Daemon {
+            service: ServiceId::Client(1),
+            call_id: 1,
+        }
+    }
+
+    fn assert_rejected(progress: Vec<HookProgress>) {
+        assert_eq!(progress.len(), 1);
+        match &progress[0] {
+            HookProgress::Done(_, result) => assert!(result.reject.is_some()),
+            HookProgress::Call(..) => panic!("action is not rejected"),
+        }
+    }
+
+    #[test]
+    fn fail_closed() {
+        let first = ServiceId::Client(2);
+        let second = ServiceId::Client(3);
+        let mut plugins = Plugins::default();
+        plugins.register(first.clone(), vec![HookPoint::HtlcAccepted]);
+        plugins.register(second.clone(), vec![HookPoint::HtlcAccepted]);
+
+        match plugins.run(htlc_call(), origin()) {
+            HookProgress::Call(plugin, _) => assert_eq!(plugin, first),
+            HookProgress::Done(..) => panic!("plugin is not called"),
+        }
+        assert!(plugins.expire(Instant::now()).is_empty());
+        assert_rejected(plugins.expire(Instant::now() + HOOK_TIMEOUT));
+
+        plugins.run(htlc_call(), origin());
+        assert_rejected(plugins.deregister(&first));
+        assert!(plugins.pending.is_empty());
+    }
+}
@@ -13,54 +13,404 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use amplify::Wrapper;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
-use std::ffi::OsStr;
-use std::io;
-use std::net::SocketAddr;
-use std::process;
-use std::time::{Duration, SystemTime};
-
-use bitcoin::hashes::hex::ToHex;
-use bitcoin::secp256k1;
-use internet2::{NodeAddr, RemoteSocketAddr, TypedEnum};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+use bitcoin::secp256k1::rand::{self, Rng};
+use bitcoin::{secp256k1, Transaction, Txid};
+use internet2::addr::InetSocketAddr;
+use internet2::zmqsocket::{self, ZmqType};
+use internet2::{
+    LocalNode, NodeAddr, RemoteNodeAddr, RemoteSocketAddr, TypedEnum,
+    ZMQ_CONTEXT,
+};
+use lnp::payment::AssetsBalance;
 use lnp::{message, ChannelId, Messages, TempChannelId};
+use lnpbp::strict_encoding::StrictEncode;
 use lnpbp::Chain;
 use microservices::esb::{self, Handler};
 use microservices::rpc::Failure;
+use wallet::{HashLock, PubkeyScript};
 
-use crate::rpc::request::{IntoProgressOrFalure, NodeInfo, OptionDetails};
+use super::acceptance::{AcceptanceError, AcceptancePolicy, DuplicateChannel};
+use super::accounting::Ledger;
+use super::backup::BackupStore;
+use super::bootstrap::{self, BootstrapConfig};
+use super::channel_ids::ChannelIdStore;
+use super::invoices::InvoiceStore;
+use super::limits::ResourceLimits;
+use super::onion::{self, OnionConfig};
+use super::peers::{AddressPreference, PeerStore};
+use super::plugins::{HookOrigin, HookProgress, Plugins};
+use super::supervisor::{Daemon, LaunchMode, Supervisor};
+use super::webhooks::{Dispatcher, WebhookConfig};
+use crate::rpc::request::{
+    AccountingEventKind, AssetBalanceInfo, ChannelBackup, ChannelInfo,
+    ChannelSnapshot, DaemonCrash, DaemonHealth, DaemonInfo, FundingBatch,
+    GossipBroadcast, HookCall, HookPoint, HookResult, HtlcForward,
+    HtlcSettlement, IntoProgressOrFalure, IntoSuccessOrFalure, NodeArchive,
+    NodeEvent, NodeEventKind, NodeInfo, NodeState, OptionDetails,
+    PaymentInterception, PeerAccess, PeerAccessUpdate, PeerFeatures, PeerList,
+    TxDepth, NODE_ARCHIVE_VERSION, NODE_STATE_VERSION,
+};
 use crate::rpc::{request, Request, ServiceBus};
-use crate::{Config, Error, LogStyle, Service, ServiceId};
+use crate::service::BridgeHandler;
+use crate::{
+    channeld, fundingd, peerd, Config, Error, LogStyle, Resource, Service,
+    ServiceId,
+};
+
+/// Time lnpd waits for the daemons to report their information before
+/// replying to the client listing peers or channels or exporting node state
+const LISTING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Period between heartbeats sent by lnpd to the registered daemons
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(10);
+
+/// Time after which a daemon not responding to heartbeats is considered
+/// unhealthy
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Configuration of the node run by `lnpd` on a single chain, in addition to
+/// the configuration shared by all daemons
+pub struct NodeConfig {
+    pub local_node: LocalNode,
+    /// File storing the node key, which is replaced when the node is
+    /// imported with a different key
+    pub key_file: PathBuf,
+    pub data_dir: PathBuf,
+    pub webhooks: WebhookConfig,
+    pub acceptance: AcceptancePolicy,
+    pub limits: ResourceLimits,
+    /// All chains the node runs on
+    pub chains: Vec<Chain>,
+    pub mode: LaunchMode,
+    pub onion: Option<OnionConfig>,
+    /// Addresses the node listens on once started
+    pub listens: Vec<RemoteSocketAddr>,
+    /// Peer access lists added to the persisted ones
+    pub access: PeerAccess,
+    /// Number of the known peers the node connects to once started
+    pub autoconnect: usize,
+    pub bootstrap: Option<BootstrapConfig>,
+    pub addresses: AddressPreference,
+}
+
+pub fn run(config: Config, node: NodeConfig) -> Result<(), Error> {
+    let NodeConfig {
+        local_node,
+        key_file,
+        data_dir,
+        webhooks,
+        acceptance,
+        limits,
+        chains,
+        mode,
+        onion,
+        listens,
+        access,
+        autoconnect,
+        bootstrap,
+        addresses,
+    } = node;
+    let ledger = Ledger::load(&data_dir)?;
+    let mut peer_access = peerd::load_access(&config.peer_access)?;
+    let configured = [
+        PeerAccessUpdate {
+            allow: true,
+            remove: false,
+            nodes: access.allowed_nodes,
+            ip_ranges: access.allowed_ips,
+            allowlist_only: Some(true).filter(|_| access.allowlist_only),
+        },
+        PeerAccessUpdate {
+            allow: false,
+            remove: false,
+            nodes: access.denied_nodes,
+            ip_ranges: access.denied_ips,
+            allowlist_only: None,
+        },
+    ];
+    if configured.iter().fold(false, |changed, update| {
+        peer_access.update(update) || changed
+    }) {
+        peerd::save_access(&config.peer_access, &peer_access)?;
+    }
+    let invoices = InvoiceStore::load(&data_dir)?;
+    let channel_ids = ChannelIdStore::load(&data_dir)?;
+    let peer_store = PeerStore::load(&data_dir, addresses)?;
+    let backups = BackupStore::load(
+        &data_dir,
+        config.chain.clone(),
+        &local_node.private_key(),
+    )?;
+    let webhooks = Dispatcher::start(webhooks);
 
-pub fn run(config: Config, node_id: secp256k1::PublicKey) -> Result<(), Error> {
-    let runtime = Runtime {
+    debug!("Opening bridge between runtime and supervisor threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    // lnpd runs a thread per each of the parallel chains
+    let supervisor_addr = format!("inproc://supervisor-{}", config.chain);
+    tx.connect(&supervisor_addr)?;
+    rx.bind(&supervisor_addr)?;
+
+    let bridge = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    let mut supervisor =
+        Supervisor::start(&config, &local_node, &data_dir, mode, bridge);
+    if supervisor.is_threaded() {
+        // Without separate binaries there is nobody else to start the
+        // network services
+        supervisor.launch(Daemon::Gossip)?;
+        supervisor.launch(Daemon::Routing)?;
+    }
+    let persisted = channeld::persisted_channels(&config, &data_dir)?;
+
+    let mut runtime = Runtime {
         identity: ServiceId::Lnpd,
-        node_id,
+        config: config.clone(),
+        data_dir: data_dir.clone(),
+        node_id: local_node.node_id(),
+        local_node,
+        key_file,
         chain: config.chain.clone(),
+        chains,
+        alias: config.alias.clone(),
+        features: node_features(&config),
         listens: none!(),
+        onion,
+        onion_address: None,
+        peer_access,
         started: SystemTime::now(),
+        daemons: none!(),
         connections: none!(),
+        inbound_connections: none!(),
+        peer_store,
         channels: none!(),
         spawning_services: none!(),
         opening_channels: none!(),
         accepting_channels: none!(),
+        channel_peers: none!(),
+        channel_ids,
+        aborting_channels: none!(),
+        recovering_channels: none!(),
+        restoring_channels: none!(),
+        disconnecting: none!(),
+        peer_features: none!(),
+        heartbeats: none!(),
+        heartbeat_seq: 0,
+        next_heartbeat: Instant::now() + HEARTBEAT_PERIOD,
+        listings: none!(),
+        batches: none!(),
+        batch_channels: none!(),
+        funding_txs: none!(),
+        funding_watches: none!(),
+        asset_balances: none!(),
+        ledger,
+        invoices,
+        invoice_issuers: none!(),
+        interceptions: none!(),
+        backups,
+        webhooks,
+        acceptance,
+        limits,
+        plugins: none!(),
+        supervisor,
+    };
+    runtime.respawn_channels(persisted);
+    runtime.autoconnect_peers(autoconnect, bootstrap);
+    runtime.start_listeners(listens);
+
+    let mut service = Service::broker(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
+}
+
+/// Optional BOLT-9 feature bits supported by the node with the given
+/// configuration, with the feature names
+pub fn node_features(config: &Config) -> BTreeMap<u16, String> {
+    let mut features = bmap! {
+        // Channel daemons always assign random short channel id aliases
+        47u16 => s!("option_scid_alias")
     };
+    if config.static_remotekey {
+        features.insert(13, s!("option_static_remotekey"));
+    }
+    if config.wumbo {
+        features.insert(19, s!("option_support_large_channel"));
+    }
+    if config.anchors {
+        features.insert(23, s!("option_anchors_zero_fee_htlc_tx"));
+    }
+    if config.zero_conf {
+        features.insert(51, s!("option_zeroconf"));
+    }
+    features
+}
 
-    Service::run(config, runtime, true)
+/// BOLT-2 `channel_flags` of the proposed channel; the lowest bit requests
+/// announcing the channel to the network
+fn channel_flags(private: bool) -> u8 {
+    if private {
+        0
+    } else {
+        1
+    }
 }
 
 pub struct Runtime {
     identity: ServiceId,
+    /// Configuration of the channel storage, used to import channels
+    config: Config,
+    data_dir: PathBuf,
     node_id: secp256k1::PublicKey,
+    local_node: LocalNode,
+    /// File keeping the node key, which is replaced when the node key is
+    /// imported from the node archive
+    key_file: PathBuf,
     chain: Chain,
+    /// All chains served by the lnpd process
+    chains: Vec<Chain>,
+    alias: Option<String>,
+    /// Optional feature bits supported by the node
+    features: BTreeMap<u16, String>,
     listens: HashSet<RemoteSocketAddr>,
+    /// Tor control port used to publish the listeners as onion service
+    onion: Option<OnionConfig>,
+    /// Address of the published onion service
+    onion_address: Option<InetSocketAddr>,
+    /// Access lists of remote peers, shared with the listeners through
+    /// the file in the data directory
+    peer_access: PeerAccess,
     started: SystemTime,
+    /// Running daemons which have registered themselves with `hello`
+    /// message, indexed by their service id
+    daemons: HashMap<ServiceId, DaemonInfo>,
     connections: HashSet<NodeAddr>,
+    /// Connections accepted from remote peers, taking the limited inbound
+    /// slots
+    inbound_connections: HashSet<NodeAddr>,
+    /// Addresses of the remote peers known from the previous connections
+    /// and gossip; the peers are reconnected on the node startup
+    peer_store: PeerStore,
     channels: HashSet<ChannelId>,
-    spawning_services: HashMap<ServiceId, ServiceId>,
-    opening_channels: HashMap<ServiceId, request::CreateChannel>,
-    accepting_channels: HashMap<ServiceId, request::CreateChannel>,
+    /// Clients awaiting the connection to the remote node, indexed by the
+    /// node id
+    spawning_services: HashMap<secp256k1::PublicKey, ServiceId>,
+    /// Channels awaiting registration of their daemons, indexed by the
+    /// temporary channel id
+    opening_channels: HashMap<ChannelId, request::CreateChannel>,
+    accepting_channels: HashMap<ChannelId, request::CreateChannel>,
+    /// Remote nodes of the channels, indexed by the channel daemon
+    channel_peers: HashMap<ServiceId, secp256k1::PublicKey>,
+    /// Current ids of the funded channels, indexed by the temporary channel
+    /// id identifying the channel daemon
+    channel_ids: ChannelIdStore,
+    /// Channel daemons ordered to abort the channel opening, with the clients
+    /// awaiting the confirmation
+    aborting_channels: HashMap<ServiceId, ServiceId>,
+    /// Channel daemons launched to recover channels from the static backup
+    recovering_channels: HashMap<ServiceId, ChannelBackup>,
+    /// Channel daemons relaunched for the channels persisted before the node
+    /// restart, which have not yet reported their remote peers
+    restoring_channels: HashSet<ServiceId>,
+    /// Connection daemons ordered to disconnect, with the clients awaiting
+    /// the confirmation
+    disconnecting: HashMap<ServiceId, ServiceId>,
+    /// Features negotiated by the connection daemons with the remote peers
+    peer_features: HashMap<ServiceId, PeerFeatures>,
+    /// Health of the registered daemons, tracked with heartbeats
+    heartbeats: HashMap<ServiceId, Heartbeat>,
+    heartbeat_seq: u64,
+    next_heartbeat: Instant,
+    /// Client requests listing peers or channels, awaiting the information
+    /// from the daemons
+    listings: Vec<Listing>,
+    /// Channel batches funded by a single transaction, indexed by the batch
+    /// id
+    batches: HashMap<u64, ChannelBatch>,
+    /// Batch ids indexed by the channel daemons opening batch channels
+    batch_channels: HashMap<ServiceId, u64>,
+    /// Signed funding transactions provided by clients, published once the
+    /// channel spending them is funded
+    funding_txs: HashMap<Txid, Transaction>,
+    /// Channel daemons awaiting confirmation of their funding transactions
+    funding_watches: HashMap<Txid, ServiceId>,
+    asset_balances: BTreeMap<ChannelId, AssetsBalance>,
+    ledger: Ledger,
+    invoices: InvoiceStore,
+    /// Daemons which have created the unpaid invoices
+    invoice_issuers: HashMap<HashLock, ServiceId>,
+    /// Daemons forwarding HTLCs with the payment hash, together with the
+    /// short channel id of the next hop
+    interceptions: HashMap<HashLock, (ServiceId, u64)>,
+    backups: BackupStore,
+    webhooks: Dispatcher,
+    /// Rules for the channels proposed by remote peers, applied before the
+    /// plugins are asked
+    acceptance: AcceptancePolicy,
+    limits: ResourceLimits,
+    plugins: Plugins,
+    /// Launched daemons, which are restarted if they crash
+    supervisor: Supervisor,
+}
+
+/// Responsiveness of a registered daemon on the CTL bus
+struct Heartbeat {
+    last_seen: Instant,
+    healthy: bool,
+}
+
+/// List of the peers or channels or the node state being collected from the
+/// daemons
+enum Listed {
+    Peers(PeerList),
+    Channels(Vec<ChannelInfo>),
+    State(NodeState),
+}
+
+/// Channels opened in a batch on behalf of a single client
+struct ChannelBatch {
+    enquirer: ServiceId,
+    size: usize,
+    /// Channel daemons which have not yet completed funding of their
+    /// channels
+    pending: HashSet<ServiceId>,
+}
+
+struct Listing {
+    enquirer: ServiceId,
+    /// Daemons which have not yet reported their information
+    awaiting: HashSet<ServiceId>,
+    listed: Listed,
+    deadline: Instant,
+}
+
+impl Listing {
+    fn into_reply(self) -> Request {
+        match self.listed {
+            Listed::Peers(peers) => Request::PeerList(peers),
+            Listed::Channels(channels) => {
+                Request::ChannelList(channels.into_iter().collect())
+            }
+            Listed::State(state) => Request::NodeState(state),
+        }
+    }
 }
 
 impl esb::Handler<ServiceBus> for Runtime {
@@ -82,9 +432,7 @@ impl esb::Handler<ServiceBus> for Runtime {
         match bus {
             ServiceBus::Msg => self.handle_rpc_msg(senders, source, request),
             ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
-            _ => {
-                Err(Error::NotSupported(ServiceBus::Bridge, request.get_type()))
-            }
+            ServiceBus::Bridge => self.handle_bridge(senders, request),
         }
     }
 
@@ -99,18 +447,81 @@ impl esb::Handler<ServiceBus> for Runtime {
 impl Runtime {
     fn handle_rpc_msg(
         &mut self,
-        _senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
         source: ServiceId,
         request: Request,
     ) -> Result<(), Error> {
+        let (request, tlvs) = request.extract_tlvs();
         match request {
-            Request::Hello => {
+            Request::Hello(_) => {
                 // Ignoring; this is used to set remote identity at ZMQ level
             }
 
             Request::PeerMessage(Messages::OpenChannel(open_channel)) => {
-                info!("Creating channel by peer request from {}", source);
-                self.create_channel(source, None, open_channel, true)?;
+                let temp_id = ChannelId::from_inner(
+                    open_channel.temporary_channel_id.into_inner(),
+                );
+                if let Err(err) = self.check_duplicate(temp_id) {
+                    warn!(
+                        "Channel proposed by {} is declined: {}",
+                        source, err
+                    );
+                    self.reject_channel(
+                        senders,
+                        source,
+                        &open_channel,
+                        err.to_string(),
+                    )?;
+                    return Ok(());
+                }
+                if let Err(err) = self.check_channel_limits() {
+                    warn!(
+                        "Channel proposed by {} is declined: {}",
+                        source, err
+                    );
+                    self.reject_channel(
+                        senders,
+                        source,
+                        &open_channel,
+                        err.to_string(),
+                    )?;
+                    return Ok(());
+                }
+                if let Err(err) = self.check_acceptance(&source, &open_channel)
+                {
+                    warn!(
+                        "Channel proposed by {} is rejected: {}",
+                        source, err
+                    );
+                    self.reject_channel(
+                        senders,
+                        source,
+                        &open_channel,
+                        err.to_string(),
+                    )?;
+                    return Ok(());
+                }
+                let call = HookCall {
+                    id: 0,
+                    point: HookPoint::ChannelProposal,
+                    peer: match source {
+                        ServiceId::Peer(ref addr) => Some(addr.clone()),
+                        _ => None,
+                    },
+                    channel_id: Some(open_channel.temporary_channel_id.into()),
+                    amount_msat: Some(open_channel.funding_satoshis * 1000),
+                    payment_hash: None,
+                    details: format!("Channel proposed by {}", source),
+                };
+                let progress = self.plugins.run(
+                    call,
+                    HookOrigin::ChannelProposal {
+                        peerd: source,
+                        open_channel,
+                        tlvs,
+                    },
+                );
+                self.process_hook(senders, progress)?;
             }
 
             Request::PeerMessage(_) => {
@@ -138,10 +549,36 @@ impl Runtime {
     ) -> Result<(), Error> {
         let mut notify_cli = None;
         match request {
-            Request::Hello => {
-                // Ignoring; this is used to set remote identity at ZMQ level
+            Request::Hello(info) => {
                 info!("{} daemon is {}", source.ended(), "connected".ended());
 
+                if info.service != source {
+                    error!(
+                        "{} daemon registers itself as {}; ignoring",
+                        source, info.service
+                    );
+                    return Ok(());
+                }
+                if info.chain != self.chain {
+                    error!(
+                        "{} daemon serves {} instead of {}; ignoring",
+                        source, info.chain, self.chain
+                    );
+                    return Ok(());
+                }
+                if self.daemons.insert(source.clone(), info.clone()).is_some() {
+                    debug!("Daemon {} is re-registered with {}", source, info);
+                } else {
+                    debug!("Daemon {} is registered with {}", source, info);
+                }
+                self.heartbeats.insert(
+                    source.clone(),
+                    Heartbeat {
+                        last_seen: Instant::now(),
+                        healthy: true,
+                    },
+                );
+
                 match &source {
                     ServiceId::Lnpd => {
                         error!(
@@ -150,6 +587,108 @@ impl Runtime {
                         );
                     }
                     ServiceId::Peer(connection_id) => {
+                        // Incoming connections are accepted by the listening
+                        // daemon, so their limit is enforced on registration
+                        if !self.connections.contains(connection_id)
+                            && !self
+                                .spawning_services
+                                .contains_key(&connection_id.id)
+                        {
+                            if let Err(err) = self
+                                .limits
+                                .check(Resource::Peers, self.connections.len())
+                                .and_then(|_| {
+                                    self.assign_inbound_slot(
+                                        senders,
+                                        connection_id,
+                                    )
+                                })
+                            {
+                                warn!(
+                                    "Closing incoming connection {}: {}",
+                                    connection_id, err
+                                );
+                                senders.send_to(
+                                    ServiceBus::Ctl,
+                                    ServiceId::Lnpd,
+                                    source.clone(),
+                                    Request::DisconnectPeer(connection_id.id),
+                                )?;
+                                return Ok(());
+                            }
+                        }
+                        for (plugin, call) in self.plugins.notify(HookCall {
+                            id: 0,
+                            point: HookPoint::PeerConnected,
+                            peer: Some(connection_id.clone()),
+                            channel_id: None,
+                            amount_msat: None,
+                            payment_hash: None,
+                            details: format!("Connected to {}", connection_id),
+                        }) {
+                            senders.send_to(
+                                ServiceBus::Ctl,
+                                ServiceId::Lnpd,
+                                plugin,
+                                Request::HookCall(call),
+                            )?;
+                        }
+                        // Channels with the node must re-establish their state
+                        // over the new connection; channels being recovered
+                        // are notified once their daemons are launched
+                        let recovering = &self.recovering_channels;
+                        for (channeld, _) in self.channel_peers.iter().filter(
+                            |(channeld, node_id)| {
+                                **node_id == connection_id.id
+                                    && !recovering.contains_key(channeld)
+                            },
+                        ) {
+                            // New connection routes peer messages addressed
+                            // by the final channel id to the channel daemon
+                            if let Some(channel_id) = match channeld {
+                                ServiceId::Channel(temp_id) => {
+                                    self.channel_ids.get(*temp_id)
+                                }
+                                _ => None,
+                            } {
+                                senders.send_to(
+                                    ServiceBus::Ctl,
+                                    ServiceId::Lnpd,
+                                    source.clone(),
+                                    Request::RouteChannel(
+                                        request::ChannelRoute {
+                                            channel_id,
+                                            channeld: channeld.clone(),
+                                        },
+                                    ),
+                                )?;
+                            }
+                            senders.send_to(
+                                ServiceBus::Ctl,
+                                ServiceId::Lnpd,
+                                channeld.clone(),
+                                Request::PeerReconnected(connection_id.clone()),
+                            )?;
+                        }
+                        // Incoming connections are identified by the node id
+                        // of the listener and have no address to reconnect
+                        if let NodeAddr::Remote(RemoteNodeAddr {
+                            node_id,
+                            remote_addr,
+                        }) = connection_id
+                        {
+                            if *node_id != self.node_id {
+                                let has_channels = self
+                                    .channel_peers
+                                    .values()
+                                    .any(|peer| peer == node_id);
+                                self.peer_store.connected(
+                                    *node_id,
+                                    *remote_addr,
+                                    has_channels,
+                                )?;
+                            }
+                        }
                         if self.connections.insert(connection_id.clone()) {
                             info!(
                                 "Connection {} is registered; total {} \
@@ -165,8 +704,12 @@ impl Runtime {
                             );
                         }
                     }
-                    ServiceId::Channel(channel_id) => {
-                        if self.channels.insert(channel_id.clone()) {
+                    ServiceId::Channel(temp_id) => {
+                        // Channels funded before the daemon restart are known
+                        // by their final ids
+                        let channel_id =
+                            self.channel_ids.get(*temp_id).unwrap_or(*temp_id);
+                        if self.channels.insert(channel_id) {
                             info!(
                                 "Channel {} is registered; total {} \
                                  channels are known",
@@ -181,12 +724,16 @@ impl Runtime {
                             );
                         }
                     }
+                    ServiceId::Gossip => self.advertise_onion(senders)?,
                     _ => {
                         // Ignoring the rest of daemon/client types
                     }
                 }
 
-                if let Some(channel_params) = self.opening_channels.get(&source)
+                if let Some(channel_params) =
+                    info.channel_id.and_then(|channel_id| {
+                        self.opening_channels.remove(&channel_id)
+                    })
                 {
                     // Tell channeld channel options and link it with the
                     // connection daemon
@@ -201,15 +748,32 @@ impl Runtime {
                             source
                         )),
                     ));
+                    if let ServiceId::Peer(ref node_addr) = channel_params.peerd
+                    {
+                        self.channel_peers.insert(source.clone(), node_addr.id);
+                        self.peer_store.set_channels(node_addr.id, true)?;
+                    }
+                    // Channel adapts to the features of the remote peer
+                    if let Some(features) =
+                        self.peer_features.get(&channel_params.peerd)
+                    {
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            self.identity(),
+                            source.clone(),
+                            Request::PeerFeatures(*features),
+                        )?;
+                    }
                     senders.send_to(
                         ServiceBus::Ctl,
                         self.identity(),
                         source.clone(),
-                        Request::OpenChannelWith(channel_params.clone()),
+                        Request::OpenChannelWith(channel_params),
                     )?;
-                    self.opening_channels.remove(&source);
                 } else if let Some(channel_params) =
-                    self.accepting_channels.get(&source)
+                    info.channel_id.and_then(|channel_id| {
+                        self.accepting_channels.remove(&channel_id)
+                    })
                 {
                     // Tell channeld channel options and link it with the
                     // connection daemon
@@ -217,15 +781,31 @@ impl Runtime {
                         "Daemon {} is known: we spawned it to create a channel. \
                          Ordering channel acceptance", source
                     );
+                    if let ServiceId::Peer(ref node_addr) = channel_params.peerd
+                    {
+                        self.channel_peers.insert(source.clone(), node_addr.id);
+                        self.peer_store.set_channels(node_addr.id, true)?;
+                    }
+                    // Channel adapts to the features of the remote peer
+                    if let Some(features) =
+                        self.peer_features.get(&channel_params.peerd)
+                    {
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            self.identity(),
+                            source.clone(),
+                            Request::PeerFeatures(*features),
+                        )?;
+                    }
                     senders.send_to(
                         ServiceBus::Ctl,
                         self.identity(),
                         source.clone(),
-                        Request::AcceptChannelFrom(channel_params.clone()),
+                        Request::AcceptChannelFrom(channel_params),
                     )?;
-                    self.accepting_channels.remove(&source);
-                } else if let Some(enquirer) =
-                    self.spawning_services.get(&source)
+                } else if let Some(enquirer) = info
+                    .remote_node
+                    .and_then(|node_id| self.spawning_services.remove(&node_id))
                 {
                     debug!(
                         "Daemon {} is known: we spawned it to create a new peer \
@@ -233,13 +813,140 @@ impl Runtime {
                         source, enquirer
                     );
                     notify_cli = Some((
-                        Some(enquirer.clone()),
+                        Some(enquirer),
                         Request::Success(OptionDetails::with(format!(
                             "Peer connected to {}",
                             source
                         ))),
                     ));
-                    self.spawning_services.remove(&source);
+                } else if let Some(backup) =
+                    self.recovering_channels.remove(&source)
+                {
+                    debug!(
+                        "Daemon {} is known: we spawned it to recover a channel \
+                         from the static backup", source
+                    );
+                    let connected =
+                        self.connections.contains(&backup.remote_peer);
+                    let remote_peer = backup.remote_peer.clone();
+                    senders.send_to(
+                        ServiceBus::Ctl,
+                        self.identity(),
+                        source.clone(),
+                        Request::RecoverChannel(backup),
+                    )?;
+                    if connected {
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            self.identity(),
+                            source.clone(),
+                            Request::PeerReconnected(remote_peer),
+                        )?;
+                    }
+                } else if self.restoring_channels.contains(&source) {
+                    debug!(
+                        "Daemon {} is known: we relaunched it for a persisted \
+                         channel. Requesting channel information",
+                        source
+                    );
+                    senders.send_to(
+                        ServiceBus::Ctl,
+                        self.identity(),
+                        source.clone(),
+                        Request::GetInfo,
+                    )?;
+                } else if self.aborting_channels.contains_key(&source) {
+                    // Channel opening was cancelled before the daemon has
+                    // started, so we just order it to terminate
+                    if let ServiceId::Channel(channel_id) = source {
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            self.identity(),
+                            source.clone(),
+                            Request::AbortChannel(channel_id),
+                        )?;
+                    }
+                }
+            }
+
+            Request::AbortChannel(channel_id) => {
+                let channeld = self
+                    .channel_ids
+                    .find(channel_id)
+                    .map(ServiceId::Channel)
+                    .unwrap_or_else(|| channel_id.into());
+                let pending = match channeld {
+                    ServiceId::Channel(ref temp_id) => {
+                        self.opening_channels.remove(temp_id)
+                    }
+                    _ => None,
+                };
+                if pending.is_some() {
+                    info!(
+                        "Opening of channel {} is cancelled before its daemon \
+                         has started",
+                        channel_id
+                    );
+                    self.aborting_channels.insert(channeld, source.clone());
+                    notify_cli = Some((
+                        Some(source),
+                        Request::Progress(format!(
+                            "Awaiting channel daemon {} to launch",
+                            channel_id
+                        )),
+                    ));
+                } else if self.channels.contains(&channel_id) {
+                    info!("Aborting opening of channel {}", channel_id);
+                    self.aborting_channels.insert(channeld.clone(), source);
+                    senders.send_to(
+                        ServiceBus::Ctl,
+                        self.identity(),
+                        channeld,
+                        Request::AbortChannel(channel_id),
+                    )?;
+                } else {
+                    let msg = format!("Unknown channel {}", channel_id);
+                    error!("{}", msg);
+                    notify_cli = Some((
+                        Some(source),
+                        Request::Failure(Failure { code: 1, info: msg }),
+                    ));
+                }
+            }
+
+            Request::Failure(failure)
+                if self.aborting_channels.contains_key(&source) =>
+            {
+                // Channel daemon has refused to abort the channel
+                let enquirer = self.aborting_channels.remove(&source);
+                notify_cli = Some((enquirer, Request::Failure(failure)));
+            }
+
+            Request::ChannelAborted(channel_id) => {
+                info!("Channel {} is {}", channel_id, "aborted".ended());
+                self.channels.remove(&channel_id);
+                if let Some(node_id) = self.channel_peers.remove(&source) {
+                    if !self.channel_peers.values().any(|peer| *peer == node_id)
+                    {
+                        self.peer_store.set_channels(node_id, false)?;
+                    }
+                }
+                self.asset_balances.remove(&channel_id);
+                self.daemons.remove(&source);
+                self.batch_channels.remove(&source);
+                if let ServiceId::Channel(ref temp_id) = source {
+                    self.channel_ids.remove(*temp_id)?;
+                    self.opening_channels.remove(temp_id);
+                    self.accepting_channels.remove(temp_id);
+                }
+                if let Some(enquirer) = self.aborting_channels.remove(&source) {
+                    notify_cli = Some((
+                        Some(enquirer),
+                        Request::Success(OptionDetails::with(format!(
+                            "Channel {} is aborted",
+                            channel_id
+                        ))),
+                    ));
                 }
             }
 
@@ -248,11 +955,17 @@ impl Runtime {
                     "Requested to update channel id {} on {}",
                     source, new_id
                 );
-                if let ServiceId::Channel(old_id) = source {
-                    if !self.channels.remove(&old_id) {
-                        warn!("Channel daemon {} was unknown", source);
+                if let ServiceId::Channel(temp_id) = source {
+                    // Funding transaction may be replaced, changing the
+                    // channel id more than once
+                    let old_id = self
+                        .channel_ids
+                        .insert(temp_id, new_id)?
+                        .unwrap_or(temp_id);
+                    self.rename_channel(old_id, new_id);
+                    if let Some(info) = self.daemons.get_mut(&source) {
+                        info.channel_id = Some(new_id);
                     }
-                    self.channels.insert(new_id);
                     debug!("Registered channel daemon id {}", new_id);
                 } else {
                     error!(
@@ -262,14 +975,42 @@ impl Runtime {
                 }
             }
 
+            Request::ChannelActivated(channel_id) => {
+                info!("Channel {} is {}", channel_id.ender(), "active".ended());
+                self.webhooks.dispatch(&NodeEvent {
+                    kind: NodeEventKind::ChannelOpened,
+                    channel_id: Some(channel_id),
+                    txid: None,
+                    amount_msat: None,
+                    details: format!(
+                        "Channel {} is locked and ready for payments",
+                        channel_id
+                    ),
+                });
+            }
+
             Request::GetInfo => {
+                // Peers are counted by their registered connection daemons
+                let peers: Vec<_> = self
+                    .daemons
+                    .keys()
+                    .filter_map(|service| match service {
+                        ServiceId::Peer(node_addr) => Some(node_addr.clone()),
+                        _ => None,
+                    })
+                    .collect();
                 senders.send_to(
                     ServiceBus::Ctl,
                     ServiceId::Lnpd,
                     source,
                     Request::NodeInfo(NodeInfo {
                         node_id: self.node_id,
+                        alias: self.alias.clone(),
+                        version: s!(env!("CARGO_PKG_VERSION")),
+                        chain: self.chain.clone(),
+                        chains: self.chains.clone(),
                         listens: self.listens.iter().cloned().collect(),
+                        onion: self.onion_address,
                         uptime: SystemTime::now()
                             .duration_since(self.started)
                             .unwrap_or(Duration::from_secs(0)),
@@ -278,34 +1019,419 @@ impl Runtime {
                             .duration_since(SystemTime::UNIX_EPOCH)
                             .unwrap_or(Duration::from_secs(0))
                             .as_secs(),
-                        peers: self.connections.iter().cloned().collect(),
+                        peer_count: peers.len(),
+                        peers,
+                        channel_count: self.channels.len(),
                         channels: self.channels.iter().cloned().collect(),
+                        features: self.features.clone(),
                     }),
                 )?;
             }
 
-            Request::ListPeers => {
+            Request::ChannelBalances(request::ChannelBalances {
+                channel_id,
+                balances,
+            }) => {
+                debug!("Updating asset balances for channel {}", channel_id);
+                self.asset_balances.insert(channel_id, balances);
+            }
+
+            Request::AssetBalances => {
+                let mut total: AssetsBalance = none!();
+                for (asset_id, amount) in self.asset_balances.values().flatten()
+                {
+                    *total.entry(*asset_id).or_insert(0) += amount;
+                }
+                senders.send_to(
+                    ServiceBus::Ctl,
+                    ServiceId::Lnpd,
+                    source,
+                    Request::AssetBalanceInfo(AssetBalanceInfo {
+                        total,
+                        channels: self.asset_balances.clone(),
+                    }),
+                )?;
+            }
+
+            Request::BookEvent(event) => {
+                debug!("Booking {} from {}", event, source);
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or(Duration::from_secs(0))
+                    .as_secs();
+                let kind = match event.kind {
+                    AccountingEventKind::PaymentSent => {
+                        Some(NodeEventKind::PaymentSettled)
+                    }
+                    AccountingEventKind::PaymentReceived => {
+                        Some(NodeEventKind::InvoicePaid)
+                    }
+                    _ => None,
+                };
+                if event.kind == AccountingEventKind::PaymentReceived {
+                    for (plugin, call) in self.plugins.notify(HookCall {
+                        id: 0,
+                        point: HookPoint::InvoicePaid,
+                        peer: None,
+                        channel_id: event.channel_id,
+                        amount_msat: Some(event.amount_msat),
+                        payment_hash: None,
+                        details: event.description.clone(),
+                    }) {
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            ServiceId::Lnpd,
+                            plugin,
+                            Request::HookCall(call),
+                        )?;
+                    }
+                }
+                if let Some(kind) = kind {
+                    self.webhooks.dispatch(&NodeEvent {
+                        kind,
+                        channel_id: event.channel_id,
+                        txid: event.txid,
+                        amount_msat: Some(event.amount_msat),
+                        details: event.description.clone(),
+                    });
+                }
+                self.ledger.book(timestamp, event)?;
+            }
+
+            Request::CreateInvoice(invoice_req) => {
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or(Duration::from_secs(0))
+                    .as_secs();
+                let info = self.invoices.create(now, invoice_req)?;
+                info!("{} {}", "Invoice created:".ended(), info);
+                // Daemons selling their services learn when they are paid
+                if !matches!(source, ServiceId::Client(_)) {
+                    self.invoice_issuers
+                        .insert(info.payment_hash, source.clone());
+                }
+                notify_cli = Some((Some(source), Request::InvoiceInfo(info)));
+            }
+
+            Request::InterceptPayment(PaymentInterception {
+                payment_hash,
+                scid,
+            }) => {
+                info!(
+                    "{} {} to {} by {}",
+                    "Intercepting payment".promo(),
+                    payment_hash,
+                    scid,
+                    source.promoter()
+                );
+                self.interceptions.insert(payment_hash, (source, scid));
+            }
+
+            Request::SettleHtlc(htlc)
+                if self.interceptions.contains_key(&htlc.payment_hash) =>
+            {
+                let (interceptor, scid) = self
+                    .interceptions
+                    .remove(&htlc.payment_hash)
+                    .expect("checked by match guard");
+                info!(
+                    "HTLC #{} of {} is {} by {}",
+                    htlc.htlc_id,
+                    htlc.payment_hash,
+                    "forwarded".ended(),
+                    interceptor.ender()
+                );
+                senders.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    source,
+                    Request::ForwardHtlc(HtlcForward {
+                        htlc_id: htlc.htlc_id,
+                        scid,
+                        interceptor,
+                    }),
+                )?;
+            }
+
+            Request::SettleHtlc(htlc) => {
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or(Duration::from_secs(0))
+                    .as_secs();
+                let preimage = match self.invoices.settle(now, &htlc) {
+                    Ok(preimage) => {
+                        info!(
+                            "HTLC #{} {} invoice {}",
+                            htlc.htlc_id,
+                            "pays".ended(),
+                            htlc.payment_hash
+                        );
+                        if let Some(issuer) =
+                            self.invoice_issuers.remove(&htlc.payment_hash)
+                        {
+                            // Issuer may have stopped since
+                            if let Err(err) = senders.send_to(
+                                ServiceBus::Ctl,
+                                self.identity(),
+                                issuer.clone(),
+                                Request::InvoiceSettled(htlc.payment_hash),
+                            ) {
+                                warn!(
+                                    "Unable to notify {} on payment of {}: {}",
+                                    issuer, htlc.payment_hash, err
+                                );
+                            }
+                        }
+                        Some(preimage)
+                    }
+                    Err(err) => {
+                        warn!(
+                            "HTLC #{} from {} is not settled: {}",
+                            htlc.htlc_id, source, err
+                        );
+                        None
+                    }
+                };
+                senders.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    source,
+                    Request::HtlcSettlement(HtlcSettlement {
+                        htlc_id: htlc.htlc_id,
+                        preimage,
+                    }),
+                )?;
+            }
+
+            Request::RegisterPlugin(points) => {
+                if let ServiceId::Other(_) = source {
+                    info!(
+                        "{} {} for {:?}",
+                        "Registering plugin".ended(),
+                        source.ender(),
+                        points
+                    );
+                    self.plugins.register(source.clone(), points);
+                    // Plugins are followed with heartbeats, such that they
+                    // are deregistered once they disconnect
+                    self.heartbeats.insert(
+                        source.clone(),
+                        Heartbeat {
+                            last_seen: Instant::now(),
+                            healthy: true,
+                        },
+                    );
+                    notify_cli = Some((
+                        Some(source),
+                        Request::Success(OptionDetails::with(
+                            "plugin registered",
+                        )),
+                    ));
+                } else {
+                    let msg = format!(
+                        "Only external services may be registered as plugins, \
+                         not {}",
+                        source
+                    );
+                    error!("{}", msg);
+                    notify_cli = Some((
+                        Some(source),
+                        Request::Failure(Failure { code: 1, info: msg }),
+                    ));
+                }
+            }
+
+            Request::RunHook(call) => {
+                let call_id = call.id;
+                let progress = self.plugins.run(
+                    call,
+                    HookOrigin::Daemon {
+                        service: source,
+                        call_id,
+                    },
+                );
+                self.process_hook(senders, progress)?;
+            }
+
+            Request::HookResult(result) => {
+                match self.plugins.result(&source, result) {
+                    Some(progress) => self.process_hook(senders, progress)?,
+                    None => warn!("Unexpected hook result from {}", source),
+                }
+            }
+
+            Request::UpdateBackup(backup) => {
+                debug!(
+                    "Updating static backup of channel {}",
+                    backup.channel_id
+                );
+                self.backups.update(backup)?;
+            }
+
+            Request::NodeEvent(event) => {
+                debug!("Dispatching {} from {}", event, source);
+                if let (NodeEventKind::ChannelClosed, Some(channel_id)) =
+                    (event.kind, event.channel_id)
+                {
+                    self.backups.remove(&channel_id)?;
+                }
+                self.webhooks.dispatch(&event);
+            }
+
+            Request::ListWebhookDeliveries => {
                 senders.send_to(
                     ServiceBus::Ctl,
                     ServiceId::Lnpd,
                     source,
-                    Request::PeerList(
-                        self.connections.iter().cloned().collect(),
+                    Request::WebhookDeliveries(
+                        self.webhooks.deliveries().into_iter().collect(),
                     ),
                 )?;
             }
 
-            Request::ListChannels => {
+            Request::ExportAccounting(export_req) => {
                 senders.send_to(
                     ServiceBus::Ctl,
                     ServiceId::Lnpd,
                     source,
-                    Request::ChannelList(
-                        self.channels.iter().cloned().collect(),
+                    Request::AccountingReport(self.ledger.export(export_req)),
+                )?;
+            }
+
+            Request::FundingTransaction(tx) => {
+                let txid = tx.txid();
+                debug!("{} provides funding transaction {}", source, txid);
+                self.funding_txs.insert(txid, tx);
+                notify_cli = Some((
+                    Some(source),
+                    Request::Success(OptionDetails::with(format!(
+                        "Funding transaction {} will be published once the \
+                         channel is funded",
+                        txid
+                    ))),
+                ));
+            }
+
+            Request::AwaitFunding(watch) => {
+                self.await_funding(senders, source, watch);
+            }
+
+            Request::TransactionConfirmed(watch) => {
+                match self.funding_watches.remove(&watch.txid) {
+                    Some(channeld) => {
+                        info!(
+                            "Funding transaction {} of {} is {}",
+                            watch.txid,
+                            channeld,
+                            "confirmed".ended()
+                        );
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            ServiceId::Lnpd,
+                            channeld,
+                            Request::TransactionConfirmed(watch),
+                        )?;
+                    }
+                    None => warn!(
+                        "Confirmation of unknown transaction {}; ignoring",
+                        watch.txid
                     ),
+                }
+            }
+
+            Request::ListPeers => {
+                let peers = PeerList {
+                    peers: vec![],
+                    inbound_slots: self.inbound_connections.len(),
+                    max_inbound_slots: self.limits.max_inbound_peers,
+                };
+                self.start_listing(senders, source, Listed::Peers(peers))?;
+            }
+
+            Request::ListDaemons => {
+                let now = Instant::now();
+                let daemons = self
+                    .daemons
+                    .iter()
+                    .map(|(service, info)| {
+                        let heartbeat = self.heartbeats.get(service);
+                        DaemonHealth {
+                            service: service.to_string(),
+                            pid: info.pid,
+                            last_seen: heartbeat
+                                .map(|hb| (now - hb.last_seen).as_secs())
+                                .unwrap_or_default(),
+                            healthy: heartbeat
+                                .map(|hb| hb.healthy)
+                                .unwrap_or(true),
+                        }
+                    })
+                    .collect();
+                senders.send_to(
+                    ServiceBus::Ctl,
+                    ServiceId::Lnpd,
+                    source.clone(),
+                    Request::DaemonList(daemons),
                 )?;
             }
 
+            Request::Heartbeat(seq) => {
+                trace!("Heartbeat {} is answered by {}", seq, source);
+                if let Some(heartbeat) = self.heartbeats.get_mut(&source) {
+                    if !heartbeat.healthy {
+                        info!(
+                            "{} daemon is {}",
+                            source.ended(),
+                            "responsive again".ended()
+                        );
+                    }
+                    heartbeat.last_seen = Instant::now();
+                    heartbeat.healthy = true;
+                }
+            }
+
+            Request::ListChannels => {
+                self.start_listing(senders, source, Listed::Channels(vec![]))?;
+            }
+
+            Request::PeerInfo(info) => {
+                self.listing_reply(senders, source, |listed| match listed {
+                    Listed::Peers(list) => list.peers.push(info.clone()),
+                    _ => {}
+                })?;
+            }
+
+            Request::ChannelInfo(info) => {
+                if self.restoring_channels.remove(&source) {
+                    self.channel_restored(senders, source.clone(), &info)?;
+                }
+                self.listing_reply(senders, source, |listed| match listed {
+                    Listed::Channels(channels) => channels.push(info.clone()),
+                    _ => {}
+                })?;
+            }
+
+            Request::DaemonState(data) => {
+                let daemon = source.clone();
+                self.listing_reply(senders, source, |listed| {
+                    let state = match listed {
+                        Listed::State(state) => state,
+                        _ => return,
+                    };
+                    match &daemon {
+                        // Channels which were never stored have no data
+                        ServiceId::Channel(channel_id) if !data.is_empty() => {
+                            state.channels.push(ChannelSnapshot {
+                                channel_id: *channel_id,
+                                data: data.clone(),
+                            })
+                        }
+                        ServiceId::Gossip => state.gossip = Some(data.clone()),
+                        _ => {}
+                    }
+                })?;
+            }
+
             Request::Listen(addr) => {
                 let addr_str = addr.addr();
                 if self.listens.contains(&addr) {
@@ -331,6 +1457,7 @@ impl Runtime {
                                    "listens".ended(), addr_str),
                     Err(ref err) => error!("{}", err.err())
                 }
+                    self.advertise_onion(senders)?;
                     senders.send_to(
                         ServiceBus::Ctl,
                         ServiceId::Lnpd,
@@ -347,35 +1474,127 @@ impl Runtime {
                 }
             }
 
-            Request::ConnectPeer(addr) => {
+            Request::ExportNode(request::ExportNode { include_secrets }) => {
                 info!(
-                    "{} to remote peer {}",
-                    "Connecting".promo(),
-                    addr.promoter()
+                    "{} by request from {}",
+                    "Exporting node state".promo(),
+                    source.promoter()
                 );
-                let resp = self.connect_peer(source.clone(), addr);
-                match resp {
-                    Ok(_) => {}
-                    Err(ref err) => error!("{}", err.err()),
-                }
-                notify_cli = Some((
-                    Some(source.clone()),
-                    resp.into_progress_or_failure(),
+                let archive = self.export_node(include_secrets);
+                notify_cli =
+                    Some((Some(source.clone()), Request::NodeArchive(archive)));
+            }
+
+            Request::ExportState(request::ExportNode { include_secrets }) => {
+                info!(
+                    "{} by request from {}",
+                    "Exporting full node state".promo(),
+                    source.promoter()
+                );
+                let state = NodeState {
+                    version: NODE_STATE_VERSION,
+                    archive: self.export_node(include_secrets),
+                    channels: vec![],
+                    gossip: None,
+                    invoices: self.invoices.export()?,
+                    payments: self.ledger.export_journal()?,
+                };
+                self.start_listing(senders, source, Listed::State(state))?;
+            }
+
+            Request::ImportState(state) => {
+                info!(
+                    "{} by request from {}",
+                    "Importing full node state".promo(),
+                    source.promoter()
+                );
+                let resp = self.import_state(senders, source.clone(), state);
+                match resp {
+                    Ok(_) => {}
+                    Err(ref err) => error!("{}", err.err()),
+                }
+                notify_cli = Some((
+                    Some(source.clone()),
+                    resp.into_success_or_failure(),
                 ));
             }
 
-            Request::OpenChannelWith(request::CreateChannel {
-                channel_req,
-                peerd,
-                report_to,
-            }) => {
+            Request::ImportNode(archive) => {
                 info!(
                     "{} by request from {}",
-                    "Creating channel".promo(),
+                    "Importing node state".promo(),
+                    source.promoter()
+                );
+                let resp = self.import_node(source.clone(), archive);
+                match resp {
+                    Ok(_) => self.advertise_onion(senders)?,
+                    Err(ref err) => error!("{}", err.err()),
+                }
+                notify_cli = Some((
+                    Some(source.clone()),
+                    resp.into_success_or_failure(),
+                ));
+            }
+
+            Request::ExportBackup => {
+                info!(
+                    "{} by request from {}",
+                    "Exporting static channel backup".promo(),
+                    source.promoter()
+                );
+                notify_cli = Some((
+                    Some(source.clone()),
+                    Request::StaticBackup(self.backups.export()),
+                ));
+            }
+
+            Request::RestoreBackup(blob) => {
+                info!(
+                    "{} by request from {}",
+                    "Recovering channels from static backup".promo(),
                     source.promoter()
                 );
-                let resp =
-                    self.create_channel(peerd, report_to, channel_req, false);
+                let resp = self.restore_backup(source.clone(), &blob);
+                match resp {
+                    Ok(_) => {}
+                    Err(ref err) => error!("{}", err.err()),
+                }
+                notify_cli = Some((
+                    Some(source.clone()),
+                    resp.into_success_or_failure(),
+                ));
+            }
+
+            Request::ConnectPeer(addr)
+                if self.connections.iter().any(|conn| conn.id == addr.id) =>
+            {
+                notify_cli = Some((
+                    Some(source.clone()),
+                    Request::Success(OptionDetails::with(format!(
+                        "Already connected to {}",
+                        addr
+                    ))),
+                ));
+            }
+
+            Request::ConnectPeer(addr)
+                if self.spawning_services.contains_key(&addr.id) =>
+            {
+                let msg = format!("Connection to {} is in progress", addr);
+                error!("{}", msg.err());
+                notify_cli = Some((
+                    Some(source.clone()),
+                    Request::Failure(Failure { code: 1, info: msg }),
+                ));
+            }
+
+            Request::ConnectPeer(addr) => {
+                info!(
+                    "{} to remote peer {}",
+                    "Connecting".promo(),
+                    addr.promoter()
+                );
+                let resp = self.connect_peer(source.clone(), addr);
                 match resp {
                     Ok(_) => {}
                     Err(ref err) => error!("{}", err.err()),
@@ -386,84 +1605,1716 @@ impl Runtime {
                 ));
             }
 
-            _ => {
-                error!(
-                    "{}",
-                    "Request is not supported by the CTL interface".err()
-                );
-                return Err(Error::NotSupported(
-                    ServiceBus::Ctl,
-                    request.get_type(),
-                ));
+            Request::DisconnectPeer(node_id) => {
+                let peers: Vec<_> = self
+                    .connections
+                    .iter()
+                    .filter(|conn| conn.id == node_id)
+                    .cloned()
+                    .collect();
+                if peers.is_empty() {
+                    let msg = format!("Not connected to {}", node_id);
+                    error!("{}", msg.err());
+                    notify_cli = Some((
+                        Some(source.clone()),
+                        Request::Failure(Failure { code: 1, info: msg }),
+                    ));
+                } else {
+                    info!(
+                        "{} from remote peer {}",
+                        "Disconnecting".promo(),
+                        node_id.promoter()
+                    );
+                    for node_addr in peers {
+                        let peerd = ServiceId::Peer(node_addr);
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            self.identity(),
+                            peerd.clone(),
+                            Request::DisconnectPeer(node_id),
+                        )?;
+                        self.disconnecting.insert(peerd, source.clone());
+                    }
+                    notify_cli = Some((
+                        Some(source.clone()),
+                        Request::Progress(format!(
+                            "Closing connection with {}",
+                            node_id
+                        )),
+                    ));
+                }
+            }
+
+            Request::UpdatePeerAccess(update) => {
+                let resp = self.update_peer_access(senders, &update);
+                notify_cli = Some((
+                    Some(source.clone()),
+                    resp.into_success_or_failure(),
+                ));
+            }
+
+            Request::GetPeerAccess => {
+                notify_cli = Some((
+                    Some(source.clone()),
+                    Request::PeerAccess(self.peer_access.clone()),
+                ));
+            }
+
+            Request::PeerFeatures(features) => {
+                debug!("Peer {} has negotiated {}", source, features);
+                self.peer_features.insert(source.clone(), features);
+                if let ServiceId::Peer(ref node_addr) = source {
+                    let daemons = &self.daemons;
+                    let channelds = self
+                        .channel_peers
+                        .iter()
+                        .filter(|(channeld, node_id)| {
+                            **node_id == node_addr.id
+                                && daemons.contains_key(channeld)
+                        })
+                        .map(|(channeld, _)| channeld.clone())
+                        .collect::<Vec<_>>();
+                    for channeld in channelds {
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            ServiceId::Lnpd,
+                            channeld,
+                            Request::PeerFeatures(features),
+                        )?;
+                    }
+                }
+            }
+
+            Request::BroadcastGossip(GossipBroadcast {
+                message,
+                tlvs,
+                origin,
+            }) => {
+                // Peers accept gossip only after init messages are exchanged
+                let peers = self
+                    .peer_features
+                    .keys()
+                    .filter(|peerd| Some(*peerd) != origin.as_ref())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                trace!("Broadcasting {} to {} peer(s)", message, peers.len());
+                for peerd in peers {
+                    senders.send_to(
+                        ServiceBus::Msg,
+                        ServiceId::Lnpd,
+                        peerd,
+                        Request::with_tlvs(message.clone(), tlvs.clone()),
+                    )?;
+                }
+            }
+
+            Request::NodeAddresses(request::NodeAddresses {
+                node_id,
+                addrs,
+            }) => {
+                // Addresses of the nodes we have never connected to are
+                // known to gossipd
+                if self.peer_store.contains(node_id) {
+                    self.peer_store.add_addrs(node_id, addrs)?;
+                }
+            }
+
+            Request::PeerDisconnected(node_addr) => {
+                info!("Peer {} is {}", node_addr, "disconnected".ended());
+                self.peer_disconnected(senders, &node_addr)?;
+                self.daemons.remove(&source);
+                // Connection is closed on purpose, so it must not be restored
+                self.supervisor.forget(&source);
+                if let Some(enquirer) = self.disconnecting.remove(&source) {
+                    notify_cli = Some((
+                        Some(enquirer),
+                        Request::Success(OptionDetails::with(format!(
+                            "Disconnected from {}",
+                            node_addr
+                        ))),
+                    ));
+                }
+            }
+
+            Request::PeerDead(request::PeerDead {
+                node_addr,
+                reason,
+                details,
+            }) => {
+                warn!(
+                    "Peer {} is {} ({}): {}",
+                    node_addr,
+                    "dead".err(),
+                    reason,
+                    details
+                );
+                self.peer_disconnected(senders, &node_addr)?;
+                self.daemons.remove(&source);
+                // Only outgoing connections can be restored by the node;
+                // incoming ones await the remote peer to connect again
+                match self.supervisor.schedule_restart(&source) {
+                    Some(delay) => {
+                        info!("Reconnecting to {} in {:?}", node_addr, delay);
+                        self.rotate_address(&source)?;
+                    }
+                    None => debug!("Awaiting {} to connect again", node_addr),
+                }
+            }
+
+            Request::OpenChannelWith(mut create) => {
+                info!(
+                    "{} by request from {}",
+                    "Creating channel".promo(),
+                    source.promoter()
+                );
+                create.channel_req.channel_flags =
+                    channel_flags(create.private);
+                // Client tracking the channel may differ from the one
+                // requesting it, and must learn the channel will not open
+                let also_report_to = create
+                    .report_to
+                    .clone()
+                    .filter(|report_to| *report_to != source);
+                let resp = self.create_channel(
+                    request::CreateChannel {
+                        funding_batch: None,
+                        remote_tlvs: empty!(),
+                        ..create
+                    },
+                    false,
+                );
+                if let Err(ref err) = resp {
+                    error!("{}", err.err());
+                    if let Some(report_to) = also_report_to {
+                        senders.send_to(
+                            ServiceBus::Ctl,
+                            ServiceId::Lnpd,
+                            report_to,
+                            Request::Failure(Failure {
+                                code: 1,
+                                info: err.to_string(),
+                            }),
+                        )?;
+                    }
+                }
+                notify_cli = Some((
+                    Some(source.clone()),
+                    resp.into_progress_or_failure(),
+                ));
+            }
+
+            Request::OpenChannelBatch(request::ChannelBatch {
+                channel_reqs,
+                peerd,
+                report_to,
+                shutdown_scriptpubkey,
+                private,
+            }) => {
+                info!(
+                    "{} of {} channel(s) by request from {}",
+                    "Creating batch".promo(),
+                    channel_reqs.len(),
+                    source.promoter()
+                );
+                let enquirer = report_to.unwrap_or_else(|| source.clone());
+                let resp = self.open_batch(
+                    peerd,
+                    enquirer,
+                    channel_reqs,
+                    shutdown_scriptpubkey,
+                    private,
+                );
+                if let Err(ref err) = resp {
+                    error!("{}", err.err());
+                }
+                notify_cli =
+                    Some((Some(source), resp.into_progress_or_failure()));
+            }
+
+            report @ Request::Progress(_)
+            | report @ Request::Success(_)
+            | report @ Request::Failure(_)
+                if self.batch_channels.contains_key(&source) =>
+            {
+                self.batch_report(senders, source, report)?;
+            }
+
+            _ => {
+                error!(
+                    "{}",
+                    "Request is not supported by the CTL interface".err()
+                );
+                return Err(Error::NotSupported(
+                    ServiceBus::Ctl,
+                    request.get_type(),
+                ));
+            }
+        }
+
+        if let Some((Some(respond_to), resp)) = notify_cli {
+            senders.send_to(
+                ServiceBus::Ctl,
+                ServiceId::Lnpd,
+                respond_to,
+                resp,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_bridge(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::DaemonCrashed(crash) => {
+                self.daemon_crashed(senders, crash)
+            }
+            Request::CheckTimeouts => {
+                self.check_heartbeats(senders)?;
+                for progress in self.plugins.expire(Instant::now()) {
+                    self.process_hook(senders, progress)?;
+                }
+                self.complete_listings(senders)
+            }
+            _ => {
+                Err(Error::NotSupported(ServiceBus::Bridge, request.get_type()))
+            }
+        }
+    }
+
+    /// Notifies external systems and clients awaiting the daemon about its
+    /// crash; the daemon itself is restarted by the supervisor, unless it has
+    /// failed to start the connection or channel it was launched for
+    fn daemon_crashed(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        crash: DaemonCrash,
+    ) -> Result<(), Error> {
+        let details = format!(
+            "{} with PID {} has crashed with {}; restarting in {} seconds",
+            crash.name, crash.pid, crash.status, crash.restart_delay
+        );
+        warn!("{}", details.err());
+
+        let channel_id = match crash.service {
+            Some(ServiceId::Channel(temp_id)) => {
+                Some(self.channel_ids.get(temp_id).unwrap_or(temp_id))
+            }
+            _ => None,
+        };
+        self.webhooks.dispatch(&NodeEvent {
+            kind: NodeEventKind::DaemonCrashed,
+            channel_id,
+            txid: None,
+            amount_msat: None,
+            details: details.clone(),
+        });
+
+        let service = match crash.service {
+            Some(service) => service,
+            None => return Ok(()),
+        };
+        if let ServiceId::Peer(ref node_addr) = service {
+            if let Some(enquirer) = self.spawning_services.remove(&node_addr.id)
+            {
+                // Connection was never established, so we report failure
+                // instead of retrying it
+                self.supervisor.forget(&service);
+                senders.send_to(
+                    ServiceBus::Ctl,
+                    ServiceId::Lnpd,
+                    enquirer,
+                    Request::Failure(Failure {
+                        code: 1,
+                        info: format!(
+                            "Unable to connect to {}: peerd exited with {}",
+                            node_addr, crash.status
+                        ),
+                    }),
+                )?;
+                return Ok(());
+            }
+            // Channels pause until the connection is restored
+            self.peer_disconnected(senders, node_addr)?;
+            self.rotate_address(&service)?;
+        }
+        if let ServiceId::Channel(temp_id) = service {
+            let pending = self
+                .opening_channels
+                .remove(&temp_id)
+                .map(|channel| (channel, false))
+                .or_else(|| {
+                    self.accepting_channels
+                        .remove(&temp_id)
+                        .map(|channel| (channel, true))
+                });
+            if let Some((channel, accept)) = pending {
+                // Daemon has exited before registering itself, so it is
+                // unable to run the channel and must not be restarted
+                self.supervisor.forget(&service);
+                let info = format!(
+                    "Unable to start channel {}: channeld exited with {}",
+                    temp_id, crash.status
+                );
+                error!("{}", info);
+                if accept {
+                    self.reject_channel(
+                        senders,
+                        channel.peerd,
+                        &channel.channel_req,
+                        s!("unable to start channel daemon"),
+                    )?;
+                } else if channel.funding_batch.is_some() {
+                    self.batch_report(
+                        senders,
+                        service,
+                        Request::Failure(Failure { code: 1, info }),
+                    )?;
+                } else if let Some(report_to) = channel.report_to {
+                    senders.send_to(
+                        ServiceBus::Ctl,
+                        ServiceId::Lnpd,
+                        report_to,
+                        Request::Failure(Failure { code: 1, info }),
+                    )?;
+                }
+                return Ok(());
+            }
+        }
+        // The daemon will register itself again once restarted
+        let info = self.daemons.remove(&service).unwrap_or_else(|| {
+            DaemonInfo::with(service.clone(), self.chain.clone())
+        });
+        let enquirer = info
+            .channel_id
+            .and_then(|channel_id| {
+                self.opening_channels
+                    .get(&channel_id)
+                    .or_else(|| self.accepting_channels.get(&channel_id))
+            })
+            .and_then(|channel| channel.report_to.clone())
+            .or_else(|| self.aborting_channels.get(&service).cloned())
+            .or_else(|| {
+                info.remote_node.and_then(|node_id| {
+                    self.spawning_services.get(&node_id).cloned()
+                })
+            });
+        if let Some(enquirer) = enquirer {
+            senders.send_to(
+                ServiceBus::Ctl,
+                ServiceId::Lnpd,
+                enquirer,
+                Request::Progress(details),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Notifies channel daemons working with the remote peer that the
+    /// connection is lost, unless the node has another connection to it
+    fn peer_disconnected(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        node_addr: &NodeAddr,
+    ) -> Result<(), Error> {
+        self.connections.remove(node_addr);
+        self.inbound_connections.remove(node_addr);
+        self.peer_features
+            .remove(&ServiceId::Peer(node_addr.clone()));
+        if self.connections.iter().any(|conn| conn.id == node_addr.id) {
+            return Ok(());
+        }
+        let daemons = &self.daemons;
+        let channelds = self
+            .channel_peers
+            .iter()
+            .filter(|(channeld, node_id)| {
+                **node_id == node_addr.id && daemons.contains_key(channeld)
+            })
+            .map(|(channeld, _)| channeld.clone())
+            .collect::<Vec<_>>();
+        for channeld in channelds {
+            debug!("Notifying {} on the peer disconnection", channeld);
+            senders.send_to(
+                ServiceBus::Ctl,
+                ServiceId::Lnpd,
+                channeld,
+                Request::PeerDisconnected(node_addr.clone()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Publishes funding transaction of the channel, if it was provided by
+    /// the client, and watches its confirmations on behalf of the channel
+    fn await_funding(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        channeld: ServiceId,
+        watch: TxDepth,
+    ) {
+        // Chain daemon may be not running yet; this must not affect lnpd
+        if let Some(tx) = self.funding_txs.remove(&watch.txid) {
+            info!(
+                "{} {} of {}",
+                "Publishing funding transaction".promo(),
+                watch.txid.promoter(),
+                channeld
+            );
+            if let Err(err) = senders.send_to(
+                ServiceBus::Ctl,
+                ServiceId::Lnpd,
+                ServiceId::Chain,
+                Request::BroadcastTransaction(tx),
+            ) {
+                error!("Unable to publish funding transaction: {}", err);
+            }
+        }
+        // Zero-conf channels do not wait for the confirmations
+        if watch.depth == 0 {
+            return;
+        }
+        debug!(
+            "Watching funding transaction {} of {} for {} confirmation(s)",
+            watch.txid, channeld, watch.depth
+        );
+        self.funding_watches.insert(watch.txid, channeld);
+        if let Err(err) = senders.send_to(
+            ServiceBus::Ctl,
+            ServiceId::Lnpd,
+            ServiceId::Chain,
+            Request::WatchTransaction(watch),
+        ) {
+            error!("Unable to watch funding transaction: {}", err);
+        }
+    }
+
+    /// Requests information from all registered daemons of the listed kind;
+    /// the client gets the list once all of them reply or on timeout
+    fn start_listing(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        enquirer: ServiceId,
+        listed: Listed,
+    ) -> Result<(), Error> {
+        let awaiting: HashSet<ServiceId> = self
+            .daemons
+            .keys()
+            .filter(|service| match (service, &listed) {
+                (ServiceId::Peer(_), Listed::Peers(_)) => true,
+                (ServiceId::Channel(_), Listed::Channels(_)) => true,
+                (ServiceId::Channel(_), Listed::State(_)) => true,
+                (ServiceId::Gossip, Listed::State(_)) => true,
+                _ => false,
+            })
+            .cloned()
+            .collect();
+        let request = match listed {
+            Listed::State(_) => Request::GetState,
+            _ => Request::GetInfo,
+        };
+        for daemon in &awaiting {
+            senders.send_to(
+                ServiceBus::Ctl,
+                ServiceId::Lnpd,
+                daemon.clone(),
+                request.clone(),
+            )?;
+        }
+        self.listings.push(Listing {
+            enquirer,
+            awaiting,
+            listed,
+            deadline: Instant::now() + LISTING_TIMEOUT,
+        });
+        self.complete_listings(senders)
+    }
+
+    /// Adds information reported by the daemon to the listings awaiting it
+    fn listing_reply(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        source: ServiceId,
+        mut add: impl FnMut(&mut Listed),
+    ) -> Result<(), Error> {
+        let mut expected = false;
+        for listing in &mut self.listings {
+            if listing.awaiting.remove(&source) {
+                add(&mut listing.listed);
+                expected = true;
+            }
+        }
+        if !expected {
+            warn!("Information from {} is not awaited; ignoring", source);
+        }
+        self.complete_listings(senders)
+    }
+
+    /// Marks daemons which have not answered heartbeats for too long as
+    /// unhealthy and sends the next heartbeat to all registered daemons once
+    /// its period comes
+    fn check_heartbeats(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+    ) -> Result<(), Error> {
+        let now = Instant::now();
+        let daemons = &self.daemons;
+        let plugins = &self.plugins;
+        self.heartbeats.retain(|service, _| {
+            daemons.contains_key(service) || plugins.is_registered(service)
+        });
+        let mut disconnected = vec![];
+        for (service, heartbeat) in &mut self.heartbeats {
+            if heartbeat.healthy
+                && now - heartbeat.last_seen > HEARTBEAT_TIMEOUT
+            {
+                warn!(
+                    "{} daemon has not responded for {:?}; marking it as \
+                     unhealthy",
+                    service, HEARTBEAT_TIMEOUT
+                );
+                heartbeat.healthy = false;
+                if self.plugins.is_registered(service) {
+                    disconnected.push(service.clone());
+                }
+            }
+        }
+        for plugin in disconnected {
+            self.deregister_plugin(senders, &plugin)?;
+        }
+
+        if now < self.next_heartbeat {
+            return Ok(());
+        }
+        self.next_heartbeat = now + HEARTBEAT_PERIOD;
+        self.heartbeat_seq += 1;
+        let plugins = self
+            .heartbeats
+            .keys()
+            .filter(|service| !self.daemons.contains_key(service));
+        for daemon in self.daemons.keys().chain(plugins) {
+            // Crashed daemons are not reachable until they are restarted,
+            // which must not prevent other daemons from being checked
+            if let Err(err) = senders.send_to(
+                ServiceBus::Ctl,
+                ServiceId::Lnpd,
+                daemon.clone(),
+                Request::Heartbeat(self.heartbeat_seq),
+            ) {
+                debug!("Unable to send heartbeat to {}: {}", daemon, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replies to the clients with the listings which are either complete or
+    /// timed out
+    fn complete_listings(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+    ) -> Result<(), Error> {
+        let now = Instant::now();
+        let (complete, pending): (Vec<_>, Vec<_>) =
+            self.listings.drain(..).partition(|listing| {
+                listing.awaiting.is_empty() || listing.deadline <= now
+            });
+        self.listings = pending;
+        for listing in complete {
+            if !listing.awaiting.is_empty() {
+                warn!(
+                    "No information from {} daemon(s) within {:?}; replying \
+                     with incomplete list",
+                    listing.awaiting.len(),
+                    LISTING_TIMEOUT
+                );
+            }
+            let enquirer = listing.enquirer.clone();
+            senders.send_to(
+                ServiceBus::Ctl,
+                ServiceId::Lnpd,
+                enquirer,
+                listing.into_reply(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn export_node(&self, include_secrets: bool) -> NodeArchive {
+        NodeArchive {
+            version: NODE_ARCHIVE_VERSION,
+            chain: self.chain.clone(),
+            node_id: self.node_id,
+            node_key: if include_secrets {
+                Some(self.local_node.private_key())
+            } else {
+                None
+            },
+            listens: self.listens.iter().cloned().collect(),
+            peers: self.connections.iter().cloned().collect(),
+            channels: self.channels.iter().cloned().collect(),
+            wallet_descriptors: self.wallet_descriptors(),
+        }
+    }
+
+    /// Descriptors of the funding wallet, which keys are derived from the
+    /// node key
+    fn wallet_descriptors(&self) -> Vec<String> {
+        fundingd::wallet_descriptors(&fundingd::wallet_master_key(
+            &self.local_node.private_key(),
+            &self.chain,
+        ))
+    }
+
+    /// Replaces the node key with the one from the archive; the node has to
+    /// be restarted to run with the new key. Previous key file is kept with
+    /// `.bak` extension.
+    fn restore_node_key(
+        &mut self,
+        node_id: secp256k1::PublicKey,
+        node_key: secp256k1::SecretKey,
+    ) -> Result<String, Error> {
+        let secp = secp256k1::Secp256k1::signing_only();
+        if secp256k1::PublicKey::from_secret_key(&secp, &node_key) != node_id {
+            return Err(Error::Other(s!(
+                "Node key in the archive does not match the archived node id"
+            )));
+        }
+        let local_node = LocalNode::from_keys(
+            node_key,
+            secp256k1::SecretKey::new(&mut rand::thread_rng()),
+        );
+        if self.key_file.exists() {
+            fs::copy(&self.key_file, self.key_file.with_extension("bak"))?;
+        }
+        let tmp_path = self.key_file.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // Node key must not be readable by other users
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        local_node
+            .strict_encode(options.open(&tmp_path)?)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        fs::rename(tmp_path, &self.key_file)?;
+        warn!(
+            "Node key is {} with the key of node {}; restart the node and \
+             import the archive once again",
+            "replaced".promo(),
+            node_id
+        );
+        Ok(format!(
+            "Node key of {} is restored; restart the node and import the \
+             archive once again to restore the rest of the node state",
+            node_id
+        ))
+    }
+
+    fn import_node(
+        &mut self,
+        source: ServiceId,
+        archive: NodeArchive,
+    ) -> Result<String, Error> {
+        if archive.version > NODE_ARCHIVE_VERSION {
+            return Err(Error::Other(format!(
+                "Node archive version {} is not supported",
+                archive.version
+            )));
+        }
+        if archive.chain != self.chain {
+            return Err(Error::Other(format!(
+                "Node archive was created for {} while the node runs on {}",
+                archive.chain, self.chain
+            )));
+        }
+        if archive.node_id != self.node_id {
+            return match archive.node_key {
+                Some(node_key) => {
+                    self.restore_node_key(archive.node_id, node_key)
+                }
+                None => Err(Error::Other(format!(
+                    "Node archive belongs to the node {} and has no node \
+                     key; restart the node with the archived key in order \
+                     to import it",
+                    archive.node_id
+                ))),
+            };
+        }
+        for descriptor in &archive.wallet_descriptors {
+            if !self.wallet_descriptors().contains(descriptor) {
+                warn!(
+                    "Wallet {} is not controlled by the node and is not \
+                     restored",
+                    descriptor
+                );
+            }
+        }
+
+        let mut listens = 0usize;
+        for addr in archive.listens {
+            if !self.listens.contains(&addr) {
+                self.listen(addr)?;
+                self.listens.insert(addr);
+                listens += 1;
+            }
+        }
+        let mut peers = 0usize;
+        for node_addr in archive.peers {
+            if !self.connections.contains(&node_addr) {
+                self.connect_peer(source.clone(), node_addr)?;
+                peers += 1;
+            }
+        }
+        if !archive.channels.is_empty() {
+            // Channel daemons restore their own state from the channel
+            // storage; here we only can report what is missing
+            warn!(
+                "Node archive lists {} channel(s) which will become available \
+                 once their channel daemons are restored",
+                archive.channels.len()
+            );
+        }
+
+        Ok(format!(
+            "Node state imported: {} listener(s) and {} peer connection(s) \
+             restored",
+            listens, peers
+        ))
+    }
+
+    /// Imports node state exported from another node instance: restores
+    /// node connections, stores data of the channels unknown to the node and
+    /// launches their daemons, and merges gossip data and invoices
+    fn import_state(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        source: ServiceId,
+        state: NodeState,
+    ) -> Result<String, Error> {
+        if state.version > NODE_STATE_VERSION {
+            return Err(Error::Other(format!(
+                "Node state version {} is not supported",
+                state.version
+            )));
+        }
+        let restart = state.archive.node_id != self.node_id;
+        let node = self.import_node(source, state.archive)?;
+        if restart {
+            // The rest of the state belongs to the node with the restored key
+            return Ok(node);
+        }
+
+        let mut imported = vec![];
+        for ChannelSnapshot { channel_id, data } in state.channels {
+            if let Err(err) = self.check_duplicate(channel_id) {
+                warn!("Channel {} is not imported: {}", channel_id, err);
+                continue;
             }
+            channeld::import_channel(
+                &self.config,
+                &self.data_dir,
+                channel_id,
+                &data,
+            )?;
+            imported.push(channel_id);
         }
+        let channels = imported.len();
+        self.respawn_channels(imported);
 
-        if let Some((Some(respond_to), resp)) = notify_cli {
-            senders.send_to(
+        if let Some(gossip) = state.gossip {
+            // Gossip daemon may be not running; this must not affect import
+            if let Err(err) = senders.send_to(
                 ServiceBus::Ctl,
                 ServiceId::Lnpd,
-                respond_to,
-                resp,
-            )?;
+                ServiceId::Gossip,
+                Request::RestoreState(gossip),
+            ) {
+                error!("Unable to restore gossip state: {}", err);
+            }
         }
+        let invoices = self.invoices.import(&state.invoices)?;
+        let payments = self.ledger.import_journal(&state.payments)?;
 
-        Ok(())
+        Ok(format!(
+            "{}; {} channel(s), {} invoice(s) and {} payment record(s) \
+             imported",
+            node, channels, invoices, payments
+        ))
+    }
+
+    /// Launches channel daemons for the backed up channels which are not
+    /// known to the node. The daemons ask remote peers to close the channels
+    /// unilaterally and sweep our funds from their commitment transactions.
+    fn restore_backup(
+        &mut self,
+        source: ServiceId,
+        blob: &[u8],
+    ) -> Result<String, Error> {
+        let backup = self.backups.open(blob)?;
+        if backup.node_id != self.node_id {
+            return Err(Error::Other(format!(
+                "Static backup belongs to the node {}",
+                backup.node_id
+            )));
+        }
+
+        let mut recovering = 0usize;
+        for channel in backup.channels {
+            if self.channels.contains(&channel.channel_id) {
+                debug!(
+                    "Channel {} is known to the node, skipping its recovery",
+                    channel.channel_id
+                );
+                continue;
+            }
+            let launched = self
+                .supervisor
+                .launch(Daemon::Channel(channel.channel_id))?;
+            info!(
+                "New instance of channeld launched {} for recovering \
+                 channel {}",
+                launched, channel.channel_id
+            );
+
+            if !self.connections.contains(&channel.remote_peer)
+                && !self.spawning_services.contains_key(&channel.remote_peer.id)
+            {
+                self.connect_peer(source.clone(), channel.remote_peer.clone())?;
+            }
+            let channeld = ServiceId::Channel(channel.channel_id);
+            self.channel_peers
+                .insert(channeld.clone(), channel.remote_peer.id);
+            self.recovering_channels.insert(channeld, channel);
+            recovering += 1;
+        }
+
+        Ok(format!(
+            "Recovery of {} channel(s) is started; funds will be swept once \
+             remote peers close the channels",
+            recovering
+        ))
     }
 
     fn listen(&mut self, addr: RemoteSocketAddr) -> Result<String, Error> {
-        if let RemoteSocketAddr::Ftcp(inet) = addr {
+        if let RemoteSocketAddr::Ftcp(inet)
+        | RemoteSocketAddr::Websocket(inet) = addr
+        {
             let socket_addr = SocketAddr::try_from(inet)?;
-            let ip = socket_addr.ip();
-            let port = socket_addr.port();
+
+            // Onion peers connect with plain TCP framing
+            let tcp = matches!(addr, RemoteSocketAddr::Ftcp(_));
+            let onion = match self.onion_address {
+                None if tcp && self.onion.is_some() => {
+                    match self.publish_onion(socket_addr) {
+                        Ok(onion) => Some(onion),
+                        Err(err) => {
+                            // The listener is still useful for clearnet peers
+                            warn!("{}", err.err());
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
 
             debug!("Instantiating peerd...");
 
             // Start channeld
-            let child = launch(
-                "peerd",
-                &["--listen", &ip.to_string(), "--port", &port.to_string()],
-            )?;
-            let msg = format!(
-                "New instance of peerd launched with PID {}",
-                child.id()
-            );
+            let launched =
+                self.supervisor.launch(Daemon::Listen(addr, onion))?;
+            let msg = format!("New instance of peerd launched {}", launched);
             info!("{}", msg);
             Ok(msg)
         } else {
             Err(Error::Other(s!(
-                "Only TCP is supported for now as an overlay protocol"
+                "Only TCP and WebSocket are supported for now as overlay \
+                 protocols"
             )))
         }
     }
 
+    /// Modifies peer access lists, closing connections with the peers which
+    /// are no longer allowed
+    fn update_peer_access(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        update: &PeerAccessUpdate,
+    ) -> Result<String, Error> {
+        let mut access = self.peer_access.clone();
+        if !access.update(update) {
+            return Ok(s!("Peer access lists are not changed"));
+        }
+        peerd::save_access(&self.config.peer_access, &access)?;
+        self.peer_access = access;
+        info!("{} peer access lists: {}", "Updated".ended(), update);
+
+        let denied: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|conn| !self.peer_access.admits_node(conn.id))
+            .cloned()
+            .collect();
+        for node_addr in &denied {
+            info!(
+                "{} from remote peer {} denied by peer access lists",
+                "Disconnecting".promo(),
+                node_addr.id.promoter()
+            );
+            senders.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                ServiceId::Peer(node_addr.clone()),
+                Request::DisconnectPeer(node_addr.id),
+            )?;
+        }
+        Ok(format!(
+            "Peer access lists are updated; {} connection(s) closed",
+            denied.len()
+        ))
+    }
+
+    /// Asks gossipd to include the address of the published onion service
+    /// into the node announcement; repeated requests are ignored by gossipd
+    fn advertise_onion(
+        &self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+    ) -> Result<(), Error> {
+        match self.onion_address {
+            Some(onion) if self.daemons.contains_key(&ServiceId::Gossip) => {
+                senders.send_to(
+                    ServiceBus::Ctl,
+                    ServiceId::Lnpd,
+                    ServiceId::Gossip,
+                    Request::AdvertiseAddress(onion),
+                )?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Publishes onion service forwarding connections to the listener on
+    /// the given socket, returning the onion address of the node
+    fn publish_onion(
+        &mut self,
+        socket_addr: SocketAddr,
+    ) -> Result<InetSocketAddr, Error> {
+        let config = match self.onion {
+            Some(ref config) => config,
+            None => {
+                return Err(Error::Other(s!("Tor control port is not set up")))
+            }
+        };
+        // Tor runs locally, so it reaches listeners bound to all
+        // interfaces through the loopback one
+        let mut target = socket_addr;
+        match target.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => {
+                target.set_ip(Ipv4Addr::LOCALHOST.into())
+            }
+            IpAddr::V6(ip) if ip.is_unspecified() => {
+                target.set_ip(Ipv6Addr::LOCALHOST.into())
+            }
+            _ => {}
+        }
+        let service =
+            onion::publish(config, &self.data_dir, socket_addr.port(), target)
+                .map_err(|err| {
+                    Error::Other(format!(
+                        "Unable to publish onion service: {}",
+                        err
+                    ))
+                })?;
+        let addr = format!("{}:{}", service.address(), socket_addr.port());
+        let onion = InetSocketAddr::from_str(&addr).map_err(|_| {
+            Error::Other(format!(
+                "Onion service {} is published, but the node is compiled \
+                 without Tor support",
+                addr
+            ))
+        })?;
+        info!(
+            "{} {} forwarding to {}",
+            "Onion service".ended(),
+            onion,
+            target
+        );
+        self.onion_address = Some(onion);
+        Ok(onion)
+    }
+
     fn connect_peer(
         &mut self,
         source: ServiceId,
         node_addr: NodeAddr,
     ) -> Result<String, Error> {
+        if !self.peer_access.admits_node(node_addr.id) {
+            return Err(Error::Other(format!(
+                "Connection to {} is denied by peer access lists",
+                node_addr.id
+            )));
+        }
+
         debug!("Instantiating peerd...");
+        self.limits.check(
+            Resource::Peers,
+            self.connections.len() + self.spawning_services.len(),
+        )?;
+        if let NodeAddr::Remote(RemoteNodeAddr {
+            node_id,
+            remote_addr,
+        }) = node_addr
+        {
+            self.peer_store.add_addrs(node_id, Some(remote_addr))?;
+        }
 
         // Start channeld
-        let child = launch("peerd", &["--connect", &node_addr.to_string()])?;
-        let msg =
-            format!("New instance of peerd launched with PID {}", child.id());
+        let launched =
+            self.supervisor.launch(Daemon::Connect(node_addr.clone()))?;
+        let msg = format!("New instance of peerd launched {}", launched);
         info!("{}", msg);
 
-        self.spawning_services
-            .insert(ServiceId::Peer(node_addr), source);
+        self.spawning_services.insert(node_addr.id, source);
         debug!("Awaiting for peerd to connect...");
 
         Ok(msg)
     }
 
+    /// Makes the connection daemon awaiting restart after the connection
+    /// failure to connect to the next address of the remote peer, if the
+    /// peer has other addresses reachable by the node
+    fn rotate_address(&mut self, service: &ServiceId) -> Result<(), Error> {
+        let (node_id, remote_addr) = match service {
+            ServiceId::Peer(NodeAddr::Remote(RemoteNodeAddr {
+                node_id,
+                remote_addr,
+            })) => (*node_id, *remote_addr),
+            _ => return Ok(()),
+        };
+        // Incoming connections are identified by the node id of the listener
+        if node_id == self.node_id {
+            return Ok(());
+        }
+        let next = match self.peer_store.failed(node_id, remote_addr)? {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+        let node_addr = NodeAddr::Remote(RemoteNodeAddr {
+            node_id,
+            remote_addr: next,
+        });
+        if self
+            .supervisor
+            .replace(service, Daemon::Connect(node_addr.clone()))
+        {
+            info!(
+                "{} to {} at another address",
+                "Reconnecting".promo(),
+                node_addr.promoter()
+            );
+        }
+        Ok(())
+    }
+
+    /// Takes a slot for the connection accepted from the remote peer. Once
+    /// all the slots are taken, a peer having no channels with the node is
+    /// evicted in favour of the peer having channels; otherwise the
+    /// connection is refused.
+    fn assign_inbound_slot(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        connection_id: &NodeAddr,
+    ) -> Result<(), Error> {
+        if let Err(err) = self
+            .limits
+            .check(Resource::InboundPeers, self.inbound_connections.len())
+        {
+            let channel_peers = &self.channel_peers;
+            let has_channels = |node_id: secp256k1::PublicKey| {
+                channel_peers.values().any(|peer| *peer == node_id)
+            };
+            if !has_channels(connection_id.id) {
+                return Err(err);
+            }
+            let evicted = self
+                .inbound_connections
+                .iter()
+                .find(|conn| !has_channels(conn.id))
+                .cloned()
+                .ok_or(err)?;
+            info!(
+                "{} remote peer {} without channels to free slot for {}",
+                "Evicting".promo(),
+                evicted.id.promoter(),
+                connection_id
+            );
+            senders.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                ServiceId::Peer(evicted.clone()),
+                Request::DisconnectPeer(evicted.id),
+            )?;
+            self.inbound_connections.remove(&evicted);
+        }
+        self.inbound_connections.insert(connection_id.clone());
+        Ok(())
+    }
+
+    fn process_hook(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        progress: HookProgress,
+    ) -> Result<(), Error> {
+        match progress {
+            HookProgress::Call(plugin, call) => {
+                debug!("Calling plugin {} for {}", plugin, call);
+                if let Err(err) = senders.send_to(
+                    ServiceBus::Ctl,
+                    ServiceId::Lnpd,
+                    plugin.clone(),
+                    Request::HookCall(call),
+                ) {
+                    warn!("Plugin {} is unreachable: {}", plugin, err);
+                    self.deregister_plugin(senders, &plugin)?;
+                }
+            }
+            HookProgress::Done(
+                HookOrigin::ChannelProposal {
+                    peerd,
+                    open_channel,
+                    tlvs,
+                },
+                HookResult {
+                    reject: None,
+                    minimum_depth,
+                    ..
+                },
+            ) => {
+                // The same channel may be proposed again while plugins were
+                // deciding on it
+                let temp_id = ChannelId::from_inner(
+                    open_channel.temporary_channel_id.into_inner(),
+                );
+                if let Err(err) = self.check_duplicate(temp_id) {
+                    warn!("Channel proposed by {} is declined: {}", peerd, err);
+                    return self.reject_channel(
+                        senders,
+                        peerd,
+                        &open_channel,
+                        err.to_string(),
+                    );
+                }
+                info!("Creating channel by peer request from {}", peerd);
+                // TLV records of the proposal are processed by channeld
+                let create = request::CreateChannel {
+                    channel_req: open_channel.clone(),
+                    peerd: peerd.clone(),
+                    report_to: None,
+                    shutdown_scriptpubkey: None,
+                    fund_from_wallet: false,
+                    minimum_depth,
+                    funding_batch: None,
+                    private: open_channel.channel_flags & 0x01 == 0,
+                    remote_tlvs: tlvs,
+                };
+                if let Err(err) = self.create_channel(create, true) {
+                    error!(
+                        "Unable to accept channel proposed by {}: {}",
+                        peerd, err
+                    );
+                    // Local error details are not disclosed to the peer
+                    self.reject_channel(
+                        senders,
+                        peerd,
+                        &open_channel,
+                        s!("unable to start channel daemon"),
+                    )?;
+                }
+            }
+            HookProgress::Done(
+                HookOrigin::ChannelProposal {
+                    peerd,
+                    open_channel,
+                    ..
+                },
+                HookResult {
+                    reject: Some(reason),
+                    ..
+                },
+            ) => {
+                warn!(
+                    "Channel proposed by {} is rejected by plugin: {}",
+                    peerd, reason
+                );
+                self.reject_channel(senders, peerd, &open_channel, reason)?;
+            }
+            HookProgress::Done(
+                HookOrigin::Daemon { service, call_id },
+                result,
+            ) => {
+                senders.send_to(
+                    ServiceBus::Ctl,
+                    ServiceId::Lnpd,
+                    service,
+                    Request::HookResult(HookResult {
+                        id: call_id,
+                        ..result
+                    }),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes disconnected plugin, rejecting the hook calls awaiting its
+    /// decision
+    fn deregister_plugin(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        plugin: &ServiceId,
+    ) -> Result<(), Error> {
+        info!("{} {}", "Deregistering plugin".ended(), plugin.ender());
+        self.heartbeats.remove(plugin);
+        for progress in self.plugins.deregister(plugin) {
+            self.process_hook(senders, progress)?;
+        }
+        Ok(())
+    }
+
+    /// Launches a listener for each of the configured local sockets; the
+    /// onion service, if requested, forwards to the first of them
+    fn start_listeners(&mut self, listens: Vec<RemoteSocketAddr>) {
+        for addr in listens {
+            if !self.listens.insert(addr) {
+                continue;
+            }
+            match self.listen(addr) {
+                Ok(_) => info!(
+                    "{} for incoming LN peer connections on {}",
+                    "Listening".ended(),
+                    addr
+                ),
+                Err(err) => {
+                    self.listens.remove(&addr);
+                    error!(
+                        "{}",
+                        format!("Unable to listen on {}: {}", addr, err).err()
+                    )
+                }
+            }
+        }
+    }
+
+    /// Launches channel daemons for the channels persisted before the node
+    /// restart; daemons resume the channels from their stored state
+    fn respawn_channels(&mut self, channels: Vec<ChannelId>) {
+        for channel_id in channels {
+            match self.supervisor.launch(Daemon::Channel(channel_id)) {
+                Ok(launched) => {
+                    info!(
+                        "{} channeld {} for persisted channel {}",
+                        "Relaunched".promo(),
+                        launched,
+                        channel_id.promoter()
+                    );
+                    self.restoring_channels
+                        .insert(ServiceId::Channel(channel_id));
+                }
+                Err(err) => error!(
+                    "{}",
+                    format!(
+                        "Unable to relaunch channeld for channel {}: {}",
+                        channel_id, err
+                    )
+                    .err()
+                ),
+            }
+        }
+    }
+
+    /// Connects to the remote peers known from the previous connections:
+    /// the ones having channels with the node and the given number of other
+    /// peers. Node which does not know any peers discovers them through DNS
+    /// seeds.
+    fn autoconnect_peers(
+        &mut self,
+        gossip_peers: usize,
+        bootstrap: Option<BootstrapConfig>,
+    ) {
+        let peers = match bootstrap {
+            _ if !self.peer_store.is_empty() => {
+                self.peer_store.autoconnect(gossip_peers)
+            }
+            None => vec![],
+            Some(_) if self.config.tor_proxy.is_some() => {
+                warn!(
+                    "DNS bootstrap is skipped since DNS queries would bypass \
+                     Tor proxy"
+                );
+                vec![]
+            }
+            Some(bootstrap) => {
+                info!("{} initial peers from DNS seeds", "Discovering".promo());
+                bootstrap::discover(&bootstrap)
+            }
+        };
+        for (launched, node_addr) in peers.into_iter().enumerate() {
+            if !self.peer_access.admits_node(node_addr.id) {
+                continue;
+            }
+            if let Err(err) = self.limits.check(Resource::Peers, launched) {
+                warn!("Not reconnecting to the rest of known peers: {}", err);
+                break;
+            }
+            match self.supervisor.launch(Daemon::Connect(node_addr.clone())) {
+                Ok(launched) => info!(
+                    "{} to known peer {} with {}",
+                    "Reconnecting".promo(),
+                    node_addr.promoter(),
+                    launched
+                ),
+                Err(err) => error!(
+                    "{}",
+                    format!("Unable to reconnect to {}: {}", node_addr, err)
+                        .err()
+                ),
+            }
+        }
+    }
+
+    /// Links channel daemon relaunched after the node restart with its remote
+    /// peer, such that the channel is re-established once the peer connects
+    fn channel_restored(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        channeld: ServiceId,
+        info: &ChannelInfo,
+    ) -> Result<(), Error> {
+        match (&channeld, info.channel_id) {
+            (ServiceId::Channel(temp_id), Some(channel_id))
+                if *temp_id != channel_id =>
+            {
+                let old_id = self
+                    .channel_ids
+                    .insert(*temp_id, channel_id)?
+                    .unwrap_or(*temp_id);
+                self.rename_channel(old_id, channel_id);
+            }
+            _ => {}
+        }
+        let node_id = match info.remote_peers.first() {
+            Some(remote_peer) => remote_peer.id,
+            None => {
+                warn!(
+                    "Persisted channel of {} has no remote peer; it can't be \
+                     re-established",
+                    channeld
+                );
+                return Ok(());
+            }
+        };
+        info!("Channel {} is resumed with peer {}", channeld, node_id);
+        self.channel_peers.insert(channeld.clone(), node_id);
+        self.peer_store.set_channels(node_id, true)?;
+        // Peers connecting later are handled on their registration
+        if let Some(connection) = self
+            .connections
+            .iter()
+            .find(|connection| connection.id == node_id)
+            .cloned()
+        {
+            senders.send_to(
+                ServiceBus::Ctl,
+                ServiceId::Lnpd,
+                channeld,
+                Request::PeerReconnected(connection),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Re-indexes channel known by its previous id
+    fn rename_channel(&mut self, old_id: ChannelId, new_id: ChannelId) {
+        if old_id == new_id {
+            return;
+        }
+        if !self.channels.remove(&old_id) {
+            warn!("Channel {} was unknown", old_id);
+        }
+        self.channels.insert(new_id);
+        if let Some(balances) = self.asset_balances.remove(&old_id) {
+            self.asset_balances.insert(new_id, balances);
+        }
+    }
+
+    /// Checks that the temporary channel id does not belong to a channel
+    /// which is being created or is already run by a channel daemon
+    fn check_duplicate(
+        &self,
+        temp_id: ChannelId,
+    ) -> Result<(), DuplicateChannel> {
+        if self.opening_channels.contains_key(&temp_id)
+            || self.accepting_channels.contains_key(&temp_id)
+        {
+            return Err(DuplicateChannel::Pending(temp_id));
+        }
+        let channeld = ServiceId::Channel(temp_id);
+        if self.daemons.contains_key(&channeld)
+            || self.channels.contains(&temp_id)
+            || self.channel_ids.get(temp_id).is_some()
+            || self.restoring_channels.contains(&channeld)
+            || self.recovering_channels.contains_key(&channeld)
+        {
+            return Err(DuplicateChannel::Running(temp_id));
+        }
+        Ok(())
+    }
+
+    /// Checks that one more channel can be created within the node resource
+    /// limits
+    fn check_channel_limits(&self) -> Result<(), Error> {
+        let pending =
+            self.opening_channels.len() + self.accepting_channels.len();
+        self.limits.check(Resource::PendingChannels, pending)?;
+        let running = self
+            .daemons
+            .keys()
+            .filter(|service| match service {
+                ServiceId::Channel(_) => true,
+                _ => false,
+            })
+            .count();
+        self.limits.check(Resource::Channels, running + pending)
+    }
+
+    /// Applies acceptance policy to the channel proposed by the remote peer
+    fn check_acceptance(
+        &self,
+        peerd: &ServiceId,
+        open_channel: &message::OpenChannel,
+    ) -> Result<(), AcceptanceError> {
+        let remote = match peerd {
+            ServiceId::Peer(addr) => addr.id,
+            // Channels can be proposed only by peer daemons
+            _ => return Ok(()),
+        };
+        let channel_count = self
+            .channel_peers
+            .values()
+            .filter(|node_id| **node_id == remote)
+            .count()
+            + self
+                .accepting_channels
+                .values()
+                .filter(|req| &req.peerd == peerd)
+                .count();
+        self.acceptance.check(remote, channel_count, open_channel)
+    }
+
+    /// Notifies the remote peer that the channel it proposed is not accepted
+    fn reject_channel(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        peerd: ServiceId,
+        open_channel: &message::OpenChannel,
+        reason: String,
+    ) -> Result<(), Error> {
+        senders.send_to(
+            ServiceBus::Msg,
+            ServiceId::Lnpd,
+            peerd,
+            Request::PeerMessage(Messages::Error(message::Error {
+                channel_id: ChannelId::from_inner(
+                    open_channel.temporary_channel_id.into_inner(),
+                ),
+                data: reason.into_bytes(),
+            })),
+        )?;
+        Ok(())
+    }
+
+    /// Creates channels with the same remote peer, funded from the funding
+    /// wallet by a single transaction. Channel daemons report to lnpd, which
+    /// forwards their progress to the enquirer under the batch id.
+    fn open_batch(
+        &mut self,
+        peerd: ServiceId,
+        enquirer: ServiceId,
+        channel_reqs: Vec<message::OpenChannel>,
+        shutdown_scriptpubkey: Option<PubkeyScript>,
+        private: bool,
+    ) -> Result<String, Error> {
+        if channel_reqs.is_empty() {
+            return Err(Error::Other(s!("channel batch is empty")));
+        }
+        let size = u16::try_from(channel_reqs.len()).map_err(|_| {
+            Error::Other(format!(
+                "channel batch can't exceed {} channels",
+                u16::MAX
+            ))
+        })?;
+        // Batch transaction is never published unless all of its channels
+        // are created, so the limits are checked for the whole batch
+        let pending =
+            self.opening_channels.len() + self.accepting_channels.len();
+        self.limits.check(
+            Resource::PendingChannels,
+            pending + channel_reqs.len() - 1,
+        )?;
+        let running = self
+            .daemons
+            .keys()
+            .filter(|service| match service {
+                ServiceId::Channel(_) => true,
+                _ => false,
+            })
+            .count();
+        self.limits.check(
+            Resource::Channels,
+            running + pending + channel_reqs.len() - 1,
+        )?;
+
+        let batch = FundingBatch {
+            batch_id: rand::thread_rng().gen(),
+            size,
+        };
+        let mut failure = None;
+        for mut channel_req in channel_reqs {
+            channel_req.channel_flags = channel_flags(private);
+            let create = request::CreateChannel {
+                channel_req,
+                peerd: peerd.clone(),
+                report_to: Some(ServiceId::Lnpd),
+                shutdown_scriptpubkey: shutdown_scriptpubkey.clone(),
+                fund_from_wallet: true,
+                minimum_depth: None,
+                funding_batch: Some(batch),
+                private,
+                remote_tlvs: empty!(),
+            };
+            if let Err(err) = self.create_channel(create, false) {
+                failure = Some(err);
+                break;
+            }
+        }
+
+        let channels = self
+            .opening_channels
+            .iter_mut()
+            .filter(|(_, channel)| channel.funding_batch == Some(batch))
+            .map(|(temp_id, channel)| (*temp_id, channel))
+            .collect::<Vec<_>>();
+        let created = channels.len();
+        if created == 0 {
+            return Err(failure.expect("batch is not empty"));
+        }
+        let mut pending = HashSet::with_capacity(created);
+        for (temp_id, channel) in channels {
+            // Channels which were created must not wait for the funding
+            // requests of the failed ones
+            channel.funding_batch = Some(FundingBatch {
+                size: created as u16,
+                ..batch
+            });
+            let channeld = ServiceId::Channel(temp_id);
+            self.batch_channels.insert(channeld.clone(), batch.batch_id);
+            pending.insert(channeld);
+        }
+        self.batches.insert(
+            batch.batch_id,
+            ChannelBatch {
+                enquirer,
+                size: created,
+                pending,
+            },
+        );
+
+        match failure {
+            None => Ok(format!(
+                "Batch #{} of {} channel(s) is created",
+                batch.batch_id, created
+            )),
+            Some(err) => Ok(format!(
+                "Batch #{} is reduced to {} channel(s): {}",
+                batch.batch_id, created, err
+            )),
+        }
+    }
+
+    /// Forwards report of the channel daemon from a batch to the client who
+    /// has requested the batch
+    fn batch_report(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        channeld: ServiceId,
+        report: Request,
+    ) -> Result<(), Error> {
+        let batch_id = match self.batch_channels.get(&channeld) {
+            Some(batch_id) => *batch_id,
+            None => return Ok(()),
+        };
+        let batch = match self.batches.get_mut(&batch_id) {
+            Some(batch) => batch,
+            None => {
+                // Channel keeps reporting to lnpd after its batch has been
+                // completed
+                debug!("{} reports on completed batch #{}", channeld, batch_id);
+                return Ok(());
+            }
+        };
+        let prefix = format!("Batch #{}, {}", batch_id, channeld);
+        let reply = match report {
+            Request::Progress(msg) => {
+                Request::Progress(format!("{}: {}", prefix, msg))
+            }
+            Request::Success(details) => {
+                batch.pending.remove(&channeld);
+                if batch.pending.is_empty() {
+                    info!("Batch #{} is {}", batch_id, "funded".ended());
+                    Request::Success(OptionDetails::with(format!(
+                        "{}: {}; all {} channel(s) of the batch are funded",
+                        prefix, details, batch.size
+                    )))
+                } else {
+                    Request::Progress(format!(
+                        "{}: {}; {} channel(s) of the batch are pending",
+                        prefix,
+                        details,
+                        batch.pending.len()
+                    ))
+                }
+            }
+            Request::Failure(failure) => {
+                batch.pending.remove(&channeld);
+                Request::Failure(Failure {
+                    code: failure.code,
+                    info: format!("{}: {}", prefix, failure.info),
+                })
+            }
+            _ => return Ok(()),
+        };
+        let enquirer = batch.enquirer.clone();
+        if batch.pending.is_empty() {
+            self.batches.remove(&batch_id);
+        }
+        // Ignoring possible error here: the channels proceed even if the
+        // client has disconnected
+        let _ =
+            senders.send_to(ServiceBus::Ctl, ServiceId::Lnpd, enquirer, reply);
+        Ok(())
+    }
+
+    /// Launches channel daemon for the channel opened by the local node or,
+    /// if `accept` is set, proposed by the remote peer
     fn create_channel(
         &mut self,
-        source: ServiceId,
-        report_to: Option<ServiceId>,
-        mut channel_req: message::OpenChannel,
+        create: request::CreateChannel,
         accept: bool,
     ) -> Result<String, Error> {
         debug!("Instantiating channeld...");
+        let mut channel_req = create.channel_req;
 
         // We need to initialize temporary channel id here
         if !accept {
@@ -474,37 +3325,62 @@ impl Runtime {
             );
         }
 
-        // Start channeld
-        let child =
-            launch("channeld", &[channel_req.temporary_channel_id.to_hex()])?;
-        let msg = format!(
-            "New instance of channeld launched with PID {}",
-            child.id()
+        let temp_id = ChannelId::from_inner(
+            channel_req.temporary_channel_id.into_inner(),
         );
+        self.check_duplicate(temp_id)?;
+        self.check_channel_limits()?;
+
+        // Start channeld
+        let launched = self.supervisor.launch(Daemon::Channel(temp_id))?;
+        let msg = format!("New instance of channeld launched {}", launched);
         info!("{}", msg);
 
-        // Construct channel creation request
-        let node_key = self.node_id;
-        let channel_req = message::OpenChannel {
-            chain_hash: self.chain.clone().chain_params().genesis_hash.into(),
-            // TODO: Take these parameters from configuration
-            push_msat: 0,
-            dust_limit_satoshis: 0,
-            max_htlc_value_in_flight_msat: 10000,
-            channel_reserve_satoshis: 0,
-            htlc_minimum_msat: 0,
-            feerate_per_kw: 1,
-            to_self_delay: 1,
-            max_accepted_htlcs: 1000,
-            funding_pubkey: node_key,
-            revocation_basepoint: node_key,
-            payment_point: node_key,
-            delayed_payment_basepoint: node_key,
-            htlc_basepoint: node_key,
-            first_per_commitment_point: node_key,
-            channel_flags: 1, // Announce the channel
-            // shutdown_scriptpubkey: None,
-            ..channel_req
+        // Construct channel creation request. Channels proposed by remote
+        // peers keep their parameters, which are validated by channeld
+        // against the node policy.
+        // Channel keys are derived by channeld for each channel and replace
+        // these placeholders
+        let dumb_key = self.node_id;
+        let channel_req = if accept {
+            channel_req
+        } else {
+            let params = self.config.channel_params;
+            message::OpenChannel {
+                chain_hash: self
+                    .chain
+                    .clone()
+                    .chain_params()
+                    .genesis_hash
+                    .into(),
+                push_msat: 0,
+                dust_limit_satoshis: params.dust_limit_satoshis,
+                // The whole channel capacity may be in flight
+                max_htlc_value_in_flight_msat: channel_req.funding_satoshis
+                    * 1000,
+                // Reserve must not be below any of the dust limits
+                channel_reserve_satoshis: self
+                    .config
+                    .policy
+                    .channel_reserve(channel_req.funding_satoshis)
+                    .max(params.dust_limit_satoshis),
+                htlc_minimum_msat: self
+                    .config
+                    .forwarding_policy
+                    .htlc_minimum_msat,
+                feerate_per_kw: params.feerate_per_kw,
+                to_self_delay: params.to_self_delay,
+                max_accepted_htlcs: params.max_accepted_htlcs,
+                funding_pubkey: dumb_key,
+                revocation_basepoint: dumb_key,
+                payment_point: dumb_key,
+                delayed_payment_basepoint: dumb_key,
+                htlc_basepoint: dumb_key,
+                first_per_commitment_point: dumb_key,
+                // Upfront shutdown script is sent by channeld in the TLV
+                // stream of the message
+                ..channel_req
+            }
         };
 
         let list = if accept {
@@ -513,13 +3389,10 @@ impl Runtime {
             &mut self.opening_channels
         };
         list.insert(
-            ServiceId::Channel(ChannelId::from_inner(
-                channel_req.temporary_channel_id.into_inner(),
-            )),
+            temp_id,
             request::CreateChannel {
                 channel_req,
-                peerd: source,
-                report_to,
+                ..create
             },
         );
         debug!("Awaiting for channeld to connect...");
@@ -527,32 +3400,3 @@ impl Runtime {
         Ok(msg)
     }
 }
-
-fn launch(
-    name: &str,
-    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
-) -> io::Result<process::Child> {
-    let mut bin_path = std::env::current_exe().map_err(|err| {
-        error!("Unable to detect binary directory: {}", err);
-        err
-    })?;
-    bin_path.pop();
-
-    bin_path.push(name);
-    #[cfg(target_os = "windows")]
-    bin_path.set_extension("exe");
-
-    debug!(
-        "Launching {} as a separate process using `{}` as binary",
-        name,
-        bin_path.to_string_lossy()
-    );
-
-    let mut cmd = process::Command::new(bin_path);
-    cmd.args(std::env::args().skip(1)).args(args);
-    trace!("Executing `{:?}`", cmd);
-    cmd.spawn().map_err(|err| {
-        error!("Error launching {}: {}", name, err);
-        err
-    })
-}
@@ -0,0 +1,684 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! Supervision of the daemons launched by `lnpd`: detects crashed daemons
+//! and restarts them with exponential backoff. Daemons run either as
+//! separate processes or as threads of the lnpd process.
+
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::hashes::hex::ToHex;
+use internet2::addr::InetSocketAddr;
+use internet2::zmqsocket::ZmqSocketAddr;
+use internet2::{LocalNode, NodeAddr, RemoteSocketAddr};
+use lnp::ChannelId;
+use lnpbp::Chain;
+use microservices::esb;
+
+use crate::rpc::request::DaemonCrash;
+use crate::rpc::{Request, ServiceBus};
+use crate::service::{BridgeHandler, Terminated};
+use crate::{channeld, gossipd, peerd, routed};
+use crate::{Config, Error, ServiceId};
+
+/// Period of checking the state of the launched daemons
+const POLL_PERIOD: Duration = Duration::from_millis(500);
+
+/// Delay before the first restart of a crashed daemon; doubles with each
+/// subsequent crash
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Maximal delay before the daemon restart
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Daemon running for this time is considered healthy, resetting its backoff
+const HEALTHY_UPTIME: Duration = Duration::from_secs(600);
+
+/// Command-line arguments which are specific to lnpd or to a chain and must
+/// not be passed to the launched daemons as is
+const LNPD_ONLY_ARGS: [&'static str; 8] = [
+    "-n",
+    "--chain",
+    "--network",
+    "--parallel-chain",
+    "--webhook",
+    "--webhook-secret",
+    "--bin-dir",
+    "--log-dir",
+];
+
+/// Daemon launched by lnpd
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+pub enum Daemon {
+    /// Channel daemon for the channel with the given temporary id
+    #[display("channeld")]
+    Channel(ChannelId),
+
+    /// Peer daemon connecting to the remote node
+    #[display("peerd")]
+    Connect(NodeAddr),
+
+    /// Peer daemon listening for incoming TCP or WebSocket connections,
+    /// which may also be published as the onion service with the given
+    /// address
+    #[display("peerd")]
+    Listen(RemoteSocketAddr, Option<InetSocketAddr>),
+
+    #[display("gossipd")]
+    Gossip,
+
+    #[display("routed")]
+    Routing,
+}
+
+impl Daemon {
+    /// Command-line arguments of the daemon binary
+    fn args(&self) -> Vec<String> {
+        match self {
+            Daemon::Channel(channel_id) => vec![channel_id.to_hex()],
+            Daemon::Connect(node_addr) => {
+                vec![s!("--connect"), node_addr.to_string()]
+            }
+            Daemon::Listen(remote_addr, onion) => {
+                let inet_addr: InetSocketAddr = remote_addr.clone().into();
+                let mut args = vec![
+                    s!("--listen"),
+                    inet_addr.address.to_string(),
+                    s!("--port"),
+                    inet_addr.port.to_string(),
+                ];
+                if let RemoteSocketAddr::Websocket(_) = remote_addr {
+                    args.extend(vec![s!("--overlay"), s!("websocket")]);
+                }
+                if let Some(onion) = onion {
+                    args.extend(vec![s!("--onion"), onion.to_string()]);
+                }
+                args
+            }
+            Daemon::Gossip | Daemon::Routing => vec![],
+        }
+    }
+
+    /// Service id of the daemon on the message buses, if known at launch
+    fn service(&self) -> Option<ServiceId> {
+        match self {
+            Daemon::Channel(channel_id) => {
+                Some(ServiceId::Channel(*channel_id))
+            }
+            Daemon::Connect(node_addr) => {
+                Some(ServiceId::Peer(node_addr.clone()))
+            }
+            // Incoming connections get their ids once accepted
+            Daemon::Listen(..) => None,
+            Daemon::Gossip => Some(ServiceId::Gossip),
+            Daemon::Routing => Some(ServiceId::Routing),
+        }
+    }
+}
+
+/// Errors launching daemons
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum LaunchError {
+    /// unable to detect directory of the lnpd binary: {0}
+    BinaryDir(String),
+
+    /// daemon binary {0} is not found
+    NotFound(String),
+
+    /// unable to open file {0} for the daemon output: {1}
+    Output(String, String),
+
+    /// unable to launch {0}: {1}
+    Spawn(String, String),
+}
+
+impl From<LaunchError> for Error {
+    fn from(err: LaunchError) -> Self {
+        Error::Other(err.to_string())
+    }
+}
+
+/// How lnpd runs the daemons
+#[derive(Clone, Debug)]
+pub enum LaunchMode {
+    /// Each daemon is a separate process running the daemon binary
+    Processes(ProcessOpts),
+
+    /// Daemons are threads of the lnpd process, connected to lnpd over the
+    /// message buses within the same ZMQ context
+    Threads {
+        /// RGB20 RPC socket used by the channel daemons
+        rgb20_socket: ZmqSocketAddr,
+    },
+}
+
+/// Options of the daemons running as separate processes
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ProcessOpts {
+    /// Directory with the daemon binaries; defaults to the directory of the
+    /// lnpd binary
+    pub bin_dir: Option<PathBuf>,
+
+    /// Directory for the files receiving output of the daemons; if absent,
+    /// daemons inherit lnpd standard output and error
+    pub log_dir: Option<PathBuf>,
+}
+
+/// Parameters of the daemons running as threads of the lnpd process
+#[derive(Clone)]
+struct ThreadContext {
+    config: Config,
+    local_node: LocalNode,
+    data_dir: PathBuf,
+    rgb20_socket: ZmqSocketAddr,
+}
+
+/// Running daemon
+enum Handle {
+    Process(process::Child),
+    /// Thread reporting the daemon exit status once it terminates
+    Thread(mpsc::Receiver<Result<(), String>>),
+}
+
+impl Handle {
+    /// Checks whether the daemon has terminated, returning its exit status
+    fn try_wait(&mut self) -> Option<Result<(), String>> {
+        match self {
+            Handle::Process(child) => match child.try_wait() {
+                Ok(None) => None,
+                Ok(Some(status)) if status.success() => Some(Ok(())),
+                Ok(Some(status)) => Some(Err(status.to_string())),
+                Err(err) => {
+                    error!(
+                        "Unable to check state of PID {}: {}",
+                        child.id(),
+                        err
+                    );
+                    None
+                }
+            },
+            Handle::Thread(receiver) => match receiver.try_recv() {
+                Ok(status) => Some(status),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => {
+                    Some(Err(s!("thread terminated without exit status")))
+                }
+            },
+        }
+    }
+
+    /// PID of the process running the daemon
+    fn pid(&self) -> u32 {
+        match self {
+            Handle::Process(child) => child.id(),
+            Handle::Thread(_) => process::id(),
+        }
+    }
+
+    /// Describes how the daemon is run, for the log and client messages
+    fn describe(&self) -> String {
+        match self {
+            Handle::Process(child) => format!("with PID {}", child.id()),
+            Handle::Thread(_) => s!("as a thread of lnpd"),
+        }
+    }
+}
+
+/// Starts daemons in the configured mode
+#[derive(Clone)]
+struct Launcher {
+    chain: Chain,
+    processes: ProcessOpts,
+    threads: Option<ThreadContext>,
+}
+
+impl Launcher {
+    fn start(&self, daemon: &Daemon) -> Result<Handle, LaunchError> {
+        match self.threads {
+            None => launch(
+                &self.chain,
+                &daemon.to_string(),
+                daemon.args(),
+                &self.processes,
+            )
+            .map(Handle::Process),
+            Some(ref context) => spawn(daemon.clone(), context.clone())
+                .map(Handle::Thread)
+                .map_err(|err| {
+                    LaunchError::Spawn(daemon.to_string(), err.to_string())
+                }),
+        }
+    }
+}
+
+struct Supervised {
+    daemon: Daemon,
+    /// Running daemon; absent while the daemon awaits restart
+    handle: Option<Handle>,
+    started: Instant,
+    backoff: Duration,
+    restart_at: Option<Instant>,
+}
+
+/// Launches daemons and keeps their handles, restarting daemons which have
+/// crashed from a background thread
+pub struct Supervisor {
+    launcher: Launcher,
+    daemons: Arc<Mutex<Vec<Supervised>>>,
+}
+
+impl Supervisor {
+    /// Starts monitor thread, which reports crashes to the `lnpd` runtime
+    /// over the bridge
+    pub fn start(
+        config: &Config,
+        local_node: &LocalNode,
+        data_dir: &Path,
+        mode: LaunchMode,
+        bridge: esb::Controller<ServiceBus, Request, BridgeHandler>,
+    ) -> Supervisor {
+        let (processes, threads) = match mode {
+            LaunchMode::Processes(opts) => (opts, None),
+            LaunchMode::Threads { rgb20_socket } => (
+                ProcessOpts::default(),
+                Some(ThreadContext {
+                    config: Config {
+                        threaded: true,
+                        ..config.clone()
+                    },
+                    local_node: local_node.clone(),
+                    data_dir: data_dir.to_path_buf(),
+                    rgb20_socket,
+                }),
+            ),
+        };
+        let launcher = Launcher {
+            chain: config.chain.clone(),
+            processes,
+            threads,
+        };
+        let daemons = Arc::new(Mutex::new(vec![]));
+        let monitor = Monitor {
+            launcher: launcher.clone(),
+            daemons: daemons.clone(),
+            bridge,
+        };
+        thread::spawn(move || monitor.run());
+        Supervisor { launcher, daemons }
+    }
+
+    /// Whether daemons run as threads of the lnpd process
+    pub fn is_threaded(&self) -> bool {
+        self.launcher.threads.is_some()
+    }
+
+    /// Launches daemon and puts it under supervision, returning description
+    /// of how the daemon is run
+    pub fn launch(&mut self, daemon: Daemon) -> Result<String, LaunchError> {
+        let handle = self.launcher.start(&daemon)?;
+        let description = handle.describe();
+        self.daemons
+            .lock()
+            .expect("poisoned mutex")
+            .push(Supervised {
+                daemon,
+                handle: Some(handle),
+                started: Instant::now(),
+                backoff: INITIAL_BACKOFF,
+                restart_at: None,
+            });
+        Ok(description)
+    }
+
+    /// Schedules restart of the daemon which is going to terminate, with the
+    /// same backoff as for the crashed daemons. Returns the restart delay, or
+    /// `None` if the daemon is not supervised.
+    pub fn schedule_restart(
+        &mut self,
+        service: &ServiceId,
+    ) -> Option<Duration> {
+        let mut daemons = self.daemons.lock().expect("poisoned mutex");
+        let supervised = daemons.iter_mut().find(|supervised| {
+            supervised.daemon.service().as_ref() == Some(service)
+        })?;
+        if supervised.started.elapsed() >= HEALTHY_UPTIME {
+            supervised.backoff = INITIAL_BACKOFF;
+        }
+        let delay = supervised.backoff;
+        supervised.restart_at = Some(Instant::now() + delay);
+        supervised.backoff = (supervised.backoff * 2).min(MAX_BACKOFF);
+        Some(delay)
+    }
+
+    /// Replaces daemon awaiting restart with another one, which is launched
+    /// instead of it with the same backoff. Returns whether the daemon was
+    /// awaiting restart.
+    pub fn replace(&mut self, service: &ServiceId, daemon: Daemon) -> bool {
+        let mut daemons = self.daemons.lock().expect("poisoned mutex");
+        match daemons.iter_mut().find(|supervised| {
+            supervised.daemon.service().as_ref() == Some(service)
+                && supervised.restart_at.is_some()
+        }) {
+            Some(supervised) => {
+                supervised.daemon = daemon;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops supervision of the daemon, such that it is not restarted once
+    /// it crashes
+    pub fn forget(&mut self, service: &ServiceId) {
+        self.daemons
+            .lock()
+            .expect("poisoned mutex")
+            .retain(|supervised| {
+                supervised.daemon.service().as_ref() != Some(service)
+            });
+    }
+}
+
+struct Monitor {
+    launcher: Launcher,
+    daemons: Arc<Mutex<Vec<Supervised>>>,
+    bridge: esb::Controller<ServiceBus, Request, BridgeHandler>,
+}
+
+impl Monitor {
+    fn run(mut self) {
+        loop {
+            thread::sleep(POLL_PERIOD);
+            let daemons = self.daemons.clone();
+            let mut daemons = daemons.lock().expect("poisoned mutex");
+            daemons.retain(|supervised| {
+                supervised.handle.is_some() || supervised.restart_at.is_some()
+            });
+            for supervised in daemons.iter_mut() {
+                self.check(supervised);
+            }
+            if let Err(err) = self.bridge.send_to(
+                ServiceBus::Bridge,
+                ServiceId::Lnpd,
+                Request::CheckTimeouts,
+            ) {
+                error!("Unable to notify lnpd runtime on timer: {}", err);
+            }
+        }
+    }
+
+    fn check(&mut self, supervised: &mut Supervised) {
+        if let Some(ref mut handle) = supervised.handle {
+            let status = match handle.try_wait() {
+                None => return,
+                Some(status) => status,
+            };
+            let pid = handle.pid();
+            supervised.handle = None;
+            let status = match status {
+                // Daemons terminate gracefully once their job is done
+                Ok(()) => {
+                    debug!("{} has terminated", supervised.daemon);
+                    return;
+                }
+                Err(status) => status,
+            };
+            if supervised.started.elapsed() >= HEALTHY_UPTIME {
+                supervised.backoff = INITIAL_BACKOFF;
+            }
+            error!(
+                "{} has crashed with {}; restarting in {:?}",
+                supervised.daemon, status, supervised.backoff
+            );
+            supervised.restart_at = Some(Instant::now() + supervised.backoff);
+            self.notify(DaemonCrash {
+                name: supervised.daemon.to_string(),
+                service: supervised.daemon.service(),
+                pid,
+                status,
+                restart_delay: supervised.backoff.as_secs(),
+            });
+            supervised.backoff = (supervised.backoff * 2).min(MAX_BACKOFF);
+        }
+
+        match supervised.restart_at {
+            Some(due) if due <= Instant::now() => {}
+            _ => return,
+        }
+        match self.launcher.start(&supervised.daemon) {
+            Ok(handle) => {
+                info!(
+                    "{} is restarted {}",
+                    supervised.daemon,
+                    handle.describe()
+                );
+                supervised.handle = Some(handle);
+                supervised.started = Instant::now();
+                supervised.restart_at = None;
+            }
+            Err(err) => {
+                error!("Unable to restart {}: {}", supervised.daemon, err);
+                supervised.restart_at =
+                    Some(Instant::now() + supervised.backoff);
+                supervised.backoff = (supervised.backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+
+    fn notify(&mut self, crash: DaemonCrash) {
+        if let Err(err) = self.bridge.send_to(
+            ServiceBus::Bridge,
+            ServiceId::Lnpd,
+            Request::DaemonCrashed(crash),
+        ) {
+            error!("Unable to notify lnpd runtime on daemon crash: {}", err);
+        }
+    }
+}
+
+/// Runs the daemon in a new thread, which reports the daemon exit status
+/// over the returned channel
+fn spawn(
+    daemon: Daemon,
+    context: ThreadContext,
+) -> io::Result<mpsc::Receiver<Result<(), String>>> {
+    let (sender, receiver) = mpsc::channel();
+    let name = daemon.to_string();
+    debug!("Launching {} as a thread", name);
+    thread::Builder::new().name(name).spawn(move || {
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| run(daemon, context)));
+        let status = match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(err)) => Err(err.to_string()),
+            Err(payload) if payload.is::<Terminated>() => Ok(()),
+            Err(payload) => Err(payload
+                .downcast_ref::<&str>()
+                .map(|msg| msg.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| s!("panic"))),
+        };
+        let _ = sender.send(status);
+    })?;
+    Ok(receiver)
+}
+
+/// Runs the daemon runtime within the current thread
+fn run(daemon: Daemon, context: ThreadContext) -> Result<(), Error> {
+    let ThreadContext {
+        config,
+        local_node,
+        data_dir,
+        rgb20_socket,
+    } = context;
+    match daemon {
+        Daemon::Channel(channel_id) => {
+            let chain = config.chain.clone();
+            channeld::run(
+                config,
+                local_node,
+                channel_id,
+                chain,
+                data_dir,
+                rgb20_socket,
+            )
+        }
+        Daemon::Connect(NodeAddr::Remote(remote_node_addr)) => {
+            info!("Connecting to {}", &remote_node_addr);
+            let (connection, custom) =
+                peerd::connect(&config, &local_node, remote_node_addr.clone())?;
+            peerd::run(
+                config,
+                connection,
+                custom,
+                NodeAddr::Remote(remote_node_addr.clone()),
+                local_node.node_id(),
+                Some(remote_node_addr.node_id),
+                None,
+                remote_node_addr.remote_addr.into(),
+                true,
+            )
+        }
+        Daemon::Connect(node_addr) => Err(Error::Other(format!(
+            "Unable to connect to {}: only remote nodes are supported",
+            node_addr
+        ))),
+        Daemon::Listen(remote_addr, onion) => {
+            peerd::run_listener(config, local_node, remote_addr, onion)
+        }
+        Daemon::Gossip => gossipd::run(config, local_node),
+        Daemon::Routing => routed::run(config),
+    }
+}
+
+/// Filters out lnpd-specific arguments together with their values, keeping
+/// the arguments which only share their prefix (like `--network-timeout`
+/// for `--network`)
+fn inherited_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut skip_value = false;
+    args.into_iter()
+        .filter(|arg| {
+            if skip_value {
+                skip_value = false;
+                return false;
+            }
+            if LNPD_ONLY_ARGS.contains(&arg.as_str()) {
+                skip_value = true;
+                return false;
+            }
+            // Values given in `--name=value` and `-nvalue` forms
+            !LNPD_ONLY_ARGS.iter().any(|name| {
+                arg.starts_with(&format!("{}=", name))
+                    || (!name.starts_with("--") && arg.starts_with(*name))
+            })
+        })
+        .collect()
+}
+
+/// Launches the daemon binary as a separate process
+fn launch(
+    chain: &Chain,
+    name: &str,
+    args: Vec<String>,
+    opts: &ProcessOpts,
+) -> Result<process::Child, LaunchError> {
+    let mut bin_path = match opts.bin_dir {
+        Some(ref dir) => dir.clone(),
+        None => {
+            let mut path = std::env::current_exe()
+                .map_err(|err| LaunchError::BinaryDir(err.to_string()))?;
+            path.pop();
+            path
+        }
+    };
+    bin_path.push(name);
+    #[cfg(target_os = "windows")]
+    bin_path.set_extension("exe");
+    if !bin_path.is_file() {
+        return Err(LaunchError::NotFound(bin_path.display().to_string()));
+    }
+
+    debug!(
+        "Launching {} as a separate process using `{}` as binary",
+        name,
+        bin_path.display()
+    );
+
+    // The daemon must run on the same chain as the lnpd instance launching
+    // it, which may be a parallel chain and not the one given in the command
+    // line
+    let inherited_args = inherited_args(std::env::args().skip(1));
+
+    // Arguments are passed to the binary as they are, without a shell
+    // interpreting them
+    let mut cmd = process::Command::new(&bin_path);
+    cmd.args(inherited_args)
+        .arg("--chain")
+        .arg(chain.to_string())
+        .args(&args)
+        .stdin(process::Stdio::null());
+    if let Some(ref log_dir) = opts.log_dir {
+        let log_path = log_dir.join(format!("{}.log", name));
+        let output = |path: &PathBuf| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| {
+                    LaunchError::Output(
+                        path.display().to_string(),
+                        err.to_string(),
+                    )
+                })
+        };
+        let stdout = output(&log_path)?;
+        let stderr = output(&log_path)?;
+        cmd.stdout(stdout).stderr(stderr);
+    }
+    trace!("Executing `{:?}`", cmd);
+    cmd.spawn()
+        .map_err(|err| LaunchError::Spawn(name.to_owned(), err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lnpd_args_filtering() {
+        let args = [
+            "--chain",
+            "testnet",
+            "--network-timeout",
+            "5",
+            "-ntestnet",
+            "--parallel-chain=signet",
+            "--bin-dir",
+            "/usr/bin",
+            "-v",
+        ];
+        assert_eq!(
+            inherited_args(args.iter().map(|arg| arg.to_string())),
+            vec!["--network-timeout", "5", "-v"]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+
+use crate::rpc::request::{NodeEvent, WebhookDelivery};
+
+/// Maximum number of delivery attempts for a single event
+const MAX_ATTEMPTS: u8 = 8;
+
+/// Delay before the first retry; doubles with each subsequent attempt
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Timeout for a single HTTP request to the endpoint
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of delivery status records kept for inspection
+const MAX_HISTORY: usize = 1000;
+
+/// Webhook endpoints receiving node events
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct WebhookConfig {
+    /// URLs of the endpoints
+    pub endpoints: Vec<String>,
+
+    /// Secret used to sign event payloads with HMAC-SHA256
+    pub secret: Option<String>,
+}
+
+struct Job {
+    id: u64,
+    endpoint: String,
+    payload: String,
+    due: Instant,
+}
+
+/// Delivers node events to the webhook endpoints from a background thread,
+/// retrying failed deliveries with exponential backoff
+pub struct Dispatcher {
+    endpoints: Vec<String>,
+    last_id: u64,
+    jobs: mpsc::Sender<Job>,
+    history: Arc<Mutex<VecDeque<WebhookDelivery>>>,
+}
+
+impl Dispatcher {
+    pub fn start(config: WebhookConfig) -> Dispatcher {
+        for endpoint in &config.endpoints {
+            if !endpoint.starts_with("https://") {
+                warn!(
+                    "Webhook endpoint {} does not use HTTPS; event payloads \
+                     will be sent unencrypted",
+                    endpoint
+                );
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let history = Arc::new(Mutex::new(VecDeque::new()));
+        let worker = Worker {
+            secret: config.secret,
+            jobs: receiver,
+            queue: vec![],
+            history: history.clone(),
+        };
+        thread::spawn(move || worker.run());
+
+        Dispatcher {
+            endpoints: config.endpoints,
+            last_id: 0,
+            jobs: sender,
+            history,
+        }
+    }
+
+    pub fn dispatch(&mut self, event: &NodeEvent) {
+        if self.endpoints.is_empty() {
+            return;
+        }
+        self.last_id += 1;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs();
+        let payload = serde_json::json!({
+            "id": self.last_id,
+            "timestamp": timestamp,
+            "event": event,
+        })
+        .to_string();
+
+        let mut history = self.history.lock().expect("poisoned mutex");
+        for endpoint in &self.endpoints {
+            history.push_back(WebhookDelivery {
+                id: self.last_id,
+                endpoint: endpoint.clone(),
+                event: event.kind,
+                attempts: 0,
+                delivered: false,
+                last_error: None,
+            });
+            let _ = self.jobs.send(Job {
+                id: self.last_id,
+                endpoint: endpoint.clone(),
+                payload: payload.clone(),
+                due: Instant::now(),
+            });
+        }
+        while history.len() > MAX_HISTORY {
+            history.pop_front();
+        }
+    }
+
+    pub fn deliveries(&self) -> Vec<WebhookDelivery> {
+        self.history
+            .lock()
+            .expect("poisoned mutex")
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+struct Worker {
+    secret: Option<String>,
+    jobs: mpsc::Receiver<Job>,
+    queue: Vec<Job>,
+    history: Arc<Mutex<VecDeque<WebhookDelivery>>>,
+}
+
+impl Worker {
+    fn run(mut self) {
+        loop {
+            let timeout = self
+                .queue
+                .iter()
+                .map(|job| job.due.saturating_duration_since(Instant::now()))
+                .min()
+                .unwrap_or(Duration::from_secs(3600));
+            match self.jobs.recv_timeout(timeout) {
+                Ok(job) => self.queue.push(job),
+                Err(RecvTimeoutError::Timeout) => {}
+                // Dispatcher is dropped together with lnpd runtime
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let now = Instant::now();
+            let (due, pending): (Vec<_>, Vec<_>) =
+                self.queue.drain(..).partition(|job| job.due <= now);
+            self.queue = pending;
+            for job in due {
+                self.deliver(job);
+            }
+        }
+    }
+
+    fn deliver(&mut self, mut job: Job) {
+        let result = self.post(&job);
+        let mut history = self.history.lock().expect("poisoned mutex");
+        let status = match history.iter_mut().find(|delivery| {
+            delivery.id == job.id && delivery.endpoint == job.endpoint
+        }) {
+            Some(status) => status,
+            // Too old to be tracked; we still complete the delivery
+            None => return,
+        };
+        status.attempts += 1;
+        match result {
+            Ok(()) => {
+                debug!("Webhook {} delivered to {}", job.id, job.endpoint);
+                status.delivered = true;
+                status.last_error = None;
+            }
+            Err(err) => {
+                warn!(
+                    "Webhook {} delivery to {} failed: {}",
+                    job.id, job.endpoint, err
+                );
+                status.last_error = Some(err);
+                if status.attempts < MAX_ATTEMPTS {
+                    job.due = Instant::now()
+                        + INITIAL_BACKOFF
+                            * 2u32.pow(status.attempts as u32 - 1);
+                    self.queue.push(job);
+                } else {
+                    error!(
+                        "Giving up delivering webhook {} to {}",
+                        job.id, job.endpoint
+                    );
+                }
+            }
+        }
+    }
+
+    fn post(&self, job: &Job) -> Result<(), String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs()
+            .to_string();
+        let mut request = ureq::post(&job.endpoint)
+            .timeout(REQUEST_TIMEOUT)
+            .set("Content-Type", "application/json")
+            .set("X-Lnp-Timestamp", &timestamp);
+        if let Some(ref secret) = self.secret {
+            // Signing the timestamp together with the payload prevents
+            // replaying old events
+            let mut engine = HmacEngine::<sha256::Hash>::new(secret.as_bytes());
+            engine.input(timestamp.as_bytes());
+            engine.input(b".");
+            engine.input(job.payload.as_bytes());
+            let signature = Hmac::<sha256::Hash>::from_engine(engine);
+            request = request.set(
+                "X-Lnp-Signature",
+                &format!("sha256={}", signature.to_hex()),
+            );
+        }
+        request
+            .send_string(&job.payload)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
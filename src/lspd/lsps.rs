@@ -0,0 +1,469 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+//! LSPS0 transport of the lightning service provider protocols: JSON-RPC 2.0
+//! messages carried by custom peer messages, and the method schemas of LSPS1
+//! (channel purchase) and LSPS2 (just-in-time channels)
+
+use bitcoin::hashes::sha256;
+use chrono::{DateTime, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::DisplayFromStr;
+
+use crate::rpc::request::ShortChannelId;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Errors defined by JSON-RPC 2.0
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Errors of `lsps2.get_info` and `lsps2.buy`
+pub const LSPS2_UNRECOGNIZED_OR_STALE_TOKEN: i64 = 200;
+pub const LSPS2_INVALID_OPENING_FEE_PARAMS: i64 = 201;
+pub const LSPS2_PAYMENT_SIZE_TOO_SMALL: i64 = 202;
+pub const LSPS2_PAYMENT_SIZE_TOO_LARGE: i64 = 203;
+
+pub const LIST_PROTOCOLS: &str = "lsps0.list_protocols";
+pub const LSPS1_GET_INFO: &str = "lsps1.get_info";
+pub const LSPS1_CREATE_ORDER: &str = "lsps1.create_order";
+pub const LSPS1_GET_ORDER: &str = "lsps1.get_order";
+pub const LSPS2_GET_INFO: &str = "lsps2.get_info";
+pub const LSPS2_BUY: &str = "lsps2.buy";
+
+/// LSPS0 message, which is either a request or a response to the request
+/// with the same id
+#[derive(Clone, PartialEq, Debug, From, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", untagged)]
+pub enum Message {
+    #[from]
+    Request(JsonRpcRequest),
+    #[from]
+    Response(JsonRpcResponse),
+}
+
+impl Message {
+    pub fn parse(payload: &[u8]) -> Result<Message, serde_json::Error> {
+        serde_json::from_slice(payload)
+    }
+
+    pub fn to_payload(&self) -> Vec<u8> {
+        serde_json::to_vec(self)
+            .expect("JSON serialization of LSPS messages does not fail")
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    pub id: String,
+}
+
+impl JsonRpcRequest {
+    pub fn with(method: &str, params: impl Serialize, id: String) -> Self {
+        JsonRpcRequest {
+            jsonrpc: s!(JSONRPC_VERSION),
+            method: method.to_owned(),
+            params: serde_json::to_value(params)
+                .expect("JSON serialization of LSPS messages does not fail"),
+            id,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    /// Request id; absent if the request can't be parsed
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+}
+
+impl JsonRpcResponse {
+    pub fn result(id: String, result: impl Serialize) -> Self {
+        JsonRpcResponse {
+            jsonrpc: s!(JSONRPC_VERSION),
+            id: Some(id),
+            result: Some(
+                serde_json::to_value(result).expect(
+                    "JSON serialization of LSPS messages does not fail",
+                ),
+            ),
+            error: None,
+        }
+    }
+
+    pub fn error(id: Option<String>, code: i64, message: String) -> Self {
+        JsonRpcResponse {
+            jsonrpc: s!(JSONRPC_VERSION),
+            id,
+            result: None,
+            error: Some(JsonRpcError {
+                code,
+                message,
+                data: None,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Display, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+#[display("error {code}: {message}")]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// Result of `lsps0.list_protocols`
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Protocols {
+    pub protocols: Vec<u16>,
+}
+
+/// Result of `lsps1.get_info`: limits of the channels sold by the provider
+#[serde_as]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps1Info {
+    pub min_required_channel_confirmations: u16,
+    pub min_funding_confirms_within_blocks: u16,
+    pub supports_zero_channel_reserve: bool,
+    pub max_channel_expiry_blocks: u32,
+    #[serde_as(as = "DisplayFromStr")]
+    pub min_initial_client_balance_sat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub max_initial_client_balance_sat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub min_initial_lsp_balance_sat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub max_initial_lsp_balance_sat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub min_channel_balance_sat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub max_channel_balance_sat: u64,
+}
+
+/// Parameters of `lsps1.create_order`
+#[serde_as]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps1CreateOrder {
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsp_balance_sat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub client_balance_sat: u64,
+    pub required_channel_confirmations: u16,
+    pub funding_confirms_within_blocks: u16,
+    pub channel_expiry_blocks: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refund_onchain_address: Option<String>,
+    pub announce_channel: bool,
+}
+
+/// Parameters of `lsps1.get_order`
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps1GetOrder {
+    pub order_id: String,
+}
+
+/// Result of `lsps1.create_order` and `lsps1.get_order`
+#[serde_as]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps1Order {
+    pub order_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsp_balance_sat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub client_balance_sat: u64,
+    pub required_channel_confirmations: u16,
+    pub funding_confirms_within_blocks: u16,
+    pub channel_expiry_blocks: u32,
+    #[serde(default)]
+    pub token: String,
+    pub created_at: String,
+    pub announce_channel: bool,
+    pub order_state: Lsps1OrderState,
+    pub payment: Lsps1Payment,
+    pub channel: Option<Lsps1Channel>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Lsps1OrderState {
+    Created,
+    Completed,
+    Failed,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps1Payment {
+    pub bolt11: Option<Lsps1Bolt11Payment>,
+    #[serde(default)]
+    pub onchain: Option<Value>,
+}
+
+/// Lightning payment of the order
+#[serde_as]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps1Bolt11Payment {
+    pub state: Lsps1PaymentState,
+    pub expires_at: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub fee_total_sat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub order_total_sat: u64,
+    /// BOLT11 invoice; empty as long as the node does not encode BOLT11
+    /// invoices, so the order is paid by its payment hash
+    #[serde(default)]
+    pub invoice: String,
+    /// Payment hash of the invoice; extension of the schema used until the
+    /// node encodes BOLT11 invoices
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_hash: Option<sha256::Hash>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Lsps1PaymentState {
+    ExpectPayment,
+    Hold,
+    Paid,
+    Refunded,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps1Channel {
+    pub funded_at: String,
+    pub funding_outpoint: String,
+    pub expires_at: String,
+}
+
+/// Parameters of `lsps2.get_info`
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps2GetInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Result of `lsps2.get_info`
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps2Info {
+    pub opening_fee_params_menu: Vec<OpeningFeeParams>,
+}
+
+/// Fee of opening a just-in-time channel, valid until the given time and
+/// authenticated by the provider promise
+#[serde_as]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct OpeningFeeParams {
+    #[serde_as(as = "DisplayFromStr")]
+    pub min_fee_msat: u64,
+    /// Fee in millionths of the payment size
+    pub proportional: u32,
+    pub valid_until: String,
+    pub min_lifetime: u32,
+    pub max_client_to_self_delay: u32,
+    #[serde_as(as = "DisplayFromStr")]
+    pub min_payment_size_msat: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub max_payment_size_msat: u64,
+    pub promise: String,
+}
+
+impl OpeningFeeParams {
+    /// Opening fee for the given payment size as it is defined by LSPS2, or
+    /// `None` on overflow
+    pub fn opening_fee(&self, payment_size_msat: u64) -> Option<u64> {
+        let fee = payment_size_msat
+            .checked_mul(self.proportional as u64)?
+            .checked_add(999_999)?
+            / 1_000_000;
+        Some(fee.max(self.min_fee_msat))
+    }
+}
+
+/// Parameters of `lsps2.buy`
+#[serde_as]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps2Buy {
+    pub opening_fee_params: OpeningFeeParams,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_size_msat: Option<u64>,
+    /// Payment hash of the client invoice; extension of the schema used
+    /// until the node intercepts HTLCs by the short channel id in their
+    /// onion
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_hash: Option<sha256::Hash>,
+}
+
+/// Result of `lsps2.buy`
+#[serde_as]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Lsps2BuyResult {
+    #[serde_as(as = "DisplayFromStr")]
+    pub jit_channel_scid: ShortChannelId,
+    pub lsp_cltv_expiry_delta: u16,
+    #[serde(default)]
+    pub client_trusts_lsp: bool,
+}
+
+/// Formats UNIX timestamp as ISO 8601 date-time used by LSPS
+pub fn datetime(timestamp: u64) -> String {
+    NaiveDateTime::from_timestamp(timestamp as i64, 0)
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+/// Parses ISO 8601 date-time used by LSPS into UNIX timestamp
+pub fn timestamp(datetime: &str) -> Option<u64> {
+    DateTime::parse_from_rfc3339(datetime)
+        .ok()
+        .map(|datetime| datetime.timestamp() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn message_kinds() {
+        let request = Message::parse(
+            br#"{"jsonrpc":"2.0","method":"lsps0.list_protocols","params":{},"id":"a1"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            Message::Request(JsonRpcRequest::with(
+                LIST_PROTOCOLS,
+                json!({}),
+                s!("a1")
+            ))
+        );
+
+        let response = Message::parse(
+            br#"{"jsonrpc":"2.0","id":"a1","result":{"protocols":[1,2]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response,
+            Message::Response(JsonRpcResponse::result(
+                s!("a1"),
+                Protocols {
+                    protocols: vec![1, 2]
+                }
+            ))
+        );
+
+        let error = Message::Response(JsonRpcResponse::error(
+            None,
+            PARSE_ERROR,
+            s!("invalid JSON"),
+        ));
+        assert_eq!(
+            serde_json::from_slice::<Value>(&error.to_payload()).unwrap(),
+            json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {"code": -32700, "message": "invalid JSON"}
+            })
+        );
+    }
+
+    #[test]
+    fn amounts_are_strings() {
+        let params: Lsps1CreateOrder = serde_json::from_value(json!({
+            "lsp_balance_sat": "5000000",
+            "client_balance_sat": "0",
+            "required_channel_confirmations": 0,
+            "funding_confirms_within_blocks": 6,
+            "channel_expiry_blocks": 144,
+            "announce_channel": true
+        }))
+        .unwrap();
+        assert_eq!(params.lsp_balance_sat, 5_000_000);
+        assert_eq!(params.token, None);
+        assert_eq!(
+            serde_json::to_value(&params).unwrap()["lsp_balance_sat"],
+            json!("5000000")
+        );
+    }
+
+    #[test]
+    fn buy_result() {
+        let result: Lsps2BuyResult = serde_json::from_value(json!({
+            "jit_channel_scid": "1x4815x29451",
+            "lsp_cltv_expiry_delta": 144,
+            "client_trusts_lsp": false
+        }))
+        .unwrap();
+        assert_eq!(
+            u64::from(result.jit_channel_scid),
+            1 << 40 | 4815 << 16 | 29451
+        );
+    }
+
+    #[test]
+    fn opening_fee() {
+        let params = OpeningFeeParams {
+            min_fee_msat: 546_000,
+            proportional: 1200,
+            valid_until: s!("2023-02-23T08:47:30.511Z"),
+            min_lifetime: 1008,
+            max_client_to_self_delay: 2016,
+            min_payment_size_msat: 1000,
+            max_payment_size_msat: 1_000_000,
+            promise: s!("abcdefghijklmnopqrstuvwxyz"),
+        };
+        assert_eq!(params.opening_fee(42_000), Some(546_000));
+        // Proportional fee is rounded up
+        assert_eq!(params.opening_fee(1_000_000_001), Some(1_200_001));
+        assert_eq!(params.opening_fee(u64::MAX), None);
+    }
+
+    #[test]
+    fn datetime_format() {
+        assert_eq!(datetime(1677141650), "2023-02-23T08:40:50.000Z");
+        assert_eq!(timestamp("2023-02-23T08:40:50.000Z"), Some(1677141650));
+    }
+}
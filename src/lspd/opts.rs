@@ -0,0 +1,88 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use clap::{AppSettings, Clap};
+use internet2::PartialNodeAddr;
+
+/// Lightning service provider daemon; part of LNP Node
+///
+/// The daemon implements both sides of the lightning service provider (LSP)
+/// protocol, which messages are exchanged with the remote nodes as custom
+/// peer messages: as a client it buys inbound channels from a configured
+/// provider; as a provider it advertises its terms and opens channels to the
+/// clients once they pay the invoice for the order fee.
+///
+/// The daemon is controlled though ZMQ ctl socket (see `ctl-socket` argument
+/// description)
+#[derive(Clap, Clone, PartialEq, Eq, Debug)]
+#[clap(
+    name = "lspd",
+    bin_name = "lspd",
+    author,
+    version,
+    setting = AppSettings::ColoredHelp
+)]
+pub struct Opts {
+    /// Name under which the daemon is registered on the service bus
+    ///
+    /// If omitted the daemon uses the default `lspd` service id
+    #[clap(long, env = "LNP_NODE_LSP_NAME")]
+    pub name: Option<String>,
+
+    /// Node of the provider to buy channels from, in
+    /// '<public_key>@<ipv4>|<ipv6>|<onionv2>|<onionv3>[:<port>]' format.
+    /// The node must be already connected to the provider
+    #[clap(long, env = "LNP_NODE_LSP_PROVIDER")]
+    pub lsp: Option<PartialNodeAddr>,
+
+    /// Sell channels to other nodes acting as a provider
+    #[clap(long)]
+    pub sell: bool,
+
+    /// Minimal capacity of a sold channel, in satoshis
+    #[clap(long, default_value = "100000")]
+    pub min_channel: u64,
+
+    /// Maximal capacity of a sold channel, in satoshis
+    #[clap(long, default_value = "16777215")]
+    pub max_channel: u64,
+
+    /// Fixed part of the channel opening fee, in satoshis
+    #[clap(long, default_value = "1000")]
+    pub fee_base: u64,
+
+    /// Proportional part of the channel opening fee, in millionths of the
+    /// channel capacity
+    #[clap(long, default_value = "5000")]
+    pub fee_ppm: u32,
+
+    /// Allow clients to order zero-confirmation channels
+    #[clap(long)]
+    pub zero_conf: bool,
+
+    /// RGB configuration: ignored by this daemon
+    #[clap(short, long = "rgb20-rpc")]
+    pub r: Option<String>,
+
+    /// These params can be read also from the configuration file, not just
+    /// command-line args or environment variables
+    #[clap(flatten)]
+    pub shared: crate::opts::Opts,
+}
+
+impl Opts {
+    pub fn process(&mut self) {
+        self.shared.process()
+    }
+}
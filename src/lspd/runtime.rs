@@ -0,0 +1,1452 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+use amplify::Wrapper;
+use bitcoin::secp256k1::{self, rand};
+use internet2::{NodeAddr, TypedEnum};
+use lnp::message;
+use microservices::esb::{self, Handler};
+use microservices::rpc::Failure;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use wallet::{HashLock, HashPreimage};
+
+use super::lsps::{self, JsonRpcRequest, JsonRpcResponse};
+use crate::rpc::request::{
+    AccountingEvent, AccountingEventKind, CreateChannel, CustomMessage,
+    HtlcSettlement, InterceptedHtlc, InvoiceInfo, InvoiceRequest, JitOffer,
+    LspOffer, LspOrder, LspOrderRequest, LspOrderState, PaymentInterception,
+    ShortChannelId, Transfer, LSPS0_MESSAGE_TYPE,
+};
+use crate::rpc::{Request, ServiceBus};
+use crate::{Config, CtlServer, Error, LogStyle, Senders, Service, ServiceId};
+
+/// Time, in seconds, during which the invoice for the order fee may be paid
+const ORDER_INVOICE_EXPIRY: u32 = 3600;
+
+/// Time, in seconds, during which the just-in-time channel opening fee
+/// offered with `lsps2.get_info` may be accepted
+const OPENING_FEE_VALIDITY: u64 = 600;
+
+/// Number of blocks within which funding of the sold channels is confirmed
+const FUNDING_CONFIRMS_WITHIN_BLOCKS: u16 = 6;
+
+/// Number of blocks during which the sold channels are kept open
+const CHANNEL_EXPIRY_BLOCKS: u32 = 13_140;
+
+/// Number of blocks during which just-in-time channels are kept open
+const JIT_CHANNEL_LIFETIME: u32 = 1008;
+
+/// Maximal `to_self_delay` the client may require in just-in-time channels
+const MAX_CLIENT_TO_SELF_DELAY: u32 = 2016;
+
+/// CLTV expiry delta of our hop forwarding payments into just-in-time
+/// channels
+const JIT_CLTV_EXPIRY_DELTA: u16 = 144;
+
+pub fn run(
+    config: Config,
+    identity: ServiceId,
+    provider: Option<NodeAddr>,
+    offer: Option<LspOffer>,
+) -> Result<(), Error> {
+    let runtime = Runtime {
+        identity,
+        provider,
+        offer,
+        orders: none!(),
+        lnpd_requests: none!(),
+        opening: none!(),
+        fee_params: none!(),
+        jit_offers: none!(),
+        jit_channels: none!(),
+        jit_payments: none!(),
+        requests: none!(),
+        purchased: none!(),
+    };
+
+    Service::run(config, runtime, false)
+}
+
+/// Node id of the remote node
+fn node_id(node: &NodeAddr) -> Option<secp256k1::PublicKey> {
+    match node {
+        NodeAddr::Remote(addr) => Some(addr.node_id),
+        _ => None,
+    }
+}
+
+/// Current UNIX timestamp
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs()
+}
+
+/// Random identifier of JSON-RPC requests, orders and fee promises
+fn random_id() -> String {
+    format!(
+        "{:016x}{:016x}",
+        rand::random::<u64>(),
+        rand::random::<u64>()
+    )
+}
+
+/// Error of the LSPS method, which is returned to the client as JSON-RPC
+/// error
+type MethodError = (i64, String);
+
+fn decode_params<T>(params: Value) -> Result<T, MethodError>
+where
+    T: DeserializeOwned,
+{
+    serde_json::from_value(params)
+        .map_err(|err| (lsps::INVALID_PARAMS, err.to_string()))
+}
+
+fn encode_result(result: impl Serialize) -> Result<Value, MethodError> {
+    Ok(serde_json::to_value(result)
+        .expect("JSON serialization of LSPS messages does not fail"))
+}
+
+/// Request made by the provider to `lnpd`, which replies to the requests in
+/// the order they were made
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum LnpdRequest {
+    /// Invoice for the fee of the order sent by the client
+    Invoice(OrderRequest),
+    /// Channel opening, tracked with [`Opening`]
+    Channel,
+}
+
+/// Channel order sent by the client with `lsps1.create_order`, which awaits
+/// the invoice for its fee
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct OrderRequest {
+    client: NodeAddr,
+    remote_id: secp256k1::PublicKey,
+    /// JSON-RPC request id, to which the order is returned
+    request_id: String,
+    params: lsps::Lsps1CreateOrder,
+    fee_sat: u64,
+}
+
+/// Channel order sold by us
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct SoldOrder {
+    order: LspOrder,
+    params: lsps::Lsps1CreateOrder,
+    created_at: u64,
+    /// Expiry of the invoice for the order fee
+    expires_at: u64,
+}
+
+impl SoldOrder {
+    /// Order as it is returned by `lsps1.create_order` and `lsps1.get_order`
+    fn to_lsps(&self) -> lsps::Lsps1Order {
+        // Order is completed only once the channel is confirmed, which is
+        // not tracked, and we do not refund failed orders
+        let (order_state, payment_state) = match self.order.state {
+            LspOrderState::AwaitingPayment => (
+                lsps::Lsps1OrderState::Created,
+                lsps::Lsps1PaymentState::ExpectPayment,
+            ),
+            LspOrderState::Opening | LspOrderState::Funding => (
+                lsps::Lsps1OrderState::Created,
+                lsps::Lsps1PaymentState::Paid,
+            ),
+            LspOrderState::Failed => {
+                (lsps::Lsps1OrderState::Failed, lsps::Lsps1PaymentState::Paid)
+            }
+        };
+        lsps::Lsps1Order {
+            order_id: self.order.order_id.clone(),
+            lsp_balance_sat: self.params.lsp_balance_sat,
+            client_balance_sat: self.params.client_balance_sat,
+            required_channel_confirmations: self
+                .params
+                .required_channel_confirmations,
+            funding_confirms_within_blocks: self
+                .params
+                .funding_confirms_within_blocks,
+            channel_expiry_blocks: self.params.channel_expiry_blocks,
+            token: self.params.token.clone().unwrap_or_default(),
+            created_at: lsps::datetime(self.created_at),
+            announce_channel: self.params.announce_channel,
+            order_state,
+            payment: lsps::Lsps1Payment {
+                bolt11: Some(lsps::Lsps1Bolt11Payment {
+                    state: payment_state,
+                    expires_at: lsps::datetime(self.expires_at),
+                    fee_total_sat: self.order.fee_sat,
+                    order_total_sat: self.order.fee_sat,
+                    invoice: s!(""),
+                    payment_hash: Some(self.order.payment_hash),
+                }),
+                onchain: None,
+            },
+            channel: None,
+        }
+    }
+}
+
+/// Channel opening initiated by the provider
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Opening {
+    /// Channel sold with a paid order
+    Order(String),
+    /// Just-in-time channel opened to forward an intercepted payment
+    Jit(JitForward),
+}
+
+/// Just-in-time channel sold with `lsps2.buy`
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct JitSale {
+    client: NodeAddr,
+    offer: JitOffer,
+}
+
+/// Payment forwarded to the just-in-time channel
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct JitForward {
+    /// Channel daemon which has received the payment HTLC
+    channeld: ServiceId,
+    htlc: InterceptedHtlc,
+    fee_msat: u64,
+}
+
+/// Request sent by us to the provider, awaiting its response, together with
+/// the service which has asked for it
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Pending {
+    /// `lsps1.get_info` for the provider terms
+    Info(ServiceId),
+    /// `lsps2.get_info` completing the provider terms with the fees
+    Fees(ServiceId, lsps::Lsps1Info),
+    /// `lsps1.get_info` preceding the channel order
+    OrderInfo(ServiceId, LspOrderRequest),
+    /// `lsps1.create_order`
+    Order(ServiceId),
+    /// `lsps2.get_info` preceding the just-in-time channel purchase
+    JitInfo(ServiceId, HashLock),
+    /// `lsps2.buy`
+    JitBuy(ServiceId, HashLock, lsps::OpeningFeeParams),
+}
+
+impl Pending {
+    fn enquirer(&self) -> ServiceId {
+        match self {
+            Pending::Info(enquirer)
+            | Pending::Fees(enquirer, _)
+            | Pending::OrderInfo(enquirer, _)
+            | Pending::Order(enquirer)
+            | Pending::JitInfo(enquirer, _)
+            | Pending::JitBuy(enquirer, ..) => enquirer.clone(),
+        }
+    }
+}
+
+pub struct Runtime {
+    identity: ServiceId,
+    /// Provider node we buy channels from, if any
+    provider: Option<NodeAddr>,
+    /// Terms under which we sell channels, if we act as a provider
+    offer: Option<LspOffer>,
+    /// Orders sold by us
+    orders: BTreeMap<String, SoldOrder>,
+    /// Requests to `lnpd` awaiting its reply
+    lnpd_requests: VecDeque<LnpdRequest>,
+    /// Channels which wait for the funding information, in the order they
+    /// were requested from `lnpd`
+    opening: VecDeque<Opening>,
+    /// Just-in-time channel opening fees offered by us, by their promise
+    fee_params: HashMap<String, lsps::OpeningFeeParams>,
+    /// Just-in-time channels sold by us, indexed by their short channel id
+    /// alias
+    jit_offers: HashMap<u64, JitSale>,
+    /// Just-in-time channels being opened, with the payment they must
+    /// forward once active
+    jit_channels: HashMap<ServiceId, JitForward>,
+    /// Payments forwarded into just-in-time channels, awaiting the client to
+    /// fulfill them
+    jit_payments: HashMap<ServiceId, JitForward>,
+    /// Requests sent to the provider, by their JSON-RPC id
+    requests: HashMap<String, Pending>,
+    /// Orders bought by us from the provider
+    purchased: Vec<LspOrder>,
+}
+
+impl CtlServer for Runtime {}
+
+impl esb::Handler<ServiceBus> for Runtime {
+    type Request = Request;
+    type Address = ServiceId;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId {
+        self.identity.clone()
+    }
+
+    fn handle(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        bus: ServiceBus,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Self::Error> {
+        match bus {
+            ServiceBus::Msg => self.handle_rpc_msg(senders, source, request),
+            ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
+            _ => Err(Error::NotSupported(bus, request.get_type())),
+        }
+    }
+
+    fn handle_err(&mut self, _: esb::Error) -> Result<(), esb::Error> {
+        // We do nothing and do not propagate error; it's already being reported
+        // with `error!` macro by the controller. If we propagate error here
+        // this will make whole daemon panic
+        Ok(())
+    }
+}
+
+impl Runtime {
+    fn handle_rpc_msg(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        let (remote_id, payload) = match request {
+            Request::CustomPeerMessage(CustomMessage {
+                remote_id,
+                msg_type: LSPS0_MESSAGE_TYPE,
+                payload,
+            }) => (remote_id, payload),
+            _ => {
+                error!("MSG RPC can be only used for forwarding LSPS messages");
+                return Err(Error::NotSupported(
+                    ServiceBus::Msg,
+                    request.get_type(),
+                ));
+            }
+        };
+        let peer = match source {
+            ServiceId::Peer(node_addr) => node_addr,
+            source => {
+                warn!("LSPS message relayed by non-peer service {}", source);
+                return Ok(());
+            }
+        };
+
+        match lsps::Message::parse(&payload) {
+            Ok(lsps::Message::Request(request)) => {
+                self.lsps_request(senders, peer, remote_id, request)
+            }
+            Ok(lsps::Message::Response(response))
+                if self.provider.as_ref().and_then(node_id)
+                    == Some(remote_id) =>
+            {
+                self.provider_replied(senders, response)
+            }
+            Ok(lsps::Message::Response(response)) => {
+                warn!(
+                    "Unsolicited LSPS response {:?} from {}",
+                    response.id, remote_id
+                );
+                Ok(())
+            }
+            Err(err) => {
+                warn!("Invalid LSPS message from {}: {}", remote_id, err);
+                let reply = JsonRpcResponse::error(
+                    None,
+                    lsps::PARSE_ERROR,
+                    err.to_string(),
+                );
+                self.send_lsps(senders, peer, remote_id, reply)
+            }
+        }
+    }
+
+    /// Serves LSPS request of the client
+    fn lsps_request(
+        &mut self,
+        senders: &mut Senders,
+        client: NodeAddr,
+        remote_id: secp256k1::PublicKey,
+        request: JsonRpcRequest,
+    ) -> Result<(), Error> {
+        let JsonRpcRequest {
+            method, params, id, ..
+        } = request;
+        debug!("Got LSPS request {} from {}", method, remote_id);
+
+        let result = match method.as_str() {
+            lsps::LIST_PROTOCOLS => encode_result(lsps::Protocols {
+                protocols: if self.offer.is_some() {
+                    vec![1, 2]
+                } else {
+                    vec![]
+                },
+            }),
+            _ if self.offer.is_none() => Err((
+                lsps::METHOD_NOT_FOUND,
+                format!("method {} is not supported", method),
+            )),
+            lsps::LSPS1_GET_INFO => encode_result(self.lsps1_info()),
+            lsps::LSPS1_CREATE_ORDER => {
+                match decode_params(params)
+                    .and_then(|params| Ok((self.check_order(&params)?, params)))
+                {
+                    Ok((fee_sat, params)) => {
+                        return self.request_invoice(
+                            senders,
+                            OrderRequest {
+                                client,
+                                remote_id,
+                                request_id: id,
+                                params,
+                                fee_sat,
+                            },
+                        )
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            lsps::LSPS1_GET_ORDER => {
+                decode_params(params).and_then(|params: lsps::Lsps1GetOrder| {
+                    match self.orders.get(&params.order_id) {
+                        Some(sold)
+                            if node_id(&sold.order.peer) == Some(remote_id) =>
+                        {
+                            encode_result(sold.to_lsps())
+                        }
+                        _ => Err((
+                            lsps::INVALID_PARAMS,
+                            format!("unknown order {}", params.order_id),
+                        )),
+                    }
+                })
+            }
+            lsps::LSPS2_GET_INFO => decode_params(params)
+                .and_then(|params| self.lsps2_info(params))
+                .and_then(encode_result),
+            lsps::LSPS2_BUY => decode_params(params)
+                .and_then(|params| {
+                    self.jit_buy(senders, client.clone(), params)
+                })
+                .and_then(encode_result),
+            _ => Err((
+                lsps::METHOD_NOT_FOUND,
+                format!("method {} is not supported", method),
+            )),
+        };
+
+        let reply = match result {
+            Ok(result) => JsonRpcResponse::result(id, result),
+            Err((code, message)) => {
+                debug!("LSPS request {} has failed: {}", method, message);
+                JsonRpcResponse::error(Some(id), code, message)
+            }
+        };
+        self.send_lsps(senders, client, remote_id, reply)
+    }
+
+    fn handle_rpc_ctl(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::Hello(_) => {
+                // Ignoring; this is used to set remote identity at ZMQ level
+            }
+
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
+            // Provider side
+            // -------------
+            Request::LspGetInfo
+                if self.provider.is_none() && self.offer.is_some() =>
+            {
+                let offer = self.offer.clone().expect("checked by match guard");
+                self.send_ctl(senders, source, Request::LspInfo(offer))?;
+            }
+
+            Request::InvoiceInfo(invoice)
+                if source == ServiceId::Lnpd
+                    && matches!(
+                        self.lnpd_requests.front(),
+                        Some(LnpdRequest::Invoice(_))
+                    ) =>
+            {
+                let order_request = match self.lnpd_requests.pop_front() {
+                    Some(LnpdRequest::Invoice(order_request)) => order_request,
+                    _ => unreachable!("checked by match guard"),
+                };
+                self.create_order(senders, order_request, invoice)?;
+            }
+
+            Request::InvoiceSettled(payment_hash) => {
+                let payment_hash = payment_hash.into_inner();
+                let order_id = self
+                    .orders
+                    .values()
+                    .find(|sold| {
+                        sold.order.payment_hash == payment_hash
+                            && sold.order.state
+                                == LspOrderState::AwaitingPayment
+                    })
+                    .map(|sold| sold.order.order_id.clone());
+                match order_id {
+                    Some(order_id) => self.open_channel(senders, order_id)?,
+                    None => {
+                        warn!(
+                            "Payment {} does not match any order",
+                            payment_hash
+                        )
+                    }
+                }
+            }
+
+            Request::Progress(_) | Request::Failure(_)
+                if source == ServiceId::Lnpd
+                    && !self.lnpd_requests.is_empty() =>
+            {
+                let lnpd_request = self
+                    .lnpd_requests
+                    .pop_front()
+                    .expect("checked by match guard");
+                match (lnpd_request, request) {
+                    (LnpdRequest::Channel, Request::Progress(info)) => {
+                        debug!("Channel opening progress: {}", info);
+                    }
+                    (LnpdRequest::Channel, Request::Failure(failure)) => {
+                        self.opening_failed(senders, failure)?;
+                    }
+                    (
+                        LnpdRequest::Invoice(order_request),
+                        Request::Failure(failure),
+                    ) => {
+                        error!(
+                            "{} {}: {}",
+                            "Unable to create order invoice for".err(),
+                            order_request.client,
+                            failure.info
+                        );
+                        let reply = JsonRpcResponse::error(
+                            Some(order_request.request_id),
+                            lsps::INTERNAL_ERROR,
+                            s!("order is not available"),
+                        );
+                        self.send_lsps(
+                            senders,
+                            order_request.client,
+                            order_request.remote_id,
+                            reply,
+                        )?;
+                    }
+                    (lnpd_request, request) => {
+                        warn!(
+                            "Unexpected reply {} to {:?}",
+                            request, lnpd_request
+                        );
+                    }
+                }
+            }
+
+            Request::ChannelFunding(pubkey_script)
+                if !self.opening.is_empty() =>
+            {
+                match self.opening.pop_front().expect("checked by match guard")
+                {
+                    Opening::Order(order_id) => {
+                        if let Some(sold) = self.orders.get_mut(&order_id) {
+                            sold.order.state = LspOrderState::Funding;
+                        }
+                        info!(
+                            "{} {} awaits funding to {}",
+                            "Channel order".ended(),
+                            order_id,
+                            pubkey_script.addr()
+                        );
+                    }
+                    Opening::Jit(forward) => {
+                        info!(
+                            "{} {} for {} awaits funding to {}",
+                            "Just-in-time channel".ended(),
+                            source,
+                            forward.htlc.payment_hash,
+                            pubkey_script.addr()
+                        );
+                        self.jit_channels.insert(source, forward);
+                    }
+                }
+            }
+
+            Request::Success(_) if self.jit_channels.contains_key(&source) => {
+                let forward = self
+                    .jit_channels
+                    .remove(&source)
+                    .expect("checked by match guard");
+                let amount = forward.htlc.amount_msat - forward.fee_msat;
+                info!(
+                    "{} {} msat of {} into just-in-time channel {}",
+                    "Forwarding".promo(),
+                    amount,
+                    forward.htlc.payment_hash,
+                    source.promoter()
+                );
+                // The same payment hash is paid to the client, which reveals
+                // the preimage settling the payment to us
+                let payment_hash = Some(forward.htlc.payment_hash);
+                self.jit_payments.insert(source.clone(), forward);
+                self.send_ctl(
+                    senders,
+                    source.clone(),
+                    Request::Transfer(Transfer {
+                        channeld: source,
+                        amount,
+                        asset: None,
+                        payment_hash,
+                    }),
+                )?;
+            }
+
+            Request::PaymentPreimage(preimage)
+                if self.jit_payments.contains_key(&source) =>
+            {
+                let forward = self
+                    .jit_payments
+                    .remove(&source)
+                    .expect("checked by match guard");
+                if HashLock::from(preimage) != forward.htlc.payment_hash {
+                    error!(
+                        "{} {}",
+                        "Invalid preimage revealed for".err(),
+                        forward.htlc.payment_hash
+                    );
+                    return self.settle_forward(senders, forward, None);
+                }
+                info!(
+                    "{} {} through just-in-time channel {}",
+                    "Payment settled".ended(),
+                    forward.htlc.payment_hash,
+                    source.ender()
+                );
+                self.settle_forward(senders, forward, Some(preimage))?;
+            }
+
+            Request::Failure(failure)
+                if self.jit_payments.contains_key(&source) =>
+            {
+                let forward = self
+                    .jit_payments
+                    .remove(&source)
+                    .expect("checked by match guard");
+                error!(
+                    "{} {}: {}",
+                    "Payment failed in just-in-time channel".err(),
+                    source,
+                    failure.info
+                );
+                self.settle_forward(senders, forward, None)?;
+            }
+
+            Request::InterceptHtlc(htlc) => {
+                let sale = self.jit_offers.remove(&htlc.scid);
+                let resp = match sale {
+                    Some(sale) => self.open_jit_channel(
+                        senders,
+                        sale,
+                        source.clone(),
+                        htlc.clone(),
+                    ),
+                    None => Err(format!("Unknown channel alias {}", htlc.scid)),
+                };
+                if let Err(info) = resp {
+                    warn!("{}", info);
+                    let forward = JitForward {
+                        channeld: source,
+                        htlc,
+                        fee_msat: 0,
+                    };
+                    self.settle_forward(senders, forward, None)?;
+                }
+            }
+
+            Request::Progress(info) => {
+                debug!("Channel opening progress: {}", info);
+            }
+
+            Request::Success(info) => {
+                debug!("Channel opening completed: {}", info);
+            }
+
+            Request::Failure(failure) => {
+                self.opening_failed(senders, failure)?;
+            }
+
+            // Client side
+            // -----------
+            Request::LspGetInfo
+            | Request::LspBuyChannel(_)
+            | Request::LspJitBuy(_)
+                if self.provider.is_some() =>
+            {
+                // Provider terms are combined from LSPS1 and LSPS2 info, and
+                // purchases are preceded by the info request providing their
+                // parameters
+                let (method, pending) = match request {
+                    Request::LspBuyChannel(order_req) => (
+                        lsps::LSPS1_GET_INFO,
+                        Pending::OrderInfo(source.clone(), order_req),
+                    ),
+                    Request::LspJitBuy(payment_hash) => (
+                        lsps::LSPS2_GET_INFO,
+                        Pending::JitInfo(source.clone(), payment_hash),
+                    ),
+                    _ => (lsps::LSPS1_GET_INFO, Pending::Info(source.clone())),
+                };
+                let params = serde_json::Map::new();
+                if let Err(info) =
+                    self.request_provider(senders, method, params, pending)
+                {
+                    return Err(self.report_failure_to(
+                        senders,
+                        source,
+                        Failure { code: 0, info },
+                    ));
+                }
+            }
+
+            Request::LspListOrders => {
+                let list = self
+                    .orders
+                    .values()
+                    .map(|sold| &sold.order)
+                    .chain(self.purchased.iter())
+                    .cloned()
+                    .collect();
+                self.send_ctl(senders, source, Request::LspOrderList(list))?;
+            }
+
+            Request::LspGetInfo
+            | Request::LspBuyChannel(_)
+            | Request::LspJitBuy(_) => {
+                let info =
+                    s!("The daemon is not configured for this role of the \
+                     lightning service provider protocol");
+                return Err(self.report_failure_to(
+                    senders,
+                    source,
+                    Failure { code: 0, info },
+                ));
+            }
+
+            _ => {
+                error!("Request is not supported by the CTL interface");
+                return Err(Error::NotSupported(
+                    ServiceBus::Ctl,
+                    request.get_type(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn send_lsps(
+        &mut self,
+        senders: &mut Senders,
+        node: NodeAddr,
+        remote_id: secp256k1::PublicKey,
+        message: impl Into<lsps::Message>,
+    ) -> Result<(), Error> {
+        let payload = message.into().to_payload();
+        senders.send_to(
+            ServiceBus::Msg,
+            self.identity(),
+            ServiceId::Peer(node),
+            Request::CustomPeerMessage(CustomMessage {
+                remote_id,
+                msg_type: LSPS0_MESSAGE_TYPE,
+                payload,
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// Sends LSPS request to the provider, remembering it until the provider
+    /// replies
+    fn request_provider(
+        &mut self,
+        senders: &mut Senders,
+        method: &str,
+        params: impl Serialize,
+        pending: Pending,
+    ) -> Result<(), String> {
+        let provider = self
+            .provider
+            .clone()
+            .expect("requests are sent only by clients");
+        let remote_id = node_id(&provider)
+            .ok_or_else(|| s!("provider must be a remote node"))?;
+        let id = random_id();
+        debug!("Sending {} to the provider {}", method, provider);
+        let request = JsonRpcRequest::with(method, params, id.clone());
+        self.send_lsps(senders, provider.clone(), remote_id, request)
+            .map_err(|err| {
+                format!("Unable to reach the provider {}: {}", provider, err)
+            })?;
+        self.requests.insert(id, pending);
+        Ok(())
+    }
+
+    /// Processes the response of the provider, passing the result to the
+    /// service which has sent the request once no more requests are needed
+    fn provider_replied(
+        &mut self,
+        senders: &mut Senders,
+        response: JsonRpcResponse,
+    ) -> Result<(), Error> {
+        let pending = match response.id.and_then(|id| self.requests.remove(&id))
+        {
+            Some(pending) => pending,
+            None => {
+                warn!(
+                    "Unsolicited response from the provider: {:?}",
+                    response.error
+                );
+                return Ok(());
+            }
+        };
+        let enquirer = pending.enquirer();
+        let result = match (response.result, response.error) {
+            (_, Some(err)) => Err(format!("provider has failed: {}", err)),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(s!("provider has sent empty response")),
+        };
+        match result
+            .and_then(|result| self.process_result(senders, pending, result))
+        {
+            Ok(Some(reply)) => self.send_ctl(senders, enquirer, reply),
+            // Next request is sent to the provider
+            Ok(None) => Ok(()),
+            Err(info) => Err(self.report_failure_to(
+                senders,
+                enquirer,
+                Failure { code: 0, info },
+            )),
+        }
+    }
+
+    /// Processes the result of the provider response, returning the reply to
+    /// the enquirer or `None` if another request is sent to the provider
+    fn process_result(
+        &mut self,
+        senders: &mut Senders,
+        pending: Pending,
+        result: Value,
+    ) -> Result<Option<Request>, String> {
+        fn decode<T: DeserializeOwned>(result: Value) -> Result<T, String> {
+            serde_json::from_value(result).map_err(|err| {
+                format!("provider has sent invalid response: {}", err)
+            })
+        }
+
+        match pending {
+            Pending::Info(enquirer) => {
+                let info = decode(result)?;
+                self.request_provider(
+                    senders,
+                    lsps::LSPS2_GET_INFO,
+                    lsps::Lsps2GetInfo::default(),
+                    Pending::Fees(enquirer, info),
+                )?;
+                Ok(None)
+            }
+
+            Pending::Fees(_, info) => {
+                let fees: lsps::Lsps2Info = decode(result)?;
+                let params = fees.opening_fee_params_menu.first();
+                Ok(Some(Request::LspInfo(LspOffer {
+                    min_channel_sat: info.min_initial_lsp_balance_sat,
+                    max_channel_sat: info.max_initial_lsp_balance_sat,
+                    fee_base_sat: params
+                        .map(|params| params.min_fee_msat / 1000)
+                        .unwrap_or_default(),
+                    fee_ppm: params
+                        .map(|params| params.proportional)
+                        .unwrap_or_default(),
+                    zero_conf: info.min_required_channel_confirmations == 0,
+                })))
+            }
+
+            Pending::OrderInfo(enquirer, order_req) => {
+                let info: lsps::Lsps1Info = decode(result)?;
+                if order_req.zero_conf
+                    && info.min_required_channel_confirmations > 0
+                {
+                    return Err(s!(
+                        "provider does not sell zero-confirmation channels"
+                    ));
+                }
+                let params = lsps::Lsps1CreateOrder {
+                    lsp_balance_sat: order_req.lsp_balance_sat,
+                    client_balance_sat: 0,
+                    required_channel_confirmations: if order_req.zero_conf {
+                        0
+                    } else {
+                        info.min_required_channel_confirmations.max(1)
+                    },
+                    funding_confirms_within_blocks: info
+                        .min_funding_confirms_within_blocks,
+                    channel_expiry_blocks: info.max_channel_expiry_blocks,
+                    token: None,
+                    refund_onchain_address: None,
+                    announce_channel: true,
+                };
+                self.request_provider(
+                    senders,
+                    lsps::LSPS1_CREATE_ORDER,
+                    params,
+                    Pending::Order(enquirer),
+                )?;
+                Ok(None)
+            }
+
+            Pending::Order(_) => {
+                let order: lsps::Lsps1Order = decode(result)?;
+                let bolt11 = order.payment.bolt11.ok_or_else(|| {
+                    s!("provider accepts only on-chain payment of the order")
+                })?;
+                // Without BOLT11 support the invoice is paid by its payment
+                // hash, which is an extension of LSPS1 order
+                let payment_hash = bolt11.payment_hash.ok_or_else(|| {
+                    s!("provider order has no payment hash; BOLT11 invoices \
+                        are not supported")
+                })?;
+                let state = match (order.order_state, bolt11.state) {
+                    (lsps::Lsps1OrderState::Failed, _) => LspOrderState::Failed,
+                    (lsps::Lsps1OrderState::Completed, _) => {
+                        LspOrderState::Funding
+                    }
+                    (_, lsps::Lsps1PaymentState::ExpectPayment) => {
+                        LspOrderState::AwaitingPayment
+                    }
+                    (_, _) => LspOrderState::Opening,
+                };
+                let order = LspOrder {
+                    order_id: order.order_id,
+                    peer: self
+                        .provider
+                        .clone()
+                        .expect("responses are accepted only from provider"),
+                    lsp_balance_sat: order.lsp_balance_sat,
+                    zero_conf: order.required_channel_confirmations == 0,
+                    fee_sat: bolt11.fee_total_sat,
+                    payment_hash,
+                    state,
+                };
+                info!(
+                    "{} {} for {} sat fee; the channel will be opened once \
+                     invoice {} is paid",
+                    "Channel ordered".ended(),
+                    order.order_id,
+                    order.fee_sat,
+                    order.payment_hash
+                );
+                self.purchased.push(order.clone());
+                Ok(Some(Request::LspOrder(order)))
+            }
+
+            Pending::JitInfo(enquirer, payment_hash) => {
+                let info: lsps::Lsps2Info = decode(result)?;
+                let params = info
+                    .opening_fee_params_menu
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| {
+                        s!("provider does not sell just-in-time channels")
+                    })?;
+                let buy = lsps::Lsps2Buy {
+                    opening_fee_params: params.clone(),
+                    payment_size_msat: None,
+                    payment_hash: Some(payment_hash.into_inner()),
+                };
+                self.request_provider(
+                    senders,
+                    lsps::LSPS2_BUY,
+                    buy,
+                    Pending::JitBuy(enquirer, payment_hash, params),
+                )?;
+                Ok(None)
+            }
+
+            Pending::JitBuy(_, payment_hash, params) => {
+                let bought: lsps::Lsps2BuyResult = decode(result)?;
+                Ok(Some(Request::LspJitOffer(JitOffer {
+                    scid: bought.jit_channel_scid,
+                    cltv_expiry_delta: bought.lsp_cltv_expiry_delta,
+                    payment_hash: payment_hash.into_inner(),
+                    min_fee_msat: params.min_fee_msat,
+                    proportional: params.proportional,
+                })))
+            }
+        }
+    }
+
+    fn opening_failed(
+        &mut self,
+        senders: &mut Senders,
+        failure: Failure,
+    ) -> Result<(), Error> {
+        match self.opening.pop_front() {
+            Some(Opening::Order(order_id)) => {
+                if let Some(sold) = self.orders.get_mut(&order_id) {
+                    sold.order.state = LspOrderState::Failed;
+                }
+                error!(
+                    "{} {}: {}",
+                    "Unable to open channel for order".err(),
+                    order_id,
+                    failure.info
+                );
+            }
+            Some(Opening::Jit(forward)) => {
+                error!(
+                    "{} {}: {}",
+                    "Unable to open just-in-time channel for".err(),
+                    forward.htlc.payment_hash,
+                    failure.info
+                );
+                self.settle_forward(senders, forward, None)?;
+            }
+            None => {
+                error!("{}: {}", "Unable to open channel".err(), failure.info)
+            }
+        }
+        Ok(())
+    }
+
+    /// Limits of the sold channels, as they are returned by `lsps1.get_info`
+    fn lsps1_info(&self) -> lsps::Lsps1Info {
+        let offer = self.offer.as_ref().expect("checked by the caller");
+        lsps::Lsps1Info {
+            min_required_channel_confirmations: if offer.zero_conf {
+                0
+            } else {
+                1
+            },
+            min_funding_confirms_within_blocks: FUNDING_CONFIRMS_WITHIN_BLOCKS,
+            supports_zero_channel_reserve: false,
+            max_channel_expiry_blocks: CHANNEL_EXPIRY_BLOCKS,
+            // Channels are funded by the provider only
+            min_initial_client_balance_sat: 0,
+            max_initial_client_balance_sat: 0,
+            min_initial_lsp_balance_sat: offer.min_channel_sat,
+            max_initial_lsp_balance_sat: offer.max_channel_sat,
+            min_channel_balance_sat: offer.min_channel_sat,
+            max_channel_balance_sat: offer.max_channel_sat,
+        }
+    }
+
+    /// Offers the fee of opening just-in-time channels, which the client
+    /// may accept with `lsps2.buy` until it expires
+    fn lsps2_info(
+        &mut self,
+        params: lsps::Lsps2GetInfo,
+    ) -> Result<lsps::Lsps2Info, MethodError> {
+        if params.token.is_some() {
+            return Err((
+                lsps::LSPS2_UNRECOGNIZED_OR_STALE_TOKEN,
+                s!("tokens are not supported"),
+            ));
+        }
+        let offer = self.offer.as_ref().expect("checked by the caller");
+        let now = now();
+        self.fee_params.retain(|_, params| {
+            lsps::timestamp(&params.valid_until)
+                .map(|valid_until| valid_until > now)
+                .unwrap_or_default()
+        });
+
+        let min_fee_msat = offer.fee_base_sat * 1000;
+        let params = lsps::OpeningFeeParams {
+            min_fee_msat,
+            proportional: offer.fee_ppm,
+            valid_until: lsps::datetime(now + OPENING_FEE_VALIDITY),
+            min_lifetime: JIT_CHANNEL_LIFETIME,
+            max_client_to_self_delay: MAX_CLIENT_TO_SELF_DELAY,
+            min_payment_size_msat: min_fee_msat + 1,
+            // Channel is funded with the payment amount rounded up to the
+            // next satoshi
+            max_payment_size_msat: offer.max_channel_sat.saturating_sub(1)
+                * 1000,
+            promise: random_id(),
+        };
+        self.fee_params
+            .insert(params.promise.clone(), params.clone());
+        Ok(lsps::Lsps2Info {
+            opening_fee_params_menu: vec![params],
+        })
+    }
+
+    /// Sells just-in-time channel, which is opened once the payment with
+    /// the given hash is forwarded to the returned short channel id alias
+    fn jit_buy(
+        &mut self,
+        senders: &mut Senders,
+        client: NodeAddr,
+        params: lsps::Lsps2Buy,
+    ) -> Result<lsps::Lsps2BuyResult, MethodError> {
+        let fee_params = params.opening_fee_params;
+        let valid = self.fee_params.get(&fee_params.promise)
+            == Some(&fee_params)
+            && lsps::timestamp(&fee_params.valid_until)
+                .map(|valid_until| valid_until > now())
+                .unwrap_or_default();
+        if !valid {
+            return Err((
+                lsps::LSPS2_INVALID_OPENING_FEE_PARAMS,
+                s!("opening fee parameters are invalid or expired"),
+            ));
+        }
+        if let Some(payment_size_msat) = params.payment_size_msat {
+            if payment_size_msat < fee_params.min_payment_size_msat
+                || fee_params.opening_fee(payment_size_msat)
+                    >= Some(payment_size_msat)
+            {
+                return Err((
+                    lsps::LSPS2_PAYMENT_SIZE_TOO_SMALL,
+                    s!("payment does not cover the opening fee"),
+                ));
+            }
+            if payment_size_msat > fee_params.max_payment_size_msat {
+                return Err((
+                    lsps::LSPS2_PAYMENT_SIZE_TOO_LARGE,
+                    s!("payment exceeds maximal channel capacity"),
+                ));
+            }
+        }
+        // HTLCs are intercepted by their payment hash, since the node does
+        // not process the onion of forwarded payments
+        let payment_hash = params.payment_hash.ok_or_else(|| {
+            (
+                lsps::INVALID_PARAMS,
+                s!("payment_hash is required by this provider"),
+            )
+        })?;
+
+        let jit_offer = JitOffer {
+            scid: ShortChannelId::from(rand::random::<u64>()),
+            cltv_expiry_delta: JIT_CLTV_EXPIRY_DELTA,
+            payment_hash,
+            min_fee_msat: fee_params.min_fee_msat,
+            proportional: fee_params.proportional,
+        };
+        let scid = u64::from(jit_offer.scid);
+        info!(
+            "{} {} for {}",
+            "Just-in-time channel offered with alias".promo(),
+            jit_offer.scid,
+            client.promoter()
+        );
+        // HTLCs of the payment are forwarded to us by lnpd
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::InterceptPayment(PaymentInterception {
+                payment_hash: HashLock::from_inner(payment_hash),
+                scid,
+            }),
+        )
+        .map_err(|err| (lsps::INTERNAL_ERROR, err.to_string()))?;
+        self.fee_params.remove(&fee_params.promise);
+        self.jit_offers.insert(
+            scid,
+            JitSale {
+                client,
+                offer: jit_offer.clone(),
+            },
+        );
+        Ok(lsps::Lsps2BuyResult {
+            jit_channel_scid: jit_offer.scid,
+            lsp_cltv_expiry_delta: jit_offer.cltv_expiry_delta,
+            client_trusts_lsp: false,
+        })
+    }
+
+    /// Checks the order against the provider terms, returning the order fee
+    fn check_order(
+        &self,
+        params: &lsps::Lsps1CreateOrder,
+    ) -> Result<u64, MethodError> {
+        let offer = self.offer.as_ref().expect("checked by the caller");
+        let info = self.lsps1_info();
+        let invalid = |info: String| Err((lsps::INVALID_PARAMS, info));
+        if params.lsp_balance_sat < offer.min_channel_sat
+            || params.lsp_balance_sat > offer.max_channel_sat
+        {
+            return invalid(format!(
+                "lsp_balance_sat must be within {}..{} sat",
+                offer.min_channel_sat, offer.max_channel_sat
+            ));
+        }
+        if params.client_balance_sat != 0 {
+            return invalid(s!("client_balance_sat must be zero"));
+        }
+        if params.required_channel_confirmations
+            < info.min_required_channel_confirmations
+        {
+            return invalid(s!("zero-confirmation channels are not sold"));
+        }
+        if params.funding_confirms_within_blocks
+            < info.min_funding_confirms_within_blocks
+        {
+            return invalid(format!(
+                "funding_confirms_within_blocks must be at least {}",
+                info.min_funding_confirms_within_blocks
+            ));
+        }
+        if params.channel_expiry_blocks > info.max_channel_expiry_blocks {
+            return invalid(format!(
+                "channel_expiry_blocks must not exceed {}",
+                info.max_channel_expiry_blocks
+            ));
+        }
+        if params.token.is_some() {
+            return invalid(s!("tokens are not supported"));
+        }
+        Ok(offer.fee_for(params.lsp_balance_sat))
+    }
+
+    /// Asks `lnpd` for the invoice paying the order fee; `lnpd` notifies us
+    /// once it is paid
+    fn request_invoice(
+        &mut self,
+        senders: &mut Senders,
+        order_request: OrderRequest,
+    ) -> Result<(), Error> {
+        let invoice_req = InvoiceRequest {
+            amount_msat: order_request.fee_sat * 1000,
+            asset_id: None,
+            expiry: ORDER_INVOICE_EXPIRY,
+        };
+        self.lnpd_requests
+            .push_back(LnpdRequest::Invoice(order_request));
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::CreateInvoice(invoice_req),
+        )
+    }
+
+    fn create_order(
+        &mut self,
+        senders: &mut Senders,
+        order_request: OrderRequest,
+        invoice: InvoiceInfo,
+    ) -> Result<(), Error> {
+        let OrderRequest {
+            client,
+            remote_id,
+            request_id,
+            params,
+            fee_sat,
+        } = order_request;
+        let created_at = now();
+        let sold = SoldOrder {
+            order: LspOrder {
+                order_id: random_id(),
+                peer: client.clone(),
+                lsp_balance_sat: params.lsp_balance_sat,
+                zero_conf: params.required_channel_confirmations == 0,
+                fee_sat,
+                payment_hash: invoice.payment_hash.into_inner(),
+                state: LspOrderState::AwaitingPayment,
+            },
+            params,
+            created_at,
+            expires_at: created_at + ORDER_INVOICE_EXPIRY as u64,
+        };
+        info!(
+            "{} {}: {} sat to {} for {} sat fee",
+            "Channel order created".promo(),
+            sold.order.order_id,
+            sold.order.lsp_balance_sat,
+            sold.order.peer.promoter(),
+            sold.order.fee_sat
+        );
+        let reply = JsonRpcResponse::result(request_id, sold.to_lsps());
+        self.orders.insert(sold.order.order_id.clone(), sold);
+        self.send_lsps(senders, client, remote_id, reply)
+    }
+
+    fn open_channel(
+        &mut self,
+        senders: &mut Senders,
+        order_id: String,
+    ) -> Result<(), Error> {
+        let order = &mut self
+            .orders
+            .get_mut(&order_id)
+            .expect("order presence is checked by the caller")
+            .order;
+        order.state = LspOrderState::Opening;
+        info!(
+            "{} {} is paid; {} to {}",
+            "Channel order".promo(),
+            order_id,
+            "opening channel".promo(),
+            order.peer.promoter()
+        );
+        let client = order.peer.clone();
+        let funding_satoshis = order.lsp_balance_sat;
+        let zero_conf = order.zero_conf;
+        self.opening.push_back(Opening::Order(order_id));
+        self.request_channel(senders, client, funding_satoshis, zero_conf)
+    }
+
+    fn open_jit_channel(
+        &mut self,
+        senders: &mut Senders,
+        sale: JitSale,
+        channeld: ServiceId,
+        htlc: InterceptedHtlc,
+    ) -> Result<(), String> {
+        let offer = self
+            .offer
+            .as_ref()
+            .expect("just-in-time channels are opened only by providers");
+        let JitSale {
+            client,
+            offer: jit_offer,
+        } = sale;
+        if htlc.payment_hash.into_inner() != jit_offer.payment_hash {
+            return Err(format!(
+                "Payment {} is not the one of the just-in-time channel",
+                htlc.payment_hash
+            ));
+        }
+        let fee_msat = jit_offer.fee_for(htlc.amount_msat);
+        if htlc.amount_msat <= fee_msat {
+            return Err(format!(
+                "Payment amount {} msat does not cover just-in-time channel \
+                 fee {} msat",
+                htlc.amount_msat, fee_msat
+            ));
+        }
+        let funding_satoshis =
+            offer.min_channel_sat.max(htlc.amount_msat / 1000 + 1);
+        if funding_satoshis > offer.max_channel_sat {
+            return Err(format!(
+                "Payment amount {} msat exceeds maximal channel capacity",
+                htlc.amount_msat
+            ));
+        }
+
+        info!(
+            "{} {} to {} for {}",
+            "Opening just-in-time channel".promo(),
+            funding_satoshis,
+            client.promoter(),
+            htlc
+        );
+        self.opening.push_back(Opening::Jit(JitForward {
+            channeld,
+            htlc,
+            fee_msat,
+        }));
+        self.request_channel(senders, client, funding_satoshis, true)
+            .map_err(|err| err.to_string())
+    }
+
+    /// Fails the HTLC forwarded to the just-in-time channel, or fulfills it
+    /// with the preimage revealed by the client
+    fn settle_forward(
+        &mut self,
+        senders: &mut Senders,
+        forward: JitForward,
+        preimage: Option<HashPreimage>,
+    ) -> Result<(), Error> {
+        if preimage.is_none() {
+            warn!(
+                "{} {}",
+                "Failing just-in-time channel payment".err(),
+                forward.htlc
+            );
+        } else {
+            self.send_ctl(
+                senders,
+                ServiceId::Lnpd,
+                Request::BookEvent(AccountingEvent {
+                    kind: AccountingEventKind::PaymentForwarded,
+                    channel_id: None,
+                    txid: None,
+                    asset: None,
+                    amount_msat: forward.htlc.amount_msat - forward.fee_msat,
+                    fee_msat: forward.fee_msat,
+                    description: format!(
+                        "Forwarding of {} into just-in-time channel",
+                        forward.htlc.payment_hash
+                    ),
+                }),
+            )?;
+        }
+        self.send_ctl(
+            senders,
+            forward.channeld,
+            Request::HtlcSettlement(HtlcSettlement {
+                htlc_id: forward.htlc.htlc_id,
+                preimage,
+            }),
+        )
+    }
+
+    fn request_channel(
+        &mut self,
+        senders: &mut Senders,
+        client: NodeAddr,
+        funding_satoshis: u64,
+        zero_conf: bool,
+    ) -> Result<(), Error> {
+        let request = Request::OpenChannelWith(CreateChannel {
+            channel_req: message::OpenChannel {
+                funding_satoshis,
+                // The rest of parameters will be filled in by the daemon
+                ..dumb!()
+            },
+            peerd: ServiceId::Peer(client),
+            report_to: Some(self.identity()),
+            shutdown_scriptpubkey: None,
+            // Sold channels are funded by the provider wallet, such that they
+            // are opened right after the payment
+            fund_from_wallet: true,
+            minimum_depth: if zero_conf { Some(0) } else { None },
+            funding_batch: None,
+            private: false,
+            remote_tlvs: empty!(),
+        });
+        self.lnpd_requests.push_back(LnpdRequest::Channel);
+        self.send_ctl(senders, ServiceId::Lnpd, request)
+    }
+}
@@ -12,11 +12,13 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use bitcoin::Address;
 use clap::{Clap, ValueHint};
 use std::fs;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
+use internet2::addr::InetSocketAddr;
 use internet2::PartialNodeAddr;
 use lnpbp::Chain;
 use microservices::shell::LogLevel;
@@ -68,7 +70,9 @@ pub struct Opts {
     /// Data directory path
     ///
     /// Path to the directory that contains LNP Node data, and where ZMQ RPC
-    /// socket files are located
+    /// socket files are located. Data for each chain are kept in a separate
+    /// subdirectory named after the chain, such that nodes for different
+    /// chains may run in parallel without sharing keys and service buses
     #[clap(
         short,
         long,
@@ -157,6 +161,359 @@ pub struct Opts {
     // TODO: Put it back to `signet` default network once rust-bitcoin will
     //       release signet support
     pub chain: Chain,
+
+    /// Human-readable name of the node
+    #[clap(long, global = true, env = "LNP_NODE_ALIAS")]
+    pub alias: Option<String>,
+
+    /// Color of the node announced to the network, in `rrggbb` hex form
+    ///
+    /// By default, the color is derived from the node id.
+    #[clap(
+        long,
+        global = true,
+        env = "LNP_NODE_RGB_COLOR",
+        parse(try_from_str = parse_rgb_color)
+    )]
+    pub rgb_color: Option<[u8; 3]>,
+
+    /// Address announced to the network for connecting to the node
+    ///
+    /// May be given multiple times. Onion service published by the node is
+    /// announced in addition to these addresses.
+    #[clap(
+        long = "announce-addr",
+        global = true,
+        env = "LNP_NODE_ANNOUNCE_ADDRS",
+        value_hint = ValueHint::Hostname
+    )]
+    pub announce_addrs: Vec<InetSocketAddr>,
+
+    /// Minimal feerate of channel commitment transactions
+    ///
+    /// Feerate is given in satoshis per kiloweight. Channels are failed if
+    /// the remote peer proposes lower feerate with `update_fee` message.
+    #[clap(
+        long,
+        global = true,
+        default_value = "253",
+        env = "LNP_NODE_MIN_FEERATE"
+    )]
+    pub min_feerate: u32,
+
+    /// Maximal feerate of channel commitment transactions
+    ///
+    /// Feerate is given in satoshis per kiloweight. Channels are failed if
+    /// the remote peer proposes higher feerate with `update_fee` message.
+    #[clap(
+        long,
+        global = true,
+        default_value = "100000",
+        env = "LNP_NODE_MAX_FEERATE"
+    )]
+    pub max_feerate: u32,
+
+    /// Initial feerate of commitment transactions of channels opened by the
+    /// node
+    ///
+    /// Feerate is given in satoshis per kiloweight and is kept within
+    /// `--min-feerate` and `--max-feerate` range.
+    #[clap(
+        long,
+        global = true,
+        default_value = "2500",
+        env = "LNP_NODE_FEERATE"
+    )]
+    pub feerate: u32,
+
+    /// Use anchor outputs commitment format for new channels
+    ///
+    /// Commitment transactions of such channels have two small outputs which
+    /// allow to bump their fee with CPFP, so the commitment feerate is not
+    /// raised following fee spikes.
+    #[clap(long, global = true, env = "LNP_NODE_ANCHORS")]
+    pub anchors: bool,
+
+    /// Do not use `option_static_remotekey` for new channels
+    ///
+    /// Channels will derive `to_remote` key for each of the commitments, so
+    /// the funds of a remote force-close can't be swept without knowing the
+    /// per-commitment point. Ignored if `--anchors` is given.
+    #[clap(long, global = true, env = "LNP_NODE_NO_STATIC_REMOTEKEY")]
+    pub no_static_remotekey: bool,
+
+    /// Accept zero-conf channels
+    ///
+    /// Channels of `option_zeroconf` type funded by remote peers become
+    /// usable right after funding signatures are exchanged, without waiting
+    /// for confirmations. The remote peer may double-spend the funding
+    /// transaction, so use with trusted peers only.
+    #[clap(long, global = true, env = "LNP_NODE_ZERO_CONF")]
+    pub zero_conf: bool,
+
+    /// Minimal dust limit, in satoshis, accepted from remote peers
+    #[clap(
+        long,
+        global = true,
+        default_value = "354",
+        env = "LNP_NODE_MIN_DUST_LIMIT"
+    )]
+    pub min_dust_limit: u64,
+
+    /// Maximal dust limit, in satoshis, accepted from remote peers
+    #[clap(
+        long,
+        global = true,
+        default_value = "10000",
+        env = "LNP_NODE_MAX_DUST_LIMIT"
+    )]
+    pub max_dust_limit: u64,
+
+    /// Dust limit, in satoshis, of our commitment transactions in channels
+    /// opened by the node
+    #[clap(
+        long,
+        global = true,
+        default_value = "546",
+        env = "LNP_NODE_DUST_LIMIT"
+    )]
+    pub dust_limit: u64,
+
+    /// Delay, in blocks, we require before the remote peer can spend its
+    /// funds from a channel it has force-closed
+    #[clap(
+        long,
+        global = true,
+        default_value = "144",
+        env = "LNP_NODE_TO_SELF_DELAY"
+    )]
+    pub to_self_delay: u16,
+
+    /// Maximal number of HTLCs remote peers may offer to us in a channel
+    #[clap(
+        long,
+        global = true,
+        default_value = "483",
+        env = "LNP_NODE_MAX_ACCEPTED_HTLCS"
+    )]
+    pub max_accepted_htlcs: u16,
+
+    /// Maximal delay, in blocks, remote peers may require before we can
+    /// spend our funds from a force-closed channel
+    #[clap(
+        long,
+        global = true,
+        default_value = "2016",
+        env = "LNP_NODE_MAX_TO_SELF_DELAY"
+    )]
+    pub max_to_self_delay: u16,
+
+    /// Minimal funding amount, in satoshis, of channels opened by remote
+    /// peers
+    #[clap(
+        long,
+        global = true,
+        default_value = "20000",
+        env = "LNP_NODE_MIN_CHANNEL_SIZE"
+    )]
+    pub min_channel_size: u64,
+
+    /// Channel reserve, in percents of the channel capacity, which we
+    /// require from remote peers
+    ///
+    /// Remote peers requiring larger reserve from us are rejected.
+    #[clap(
+        long,
+        global = true,
+        default_value = "1",
+        env = "LNP_NODE_CHANNEL_RESERVE"
+    )]
+    pub channel_reserve: u8,
+
+    /// Minimal number of funding transaction confirmations required for
+    /// channels opened by remote peers
+    #[clap(
+        long,
+        global = true,
+        default_value = "3",
+        env = "LNP_NODE_MIN_DEPTH"
+    )]
+    pub min_depth: u32,
+
+    /// Maximal number of funding transaction confirmations
+    ///
+    /// Required depth of channels opened by remote peers does not grow
+    /// above it; channels requiring more confirmations from us are rejected.
+    #[clap(
+        long,
+        global = true,
+        default_value = "144",
+        env = "LNP_NODE_MAX_DEPTH"
+    )]
+    pub max_depth: u32,
+
+    /// Funding amount, in satoshis, for each of which channels opened by
+    /// remote peers require one more confirmation above `--min-depth`
+    #[clap(
+        long,
+        global = true,
+        default_value = "1000000",
+        env = "LNP_NODE_DEPTH_STEP"
+    )]
+    pub depth_step: u64,
+
+    /// Support large channels (`option_support_large_channel`)
+    ///
+    /// Without this option channel funding is limited to 2^24 satoshis.
+    #[clap(long, global = true, env = "LNP_NODE_WUMBO")]
+    pub wumbo: bool,
+
+    /// Maximal funding amount, in satoshis, of large channels
+    ///
+    /// Used only with `--wumbo`.
+    #[clap(
+        long,
+        global = true,
+        default_value = "1000000000",
+        env = "LNP_NODE_MAX_WUMBO_SIZE"
+    )]
+    pub max_wumbo_size: u64,
+
+    /// Time, in seconds, after which negotiation of a new channel is aborted
+    /// if none of the funding signatures is exchanged
+    #[clap(
+        long,
+        global = true,
+        default_value = "600",
+        env = "LNP_NODE_NEGOTIATION_TIMEOUT"
+    )]
+    pub negotiation_timeout: u64,
+
+    /// Interval, in milliseconds, at which gossip messages queued for the
+    /// remote peers are sent
+    ///
+    /// Channel messages are sent immediately, ahead of the queued gossip.
+    #[clap(
+        long,
+        global = true,
+        default_value = "200",
+        env = "LNP_NODE_GOSSIP_FLUSH_INTERVAL"
+    )]
+    pub gossip_flush_interval: u64,
+
+    /// Interval, in seconds, at which the validated gossip is relayed to
+    /// the remote peers
+    ///
+    /// Gossip received within the interval is deduplicated, so only the
+    /// latest update of each channel direction and node is relayed.
+    #[clap(
+        long,
+        global = true,
+        default_value = "60",
+        env = "LNP_NODE_GOSSIP_BROADCAST_INTERVAL"
+    )]
+    pub gossip_broadcast_interval: u64,
+
+    /// Number of gossip messages per minute exchanged with each of the
+    /// remote peers
+    ///
+    /// Gossip received over the limit is dropped, and gossip sent over the
+    /// limit is delayed. Zero value disables the limit.
+    #[clap(
+        long,
+        global = true,
+        default_value = "6000",
+        env = "LNP_NODE_GOSSIP_RATE_LIMIT"
+    )]
+    pub gossip_rate_limit: u32,
+
+    /// Number of pings per minute replied to each of the remote peers
+    ///
+    /// Zero value disables the limit.
+    #[clap(
+        long,
+        global = true,
+        default_value = "10",
+        env = "LNP_NODE_PING_RATE_LIMIT"
+    )]
+    pub ping_rate_limit: u32,
+
+    /// Number of blocks after which channels funded by remote peers are
+    /// closed if the funding transaction is not confirmed; their state is
+    /// kept in case the transaction gets mined later
+    #[clap(
+        long,
+        global = true,
+        default_value = "2016",
+        env = "LNP_NODE_FUNDING_TIMEOUT"
+    )]
+    pub funding_timeout: u32,
+
+    /// Address receiving our funds on cooperative close of new channels
+    ///
+    /// The address is committed to the channel as its upfront shutdown
+    /// script, so the channels can't be closed to any other address.
+    #[clap(long, global = true, env = "LNP_NODE_SHUTDOWN_ADDRESS")]
+    pub shutdown_address: Option<Address>,
+
+    /// Keep channel state in embedded key-value database
+    ///
+    /// If set, specifies directory of the database; by default channel state
+    /// is kept in files inside `--data-dir` directory. If the argument is
+    /// provided in form of flag, without value, uses `kv` subdirectory of
+    /// `--data-dir`.
+    #[clap(
+        long,
+        global = true,
+        env = "LNP_NODE_KV_STORAGE",
+        value_hint = ValueHint::DirPath
+    )]
+    pub kv_storage: Option<Option<PathBuf>>,
+
+    /// Fixed fee, in millisatoshis, charged for forwarding payments over
+    /// new channels
+    #[clap(
+        long,
+        global = true,
+        default_value = "1000",
+        env = "LNP_NODE_FEE_BASE_MSAT"
+    )]
+    pub fee_base_msat: u32,
+
+    /// Proportional fee, in millionths of the forwarded amount, charged for
+    /// forwarding payments over new channels
+    #[clap(
+        long,
+        global = true,
+        default_value = "1",
+        env = "LNP_NODE_FEE_PROPORTIONAL_MILLIONTHS"
+    )]
+    pub fee_proportional_millionths: u32,
+
+    /// Number of blocks subtracted from the expiry of HTLCs forwarded over
+    /// new channels
+    #[clap(
+        long,
+        global = true,
+        default_value = "40",
+        env = "LNP_NODE_CLTV_EXPIRY_DELTA"
+    )]
+    pub cltv_expiry_delta: u16,
+
+    /// Minimal amount, in millisatoshis, of HTLCs forwarded over new
+    /// channels
+    #[clap(
+        long,
+        global = true,
+        default_value = "1",
+        env = "LNP_NODE_HTLC_MINIMUM_MSAT"
+    )]
+    pub htlc_minimum_msat: u64,
+
+    /// Maximal amount, in millisatoshis, of HTLCs forwarded over new
+    /// channels; by default limited by the channel capacity only
+    #[clap(long, global = true, env = "LNP_NODE_HTLC_MAXIMUM_MSAT")]
+    pub htlc_maximum_msat: Option<u64>,
 }
 
 impl Opts {
@@ -168,6 +525,7 @@ impl Opts {
             shellexpand::tilde(&me.data_dir.to_string_lossy().to_string())
                 .to_string(),
         );
+        me.data_dir.push(me.chain.to_string());
         fs::create_dir_all(&me.data_dir)
             .expect("Unable to access data directory");
 
@@ -180,6 +538,8 @@ impl Opts {
                 _ => {}
             }
         }
+
+        self.data_dir = me.data_dir;
     }
 
     pub fn process_dir(&self, path: &mut String) {
@@ -187,3 +547,18 @@ impl Opts {
         *path = shellexpand::tilde(path).to_string();
     }
 }
+
+/// Parses RGB color given in `rrggbb` hex form, optionally prefixed with `#`
+fn parse_rgb_color(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.trim_start_matches('#');
+    let err = || format!("invalid RGB color `{}`", s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(err());
+    }
+    let mut color = [0u8; 3];
+    for (pos, byte) in color.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[pos * 2..pos * 2 + 2], 16)
+            .map_err(|_| err())?;
+    }
+    Ok(color)
+}
@@ -0,0 +1,46 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Persistence of the remote peer access lists. The lists are kept in a
+//! file shared by `lnpd`, which modifies them, and the listening `peerd`
+//! instances, which re-read them for each incoming connection.
+
+use std::fs;
+use std::path::Path;
+
+use lnpbp::strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::rpc::request::PeerAccess;
+use crate::Error;
+
+/// Reads peer access lists; absent file means no restrictions
+pub fn load_access(path: &Path) -> Result<PeerAccess, Error> {
+    if !path.exists() {
+        return Ok(PeerAccess::default());
+    }
+    PeerAccess::strict_decode(fs::File::open(path)?).map_err(|err| {
+        Error::Other(format!("Peer access lists are corrupted: {}", err))
+    })
+}
+
+/// Writes peer access lists, replacing the file atomically such that
+/// listeners never read partially written lists
+pub fn save_access(path: &Path, access: &PeerAccess) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    access
+        .strict_encode(fs::File::create(&tmp_path)?)
+        .map_err(|err| Error::Other(err.to_string()))?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
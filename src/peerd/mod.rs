@@ -12,10 +12,25 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+mod access;
+mod noise;
 #[cfg(feature = "shell")]
 mod opts;
+mod ratelimit;
 mod runtime;
+mod socks;
+mod throttle;
+mod websocket;
 
+pub use access::{load_access, save_access};
+pub use noise::{CustomChannel, NoiseError};
 #[cfg(feature = "shell")]
 pub use opts::{KeyOpts, Opts};
-pub use runtime::run;
+pub use ratelimit::RateLimiter;
+pub use runtime::{
+    accept, admits_connection, connect, listener_addr, run, run_listener,
+    MAX_HANDSHAKES_PER_MINUTE,
+};
+pub use socks::SocksError;
+pub use throttle::{MessageClass, Throttle};
+pub use websocket::WsError;
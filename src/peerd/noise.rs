@@ -0,0 +1,924 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Encrypted and authenticated transport of the lightning peer connections
+//! (BOLT-8): Noise_XK handshake over secp256k1 with ChaCha20-Poly1305 and
+//! SHA-256.
+//!
+//! Encrypted stream is bridged to a loopback TCP socket carrying plaintext
+//! messages prefixed with their length, which is the framing of unencrypted
+//! peer sessions, so the session runs over it in the same way as over the
+//! ordinary TCP connection. Messages of custom types, unknown to the session,
+//! are relayed around it through [`CustomChannel`], as are the TLV streams
+//! extending the messages known to the session.
+//!
+//! Messages sent by the session are queued, and all of the queued messages
+//! are encrypted and written to the socket at once. Gossip is written only
+//! after the rest of the queued messages, so HTLC updates and other channel
+//! messages are not delayed by gossip bursts.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use amplify::IoError;
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoin::secp256k1::rand;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::rpc::request::{is_custom_type, CustomMessage};
+use crate::rpc::tlv;
+
+const PROTOCOL_NAME: &[u8] = b"Noise_XK_secp256k1_ChaChaPoly_SHA256";
+const PROLOGUE: &[u8] = b"lightning";
+
+const ACT_ONE_LEN: usize = 50;
+const ACT_TWO_LEN: usize = 50;
+const ACT_THREE_LEN: usize = 66;
+const MAC_LEN: usize = 16;
+
+/// Time in which the remote peer must send each of the handshake acts
+const ACT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of encryptions or decryptions after which the key is rotated; each
+/// message takes two of them, for the length and for the body
+const KEY_ROTATION_INTERVAL: u64 = 1000;
+
+/// Lightning messages consist of at least two-byte type and can't exceed
+/// 65535 bytes
+const MIN_MESSAGE_LEN: usize = 2;
+const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Size of the length prefix of the plaintext messages in the loopback
+/// stream
+const FRAME_PREFIX_LEN: usize = 2;
+
+/// Size of the queued messages after which they are written to the socket,
+/// even if more messages are queued
+const MAX_WRITE_LEN: usize = 65536;
+
+/// Serialized TLV streams of the extensible messages (see
+/// [`tlv::is_extensible`])
+/// in the order of the messages
+pub type TlvQueue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// Channels relaying messages of custom types between the remote peer and
+/// the connection runtime
+pub struct CustomChannel {
+    /// Custom messages received from the remote peer
+    pub incoming: Receiver<CustomMessage>,
+    /// Custom messages to be sent to the remote peer once the given number
+    /// of the session messages is sent, so the order of the messages is
+    /// kept
+    pub outgoing: Sender<(u64, CustomMessage)>,
+    /// TLV streams cut from the extensible messages received from the remote
+    /// peer; the stream is queued before the message is passed to the
+    /// session
+    pub incoming_tlvs: TlvQueue,
+    /// TLV streams to be appended to the extensible messages sent by the
+    /// session; the stream must be queued before the message is sent
+    pub outgoing_tlvs: TlvQueue,
+}
+
+/// Encrypted stream shared by the session and the custom messages
+struct SharedWriter {
+    stream: TcpStream,
+    sender: CipherState,
+    /// Number of the session messages sent to the remote peer; the maximal
+    /// value means the session is stopped
+    session_sent: u64,
+}
+
+/// Messages sent by the session, awaiting to be written to the remote peer.
+/// Each of the queues keeps the order of its messages.
+#[derive(Default)]
+struct OutgoingQueue {
+    messages: VecDeque<Vec<u8>>,
+    gossip: VecDeque<Vec<u8>>,
+    /// Session has stopped, so no more messages will be queued
+    closed: bool,
+}
+
+impl OutgoingQueue {
+    fn push(&mut self, message: Vec<u8>) {
+        match message.get(..2) {
+            Some(&[hi, lo]) if is_gossip_type(u16::from_be_bytes([hi, lo])) => {
+                self.gossip.push_back(message)
+            }
+            _ => self.messages.push_back(message),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.gossip.is_empty()
+    }
+
+    /// Takes the messages to be written at once, gossip going last
+    fn take_batch(&mut self) -> Vec<Vec<u8>> {
+        let mut batch = vec![];
+        let mut len = 0;
+        while len < MAX_WRITE_LEN {
+            let message = match self.messages.pop_front() {
+                Some(message) => message,
+                None => match self.gossip.pop_front() {
+                    Some(message) => message,
+                    None => break,
+                },
+            };
+            len += message.len();
+            batch.push(message);
+        }
+        batch
+    }
+}
+
+/// Checks whether the message type belongs to gossip (BOLT-7); this excludes
+/// `announcement_signatures`, which is exchanged over the channel
+fn is_gossip_type(msg_type: u16) -> bool {
+    matches!(msg_type, 256..=258 | 261..=265)
+}
+
+/// Errors of the encrypted peer connections
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum NoiseError {
+    /// I/O error in encrypted peer connection: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// remote peer has not sent act {0} of the handshake in time
+    ActTimeout(u8),
+
+    /// act {0} of the handshake has unknown version {1}
+    UnknownVersion(u8, u8),
+
+    /// act {0} of the handshake contains invalid public key
+    InvalidPublicKey(u8),
+
+    /// act {0} of the handshake fails authentication
+    InvalidMac(u8),
+
+    /// encrypted message fails authentication
+    MessageMac,
+
+    /// message of {0} bytes is too short
+    MessageTooShort(usize),
+
+    /// message of {0} bytes exceeds the maximal length
+    MessageTooLarge(usize),
+
+    /// loopback bridge is hijacked
+    BridgeHijacked,
+}
+
+/// Performs the initiator side of the handshake with the remote node,
+/// returning the plaintext loopback stream and the channel of custom messages
+pub fn initiate(
+    mut stream: TcpStream,
+    local_key: &SecretKey,
+    remote_id: PublicKey,
+) -> Result<(TcpStream, CustomChannel), NoiseError> {
+    trace!("Sending act one of the handshake");
+    let ephemeral = SecretKey::new(&mut rand::thread_rng());
+    let (initiator, act) = Initiator::start(&remote_id, ephemeral);
+    stream.write_all(&act)?;
+
+    trace!("Awaiting act two of the handshake");
+    let mut act = [0u8; ACT_TWO_LEN];
+    read_act(&mut stream, 2, &mut act)?;
+
+    trace!("Sending act three of the handshake");
+    let (act, sender, receiver) = initiator.finish(local_key, &act)?;
+    stream.write_all(&act)?;
+
+    bridge(stream, sender, receiver, remote_id)
+}
+
+/// Performs the responder side of the handshake with the remote node,
+/// returning the plaintext loopback stream, the channel of custom messages
+/// and the node id of the remote peer
+pub fn respond(
+    mut stream: TcpStream,
+    local_key: &SecretKey,
+) -> Result<(TcpStream, CustomChannel, PublicKey), NoiseError> {
+    trace!("Awaiting act one of the handshake");
+    let mut act = [0u8; ACT_ONE_LEN];
+    read_act(&mut stream, 1, &mut act)?;
+
+    trace!("Sending act two of the handshake");
+    let ephemeral = SecretKey::new(&mut rand::thread_rng());
+    let (responder, reply) = Responder::start(local_key, ephemeral, &act)?;
+    stream.write_all(&reply)?;
+
+    trace!("Awaiting act three of the handshake");
+    let mut act = [0u8; ACT_THREE_LEN];
+    read_act(&mut stream, 3, &mut act)?;
+    let (remote_id, sender, receiver) = responder.finish(&act)?;
+
+    let (stream, custom) = bridge(stream, sender, receiver, remote_id)?;
+    Ok((stream, custom, remote_id))
+}
+
+/// Initiator side of the handshake which has sent act one
+struct Initiator {
+    state: SymmetricState,
+    ephemeral: SecretKey,
+}
+
+impl Initiator {
+    /// Starts the handshake with the given ephemeral key, returning act one
+    fn start(remote_id: &PublicKey, ephemeral: SecretKey) -> (Self, Vec<u8>) {
+        let secp = Secp256k1::signing_only();
+        let mut state = SymmetricState::with(remote_id);
+        let ephemeral_pub = PublicKey::from_secret_key(&secp, &ephemeral);
+
+        state.mix_hash(&ephemeral_pub.serialize());
+        let temp_k1 = state.mix_key(&ecdh(remote_id, &ephemeral));
+        let mut act = vec![0u8];
+        act.extend(&ephemeral_pub.serialize());
+        act.extend(state.encrypt_and_hash(&temp_k1, 0, &[]));
+        (Initiator { state, ephemeral }, act)
+    }
+
+    /// Processes act two, returning act three together with the sending and
+    /// receiving keys
+    fn finish(
+        mut self,
+        local_key: &SecretKey,
+        act: &[u8],
+    ) -> Result<(Vec<u8>, CipherState, CipherState), NoiseError> {
+        let secp = Secp256k1::signing_only();
+        let state = &mut self.state;
+        let remote_ephemeral = PublicKey::from_slice(&act[1..34])
+            .map_err(|_| NoiseError::InvalidPublicKey(2))?;
+        state.mix_hash(&act[1..34]);
+        let temp_k2 = state.mix_key(&ecdh(&remote_ephemeral, &self.ephemeral));
+        state.decrypt_and_hash(&temp_k2, 0, &act[34..], 2)?;
+
+        let local_id = PublicKey::from_secret_key(&secp, local_key);
+        let mut act = vec![0u8];
+        act.extend(state.encrypt_and_hash(&temp_k2, 1, &local_id.serialize()));
+        let temp_k3 = state.mix_key(&ecdh(&remote_ephemeral, local_key));
+        act.extend(encrypt(&temp_k3, 0, &state.h, &[]));
+
+        let (sender, receiver) = self.state.split();
+        Ok((act, sender, receiver))
+    }
+}
+
+/// Responder side of the handshake which has sent act two
+struct Responder {
+    state: SymmetricState,
+    ephemeral: SecretKey,
+    temp_k2: [u8; 32],
+}
+
+impl Responder {
+    /// Processes act one, returning act two produced with the given
+    /// ephemeral key
+    fn start(
+        local_key: &SecretKey,
+        ephemeral: SecretKey,
+        act: &[u8],
+    ) -> Result<(Self, Vec<u8>), NoiseError> {
+        let secp = Secp256k1::signing_only();
+        let local_id = PublicKey::from_secret_key(&secp, local_key);
+        let mut state = SymmetricState::with(&local_id);
+
+        let remote_ephemeral = PublicKey::from_slice(&act[1..34])
+            .map_err(|_| NoiseError::InvalidPublicKey(1))?;
+        state.mix_hash(&act[1..34]);
+        let temp_k1 = state.mix_key(&ecdh(&remote_ephemeral, local_key));
+        state.decrypt_and_hash(&temp_k1, 0, &act[34..], 1)?;
+
+        let ephemeral_pub = PublicKey::from_secret_key(&secp, &ephemeral);
+        state.mix_hash(&ephemeral_pub.serialize());
+        let temp_k2 = state.mix_key(&ecdh(&remote_ephemeral, &ephemeral));
+        let mut reply = vec![0u8];
+        reply.extend(&ephemeral_pub.serialize());
+        reply.extend(state.encrypt_and_hash(&temp_k2, 0, &[]));
+        Ok((
+            Responder {
+                state,
+                ephemeral,
+                temp_k2,
+            },
+            reply,
+        ))
+    }
+
+    /// Processes act three, returning node id of the remote peer together
+    /// with the sending and receiving keys
+    fn finish(
+        mut self,
+        act: &[u8],
+    ) -> Result<(PublicKey, CipherState, CipherState), NoiseError> {
+        let state = &mut self.state;
+        let remote_key =
+            state.decrypt_and_hash(&self.temp_k2, 1, &act[1..50], 3)?;
+        let remote_id = PublicKey::from_slice(&remote_key)
+            .map_err(|_| NoiseError::InvalidPublicKey(3))?;
+        let temp_k3 = state.mix_key(&ecdh(&remote_id, &self.ephemeral));
+        decrypt(&temp_k3, 0, &state.h, &act[50..])
+            .ok_or(NoiseError::InvalidMac(3))?;
+
+        let (receiver, sender) = self.state.split();
+        Ok((remote_id, sender, receiver))
+    }
+}
+
+/// Handshake state shared by both parties
+struct SymmetricState {
+    /// Chaining key
+    ck: [u8; 32],
+    /// Handshake hash
+    h: [u8; 32],
+}
+
+impl SymmetricState {
+    fn with(responder_id: &PublicKey) -> SymmetricState {
+        let h = sha256::Hash::hash(PROTOCOL_NAME).into_inner();
+        let mut state = SymmetricState { ck: h, h };
+        state.mix_hash(PROLOGUE);
+        state.mix_hash(&responder_id.serialize());
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.h);
+        engine.input(data);
+        self.h = sha256::Hash::from_engine(engine).into_inner();
+    }
+
+    /// Mixes the shared secret into the chaining key, returning the
+    /// temporary key
+    fn mix_key(&mut self, secret: &[u8]) -> [u8; 32] {
+        let (ck, temp_k) = hkdf(&self.ck, secret);
+        self.ck = ck;
+        temp_k
+    }
+
+    fn encrypt_and_hash(
+        &mut self,
+        key: &[u8; 32],
+        nonce: u64,
+        plaintext: &[u8],
+    ) -> Vec<u8> {
+        let ciphertext = encrypt(key, nonce, &self.h, plaintext);
+        self.mix_hash(&ciphertext);
+        ciphertext
+    }
+
+    fn decrypt_and_hash(
+        &mut self,
+        key: &[u8; 32],
+        nonce: u64,
+        ciphertext: &[u8],
+        act: u8,
+    ) -> Result<Vec<u8>, NoiseError> {
+        let plaintext = decrypt(key, nonce, &self.h, ciphertext)
+            .ok_or(NoiseError::InvalidMac(act))?;
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    /// Derives keys of the initiator and of the responder
+    fn split(self) -> (CipherState, CipherState) {
+        let (k1, k2) = hkdf(&self.ck, &[]);
+        (
+            CipherState::with(self.ck, k1),
+            CipherState::with(self.ck, k2),
+        )
+    }
+}
+
+/// Key of a single direction of the connection, rotated as it is used
+struct CipherState {
+    ck: [u8; 32],
+    key: [u8; 32],
+    nonce: u64,
+}
+
+impl CipherState {
+    fn with(ck: [u8; 32], key: [u8; 32]) -> CipherState {
+        CipherState { ck, key, nonce: 0 }
+    }
+
+    fn encrypt(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = encrypt(&self.key, self.nonce, &[], plaintext);
+        self.advance();
+        ciphertext
+    }
+
+    fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        let plaintext = decrypt(&self.key, self.nonce, &[], ciphertext)
+            .ok_or(NoiseError::MessageMac)?;
+        self.advance();
+        Ok(plaintext)
+    }
+
+    fn advance(&mut self) {
+        self.nonce += 1;
+        if self.nonce == KEY_ROTATION_INTERVAL {
+            let (ck, key) = hkdf(&self.ck, &self.key);
+            self.ck = ck;
+            self.key = key;
+            self.nonce = 0;
+        }
+    }
+}
+
+fn hkdf(salt: &[u8; 32], ikm: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut engine = HmacEngine::<sha256::Hash>::new(salt);
+    engine.input(ikm);
+    let prk = Hmac::<sha256::Hash>::from_engine(engine);
+
+    let mut engine = HmacEngine::<sha256::Hash>::new(&prk[..]);
+    engine.input(&[1u8]);
+    let t1 = Hmac::<sha256::Hash>::from_engine(engine);
+
+    let mut engine = HmacEngine::<sha256::Hash>::new(&prk[..]);
+    engine.input(&t1[..]);
+    engine.input(&[2u8]);
+    let t2 = Hmac::<sha256::Hash>::from_engine(engine);
+
+    (t1.into_inner(), t2.into_inner())
+}
+
+/// SHA-256 of the compressed shared point
+fn ecdh(remote: &PublicKey, local: &SecretKey) -> [u8; 32] {
+    let shared = SharedSecret::new(remote, local);
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&shared[..]);
+    secret
+}
+
+fn nonce(n: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&n.to_le_bytes());
+    nonce
+}
+
+fn encrypt(key: &[u8; 32], n: u64, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(
+            Nonce::from_slice(&nonce(n)),
+            Payload {
+                msg: plaintext,
+                aad: ad,
+            },
+        )
+        .expect("ChaCha20Poly1305 encryption does not fail")
+}
+
+fn decrypt(
+    key: &[u8; 32],
+    n: u64,
+    ad: &[u8],
+    ciphertext: &[u8],
+) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(
+            Nonce::from_slice(&nonce(n)),
+            Payload {
+                msg: ciphertext,
+                aad: ad,
+            },
+        )
+        .ok()
+}
+
+/// Reads the handshake act, which must arrive in full within the act timeout
+fn read_act(
+    stream: &mut TcpStream,
+    act: u8,
+    buf: &mut [u8],
+) -> Result<(), NoiseError> {
+    let deadline = Instant::now() + ACT_TIMEOUT;
+    let mut read = 0;
+    while read < buf.len() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            return Err(NoiseError::ActTimeout(act));
+        }
+        stream.set_read_timeout(Some(left))?;
+        match stream.read(&mut buf[read..]) {
+            Ok(0) => {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+            }
+            Ok(len) => read += len,
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Err(NoiseError::ActTimeout(act))
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    stream.set_read_timeout(None)?;
+    if buf[0] != 0 {
+        return Err(NoiseError::UnknownVersion(act, buf[0]));
+    }
+    Ok(())
+}
+
+fn read_message(
+    reader: &mut impl Read,
+    cipher: &mut CipherState,
+) -> Result<Vec<u8>, NoiseError> {
+    let mut header = [0u8; 2 + MAC_LEN];
+    reader.read_exact(&mut header)?;
+    let len = cipher.decrypt(&header)?;
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    if len < MIN_MESSAGE_LEN {
+        return Err(NoiseError::MessageTooShort(len));
+    }
+    let mut body = vec![0u8; len + MAC_LEN];
+    reader.read_exact(&mut body)?;
+    cipher.decrypt(&body)
+}
+
+fn write_message(
+    writer: &mut impl Write,
+    cipher: &mut CipherState,
+    message: &[u8],
+) -> Result<(), NoiseError> {
+    if message.len() < MIN_MESSAGE_LEN {
+        return Err(NoiseError::MessageTooShort(message.len()));
+    }
+    if message.len() > MAX_MESSAGE_LEN {
+        return Err(NoiseError::MessageTooLarge(message.len()));
+    }
+    let mut packet = cipher.encrypt(&(message.len() as u16).to_be_bytes());
+    packet.extend(cipher.encrypt(message));
+    writer.write_all(&packet)?;
+    Ok(())
+}
+
+/// Connects encrypted stream to a new loopback TCP socket, relaying messages
+/// between them in both directions in background threads. Messages of custom
+/// types bypass the loopback socket and are relayed through the returned
+/// channel.
+fn bridge(
+    stream: TcpStream,
+    sender: CipherState,
+    mut receiver: CipherState,
+    remote_id: PublicKey,
+) -> Result<(TcpStream, CustomChannel), NoiseError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let local = TcpStream::connect(listener.local_addr()?)?;
+    let (mut inner, peer) = listener.accept()?;
+    // Some other local process may have raced us connecting to the socket
+    if peer != local.local_addr()? {
+        return Err(NoiseError::BridgeHijacked);
+    }
+    let mut inner_reader = inner.try_clone()?;
+    let mut reader = stream.try_clone()?;
+    // Both the session and the custom messages are encrypted with the same
+    // cipher state, which must see the messages in the order they are sent
+    let writer = Arc::new((
+        Mutex::new(SharedWriter {
+            stream,
+            sender,
+            session_sent: 0,
+        }),
+        Condvar::new(),
+    ));
+    let custom_writer = writer.clone();
+    let (incoming_tx, incoming) = mpsc::channel();
+    let (outgoing, outgoing_rx) = mpsc::channel::<(u64, CustomMessage)>();
+    let incoming_tlvs = TlvQueue::default();
+    let outgoing_tlvs = TlvQueue::default();
+    let incoming_queue = incoming_tlvs.clone();
+    let outgoing_queue = outgoing_tlvs.clone();
+
+    thread::spawn(move || {
+        let result = (|| -> Result<(), NoiseError> {
+            loop {
+                let mut message = read_message(&mut reader, &mut receiver)?;
+                let msg_type = u16::from_be_bytes([message[0], message[1]]);
+                if is_custom_type(msg_type) {
+                    // Runtime may have already stopped, and then custom
+                    // messages are of no use
+                    let _ = incoming_tx.send(CustomMessage {
+                        remote_id,
+                        msg_type,
+                        payload: message[2..].to_vec(),
+                    });
+                    continue;
+                }
+                if let Some(len) = tlv::base_len(msg_type, &message[2..]) {
+                    let tlvs = if message.len() > 2 + len {
+                        message.split_off(2 + len)
+                    } else {
+                        vec![]
+                    };
+                    incoming_queue
+                        .lock()
+                        .expect("poisoned mutex")
+                        .push_back(tlvs);
+                }
+                let mut frame = (message.len() as u16).to_be_bytes().to_vec();
+                frame.extend(message);
+                inner.write_all(&frame)?;
+            }
+        })();
+        if let Err(err) = result {
+            debug!("Encrypted peer connection is broken: {}", err);
+        }
+        let _ = inner.shutdown(Shutdown::Both);
+        let _ = reader.shutdown(Shutdown::Both);
+    });
+
+    let write_queue =
+        Arc::new((Mutex::new(OutgoingQueue::default()), Condvar::new()));
+    let session_queue = write_queue.clone();
+    let loopback = inner_reader.try_clone()?;
+
+    thread::spawn(move || {
+        let result = (|| -> Result<(), NoiseError> {
+            loop {
+                let mut prefix = [0u8; FRAME_PREFIX_LEN];
+                inner_reader.read_exact(&mut prefix)?;
+                let mut message =
+                    vec![0u8; u16::from_be_bytes(prefix) as usize];
+                inner_reader.read_exact(&mut message)?;
+                if message.len() >= MIN_MESSAGE_LEN
+                    && tlv::is_extensible(u16::from_be_bytes([
+                        message[0], message[1],
+                    ]))
+                {
+                    if let Some(tlvs) = outgoing_queue
+                        .lock()
+                        .expect("poisoned mutex")
+                        .pop_front()
+                    {
+                        message.extend(tlvs);
+                    }
+                }
+                let (lock, queued) = &*session_queue;
+                lock.lock().expect("poisoned mutex").push(message);
+                queued.notify_one();
+            }
+        })();
+        if let Err(err) = result {
+            debug!("Peer session has stopped: {}", err);
+        }
+        let _ = inner_reader.shutdown(Shutdown::Both);
+        let (lock, queued) = &*session_queue;
+        lock.lock().expect("poisoned mutex").closed = true;
+        queued.notify_one();
+    });
+
+    thread::spawn(move || {
+        let result = (|| -> Result<(), NoiseError> {
+            loop {
+                let batch = {
+                    let (lock, queued) = &*write_queue;
+                    let mut queue = queued
+                        .wait_while(
+                            lock.lock().expect("poisoned mutex"),
+                            |queue| queue.is_empty() && !queue.closed,
+                        )
+                        .expect("poisoned mutex");
+                    if queue.is_empty() {
+                        return Ok(());
+                    }
+                    queue.take_batch()
+                };
+                let mut packets = vec![];
+                let (lock, sent) = &*writer;
+                let mut writer = lock.lock().expect("poisoned mutex");
+                let SharedWriter { stream, sender, .. } = &mut *writer;
+                for message in &batch {
+                    write_message(&mut packets, sender, message)?;
+                }
+                stream.write_all(&packets)?;
+                writer.session_sent += batch.len() as u64;
+                sent.notify_all();
+            }
+        })();
+        if let Err(err) = result {
+            debug!("Encrypted peer connection is broken: {}", err);
+        }
+        let _ = loopback.shutdown(Shutdown::Both);
+        let (lock, sent) = &*writer;
+        let mut writer = lock.lock().expect("poisoned mutex");
+        let _ = writer.stream.shutdown(Shutdown::Both);
+        // Custom messages must not wait for the stopped session
+        writer.session_sent = u64::MAX;
+        sent.notify_all();
+    });
+
+    thread::spawn(move || {
+        // Ends once the runtime drops its sender
+        for (after, custom) in outgoing_rx {
+            let mut message = custom.msg_type.to_be_bytes().to_vec();
+            message.extend(custom.payload);
+            let (lock, sent) = &*custom_writer;
+            let mut writer = sent
+                .wait_while(lock.lock().expect("poisoned mutex"), |writer| {
+                    writer.session_sent < after
+                })
+                .expect("poisoned mutex");
+            let SharedWriter { stream, sender, .. } = &mut *writer;
+            if let Err(err) = write_message(stream, sender, &message) {
+                debug!("Unable to send custom message: {}", err);
+                let _ = stream.shutdown(Shutdown::Both);
+                break;
+            }
+        }
+    });
+
+    Ok((
+        local,
+        CustomChannel {
+            incoming,
+            outgoing,
+            incoming_tlvs,
+            outgoing_tlvs,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+    use std::io::Cursor;
+
+    // BOLT8 Appendix A: Transport Test Vectors
+    fn secret(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        Vec::from_hex(hex).unwrap()
+    }
+
+    const ACT_ONE: &str = "00036360e856310ce5d294e8be33fc807077dc56ac80d95d9\
+                           cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6a";
+    const ACT_TWO: &str = "0002466d7fcae563e5cb09a0d1870bb580344804617879a14\
+                           949cf22285f1bae3f276e2470b93aac583c9ef6eafca3f730ae";
+    const ACT_THREE: &str = "00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc\
+                             9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c\
+                             38228dc68b1c466263b47fdf31e560e139ba";
+    const CK: &str =
+        "919219dbb2920afa8db80f9a51787a840bcf111ed8d588caf9ab4be716e42b01";
+    const SK: &str =
+        "969ab31b4d288cedf6218839b27a3e2140827047f2c0f01bf5c04435d43511a9";
+    const RK: &str =
+        "bb9020b8965f4df047e07f955f3c4b88418984aadc5cdb35096b9ea8fa5c3442";
+
+    fn responder_id() -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret(0x21))
+    }
+
+    #[test]
+    fn handshake() {
+        let (initiator, act_one) =
+            Initiator::start(&responder_id(), secret(0x12));
+        assert_eq!(act_one.to_hex(), ACT_ONE);
+
+        let (responder, act_two) =
+            Responder::start(&secret(0x21), secret(0x22), &act_one).unwrap();
+        assert_eq!(act_two.to_hex(), ACT_TWO);
+
+        let (act_three, sender, receiver) =
+            initiator.finish(&secret(0x11), &act_two).unwrap();
+        assert_eq!(act_three.to_hex(), ACT_THREE);
+        assert_eq!(sender.ck.to_hex(), CK);
+        assert_eq!(sender.key.to_hex(), SK);
+        assert_eq!(receiver.key.to_hex(), RK);
+
+        let (remote_id, sender, receiver) =
+            responder.finish(&act_three).unwrap();
+        assert_eq!(
+            remote_id,
+            PublicKey::from_secret_key(
+                &Secp256k1::signing_only(),
+                &secret(0x11)
+            )
+        );
+        assert_eq!(sender.key.to_hex(), RK);
+        assert_eq!(receiver.key.to_hex(), SK);
+    }
+
+    #[test]
+    fn invalid_act_one() {
+        // Bad key serialization
+        let mut act = bytes(ACT_ONE);
+        act[1] = 0x04;
+        assert!(matches!(
+            Responder::start(&secret(0x21), secret(0x22), &act),
+            Err(NoiseError::InvalidPublicKey(1))
+        ));
+
+        // Bad MAC
+        let mut act = bytes(ACT_ONE);
+        *act.last_mut().unwrap() ^= 0x01;
+        assert!(matches!(
+            Responder::start(&secret(0x21), secret(0x22), &act),
+            Err(NoiseError::InvalidMac(1))
+        ));
+    }
+
+    #[test]
+    fn invalid_act_two() {
+        let (initiator, _) = Initiator::start(&responder_id(), secret(0x12));
+        let mut act = bytes(ACT_TWO);
+        *act.last_mut().unwrap() ^= 0x01;
+        assert!(matches!(
+            initiator.finish(&secret(0x11), &act),
+            Err(NoiseError::InvalidMac(2))
+        ));
+    }
+
+    #[test]
+    fn invalid_act_three() {
+        let (_, act_one) = Initiator::start(&responder_id(), secret(0x12));
+        let (responder, _) =
+            Responder::start(&secret(0x21), secret(0x22), &act_one).unwrap();
+        let mut act = bytes(ACT_THREE);
+        *act.last_mut().unwrap() ^= 0x01;
+        assert!(matches!(
+            responder.finish(&act),
+            Err(NoiseError::InvalidMac(3))
+        ));
+    }
+
+    #[test]
+    fn message_encryption() {
+        let mut ck = [0u8; 32];
+        ck.copy_from_slice(&bytes(CK));
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&bytes(SK));
+        let mut sender = CipherState::with(ck, key);
+        let mut receiver = CipherState::with(ck, key);
+
+        let expected = [
+            (0, "cf2b30ddf0cf3f80e7c35a6e6730b59fe802473180f396d88a8fb0db8cbcf25d2f214cf9ea1d95"),
+            (1, "72887022101f0b6753e0c7de21657d35a4cb2a1f5cde2650528bbc8f837d0f0d7ad833b1a256a1"),
+            (500, "178cb9d7387190fa34db9c2d50027d21793c9bc2d40b1e14dcf30ebeeeb220f48364f7a4c68bf8"),
+            (501, "1b186c57d44eb6de4c057c49940d79bb838a145cb528d6e8fd26dbe50a60ca2c104b56b60e45bd"),
+            (1000, "4a2f3cc3b5e78ddb83dcb426d9863d9d9a723b0337c89dd0b005d89f8d3c05c52b76b29b740f09"),
+            (1001, "2ecd8c8a5629d0d02ab457a0fdd0f7b90a192cd46be5ecb6ca570bfc5e268338b1a16cf4ef2d36"),
+        ];
+        for no in 0..1002 {
+            let mut packet = vec![];
+            write_message(&mut packet, &mut sender, b"hello").unwrap();
+            if let Some((_, hex)) = expected.iter().find(|(n, _)| *n == no) {
+                assert_eq!(packet.to_hex(), *hex);
+            }
+            let message =
+                read_message(&mut Cursor::new(packet), &mut receiver).unwrap();
+            assert_eq!(message, b"hello");
+        }
+    }
+
+    #[test]
+    fn gossip_goes_last() {
+        let mut queue = OutgoingQueue::default();
+        // channel_update, update_add_htlc, node_announcement, commitment_signed
+        queue.push(vec![0x01, 0x02, 0xAA]);
+        queue.push(vec![0x00, 0x80, 0x01]);
+        queue.push(vec![0x01, 0x01, 0xBB]);
+        queue.push(vec![0x00, 0x84, 0x02]);
+        assert_eq!(
+            queue.take_batch(),
+            vec![
+                vec![0x00, 0x80, 0x01],
+                vec![0x00, 0x84, 0x02],
+                vec![0x01, 0x02, 0xAA],
+                vec![0x01, 0x01, 0xBB],
+            ]
+        );
+        assert!(queue.is_empty());
+
+        // announcement_signatures belongs to the channel
+        queue.push(vec![0x01, 0x03]);
+        assert_eq!(queue.messages.len(), 1);
+    }
+}
@@ -17,6 +17,7 @@ use std::fs;
 use std::net::IpAddr;
 use std::path::PathBuf;
 
+use internet2::addr::InetSocketAddr;
 use internet2::{FramingProtocol, LocalNode, RemoteNodeAddr};
 use lnpbp::strict_encoding::{StrictDecode, StrictEncode};
 
@@ -72,6 +73,13 @@ pub struct Opts {
     #[clap(short, long, default_value = "9735")]
     pub port: u16,
 
+    /// Onion service address forwarding connections to the listening socket
+    ///
+    /// Incoming connections coming from the loopback interface, where they
+    /// are forwarded by Tor, are identified by this address.
+    #[clap(long, requires = "listen")]
+    pub onion: Option<InetSocketAddr>,
+
     /// Overlay peer communications through different transport protocol.
     #[clap(
         short,
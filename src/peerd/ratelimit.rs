@@ -0,0 +1,73 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Window in which the incoming connection attempts are counted
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits the rate of incoming connection attempts from a single IP address
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    max_per_minute: usize,
+    attempts: HashMap<IpAddr, VecDeque<Instant>>,
+    /// Total number of rejected attempts
+    rejected: usize,
+}
+
+impl RateLimiter {
+    pub fn with(max_per_minute: usize) -> RateLimiter {
+        RateLimiter {
+            max_per_minute,
+            attempts: none!(),
+            rejected: 0,
+        }
+    }
+
+    /// Registers connection attempt, returning whether it is within the
+    /// limit. Attempts from the loopback interface are not limited, since
+    /// these are the onion peers forwarded by Tor and local clients.
+    pub fn admit(&mut self, ip: IpAddr) -> bool {
+        if ip.is_loopback() {
+            return true;
+        }
+        let now = Instant::now();
+        // Forgetting stale attempts of all addresses keeps the memory used
+        // by the limiter proportional to the current connection rate
+        self.attempts.retain(|_, times| {
+            while times
+                .front()
+                .map(|time| now.duration_since(*time) >= RATE_WINDOW)
+                .unwrap_or_default()
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        let times = self.attempts.entry(ip).or_default();
+        if times.len() >= self.max_per_minute {
+            self.rejected += 1;
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+
+    /// Total number of rejected connection attempts
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+}
@@ -12,29 +12,82 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::collections::HashMap;
-use std::thread::spawn;
-use std::time::{Duration, SystemTime};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant, SystemTime};
 
-use amplify::Bipolar;
+use amplify::{Bipolar, Slice32, Wrapper};
 use bitcoin::secp256k1::rand::{self, Rng};
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use bitcoin::BlockHash;
 use internet2::addr::InetSocketAddr;
 use internet2::{
-    presentation, transport, zmqsocket, NodeAddr, TypedEnum, ZmqType,
-    ZMQ_CONTEXT,
+    presentation, session, transport, zmqsocket, LocalNode, NodeAddr,
+    RemoteNodeAddr, RemoteSocketAddr, TypedEnum, ZmqType, ZMQ_CONTEXT,
 };
-use lnp::{message, Messages};
+use lnp::features::InitFeatures;
+use lnp::{message, ChannelId, Messages};
 use microservices::esb::{self, Handler};
 use microservices::node::TryService;
 use microservices::peer::{self, PeerConnection, PeerSender, SendMessage};
 
-use crate::rpc::{request::PeerInfo, Request, ServiceBus};
+use super::access::load_access;
+use super::noise::{CustomChannel, TlvQueue};
+use super::ratelimit::RateLimiter;
+use super::throttle::{MessageClass, Throttle};
+use super::{noise, socks, websocket};
+use crate::config::MAX_FUNDING_SATOSHIS;
+use crate::gossipd::{AnnouncementSignatures, GossipTimestampFilter};
+use crate::rpc::request::{
+    ChannelRoute, CustomMessage, ExtendedMessage, MessageTraffic, PeerDead,
+    PeerDeadReason, PeerFeatures, PeerInfo, LSPS0_MESSAGE_TYPE, STFU_TYPE,
+    TOWER_REPLY_TYPE, TOWER_REQUEST_TYPE,
+};
+use crate::rpc::tlv::{self, TlvStream};
+use crate::rpc::{Request, ServiceBus};
+use crate::service::{self, BridgeHandler};
 use crate::{Config, CtlServer, Error, LogStyle, Service, ServiceId};
 
+/// Counter making names of the bridge sockets unique within the process,
+/// where peer connections may run as threads
+static BRIDGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Period of `ping` messages sent to the remote peer. Peer which has not
+/// replied with `pong` by the time the next ping is due is disconnected.
+const PING_PERIOD: Duration = Duration::from_secs(30);
+
+/// Pings requesting this or larger `pong` size must not be replied (BOLT-1)
+const MAX_PONG_SIZE: u16 = 65532;
+
+/// Time in which the remote peer must send its `init` message; checked with
+/// the ping timer
+const INIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Number of messages the remote peer may send before `init`; these are
+/// ignored, and the peer exceeding the limit is disconnected
+const MAX_PRE_INIT_MESSAGES: usize = 8;
+
+/// Number of queued gossip messages which are sent without awaiting the
+/// flush timer
+const MAX_GOSSIP_BATCH: usize = 64;
+
+/// Number of gossip messages delayed by the rate limit which are kept in the
+/// queue; once reached, the oldest messages are dropped
+const MAX_GOSSIP_QUEUE: usize = 10_000;
+
+/// Number of incoming connections accepted from a single IP address per
+/// minute
+pub const MAX_HANDSHAKES_PER_MINUTE: usize = 10;
+
 pub fn run(
     config: Config,
     connection: PeerConnection,
+    custom: Option<CustomChannel>,
     id: NodeAddr,
     local_id: PublicKey,
     remote_id: Option<PublicKey>,
@@ -48,30 +101,92 @@ pub fn run(
     debug!("Opening bridge between runtime and peer listener threads");
     let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
     let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
-    tx.connect("inproc://bridge")?;
-    rx.bind("inproc://bridge")?;
+    let bridge_addr = format!(
+        "inproc://bridge-{}",
+        BRIDGE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    tx.connect(&bridge_addr)?;
+    rx.bind(&bridge_addr)?;
 
     let identity = ServiceId::Peer(id);
 
     debug!("Starting thread listening for messages from the remote peer");
+    // Bridge is shared with the timer thread, since the runtime has a single
+    // bridge socket
+    let bridge = Arc::new(Mutex::new(esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?));
     let bridge_handler = ListenerRuntime {
         identity: identity.clone(),
-        bridge: esb::Controller::with(
-            map! {
-                ServiceBus::Bridge => esb::BusConfig {
-                    carrier: zmqsocket::Carrier::Socket(tx),
-                    router: None,
-                    queued: true,
-                }
-            },
-            BridgeHandler,
-            ZmqType::Rep,
-        )?,
+        bridge: bridge.clone(),
     };
     let listener = peer::Listener::with(receiver, bridge_handler);
     spawn(move || listener.run_or_panic("peerd-listener"));
     // TODO: Use the handle returned by spawn to track the child process
 
+    let incoming_tlvs = custom.as_ref().map(|c| c.incoming_tlvs.clone());
+    let outgoing_tlvs = custom.as_ref().map(|c| c.outgoing_tlvs.clone());
+    let custom_sender = custom.map(|custom| {
+        debug!("Starting thread relaying custom messages to the runtime");
+        let custom_identity = identity.clone();
+        let custom_bridge = Arc::downgrade(&bridge);
+        let incoming = custom.incoming;
+        spawn(move || {
+            // Stops once the connection is broken or the runtime is gone
+            for message in incoming {
+                let bridge = match custom_bridge.upgrade() {
+                    Some(bridge) => bridge,
+                    None => break,
+                };
+                let mut bridge = bridge.lock().expect("poisoned mutex");
+                if let Err(err) = bridge.send_to(
+                    ServiceBus::Bridge,
+                    custom_identity.clone(),
+                    Request::CustomPeerMessage(message),
+                ) {
+                    error!("Unable to relay custom message: {}", err);
+                    break;
+                }
+            }
+        });
+        custom.outgoing
+    });
+
+    debug!("Starting thread pinging the remote peer and flushing gossip");
+    let timer_identity = identity.clone();
+    let timer = Arc::downgrade(&bridge);
+    let flush_interval = config.gossip_flush_interval;
+    drop(bridge);
+    let mut last_ping = Instant::now();
+    spawn(move || loop {
+        sleep(flush_interval);
+        // Timer stops together with the listener thread
+        let bridge = match timer.upgrade() {
+            Some(bridge) => bridge,
+            None => break,
+        };
+        let mut requests = vec![Request::FlushGossip];
+        if last_ping.elapsed() >= PING_PERIOD {
+            last_ping = Instant::now();
+            requests.push(Request::PingPeer);
+        }
+        let mut bridge = bridge.lock().expect("poisoned mutex");
+        if let Err(err) = requests.into_iter().try_for_each(|request| {
+            bridge.send_to(ServiceBus::Bridge, timer_identity.clone(), request)
+        }) {
+            error!("Unable to notify peer runtime on timer: {}", err);
+            break;
+        }
+    });
+
     debug!("Staring main service runtime");
     let runtime = Runtime {
         identity,
@@ -80,12 +195,27 @@ pub fn run(
         local_socket,
         remote_socket,
         routing: empty!(),
+        channels: empty!(),
         sender,
+        custom_sender,
+        session_sent: 0,
+        incoming_tlvs,
+        outgoing_tlvs,
         connect,
+        local_features: local_features(&config),
+        init_sent: false,
+        features: None,
+        threaded: config.threaded,
         started: SystemTime::now(),
         messages_sent: 0,
         messages_received: 0,
+        traffic: none!(),
         awaited_pong: None,
+        pre_init_messages: 0,
+        gossip_queue: none!(),
+        throttle: Throttle::with(config.throttle),
+        chain_hash: config.chain.clone().chain_params().genesis_hash,
+        gossip_filter: None,
     };
     let mut service = Service::service(config, runtime)?;
     service.add_loopback(rx)?;
@@ -93,44 +223,270 @@ pub fn run(
     unreachable!()
 }
 
-pub struct BridgeHandler;
+/// Connects to the remote peer, directly or through the Tor proxy, if one is
+/// configured. Peers with WebSocket addresses are connected using WebSocket
+/// framing. Custom messages can be exchanged only over the encrypted
+/// connections, for which their channel is returned.
+pub fn connect(
+    config: &Config,
+    local_node: &LocalNode,
+    remote_node_addr: RemoteNodeAddr,
+) -> Result<(PeerConnection, Option<CustomChannel>), Error> {
+    let (inet_addr, websocket) = match remote_node_addr.remote_addr {
+        RemoteSocketAddr::Ftcp(inet_addr) => (Some(inet_addr), false),
+        RemoteSocketAddr::Websocket(inet_addr) => (Some(inet_addr), true),
+        _ => (None, false),
+    };
 
-impl esb::Handler<ServiceBus> for BridgeHandler {
-    type Request = Request;
-    type Address = ServiceId;
-    type Error = Error;
+    let access = load_access(&config.peer_access)?;
+    let denied_ip = inet_addr
+        .and_then(|inet_addr| SocketAddr::try_from(inet_addr).ok())
+        .map(|socket_addr| access.denied_ips_contain(socket_addr.ip()))
+        .unwrap_or_default();
+    if !access.admits_node(remote_node_addr.node_id) || denied_ip {
+        return Err(Error::Other(format!(
+            "Connection to {} is denied by peer access lists",
+            remote_node_addr
+        )));
+    }
 
-    fn identity(&self) -> ServiceId {
-        ServiceId::Loopback
+    let inet_addr = match (inet_addr, config.tor_proxy) {
+        (Some(inet_addr), _) => inet_addr,
+        (None, Some(_)) => {
+            return Err(Error::Other(format!(
+                "Connection to {} can't be established through Tor proxy",
+                remote_node_addr.remote_addr
+            )))
+        }
+        (None, None) => {
+            let connection =
+                PeerConnection::connect(remote_node_addr, local_node)?;
+            return Ok((connection, None));
+        }
+    };
+
+    let stream = match config.tor_proxy {
+        None => TcpStream::connect(SocketAddr::try_from(inet_addr)?)?,
+        Some(proxy) => {
+            // Onion addresses are resolved by the proxy
+            let target = match SocketAddr::try_from(inet_addr) {
+                Ok(socket_addr) => socks::Target::Ip(socket_addr),
+                Err(_) => {
+                    let addr = inet_addr.to_string();
+                    let (host, port) = match addr.rfind(':') {
+                        Some(pos) => (&addr[..pos], &addr[pos + 1..]),
+                        None => (addr.as_str(), ""),
+                    };
+                    let port = port.parse().map_err(|_| {
+                        Error::Other(format!(
+                            "Remote peer address {} has no port",
+                            addr
+                        ))
+                    })?;
+                    socks::Target::Domain(host.to_owned(), port)
+                }
+            };
+            info!("Connecting to {} through Tor proxy {}", target, proxy);
+            socks::connect(
+                proxy,
+                &target,
+                &remote_node_addr.node_id.to_string(),
+            )
+            .map_err(|err| Error::Other(err.to_string()))?
+        }
+    };
+    let stream = if websocket {
+        debug!("Opening WebSocket connection to {}", inet_addr);
+        websocket::connect(stream, &inet_addr.to_string())
+            .map_err(|err| Error::Other(err.to_string()))?
+    } else {
+        stream
+    };
+    let (stream, custom) = noise::initiate(
+        stream,
+        &local_node.private_key(),
+        remote_node_addr.node_id,
+    )?;
+    let session = session::Raw::with_ftcp_unencrypted(stream, inet_addr)?;
+    Ok((PeerConnection::with(session), Some(custom)))
+}
+
+/// Features advertised in the `init` message according to the node
+/// configuration
+fn local_features(config: &Config) -> InitFeatures {
+    InitFeatures {
+        // Channel re-establishment provides the last per-commitment secret
+        option_data_loss_protect: true,
+        option_static_remotekey: config.static_remotekey,
+        option_support_large_channel: config.wumbo,
+        gossip_queries: true,
+        ..none!()
     }
+}
 
-    fn handle(
-        &mut self,
-        _senders: &mut esb::SenderList<ServiceBus, ServiceId>,
-        _bus: ServiceBus,
-        _addr: ServiceId,
-        _request: Request,
-    ) -> Result<(), Error> {
-        // Bridge does not receive replies for now
-        Ok(())
+/// Listens for incoming connections, running the connection runtime for each
+/// of them in a new thread. Used by the daemons running as threads of the
+/// lnpd process, which can't be forked.
+pub fn run_listener(
+    config: Config,
+    local_node: LocalNode,
+    remote_addr: RemoteSocketAddr,
+    onion: Option<InetSocketAddr>,
+) -> Result<(), Error> {
+    let local_id = local_node.node_id();
+    let (inet_addr, websocket) = match remote_addr {
+        RemoteSocketAddr::Ftcp(inet_addr) => (inet_addr, false),
+        RemoteSocketAddr::Websocket(inet_addr) => (inet_addr, true),
+        addr => {
+            return Err(Error::Other(format!(
+                "Listening on {} is not supported",
+                addr
+            )))
+        }
+    };
+
+    debug!("Binding TCP socket {}", inet_addr);
+    let listener = TcpListener::bind(SocketAddr::try_from(inet_addr)?)?;
+
+    let mut rate_limiter = RateLimiter::with(MAX_HANDSHAKES_PER_MINUTE);
+    debug!("Running TCP listener event loop");
+    loop {
+        debug!("Awaiting for incoming connections...");
+        let (stream, remote_socket_addr) = listener.accept()?;
+        debug!("New connection from {}", remote_socket_addr);
+        if !rate_limiter.admit(remote_socket_addr.ip()) {
+            warn!(
+                "{} connection from {}: too many attempts ({} rejected in \
+                 total)",
+                "Rejecting".err(),
+                remote_socket_addr,
+                rate_limiter.rejected()
+            );
+            continue;
+        }
+        if !admits_connection(&config, remote_socket_addr) {
+            continue;
+        }
+        let addr = listener_addr(inet_addr, onion, remote_socket_addr);
+        let id = NodeAddr::Remote(RemoteNodeAddr {
+            node_id: local_id,
+            remote_addr: if websocket {
+                RemoteSocketAddr::Websocket(addr)
+            } else {
+                RemoteSocketAddr::Ftcp(addr)
+            },
+        });
+
+        let config = config.clone();
+        let secret_key = local_node.private_key();
+        spawn(move || {
+            // Handshakes must not block the listener
+            let (connection, custom, remote_id) =
+                match accept(stream, inet_addr, websocket, &secret_key) {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        error!("Error accepting connection: {}", err);
+                        return;
+                    }
+                };
+            if let Err(err) = run(
+                config,
+                connection,
+                Some(custom),
+                id,
+                local_id,
+                Some(remote_id),
+                Some(inet_addr),
+                remote_socket_addr.into(),
+                false,
+            ) {
+                error!("Error running peerd runtime: {}", err);
+            }
+        });
+    }
+}
+
+/// Establishes session with the remote peer over the accepted connection,
+/// performing WebSocket handshake first if the listener uses WebSocket
+/// framing. Returns the session and the channel of custom messages together
+/// with the node id of the remote peer, authenticated by the handshake.
+pub fn accept(
+    stream: TcpStream,
+    inet_addr: InetSocketAddr,
+    websocket: bool,
+    local_key: &SecretKey,
+) -> Result<(PeerConnection, CustomChannel, PublicKey), Error> {
+    let stream = if websocket {
+        debug!("Accepting WebSocket connection");
+        websocket::accept(stream)
+            .map_err(|err| Error::Other(err.to_string()))?
+    } else {
+        stream
+    };
+    debug!("Establishing session with the remote");
+    let (stream, custom, remote_id) = noise::respond(stream, local_key)?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let session = session::Raw::with_ftcp_unencrypted(stream, inet_addr)?;
+    Ok((PeerConnection::with(session), custom, remote_id))
+}
+
+/// Checks the incoming connection against the peer access lists, which are
+/// re-read each time so the changes made by lnpd apply immediately
+pub fn admits_connection(
+    config: &Config,
+    remote_socket_addr: SocketAddr,
+) -> bool {
+    match load_access(&config.peer_access) {
+        Ok(access) if access.admits_ip(remote_socket_addr.ip()) => true,
+        Ok(_) => {
+            info!(
+                "{} connection from {} denied by peer access lists",
+                "Rejecting".err(),
+                remote_socket_addr
+            );
+            false
+        }
+        Err(err) => {
+            error!(
+                "{}",
+                format!(
+                    "Rejecting connection from {}: {}",
+                    remote_socket_addr, err
+                )
+                .err()
+            );
+            false
+        }
     }
+}
 
-    fn handle_err(&mut self, err: esb::Error) -> Result<(), esb::Error> {
-        // We simply propagate the error since it's already being reported
-        Err(err)?
+/// Address by which the incoming connection has reached the node. Tor
+/// forwards connections to the onion service from the loopback interface,
+/// so these are classified as onion ones.
+pub fn listener_addr(
+    inet_addr: InetSocketAddr,
+    onion: Option<InetSocketAddr>,
+    remote_socket_addr: SocketAddr,
+) -> InetSocketAddr {
+    match onion {
+        Some(onion) if remote_socket_addr.ip().is_loopback() => onion,
+        _ => inet_addr,
     }
 }
 
 pub struct ListenerRuntime {
     identity: ServiceId,
-    bridge: esb::Controller<ServiceBus, Request, BridgeHandler>,
+    bridge: Arc<Mutex<esb::Controller<ServiceBus, Request, BridgeHandler>>>,
 }
 
 impl ListenerRuntime {
     fn send_over_bridge(&mut self, req: Request) -> Result<(), Error> {
         debug!("Forwarding LNPWP message over BRIDGE interface to the runtime");
-        self.bridge
-            .send_to(ServiceBus::Bridge, self.identity.clone(), req)?;
+        self.bridge.lock().expect("poisoned mutex").send_to(
+            ServiceBus::Bridge,
+            self.identity.clone(),
+            req,
+        )?;
         Ok(())
     }
 }
@@ -150,15 +506,36 @@ impl peer::Handler for ListenerRuntime {
             Error::Peer(presentation::Error::Transport(
                 transport::Error::TimedOut,
             )) => {
-                trace!("Time to ping the remote peer");
-                // This means socket reading timeout and the fact that we need
-                // to send a ping message
-                self.send_over_bridge(Request::PingPeer)
+                // Socket reading timeout: the remote peer is pinged by the
+                // timer, so we just keep reading
+                trace!("No messages from the remote peer");
+                Ok(())
             }
             // for all other error types, indicating internal errors, we
-            // propagate error to the upper level
+            // report the peer as dead to the runtime and propagate error to
+            // the upper level
             _ => {
                 error!("Unrecoverable peer error {}, halting", err);
+                let reason = match err {
+                    Error::Peer(presentation::Error::Transport(
+                        transport::Error::SocketIo(
+                            std::io::ErrorKind::UnexpectedEof,
+                        ),
+                    )) => PeerDeadReason::ConnectionClosed,
+                    _ => PeerDeadReason::TransportError,
+                };
+                if let ServiceId::Peer(ref node_addr) = self.identity {
+                    let dead = PeerDead {
+                        node_addr: node_addr.clone(),
+                        reason,
+                        details: err.to_string(),
+                    };
+                    if let Err(err) =
+                        self.send_over_bridge(Request::PeerDead(dead))
+                    {
+                        error!("Unable to notify peer runtime: {}", err);
+                    }
+                }
                 Err(err)
             }
         }
@@ -173,13 +550,45 @@ pub struct Runtime {
     remote_socket: InetSocketAddr,
 
     routing: HashMap<ServiceId, ServiceId>,
+    /// Channel daemons working with the remote peer
+    channels: HashSet<ServiceId>,
     sender: PeerSender,
+    /// Sender of the custom messages, which are available only over the
+    /// encrypted connections
+    custom_sender: Option<Sender<(u64, CustomMessage)>>,
+    /// Number of messages sent over the session, which precede the custom
+    /// messages sent after them
+    session_sent: u64,
+    /// TLV streams of the messages received from the remote peer, which are
+    /// available only over the encrypted connections
+    incoming_tlvs: Option<TlvQueue>,
+    /// TLV streams of the messages sent to the remote peer
+    outgoing_tlvs: Option<TlvQueue>,
     connect: bool,
+    /// Features advertised by the local node in the `init` message
+    local_features: InitFeatures,
+    init_sent: bool,
+    /// Features supported by both nodes, known once the remote peer has sent
+    /// its `init` message
+    features: Option<PeerFeatures>,
+    /// Whether the daemon runs as a thread of the lnpd process
+    threaded: bool,
 
     started: SystemTime,
     messages_sent: usize,
     messages_received: usize,
+    /// Traffic exchanged with the remote peer, indexed by the message type
+    traffic: BTreeMap<u16, MessageTraffic>,
     awaited_pong: Option<u16>,
+    /// Messages received from the remote peer before its `init` message
+    pre_init_messages: usize,
+    /// Gossip messages awaiting to be sent to the remote peer
+    gossip_queue: VecDeque<(Messages, TlvStream)>,
+    /// Rate limits of gossip and pings exchanged with the remote peer
+    throttle: Throttle,
+    chain_hash: BlockHash,
+    /// Filter of the gossip relayed to the remote peer, set by the peer
+    gossip_filter: Option<GossipTimestampFilter>,
 }
 
 impl CtlServer for Runtime {}
@@ -197,17 +606,28 @@ impl esb::Handler<ServiceBus> for Runtime {
         &mut self,
         _senders: &mut esb::SenderList<ServiceBus, ServiceId>,
     ) -> Result<(), Error> {
-        if self.connect {
-            info!("{} with the remote peer", "Initializing connection".promo());
+        if !self.init_sent {
+            // Both sides send `init` as the first message of the connection
+            if self.connect {
+                info!(
+                    "{} with the remote peer",
+                    "Initializing connection".promo()
+                );
+            } else {
+                info!(
+                    "{} from the remote peer",
+                    "Accepting connection".promo()
+                );
+            }
 
-            self.sender.send_message(Messages::Init(message::Init {
+            self.send_peer(Messages::Init(message::Init {
                 global_features: none!(),
-                local_features: none!(),
+                local_features: self.local_features.clone(),
                 assets: none!(),
                 // unknown_tlvs: none!(),
             }))?;
 
-            self.connect = false;
+            self.init_sent = true;
         }
         Ok(())
     }
@@ -241,6 +661,12 @@ impl Runtime {
         source: ServiceId,
         request: Request,
     ) -> Result<(), Error> {
+        if let ServiceId::Channel(_) = source {
+            self.channels.insert(source.clone());
+        }
+        // Broadcasted gossip is subject to the filter of the peer, unlike the
+        // replies to its gossip queries
+        let broadcasted = source == ServiceId::Lnpd;
         match &request {
             Request::PeerMessage(Messages::FundingSigned(
                 message::FundingSigned { channel_id, .. },
@@ -249,18 +675,48 @@ impl Runtime {
                     "Renaming channeld service from temporary id {:#} to channel id #{:#}", 
                     source, channel_id
                 );
-                self.routing.remove(&source);
+                // Channel id changes each time funding transaction is
+                // replaced, so we drop all previous routes to the daemon
+                self.routing.retain(|_, channeld| *channeld != source);
                 self.routing.insert(channel_id.clone().into(), source);
             }
             _ => {}
         }
+        // Node announcements are relayed together with their TLV records
+        let (request, tlvs) = request.extract_tlvs();
         match request {
-            Request::PeerMessage(message) => {
+            Request::PeerMessage(
+                message @ Messages::ChannelAnnouncement(_),
+            )
+            | Request::PeerMessage(message @ Messages::NodeAnnouncement(_))
+            | Request::PeerMessage(message @ Messages::ChannelUpdate(_)) => {
+                if broadcasted && !self.gossip_allowed(&message) {
+                    trace!("Gossip is filtered out by the remote peer");
+                    return Ok(());
+                }
+                trace!("Queueing gossip for the remote peer");
+                if self.gossip_queue.len() >= MAX_GOSSIP_QUEUE {
+                    self.gossip_queue.pop_front();
+                }
+                self.gossip_queue.push_back((message, tlvs));
+                if self.gossip_queue.len() >= MAX_GOSSIP_BATCH {
+                    self.flush_gossip()?;
+                }
+            }
+            Request::PeerMessage(message) if tlvs.is_empty() => {
                 // 1. Check permissions
                 // 2. Forward to the remote peer
+                // Channel messages go ahead of the queued gossip
                 debug!("Forwarding LN peer message to the remote peer");
-                self.messages_sent += 1;
-                self.sender.send_message(message)?;
+                self.send_peer(message)?;
+            }
+            Request::PeerMessage(message) => {
+                debug!("Forwarding extended peer message to the remote peer");
+                self.send_extended(message, tlvs)?;
+            }
+            Request::CustomPeerMessage(message) => {
+                debug!("Forwarding custom message to the remote peer");
+                self.send_custom(message)?;
             }
             _ => {
                 error!(
@@ -282,15 +738,40 @@ impl Runtime {
         request: Request,
     ) -> Result<(), Error> {
         match request {
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
             Request::UpdateChannelId(channel_id) => {
                 debug!(
                     "Renaming channeld service from temporary id {:#} to channel id #{:#}",
                     source, channel_id
                 );
-                self.routing.remove(&source);
+                // Channel id changes each time funding transaction is
+                // replaced, so we drop all previous routes to the daemon
+                self.routing.retain(|_, channeld| *channeld != source);
                 self.routing.insert(channel_id.clone().into(), source);
             }
 
+            Request::RouteChannel(ChannelRoute {
+                channel_id,
+                channeld,
+            }) => {
+                debug!("Routing channel {} to {}", channel_id, channeld);
+                self.routing.retain(|_, service| *service != channeld);
+                self.routing.insert(channel_id.into(), channeld.clone());
+                self.channels.insert(channeld);
+            }
+
+            Request::DisconnectPeer(_) => {
+                info!("{} from the remote peer", "Disconnecting".promo());
+                // Channel messages must reach the peer before the warning,
+                // while the queued gossip is of no use anymore
+                self.gossip_queue.clear();
+                self.warn_peer("connection is closed by the node operator");
+                self.disconnect(senders)?;
+            }
+
             Request::GetInfo => {
                 let info = PeerInfo {
                     local_id: self.local_id,
@@ -321,8 +802,11 @@ impl Runtime {
                             }
                         })
                         .collect(),
-                    connected: !self.connect,
+                    connected: self.features.is_some(),
+                    inbound: !self.connect,
                     awaits_pong: self.awaited_pong.is_some(),
+                    features: self.features,
+                    traffic: self.traffic.clone(),
                 };
                 self.send_ctl(senders, source, Request::PeerInfo(info))?;
             }
@@ -344,15 +828,118 @@ impl Runtime {
         _source: ServiceId,
         request: Request,
     ) -> Result<(), Error> {
-        debug!("BRIDGE RPC request: {}", request);
+        // Flush requests come from the timer too often to be logged
+        if !matches!(request, Request::FlushGossip) {
+            debug!("BRIDGE RPC request: {}", request);
+        }
+
+        // TLV streams are queued for each of the extensible messages, so they
+        // are taken before any of the messages is dropped
+        let tlvs = match request {
+            Request::PeerMessage(ref message) => {
+                match self.take_tlvs(message.get_type().into_inner()) {
+                    Ok(tlvs) => tlvs,
+                    Err(err) => {
+                        warn!("Remote peer sent {}: {}", message, err);
+                        self.warn_peer(&err.to_string());
+                        return Ok(());
+                    }
+                }
+            }
+            _ => empty!(),
+        };
 
-        if let Request::PeerMessage(_) = request {
+        if let Request::PeerMessage(ref message) = request {
+            self.messages_received += 1;
+            let traffic = self
+                .traffic
+                .entry(message.get_type().into_inner())
+                .or_default();
+            traffic.messages_received += 1;
+            traffic.bytes_received += message.serialize().len();
+            if !self.throttle.admit(message) {
+                traffic.messages_throttled += 1;
+                trace!(
+                    "Dropping {} exceeding {} rate limit",
+                    message,
+                    MessageClass::of(message)
+                );
+                return Ok(());
+            }
+        }
+        if let Request::CustomPeerMessage(ref message) = request {
             self.messages_received += 1;
+            let traffic = self.traffic.entry(message.msg_type).or_default();
+            traffic.messages_received += 1;
+            traffic.bytes_received += message.payload.len() + 2;
         }
 
         match &request {
-            Request::PingPeer => {
-                self.ping()?;
+            Request::CustomPeerMessage(message) if self.features.is_none() => {
+                debug!(
+                    "Ignoring custom message {} received before init",
+                    message
+                );
+            }
+
+            Request::PeerMessage(message)
+                if self.features.is_none()
+                    && !matches!(message, Messages::Init(_)) =>
+            {
+                self.pre_init_messages += 1;
+                if self.pre_init_messages > MAX_PRE_INIT_MESSAGES {
+                    self.warn_peer("too many messages before init");
+                    let details = format!(
+                        "{} messages are sent before init",
+                        self.pre_init_messages
+                    );
+                    self.peer_dead(
+                        senders,
+                        PeerDeadReason::ProtocolViolation,
+                        details,
+                    )?;
+                } else {
+                    debug!("Ignoring {} received before init", message);
+                }
+            }
+
+            Request::FlushGossip => self.flush_gossip()?,
+
+            Request::PingPeer if self.features.is_none() => {
+                // Pinging is possible only after init messages are exchanged
+                let elapsed = SystemTime::now()
+                    .duration_since(self.started)
+                    .unwrap_or_default();
+                if elapsed >= INIT_TIMEOUT {
+                    self.warn_peer("init message is not received in time");
+                    let details = format!(
+                        "no init message in {} seconds",
+                        elapsed.as_secs()
+                    );
+                    self.peer_dead(
+                        senders,
+                        PeerDeadReason::InitTimeout,
+                        details,
+                    )?;
+                }
+            }
+
+            Request::PingPeer => match self.ping() {
+                Ok(()) => {}
+                Err(Error::NotResponding) => self.peer_dead(
+                    senders,
+                    PeerDeadReason::PingTimeout,
+                    format!("no pong in {} seconds", PING_PERIOD.as_secs()),
+                )?,
+                Err(err) => self.peer_dead(
+                    senders,
+                    PeerDeadReason::TransportError,
+                    err.to_string(),
+                )?,
+            },
+
+            Request::PeerDead(dead) => {
+                self.peer_dead(senders, dead.reason, dead.details.clone())?;
             }
 
             Request::PeerMessage(Messages::Ping(message::Ping {
@@ -373,12 +960,34 @@ impl Runtime {
                 self.awaited_pong = None;
             }
 
+            Request::PeerMessage(Messages::Init(init)) => {
+                self.peer_initialized(senders, init)?;
+            }
+
+            Request::PeerMessage(Messages::OpenChannel(open_channel))
+                if open_channel.funding_satoshis >= MAX_FUNDING_SATOSHIS
+                    && !self
+                        .features
+                        .map(|features| features.large_channel)
+                        .unwrap_or_default() =>
+            {
+                warn!(
+                    "Remote peer proposed large channel of {} sat without \
+                     option_support_large_channel negotiated",
+                    open_channel.funding_satoshis
+                );
+                self.send_peer(Messages::Error(message::Error {
+                    channel_id: open_channel.temporary_channel_id.into(),
+                    data: b"large channels are not supported".to_vec(),
+                }))?;
+            }
+
             Request::PeerMessage(Messages::OpenChannel(_)) => {
                 senders.send_to(
                     ServiceBus::Msg,
                     self.identity(),
                     ServiceId::Lnpd,
-                    request,
+                    extend(request, tlvs),
                 )?;
             }
 
@@ -390,10 +999,38 @@ impl Runtime {
                     ServiceBus::Msg,
                     self.identity(),
                     channeld,
-                    request,
+                    extend(request, tlvs),
                 )?;
             }
 
+            // Error with all-zero channel id fails all channels with the peer
+            Request::PeerMessage(Messages::Error(message::Error {
+                channel_id,
+                ..
+            })) if *channel_id == zero!() => {
+                for channeld in &self.channels {
+                    senders.send_to(
+                        ServiceBus::Msg,
+                        self.identity(),
+                        channeld.clone(),
+                        request.clone(),
+                    )?;
+                }
+            }
+
+            // Warnings do not affect channels, so the ones not related to a
+            // specific channel are only logged
+            Request::PeerMessage(Messages::Warning(message::Warning {
+                channel_id,
+                data,
+            })) if *channel_id == zero!() => {
+                warn!(
+                    "Remote peer {}: {}",
+                    "warns".err(),
+                    String::from_utf8_lossy(data)
+                );
+            }
+
             Request::PeerMessage(Messages::FundingCreated(
                 message::FundingCreated {
                     temporary_channel_id,
@@ -408,6 +1045,20 @@ impl Runtime {
                 )?;
             }
 
+            Request::PeerMessage(
+                message @ Messages::AnnouncementSignatures(_),
+            ) => {
+                let channel_id =
+                    AnnouncementSignatures::with(message)?.channel_id;
+                let channeld: ServiceId = channel_id.into();
+                senders.send_to(
+                    ServiceBus::Msg,
+                    self.identity(),
+                    self.routing.get(&channeld).cloned().unwrap_or(channeld),
+                    request,
+                )?;
+            }
+
             Request::PeerMessage(Messages::FundingSigned(
                 message::FundingSigned { channel_id, .. },
             ))
@@ -426,24 +1077,123 @@ impl Runtime {
             | Request::PeerMessage(Messages::UpdateFailMalformedHtlc(
                 message::UpdateFailMalformedHtlc { channel_id, .. },
             ))
+            | Request::PeerMessage(Messages::UpdateFee(message::UpdateFee {
+                channel_id,
+                ..
+            }))
             | Request::PeerMessage(Messages::AssignFunds(
                 message::AssignFunds { channel_id, .. },
-            )) => {
+            ))
+            | Request::PeerMessage(Messages::Shutdown(message::Shutdown {
+                channel_id,
+                ..
+            }))
+            | Request::PeerMessage(Messages::ClosingSigned(
+                message::ClosingSigned { channel_id, .. },
+            ))
+            | Request::PeerMessage(Messages::ChannelReestablish(
+                message::ChannelReestablish { channel_id, .. },
+            ))
+            | Request::PeerMessage(Messages::CommitmentSigned(
+                message::CommitmentSigned { channel_id, .. },
+            ))
+            | Request::PeerMessage(Messages::RevokeAndAck(
+                message::RevokeAndAck { channel_id, .. },
+            ))
+            | Request::PeerMessage(Messages::Error(message::Error {
+                channel_id,
+                ..
+            }))
+            | Request::PeerMessage(Messages::Warning(message::Warning {
+                channel_id,
+                ..
+            })) => {
                 let channeld: ServiceId = channel_id.clone().into();
                 senders.send_to(
                     ServiceBus::Msg,
                     self.identity(),
                     self.routing.get(&channeld).cloned().unwrap_or(channeld),
-                    request,
+                    extend(request, tlvs),
                 )?;
             }
 
+            Request::PeerMessage(Messages::ChannelAnnouncement(_))
+            | Request::PeerMessage(Messages::NodeAnnouncement(_))
+            | Request::PeerMessage(Messages::ChannelUpdate(_))
+            | Request::PeerMessage(Messages::QueryChannelRange(_))
+            | Request::PeerMessage(Messages::ReplyChannelRange(_))
+            | Request::PeerMessage(Messages::QueryShortChannelIds(_))
+            | Request::PeerMessage(Messages::ReplyShortChannelIdsEnd(_)) => {
+                senders.send_to(
+                    ServiceBus::Msg,
+                    self.identity(),
+                    ServiceId::Gossip,
+                    extend(request, tlvs),
+                )?;
+            }
+
+            Request::PeerMessage(
+                message @ Messages::GossipTimestampFilter(_),
+            ) => match GossipTimestampFilter::with(message) {
+                Ok(filter) if filter.chain_hash != self.chain_hash => {
+                    debug!("Ignoring gossip filter for a different chain")
+                }
+                Ok(filter) => {
+                    debug!("Remote peer sets gossip filter {}", filter);
+                    self.gossip_filter = Some(filter);
+                }
+                Err(err) => warn!("Invalid gossip filter: {}", err),
+            },
+
             Request::PeerMessage(message) => {
                 // 1. Check permissions
                 // 2. Forward to the corresponding daemon
                 debug!("Got peer LNPWP message {}", message);
             }
 
+            Request::CustomPeerMessage(message) => {
+                let daemon = match message.msg_type {
+                    STFU_TYPE if message.payload.len() >= 32 => {
+                        let mut channel_id = [0u8; 32];
+                        channel_id.copy_from_slice(&message.payload[..32]);
+                        let channeld: ServiceId = ChannelId::from_inner(
+                            Slice32::from_inner(channel_id),
+                        )
+                        .into();
+                        self.routing.get(&channeld).cloned().unwrap_or(channeld)
+                    }
+                    TOWER_REQUEST_TYPE => ServiceId::Tower,
+                    TOWER_REPLY_TYPE => ServiceId::TowerClient,
+                    LSPS0_MESSAGE_TYPE => ServiceId::Lsp,
+                    msg_type if msg_type % 2 == 1 => {
+                        debug!("Ignoring custom message of unknown type");
+                        return Ok(());
+                    }
+                    msg_type => {
+                        // Unknown even messages must fail the connection
+                        // (BOLT-1)
+                        self.warn_peer("unknown even message type");
+                        return self.peer_dead(
+                            senders,
+                            PeerDeadReason::ProtocolViolation,
+                            format!("unknown even message type {}", msg_type),
+                        );
+                    }
+                };
+                // Daemon implementing the protocol may not be running
+                if let Err(err) = senders.send_to(
+                    ServiceBus::Msg,
+                    self.identity(),
+                    daemon.clone(),
+                    request.clone(),
+                ) {
+                    debug!(
+                        "Custom message is not delivered to {}: {}",
+                        daemon, err
+                    );
+                }
+            }
+
             _ => {
                 error!("Request is not supported by the BRIDGE interface");
                 return Err(Error::NotSupported(
@@ -455,20 +1205,255 @@ impl Runtime {
         Ok(())
     }
 
+    /// Negotiates features with the remote peer, disconnecting it if it
+    /// requires features unknown to us, and reports them to lnpd and gossipd
+    fn peer_initialized(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        init: &message::Init,
+    ) -> Result<(), Error> {
+        // Nodes must fail the connection on unknown even (required) bits
+        if let Some(bit) = init
+            .global_features
+            .unknown
+            .iter()
+            .chain(init.local_features.unknown.iter())
+            .find(|bit| bit % 2 == 0)
+        {
+            warn!(
+                "Remote peer {} unknown feature bit {}",
+                "requires".err(),
+                bit
+            );
+            self.send_peer(Messages::Error(message::Error {
+                channel_id: zero!(),
+                data: format!("unsupported required feature {}", bit)
+                    .into_bytes(),
+            }))?;
+            return self.disconnect(senders);
+        }
+
+        let local = &self.local_features;
+        let remote = |feature: fn(&InitFeatures) -> bool| {
+            feature(&init.global_features) || feature(&init.local_features)
+        };
+        let features = PeerFeatures {
+            data_loss_protect: local.option_data_loss_protect
+                && remote(|f| f.option_data_loss_protect),
+            static_remotekey: local.option_static_remotekey
+                && remote(|f| f.option_static_remotekey),
+            large_channel: local.option_support_large_channel
+                && remote(|f| f.option_support_large_channel),
+            initial_routing_sync: remote(|f| f.initial_routing_sync),
+            gossip_queries: local.gossip_queries
+                && remote(|f| f.gossip_queries),
+        };
+        info!("Features negotiated with the remote peer: {}", features);
+        self.features = Some(features);
+
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::PeerFeatures(features),
+        )?;
+        // Gossip daemon may not be running
+        let _ = self.send_ctl(
+            senders,
+            ServiceId::Gossip,
+            Request::PeerFeatures(features),
+        );
+        Ok(())
+    }
+
+    /// Closes the connection, notifying lnpd, which pauses the channels with
+    /// the remote peer until it connects again
+    fn disconnect(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+    ) -> Result<(), Error> {
+        if let ServiceId::Peer(ref node_addr) = self.identity {
+            let node_addr = node_addr.clone();
+            self.send_ctl(
+                senders,
+                ServiceId::Lnpd,
+                Request::PeerDisconnected(node_addr),
+            )?;
+        }
+        self.terminate()
+    }
+
+    /// Reports the remote peer which stopped responding or has broken the
+    /// connection to lnpd, which pauses the channels with the peer and
+    /// schedules reconnection, and closes the connection
+    fn peer_dead(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        reason: PeerDeadReason,
+        details: String,
+    ) -> Result<(), Error> {
+        warn!("Remote peer {} ({}): {}", "is dead".err(), reason, details);
+        if let ServiceId::Peer(ref node_addr) = self.identity {
+            let node_addr = node_addr.clone();
+            self.send_ctl(
+                senders,
+                ServiceId::Lnpd,
+                Request::PeerDead(PeerDead {
+                    node_addr,
+                    reason,
+                    details,
+                }),
+            )?;
+        }
+        self.terminate()
+    }
+
+    fn terminate(&self) -> Result<(), Error> {
+        // Give the message bus time to deliver the notification
+        sleep(Duration::from_secs(1));
+        service::terminate(self.threaded);
+    }
+
+    /// Sends BOLT-1 warning not related to any specific channel to the
+    /// remote peer. The peer may already be unreachable, so errors are only
+    /// logged.
+    fn warn_peer(&mut self, reason: &str) {
+        if let Err(err) = self.send_peer(Messages::Warning(message::Warning {
+            channel_id: ChannelId::default(),
+            data: reason.as_bytes().to_vec(),
+        })) {
+            debug!("Unable to send warning to the remote peer: {}", err);
+        }
+    }
+
+    /// Sends the message to the remote peer, accounting its traffic
+    fn send_peer(&mut self, message: Messages) -> Result<(), Error> {
+        self.send_extended(message, empty!())
+    }
+
+    /// Sends the message extended with the TLV stream to the remote peer,
+    /// accounting its traffic. Unencrypted connections do not carry the TLV
+    /// streams, so the plain message is sent over them.
+    fn send_extended(
+        &mut self,
+        message: Messages,
+        tlvs: TlvStream,
+    ) -> Result<(), Error> {
+        let msg_type = message.get_type().into_inner();
+        let mut tlv_len = 0;
+        match (tlv::is_extensible(msg_type), &self.outgoing_tlvs) {
+            (true, Some(queue)) => {
+                let data = tlv::encode(&tlvs);
+                tlv_len = data.len();
+                queue.lock().expect("poisoned mutex").push_back(data);
+            }
+            _ if !tlvs.is_empty() => {
+                warn!(
+                    "TLV extensions of {} can't be sent to the remote peer",
+                    message
+                );
+            }
+            _ => {}
+        }
+        // TODO: Feed the traffic to the metrics exporter once the node
+        //       has one
+        self.messages_sent += 1;
+        let traffic = self.traffic.entry(msg_type).or_default();
+        traffic.messages_sent += 1;
+        traffic.bytes_sent += message.serialize().len() + tlv_len;
+        self.sender.send_message(message)?;
+        self.session_sent += 1;
+        Ok(())
+    }
+
+    /// Takes TLV stream of the extensible message received from the remote
+    /// peer
+    fn take_tlvs(&mut self, msg_type: u16) -> Result<TlvStream, tlv::TlvError> {
+        let queue = match (tlv::is_extensible(msg_type), &self.incoming_tlvs) {
+            (true, Some(queue)) => queue,
+            _ => return Ok(empty!()),
+        };
+        let data = queue
+            .lock()
+            .expect("poisoned mutex")
+            .pop_front()
+            .unwrap_or_default();
+        tlv::decode(msg_type, &data)
+    }
+
+    /// Sends the custom message to the remote peer, accounting its traffic
+    fn send_custom(&mut self, message: CustomMessage) -> Result<(), Error> {
+        if Some(message.remote_id) != self.remote_id {
+            return Err(Error::Other(format!(
+                "Custom message is addressed to {} and not to the remote peer",
+                message.remote_id
+            )));
+        }
+        let sender = self.custom_sender.as_ref().ok_or_else(|| {
+            Error::Other(s!("Custom messages require encrypted connection"))
+        })?;
+        self.messages_sent += 1;
+        let traffic = self.traffic.entry(message.msg_type).or_default();
+        traffic.messages_sent += 1;
+        traffic.bytes_sent += message.payload.len() + 2;
+        sender.send((self.session_sent, message)).map_err(|_| {
+            Error::Other(s!("Connection with the remote peer is closed"))
+        })?;
+        Ok(())
+    }
+
+    /// Checks whether the gossip message may be relayed to the remote peer.
+    /// Peers negotiating `gossip_queries` receive no gossip until they set
+    /// their filter.
+    fn gossip_allowed(&self, message: &Messages) -> bool {
+        match self.gossip_filter {
+            Some(filter) => {
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|duration| duration.as_secs() as u32)
+                    .unwrap_or_default();
+                filter.allows(message, now)
+            }
+            None => !self
+                .features
+                .map(|features| features.gossip_queries)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Sends the queued gossip messages to the remote peer
+    fn flush_gossip(&mut self) -> Result<(), Error> {
+        if self.gossip_queue.is_empty() {
+            return Ok(());
+        }
+        // Gossip over the rate limit awaits the next flush
+        let count = self.gossip_queue.len().min(self.throttle.gossip_quota());
+        trace!(
+            "Sending {} of {} gossip message(s)",
+            count,
+            self.gossip_queue.len()
+        );
+        // Encrypted connections coalesce the messages into a single socket
+        // write, putting them behind the pending channel messages
+        for (message, tlvs) in
+            self.gossip_queue.drain(..count).collect::<Vec<_>>()
+        {
+            self.throttle.gossip_sent();
+            self.send_extended(message, tlvs)?;
+        }
+        Ok(())
+    }
+
     fn ping(&mut self) -> Result<(), Error> {
         trace!("Sending ping to the remote peer");
         if self.awaited_pong.is_some() {
             return Err(Error::NotResponding);
         }
+        // Randomized sizes prevent traffic analysis; the data must be zeros
         let mut rng = rand::thread_rng();
         let len: u16 = rng.gen_range(4, 32);
-        let mut noise = vec![0u8; len as usize];
-        for i in 0..noise.len() {
-            noise[i] = rng.gen();
-        }
+        let noise = vec![0u8; len as usize];
         let pong_size = rng.gen_range(4, 32);
-        self.messages_sent += 1;
-        self.sender.send_message(Messages::Ping(message::Ping {
+        self.send_peer(Messages::Ping(message::Ping {
             ignored: noise,
             pong_size,
         }))?;
@@ -477,14 +1462,24 @@ impl Runtime {
     }
 
     fn pong(&mut self, pong_size: u16) -> Result<(), Error> {
-        trace!("Replying with pong to the remote peer");
-        let mut noise = vec![0u8; pong_size as usize];
-        let mut rng = rand::thread_rng();
-        for i in 0..noise.len() {
-            noise[i] = rng.gen();
+        if pong_size >= MAX_PONG_SIZE {
+            trace!("Ping does not require a reply");
+            return Ok(());
         }
-        self.messages_sent += 1;
-        self.sender.send_message(Messages::Pong(noise))?;
+        trace!("Replying with pong to the remote peer");
+        let noise = vec![0u8; pong_size as usize];
+        self.send_peer(Messages::Pong(noise))?;
         Ok(())
     }
 }
+
+/// Attaches the TLV stream received from the remote peer to the forwarded
+/// peer message
+fn extend(request: Request, tlvs: TlvStream) -> Request {
+    match request {
+        Request::PeerMessage(message) if !tlvs.is_empty() => {
+            Request::ExtendedPeerMessage(ExtendedMessage { message, tlvs })
+        }
+        request => request,
+    }
+}
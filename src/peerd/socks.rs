@@ -0,0 +1,169 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Minimal SOCKS5 client (RFC 1928, RFC 1929) used for connecting to remote
+//! peers through Tor proxy
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use amplify::IoError;
+
+const SOCKS_VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+/// Username/password authentication method, used for stream isolation
+const AUTH_USERNAME_PASSWORD: u8 = 2;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Errors connecting through SOCKS5 proxy
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum SocksError {
+    /// I/O error communicating with the proxy: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// proxy uses unsupported SOCKS version {0}
+    Version(u8),
+
+    /// proxy does not accept username/password authentication required for
+    /// stream isolation
+    AuthMethod,
+
+    /// proxy has rejected stream isolation credentials
+    AuthRejected,
+
+    /// `{0}` is too long for SOCKS5 request
+    TooLong(String),
+
+    /// proxy is unable to connect to the remote peer: {0}
+    Connect(&'static str),
+
+    /// proxy has replied with unknown address type {0}
+    AddressType(u8),
+}
+
+/// Remote peer address as it is passed to the proxy
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+pub enum Target {
+    #[display("{0}")]
+    Ip(SocketAddr),
+
+    /// Host name resolved by the proxy, like Tor onion address
+    #[display("{0}:{1}")]
+    Domain(String, u16),
+}
+
+/// Opens TCP stream to the target through SOCKS5 proxy. Tor isolates
+/// streams authenticated with different credentials into separate circuits,
+/// so each remote peer must use its own `isolation` string.
+pub fn connect(
+    proxy: SocketAddr,
+    target: &Target,
+    isolation: &str,
+) -> Result<TcpStream, SocksError> {
+    let mut stream = TcpStream::connect(proxy)?;
+
+    stream.write_all(&[SOCKS_VERSION, 1, AUTH_USERNAME_PASSWORD])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(SocksError::Version(reply[0]));
+    }
+    if reply[1] != AUTH_USERNAME_PASSWORD {
+        return Err(SocksError::AuthMethod);
+    }
+
+    let username = isolation.as_bytes();
+    if username.len() > u8::MAX as usize {
+        return Err(SocksError::TooLong(isolation.to_owned()));
+    }
+    // Password does not affect isolation, but must not be empty
+    let password = b"lnp";
+    let mut auth = vec![AUTH_VERSION, username.len() as u8];
+    auth.extend_from_slice(username);
+    auth.push(password.len() as u8);
+    auth.extend_from_slice(password);
+    stream.write_all(&auth)?;
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        return Err(SocksError::AuthRejected);
+    }
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0];
+    let port = match target {
+        Target::Ip(SocketAddr::V4(addr)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Ip(SocketAddr::V6(addr)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Domain(host, port) => {
+            if host.len() > u8::MAX as usize {
+                return Err(SocksError::TooLong(host.clone()));
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+            *port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header)?;
+    if header[0] != SOCKS_VERSION {
+        return Err(SocksError::Version(header[0]));
+    }
+    if header[1] != 0 {
+        return Err(SocksError::Connect(reply_reason(header[1])));
+    }
+    // Address the proxy has bound is not used, but must be consumed
+    let addr_len = match header[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        atyp => return Err(SocksError::AddressType(atyp)),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    Ok(stream)
+}
+
+fn reply_reason(code: u8) -> &'static str {
+    match code {
+        1 => "general proxy failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
@@ -0,0 +1,136 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::time::Instant;
+
+use lnp::Messages;
+
+use crate::config::ThrottleLimits;
+
+/// Class of the messages exchanged with the remote peer, which are rate
+/// limited separately
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+pub enum MessageClass {
+    #[display("gossip")]
+    Gossip,
+
+    #[display("ping")]
+    Ping,
+
+    /// Channel and HTLC messages, as well as the connection management
+    /// ones; these are never throttled
+    #[display("channel")]
+    Channel,
+}
+
+impl MessageClass {
+    pub fn of(message: &Messages) -> MessageClass {
+        match message {
+            Messages::ChannelAnnouncement(_)
+            | Messages::NodeAnnouncement(_)
+            | Messages::ChannelUpdate(_) => MessageClass::Gossip,
+            Messages::Ping(_) => MessageClass::Ping,
+            _ => MessageClass::Channel,
+        }
+    }
+}
+
+/// Token bucket refilled at the constant rate up to the number of messages
+/// allowed per minute
+#[derive(Clone, Debug)]
+struct Bucket {
+    per_minute: u32,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn with(per_minute: u32) -> Bucket {
+        Bucket {
+            per_minute,
+            tokens: per_minute as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        self.tokens = (self.tokens + elapsed * self.per_minute as f64 / 60.0)
+            .min(self.per_minute as f64);
+    }
+
+    /// Takes token for a single message, if any is available. Zero rate
+    /// means the class is not limited.
+    fn take(&mut self) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+        self.refill();
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Number of whole tokens available
+    fn available(&mut self) -> usize {
+        if self.per_minute == 0 {
+            return usize::MAX;
+        }
+        self.refill();
+        self.tokens as usize
+    }
+}
+
+/// Rate limits of the messages exchanged with a single remote peer
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    gossip_in: Bucket,
+    gossip_out: Bucket,
+    ping: Bucket,
+}
+
+impl Throttle {
+    pub fn with(limits: ThrottleLimits) -> Throttle {
+        Throttle {
+            gossip_in: Bucket::with(limits.gossip_per_minute),
+            gossip_out: Bucket::with(limits.gossip_per_minute),
+            ping: Bucket::with(limits.pings_per_minute),
+        }
+    }
+
+    /// Checks whether the message received from the remote peer is within
+    /// the limits of its class
+    pub fn admit(&mut self, message: &Messages) -> bool {
+        match MessageClass::of(message) {
+            MessageClass::Gossip => self.gossip_in.take(),
+            MessageClass::Ping => self.ping.take(),
+            MessageClass::Channel => true,
+        }
+    }
+
+    /// Number of gossip messages which may be sent to the remote peer now;
+    /// the rest await the next flush
+    pub fn gossip_quota(&mut self) -> usize {
+        self.gossip_out.available()
+    }
+
+    /// Accounts gossip message sent to the remote peer
+    pub fn gossip_sent(&mut self) {
+        self.gossip_out.take();
+    }
+}
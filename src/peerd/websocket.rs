@@ -0,0 +1,304 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! WebSocket framing (RFC 6455) of the lightning peer connections, allowing
+//! browser-based and firewall-restricted peers to reach the node.
+//!
+//! WebSocket stream is bridged to a loopback TCP socket carrying the plain
+//! lightning byte stream, so the peer session runs over it in the same way
+//! as over the ordinary TCP connection. TLS (`wss://`) is expected to be
+//! terminated by a reverse proxy in front of the listener.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use amplify::IoError;
+use bitcoin::hashes::{sha1, Hash};
+use bitcoin::secp256k1::rand::{self, RngCore};
+
+/// GUID which is appended to the client key in the server handshake reply
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Maximal size of the HTTP handshake header
+const MAX_HEADER_LEN: usize = 8192;
+/// Time in which the remote peer must complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximal size of a single frame, which is buffered in memory before being
+/// relayed; lightning messages are limited to 64kB
+const MAX_FRAME_LEN: u64 = 1 << 17;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Errors of WebSocket connections
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum WsError {
+    /// I/O error in WebSocket connection: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// invalid WebSocket handshake: {0}
+    Handshake(String),
+
+    /// WebSocket frame of {0} bytes exceeds the size limit
+    FrameTooLarge(u64),
+
+    /// unsupported WebSocket frame type {0}
+    Opcode(u8),
+}
+
+/// Performs the server side of the WebSocket handshake on the incoming
+/// connection, returning the stream carrying the unframed data
+pub fn accept(stream: TcpStream) -> Result<TcpStream, WsError> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let headers = read_headers(&mut reader)?;
+    let key = header(&headers, "sec-websocket-key").ok_or_else(|| {
+        WsError::Handshake(s!("no `Sec-WebSocket-Key` header"))
+    })?;
+    let mut writer = stream;
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )?;
+    writer.flush()?;
+    writer.set_read_timeout(None)?;
+    bridge(reader, writer, false)
+}
+
+/// Performs the client side of the WebSocket handshake with the remote
+/// peer at `host`, returning the stream carrying the unframed data
+pub fn connect(stream: TcpStream, host: &str) -> Result<TcpStream, WsError> {
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let key = base64::encode(&nonce);
+
+    let mut writer = stream;
+    write!(
+        writer,
+        "GET / HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        host, key
+    )?;
+    writer.flush()?;
+
+    writer.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(writer.try_clone()?);
+    let headers = read_headers(&mut reader)?;
+    writer.set_read_timeout(None)?;
+    if !headers[0].starts_with("HTTP/1.1 101") {
+        return Err(WsError::Handshake(headers[0].clone()));
+    }
+    if header(&headers, "sec-websocket-accept") != Some(accept_key(&key)) {
+        return Err(WsError::Handshake(s!(
+            "invalid `Sec-WebSocket-Accept` header"
+        )));
+    }
+    bridge(reader, writer, true)
+}
+
+/// Reads HTTP request or status line with the headers following it
+fn read_headers(
+    reader: &mut BufReader<TcpStream>,
+) -> Result<Vec<String>, WsError> {
+    let mut lines = vec![];
+    let mut len = 0usize;
+    loop {
+        let mut line = String::new();
+        len += reader.read_line(&mut line)?;
+        if len > MAX_HEADER_LEN {
+            return Err(WsError::Handshake(s!("header is too long")));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_owned());
+    }
+    if lines.is_empty() {
+        return Err(WsError::Handshake(s!("connection is closed")));
+    }
+    Ok(lines)
+}
+
+fn header(headers: &[String], name: &str) -> Option<String> {
+    headers.iter().skip(1).find_map(|line| {
+        let mut split = line.splitn(2, ':');
+        let key = split.next()?.trim();
+        if key.eq_ignore_ascii_case(name) {
+            Some(split.next()?.trim().to_owned())
+        } else {
+            None
+        }
+    })
+}
+
+fn accept_key(key: &str) -> String {
+    let hash = sha1::Hash::hash(format!("{}{}", key, WS_GUID).as_bytes());
+    base64::encode(&hash[..])
+}
+
+/// Writes a single frame; client frames have to be masked
+fn write_frame(
+    stream: &mut TcpStream,
+    opcode: u8,
+    payload: &[u8],
+    mask: bool,
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    let mask_bit = if mask { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    if mask {
+        let mut key = [0u8; 4];
+        rand::thread_rng().fill_bytes(&mut key);
+        frame.extend_from_slice(&key);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ key[i % 4]),
+        );
+    } else {
+        frame.extend_from_slice(payload);
+    }
+    stream.write_all(&frame)
+}
+
+/// Reads a single frame, returning its opcode and unmasked payload
+fn read_frame(reader: &mut impl Read) -> Result<(u8, Vec<u8>), WsError> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head)?;
+    let opcode = head[0] & 0x0F;
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf)?;
+            u16::from_be_bytes(buf) as u64
+        }
+        127 => {
+            let mut buf = [0u8; 8];
+            reader.read_exact(&mut buf)?;
+            u64::from_be_bytes(buf)
+        }
+        len => len as u64,
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(WsError::FrameTooLarge(len));
+    }
+    let mut key = [0u8; 4];
+    let masked = head[1] & 0x80 != 0;
+    if masked {
+        reader.read_exact(&mut key)?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    if masked {
+        payload
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte ^= key[i % 4]);
+    }
+    Ok((opcode, payload))
+}
+
+/// Connects WebSocket stream to a new loopback TCP socket, relaying data
+/// between them in both directions in background threads
+fn bridge(
+    mut reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    mask: bool,
+) -> Result<TcpStream, WsError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let local = TcpStream::connect(listener.local_addr()?)?;
+    let (mut inner, peer) = listener.accept()?;
+    // Some other local process may have raced us connecting to the socket
+    if peer != local.local_addr()? {
+        return Err(WsError::Handshake(s!("loopback bridge is hijacked")));
+    }
+    let mut inner_reader = inner.try_clone()?;
+    let writer = Arc::new(Mutex::new(writer));
+
+    let ws_writer = writer.clone();
+    thread::spawn(move || {
+        let result = (|| -> Result<(), WsError> {
+            loop {
+                let (opcode, payload) = read_frame(&mut reader)?;
+                match opcode {
+                    OP_BINARY | OP_CONTINUATION => inner.write_all(&payload)?,
+                    OP_PING => write_frame(
+                        &mut ws_writer.lock().expect("poisoned mutex"),
+                        OP_PONG,
+                        &payload,
+                        mask,
+                    )?,
+                    OP_PONG => {}
+                    OP_CLOSE => return Ok(()),
+                    opcode => return Err(WsError::Opcode(opcode)),
+                }
+            }
+        })();
+        if let Err(err) = result {
+            debug!("WebSocket connection is broken: {}", err);
+        }
+        let _ = inner.shutdown(Shutdown::Both);
+        let _ = ws_writer
+            .lock()
+            .expect("poisoned mutex")
+            .shutdown(Shutdown::Both);
+    });
+
+    thread::spawn(move || {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let len = match inner_reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => len,
+            };
+            let mut stream = writer.lock().expect("poisoned mutex");
+            if write_frame(&mut stream, OP_BINARY, &buf[..len], mask).is_err() {
+                break;
+            }
+        }
+        let mut stream = writer.lock().expect("poisoned mutex");
+        let _ = write_frame(&mut stream, OP_CLOSE, &[], mask);
+        let _ = stream.shutdown(Shutdown::Both);
+    });
+
+    Ok(local)
+}
@@ -89,11 +89,21 @@ impl Runtime {
 
     fn handle_rpc_ctl(
         &mut self,
-        _senders: &mut esb::SenderList<ServiceBus, ServiceId>,
-        _source: ServiceId,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        source: ServiceId,
         request: Request,
     ) -> Result<(), Error> {
         match request {
+            Request::Heartbeat(seq) => {
+                senders.send_to(
+                    ServiceBus::Ctl,
+                    self.identity.clone(),
+                    source,
+                    Request::Heartbeat(seq),
+                )?;
+                Ok(())
+            }
+
             _ => {
                 error!("Request is not supported by the CTL interface");
                 return Err(Error::NotSupported(
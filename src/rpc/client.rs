@@ -132,6 +132,13 @@ impl Client {
                 Request::Success(OptionDetails(None)) => {
                     println!("{}", "Success".ended());
                 }
+                Request::PaymentPreimage(preimage) => {
+                    println!(
+                        "{}payment preimage {}",
+                        "Success: ".ended(),
+                        preimage
+                    );
+                }
                 other => {
                     eprintln!(
                         "{}: {}",
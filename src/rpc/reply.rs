@@ -34,8 +34,13 @@ impl rpc_connection::Reply for Reply {}
 
 impl From<Error> for rpc::Failure {
     fn from(err: Error) -> Self {
+        let code = match err {
+            // Clients may retry once the resources are released
+            Error::ResourceLimit(..) => 2,
+            _ => 1, // Error from LNPD
+        };
         rpc::Failure {
-            code: 1, // Error from LNPD
+            code,
             info: err.to_string(),
         }
     }
@@ -19,21 +19,26 @@ use serde_with::{DisplayFromStr, DurationSeconds, Same};
 use std::collections::BTreeMap;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::iter::FromIterator;
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::time::Duration;
 
-use bitcoin::{secp256k1, OutPoint};
+use bitcoin::hashes::sha256;
+use bitcoin::{secp256k1, OutPoint, Script, Transaction, TxOut, Txid};
 use internet2::{NodeAddr, RemoteSocketAddr};
 use lnp::payment::{self, AssetsBalance, Lifecycle};
 use lnp::{message, ChannelId, Messages, TempChannelId};
 use lnpbp::chain::AssetId;
-use lnpbp::strict_encoding::{StrictDecode, StrictEncode};
+use lnpbp::strict_encoding::{strict_serialize, StrictDecode, StrictEncode};
+use lnpbp::Chain;
 use microservices::rpc::Failure;
 use microservices::rpc_connection;
-use wallet::PubkeyScript;
+use wallet::{HashLock, HashPreimage, PubkeyScript};
 
 #[cfg(feature = "rgb")]
 use rgb::Consignment;
 
+use crate::rpc::tlv::TlvStream;
 use crate::ServiceId;
 
 #[derive(Clone, Debug, Display, From, LnpApi)]
@@ -42,8 +47,8 @@ use crate::ServiceId;
 #[non_exhaustive]
 pub enum Request {
     #[lnp_api(type = 0)]
-    #[display("hello()")]
-    Hello,
+    #[display("hello({0})")]
+    Hello(DaemonInfo),
 
     #[lnp_api(type = 1)]
     #[display("update_channel_id({0})")]
@@ -53,6 +58,48 @@ pub enum Request {
     #[display("send_message({0})")]
     PeerMessage(Messages),
 
+    // Issued by `lnpd` to a new `peerd` for each funded channel with the
+    // remote peer
+    #[lnp_api(type = 3)]
+    #[display("route_channel({0})")]
+    RouteChannel(ChannelRoute),
+
+    // Issued by `lnpd` to each registered daemon, which echoes it back to
+    // confirm it is still responsive
+    #[lnp_api(type = 4)]
+    #[display("heartbeat({0})")]
+    Heartbeat(u64),
+
+    // Issued by `lnpd` to the daemons keeping state, which reply with
+    // `DaemonState`
+    #[lnp_api(type = 5)]
+    #[display("get_state()")]
+    GetState,
+
+    // Issued by `lnpd` to `gossipd` with the state exported from another
+    // node instance
+    #[lnp_api(type = 6)]
+    #[display("restore_state(...)")]
+    RestoreState(Vec<u8>),
+
+    // Issued by the timer threads of `peerd` and `gossipd` to their runtimes
+    // to send gossip messages queued for the remote peers
+    #[lnp_api(type = 7)]
+    #[display("flush_gossip()")]
+    FlushGossip,
+
+    // Exchanged between `peerd` and the daemons implementing protocols on
+    // top of the custom peer messages
+    #[lnp_api(type = 8)]
+    #[display("custom_message({0})")]
+    CustomPeerMessage(CustomMessage),
+
+    // Exchanged between `peerd` and the daemons for the peer messages
+    // extended with TLV records
+    #[lnp_api(type = 9)]
+    #[display("extended_message({0})")]
+    ExtendedPeerMessage(ExtendedMessage),
+
     // Can be issued from `cli` to `lnpd`
     #[lnp_api(type = 100)]
     #[display("get_info()")]
@@ -68,6 +115,16 @@ pub enum Request {
     #[display("list_channels()")]
     ListChannels,
 
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 103)]
+    #[display("asset_balances()")]
+    AssetBalances,
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 104)]
+    #[display("list_daemons()")]
+    ListDaemons,
+
     // Can be issued from `cli` to `lnpd`
     #[lnp_api(type = 200)]
     #[display("listen({0})")]
@@ -107,12 +164,505 @@ pub enum Request {
     #[display("transfer({0})")]
     Transfer(Transfer),
 
+    // Can be issued from `cli` to a specific `channeld`
+    #[cfg(feature = "rgb")]
+    #[lnp_api(type = 209)]
+    #[display("fund_channel_with_assets({0})")]
+    FundChannelWithAssets(AssetFunding),
+
+    // Issued by `channeld` to `lnpd` each time channel asset balances change
+    #[lnp_api(type = 210)]
+    #[display("channel_balances({0})")]
+    ChannelBalances(ChannelBalances),
+
+    // Issued by `channeld` to `lnpd` once both peers have exchanged
+    // `funding_locked` and the channel may be used for payments
+    #[lnp_api(type = 211)]
+    #[display("channel_activated({0})")]
+    ChannelActivated(ChannelId),
+
+    // Can be issued from `cli` to a specific `channeld`
+    #[lnp_api(type = 212)]
+    #[display("close_channel({0})")]
+    CloseChannel(CloseChannel),
+
+    // Can be issued from `cli` to a specific `channeld`
+    #[lnp_api(type = 213)]
+    #[display("force_close_channel()")]
+    ForceCloseChannel,
+
+    // Issued by `lnpd` to `channeld` when a connection to the channel remote
+    // peer is established again
+    #[lnp_api(type = 214)]
+    #[display("peer_reconnected({0})")]
+    PeerReconnected(NodeAddr),
+
+    // Can be issued from `cli` to a specific `channeld` funded by the node
+    #[lnp_api(type = 215)]
+    #[display("update_channel_fee({0})")]
+    UpdateChannelFee(u32),
+
+    // Issued by `channeld` to `lnpd` when the channel negotiation is aborted
+    // and the daemon terminates
+    #[lnp_api(type = 217)]
+    #[display("channel_aborted({0})")]
+    ChannelAborted(ChannelId),
+
+    // Issued periodically by the `channeld` and `wtclientd` timer threads and
+    // `lnpd` supervisor thread to their runtimes
+    #[lnp_api(type = 218)]
+    #[display("check_timeouts()")]
+    CheckTimeouts,
+
+    // Can be issued from `cli` to a specific `channeld` to replace the
+    // funding transaction before the channel is locked
+    #[lnp_api(type = 219)]
+    #[display("replace_funding({0})")]
+    ReplaceFunding(OutPoint),
+
+    // Can be issued from `cli` to `lnpd`, which forwards it to the specific
+    // `channeld`, to cancel opening of a channel which is not funded yet
+    #[lnp_api(type = 220)]
+    #[display("abort_channel({0})")]
+    AbortChannel(ChannelId),
+
+    // Can be issued from `cli` to a specific `channeld`
+    #[lnp_api(type = 221)]
+    #[display("set_channel_policy({0})")]
+    SetChannelPolicy(ForwardingPolicy),
+
+    // Issued by `channeld` to `lnpd` once the channel is opened, such that it
+    // gets into the static channel backup
+    #[lnp_api(type = 222)]
+    #[display("update_backup({0})")]
+    UpdateBackup(ChannelBackup),
+
+    // Issued by `lnpd` to a `channeld` launched for recovering the channel
+    // from the static channel backup
+    #[lnp_api(type = 223)]
+    #[display("recover_channel({0})")]
+    RecoverChannel(ChannelBackup),
+
+    // Can be issued to a specific `channeld` by any daemon requiring the
+    // channel to stop updates, such as splicing; the daemon is notified once
+    // the channel is quiescent
+    #[lnp_api(type = 224)]
+    #[display("request_quiescence()")]
+    RequestQuiescence,
+
+    // Issued to a specific `channeld` by the daemon which has requested
+    // quiescence once the operation requiring it is completed
+    #[lnp_api(type = 225)]
+    #[display("release_quiescence()")]
+    ReleaseQuiescence,
+
+    // Issued by `channeld` to `lnpd` once both signatures for the first
+    // commitment are present; `lnpd` publishes the funding transaction, if
+    // it is known, and forwards `TransactionConfirmed` to the channel once
+    // the transaction reaches the given depth
+    #[lnp_api(type = 226)]
+    #[display("await_funding({0})")]
+    AwaitFunding(TxDepth),
+
+    // Can be issued from `cli` to `lnpd`, which forwards it to the `peerd`
+    // instances connected to the remote node
+    #[lnp_api(type = 227)]
+    #[display("disconnect_peer({0})")]
+    DisconnectPeer(secp256k1::PublicKey),
+
+    // Issued by `peerd` to `lnpd` once the connection is closed, right
+    // before the daemon terminates; `lnpd` forwards it to the `channeld`
+    // instances working with the remote peer, also when `peerd` has exited
+    // on its own
+    #[lnp_api(type = 228)]
+    #[display("peer_disconnected({0})")]
+    PeerDisconnected(NodeAddr),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 229)]
+    #[display("open_channel_batch({0})")]
+    OpenChannelBatch(ChannelBatch),
+
+    // Issued by `peerd` to `lnpd` and `gossipd` once `init` messages are
+    // exchanged with the remote peer; `lnpd` forwards it to the `channeld`
+    // instances working with the peer
+    #[lnp_api(type = 230)]
+    #[display("peer_features({0})")]
+    PeerFeatures(PeerFeatures),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 231)]
+    #[display("update_peer_access({0})")]
+    UpdatePeerAccess(PeerAccessUpdate),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 232)]
+    #[display("get_peer_access()")]
+    GetPeerAccess,
+
+    // Issued by `gossipd` to `lnpd`, which forwards the message to all
+    // initialized peer connections except the one it came from
+    #[lnp_api(type = 233)]
+    #[display("broadcast_gossip({0})")]
+    BroadcastGossip(GossipBroadcast),
+
+    // Issued by `peerd` to `lnpd` when the remote peer stops responding or
+    // the connection breaks; `lnpd` notifies the `channeld` instances working
+    // with the peer and schedules reconnection
+    #[lnp_api(type = 234)]
+    #[display("peer_dead({0})")]
+    PeerDead(PeerDead),
+
+    // Issued by `gossipd` to `lnpd` with the addresses advertised by the
+    // remote node in its `node_announcement`
+    #[lnp_api(type = 235)]
+    #[display("node_addresses({0})")]
+    NodeAddresses(NodeAddresses),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 300)]
+    #[display("export_node({0})")]
+    ExportNode(ExportNode),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 301)]
+    #[display("import_node({0})")]
+    ImportNode(NodeArchive),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 302)]
+    #[display("export_accounting({0})")]
+    ExportAccounting(ExportAccounting),
+
+    // Issued by daemons to `lnpd` to record an event affecting node funds
+    #[lnp_api(type = 303)]
+    #[display("book_event({0})")]
+    BookEvent(AccountingEvent),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 304)]
+    #[display("export_backup()")]
+    ExportBackup,
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 305)]
+    #[display("restore_backup(...)")]
+    RestoreBackup(Vec<u8>),
+
+    // Issued by the `lnpd` supervisor thread to its runtime when a daemon
+    // launched by `lnpd` has crashed
+    #[lnp_api(type = 306)]
+    #[display("daemon_crashed({0})")]
+    DaemonCrashed(DaemonCrash),
+
+    // Can be issued from `cli` to `lnpd` with signed funding transaction,
+    // which is published once the channel spending it is funded
+    #[lnp_api(type = 307)]
+    #[display("funding_transaction(...)")]
+    FundingTransaction(Transaction),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 308)]
+    #[display("export_state({0})")]
+    ExportState(ExportNode),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 309)]
+    #[display("import_state({0})")]
+    ImportState(NodeState),
+
     /* TODO: Activate after lightning-invoice library update
     // Can be issued from `cli` to a specific `peerd`
     #[lnp_api(type = 208)]
     #[display("pay_invoice({0})")]
     PayInvoice(Invoice),
      */
+    // Issued by `channeld` to `wtclientd` for each revoked commitment
+    #[lnp_api(type = 401)]
+    #[display("append_justice_blob({0})")]
+    AppendJusticeBlob(JusticeBlob),
+
+    // Can be issued from `cli` to `wtclientd`
+    #[lnp_api(type = 402)]
+    #[display("list_towers()")]
+    ListTowers,
+
+    // Issued by the chain watching service to notify about newly mined
+    // transactions
+    #[lnp_api(type = 500)]
+    #[display("chain_transactions(...)")]
+    ChainTransactions(Vec<Transaction>),
+
+    // Can be issued to the chain service by any daemon
+    #[lnp_api(type = 501)]
+    #[display("broadcast_transaction(...)")]
+    BroadcastTransaction(Transaction),
+
+    // Can be issued to the chain service by any daemon willing to receive
+    // `ChainTransactions` notifications
+    #[lnp_api(type = 502)]
+    #[display("chain_subscribe()")]
+    ChainSubscribe,
+
+    // Can be issued from `cli` to the simulated chain service; mines the
+    // given number of blocks, warping chain time forward
+    #[lnp_api(type = 503)]
+    #[display("mine_blocks({0})")]
+    MineBlocks(u32),
+
+    // Can be issued from `cli` to the simulated chain service
+    #[lnp_api(type = 504)]
+    #[display("reorg_chain({0})")]
+    ReorgChain(ChainReorg),
+
+    // Can be issued from `cli` to the simulated chain service
+    #[lnp_api(type = 505)]
+    #[display("set_fee_estimate({0})")]
+    SetFeeEstimate(u32),
+
+    // Can be issued to the chain service by any daemon
+    #[lnp_api(type = 506)]
+    #[display("get_fee_estimate()")]
+    GetFeeEstimate,
+
+    // Can be issued from `cli` to the chain service
+    #[lnp_api(type = 507)]
+    #[display("get_chain_info()")]
+    GetChainInfo,
+
+    // Can be issued to the chain service by any daemon; the service replies
+    // with `TransactionConfirmed` once the transaction reaches given depth
+    #[lnp_api(type = 508)]
+    #[display("watch_transaction({0})")]
+    WatchTransaction(TxDepth),
+
+    // Issued by the chain service in reply to `WatchTransaction`
+    #[lnp_api(type = 509)]
+    #[display("transaction_confirmed({0})")]
+    TransactionConfirmed(TxDepth),
+
+    // Can be issued to the chain service by any daemon; the service replies
+    // with `ChannelOutput`
+    #[lnp_api(type = 510)]
+    #[display("get_channel_output({0})")]
+    GetChannelOutput(ShortChannelId),
+
+    // Issued by the chain service in reply to `GetChannelOutput`
+    #[lnp_api(type = 511)]
+    #[display("channel_output({0})")]
+    ChannelOutput(ChannelOutput),
+
+    // Can be issued to the chain service by any daemon; the service replies
+    // with `OutputLocation`
+    #[lnp_api(type = 512)]
+    #[display("locate_output({0})")]
+    LocateOutput(OutPoint),
+
+    // Issued by the chain service in reply to `LocateOutput`
+    #[lnp_api(type = 513)]
+    #[display("output_location({0})")]
+    OutputLocation(OutputLocation),
+
+    // Can be issued from `cli` to `lspd`, which asks the provider with
+    // `lsps1.get_info` and `lsps2.get_info` if it acts as a LSP client
+    #[lnp_api(type = 600)]
+    #[display("lsp_get_info()")]
+    LspGetInfo,
+
+    // Can be issued from `cli` to `lspd`
+    #[lnp_api(type = 603)]
+    #[display("lsp_list_orders()")]
+    LspListOrders,
+
+    // Can be issued from `cli` to `lspd` acting as a LSP client
+    #[lnp_api(type = 604)]
+    #[display("lsp_buy_channel({0})")]
+    LspBuyChannel(LspOrderRequest),
+
+    // Can be issued from `cli` to `lspd` acting as a LSP client with the
+    // payment hash of the invoice paid through the just-in-time channel
+    #[lnp_api(type = 605)]
+    #[display("lsp_jit_buy({0})")]
+    LspJitBuy(HashLock),
+
+    // Issued by `channeld` to a lightning service provider `lspd` when an
+    // HTLC is forwarded to a short channel id alias of a just-in-time
+    // channel; `lspd` settles the HTLC with `HtlcSettlement`
+    #[lnp_api(type = 606)]
+    #[display("intercept_htlc({0})")]
+    InterceptHtlc(InterceptedHtlc),
+
+    // Issued by a lightning service provider `lspd` to `lnpd`, such that
+    // HTLCs with the payment hash are forwarded to `lspd` instead of being
+    // failed
+    #[lnp_api(type = 607)]
+    #[display("intercept_payment({0})")]
+    InterceptPayment(PaymentInterception),
+
+    // Can be issued from `cli` to `gossipd`
+    #[lnp_api(type = 701)]
+    #[display("list_leases()")]
+    ListLeases,
+
+    // Can be issued from `cli` to `gossipd`
+    #[lnp_api(type = 702)]
+    #[display("quote_lease({0})")]
+    QuoteLease(LeaseRequest),
+
+    // Can be issued from `cli` or `routed` to `gossipd`
+    #[lnp_api(type = 704)]
+    #[display("list_nodes()")]
+    ListNodes,
+
+    // Can be issued from `cli` or `routed` to `gossipd`
+    #[lnp_api(type = 705)]
+    #[display("describe_graph({0})")]
+    DescribeGraph(GraphFilter),
+
+    // Issued by `lnpd` to `gossipd` when the node becomes reachable on a new
+    // address, like a published onion service, such that it gets announced
+    // with `node_announcement`
+    #[lnp_api(type = 706)]
+    #[display("advertise_address({0})")]
+    AdvertiseAddress(InetSocketAddr),
+
+    // Issued by `channeld` to `gossipd` when forwarding policy of an active
+    // channel changes, such that it gets announced with `channel_update`
+    #[lnp_api(type = 703)]
+    #[display("channel_policy_updated({0})")]
+    ChannelPolicyUpdated(ChannelPolicyUpdate),
+
+    // Can be issued from `cli` to `swapd` acting as a swap client
+    #[lnp_api(type = 800)]
+    #[display("swap_start({0})")]
+    SwapStart(SwapRequest),
+
+    // Can be issued by a swap client to a swap provider `swapd`
+    #[lnp_api(type = 801)]
+    #[display("swap_create({0})")]
+    SwapCreate(SwapProposal),
+
+    // Can be issued from `cli` to `swapd`
+    #[lnp_api(type = 804)]
+    #[display("swap_refund({0})")]
+    SwapRefund(sha256::Hash),
+
+    // Can be issued from `cli` to `swapd`
+    #[lnp_api(type = 805)]
+    #[display("list_swaps()")]
+    ListSwaps,
+
+    // Issued by daemons to `lnpd` to notify external systems about node
+    // events
+    #[lnp_api(type = 900)]
+    #[display("node_event({0})")]
+    NodeEvent(NodeEvent),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 901)]
+    #[display("list_webhook_deliveries()")]
+    ListWebhookDeliveries,
+
+    // Issued by a plugin registered as `ServiceId::Other` to `lnpd` to
+    // subscribe to hook points. The plugin must answer `Heartbeat` requests
+    // to remain registered.
+    #[lnp_api(type = 902)]
+    #[display("register_plugin(...)")]
+    RegisterPlugin(Vec<HookPoint>),
+
+    // Issued by daemons to `lnpd` to run plugins subscribed to a hook point
+    #[lnp_api(type = 903)]
+    #[display("run_hook({0})")]
+    RunHook(HookCall),
+
+    // Issued by `lnpd` to a plugin subscribed to the hook point
+    #[lnp_api(type = 904)]
+    #[display("hook_call({0})")]
+    HookCall(HookCall),
+
+    // Plugin decision on a hook call, sent by the plugin to `lnpd` and by
+    // `lnpd` to the daemon which has run the hook
+    #[lnp_api(type = 905)]
+    #[display("hook_result({0})")]
+    HookResult(HookResult),
+
+    // Can be issued from `cli` to `lnpd`
+    #[lnp_api(type = 906)]
+    #[display("create_invoice({0})")]
+    CreateInvoice(InvoiceRequest),
+
+    // Issued by `channeld` to `lnpd` for HTLCs paying to the local node
+    #[lnp_api(type = 907)]
+    #[display("settle_htlc({0})")]
+    SettleHtlc(IncomingHtlc),
+
+    // Issued by `lnpd` to `channeld` in response to `SettleHtlc`
+    #[lnp_api(type = 908)]
+    #[display("htlc_settlement({0})")]
+    HtlcSettlement(HtlcSettlement),
+
+    // Issued by `lnpd` to the daemon which has created the invoice, once an
+    // HTLC paying it is settled
+    #[lnp_api(type = 909)]
+    #[display("invoice_settled({0})")]
+    InvoiceSettled(HashLock),
+
+    // Issued by `lnpd` to `channeld` in response to `SettleHtlc` for HTLCs
+    // which are not paying to the local node and are forwarded by the
+    // daemon intercepting their payment hash
+    #[lnp_api(type = 910)]
+    #[display("forward_htlc({0})")]
+    ForwardHtlc(HtlcForward),
+
+    // Issued by `channeld` to the funding wallet `fundingd`, which replies
+    // with `FundChannel` once the funding transaction is constructed
+    #[lnp_api(type = 1300)]
+    #[display("construct_funding({0})")]
+    ConstructFunding(FundingRequest),
+
+    // Issued by `channeld` to `fundingd` once both signatures for the first
+    // commitment are present and the funding transaction may be broadcast
+    #[lnp_api(type = 1301)]
+    #[display("publish_funding()")]
+    PublishFunding,
+
+    // Issued by `channeld` to `fundingd` when the channel is aborted before
+    // its funding transaction is published
+    #[lnp_api(type = 1302)]
+    #[display("release_funding()")]
+    ReleaseFunding,
+
+    // Can be issued from `cli` to `fundingd`
+    #[lnp_api(type = 1303)]
+    #[display("get_wallet_info()")]
+    GetWalletInfo,
+
+    // Issued by `channeld` to `fundingd` to bump the fee of the published
+    // commitment transaction through its anchor output; `fundingd` replies
+    // with `SignCpfp`
+    #[lnp_api(type = 1304)]
+    #[display("construct_cpfp({0})")]
+    ConstructCpfp(CpfpRequest),
+
+    // Issued by `fundingd` to `channeld` with the child transaction, which
+    // anchor input is left for the channel to sign
+    #[lnp_api(type = 1305)]
+    #[display("sign_cpfp({0})")]
+    SignCpfp(CpfpChild),
+
+    // Issued by `channeld` to `fundingd` for a fresh wallet script receiving
+    // our funds once the channel is closed; `fundingd` replies with
+    // `WalletScript`
+    #[lnp_api(type = 1306)]
+    #[display("get_wallet_script()")]
+    GetWalletScript,
+
+    // Issued by `fundingd` to `channeld` with the wallet script given out to
+    // the channel
+    #[lnp_api(type = 1307)]
+    #[display("wallet_script({0})")]
+    WalletScript(PubkeyScript),
+
     // Responses to CLI
     // ----------------
     #[lnp_api(type = 1002)]
@@ -146,12 +696,135 @@ pub enum Request {
     #[lnp_api(type = 1103)]
     #[display("peer_list({0})", alt = "{0:#}")]
     #[from]
-    PeerList(List<NodeAddr>),
+    PeerList(PeerList),
 
     #[lnp_api(type = 1104)]
     #[display("channel_list({0})", alt = "{0:#}")]
     #[from]
-    ChannelList(List<ChannelId>),
+    ChannelList(List<ChannelInfo>),
+
+    #[lnp_api(type = 1105)]
+    #[display("node_archive({0})")]
+    #[from]
+    NodeArchive(NodeArchive),
+
+    #[lnp_api(type = 1106)]
+    #[display("tower_list({0})", alt = "{0:#}")]
+    #[from]
+    TowerList(List<TowerInfo>),
+
+    #[lnp_api(type = 1107)]
+    #[display("lsp_info({0})", alt = "{0:#}")]
+    #[from]
+    LspInfo(LspOffer),
+
+    #[lnp_api(type = 1108)]
+    #[display("lsp_order({0})", alt = "{0:#}")]
+    #[from]
+    LspOrder(LspOrder),
+
+    #[lnp_api(type = 1109)]
+    #[display("lsp_order_list({0})", alt = "{0:#}")]
+    #[from]
+    LspOrderList(List<LspOrder>),
+
+    #[lnp_api(type = 1114)]
+    #[display("lsp_jit_offer({0})", alt = "{0:#}")]
+    #[from]
+    LspJitOffer(JitOffer),
+
+    #[lnp_api(type = 1110)]
+    #[display("lease_list({0})", alt = "{0:#}")]
+    #[from]
+    LeaseList(List<NodeLease>),
+
+    #[lnp_api(type = 1111)]
+    #[display("lease_quote({0})", alt = "{0:#}")]
+    #[from]
+    LeaseQuote(LeaseQuote),
+
+    #[lnp_api(type = 1112)]
+    #[display("swap_info({0})", alt = "{0:#}")]
+    #[from]
+    SwapInfo(SwapInfo),
+
+    #[lnp_api(type = 1113)]
+    #[display("swap_list({0})", alt = "{0:#}")]
+    #[from]
+    SwapList(List<SwapInfo>),
+
+    #[lnp_api(type = 1115)]
+    #[display("asset_balance_info({0})", alt = "{0:#}")]
+    #[from]
+    AssetBalanceInfo(AssetBalanceInfo),
+
+    #[lnp_api(type = 1116)]
+    #[display("accounting_report(...)")]
+    AccountingReport(String),
+
+    #[lnp_api(type = 1117)]
+    #[display("webhook_deliveries({0})", alt = "{0:#}")]
+    #[from]
+    WebhookDeliveries(List<WebhookDelivery>),
+
+    #[lnp_api(type = 1118)]
+    #[display("fee_estimate({0})")]
+    FeeEstimate(u32),
+
+    #[lnp_api(type = 1119)]
+    #[display("chain_info({0})", alt = "{0:#}")]
+    #[from]
+    ChainInfo(ChainInfo),
+
+    #[lnp_api(type = 1120)]
+    #[display("invoice_info({0})")]
+    #[from]
+    InvoiceInfo(InvoiceInfo),
+
+    #[lnp_api(type = 1121)]
+    #[display("static_backup(...)")]
+    StaticBackup(Vec<u8>),
+
+    #[lnp_api(type = 1122)]
+    #[display("wallet_info({0})", alt = "{0:#}")]
+    #[from]
+    WalletInfo(WalletInfo),
+
+    #[lnp_api(type = 1123)]
+    #[display("daemon_list({0})", alt = "{0:#}")]
+    #[from]
+    DaemonList(List<DaemonHealth>),
+
+    #[lnp_api(type = 1124)]
+    #[display("daemon_state(...)")]
+    DaemonState(Vec<u8>),
+
+    #[lnp_api(type = 1125)]
+    #[display("node_state({0})")]
+    #[from]
+    NodeState(NodeState),
+
+    #[lnp_api(type = 1126)]
+    #[display("peer_access({0})", alt = "{0:#}")]
+    #[from]
+    PeerAccess(PeerAccess),
+
+    #[lnp_api(type = 1127)]
+    #[display("node_list({0})", alt = "{0:#}")]
+    #[from]
+    NodeList(List<AnnouncedNode>),
+
+    #[lnp_api(type = 1128)]
+    #[display("graph_info({0})", alt = "{0:#}")]
+    #[from]
+    GraphInfo(GraphInfo),
+
+    // Issued by `channeld` to the enquirer of the transfer paying the given
+    // payment hash, once the remote peer fulfills the HTLC
+    #[lnp_api(type = 1130)]
+    #[display("payment_preimage({0})")]
+    #[from]
+    PaymentPreimage(HashPreimage),
 
     #[lnp_api(type = 1203)]
     #[display("channel_funding({0})", alt = "{0:#}")]
@@ -161,6 +834,87 @@ pub enum Request {
 
 impl rpc_connection::Request for Request {}
 
+impl Request {
+    /// Splits the peer message extended with TLV records into the plain
+    /// peer message request and its TLV stream; other requests are returned
+    /// with an empty stream
+    pub fn extract_tlvs(self) -> (Request, TlvStream) {
+        match self {
+            Request::ExtendedPeerMessage(ExtendedMessage { message, tlvs }) => {
+                (Request::PeerMessage(message), tlvs)
+            }
+            request => (request, empty!()),
+        }
+    }
+
+    /// Constructs the peer message request, extending the message with the
+    /// TLV stream unless it is empty
+    pub fn with_tlvs(message: Messages, tlvs: TlvStream) -> Request {
+        if tlvs.is_empty() {
+            Request::PeerMessage(message)
+        } else {
+            Request::ExtendedPeerMessage(ExtendedMessage { message, tlvs })
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{message}")]
+pub struct GossipBroadcast {
+    pub message: Messages,
+    /// TLV records of the message, which are covered by its signature
+    pub tlvs: TlvStream,
+    /// Peer connection from which the message was received, if any
+    pub origin: Option<ServiceId>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{node_addr}, {reason}: {details}")]
+pub struct PeerDead {
+    pub node_addr: NodeAddr,
+    pub reason: PeerDeadReason,
+    /// Human-readable details of the failure
+    pub details: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{node_id}, ...")]
+pub struct NodeAddresses {
+    pub node_id: secp256k1::PublicKey,
+    pub addrs: Vec<RemoteSocketAddr>,
+}
+
+/// Reason for considering the remote peer dead
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub enum PeerDeadReason {
+    /// Remote peer has not replied to the `ping` message in time
+    #[display("ping_timeout")]
+    PingTimeout,
+
+    /// Remote peer has not sent `init` message in time
+    #[display("init_timeout")]
+    InitTimeout,
+
+    /// Remote peer has sent messages not allowed by BOLT-1
+    #[display("protocol_violation")]
+    ProtocolViolation,
+
+    /// Remote peer has closed the connection, which was detected by reading
+    /// zero bytes from the socket
+    #[display("connection_closed")]
+    ConnectionClosed,
+
+    /// Reading from or writing to the connection has failed
+    #[display("transport_error")]
+    TransportError,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
 #[strict_encoding_crate(lnpbp::strict_encoding)]
 #[display("{peerd}, ...")]
@@ -168,6 +922,96 @@ pub struct CreateChannel {
     pub channel_req: message::OpenChannel,
     pub peerd: ServiceId,
     pub report_to: Option<ServiceId>,
+    /// Script receiving our funds on cooperative close, committed to the
+    /// channel upfront; if none is given, node-wide setting is used
+    pub shutdown_scriptpubkey: Option<PubkeyScript>,
+    /// Whether the funding transaction is constructed by the node funding
+    /// wallet `fundingd` instead of being provided with `FundChannel`
+    pub fund_from_wallet: bool,
+    /// Number of confirmations required from the funding transaction of
+    /// the accepted channel, overriding the node policy
+    pub minimum_depth: Option<u32>,
+    /// Batch of channels funded from the funding wallet with a single
+    /// transaction, which the channel belongs to
+    pub funding_batch: Option<FundingBatch>,
+    /// Whether the channel is kept private instead of being announced to
+    /// the network once its funding transaction is deep enough
+    pub private: bool,
+    /// TLV records of the `open_channel` message proposing the channel;
+    /// empty for the channels opened by the local node
+    pub remote_tlvs: TlvStream,
+}
+
+/// Request for opening multiple channels with the same remote peer, funded
+/// from the funding wallet with a single transaction
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{peerd}, ...")]
+pub struct ChannelBatch {
+    pub channel_reqs: Vec<message::OpenChannel>,
+    pub peerd: ServiceId,
+    pub report_to: Option<ServiceId>,
+    /// Script receiving our funds on cooperative close of each of the
+    /// channels; if none is given, node-wide setting is used
+    pub shutdown_scriptpubkey: Option<PubkeyScript>,
+    /// Whether the channels are kept private instead of being announced to
+    /// the network
+    pub private: bool,
+}
+
+/// Channels sharing a single funding transaction
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("batch #{batch_id} of {size}")]
+pub struct FundingBatch {
+    pub batch_id: u64,
+    /// Number of channels in the batch
+    pub size: u16,
+}
+
+/// Request to the funding wallet for the channel funding transaction
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{amount} sat to {script_pubkey}, {feerate_per_kw} sat/kw")]
+pub struct FundingRequest {
+    /// Funding output script. It is the first output of the transaction,
+    /// unless the channel is funded in a batch, where outputs follow the
+    /// order of the funding requests.
+    pub script_pubkey: PubkeyScript,
+    pub amount: u64,
+    pub feerate_per_kw: u32,
+    /// Batch which shares the funding transaction with other channels
+    pub batch: Option<FundingBatch>,
+}
+
+/// Request to the funding wallet for the child transaction bumping the fee
+/// of the published commitment transaction through our anchor output
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{anchor}, {feerate_per_kw} sat/kw")]
+pub struct CpfpRequest {
+    /// Anchor output, which is spent by the first input of the child
+    pub anchor: OutPoint,
+    pub anchor_value: u64,
+    /// Weight of the parent commitment transaction
+    pub parent_weight: u64,
+    /// Fee paid by the parent commitment transaction
+    pub parent_fee: u64,
+    /// Fee rate of the package of the parent and child transactions
+    pub feerate_per_kw: u32,
+}
+
+/// Child transaction constructed by the funding wallet, with all inputs
+/// signed except the anchor one
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{fee} sat")]
+pub struct CpfpChild {
+    pub tx: Transaction,
+    /// Fee paid by the child transaction
+    pub fee: u64,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
@@ -177,64 +1021,1427 @@ pub struct Transfer {
     pub channeld: ServiceId,
     pub amount: u64,
     pub asset: Option<AssetId>,
+    /// Payment hash of the invoice paid with the transfer; if absent the
+    /// HTLC is locked with a random preimage
+    pub payment_hash: Option<HashLock>,
 }
 
-#[cfg(feature = "rgb")]
 #[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
 #[strict_encoding_crate(lnpbp::strict_encoding)]
-#[display("{outpoint}, {blinding}, ...")]
-pub struct RefillChannel {
-    pub consignment: Consignment,
-    pub outpoint: OutPoint,
-    pub blinding: u64,
+#[display("{scriptpubkey:?}")]
+pub struct CloseChannel {
+    /// Script receiving our funds; if none is given, the funds are sent to
+    /// the node key
+    pub scriptpubkey: Option<PubkeyScript>,
 }
 
-#[cfg_attr(feature = "serde", serde_as)]
 #[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
-#[cfg_attr(
-    feature = "serde",
-    derive(Serialize, Deserialize),
-    serde(crate = "serde_crate")
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("include_secrets={include_secrets}")]
+pub struct ExportNode {
+    /// Whether the node private key must be included into the archive
+    pub include_secrets: bool,
+}
+
+/// Format of the exported bookkeeping records
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
 )]
 #[strict_encoding_crate(lnpbp::strict_encoding)]
-#[display(NodeInfo::to_yaml_string)]
-pub struct NodeInfo {
-    pub node_id: secp256k1::PublicKey,
-    pub listens: Vec<RemoteSocketAddr>,
-    #[serde_as(as = "DurationSeconds")]
-    pub uptime: Duration,
-    pub since: u64,
-    #[serde_as(as = "Vec<DisplayFromStr>")]
-    pub peers: Vec<NodeAddr>,
-    #[serde_as(as = "Vec<DisplayFromStr>")]
-    pub channels: Vec<ChannelId>,
+pub enum AccountingFormat {
+    /// Comma-separated values with one double-entry record per line
+    #[display("csv")]
+    Csv,
+
+    /// Plain text accounting ledger for beancount
+    #[display("beancount")]
+    Beancount,
 }
 
-#[cfg_attr(feature = "serde", serde_as)]
-#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
-#[cfg_attr(
-    feature = "serde",
-    derive(Serialize, Deserialize),
-    serde(crate = "serde_crate")
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode,
 )]
 #[strict_encoding_crate(lnpbp::strict_encoding)]
-#[display(PeerInfo::to_yaml_string)]
-pub struct PeerInfo {
-    pub local_id: secp256k1::PublicKey,
-    pub remote_id: Vec<secp256k1::PublicKey>,
-    #[serde_as(as = "Option<DisplayFromStr>")]
-    pub local_socket: Option<InetSocketAddr>,
-    #[serde_as(as = "Vec<DisplayFromStr>")]
-    pub remote_socket: Vec<InetSocketAddr>,
-    #[serde_as(as = "DurationSeconds")]
-    pub uptime: Duration,
-    pub since: u64,
+#[display("{format}, {since:?}..{till:?}")]
+pub struct ExportAccounting {
+    pub format: AccountingFormat,
+    /// Start of the exported period, as a UNIX timestamp (inclusive)
+    pub since: Option<u64>,
+    /// End of the exported period, as a UNIX timestamp (exclusive)
+    pub till: Option<u64>,
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub enum AccountingEventKind {
+    /// Funds moved from the on-chain wallet into a channel
+    #[display("channel_funded")]
+    ChannelFunded,
+
+    /// Off-chain payment sent; fee is the routing fee
+    #[display("payment_sent")]
+    PaymentSent,
+
+    /// Off-chain payment received
+    #[display("payment_received")]
+    PaymentReceived,
+
+    /// Miner fee paid by a transaction published by the node
+    #[display("onchain_fee")]
+    OnchainFee,
+
+    /// Fee paid to a service provider (swap, LSP etc)
+    #[display("service_fee_paid")]
+    ServiceFeePaid,
+
+    /// Fee earned by serving other nodes as a service provider
+    #[display("service_fee_earned")]
+    ServiceFeeEarned,
+
+    /// Payment forwarded by the node, which channels have booked as received
+    /// and sent; fee is the forwarding fee
+    #[display("payment_forwarded")]
+    PaymentForwarded,
+}
+
+/// Event affecting the node funds, from which bookkeeping records are
+/// derived
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{kind}, {amount_msat} msat, ...")]
+pub struct AccountingEvent {
+    pub kind: AccountingEventKind,
+    pub channel_id: Option<ChannelId>,
+    pub txid: Option<Txid>,
+    /// Asset of the amount; bitcoin if absent
+    pub asset: Option<AssetId>,
+    /// Amount in millisatoshis, or in atomic units for assets
+    pub amount_msat: u64,
+    /// Fee in millisatoshis paid on top of the amount
+    pub fee_msat: u64,
+    pub description: String,
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "snake_case")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub enum NodeEventKind {
+    /// Payment to the node is received
+    #[display("invoice_paid")]
+    InvoicePaid,
+
+    /// Payment sent by the node is settled
+    #[display("payment_settled")]
+    PaymentSettled,
+
+    /// Payment sent by the node has failed
+    #[display("payment_failed")]
+    PaymentFailed,
+
+    /// Channel funding is created and the channel got its permanent id
+    #[display("channel_opened")]
+    ChannelOpened,
+
+    /// Channel is closed
+    #[display("channel_closed")]
+    ChannelClosed,
+
+    /// Remote party has published revoked channel state
+    #[display("breach_detected")]
+    BreachDetected,
+
+    /// Remote peer has failed the channel with an error message
+    #[display("channel_failed")]
+    ChannelFailed,
+
+    /// Daemon launched by the node has crashed and is going to be restarted
+    #[display("daemon_crashed")]
+    DaemonCrashed,
+}
+
+/// Points in the node workflow at which plugins can be called
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Display,
+    StrictEncode,
+    StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub enum HookPoint {
+    /// Connection with a remote peer is established; notification only
+    #[display("peer_connected")]
+    PeerConnected,
+
+    /// Remote peer offers HTLC to a channel; can be rejected
+    #[display("htlc_accepted")]
+    HtlcAccepted,
+
+    /// Payment to the node is received; notification only
+    #[display("invoice_paid")]
+    InvoicePaid,
+
+    /// Remote peer proposes to open a channel; can be rejected
+    #[display("channel_proposal")]
+    ChannelProposal,
+}
+
+impl HookPoint {
+    /// Whether plugins may reject the action at this hook point, such that
+    /// the node has to wait for their decisions before proceeding
+    pub fn is_vetoable(self) -> bool {
+        match self {
+            HookPoint::HtlcAccepted | HookPoint::ChannelProposal => true,
+            HookPoint::PeerConnected | HookPoint::InvoicePaid => false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("#{id} {point}, ...")]
+pub struct HookCall {
+    /// Call identifier, which must be repeated in the [`HookResult`]
+    pub id: u64,
+    pub point: HookPoint,
+    pub peer: Option<NodeAddr>,
+    pub channel_id: Option<ChannelId>,
+    pub amount_msat: Option<u64>,
+    /// Payment hash of the HTLC for `htlc_accepted` hook point
+    pub payment_hash: Option<HashLock>,
+    pub details: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("#{id}, reject={reject:?}")]
+pub struct HookResult {
+    pub id: u64,
+    /// Reason for rejecting the action; if absent the node proceeds with
+    /// the action
+    pub reject: Option<String>,
+    /// Number of confirmations required before a channel proposed by the
+    /// remote peer can be used; amends `channel_proposal` action only
+    pub minimum_depth: Option<u32>,
+    /// Preimage with which the plugin resolves the HTLC on its own, such
+    /// that the node fulfills it without looking for the invoice; amends
+    /// `htlc_accepted` action only
+    pub preimage: Option<HashPreimage>,
+}
+
+/// Node event delivered to external systems via webhooks
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{kind}, ...")]
+pub struct NodeEvent {
+    pub kind: NodeEventKind,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub channel_id: Option<ChannelId>,
+    pub txid: Option<Txid>,
+    pub amount_msat: Option<u64>,
+    pub details: String,
+}
+
+/// Version of the [`NodeArchive`] format produced by the current code
+pub const NODE_ARCHIVE_VERSION: u16 = 1;
+
+/// Snapshot of the node state used for host migration and cold backups
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("v{version}, {node_id}, {chain}, ...")]
+pub struct NodeArchive {
+    pub version: u16,
+    pub chain: Chain,
+    pub node_id: secp256k1::PublicKey,
+    /// Node private key; present only if the archive was exported with
+    /// secrets
+    pub node_key: Option<secp256k1::SecretKey>,
+    pub listens: Vec<RemoteSocketAddr>,
+    pub peers: Vec<NodeAddr>,
+    pub channels: Vec<ChannelId>,
+    /// Output descriptors of the node wallets, which funds are rescanned
+    /// from the chain
+    pub wallet_descriptors: Vec<String>,
+}
+
+/// Version of the [`NodeState`] format produced by the current code
+pub const NODE_STATE_VERSION: u16 = 1;
+
+/// Complete node state gathered from all daemons, used for migration of the
+/// node between machines
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("v{version}, {archive}, ...")]
+pub struct NodeState {
+    pub version: u16,
+    pub archive: NodeArchive,
+    /// Stored data of the channels, as exported by their channel daemons
+    pub channels: Vec<ChannelSnapshot>,
+    /// State exported by `gossipd`, if it was running
+    pub gossip: Option<Vec<u8>>,
+    /// Invoices created by the node with their preimages
+    pub invoices: Vec<u8>,
+    /// Journal of the payments and other events affecting node funds
+    pub payments: Vec<u8>,
+}
+
+/// Stored data of a single channel
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{channel_id}")]
+pub struct ChannelSnapshot {
+    /// Id the channel daemon is launched with
+    pub channel_id: ChannelId,
+    pub data: Vec<u8>,
+}
+
+/// Channel data allowing to recover funds from the channel after the loss of
+/// the channel state, by asking the remote peer to close the channel
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{channel_id}, {remote_peer}, ...")]
+pub struct ChannelBackup {
+    pub channel_id: ChannelId,
+    /// Temporary channel id, from which our channel keys are derived
+    pub temporary_channel_id: TempChannelId,
+    /// Local randomness mixed into the derivation of our channel keys
+    pub key_salt: [u8; 32],
+    pub remote_peer: NodeAddr,
+    pub funding_outpoint: OutPoint,
+    pub funding_satoshis: u64,
+    pub is_originator: bool,
+    pub anchors: bool,
+    pub static_remotekey: bool,
+    pub local_keys: payment::channel::Keyset,
+    pub remote_keys: payment::channel::Keyset,
+}
+
+/// Channel daemon receiving peer messages addressed by the final channel id
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{channel_id} -> {channeld}")]
+pub struct ChannelRoute {
+    pub channel_id: ChannelId,
+    pub channeld: ServiceId,
+}
+
+/// Registration data which each daemon sends to `lnpd` on its startup
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{service}@{chain}, pid={pid}, ...")]
+pub struct DaemonInfo {
+    /// Service id under which the daemon is connected to the service buses
+    pub service: ServiceId,
+    /// Chain served by the daemon; `lnpd` of each chain accepts only the
+    /// daemons of the same chain
+    pub chain: Chain,
+    pub pid: u32,
+    /// Remote node the daemon is connected to, for peerd
+    pub remote_node: Option<secp256k1::PublicKey>,
+    /// Channel operated by the daemon, for channeld
+    pub channel_id: Option<ChannelId>,
+}
+
+impl DaemonInfo {
+    pub fn with(service: ServiceId, chain: Chain) -> DaemonInfo {
+        let (remote_node, channel_id) = match service {
+            ServiceId::Peer(ref node_addr) => (Some(node_addr.id), None),
+            ServiceId::Channel(channel_id) => (None, Some(channel_id)),
+            _ => (None, None),
+        };
+        DaemonInfo {
+            service,
+            chain,
+            pid: std::process::id(),
+            remote_node,
+            channel_id,
+        }
+    }
+}
+
+/// Crash of a daemon launched by `lnpd`
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{name}, pid={pid}, {status}, ...")]
+pub struct DaemonCrash {
+    /// Name of the daemon binary
+    pub name: String,
+    /// Service id of the daemon, if it is known at launch
+    pub service: Option<ServiceId>,
+    pub pid: u32,
+    /// Exit status of the daemon process
+    pub status: String,
+    /// Delay before the daemon restart, in seconds
+    pub restart_delay: u64,
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("max_updates={max_updates}")]
+pub struct TowerSession {
+    /// Session to renew, or the session opened by the tower in its reply
+    pub session: Option<TowerSessionId>,
+    /// Maximum number of justice blobs the client is going to upload within
+    /// the session
+    pub max_updates: u16,
+}
+
+/// Identifier of a watchtower session, generated by the tower at random
+#[derive(
+    Wrapper,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Display,
+    From,
+    StrictEncode,
+    StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{0:016x}")]
+pub struct TowerSessionId(u64);
+
+/// Messages of the watchtower protocol, exchanged between `wtclientd` and
+/// `towerd` of the remote node as custom peer messages
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub enum TowerMessage {
+    /// Opens a new session or renews the given one, resetting its quota
+    #[display("create_session({0})")]
+    CreateSession(TowerSession),
+
+    #[display("append_blob({0}, {1})")]
+    AppendBlob(TowerSessionId, JusticeBlob),
+
+    /// Reply of the tower with the opened session
+    #[display("session_created({0})")]
+    SessionCreated(TowerSession),
+
+    #[display("blob_accepted({0}, {1})")]
+    BlobAccepted(TowerSessionId, BreachHint),
+
+    #[display("rejected({0})")]
+    Rejected(String),
+}
+
+/// Smallest type of the custom peer messages (BOLT-1), which are relayed by
+/// `peerd` to the daemons implementing the corresponding protocols
+pub const CUSTOM_MESSAGE_TYPE_MIN: u16 = 32768;
+
+/// Type of `stfu` message (quiescence), which is not known to the LN message
+/// unmarshaller and is relayed by `peerd` as a custom message
+pub const STFU_TYPE: u16 = 2;
+
+/// Whether the peer messages of the given type are relayed by `peerd` as
+/// custom messages
+pub fn is_custom_type(msg_type: u16) -> bool {
+    msg_type >= CUSTOM_MESSAGE_TYPE_MIN || msg_type == STFU_TYPE
+}
+
+/// Custom message type of the watchtower requests sent to `towerd`
+pub const TOWER_REQUEST_TYPE: u16 = 42001;
+
+/// Custom message type of the watchtower replies sent to `wtclientd`
+pub const TOWER_REPLY_TYPE: u16 = 42003;
+
+/// LSPS0 custom message type carrying JSON-RPC messages of the lightning
+/// service provider protocols, which are sent to `lspd`
+pub const LSPS0_MESSAGE_TYPE: u16 = 37913;
+
+/// Peer message of a custom type, which is not known to the LN message
+/// unmarshaller
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{msg_type}, ...")]
+pub struct CustomMessage {
+    /// Node id of the remote peer which has sent or has to receive the
+    /// message
+    pub remote_id: secp256k1::PublicKey,
+    pub msg_type: u16,
+    pub payload: Vec<u8>,
+}
+
+impl CustomMessage {
+    pub fn with(
+        remote_id: secp256k1::PublicKey,
+        msg_type: u16,
+        data: &impl StrictEncode,
+    ) -> Self {
+        CustomMessage {
+            remote_id,
+            msg_type,
+            payload: strict_serialize(data)
+                .expect("Memory-based encoding does not fail"),
+        }
+    }
+}
+
+/// Peer message together with the TLV stream extending it
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{message}, ...")]
+pub struct ExtendedMessage {
+    pub message: Messages,
+    pub tlvs: TlvStream,
+}
+
+/// First half of the breach transaction id, used by a watchtower to match
+/// justice blobs against mined transactions without being able to decrypt
+/// the blob before the breach happens
+#[derive(
+    Wrapper,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Display,
+    From,
+    StrictEncode,
+    StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{0:032x}")]
+pub struct BreachHint(u128);
+
+impl BreachHint {
+    pub fn with(txid: &Txid) -> Self {
+        let mut buf = [0u8; 16];
+        buf.copy_from_slice(&txid[..16]);
+        Self(u128::from_be_bytes(buf))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{hint}, ...")]
+pub struct JusticeBlob {
+    pub hint: BreachHint,
+    /// Penalty transaction encrypted with the key derived from the breach
+    /// transaction id
+    pub encrypted: Vec<u8>,
+}
+
+/// Request for an inbound channel from a lightning service provider
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{lsp_balance_sat} sat, zero_conf={zero_conf}")]
+pub struct LspOrderRequest {
+    /// Channel capacity funded by the provider
+    pub lsp_balance_sat: u64,
+    /// Whether the channel must be usable before the funding transaction is
+    /// confirmed
+    pub zero_conf: bool,
+}
+
+/// Terms under which a lightning service provider sells channels
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(LspOffer::to_yaml_string)]
+pub struct LspOffer {
+    pub min_channel_sat: u64,
+    pub max_channel_sat: u64,
+    pub fee_base_sat: u64,
+    pub fee_ppm: u32,
+    pub zero_conf: bool,
+}
+
+impl LspOffer {
+    /// Fee the provider charges for opening a channel of the given capacity
+    pub fn fee_for(&self, lsp_balance_sat: u64) -> u64 {
+        self.fee_base_sat
+            + lsp_balance_sat.saturating_mul(self.fee_ppm as u64) / 1_000_000
+    }
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub enum LspOrderState {
+    /// The order waits for the fee payment
+    #[display("awaiting_payment")]
+    AwaitingPayment,
+
+    /// The fee is paid and the channel is being opened
+    #[display("opening")]
+    Opening,
+
+    /// The channel awaits funding transaction
+    #[display("funding")]
+    Funding,
+
+    /// The channel can't be opened
+    #[display("failed")]
+    Failed,
+}
+
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(LspOrder::to_yaml_string)]
+pub struct LspOrder {
+    pub order_id: String,
+    /// Client of the order sold by us, or provider of the order we have
+    /// bought
+    #[serde_as(as = "DisplayFromStr")]
+    pub peer: NodeAddr,
+    pub lsp_balance_sat: u64,
+    pub zero_conf: bool,
+    pub fee_sat: u64,
+    /// Payment hash of the provider invoice for the order fee
+    #[serde_as(as = "DisplayFromStr")]
+    pub payment_hash: sha256::Hash,
+    pub state: LspOrderState,
+}
+
+/// Terms of a just-in-time channel: the provider opens the channel to the
+/// client once a payment is forwarded to the `scid` alias, and deducts the
+/// fee from the forwarded amount
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(JitOffer::to_yaml_string)]
+pub struct JitOffer {
+    /// Short channel id alias the payer must route the payment to
+    #[serde_as(as = "DisplayFromStr")]
+    pub scid: ShortChannelId,
+    /// CLTV expiry delta of the provider hop in the route to the client
+    pub cltv_expiry_delta: u16,
+    /// Payment hash of the client invoice which is paid through the channel
+    #[serde_as(as = "DisplayFromStr")]
+    pub payment_hash: sha256::Hash,
+    pub min_fee_msat: u64,
+    /// Fee in millionths of the forwarded amount
+    pub proportional: u32,
+}
+
+impl JitOffer {
+    /// Fee deducted from the forwarded payment, in millisatoshis, as it is
+    /// defined by LSPS2
+    pub fn fee_for(&self, amount_msat: u64) -> u64 {
+        let fee = amount_msat
+            .saturating_mul(self.proportional as u64)
+            .saturating_add(999_999)
+            / 1_000_000;
+        fee.max(self.min_fee_msat)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("#{htlc_id} {amount_msat} msat to {scid}, {payment_hash}")]
+pub struct InterceptedHtlc {
+    /// Id of the HTLC offered by the remote peer of the channel daemon
+    /// sending the request
+    pub htlc_id: u64,
+    /// Short channel id of the next hop
+    pub scid: u64,
+    pub payment_hash: HashLock,
+    pub amount_msat: u64,
+}
+
+/// Payment which HTLCs are forwarded to the just-in-time channel with the
+/// short channel id alias
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{payment_hash} to {scid}")]
+pub struct PaymentInterception {
+    pub payment_hash: HashLock,
+    pub scid: u64,
+}
+
+/// HTLC which is forwarded to the next hop by the intercepting daemon
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("#{htlc_id} to {scid} via {interceptor}")]
+pub struct HtlcForward {
+    pub htlc_id: u64,
+    pub scid: u64,
+    pub interceptor: ServiceId,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{amount_msat} msat, expiry {expiry}s")]
+pub struct InvoiceRequest {
+    /// Amount to be paid, in millisatoshis or atomic asset units
+    pub amount_msat: u64,
+    /// Asset of the payment; bitcoin if absent
+    pub asset_id: Option<AssetId>,
+    /// Time, in seconds, during which the invoice may be paid
+    pub expiry: u32,
+}
+
+/// Invoice created by the node, which may be paid by HTLCs with its payment
+/// hash
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{payment_hash}: {amount_msat} msat, expires at {expires_at}")]
+pub struct InvoiceInfo {
+    pub payment_hash: HashLock,
+    pub amount_msat: u64,
+    pub asset_id: Option<AssetId>,
+    /// Minimal number of blocks left before expiry of the paying HTLCs
+    pub min_final_cltv_expiry: u32,
+    /// UNIX timestamp after which the invoice can't be paid
+    pub expires_at: u64,
+}
+
+/// HTLC offered by the remote peer and paying to the local node
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("#{htlc_id} {amount_msat} msat, {payment_hash}")]
+pub struct IncomingHtlc {
+    pub htlc_id: u64,
+    pub payment_hash: HashLock,
+    pub amount_msat: u64,
+    pub asset_id: Option<AssetId>,
+    pub cltv_expiry: u32,
+    /// Current chain height known to the channel daemon
+    pub chain_height: Option<u32>,
+}
+
+/// Preimage settling an incoming HTLC, or absence of it if the HTLC does not
+/// match a payable invoice and must be failed
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("#{htlc_id}")]
+pub struct HtlcSettlement {
+    pub htlc_id: u64,
+    pub preimage: Option<HashPreimage>,
+}
+
+/// Liquidity lease rates advertised by a node willing to fund channels
+/// (`option_will_fund`)
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(LeaseRates::to_yaml_string)]
+pub struct LeaseRates {
+    /// Weight of the funding inputs and outputs added by the seller, which
+    /// is paid by the buyer
+    pub funding_weight: u16,
+    /// Proportional lease fee, in basis points of the leased amount
+    pub lease_fee_basis: u16,
+    /// Maximal proportional routing fee the seller charges over the leased
+    /// channel, in thousandths
+    pub channel_fee_max_proportional_thousandths: u16,
+    /// Fixed lease fee, in satoshis
+    pub lease_fee_base_sat: u32,
+    /// Maximal base routing fee the seller charges over the leased channel
+    pub channel_fee_max_base_msat: u32,
+}
+
+impl LeaseRates {
+    /// Total fee the buyer pays for leasing given amount with the funding
+    /// transaction paying given fee rate
+    pub fn lease_fee(&self, amount_sat: u64, feerate_per_kw: u32) -> u64 {
+        self.lease_fee_base_sat as u64
+            + amount_sat.saturating_mul(self.lease_fee_basis as u64) / 10_000
+            + self.funding_weight as u64 * feerate_per_kw as u64 / 1000
+    }
+
+    /// Parses `lease_rates` from its wire encoding, failing on the
+    /// non-minimal encoding of the truncated integer
+    pub fn from_bytes(data: &[u8]) -> Option<LeaseRates> {
+        if data.len() < 10 || data.len() > 14 || data.get(10) == Some(&0) {
+            return None;
+        }
+        let u16_at =
+            |pos: usize| u16::from_be_bytes([data[pos], data[pos + 1]]);
+        let u32_from = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .fold(0u32, |value, byte| (value << 8) | *byte as u32)
+        };
+        Some(LeaseRates {
+            funding_weight: u16_at(0),
+            lease_fee_basis: u16_at(2),
+            channel_fee_max_proportional_thousandths: u16_at(4),
+            lease_fee_base_sat: u32_from(&data[6..10]),
+            channel_fee_max_base_msat: u32_from(&data[10..]),
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(NodeLease::to_yaml_string)]
+pub struct NodeLease {
+    pub node_id: secp256k1::PublicKey,
+    pub rates: LeaseRates,
+}
+
+/// Remote node known from its `node_announcement`
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(AnnouncedNode::to_yaml_string)]
+pub struct AnnouncedNode {
+    pub node_id: secp256k1::PublicKey,
+    /// Timestamp of the latest announcement of the node
+    pub timestamp: u32,
+    pub alias: String,
+    /// RGB color of the node, in `#rrggbb` form
+    pub color: String,
+    /// Feature bits advertised by the node
+    pub features: Vec<u16>,
+    pub addresses: Vec<RemoteSocketAddr>,
+    /// Number of the public channels of the node
+    pub channels: usize,
+}
+
+/// Part of the network graph requested with `DescribeGraph`
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Debug,
+    Default,
+    Display,
+    StrictEncode,
+    StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(Debug)]
+pub struct GraphFilter {
+    /// Node which channels, together with their other nodes, are described;
+    /// if absent, the whole graph is described
+    pub node_id: Option<secp256k1::PublicKey>,
+    /// Skip the channels disabled in both directions
+    pub active_only: bool,
+}
+
+/// Public channel of the network graph known from gossip
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct GraphEdge {
+    #[serde_as(as = "DisplayFromStr")]
+    pub short_channel_id: ShortChannelId,
+    pub node_id_1: secp256k1::PublicKey,
+    pub node_id_2: secp256k1::PublicKey,
+    pub capacity_sat: u64,
+    /// Policy of forwarding from the first node, unless it is not announced
+    pub policy_1: Option<EdgePolicy>,
+    /// Policy of forwarding from the second node, unless it is not announced
+    pub policy_2: Option<EdgePolicy>,
+}
+
+/// Direction of the public channel, as announced by its latest
+/// `channel_update`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct EdgePolicy {
+    pub timestamp: u32,
+    pub disabled: bool,
+    pub policy: ForwardingPolicy,
+}
+
+/// Nodes and channels of the network graph known to gossipd
+#[derive(
+    Clone, PartialEq, Eq, Debug, Default, Display, StrictEncode, StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(GraphInfo::to_yaml_string)]
+pub struct GraphInfo {
+    pub nodes: Vec<AnnouncedNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{amount_sat} sat from {node_id} at {feerate_per_kw} sat/kw")]
+pub struct LeaseRequest {
+    pub node_id: secp256k1::PublicKey,
+    pub amount_sat: u64,
+    pub feerate_per_kw: u32,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(LeaseQuote::to_yaml_string)]
+pub struct LeaseQuote {
+    pub node_id: secp256k1::PublicKey,
+    pub amount_sat: u64,
+    pub fee_sat: u64,
+    pub rates: LeaseRates,
+}
+
+/// Policy of forwarding payments over a channel, announced to the network
+/// with `channel_update` messages
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(ForwardingPolicy::to_yaml_string)]
+pub struct ForwardingPolicy {
+    /// Fixed fee charged for each forwarded payment, in millisatoshis
+    pub fee_base_msat: u32,
+    /// Proportional fee charged for each forwarded payment, in millionths
+    /// of the forwarded amount
+    pub fee_proportional_millionths: u32,
+    /// Number of blocks subtracted from the expiry of the forwarded HTLCs
+    pub cltv_expiry_delta: u16,
+    /// Minimal amount of the forwarded HTLCs, in millisatoshis
+    pub htlc_minimum_msat: u64,
+    /// Maximal amount of the forwarded HTLCs, in millisatoshis; if absent,
+    /// limited by the channel capacity only
+    pub htlc_maximum_msat: Option<u64>,
+}
+
+impl ForwardingPolicy {
+    /// Fee charged for forwarding given amount
+    pub fn fee_msat(&self, amount_msat: u64) -> u64 {
+        self.fee_base_msat as u64
+            + amount_msat
+                .saturating_mul(self.fee_proportional_millionths as u64)
+                / 1_000_000
+    }
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{channel_id}: {policy}")]
+pub struct ChannelPolicyUpdate {
+    pub channel_id: ChannelId,
+    pub policy: ForwardingPolicy,
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub enum SwapDirection {
+    /// On-chain funds are swapped for an off-chain payment, increasing
+    /// outbound channel liquidity
+    #[display("in")]
+    In,
+
+    /// Off-chain payment is swapped for on-chain funds, increasing inbound
+    /// channel liquidity
+    #[display("out")]
+    Out,
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("swap-{direction} {amount_sat} sat via {channel_id}")]
+pub struct SwapRequest {
+    pub direction: SwapDirection,
+    pub amount_sat: u64,
+    /// Channel with the swap provider carrying the off-chain payment
+    pub channel_id: ChannelId,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("swap-{direction} {amount_sat} sat via {channel_id}")]
+pub struct SwapProposal {
+    pub direction: SwapDirection,
+    pub amount_sat: u64,
+    pub channel_id: ChannelId,
+    /// Payment hash of the invoice created by the swap client for the
+    /// off-chain part of a swap-in; the provider creates the invoice for a
+    /// swap-out
+    pub payment_hash: Option<sha256::Hash>,
+    pub client_pubkey: secp256k1::PublicKey,
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub enum SwapState {
+    /// The swap HTLC output awaits funding
+    #[display("created")]
+    Created,
+
+    /// The swap HTLC output is funded
+    #[display("funded")]
+    Funded,
+
+    /// The off-chain payment of the swap is fulfilled
+    #[display("paid")]
+    Paid,
+
+    /// The swap HTLC output is spent with the preimage
+    #[display("claimed")]
+    Claimed,
+
+    /// The swap HTLC output is spent back after the timeout
+    #[display("refunded")]
+    Refunded,
+}
+
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(SwapInfo::to_yaml_string)]
+pub struct SwapInfo {
+    /// Payment hash of the invoice paying the off-chain part of the swap
+    pub payment_hash: sha256::Hash,
+    pub direction: SwapDirection,
+    pub amount_sat: u64,
+    pub fee_sat: u64,
+    /// Channel between the client and the provider carrying the off-chain
+    /// payment
+    #[serde_as(as = "DisplayFromStr")]
+    pub channel_id: ChannelId,
+    pub client_pubkey: secp256k1::PublicKey,
+    pub provider_pubkey: secp256k1::PublicKey,
+    /// Number of blocks after the funding after which the HTLC output can be
+    /// refunded
+    pub timeout: u16,
+    /// `scriptPubkey` of the HTLC output to fund
+    pub htlc_script: Script,
+    pub state: SwapState,
+}
+
+#[cfg(feature = "rgb")]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{outpoint}, {blinding}, ...")]
+pub struct RefillChannel {
+    pub consignment: Consignment,
+    pub outpoint: OutPoint,
+    pub blinding: u64,
+}
+
+/// Funding of a new channel with RGB20 assets, which are assigned to the
+/// channel funding output by the consignment
+#[cfg(feature = "rgb")]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{funding_outpoint}, {blinding}, ...")]
+pub struct AssetFunding {
+    pub funding_outpoint: OutPoint,
+    pub consignment: Consignment,
+    pub blinding: u64,
+}
+
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(NodeInfo::to_yaml_string)]
+pub struct NodeInfo {
+    pub node_id: secp256k1::PublicKey,
+    pub alias: Option<String>,
+    pub version: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub chain: Chain,
+    /// All chains served by the node, each with its own node id
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub chains: Vec<Chain>,
+    pub listens: Vec<RemoteSocketAddr>,
+    /// Address of the node onion service, if it is published through Tor
+    /// control port
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub onion: Option<InetSocketAddr>,
+    #[serde_as(as = "DurationSeconds")]
+    pub uptime: Duration,
+    pub since: u64,
+    pub peer_count: usize,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub peers: Vec<NodeAddr>,
+    pub channel_count: usize,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub channels: Vec<ChannelId>,
+    /// Optional feature bits supported by the node with the feature names
+    pub features: BTreeMap<u16, String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(PeerList::to_yaml_string)]
+pub struct PeerList {
+    pub peers: Vec<PeerInfo>,
+    /// Number of used slots for the connections accepted from remote peers
+    pub inbound_slots: usize,
+    /// Maximal number of connections accepted from remote peers, if limited
+    pub max_inbound_slots: Option<usize>,
+}
+
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(PeerInfo::to_yaml_string)]
+pub struct PeerInfo {
+    pub local_id: secp256k1::PublicKey,
+    pub remote_id: Vec<secp256k1::PublicKey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub local_socket: Option<InetSocketAddr>,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub remote_socket: Vec<InetSocketAddr>,
+    #[serde_as(as = "DurationSeconds")]
+    pub uptime: Duration,
+    pub since: u64,
     pub messages_sent: usize,
     pub messages_received: usize,
     #[serde_as(as = "Vec<DisplayFromStr>")]
     pub channels: Vec<ChannelId>,
     pub connected: bool,
+    /// Whether the connection is accepted from the remote peer
+    pub inbound: bool,
     pub awaits_pong: bool,
+    /// Features negotiated with the remote peer, once `init` messages are
+    /// exchanged
+    pub features: Option<PeerFeatures>,
+    /// Traffic exchanged with the remote peer, indexed by the message type
+    pub traffic: BTreeMap<u16, MessageTraffic>,
+}
+
+/// Number and total size of the messages of a single type exchanged with
+/// the remote peer
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Default,
+    Display,
+    StrictEncode,
+    StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(Debug)]
+pub struct MessageTraffic {
+    pub messages_sent: usize,
+    pub messages_received: usize,
+    /// Size of the serialized messages sent, excluding transport framing
+    pub bytes_sent: usize,
+    /// Size of the serialized messages received, excluding transport
+    /// framing
+    pub bytes_received: usize,
+    /// Received messages dropped for exceeding the rate limits
+    pub messages_throttled: usize,
+}
+
+/// Features negotiated with the remote peer in `init` messages
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Default,
+    Display,
+    StrictEncode,
+    StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(Debug)]
+pub struct PeerFeatures {
+    /// Both nodes support `option_data_loss_protect`
+    pub data_loss_protect: bool,
+    /// Both nodes support `option_static_remotekey`
+    pub static_remotekey: bool,
+    /// Both nodes support `option_support_large_channel`
+    pub large_channel: bool,
+    /// Remote peer requests the full routing table on connection
+    pub initial_routing_sync: bool,
+    /// Both nodes support `gossip_queries`, so the gossip is relayed only
+    /// according to the `gossip_timestamp_filter` of the peer
+    pub gossip_queries: bool,
+}
+
+/// Range of IP addresses given by the network address and prefix length
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{addr}/{prefix}")]
+pub struct IpRange {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl IpRange {
+    fn max_prefix(addr: IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// Checks whether the address belongs to the range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        fn masked(bits: u128, len: u8, prefix: u8) -> u128 {
+            match prefix {
+                0 => 0,
+                prefix => bits >> (len - prefix),
+            }
+        }
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                masked(u32::from(range) as u128, 32, self.prefix)
+                    == masked(u32::from(ip) as u128, 32, self.prefix)
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                masked(u128::from(range), 128, self.prefix)
+                    == masked(u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = IpRangeError;
+
+    /// Parses `<ip>[/<prefix>]` string; address without prefix is a range
+    /// of a single address
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || IpRangeError(s.to_owned());
+        let mut split = s.splitn(2, '/');
+        let addr = IpAddr::from_str(split.next().unwrap_or_default())
+            .map_err(|_| err())?;
+        let prefix = match split.next() {
+            Some(prefix) => prefix.parse().map_err(|_| err())?,
+            None => IpRange::max_prefix(addr),
+        };
+        if prefix > IpRange::max_prefix(addr) {
+            return Err(err());
+        }
+        Ok(IpRange { addr, prefix })
+    }
+}
+
+/// Error parsing IP address range
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display("invalid IP address range `{0}`")]
+pub struct IpRangeError(String);
+
+/// Access lists of remote peers. Checked by `peerd` for each incoming
+/// connection before the session with the remote peer is established, and
+/// by `lnpd` before connecting to remote peers.
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(
+    Clone, PartialEq, Eq, Debug, Default, Display, StrictEncode, StrictDecode,
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(PeerAccess::to_yaml_string)]
+pub struct PeerAccess {
+    /// Only peers matching the allowlist may connect to the node
+    pub allowlist_only: bool,
+    pub allowed_nodes: Vec<secp256k1::PublicKey>,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub allowed_ips: Vec<IpRange>,
+    pub denied_nodes: Vec<secp256k1::PublicKey>,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub denied_ips: Vec<IpRange>,
+}
+
+impl PeerAccess {
+    /// Checks whether connections with the remote node are allowed
+    pub fn admits_node(&self, node_id: secp256k1::PublicKey) -> bool {
+        !self.denied_nodes.contains(&node_id)
+            && (!self.allowlist_only || self.allowed_nodes.contains(&node_id))
+    }
+
+    /// Checks whether connections from the given IP address are allowed.
+    /// Node ids of incoming connections are not known before the session
+    /// is established, so in allowlist-only mode these have to come from
+    /// the allowed IP ranges.
+    pub fn admits_ip(&self, ip: IpAddr) -> bool {
+        !self.denied_ips_contain(ip)
+            && (!self.allowlist_only
+                || self.allowed_ips.iter().any(|range| range.contains(ip)))
+    }
+
+    /// Checks whether the address belongs to any of the denied IP ranges
+    pub fn denied_ips_contain(&self, ip: IpAddr) -> bool {
+        self.denied_ips.iter().any(|range| range.contains(ip))
+    }
+
+    /// Applies the update, returning whether anything has changed
+    pub fn update(&mut self, update: &PeerAccessUpdate) -> bool {
+        fn apply<T: PartialEq + Copy>(
+            list: &mut Vec<T>,
+            items: &[T],
+            remove: bool,
+        ) -> bool {
+            let len = list.len();
+            if remove {
+                list.retain(|item| !items.contains(item));
+            } else {
+                for item in items {
+                    if !list.contains(item) {
+                        list.push(*item);
+                    }
+                }
+            }
+            len != list.len()
+        }
+
+        let (nodes, ips) = if update.allow {
+            (&mut self.allowed_nodes, &mut self.allowed_ips)
+        } else {
+            (&mut self.denied_nodes, &mut self.denied_ips)
+        };
+        let mut changed = apply(nodes, &update.nodes, update.remove);
+        changed |= apply(ips, &update.ip_ranges, update.remove);
+        if let Some(allowlist_only) = update.allowlist_only {
+            changed |= self.allowlist_only != allowlist_only;
+            self.allowlist_only = allowlist_only;
+        }
+        changed
+    }
+}
+
+/// Modification of the remote peer access lists
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(Debug)]
+pub struct PeerAccessUpdate {
+    /// Whether the allowlist is modified, rather than the denylist
+    pub allow: bool,
+    /// Whether the entries are removed from the list instead of being added
+    pub remove: bool,
+    pub nodes: Vec<secp256k1::PublicKey>,
+    pub ip_ranges: Vec<IpRange>,
+    /// Switches allowlist-only mode, if given
+    pub allowlist_only: Option<bool>,
 }
 
 pub type RemotePeerMap<T> = BTreeMap<NodeAddr, T>;
@@ -255,6 +2462,7 @@ pub struct ChannelInfo {
     #[serde_as(as = "DisplayFromStr")]
     pub temporary_channel_id: TempChannelId,
     pub state: Lifecycle,
+    /// Bitcoin balances of the channel sides in satoshis
     pub local_capacity: u64,
     #[serde_as(as = "BTreeMap<DisplayFromStr, Same>")]
     pub remote_capacities: RemotePeerMap<u64>,
@@ -266,7 +2474,19 @@ pub struct ChannelInfo {
         as = "BTreeMap<DisplayFromStr, BTreeMap<DisplayFromStr, Same>>"
     )]
     pub remote_balances: RemotePeerMap<AssetsBalance>,
+    /// Asset amounts locked in HTLCs offered by the local node
+    #[serde_as(as = "BTreeMap<DisplayFromStr, Same>")]
+    pub offered_in_flight: AssetsBalance,
+    /// Asset amounts locked in HTLCs offered by the remote peer
+    #[serde_as(as = "BTreeMap<DisplayFromStr, Same>")]
+    pub received_in_flight: AssetsBalance,
     pub funding_outpoint: OutPoint,
+    /// Short channel id alias used for the channel before it is confirmed
+    /// and in route hints of unannounced channels
+    pub scid_alias: u64,
+    /// Short channel id alias assigned to the channel by the remote peer,
+    /// which is used in route hints to the local node
+    pub remote_scid_alias: Option<u64>,
     #[serde_as(as = "Vec<DisplayFromStr>")]
     pub remote_peers: Vec<NodeAddr>,
     #[serde_as(as = "DurationSeconds")]
@@ -280,6 +2500,241 @@ pub struct ChannelInfo {
     pub local_keys: payment::channel::Keyset,
     #[serde_as(as = "BTreeMap<DisplayFromStr, Same>")]
     pub remote_keys: BTreeMap<NodeAddr, payment::channel::Keyset>,
+    pub forwarding_policy: ForwardingPolicy,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(TowerInfo::to_yaml_string)]
+pub struct TowerInfo {
+    pub tower: String,
+    pub session_open: bool,
+    pub acked: u64,
+    pub backlog: usize,
+    pub last_ack: Option<u64>,
+    pub healthy: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{channel_id}, ...")]
+pub struct ChannelBalances {
+    pub channel_id: ChannelId,
+    pub balances: AssetsBalance,
+}
+
+/// Local asset balances of the node aggregated across all its channels
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(AssetBalanceInfo::to_yaml_string)]
+pub struct AssetBalanceInfo {
+    #[serde_as(as = "BTreeMap<DisplayFromStr, Same>")]
+    pub total: AssetsBalance,
+    #[serde_as(
+        as = "BTreeMap<DisplayFromStr, BTreeMap<DisplayFromStr, Same>>"
+    )]
+    pub channels: BTreeMap<ChannelId, AssetsBalance>,
+}
+
+/// Transaction mined under a given number of blocks
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{txid}, depth: {depth}")]
+pub struct TxDepth {
+    pub txid: Txid,
+    pub depth: u32,
+}
+
+/// Location of the channel funding output in the chain (BOLT-7)
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Display,
+    StrictEncode,
+    StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{block_height}x{tx_index}x{output_index}")]
+pub struct ShortChannelId {
+    pub block_height: u32,
+    pub tx_index: u32,
+    pub output_index: u16,
+}
+
+impl From<u64> for ShortChannelId {
+    fn from(id: u64) -> Self {
+        ShortChannelId {
+            block_height: (id >> 40) as u32 & 0xFF_FFFF,
+            tx_index: (id >> 16) as u32 & 0xFF_FFFF,
+            output_index: id as u16,
+        }
+    }
+}
+
+impl FromStr for ShortChannelId {
+    type Err = ShortChannelIdError;
+
+    /// Parses `<block>x<tx>x<output>` string
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ShortChannelIdError(s.to_owned());
+        let mut split = s.split('x');
+        let mut next = || split.next().ok_or_else(err);
+        let block_height = next()?.parse().map_err(|_| err())?;
+        let tx_index = next()?.parse().map_err(|_| err())?;
+        let output_index = next()?.parse().map_err(|_| err())?;
+        if split.next().is_some()
+            || block_height > 0xFF_FFFF
+            || tx_index > 0xFF_FFFF
+        {
+            return Err(err());
+        }
+        Ok(ShortChannelId {
+            block_height,
+            tx_index,
+            output_index,
+        })
+    }
+}
+
+/// Error parsing short channel id
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display("invalid short channel id `{0}`")]
+pub struct ShortChannelIdError(String);
+
+impl From<ShortChannelId> for u64 {
+    fn from(id: ShortChannelId) -> Self {
+        (id.block_height as u64) << 40
+            | (id.tx_index as u64) << 16
+            | id.output_index as u64
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{short_channel_id}, ...")]
+pub struct ChannelOutput {
+    pub short_channel_id: ShortChannelId,
+    /// Funding output, unless it does not exist or is already spent
+    pub output: Option<TxOut>,
+    /// Funding outpoint, present together with the funding output
+    pub outpoint: Option<OutPoint>,
+}
+
+/// Position of the mined output in the chain, which is the short channel id
+/// of the channel funded by it
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode,
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{outpoint}, ...")]
+pub struct OutputLocation {
+    pub outpoint: OutPoint,
+    /// Position of the output, unless it is not mined yet
+    pub short_channel_id: Option<ShortChannelId>,
+}
+
+/// Replacement of the top blocks of the simulated chain
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display("{depth}, evict: {evict}")]
+pub struct ChainReorg {
+    /// Number of blocks disconnected from the chain tip
+    pub depth: u32,
+
+    /// Whether transactions from the disconnected blocks are dropped instead
+    /// of being mined again
+    pub evict: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(ChainInfo::to_yaml_string)]
+pub struct ChainInfo {
+    pub simulated: bool,
+    pub height: u32,
+    pub tip_time: u32,
+    pub mempool_size: u32,
+    pub feerate_per_kw: u32,
+    pub subscribers: u32,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(WalletInfo::to_yaml_string)]
+pub struct WalletInfo {
+    /// Address receiving deposits to the funding wallet, if it can be
+    /// represented for the current chain
+    pub address: Option<String>,
+    /// Total value of the wallet outputs, in satoshis
+    pub balance: u64,
+    /// Value of the outputs reserved for channels being funded
+    pub reserved: u64,
+    pub utxos: u32,
+}
+
+/// Health of a daemon registered with `lnpd`, as tracked by the heartbeat
+/// protocol
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(DaemonHealth::to_yaml_string)]
+pub struct DaemonHealth {
+    pub service: String,
+    pub pid: u32,
+    /// Seconds passed since the daemon last responded on the CTL bus
+    pub last_seen: u64,
+    pub healthy: bool,
+}
+
+/// Delivery status of a node event to a webhook endpoint
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+#[display(WebhookDelivery::to_yaml_string)]
+pub struct WebhookDelivery {
+    pub id: u64,
+    pub endpoint: String,
+    pub event: NodeEventKind,
+    pub attempts: u8,
+    pub delivered: bool,
+    pub last_error: Option<String>,
 }
 
 #[cfg(feature = "serde")]
@@ -287,7 +2742,43 @@ impl ToYamlString for NodeInfo {}
 #[cfg(feature = "serde")]
 impl ToYamlString for PeerInfo {}
 #[cfg(feature = "serde")]
+impl ToYamlString for PeerList {}
+#[cfg(feature = "serde")]
+impl ToYamlString for PeerAccess {}
+#[cfg(feature = "serde")]
 impl ToYamlString for ChannelInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for TowerInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for LspOffer {}
+#[cfg(feature = "serde")]
+impl ToYamlString for LspOrder {}
+#[cfg(feature = "serde")]
+impl ToYamlString for JitOffer {}
+#[cfg(feature = "serde")]
+impl ToYamlString for LeaseRates {}
+#[cfg(feature = "serde")]
+impl ToYamlString for NodeLease {}
+#[cfg(feature = "serde")]
+impl ToYamlString for LeaseQuote {}
+#[cfg(feature = "serde")]
+impl ToYamlString for AnnouncedNode {}
+#[cfg(feature = "serde")]
+impl ToYamlString for GraphInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for ForwardingPolicy {}
+#[cfg(feature = "serde")]
+impl ToYamlString for SwapInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for AssetBalanceInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for WebhookDelivery {}
+#[cfg(feature = "serde")]
+impl ToYamlString for ChainInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for WalletInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for DaemonHealth {}
 
 #[derive(
     Wrapper, Clone, PartialEq, Eq, Debug, From, StrictEncode, StrictDecode,
@@ -0,0 +1,238 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! TLV streams extending the peer messages (BOLT-1), which are not known to
+//! the LN message unmarshaller. `peerd` cuts them from the received messages
+//! and appends them to the sent ones, while the daemons exchange them with
+//! `peerd` together with the messages they extend.
+
+use std::collections::BTreeMap;
+
+/// Types of the records mapped to their values
+pub type TlvStream = BTreeMap<u64, Vec<u8>>;
+
+/// `open_channel` record carrying the upfront shutdown script
+pub const UPFRONT_SHUTDOWN_SCRIPT: u64 = 0;
+
+/// `open_channel` and `accept_channel` record carrying the channel type
+pub const CHANNEL_TYPE: u64 = 1;
+
+/// `funding_locked` record carrying the alias of the short channel id
+pub const SHORT_CHANNEL_ID_ALIAS: u64 = 1;
+
+/// `node_announcement` record carrying the liquidity lease rates of the
+/// node (`option_will_fund`)
+pub const OPTION_WILL_FUND: u64 = 1;
+
+/// BOLT-7 type of `node_announcement` message
+const NODE_ANNOUNCEMENT: u16 = 257;
+
+/// Errors of the TLV stream decoding
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TlvError {
+    /// TLV stream is truncated
+    Truncated,
+
+    /// TLV stream contains non-minimally encoded BigSize value
+    NonMinimal,
+
+    /// TLV record of type {0} does not follow the record of greater or equal
+    /// type
+    Unordered(u64),
+
+    /// TLV stream contains unknown even record of type {0}
+    UnknownEven(u64),
+
+    /// value of TLV record of type {0} is invalid
+    InvalidValue(u64),
+}
+
+/// Whether the messages of the given type are extended with TLV streams
+/// by the node
+pub fn is_extensible(msg_type: u16) -> bool {
+    matches!(msg_type, 32 | 33 | 36 | NODE_ANNOUNCEMENT)
+}
+
+/// Length of the message payload preceding the TLV stream for the messages
+/// extended by the node, or `None` for the rest of the messages. Length of
+/// `node_announcement` depends on its features and addresses, and the
+/// whole payload is taken if it is truncated.
+pub fn base_len(msg_type: u16, payload: &[u8]) -> Option<usize> {
+    match msg_type {
+        // open_channel
+        32 => Some(319),
+        // accept_channel
+        33 => Some(270),
+        // funding_locked
+        36 => Some(65),
+        NODE_ANNOUNCEMENT => {
+            let field_len = |pos: usize| {
+                payload
+                    .get(pos..pos + 2)
+                    .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            };
+            // Signature precedes the features, which are followed by the
+            // timestamp, node id, color and alias preceding the addresses
+            let addr_pos =
+                field_len(64).map(|len| 64 + 2 + len + 4 + 33 + 3 + 32);
+            let len = addr_pos
+                .and_then(|pos| field_len(pos).map(|len| pos + 2 + len))
+                .unwrap_or(payload.len());
+            Some(len.min(payload.len()))
+        }
+        _ => None,
+    }
+}
+
+/// Whether the node processes the TLV records of the given type in the
+/// message of the given type
+fn is_known(msg_type: u16, record_type: u64) -> bool {
+    matches!(
+        (msg_type, record_type),
+        (32, UPFRONT_SHUTDOWN_SCRIPT)
+            | (32, CHANNEL_TYPE)
+            | (33, UPFRONT_SHUTDOWN_SCRIPT)
+            | (33, CHANNEL_TYPE)
+            | (36, SHORT_CHANNEL_ID_ALIAS)
+    )
+}
+
+fn write_bigsize(data: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xFC => data.push(value as u8),
+        0xFD..=0xFFFF => {
+            data.push(0xFD);
+            data.extend(&(value as u16).to_be_bytes());
+        }
+        0x10000..=0xFFFFFFFF => {
+            data.push(0xFE);
+            data.extend(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            data.push(0xFF);
+            data.extend(&value.to_be_bytes());
+        }
+    }
+}
+
+fn read_bigsize(data: &[u8], pos: &mut usize) -> Result<u64, TlvError> {
+    let prefix = *data.get(*pos).ok_or(TlvError::Truncated)?;
+    let (len, min) = match prefix {
+        0xFD => (2, 0xFD),
+        0xFE => (4, 0x10000),
+        0xFF => (8, 0x100000000),
+        _ => {
+            *pos += 1;
+            return Ok(prefix as u64);
+        }
+    };
+    let bytes = data
+        .get(*pos + 1..*pos + 1 + len)
+        .ok_or(TlvError::Truncated)?;
+    let value = bytes
+        .iter()
+        .fold(0u64, |value, byte| (value << 8) | *byte as u64);
+    if value < min {
+        return Err(TlvError::NonMinimal);
+    }
+    *pos += 1 + len;
+    Ok(value)
+}
+
+/// Serializes the TLV stream
+pub fn encode(stream: &TlvStream) -> Vec<u8> {
+    let mut data = vec![];
+    for (record_type, value) in stream {
+        write_bigsize(&mut data, *record_type);
+        write_bigsize(&mut data, value.len() as u64);
+        data.extend(value);
+    }
+    data
+}
+
+/// Parses the TLV stream of the message with the given type; unknown odd
+/// records are ignored, while unknown even records fail the message
+pub fn decode(msg_type: u16, data: &[u8]) -> Result<TlvStream, TlvError> {
+    let mut stream = TlvStream::new();
+    let mut pos = 0;
+    let mut last_type = None;
+    while pos < data.len() {
+        let record_type = read_bigsize(data, &mut pos)?;
+        if matches!(last_type, Some(last) if last >= record_type) {
+            return Err(TlvError::Unordered(record_type));
+        }
+        last_type = Some(record_type);
+        let len = read_bigsize(data, &mut pos)? as usize;
+        let value = data
+            .get(pos..pos.saturating_add(len))
+            .ok_or(TlvError::Truncated)?;
+        pos += len;
+        // Signatures of the gossip commit to all of its records, which are
+        // kept for relaying the message
+        if is_known(msg_type, record_type) || msg_type == NODE_ANNOUNCEMENT {
+            stream.insert(record_type, value.to_vec());
+        } else if record_type % 2 == 0 {
+            return Err(TlvError::UnknownEven(record_type));
+        }
+    }
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bigsize_roundtrip() {
+        for value in [0u64, 0xFC, 0xFD, 0xFFFF, 0x10000, 0x100000000] {
+            let mut data = vec![];
+            write_bigsize(&mut data, value);
+            let mut pos = 0;
+            assert_eq!(read_bigsize(&data, &mut pos), Ok(value));
+            assert_eq!(pos, data.len());
+        }
+        let mut pos = 0;
+        assert_eq!(
+            read_bigsize(&[0xFD, 0x00, 0xFC], &mut pos),
+            Err(TlvError::NonMinimal)
+        );
+    }
+
+    #[test]
+    fn stream_decoding() {
+        let stream = bmap! { 0u64 => vec![0x51], 1u64 => vec![] };
+        assert_eq!(decode(32, &encode(&stream)), Ok(stream));
+        // Unknown odd records are skipped
+        assert_eq!(decode(36, &[3, 1, 0]), Ok(TlvStream::new()));
+        assert_eq!(decode(36, &[2, 1, 0]), Err(TlvError::UnknownEven(2)));
+        assert_eq!(decode(32, &[1, 0, 0, 0]), Err(TlvError::Unordered(0)));
+        assert_eq!(decode(32, &[0, 2, 0]), Err(TlvError::Truncated));
+        // Records of gossip are kept whatever their type is
+        assert_eq!(decode(257, &[2, 1, 0]), Ok(bmap! { 2u64 => vec![0] }));
+    }
+
+    #[test]
+    fn node_announcement_len() {
+        let mut payload = vec![0u8; 64];
+        payload.extend(&[0, 1, 0x80]);
+        payload.extend(vec![0u8; 4 + 33 + 3 + 32]);
+        payload.extend(&[0, 7, 1, 127, 0, 0, 1, 0x26, 0x07]);
+        let len = payload.len();
+        payload.extend(&[1, 0]);
+        assert_eq!(base_len(257, &payload), Some(len));
+        assert_eq!(base_len(257, &payload[..70]), Some(70));
+        assert_eq!(base_len(1, &payload), None);
+    }
+}
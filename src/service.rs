@@ -20,10 +20,13 @@ use bitcoin::hashes::hex::{self, ToHex};
 use internet2::{zmqsocket, NodeAddr, ZmqType};
 use lnp::{ChannelId, TempChannelId};
 use lnpbp::strict_encoding::{strict_deserialize, strict_serialize};
+use lnpbp::Chain;
 #[cfg(feature = "node")]
 use microservices::node::TryService;
 use microservices::{esb, rpc};
 
+#[cfg(feature = "node")]
+use crate::rpc::request::DaemonInfo;
 use crate::rpc::{Request, ServiceBus};
 use crate::Config;
 use crate::Error;
@@ -109,6 +112,24 @@ pub enum ServiceId {
 
     #[display("other<{0}>")]
     Other(ClientName),
+
+    #[display("towerd")]
+    Tower,
+
+    #[display("chaind")]
+    Chain,
+
+    #[display("wtclientd")]
+    TowerClient,
+
+    #[display("lspd")]
+    Lsp,
+
+    #[display("swapd")]
+    Swap,
+
+    #[display("fundingd")]
+    Funding,
 }
 
 impl ServiceId {
@@ -149,6 +170,8 @@ where
 {
     esb: esb::Controller<ServiceBus, Request, Runtime>,
     broker: bool,
+    /// Chain served by the daemon, which is announced to `lnpd`
+    chain: Chain,
 }
 
 impl<Runtime> Service<Runtime>
@@ -172,6 +195,7 @@ where
         runtime: Runtime,
         broker: bool,
     ) -> Result<Self, esb::Error> {
+        let chain = config.chain.clone();
         let router = if !broker {
             Some(ServiceId::router())
         } else {
@@ -197,7 +221,7 @@ where
                 ZmqType::RouterConnect
             },
         )?;
-        Ok(Self { esb, broker })
+        Ok(Self { esb, broker, chain })
     }
 
     pub fn broker(
@@ -218,6 +242,10 @@ where
         self.broker
     }
 
+    pub fn chain(&self) -> &Chain {
+        &self.chain
+    }
+
     pub fn add_loopback(
         &mut self,
         socket: zmq::Socket,
@@ -234,22 +262,24 @@ where
 
     #[cfg(feature = "node")]
     pub fn run_loop(mut self) -> Result<(), Error> {
+        let identity = self.esb.handler().identity();
+
         if !self.is_broker() {
             std::thread::sleep(core::time::Duration::from_secs(1));
+            let info = DaemonInfo::with(identity.clone(), self.chain.clone());
             self.esb.send_to(
                 ServiceBus::Ctl,
                 ServiceId::Lnpd,
-                Request::Hello,
+                Request::Hello(info.clone()),
             )?;
             self.esb.send_to(
                 ServiceBus::Msg,
                 ServiceId::Lnpd,
-                Request::Hello,
+                Request::Hello(info),
             )?;
         }
 
-        let identity = self.esb.handler().identity();
-        info!("{} started", identity);
+        info!("{} started for {}", identity, self.chain);
 
         self.esb.run_or_panic(&identity.to_string());
 
@@ -259,6 +289,53 @@ where
 
 pub type Senders = esb::SenderList<ServiceBus, ServiceId>;
 
+/// Unwinding payload of a daemon thread which has terminated gracefully
+#[cfg(feature = "node")]
+pub struct Terminated;
+
+/// Terminates the daemon once its job is done. Daemons running as threads of
+/// the lnpd process unwind their thread instead of exiting the process.
+#[cfg(feature = "node")]
+pub fn terminate(threaded: bool) -> ! {
+    if threaded {
+        std::panic::resume_unwind(Box::new(Terminated))
+    } else {
+        std::process::exit(0)
+    }
+}
+
+/// Handler of the loopback bridge controller, used by threads sending
+/// requests to the service runtime
+#[cfg(feature = "node")]
+pub struct BridgeHandler;
+
+#[cfg(feature = "node")]
+impl esb::Handler<ServiceBus> for BridgeHandler {
+    type Request = Request;
+    type Address = ServiceId;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId {
+        ServiceId::Loopback
+    }
+
+    fn handle(
+        &mut self,
+        _senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        _bus: ServiceBus,
+        _addr: ServiceId,
+        _request: Request,
+    ) -> Result<(), Error> {
+        // Bridge does not receive replies for now
+        Ok(())
+    }
+
+    fn handle_err(&mut self, err: esb::Error) -> Result<(), esb::Error> {
+        // We simply propagate the error since it's already being reported
+        Err(err)?
+    }
+}
+
 pub trait TryToServiceId {
     fn try_to_service_id(&self) -> Option<ServiceId>;
 }
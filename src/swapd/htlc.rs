@@ -0,0 +1,164 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::sha256;
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::{OutPoint, Script, SigHashType, Transaction, TxIn, TxOut};
+
+/// On-chain part of a submarine swap: output which can be claimed by one
+/// party knowing the payment preimage or refunded to the other party after a
+/// relative timeout
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SwapHtlc {
+    pub payment_hash: sha256::Hash,
+    pub claim_pubkey: secp256k1::PublicKey,
+    pub refund_pubkey: secp256k1::PublicKey,
+    pub timeout: u16,
+}
+
+impl SwapHtlc {
+    pub fn witness_script(&self) -> Script {
+        Builder::new()
+            .push_opcode(OP_SIZE)
+            .push_int(32)
+            .push_opcode(OP_EQUAL)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_SHA256)
+            .push_slice(&self.payment_hash[..])
+            .push_opcode(OP_EQUALVERIFY)
+            .push_slice(&self.claim_pubkey.serialize())
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_DROP)
+            .push_int(self.timeout as i64)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_slice(&self.refund_pubkey.serialize())
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+    }
+
+    pub fn script_pubkey(&self) -> Script {
+        self.witness_script().to_v0_p2wsh()
+    }
+
+    /// Returns index of the transaction output funding the HTLC, if any
+    pub fn funding_vout(&self, tx: &Transaction) -> Option<u32> {
+        let script_pubkey = self.script_pubkey();
+        tx.output
+            .iter()
+            .position(|txout| txout.script_pubkey == script_pubkey)
+            .map(|vout| vout as u32)
+    }
+
+    /// Constructs transaction spending the HTLC output with the preimage
+    pub fn claim_tx(
+        &self,
+        funding: OutPoint,
+        amount: u64,
+        preimage: &[u8],
+        claim_key: &secp256k1::SecretKey,
+        destination: Script,
+        fee: u64,
+    ) -> Transaction {
+        self.spend(
+            (funding, amount),
+            0xFFFF_FFFF,
+            preimage.to_vec(),
+            claim_key,
+            destination,
+            fee,
+        )
+    }
+
+    /// Constructs transaction spending the HTLC output back to the funding
+    /// party; it can be mined only after the HTLC timeout
+    pub fn refund_tx(
+        &self,
+        funding: OutPoint,
+        amount: u64,
+        refund_key: &secp256k1::SecretKey,
+        destination: Script,
+        fee: u64,
+    ) -> Transaction {
+        self.spend(
+            (funding, amount),
+            self.timeout as u32,
+            vec![],
+            refund_key,
+            destination,
+            fee,
+        )
+    }
+
+    fn spend(
+        &self,
+        (funding, amount): (OutPoint, u64),
+        sequence: u32,
+        secret: Vec<u8>,
+        key: &secp256k1::SecretKey,
+        destination: Script,
+        fee: u64,
+    ) -> Transaction {
+        let witness_script = self.witness_script();
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: funding,
+                script_sig: Script::new(),
+                sequence,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: amount.saturating_sub(fee),
+                script_pubkey: destination,
+            }],
+        };
+
+        let sighash = SigHashCache::new(&tx).signature_hash(
+            0,
+            &witness_script,
+            amount,
+            SigHashType::All,
+        );
+        let secp = Secp256k1::signing_only();
+        let message = secp256k1::Message::from_slice(&sighash[..])
+            .expect("sighash is always 32 bytes long");
+        let mut sig = secp.sign(&message, key).serialize_der().to_vec();
+        sig.push(SigHashType::All.as_u32() as u8);
+
+        tx.input[0].witness = vec![sig, secret, witness_script.into_bytes()];
+        tx
+    }
+}
+
+/// Extracts swap preimage from the witness of a transaction input claiming
+/// the HTLC output
+pub fn extract_preimage(
+    txin: &TxIn,
+    payment_hash: &sha256::Hash,
+) -> Option<Vec<u8>> {
+    use bitcoin::hashes::Hash;
+
+    match txin.witness.as_slice() {
+        [_, preimage, _] if sha256::Hash::hash(preimage) == *payment_hash => {
+            Some(preimage.clone())
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,93 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use clap::{AppSettings, Clap};
+
+use bitcoin::Address;
+
+/// Submarine swap daemon; part of LNP Node
+///
+/// The daemon runs submarine swaps: it negotiates swap HTLC outputs with a
+/// swap provider (or with swap clients, if running as a provider), watches
+/// the chain for their funding, pays or invoices the off-chain part through
+/// the channel with the counterparty and claims or refunds the outputs.
+/// Swaps-in refill outbound channel liquidity, swaps-out refill inbound
+/// liquidity.
+///
+/// The daemon is controlled though ZMQ ctl socket (see `ctl-socket` argument
+/// description)
+#[derive(Clap, Clone, PartialEq, Eq, Debug)]
+#[clap(
+    name = "swapd",
+    bin_name = "swapd",
+    author,
+    version,
+    setting = AppSettings::ColoredHelp
+)]
+pub struct Opts {
+    /// Name under which the daemon is registered on the service bus
+    ///
+    /// If omitted the daemon uses the default `swapd` service id
+    #[clap(long, env = "LNP_NODE_SWAP_NAME")]
+    pub name: Option<String>,
+
+    /// Service name of the swap provider to use
+    #[clap(long, env = "LNP_NODE_SWAP_PROVIDER")]
+    pub provider: Option<String>,
+
+    /// Serve swaps to other nodes acting as a swap provider
+    #[clap(long)]
+    pub serve: bool,
+
+    /// Address receiving funds claimed or refunded from swap outputs
+    #[clap(long, env = "LNP_NODE_SWAP_SWEEP_ADDRESS")]
+    pub sweep_address: Address,
+
+    /// Fee paid by transactions claiming or refunding swap outputs, in
+    /// satoshis
+    #[clap(long, default_value = "1000")]
+    pub sweep_fee: u64,
+
+    /// Minimal amount of a served swap, in satoshis
+    #[clap(long, default_value = "100000")]
+    pub min_amount: u64,
+
+    /// Maximal amount of a served swap, in satoshis
+    #[clap(long, default_value = "10000000")]
+    pub max_amount: u64,
+
+    /// Fee charged for a served swap, in millionths of the swap amount
+    #[clap(long, default_value = "5000")]
+    pub fee_ppm: u32,
+
+    /// Number of blocks after the funding after which a served swap output
+    /// can be refunded
+    #[clap(long, default_value = "144")]
+    pub timeout: u16,
+
+    /// RGB configuration: ignored by this daemon
+    #[clap(short, long = "rgb20-rpc")]
+    pub r: Option<String>,
+
+    /// These params can be read also from the configuration file, not just
+    /// command-line args or environment variables
+    #[clap(flatten)]
+    pub shared: crate::opts::Opts,
+}
+
+impl Opts {
+    pub fn process(&mut self) {
+        self.shared.process()
+    }
+}
@@ -0,0 +1,942 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+
+use amplify::Wrapper;
+use bitcoin::hashes::sha256;
+use bitcoin::secp256k1::{self, rand, Secp256k1};
+use bitcoin::{OutPoint, Script, Transaction};
+use internet2::TypedEnum;
+use lnpbp::strict_encoding::{StrictDecode, StrictEncode};
+use microservices::esb::{self, Handler};
+use microservices::rpc::Failure;
+use wallet::HashLock;
+
+use super::htlc::{extract_preimage, SwapHtlc};
+use crate::rpc::request::{
+    AccountingEvent, AccountingEventKind, InvoiceRequest, SwapDirection,
+    SwapInfo, SwapProposal, SwapRequest, SwapState, Transfer,
+};
+use crate::rpc::{Request, ServiceBus};
+use crate::{Config, CtlServer, Error, LogStyle, Senders, Service, ServiceId};
+
+pub const SWAP_DB_FILE: &'static str = "swaps.dat";
+
+/// Time, in seconds, during which the off-chain part of a swap may be paid
+const SWAP_INVOICE_EXPIRY: u32 = 86400;
+
+/// Terms under which swaps are served to other nodes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SwapTerms {
+    pub min_amount: u64,
+    pub max_amount: u64,
+    pub fee_ppm: u32,
+    pub timeout: u16,
+}
+
+impl SwapTerms {
+    /// Fee charged for a swap of the given amount, in satoshis
+    pub fn fee_for(&self, amount_sat: u64) -> u64 {
+        amount_sat * self.fee_ppm as u64 / 1_000_000
+    }
+}
+
+pub fn run(
+    config: Config,
+    identity: ServiceId,
+    data_dir: PathBuf,
+    provider: Option<ServiceId>,
+    terms: Option<SwapTerms>,
+    sweep_script: Script,
+    sweep_fee: u64,
+) -> Result<(), Error> {
+    let db_path = data_dir.join(SWAP_DB_FILE);
+    let swaps: Vec<Swap> = if db_path.exists() {
+        debug!("Loading swaps from {:?}", db_path);
+        StrictDecode::strict_decode(fs::File::open(&db_path)?).map_err(
+            |err| Error::Other(format!("Swap database is corrupted: {}", err)),
+        )?
+    } else {
+        vec![]
+    };
+    info!("Loaded {} swap(s)", swaps.len());
+
+    let runtime = Runtime {
+        identity,
+        db_path,
+        provider,
+        terms,
+        sweep_script,
+        sweep_fee,
+        swaps: swaps
+            .into_iter()
+            .map(|swap| (swap.info.payment_hash, swap))
+            .collect(),
+        pending: none!(),
+        lnpd_requests: none!(),
+        payments: none!(),
+    };
+
+    Service::run(config, runtime, false)
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct Swap {
+    info: SwapInfo,
+    counterparty: ServiceId,
+    is_provider: bool,
+    local_key: secp256k1::SecretKey,
+    preimage: Option<Vec<u8>>,
+    funding: Option<OutPoint>,
+    funding_amount: u64,
+}
+
+impl Swap {
+    fn htlc(&self) -> SwapHtlc {
+        let (claim_pubkey, refund_pubkey) = match self.info.direction {
+            SwapDirection::In => {
+                (self.info.provider_pubkey, self.info.client_pubkey)
+            }
+            SwapDirection::Out => {
+                (self.info.client_pubkey, self.info.provider_pubkey)
+            }
+        };
+        SwapHtlc {
+            payment_hash: self.info.payment_hash,
+            claim_pubkey,
+            refund_pubkey,
+            timeout: self.info.timeout,
+        }
+    }
+
+    /// Whether we are the party claiming the HTLC output with the preimage,
+    /// which is learned from the fulfillment of our off-chain payment
+    fn is_claimer(&self) -> bool {
+        match self.info.direction {
+            SwapDirection::In => self.is_provider,
+            SwapDirection::Out => !self.is_provider,
+        }
+    }
+
+    /// Whether we are the party which must fund the HTLC output; it
+    /// receives the off-chain payment with its invoice
+    fn is_funder(&self) -> bool {
+        !self.is_claimer()
+    }
+
+    /// Amount the HTLC output must be funded with, in satoshis
+    fn funding_sat(&self) -> u64 {
+        match self.info.direction {
+            SwapDirection::In => self.info.amount_sat + self.info.fee_sat,
+            SwapDirection::Out => self.info.amount_sat,
+        }
+    }
+
+    /// Amount of the off-chain payment, in millisatoshis
+    fn payment_msat(&self) -> u64 {
+        match self.info.direction {
+            SwapDirection::In => self.info.amount_sat * 1000,
+            SwapDirection::Out => {
+                (self.info.amount_sat + self.info.fee_sat) * 1000
+            }
+        }
+    }
+}
+
+/// Swap requested from the provider which was not yet confirmed by it
+struct PendingSwap {
+    request: SwapRequest,
+    /// Payment hash of our invoice, for swaps-in
+    payment_hash: Option<sha256::Hash>,
+    local_key: secp256k1::SecretKey,
+    enquirer: ServiceId,
+}
+
+impl PendingSwap {
+    fn client_pubkey(&self) -> secp256k1::PublicKey {
+        secp256k1::PublicKey::from_secret_key(
+            &Secp256k1::signing_only(),
+            &self.local_key,
+        )
+    }
+}
+
+/// Swap invoices requested from `lnpd`, which replies in the order of the
+/// requests
+enum LnpdRequest {
+    /// Invoice for the swap-in we request from the provider
+    SwapIn(PendingSwap),
+    /// Invoice for the swap-out we serve to a client
+    SwapOut {
+        client: ServiceId,
+        proposal: SwapProposal,
+    },
+}
+
+pub struct Runtime {
+    identity: ServiceId,
+    db_path: PathBuf,
+    provider: Option<ServiceId>,
+    terms: Option<SwapTerms>,
+    sweep_script: Script,
+    sweep_fee: u64,
+    swaps: BTreeMap<sha256::Hash, Swap>,
+    pending: VecDeque<PendingSwap>,
+    lnpd_requests: VecDeque<LnpdRequest>,
+    /// Off-chain payments of the swaps we claim, indexed by the channel
+    /// daemons sending them
+    payments: HashMap<ServiceId, sha256::Hash>,
+}
+
+impl CtlServer for Runtime {}
+
+impl esb::Handler<ServiceBus> for Runtime {
+    type Request = Request;
+    type Address = ServiceId;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId {
+        self.identity.clone()
+    }
+
+    fn handle(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        bus: ServiceBus,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Self::Error> {
+        match bus {
+            ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
+            _ => Err(Error::NotSupported(bus, request.get_type())),
+        }
+    }
+
+    fn handle_err(&mut self, _: esb::Error) -> Result<(), esb::Error> {
+        // We do nothing and do not propagate error; it's already being reported
+        // with `error!` macro by the controller. If we propagate error here
+        // this will make whole daemon panic
+        Ok(())
+    }
+}
+
+impl Runtime {
+    fn handle_rpc_ctl(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        let from_provider = Some(&source) == self.provider.as_ref();
+        match request {
+            Request::Hello(_) => {
+                // Ignoring; this is used to set remote identity at ZMQ level
+            }
+
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
+            // Client side
+            // -----------
+            Request::SwapStart(swap_req) if self.provider.is_some() => {
+                let pending = PendingSwap {
+                    request: swap_req,
+                    payment_hash: None,
+                    local_key: secp256k1::SecretKey::new(
+                        &mut rand::thread_rng(),
+                    ),
+                    enquirer: source,
+                };
+                match swap_req.direction {
+                    // We receive the off-chain part of a swap-in
+                    SwapDirection::In => self.request_invoice(
+                        senders,
+                        swap_req.amount_sat * 1000,
+                        LnpdRequest::SwapIn(pending),
+                    )?,
+                    SwapDirection::Out => {
+                        self.propose_swap(senders, pending)?
+                    }
+                }
+            }
+
+            Request::SwapInfo(info) if from_provider => {
+                self.accept_swap(senders, source, info)?;
+            }
+
+            Request::Failure(failure) if from_provider => {
+                if let Some(pending) = self.pending.pop_front() {
+                    error!(
+                        "{} {}: {}",
+                        "Swap provider rejected".err(),
+                        pending.request,
+                        failure.err_details()
+                    );
+                    self.send_ctl(
+                        senders,
+                        pending.enquirer,
+                        Request::Failure(failure),
+                    )?;
+                }
+            }
+
+            // Provider side
+            // -------------
+            Request::SwapCreate(proposal) if self.terms.is_some() => {
+                match (proposal.direction, proposal.payment_hash) {
+                    (SwapDirection::In, Some(payment_hash)) => {
+                        let resp = self.serve_swap(
+                            source.clone(),
+                            proposal,
+                            payment_hash,
+                        );
+                        self.reply_swap(senders, source, resp)?;
+                    }
+                    // We receive the off-chain part of a swap-out
+                    (SwapDirection::Out, None) => {
+                        let fee_sat = match self.check_proposal(&proposal) {
+                            Ok(fee_sat) => fee_sat,
+                            Err(info) => {
+                                return self.reply_swap(
+                                    senders,
+                                    source,
+                                    Err(info),
+                                )
+                            }
+                        };
+                        let amount_msat =
+                            (proposal.amount_sat + fee_sat) * 1000;
+                        self.request_invoice(
+                            senders,
+                            amount_msat,
+                            LnpdRequest::SwapOut {
+                                client: source,
+                                proposal,
+                            },
+                        )?;
+                    }
+                    _ => {
+                        let info = s!("Swap invoice must be created by the \
+                                       receiver of the off-chain payment");
+                        return self.reply_swap(senders, source, Err(info));
+                    }
+                }
+            }
+
+            // Off-chain payments
+            // ------------------
+            Request::InvoiceInfo(invoice) if source == ServiceId::Lnpd => {
+                let payment_hash = invoice.payment_hash.into_inner();
+                match self.lnpd_requests.pop_front() {
+                    Some(LnpdRequest::SwapIn(mut pending)) => {
+                        pending.payment_hash = Some(payment_hash);
+                        self.propose_swap(senders, pending)?;
+                    }
+                    Some(LnpdRequest::SwapOut { client, proposal }) => {
+                        let resp = self.serve_swap(
+                            client.clone(),
+                            proposal,
+                            payment_hash,
+                        );
+                        self.reply_swap(senders, client, resp)?;
+                    }
+                    None => warn!("Unsolicited invoice {}", payment_hash),
+                }
+            }
+
+            Request::Failure(failure) if source == ServiceId::Lnpd => {
+                let enquirer = match self.lnpd_requests.pop_front() {
+                    Some(LnpdRequest::SwapIn(pending)) => pending.enquirer,
+                    Some(LnpdRequest::SwapOut { client, .. }) => client,
+                    None => {
+                        error!("{}", failure.err_details());
+                        return Ok(());
+                    }
+                };
+                error!(
+                    "{} {}",
+                    "Unable to create swap invoice:".err(),
+                    failure.err_details()
+                );
+                self.send_ctl(senders, enquirer, Request::Failure(failure))?;
+            }
+
+            Request::InvoiceSettled(payment_hash)
+                if source == ServiceId::Lnpd =>
+            {
+                let payment_hash = payment_hash.into_inner();
+                if let Some(swap) = self.swaps.get_mut(&payment_hash) {
+                    info!(
+                        "{} for swap {}",
+                        "Off-chain payment received".ended(),
+                        payment_hash
+                    );
+                    if swap.info.state == SwapState::Funded {
+                        swap.info.state = SwapState::Paid;
+                    }
+                    self.save()?;
+                }
+            }
+
+            Request::PaymentPreimage(preimage) => {
+                self.payments.remove(&source);
+                let payment_hash = HashLock::from(preimage).into_inner();
+                let swap = match self.swaps.get_mut(&payment_hash) {
+                    Some(swap) => swap,
+                    None => {
+                        warn!("Preimage of unknown swap {}", payment_hash);
+                        return Ok(());
+                    }
+                };
+                info!(
+                    "{} for swap {}",
+                    "Off-chain payment fulfilled".ended(),
+                    payment_hash
+                );
+                swap.preimage = Some(preimage.as_ref().to_vec());
+                if swap.info.state == SwapState::Funded {
+                    swap.info.state = SwapState::Paid;
+                }
+                self.save()?;
+                self.claim(senders, payment_hash)?;
+            }
+
+            Request::Failure(failure)
+                if self.payments.contains_key(&source) =>
+            {
+                let payment_hash = self
+                    .payments
+                    .remove(&source)
+                    .expect("checked by match guard");
+                // The funding party refunds the swap output after the timeout
+                error!(
+                    "{} {}: {}",
+                    "Off-chain payment failed for swap".err(),
+                    payment_hash,
+                    failure.err_details()
+                );
+            }
+
+            // Both sides
+            // ----------
+            Request::SwapRefund(payment_hash) => {
+                let resp = self.refund(senders, payment_hash);
+                match resp {
+                    Ok(txid) => self.report_success_to(
+                        senders,
+                        source,
+                        Some(format!("Refund transaction {} broadcast", txid)),
+                    )?,
+                    Err(info) => {
+                        return Err(self.report_failure_to(
+                            senders,
+                            source,
+                            Failure { code: 0, info },
+                        ))
+                    }
+                }
+            }
+
+            Request::ListSwaps => {
+                let list =
+                    self.swaps.values().map(|swap| swap.info.clone()).collect();
+                self.send_ctl(senders, source, Request::SwapList(list))?;
+            }
+
+            Request::ChainTransactions(txs) => {
+                for tx in txs {
+                    self.process_tx(senders, &tx)?;
+                }
+            }
+
+            Request::SwapStart(_) | Request::SwapCreate(_) => {
+                let info = s!("The daemon is not configured for this role \
+                               of the submarine swap protocol");
+                return Err(self.report_failure_to(
+                    senders,
+                    source,
+                    Failure { code: 0, info },
+                ));
+            }
+
+            _ => {
+                error!("Request is not supported by the CTL interface");
+                return Err(Error::NotSupported(
+                    ServiceBus::Ctl,
+                    request.get_type(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn request_invoice(
+        &mut self,
+        senders: &mut Senders,
+        amount_msat: u64,
+        request: LnpdRequest,
+    ) -> Result<(), Error> {
+        self.lnpd_requests.push_back(request);
+        self.send_ctl(
+            senders,
+            ServiceId::Lnpd,
+            Request::CreateInvoice(InvoiceRequest {
+                amount_msat,
+                asset_id: None,
+                expiry: SWAP_INVOICE_EXPIRY,
+            }),
+        )
+    }
+
+    fn propose_swap(
+        &mut self,
+        senders: &mut Senders,
+        pending: PendingSwap,
+    ) -> Result<(), Error> {
+        let provider = self
+            .provider
+            .clone()
+            .expect("swaps are proposed only by clients");
+        info!(
+            "{} {} with {}",
+            "Requesting".promo(),
+            pending.request,
+            provider.promoter()
+        );
+        self.send_ctl(
+            senders,
+            provider,
+            Request::SwapCreate(SwapProposal {
+                direction: pending.request.direction,
+                amount_sat: pending.request.amount_sat,
+                channel_id: pending.request.channel_id,
+                payment_hash: pending.payment_hash,
+                client_pubkey: pending.client_pubkey(),
+            }),
+        )?;
+        self.pending.push_back(pending);
+        Ok(())
+    }
+
+    fn reply_swap(
+        &mut self,
+        senders: &mut Senders,
+        client: ServiceId,
+        resp: Result<SwapInfo, String>,
+    ) -> Result<(), Error> {
+        match resp {
+            Ok(info) => self.send_ctl(senders, client, Request::SwapInfo(info)),
+            Err(info) => Err(self.report_failure_to(
+                senders,
+                client,
+                Failure { code: 0, info },
+            )),
+        }
+    }
+
+    fn accept_swap(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        info: SwapInfo,
+    ) -> Result<(), Error> {
+        let pos = self
+            .pending
+            .iter()
+            .position(|pending| pending.client_pubkey() == info.client_pubkey);
+        let pending = match pos.and_then(|pos| self.pending.remove(pos)) {
+            Some(pending) => pending,
+            None => {
+                warn!("Unsolicited swap {} from {}", info.payment_hash, source);
+                return Ok(());
+            }
+        };
+
+        let swap = Swap {
+            info,
+            counterparty: source,
+            is_provider: false,
+            local_key: pending.local_key,
+            preimage: None,
+            funding: None,
+            funding_amount: 0,
+        };
+        // We must not trust the provider and check that the swap output
+        // commits to our key and to the invoice we pay or get paid
+        let payment_hash =
+            pending.payment_hash.unwrap_or(swap.info.payment_hash);
+        if swap.info.direction != pending.request.direction
+            || swap.info.amount_sat != pending.request.amount_sat
+            || swap.info.channel_id != pending.request.channel_id
+            || swap.info.payment_hash != payment_hash
+            || swap.info.htlc_script != swap.htlc().script_pubkey()
+        {
+            error!(
+                "{} {}",
+                "Swap provider returned invalid swap output for".err(),
+                swap.info.payment_hash
+            );
+            return Err(self.report_failure_to(
+                senders,
+                pending.enquirer,
+                Failure {
+                    code: 0,
+                    info: s!("Swap provider returned invalid swap output"),
+                },
+            ));
+        }
+
+        info!(
+            "{} {} for {} sat fee",
+            "Swap created".ended(),
+            swap.info.payment_hash,
+            swap.info.fee_sat
+        );
+        if swap.is_funder() {
+            info!(
+                "{} {} sat to {}",
+                "Please fund swap output with".promo(),
+                swap.funding_sat(),
+                swap.info.htlc_script.addr()
+            );
+        }
+        let info = swap.info.clone();
+        self.swaps.insert(info.payment_hash, swap);
+        self.save()?;
+        self.send_ctl(senders, pending.enquirer, Request::SwapInfo(info))
+    }
+
+    /// Checks the proposal against the served terms, returning the swap fee
+    fn check_proposal(&self, proposal: &SwapProposal) -> Result<u64, String> {
+        let terms = self.terms.expect("swaps are served only by providers");
+        if proposal.amount_sat < terms.min_amount
+            || proposal.amount_sat > terms.max_amount
+        {
+            return Err(format!(
+                "Swap amount must be within {}..{} sat",
+                terms.min_amount, terms.max_amount
+            ));
+        }
+        Ok(terms.fee_for(proposal.amount_sat))
+    }
+
+    fn serve_swap(
+        &mut self,
+        client: ServiceId,
+        proposal: SwapProposal,
+        payment_hash: sha256::Hash,
+    ) -> Result<SwapInfo, String> {
+        let terms = self.terms.expect("swaps are served only by providers");
+        let fee_sat = self.check_proposal(&proposal)?;
+        if self.swaps.contains_key(&payment_hash) {
+            return Err(s!("Swap with the same payment hash already exists"));
+        }
+
+        let local_key = secp256k1::SecretKey::new(&mut rand::thread_rng());
+        let mut swap = Swap {
+            info: SwapInfo {
+                payment_hash,
+                direction: proposal.direction,
+                amount_sat: proposal.amount_sat,
+                fee_sat,
+                channel_id: proposal.channel_id,
+                client_pubkey: proposal.client_pubkey,
+                provider_pubkey: secp256k1::PublicKey::from_secret_key(
+                    &Secp256k1::signing_only(),
+                    &local_key,
+                ),
+                timeout: terms.timeout,
+                htlc_script: Script::new(),
+                state: SwapState::Created,
+            },
+            counterparty: client,
+            is_provider: true,
+            local_key,
+            preimage: None,
+            funding: None,
+            funding_amount: 0,
+        };
+        swap.info.htlc_script = swap.htlc().script_pubkey();
+
+        info!(
+            "{} {} with {}",
+            "Serving".promo(),
+            proposal,
+            swap.counterparty.promoter()
+        );
+        if swap.is_funder() {
+            info!(
+                "{} {} sat to {}",
+                "Please fund swap output with".promo(),
+                swap.funding_sat(),
+                swap.info.htlc_script.addr()
+            );
+        }
+        let info = swap.info.clone();
+        self.swaps.insert(info.payment_hash, swap);
+        self.save().map_err(|err| err.to_string())?;
+        Ok(info)
+    }
+
+    fn process_tx(
+        &mut self,
+        senders: &mut Senders,
+        tx: &Transaction,
+    ) -> Result<(), Error> {
+        let txid = tx.txid();
+        let mut payable = vec![];
+        let mut events = vec![];
+        let mut updated = false;
+        for (payment_hash, swap) in &mut self.swaps {
+            match swap.info.state {
+                SwapState::Created => {
+                    let vout = match swap.htlc().funding_vout(tx) {
+                        Some(vout) => vout,
+                        None => continue,
+                    };
+                    info!(
+                        "{} {}",
+                        "Swap output funded for".ended(),
+                        payment_hash
+                    );
+                    swap.funding = Some(OutPoint::new(txid, vout));
+                    swap.funding_amount = tx.output[vout as usize].value;
+                    swap.info.state = SwapState::Funded;
+                    // The output is claimed once our off-chain payment
+                    // reveals the preimage
+                    if swap.is_claimer() {
+                        payable.push(*payment_hash);
+                    }
+                    updated = true;
+                }
+                SwapState::Funded | SwapState::Paid => {
+                    let txin =
+                        match tx.input.iter().find(|txin| {
+                            Some(txin.previous_output) == swap.funding
+                        }) {
+                            Some(txin) => txin,
+                            None => continue,
+                        };
+                    match extract_preimage(txin, payment_hash) {
+                        Some(preimage) => {
+                            info!(
+                                "{} {}",
+                                "Swap output claimed for".ended(),
+                                payment_hash
+                            );
+                            if swap.preimage.is_none() {
+                                swap.preimage = Some(preimage);
+                            }
+                            swap.info.state = SwapState::Claimed;
+                            let kind = if swap.is_provider {
+                                AccountingEventKind::ServiceFeeEarned
+                            } else {
+                                AccountingEventKind::ServiceFeePaid
+                            };
+                            events.push(AccountingEvent {
+                                kind,
+                                channel_id: None,
+                                txid: Some(txid),
+                                asset: None,
+                                amount_msat: swap.info.fee_sat * 1000,
+                                fee_msat: 0,
+                                description: format!(
+                                    "Fee for swap {}",
+                                    payment_hash
+                                ),
+                            });
+                        }
+                        None => {
+                            info!(
+                                "{} {}",
+                                "Swap output refunded for".ended(),
+                                payment_hash
+                            );
+                            swap.info.state = SwapState::Refunded;
+                        }
+                    }
+                    let published = match swap.info.state {
+                        SwapState::Claimed => swap.is_claimer(),
+                        _ => swap.is_funder(),
+                    };
+                    if published {
+                        // Sweeping transaction spends only the swap output
+                        let fee = swap.funding_amount.saturating_sub(
+                            tx.output.iter().map(|txout| txout.value).sum(),
+                        );
+                        events.push(AccountingEvent {
+                            kind: AccountingEventKind::OnchainFee,
+                            channel_id: None,
+                            txid: Some(txid),
+                            asset: None,
+                            amount_msat: fee * 1000,
+                            fee_msat: 0,
+                            description: format!(
+                                "Sweeping of swap {} output",
+                                payment_hash
+                            ),
+                        });
+                    }
+                    updated = true;
+                }
+                SwapState::Claimed | SwapState::Refunded => {}
+            }
+        }
+        for payment_hash in payable {
+            self.pay(senders, payment_hash)?;
+        }
+        for event in events {
+            self.send_ctl(senders, ServiceId::Lnpd, Request::BookEvent(event))?;
+        }
+        if updated {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn pay(
+        &mut self,
+        senders: &mut Senders,
+        payment_hash: sha256::Hash,
+    ) -> Result<(), Error> {
+        let swap = self
+            .swaps
+            .get(&payment_hash)
+            .expect("swap presence is checked by the caller");
+        if swap.funding_amount < swap.funding_sat() {
+            warn!(
+                "{} {}: output is funded with {} sat instead of {} sat",
+                "Not paying swap".err(),
+                payment_hash,
+                swap.funding_amount,
+                swap.funding_sat()
+            );
+            return Ok(());
+        }
+        let channeld = ServiceId::Channel(swap.info.channel_id);
+        let amount = swap.payment_msat();
+        info!(
+            "{} {} msat for swap {} via {}",
+            "Paying".promo(),
+            amount,
+            payment_hash,
+            channeld.promoter()
+        );
+        self.payments.insert(channeld.clone(), payment_hash);
+        self.send_ctl(
+            senders,
+            channeld.clone(),
+            Request::Transfer(Transfer {
+                channeld,
+                amount,
+                asset: None,
+                payment_hash: Some(HashLock::from(payment_hash)),
+            }),
+        )
+    }
+
+    fn claim(
+        &mut self,
+        senders: &mut Senders,
+        payment_hash: sha256::Hash,
+    ) -> Result<(), Error> {
+        let swap = self
+            .swaps
+            .get(&payment_hash)
+            .expect("swap presence is checked by the caller");
+        // Preimage is known only once our off-chain payment is fulfilled
+        let (funding, preimage) =
+            match (swap.info.state, swap.funding, &swap.preimage) {
+                (SwapState::Paid, Some(funding), Some(preimage)) => {
+                    (funding, preimage)
+                }
+                _ => return Ok(()),
+            };
+        let tx = swap.htlc().claim_tx(
+            funding,
+            swap.funding_amount,
+            preimage,
+            &swap.local_key,
+            self.sweep_script.clone(),
+            self.sweep_fee,
+        );
+        info!(
+            "{} {} for swap {}",
+            "Broadcasting claim transaction".promo(),
+            tx.txid().promoter(),
+            payment_hash
+        );
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(tx),
+        )
+    }
+
+    fn refund(
+        &mut self,
+        senders: &mut Senders,
+        payment_hash: sha256::Hash,
+    ) -> Result<bitcoin::Txid, String> {
+        let swap = self
+            .swaps
+            .get(&payment_hash)
+            .ok_or_else(|| format!("Unknown swap {}", payment_hash))?;
+        if !swap.is_funder() {
+            return Err(s!("Only the funding party can refund the swap"));
+        }
+        let funding = match (swap.info.state, swap.funding) {
+            (SwapState::Funded, Some(funding)) => funding,
+            (state, _) => {
+                return Err(format!("Swap {} is {}", payment_hash, state))
+            }
+        };
+        let tx = swap.htlc().refund_tx(
+            funding,
+            swap.funding_amount,
+            &swap.local_key,
+            self.sweep_script.clone(),
+            self.sweep_fee,
+        );
+        let txid = tx.txid();
+        info!(
+            "{} {} for swap {}",
+            "Broadcasting refund transaction".promo(),
+            txid.promoter(),
+            payment_hash
+        );
+        self.send_ctl(
+            senders,
+            ServiceId::Chain,
+            Request::BroadcastTransaction(tx),
+        )
+        .map_err(|err| err.to_string())?;
+        Ok(txid)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let swaps = self.swaps.values().cloned().collect::<Vec<_>>();
+        let tmp_path = self.db_path.with_extension("tmp");
+        swaps
+            .strict_encode(fs::File::create(&tmp_path)?)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        fs::rename(tmp_path, &self.db_path)?;
+        Ok(())
+    }
+}
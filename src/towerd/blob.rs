@@ -0,0 +1,117 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::rand::{self, RngCore};
+use bitcoin::{Transaction, Txid};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use lnpbp::strict_encoding::{strict_deserialize, strict_serialize};
+
+use crate::rpc::request::{BreachHint, JusticeBlob};
+
+const NONCE_LEN: usize = 12;
+
+fn cipher(breach_txid: &Txid) -> ChaCha20Poly1305 {
+    let key = sha256::Hash::hash(&breach_txid[..]);
+    ChaCha20Poly1305::new(Key::from_slice(&key[..]))
+}
+
+impl JusticeBlob {
+    /// Encrypts penalty transaction such that it can be decrypted only by
+    /// somebody who knows the full id of the breach transaction
+    pub fn seal(breach_txid: &Txid, penalty_tx: &Transaction) -> Self {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let plaintext = strict_serialize(penalty_tx)
+            .expect("Memory-based encoding does not fail");
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(
+            cipher(breach_txid)
+                .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+                .expect("ChaCha20Poly1305 encryption does not fail"),
+        );
+
+        JusticeBlob {
+            hint: BreachHint::with(breach_txid),
+            encrypted,
+        }
+    }
+
+    /// Decrypts penalty transaction using the breach transaction id. Returns
+    /// `None` if the blob was not created for the given transaction or is
+    /// malformed.
+    pub fn open(&self, breach_txid: &Txid) -> Option<Transaction> {
+        if self.encrypted.len() <= NONCE_LEN
+            || self.hint != BreachHint::with(breach_txid)
+        {
+            return None;
+        }
+        let (nonce, ciphertext) = self.encrypted.split_at(NONCE_LEN);
+        let plaintext = cipher(breach_txid)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()?;
+        strict_deserialize(&plaintext).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::{OutPoint, Script, TxIn, TxOut};
+
+    fn penalty_tx() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(breach_txid(), 0),
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    fn breach_txid() -> Txid {
+        Txid::from_hex(
+            "8984484a580b825b9972d7adb15050b3ab624ccd731946b3eeddb92f4e7ef6be",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn seal_open() {
+        let blob = JusticeBlob::seal(&breach_txid(), &penalty_tx());
+        assert_eq!(blob.hint, BreachHint::with(&breach_txid()));
+        assert_eq!(blob.open(&breach_txid()), Some(penalty_tx()));
+
+        let other = Txid::from_hex(
+            "35af2c90e84decff1c178c6d600bc0e9de29af15a11b3711db623f960f24ae11",
+        )
+        .unwrap();
+        assert_eq!(blob.open(&other), None);
+
+        // Blob with the hint of the breach, but encrypted with another key
+        let mut forged = JusticeBlob::seal(&other, &penalty_tx());
+        forged.hint = blob.hint;
+        assert_eq!(forged.open(&breach_txid()), None);
+    }
+}
@@ -0,0 +1,53 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use clap::{AppSettings, Clap};
+
+/// Watchtower server daemon; part of LNP Node
+///
+/// The daemon accepts justice transaction blobs from remote lightning nodes,
+/// which upload them over their peer connections, watches the chain for
+/// breaches of their channels and broadcasts penalty transactions on their
+/// behalf.
+///
+/// The daemon is controlled though ZMQ ctl socket (see `ctl-socket` argument
+/// description)
+#[derive(Clap, Clone, PartialEq, Eq, Debug)]
+#[clap(
+    name = "towerd",
+    bin_name = "towerd",
+    author,
+    version,
+    setting = AppSettings::ColoredHelp
+)]
+pub struct Opts {
+    /// Maximum number of justice blobs accepted from a single client session
+    #[clap(long, default_value = "1024", env = "LNP_NODE_TOWER_MAX_UPDATES")]
+    pub max_session_updates: u16,
+
+    /// RGB configuration: ignored by this daemon
+    #[clap(short, long = "rgb20-rpc")]
+    pub r: Option<String>,
+
+    /// These params can be read also from the configuration file, not just
+    /// command-line args or environment variables
+    #[clap(flatten)]
+    pub shared: crate::opts::Opts,
+}
+
+impl Opts {
+    pub fn process(&mut self) {
+        self.shared.process()
+    }
+}
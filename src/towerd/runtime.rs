@@ -0,0 +1,405 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use bitcoin::secp256k1::{rand, PublicKey};
+use bitcoin::Transaction;
+use internet2::TypedEnum;
+use lnpbp::strict_encoding::{
+    strict_deserialize, strict_serialize, StrictDecode, StrictEncode,
+};
+use microservices::esb;
+
+use crate::rpc::request::{
+    BreachHint, CustomMessage, JusticeBlob, TowerMessage, TowerSession,
+    TowerSessionId, TOWER_REPLY_TYPE, TOWER_REQUEST_TYPE,
+};
+use crate::rpc::{Request, ServiceBus};
+use crate::{Config, CtlServer, Error, LogStyle, Senders, Service, ServiceId};
+
+pub const TOWER_DB_FILE: &'static str = "tower.dat";
+
+pub fn run(
+    config: Config,
+    data_dir: PathBuf,
+    max_session_updates: u16,
+) -> Result<(), Error> {
+    let db_path = data_dir.join(TOWER_DB_FILE);
+    let mut sessions = HashMap::new();
+    let mut blobs = HashMap::<_, Vec<JusticeBlob>>::new();
+    if db_path.exists() {
+        debug!("Loading watchtower database from {:?}", db_path);
+        let mut reader = io::BufReader::new(fs::File::open(&db_path)?);
+        // The last record is incomplete if the daemon was terminated while
+        // writing it, so reading stops on the first failure
+        while let Ok(record) = TowerRecord::strict_decode(&mut reader) {
+            match record {
+                TowerRecord::Session(id, session) => {
+                    // New session of the client replaces its previous one
+                    sessions.retain(|other, state: &mut SessionState| {
+                        *other == id || state.client != session.client
+                    });
+                    sessions.insert(id, session);
+                }
+                TowerRecord::Blob(id, blob) => {
+                    if let Some(session) =
+                        id.and_then(|id| sessions.get_mut(&id))
+                    {
+                        session.updates += 1;
+                    }
+                    blobs.entry(blob.hint).or_insert(vec![]).push(blob);
+                }
+            }
+        }
+    }
+    info!(
+        "Watchtower serves {} session(s) with {} justice blob(s)",
+        sessions.len(),
+        blobs.values().map(Vec::len).sum::<usize>()
+    );
+
+    let db = compact(&db_path, &sessions, &blobs)?;
+    let runtime = Runtime {
+        identity: ServiceId::Tower,
+        db_path,
+        db,
+        max_session_updates,
+        sessions,
+        blobs,
+    };
+
+    Service::run(config, runtime, false)
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+struct SessionState {
+    /// Node id of the client which has opened the session
+    client: PublicKey,
+    max_updates: u16,
+    updates: u16,
+}
+
+/// Record of the watchtower database log; the number of session updates is
+/// restored by counting blob records of the session following its record
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+enum TowerRecord {
+    /// Session opened or renewed by the client
+    Session(TowerSessionId, SessionState),
+    /// Justice blob uploaded within the session; blobs written by the
+    /// compaction have no session, since their updates are already counted
+    /// in the session records
+    Blob(Option<TowerSessionId>, JusticeBlob),
+}
+
+pub struct Runtime {
+    identity: ServiceId,
+    db_path: PathBuf,
+    /// Database log opened for appending
+    db: fs::File,
+    max_session_updates: u16,
+    sessions: HashMap<TowerSessionId, SessionState>,
+    blobs: HashMap<BreachHint, Vec<JusticeBlob>>,
+}
+
+impl CtlServer for Runtime {}
+
+impl esb::Handler<ServiceBus> for Runtime {
+    type Request = Request;
+    type Address = ServiceId;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId {
+        self.identity.clone()
+    }
+
+    fn handle(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        bus: ServiceBus,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Self::Error> {
+        match bus {
+            ServiceBus::Msg => self.handle_rpc_msg(senders, source, request),
+            ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
+            _ => Err(Error::NotSupported(bus, request.get_type())),
+        }
+    }
+
+    fn handle_err(&mut self, _: esb::Error) -> Result<(), esb::Error> {
+        // We do nothing and do not propagate error; it's already being reported
+        // with `error!` macro by the controller. If we propagate error here
+        // this will make whole daemon panic
+        Ok(())
+    }
+}
+
+impl Runtime {
+    fn handle_rpc_msg(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        let (client, message) = match request {
+            Request::CustomPeerMessage(CustomMessage {
+                remote_id,
+                msg_type: TOWER_REQUEST_TYPE,
+                payload,
+            }) => match strict_deserialize(&payload) {
+                Ok(message) => (remote_id, message),
+                Err(err) => {
+                    warn!(
+                        "Invalid watchtower request from {}: {}",
+                        remote_id, err
+                    );
+                    return Ok(());
+                }
+            },
+            _ => {
+                error!(
+                    "MSG RPC can be only used for forwarding watchtower \
+                     requests"
+                );
+                return Err(Error::NotSupported(
+                    ServiceBus::Msg,
+                    request.get_type(),
+                ));
+            }
+        };
+
+        let reply = match message {
+            TowerMessage::CreateSession(TowerSession {
+                session,
+                max_updates,
+            }) => self.create_session(client, session, max_updates)?,
+            TowerMessage::AppendBlob(id, blob) => {
+                self.append_blob(client, id, blob)?
+            }
+            message => TowerMessage::Rejected(format!(
+                "unexpected request {}",
+                message
+            )),
+        };
+        senders.send_to(
+            ServiceBus::Msg,
+            self.identity(),
+            source,
+            Request::CustomPeerMessage(CustomMessage::with(
+                client,
+                TOWER_REPLY_TYPE,
+                &reply,
+            )),
+        )?;
+        Ok(())
+    }
+
+    fn handle_rpc_ctl(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::Hello(_) => {
+                // Ignoring; this is used to set remote identity at ZMQ level
+            }
+
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
+            Request::ChainTransactions(txs) => {
+                for tx in txs {
+                    self.check_breach(senders, &tx)?;
+                }
+            }
+
+            _ => {
+                error!("Request is not supported by the CTL interface");
+                return Err(Error::NotSupported(
+                    ServiceBus::Ctl,
+                    request.get_type(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a new session for the client, replacing its previous session,
+    /// or renews the existing one, resetting its quota
+    fn create_session(
+        &mut self,
+        client: PublicKey,
+        session: Option<TowerSessionId>,
+        max_updates: u16,
+    ) -> Result<TowerMessage, Error> {
+        let id = match session {
+            Some(id) => match self.sessions.get(&id) {
+                Some(session) if session.client == client => id,
+                _ => {
+                    return Ok(TowerMessage::Rejected(format!(
+                        "unknown session {}",
+                        id
+                    )))
+                }
+            },
+            None => {
+                self.sessions.retain(|_, session| session.client != client);
+                TowerSessionId::from(rand::random::<u64>())
+            }
+        };
+        let max_updates = max_updates.min(self.max_session_updates);
+        info!(
+            "{} {} with {} for {} updates",
+            if session.is_some() {
+                "Renewing watchtower session"
+            } else {
+                "Opening watchtower session"
+            }
+            .promo(),
+            id,
+            client.promoter(),
+            max_updates
+        );
+        let state = SessionState {
+            client,
+            max_updates,
+            updates: 0,
+        };
+        self.append(TowerRecord::Session(id, state.clone()))?;
+        self.sessions.insert(id, state);
+        Ok(TowerMessage::SessionCreated(TowerSession {
+            session: Some(id),
+            max_updates,
+        }))
+    }
+
+    fn append_blob(
+        &mut self,
+        client: PublicKey,
+        id: TowerSessionId,
+        blob: JusticeBlob,
+    ) -> Result<TowerMessage, Error> {
+        let session = match self.sessions.get_mut(&id) {
+            Some(session) if session.client == client => session,
+            _ => {
+                return Ok(TowerMessage::Rejected(format!(
+                    "unknown session {}",
+                    id
+                )))
+            }
+        };
+        if session.updates >= session.max_updates {
+            return Ok(TowerMessage::Rejected(format!(
+                "session {} is exhausted",
+                id
+            )));
+        }
+        session.updates += 1;
+        trace!("Storing justice blob {} from {}", blob.hint, client);
+        let hint = blob.hint;
+        self.append(TowerRecord::Blob(Some(id), blob.clone()))?;
+        self.blobs.entry(hint).or_insert(vec![]).push(blob);
+        Ok(TowerMessage::BlobAccepted(id, hint))
+    }
+
+    fn check_breach(
+        &mut self,
+        senders: &mut Senders,
+        tx: &Transaction,
+    ) -> Result<(), Error> {
+        let txid = tx.txid();
+        let hint = BreachHint::with(&txid);
+        let blobs = match self.blobs.remove(&hint) {
+            None => return Ok(()),
+            Some(blobs) => blobs,
+        };
+
+        // Channels of the tower clients are not the node channels, so no
+        // node event is produced here: breaches of the node own channels are
+        // reported by their channel daemons
+        warn!(
+            "{} {}",
+            "Channel breach detected in transaction".err(),
+            txid.err_details()
+        );
+        let mut remaining = vec![];
+        for blob in blobs {
+            match blob.open(&txid) {
+                Some(penalty_tx) => {
+                    info!(
+                        "{} {}",
+                        "Broadcasting penalty transaction".promo(),
+                        penalty_tx.txid().promoter()
+                    );
+                    self.send_ctl(
+                        senders,
+                        ServiceId::Chain,
+                        Request::BroadcastTransaction(penalty_tx),
+                    )?;
+                }
+                // Hint collision: the blob belongs to some other transaction
+                None => remaining.push(blob),
+            }
+        }
+        if !remaining.is_empty() {
+            self.blobs.insert(hint, remaining);
+        }
+        // Opened blobs are dropped from the database
+        self.db = compact(&self.db_path, &self.sessions, &self.blobs)?;
+        Ok(())
+    }
+
+    fn append(&mut self, record: TowerRecord) -> Result<(), Error> {
+        let data = strict_serialize(&record)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        self.db.write_all(&data)?;
+        self.db.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Writes sessions and blobs into a new database log, replacing the existing
+/// one, and opens it for appending
+fn compact(
+    path: &PathBuf,
+    sessions: &HashMap<TowerSessionId, SessionState>,
+    blobs: &HashMap<BreachHint, Vec<JusticeBlob>>,
+) -> Result<fs::File, Error> {
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = io::BufWriter::new(fs::File::create(&tmp_path)?);
+    let mut write = |record: TowerRecord| -> Result<(), Error> {
+        record
+            .strict_encode(&mut writer)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        Ok(())
+    };
+    for (id, session) in sessions {
+        write(TowerRecord::Session(*id, session.clone()))?;
+    }
+    for blob in blobs.values().flatten() {
+        write(TowerRecord::Blob(None, blob.clone()))?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, path)?;
+    Ok(fs::OpenOptions::new().append(true).open(path)?)
+}
@@ -0,0 +1,71 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use clap::{AppSettings, Clap};
+use internet2::RemoteNodeAddr;
+
+/// Watchtower client daemon; part of LNP Node
+///
+/// The daemon keeps sessions with multiple watchtowers, uploads justice
+/// transaction blobs produced by channel daemons to each of them and tracks
+/// which blobs were acknowledged by every tower.
+///
+/// The daemon is controlled though ZMQ ctl socket (see `ctl-socket` argument
+/// description)
+#[derive(Clap, Clone, PartialEq, Eq, Debug)]
+#[clap(
+    name = "wtclientd",
+    bin_name = "wtclientd",
+    author,
+    version,
+    setting = AppSettings::ColoredHelp
+)]
+pub struct Opts {
+    /// Remote nodes running the watchtowers to use, in
+    /// `<node_id>@<host>:<port>` format
+    ///
+    /// Can be used multiple times; each blob is uploaded to all of the
+    /// provided towers over the peer connections with their nodes.
+    #[clap(long = "tower")]
+    pub towers: Vec<RemoteNodeAddr>,
+
+    /// Number of updates requested for each tower session
+    #[clap(long, default_value = "1024")]
+    pub session_updates: u16,
+
+    /// Number of unacknowledged blobs after which a tower is reported as
+    /// falling behind
+    #[clap(long, default_value = "32")]
+    pub max_backlog: usize,
+
+    /// Number of seconds to wait for a tower to acknowledge a blob before
+    /// reporting it as unresponsive
+    #[clap(long, default_value = "300")]
+    pub ack_timeout: u64,
+
+    /// RGB configuration: ignored by this daemon
+    #[clap(short, long = "rgb20-rpc")]
+    pub r: Option<String>,
+
+    /// These params can be read also from the configuration file, not just
+    /// command-line args or environment variables
+    #[clap(flatten)]
+    pub shared: crate::opts::Opts,
+}
+
+impl Opts {
+    pub fn process(&mut self) {
+        self.shared.process()
+    }
+}
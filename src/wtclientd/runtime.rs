@@ -0,0 +1,620 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{HashMap, VecDeque};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime};
+
+use bitcoin::secp256k1::PublicKey;
+use internet2::zmqsocket::{self, ZmqType};
+use internet2::{NodeAddr, RemoteNodeAddr, TypedEnum, ZMQ_CONTEXT};
+use lnpbp::strict_encoding::strict_deserialize;
+use microservices::esb::{self, Handler};
+
+use crate::rpc::request::{
+    CustomMessage, JusticeBlob, TowerInfo, TowerMessage, TowerSession,
+    TowerSessionId, TOWER_REPLY_TYPE, TOWER_REQUEST_TYPE,
+};
+use crate::rpc::{Request, ServiceBus};
+use crate::service::BridgeHandler;
+use crate::{Config, CtlServer, Error, LogStyle, Senders, Service, ServiceId};
+
+/// Period of the timer checking health of the towers and retrying failed
+/// sessions; it is also the delay before the first retry
+const TIMER_PERIOD: Duration = Duration::from_secs(30);
+
+/// Retry delay is doubled with each failure of the tower up to this limit
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+pub fn run(
+    config: Config,
+    towers: Vec<RemoteNodeAddr>,
+    session_updates: u16,
+    max_backlog: usize,
+    ack_timeout: Duration,
+) -> Result<(), Error> {
+    let runtime = Runtime {
+        identity: ServiceId::TowerClient,
+        towers: towers
+            .into_iter()
+            .map(|addr| (addr.node_id, TowerState::with(addr)))
+            .collect(),
+        connecting: None,
+        connect_queue: none!(),
+        session_updates,
+        max_backlog,
+        ack_timeout,
+    };
+
+    debug!("Opening bridge between runtime and timer threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    tx.connect("inproc://wtclientd-timer")?;
+    rx.bind("inproc://wtclientd-timer")?;
+
+    let mut timer = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    spawn(move || loop {
+        sleep(TIMER_PERIOD);
+        if let Err(err) = timer.send_to(
+            ServiceBus::Bridge,
+            ServiceId::TowerClient,
+            Request::CheckTimeouts,
+        ) {
+            error!("Unable to notify watchtower client on timer: {}", err);
+        }
+    });
+
+    let mut service = Service::service(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct TowerState {
+    addr: RemoteNodeAddr,
+    /// Session opened by the tower, which is kept when it is renewed
+    session: Option<TowerSessionId>,
+    session_open: bool,
+    /// Number of updates the tower has granted to the session
+    max_updates: u16,
+    /// Number of blobs sent within the session
+    updates: u16,
+    acked: u64,
+    /// Blobs not yet acknowledged by the tower, with the time they were sent
+    backlog: VecDeque<(JusticeBlob, Option<SystemTime>)>,
+    last_ack: Option<SystemTime>,
+    healthy: bool,
+    /// Number of consecutive failures of the tower, which increase the delay
+    /// before the session is opened again
+    failures: u32,
+    /// Time after which the session may be opened again
+    retry_at: Option<SystemTime>,
+    /// Whether the reconnection was requested and has not resulted in a
+    /// session yet
+    reconnecting: bool,
+}
+
+impl TowerState {
+    fn with(addr: RemoteNodeAddr) -> Self {
+        TowerState {
+            addr,
+            session: None,
+            session_open: false,
+            max_updates: 0,
+            updates: 0,
+            acked: 0,
+            backlog: none!(),
+            last_ack: None,
+            healthy: false,
+            failures: 0,
+            retry_at: None,
+            reconnecting: false,
+        }
+    }
+
+    fn retry_due(&self, now: SystemTime) -> bool {
+        self.retry_at.map(|time| time <= now).unwrap_or(true)
+    }
+
+    /// Closes the session after the failure, scheduling its reopening with
+    /// the delay growing exponentially with the number of failures
+    fn fail(&mut self, now: SystemTime) {
+        self.failures += 1;
+        self.session_open = false;
+        let delay = TIMER_PERIOD
+            .checked_mul(1 << self.failures.min(16))
+            .unwrap_or(MAX_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY);
+        self.retry_at = Some(now + delay);
+        warn!(
+            "Watchtower {} has failed {} time(s); retrying in {} seconds",
+            self.addr,
+            self.failures,
+            delay.as_secs()
+        );
+    }
+
+    fn in_flight(&self) -> usize {
+        self.backlog
+            .iter()
+            .filter(|(_, sent)| sent.is_some())
+            .count()
+    }
+
+    fn peer(&self) -> ServiceId {
+        ServiceId::Peer(NodeAddr::Remote(self.addr.clone()))
+    }
+}
+
+pub struct Runtime {
+    identity: ServiceId,
+    towers: HashMap<PublicKey, TowerState>,
+    /// Tower which connection is requested from lnpd. Replies of lnpd do not
+    /// tell which peer got connected, so the towers are connected one by one.
+    connecting: Option<PublicKey>,
+    /// Towers awaiting their connection to be requested
+    connect_queue: VecDeque<PublicKey>,
+    session_updates: u16,
+    max_backlog: usize,
+    ack_timeout: Duration,
+}
+
+impl CtlServer for Runtime {}
+
+impl esb::Handler<ServiceBus> for Runtime {
+    type Request = Request;
+    type Address = ServiceId;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId {
+        self.identity.clone()
+    }
+
+    fn on_ready(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        // Sessions are opened once lnpd reports the towers connected
+        let towers = self.towers.keys().copied().collect::<Vec<_>>();
+        for tower in towers {
+            self.connect_tower(senders, tower)?;
+        }
+        Ok(())
+    }
+
+    fn handle(
+        &mut self,
+        senders: &mut esb::SenderList<ServiceBus, ServiceId>,
+        bus: ServiceBus,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Self::Error> {
+        let res = match bus {
+            ServiceBus::Msg => self.handle_rpc_msg(senders, source, request),
+            ServiceBus::Ctl => self.handle_rpc_ctl(senders, source, request),
+            ServiceBus::Bridge => self.handle_bridge(senders, request),
+        };
+        self.check_health();
+        res
+    }
+
+    fn handle_err(&mut self, _: esb::Error) -> Result<(), esb::Error> {
+        // We do nothing and do not propagate error; it's already being reported
+        // with `error!` macro by the controller. If we propagate error here
+        // this will make whole daemon panic
+        Ok(())
+    }
+}
+
+impl Runtime {
+    fn handle_rpc_msg(
+        &mut self,
+        senders: &mut Senders,
+        _source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        let (tower, message) = match request {
+            Request::CustomPeerMessage(CustomMessage {
+                remote_id,
+                msg_type: TOWER_REPLY_TYPE,
+                payload,
+            }) if self.towers.contains_key(&remote_id) => {
+                match strict_deserialize(&payload) {
+                    Ok(message) => (remote_id, message),
+                    Err(err) => {
+                        warn!(
+                            "Invalid reply of watchtower {}: {}",
+                            remote_id, err
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+            Request::CustomPeerMessage(CustomMessage { remote_id, .. }) => {
+                debug!("Ignoring watchtower reply from {}", remote_id);
+                return Ok(());
+            }
+            _ => {
+                error!(
+                    "MSG RPC can be only used for forwarding watchtower \
+                     replies"
+                );
+                return Err(Error::NotSupported(
+                    ServiceBus::Msg,
+                    request.get_type(),
+                ));
+            }
+        };
+
+        let state = self
+            .towers
+            .get_mut(&tower)
+            .expect("tower presence is checked by the match guard");
+        match message {
+            TowerMessage::SessionCreated(TowerSession {
+                session: Some(id),
+                max_updates,
+            }) => {
+                info!(
+                    "{} {} with {}",
+                    "Opened tower session".ended(),
+                    id,
+                    state.addr
+                );
+                state.session = Some(id);
+                state.session_open = true;
+                state.max_updates = max_updates;
+                state.updates = 0;
+                state.failures = 0;
+                state.retry_at = None;
+                state.reconnecting = false;
+            }
+            TowerMessage::BlobAccepted(id, hint)
+                if state.session == Some(id) =>
+            {
+                match state.backlog.iter().position(|(blob, sent)| {
+                    blob.hint == hint && sent.is_some()
+                }) {
+                    Some(pos) => {
+                        trace!("Tower {} acknowledged blob {}", tower, hint);
+                        state.backlog.remove(pos);
+                        state.acked += 1;
+                        state.last_ack = Some(SystemTime::now());
+                        state.failures = 0;
+                    }
+                    None => {
+                        warn!("Tower {} acknowledged unknown blob", tower)
+                    }
+                }
+            }
+            TowerMessage::Rejected(info) => {
+                error!(
+                    "{} {}: {}",
+                    "Watchtower".err(),
+                    tower.err(),
+                    info.err_details()
+                );
+                // New session is opened by the timer, and all unacknowledged
+                // blobs are re-sent then
+                state.session = None;
+                state.reconnecting = false;
+                state.fail(SystemTime::now());
+            }
+            message => {
+                warn!("Unexpected reply {} from watchtower {}", message, tower)
+            }
+        }
+        self.flush(senders)
+    }
+
+    fn handle_rpc_ctl(
+        &mut self,
+        senders: &mut Senders,
+        source: ServiceId,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::Hello(_) => {
+                // Ignoring; this is used to set remote identity at ZMQ level
+            }
+
+            Request::Heartbeat(seq) => {
+                self.send_ctl(senders, source, Request::Heartbeat(seq))?;
+            }
+
+            Request::AppendJusticeBlob(blob) => {
+                trace!("Got justice blob {} from {}", blob.hint, source);
+                for tower in self.towers.values_mut() {
+                    tower.backlog.push_back((blob.clone(), None));
+                }
+                self.flush(senders)?;
+            }
+
+            Request::Success(_) if source == ServiceId::Lnpd => {
+                if let Some(tower) = self.connecting.take() {
+                    let session_open = self
+                        .towers
+                        .get(&tower)
+                        .map(|state| state.session_open)
+                        .unwrap_or(true);
+                    if !session_open {
+                        self.open_session(senders, tower)?;
+                    }
+                }
+                self.connect_next(senders)?;
+            }
+
+            Request::Progress(_) if source == ServiceId::Lnpd => {}
+
+            Request::Failure(failure) if source == ServiceId::Lnpd => {
+                error!(
+                    "{} {}: {}",
+                    "Unable to connect to watchtower".err(),
+                    self.connecting
+                        .map(|tower| tower.to_string())
+                        .unwrap_or_default()
+                        .err(),
+                    failure.err_details()
+                );
+                self.connecting = None;
+                self.connect_next(senders)?;
+            }
+
+            Request::ListTowers => {
+                let list = self
+                    .towers
+                    .values()
+                    .map(|state| TowerInfo {
+                        tower: state.addr.to_string(),
+                        session_open: state.session_open,
+                        acked: state.acked,
+                        backlog: state.backlog.len(),
+                        last_ack: state.last_ack.map(|time| {
+                            time.duration_since(SystemTime::UNIX_EPOCH)
+                                .unwrap_or(Duration::from_secs(0))
+                                .as_secs()
+                        }),
+                        healthy: state.healthy,
+                    })
+                    .collect();
+                self.send_ctl(senders, source, Request::TowerList(list))?;
+            }
+
+            _ => {
+                error!("Request is not supported by the CTL interface");
+                return Err(Error::NotSupported(
+                    ServiceBus::Ctl,
+                    request.get_type(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_bridge(
+        &mut self,
+        senders: &mut Senders,
+        request: Request,
+    ) -> Result<(), Error> {
+        match request {
+            Request::CheckTimeouts => self.check_timeouts(senders),
+            _ => {
+                Err(Error::NotSupported(ServiceBus::Bridge, request.get_type()))
+            }
+        }
+    }
+
+    /// Fails the towers which do not acknowledge sent blobs in time, and
+    /// reconnects the towers which sessions are due to be reopened. Sessions
+    /// are opened once lnpd reports the connection.
+    fn check_timeouts(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        let now = SystemTime::now();
+        let ack_timeout = self.ack_timeout;
+        let mut reconnect = vec![];
+        for state in self.towers.values_mut() {
+            let stalled = state
+                .backlog
+                .front()
+                .and_then(|(_, sent)| *sent)
+                .and_then(|sent| now.duration_since(sent).ok())
+                .map(|waited| waited > ack_timeout)
+                .unwrap_or_default();
+            if state.session_open && stalled {
+                state.reconnecting = false;
+                state.fail(now);
+            } else if !state.session_open && state.retry_due(now) {
+                if state.reconnecting {
+                    // Session was not opened since the previous attempt
+                    state.fail(now);
+                    if self.connecting == Some(state.addr.node_id) {
+                        // lnpd has not replied, so we stop awaiting it
+                        self.connecting = None;
+                    }
+                } else {
+                    state.reconnecting = true;
+                    state.retry_at = Some(now + TIMER_PERIOD);
+                }
+                reconnect.push(state.addr.node_id);
+            }
+        }
+        for tower in reconnect {
+            self.connect_tower(senders, tower)?;
+        }
+        Ok(())
+    }
+
+    /// Queues the connection to the tower, which session is opened once lnpd
+    /// reports the tower connected
+    fn connect_tower(
+        &mut self,
+        senders: &mut Senders,
+        tower: PublicKey,
+    ) -> Result<(), Error> {
+        if self.connecting != Some(tower)
+            && !self.connect_queue.contains(&tower)
+        {
+            self.connect_queue.push_back(tower);
+        }
+        self.connect_next(senders)
+    }
+
+    /// Requests connection to the next queued tower unless the connection to
+    /// another tower is in progress
+    fn connect_next(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        if self.connecting.is_some() {
+            return Ok(());
+        }
+        while let Some(tower) = self.connect_queue.pop_front() {
+            let addr = match self.towers.get(&tower) {
+                Some(state) => state.addr.clone(),
+                None => continue,
+            };
+            debug!("Connecting to watchtower {}", addr);
+            self.connecting = Some(tower);
+            return self.send_ctl(
+                senders,
+                ServiceId::Lnpd,
+                Request::ConnectPeer(NodeAddr::Remote(addr)),
+            );
+        }
+        Ok(())
+    }
+
+    /// Opens a new session with the tower or renews the existing one
+    fn open_session(
+        &mut self,
+        senders: &mut Senders,
+        tower: PublicKey,
+    ) -> Result<(), Error> {
+        let max_updates = self.session_updates;
+        let state = match self.towers.get_mut(&tower) {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        debug!("Opening session with watchtower {}", state.addr);
+        state.session_open = false;
+        state.backlog.iter_mut().for_each(|(_, sent)| *sent = None);
+        let message = TowerMessage::CreateSession(TowerSession {
+            session: state.session,
+            max_updates,
+        });
+        let peer = state.peer();
+        self.send_tower(senders, peer, tower, message)
+    }
+
+    fn flush(&mut self, senders: &mut Senders) -> Result<(), Error> {
+        let mut exhausted = vec![];
+        let mut messages = vec![];
+        for (tower, state) in &mut self.towers {
+            let id = match state.session {
+                Some(id) if state.session_open => id,
+                _ => continue,
+            };
+            for (blob, sent) in &mut state.backlog {
+                if sent.is_some() {
+                    continue;
+                }
+                if state.updates >= state.max_updates {
+                    exhausted.push(*tower);
+                    break;
+                }
+                messages.push((
+                    state.peer(),
+                    *tower,
+                    TowerMessage::AppendBlob(id, blob.clone()),
+                ));
+                state.updates += 1;
+                *sent = Some(SystemTime::now());
+            }
+        }
+        for (peer, tower, message) in messages {
+            self.send_tower(senders, peer, tower, message)?;
+        }
+        for tower in exhausted {
+            if self
+                .towers
+                .get(&tower)
+                .map(|state| state.in_flight() == 0)
+                .unwrap_or_default()
+            {
+                // Renewal resets the quota of the session, so it is requested
+                // once the tower has acknowledged all sent blobs
+                self.open_session(senders, tower)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_tower(
+        &mut self,
+        senders: &mut Senders,
+        peer: ServiceId,
+        tower: PublicKey,
+        message: TowerMessage,
+    ) -> Result<(), Error> {
+        senders.send_to(
+            ServiceBus::Msg,
+            self.identity(),
+            peer,
+            Request::CustomPeerMessage(CustomMessage::with(
+                tower,
+                TOWER_REQUEST_TYPE,
+                &message,
+            )),
+        )?;
+        Ok(())
+    }
+
+    fn check_health(&mut self) {
+        let now = SystemTime::now();
+        for state in self.towers.values_mut() {
+            let stalled = state
+                .backlog
+                .front()
+                .and_then(|(_, sent)| *sent)
+                .and_then(|sent| now.duration_since(sent).ok())
+                .map(|waited| waited > self.ack_timeout)
+                .unwrap_or_default();
+            let behind = state.backlog.len() > self.max_backlog;
+            let healthy = !stalled && !behind;
+
+            if state.healthy && !healthy {
+                warn!(
+                    "{} {} {}: {} blob(s) in backlog, {} in flight",
+                    "Watchtower".err(),
+                    state.addr.err(),
+                    if stalled {
+                        "does not respond"
+                    } else {
+                        "falls behind"
+                    }
+                    .err(),
+                    state.backlog.len(),
+                    state.in_flight()
+                );
+            } else if !state.healthy && healthy && state.session_open {
+                info!("Watchtower {} is {}", state.addr, "healthy".ended());
+            }
+            state.healthy = healthy;
+        }
+    }
+}